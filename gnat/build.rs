@@ -7,23 +7,52 @@
  */
 extern crate pkg_config;
 
+use std::env;
+
 fn main() {
+    // supported targets
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let multiarch = match target_arch.as_str() {
+        "x86_64" => "x86_64-linux-gnu",
+        "aarch64" => "aarch64-linux-gnu",
+        _ => panic!("unsupported target architecture: {}", target_arch),
+    };
+
+    // GNAT_PREFIX points at the gnat_base install (or a cross sysroot copy of it)
+    println!("cargo:rerun-if-env-changed=GNAT_PREFIX");
+    let prefix = env::var("GNAT_PREFIX").unwrap_or("/opt/gnat".to_string());
+
     // compile options
-    let src = ["src/ipfix/import_libfixbuf.c", "src/ipfix/export_parquet.c"];
+    let src = [
+        "src/ipfix/import_libfixbuf.c",
+        "src/ipfix/export_parquet.c",
+        "src/ipfix/export_dpi.c",
+    ];
     let mut builder = cc::Build::new();
+    match pkg_config::Config::new()
+        .cargo_metadata(false)
+        .probe("glib-2.0")
+    {
+        Ok(glib) => {
+            builder.includes(glib.include_paths);
+        }
+        Err(_) => {
+            builder
+                .include("/usr/include/glib-2.0")
+                .include(format!("/usr/lib/{}/glib-2.0/include", multiarch));
+        }
+    }
     let build = builder
         .files(src.iter())
-        .include("/usr/include/glib-2.0")
-        .include("/usr/lib/x86_64-linux-gnu/glib-2.0/include")
-        .include("/opt/gnat/include")
+        .include(format!("{}/include", prefix))
         .include("/usr/local/include")
         .flag("-Wno-unused-parameter")
         .opt_level(2);
     build.compile("libfixbuf");
 
     // link options
-    println!("cargo:rustc-link-search=/opt/gnat/lib");
-    println!("cargo:rustc-link-search=/usr/local/lib");  
+    println!("cargo:rustc-link-search={}/lib", prefix);
+    println!("cargo:rustc-link-search=/usr/local/lib");
     println!("cargo:rustc-link-lib=fixbuf");
     println!("cargo:rustc-link-lib=airframe");
     println!("cargo:rustc-link-lib=ndpi");
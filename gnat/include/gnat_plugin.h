@@ -0,0 +1,41 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+/*
+ * GNAT plugin ABI
+ *
+ * A plugin is a shared library (*.so) placed in the plugins directory
+ * and run by gnat_plugin as a pipeline stage. For every parquet file
+ * in the stage input directory, gnat_plugin calls gnat_plugin_process()
+ * with the input file, an output file to create, and the stage options.
+ *
+ * Enrichers and detectors write a parquet file with the flow schema to
+ * output_file; sinks may leave output_file absent. Return 0 on success
+ * and a negative value on error.
+ */
+#pragma once
+
+#define GNAT_PLUGIN_ABI_VERSION 1
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* must return GNAT_PLUGIN_ABI_VERSION */
+int gnat_plugin_abi_version(void);
+
+/* unique plugin name, used by --name */
+const char *gnat_plugin_name(void);
+
+int gnat_plugin_process(const char *input_file,
+                        const char *output_file,
+                        const char *options);
+
+#ifdef __cplusplus
+}
+#endif
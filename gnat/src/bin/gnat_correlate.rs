@@ -0,0 +1,11 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+fn main() {
+    gnat::stages::correlate::stage(std::env::args().collect());
+}
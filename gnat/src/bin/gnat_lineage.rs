@@ -0,0 +1,76 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use clap::Parser;
+use gnat::core::lineage;
+use gnat::core::logging;
+use gnat::core::scratch;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// flow parquet file
+    #[arg(long)]
+    input: String,
+
+    /// print the chain as JSON
+    #[arg(long)]
+    json: Option<bool>,
+}
+
+fn main() {
+    let args = Args::parse();
+    let _stage = logging::init("gnat_lineage");
+    let input_spec = args.input.clone();
+    let json = args.json.unwrap_or(false);
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !input_spec.starts_with("s3://") && !Path::new(&input_spec).is_file() {
+        error!("invalid --input {}; not a file", input_spec);
+        std::process::exit(exitcode::CONFIG)
+    }
+    if input_spec.starts_with("s3://") {
+        scratch::enable_s3();
+    }
+
+    let conn = match scratch::open_in_memory("lineage") {
+        Ok(c) => c,
+        Err(e) => {
+            error!("open_in_memory() - {:?}", e);
+            std::process::exit(exitcode::SOFTWARE)
+        }
+    };
+    let chain = match lineage::read(&conn, &input_spec) {
+        Ok(chain) => chain,
+        Err(e) => {
+            error!("reading {} - {:?}", input_spec, e);
+            std::process::exit(exitcode::SOFTWARE)
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&chain).unwrap_or_default());
+        return;
+    }
+    if chain.is_empty() {
+        println!("{}: no lineage recorded", input_spec);
+    }
+    for step in chain.iter() {
+        println!(
+            "{} {:<10} {} <- {}",
+            step.time,
+            step.stage,
+            step.batch,
+            step.sources.join(", ")
+        );
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use clap::Parser;
+use gnat::core::pipeline::{run, Check, Entrypoint, PipelineConfig};
+use gnat::core::logging;
+use gnat::core::shutdown;
+use gnat::stages;
+use std::path::Path;
+use tracing::{error, info};
+
+// collect runs as a child process: its libfixbuf loop replaces the
+// signal handlers, only stops on a signal, and exits on a bad option
+const ENTRYPOINTS: &[(&str, Entrypoint, Check)] = &[
+    ("import", stages::import::stage, stages::import::check),
+    ("batch", stages::batch::stage, stages::batch::check),
+    ("export", stages::export::stage, stages::export::check),
+    ("plugin", stages::plugin::stage, stages::plugin::check),
+    #[cfg(feature = "wasm")]
+    ("transform", stages::transform::stage, stages::transform::check),
+    ("tag", stages::tag::stage, stages::tag::check),
+    ("site", stages::site::stage, stages::site::check),
+    ("enrich", stages::enrich::stage, stages::enrich::check),
+    ("dga", stages::dga::stage, stages::dga::check),
+    ("sample", stages::sample::stage, stages::sample::check),
+    ("correlate", stages::correlate::stage, stages::correlate::check),
+    ("detect", stages::detect::stage, stages::detect::check),
+    ("beacon", stages::beacon::stage, stages::beacon::check),
+    ("scan", stages::scan::stage, stages::scan::check),
+    ("asset", stages::asset::stage, stages::asset::check),
+    ("stitch", stages::stitch::stage, stages::stitch::check),
+    #[cfg(feature = "kafka")]
+    ("kafka", stages::kafka::stage, stages::kafka::check),
+    ("report", stages::report::stage, stages::report::check),
+];
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    config: String,
+
+    /// validate the configuration and exit
+    #[arg(long)]
+    check: Option<bool>,
+
+    /// address:port of the HTTP control API
+    #[arg(long)]
+    control: Option<String>,
+}
+
+fn main() {
+    let args = Args::parse();
+    shutdown::install();
+    let _stage = logging::init("gnat_run");
+    let config_spec = args.config.clone();
+    let check = args.check.unwrap_or(false);
+    let control_spec = args.control.unwrap_or(String::new()).clone();
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !Path::new(&config_spec).is_file() {
+        error!("invalid --config file {}", config_spec);
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if !control_spec.is_empty() && control_spec.parse::<std::net::SocketAddr>().is_err() {
+        error!("invalid --control address {} [address:port]", control_spec);
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if check {
+        let checked = PipelineConfig::load(&config_spec)
+            .map(|c| c.validate().and_then(|order| c.check(ENTRYPOINTS).map(|_| order)));
+        match checked {
+            Ok(Ok(order)) => {
+                info!("pipeline: {} valid [{} stages]", config_spec, order.len());
+                return;
+            }
+            Ok(Err(e)) => error!("{}", e),
+            Err(e) => error!("{}", e),
+        }
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if let Err(e) = run(&config_spec, &control_spec, ENTRYPOINTS) {
+        error!("{}", e);
+        std::process::exit(exitcode::SOFTWARE)
+    }
+}
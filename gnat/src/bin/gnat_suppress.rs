@@ -0,0 +1,164 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use clap::Parser;
+use gnat::core::logging;
+use gnat::core::suppress::{Store, Suppression, SUPPRESS_STAGES};
+use std::path::Path;
+use tracing::{error, info};
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// suppression store (DuckDB file), created when missing
+    #[arg(long)]
+    store: String,
+
+    /// add | import | remove | list | expire
+    #[arg(long)]
+    command: String,
+
+    #[arg(long)]
+    observ: Option<String>,
+
+    #[arg(long)]
+    saddr: Option<String>,
+
+    #[arg(long)]
+    daddr: Option<String>,
+
+    #[arg(long)]
+    proto: Option<String>,
+
+    #[arg(long)]
+    dport: Option<u16>,
+
+    #[arg(long)]
+    appid: Option<String>,
+
+    /// limit the suppression to one stage: detect, beacon or scan
+    #[arg(long)]
+    stage: Option<String>,
+
+    /// days until the suppression expires
+    #[arg(long)]
+    days: Option<u64>,
+
+    /// recorded in the audit table (default $USER)
+    #[arg(long)]
+    author: Option<String>,
+
+    #[arg(long)]
+    reason: Option<String>,
+
+    /// JSON array of suppressions, for --command import
+    #[arg(long)]
+    file: Option<String>,
+
+    /// suppression to remove, for --command remove
+    #[arg(long)]
+    id: Option<u64>,
+}
+
+fn main() {
+    let args = Args::parse();
+    let _stage = logging::init("gnat_suppress");
+    let store_spec = args.store.clone();
+    let command = args.command.clone();
+    let days = args.days.unwrap_or(7);
+    let author = args
+        .author
+        .clone()
+        .unwrap_or(std::env::var("USER").unwrap_or(String::from("unknown")));
+    let file_spec = args.file.clone().unwrap_or(String::new());
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    let parent = Path::new(&store_spec).parent().unwrap_or(Path::new("."));
+    if !parent.as_os_str().is_empty() && !parent.is_dir() {
+        error!("invalid --store {}; {} is not a directory", store_spec, parent.display());
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if !["add", "import", "remove", "list", "expire"].contains(&command.as_str()) {
+        error!("invalid --command {} (add|import|remove|list|expire)", command);
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if let Some(stage) = &args.stage {
+        if !SUPPRESS_STAGES.contains(&stage.as_str()) {
+            error!("invalid --stage {} (detect|beacon|scan)", stage);
+            std::process::exit(exitcode::CONFIG)
+        }
+    }
+
+    if days == 0 {
+        error!("--days must be greater than 0");
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if command == "import" && !Path::new(&file_spec).is_file() {
+        error!("--command import requires --file <json file>");
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if command == "remove" && args.id.is_none() {
+        error!("--command remove requires --id <id>");
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    let store = match Store::open(&store_spec) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("opening {} - {:?}", store_spec, e);
+            std::process::exit(exitcode::SOFTWARE)
+        }
+    };
+    // expired suppressions are dropped, with an audit entry, on every run
+    let result = store.expire().and_then(|expired| {
+        if expired > 0 {
+            info!("expired {} suppressions", expired);
+        }
+        match command.as_str() {
+            "add" => {
+                let suppression = Suppression {
+                    observ: args.observ.clone(),
+                    saddr: args.saddr.clone(),
+                    daddr: args.daddr.clone(),
+                    proto: args.proto.clone(),
+                    dport: args.dport,
+                    appid: args.appid.clone(),
+                    stage: args.stage.clone(),
+                    days: Some(days),
+                    author: None,
+                    reason: args.reason.clone(),
+                };
+                store.add(&suppression, &author).map(|id| println!("{}", id))
+            }
+            "import" => store
+                .import(&file_spec, &author)
+                .map(|count| info!("imported {} suppressions from {}", count, file_spec)),
+            "remove" => {
+                let id = args.id.unwrap_or_default();
+                store.remove(id, &author).map(|removed| {
+                    if !removed {
+                        error!("no suppression {}", id);
+                    }
+                })
+            }
+            "list" => store.list().map(|lines| lines.iter().for_each(|l| println!("{}", l))),
+            _ => Ok(()),
+        }
+    });
+    if let Err(e) = result {
+        error!("{}", e);
+        std::process::exit(exitcode::SOFTWARE)
+    }
+}
@@ -0,0 +1,357 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Address anonymization for shared datasets (gnat_export --anonymize)
+//
+// saddr and daddr are replaced in one of two modes:
+//
+//   prefix   prefix-preserving, in the manner of Crypto-PAn: addresses that
+//            share their first n bits are mapped to addresses that share
+//            their first n bits, so subnets stay recognizable. Each bit is
+//            flipped or kept by a pseudorandom function of the key and the
+//            bits before it; IPv4 stays IPv4 and IPv6 stays IPv6
+//   hmac     32 hex digits of the HMAC-SHA256 of the address, which keeps
+//            nothing of its structure
+//
+// smac and dmac become the first six bytes of an HMAC-SHA256 of the MAC,
+// marked locally administered; 00:00:00:00:00:00 (no MAC) is kept. Both
+// modes use HMAC-SHA256 under the key file's contents, so the same key maps
+// an address the same way in every file and every run.
+//
+
+use crate::core::context::Local;
+use crate::core::error::GnatError;
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+
+use duckdb::{params, Connection};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+pub const ANONYMIZE_MODES: [&str; 2] = ["prefix", "hmac"];
+
+const NO_MAC: &str = "00:00:00:00:00:00";
+const MIN_KEY_LENGTH: usize = 16;
+const HMAC_BLOCK: usize = 64;
+
+static ANONYMIZER: Local<Anonymizer> = Local::new();
+
+struct Anonymizer {
+    mode: String,
+    key: Vec<u8>,
+}
+
+// (address bits, prefix length, prefix) -> flip the next bit
+type FlipCache = HashMap<(u32, u32, u128), bool>;
+
+impl Anonymizer {
+    fn hmac(&self, message: &[u8]) -> [u8; 32] {
+        let mut block = [0u8; HMAC_BLOCK];
+        if self.key.len() > HMAC_BLOCK {
+            block[..32].copy_from_slice(&Sha256::digest(&self.key));
+        } else {
+            block[..self.key.len()].copy_from_slice(&self.key);
+        }
+        let mut inner = Sha256::new();
+        inner.update(block.map(|b| b ^ 0x36));
+        inner.update(message);
+        let mut outer = Sha256::new();
+        outer.update(block.map(|b| b ^ 0x5c));
+        outer.update(inner.finalize());
+        outer.finalize().into()
+    }
+
+    //
+    // Flip each of the bits of address by the pseudorandom function of the
+    // bits before it
+    //
+    fn prefix_preserving(&self, cache: &mut FlipCache, bits: u32, address: u128) -> u128 {
+        let mut flips: u128 = 0;
+        for length in 0..bits {
+            let prefix = if length == 0 { 0 } else { address >> (bits - length) };
+            let flip = *cache.entry((bits, length, prefix)).or_insert_with(|| {
+                let mut message = vec![bits as u8, length as u8];
+                message.extend_from_slice(&prefix.to_be_bytes());
+                self.hmac(&message)[0] & 0x80 != 0
+            });
+            if flip {
+                flips |= 1 << (bits - 1 - length);
+            }
+        }
+        address ^ flips
+    }
+
+    fn address(&self, cache: &mut FlipCache, address: &str) -> String {
+        let hmac = || -> String {
+            self.hmac(format!("addr|{}", address).as_bytes())[..16]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect()
+        };
+        if self.mode == "hmac" {
+            return hmac();
+        }
+        match address.parse::<IpAddr>() {
+            Ok(IpAddr::V4(v4)) => {
+                let anonymized = self.prefix_preserving(cache, 32, u32::from(v4) as u128);
+                std::net::Ipv4Addr::from(anonymized as u32).to_string()
+            }
+            Ok(IpAddr::V6(v6)) => {
+                let anonymized = self.prefix_preserving(cache, 128, u128::from(v6));
+                std::net::Ipv6Addr::from(anonymized).to_string()
+            }
+            // not an address, so there is no prefix to keep
+            Err(_) => hmac(),
+        }
+    }
+
+    fn mac(&self, mac: &str) -> String {
+        if mac == NO_MAC {
+            return String::from(mac);
+        }
+        let mut bytes = self.hmac(format!("mac|{}", mac.to_lowercase()).as_bytes());
+        // locally administered, unicast
+        bytes[0] = (bytes[0] | 0x02) & 0xfe;
+        bytes[..6]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<String>>()
+            .join(":")
+    }
+}
+
+//
+// Anonymize the flows of every export from now on with the key in key_spec
+//
+pub fn enable(mode: &String, key_spec: &String) -> Result<(), GnatError> {
+    if !ANONYMIZE_MODES.contains(&mode.as_str()) {
+        return Err(GnatError::Config(format!("invalid mode {} (prefix|hmac)", mode)));
+    }
+    let contents = fs::read(key_spec)?;
+    let key = contents.trim_ascii_end().to_vec();
+    if key.len() < MIN_KEY_LENGTH {
+        return Err(GnatError::Config(format!(
+            "{}: the key must be at least {} bytes",
+            key_spec, MIN_KEY_LENGTH
+        )));
+    }
+    info!("anonymize: {} addresses with the key in {}", mode, key_spec);
+    let _ = ANONYMIZER.set(Anonymizer {
+        mode: mode.clone(),
+        key,
+    });
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    ANONYMIZER.get().is_some()
+}
+
+fn distinct(conn: &Connection, sql_command: &str) -> Result<Vec<String>, duckdb::Error> {
+    let mut stmt = conn.prepare(sql_command)?;
+    let values = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, duckdb::Error>>()?;
+    Ok(values)
+}
+
+//
+// Load source into a table with its addresses and MACs anonymized and
+// return the SELECT to use in its place; source is returned as is when
+// anonymization is off
+//
+pub fn apply(conn: &Connection, source: &String) -> Result<String, duckdb::Error> {
+    let Some(anonymizer) = ANONYMIZER.get() else {
+        return Ok(source.clone());
+    };
+    let sql_command = format!(
+        "CREATE TABLE anonymized AS {};
+         CREATE TABLE addr_map (addr VARCHAR, anon VARCHAR);
+         CREATE TABLE mac_map (mac VARCHAR, anon VARCHAR);",
+        source
+    );
+    conn.execute_batch(&sql_command)?;
+
+    let addresses = distinct(
+        conn,
+        "SELECT saddr FROM anonymized WHERE saddr IS NOT NULL
+            UNION SELECT daddr FROM anonymized WHERE daddr IS NOT NULL;",
+    )?;
+    let macs = distinct(
+        conn,
+        "SELECT smac FROM anonymized WHERE smac IS NOT NULL
+            UNION SELECT dmac FROM anonymized WHERE dmac IS NOT NULL;",
+    )?;
+    {
+        let mut cache = FlipCache::new();
+        let mut appender = conn.appender("addr_map")?;
+        for address in addresses.iter() {
+            appender.append_row(params![address, anonymizer.address(&mut cache, address)])?;
+        }
+        let mut appender = conn.appender("mac_map")?;
+        for mac in macs.iter() {
+            appender.append_row(params![mac, anonymizer.mac(mac)])?;
+        }
+    }
+
+    conn.execute_batch(
+        "UPDATE anonymized SET saddr = m.anon FROM addr_map m WHERE m.addr = anonymized.saddr;
+         UPDATE anonymized SET daddr = m.anon FROM addr_map m WHERE m.addr = anonymized.daddr;
+         UPDATE anonymized SET smac = m.anon FROM mac_map m WHERE m.mac = anonymized.smac;
+         UPDATE anonymized SET dmac = m.anon FROM mac_map m WHERE m.mac = anonymized.dmac;",
+    )?;
+    Ok(String::from("SELECT * FROM anonymized"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anonymizer(mode: &str, key: &[u8]) -> Anonymizer {
+        Anonymizer {
+            mode: String::from(mode),
+            key: key.to_vec(),
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn bits(address: &str) -> (u32, u128) {
+        match address.parse::<IpAddr>().unwrap() {
+            IpAddr::V4(v4) => (32, u32::from(v4) as u128),
+            IpAddr::V6(v6) => (128, u128::from(v6)),
+        }
+    }
+
+    // bits two addresses share before they differ
+    fn common_prefix(a: &str, b: &str) -> u32 {
+        let ((bits, a), (_, b)) = (bits(a), bits(b));
+        ((a ^ b) << (128 - bits)).leading_zeros().min(bits)
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        let short = anonymizer("hmac", &[0x0b; 20]);
+        assert_eq!(
+            hex(&short.hmac(b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        // a key longer than the block is hashed first
+        let long = anonymizer("hmac", &[0xaa; 131]);
+        assert_eq!(
+            hex(&long.hmac(b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn prefix_mode_preserves_prefixes() {
+        let anonymizer = anonymizer("prefix", b"0123456789abcdef");
+        let mut cache = FlipCache::new();
+        let families = [
+            vec![
+                "10.1.2.3",
+                "10.1.2.200",
+                "10.1.3.3",
+                "10.200.0.1",
+                "192.168.1.1",
+                "0.0.0.0",
+                "255.255.255.255",
+            ],
+            vec!["2001:db8::1", "2001:db8::2", "2001:db8:1::1", "fe80::1"],
+        ];
+        for addresses in families.iter() {
+            for a in addresses.iter() {
+                let anon_a = anonymizer.address(&mut cache, a);
+                // IPv4 stays IPv4 and IPv6 stays IPv6
+                assert_eq!(bits(a).0, bits(&anon_a).0);
+                for b in addresses.iter() {
+                    let anon_b = anonymizer.address(&mut cache, b);
+                    assert_eq!(
+                        common_prefix(a, b),
+                        common_prefix(&anon_a, &anon_b),
+                        "{} {}",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+        assert_ne!(anonymizer.address(&mut cache, "10.1.2.3"), "10.1.2.3");
+        // the same key maps an address the same way without the cache
+        assert_eq!(
+            anonymizer.address(&mut FlipCache::new(), "10.1.2.3"),
+            anonymizer.address(&mut cache, "10.1.2.3")
+        );
+    }
+
+    #[test]
+    fn hmac_mode_and_macs() {
+        let anonymizer = anonymizer("hmac", b"0123456789abcdef");
+        let mut cache = FlipCache::new();
+        let address = anonymizer.address(&mut cache, "10.1.2.3");
+        assert_eq!(address.len(), 32);
+        assert_ne!(address, anonymizer.address(&mut cache, "10.1.2.4"));
+
+        assert_eq!(anonymizer.mac(NO_MAC), NO_MAC);
+        let mac = anonymizer.mac("00:1B:44:11:3A:B7");
+        assert_eq!(mac, anonymizer.mac("00:1b:44:11:3a:b7"));
+        let first = u8::from_str_radix(&mac[..2], 16).unwrap();
+        assert_eq!(first & 0x03, 0x02, "{} is not locally administered unicast", mac);
+    }
+
+    #[test]
+    fn apply_rewrites_addresses_and_macs() {
+        let key_spec = std::env::temp_dir().join(format!("gnat-{}-anonymize.key", std::process::id()));
+        fs::write(&key_spec, "0123456789abcdef\n").unwrap();
+        let key_spec = key_spec.to_string_lossy().to_string();
+        let id = crate::core::context::next();
+        std::thread::spawn(move || {
+            crate::core::context::enter(id);
+            assert!(!enabled());
+            assert!(enable(&String::from("xor"), &key_spec).is_err());
+            enable(&String::from("prefix"), &key_spec).unwrap();
+            let conn = Connection::open_in_memory().unwrap();
+            conn.execute_batch(
+                "CREATE TABLE flow AS SELECT * FROM (VALUES
+                    ('10.1.2.3', '10.1.2.4', '00:1b:44:11:3a:b7', '00:00:00:00:00:00'),
+                    ('10.1.2.4', NULL, NULL, '00:1b:44:11:3a:b7'))
+                    t(saddr, daddr, smac, dmac);",
+            )
+            .unwrap();
+            let select = apply(&conn, &String::from("SELECT * FROM flow")).unwrap();
+            let rows: Vec<(String, Option<String>, Option<String>, String)> = conn
+                .prepare(&format!("{} ORDER BY saddr;", select))
+                .unwrap()
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            let anonymizer = ANONYMIZER.get().unwrap();
+            let mut cache = FlipCache::new();
+            let (a, b) = (
+                anonymizer.address(&mut cache, "10.1.2.3"),
+                anonymizer.address(&mut cache, "10.1.2.4"),
+            );
+            let mac = anonymizer.mac("00:1b:44:11:3a:b7");
+            let mut expected = vec![
+                (a.clone(), Some(b.clone()), Some(mac.clone()), String::from(NO_MAC)),
+                (b, None, None, mac),
+            ];
+            expected.sort();
+            assert_eq!(rows, expected);
+        })
+        .join()
+        .unwrap();
+    }
+}
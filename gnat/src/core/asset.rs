@@ -0,0 +1,291 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Asset inventory stage (gnat_asset)
+//
+// Internal addresses, those the import marked "private" in scountry or
+// dcountry, are tracked per observ in the asset table of a DuckDB file:
+//
+//   asset (observ, addr, mac, first_seen, last_seen, flows)
+//
+// mac is the last MAC seen with the address (smac for saddr, dmac for
+// daddr), so it is only meaningful for hosts on the segment YAF watches;
+// hosts beyond a router carry the router's MAC.
+//
+// Each batch raises "asset" triggers (see trigger.rs):
+//
+//   new-asset   an address not in the inventory; value is its flow count
+//   mac-change  a known address seen with another MAC; detail is old -> new
+//
+// No triggers are raised for the first `learn` hours of flows, while the
+// inventory fills. Input files are passed to the output unchanged.
+//
+
+use crate::core::error::GnatError;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::spool::process_directory;
+use crate::core::trigger::{self, TRIGGER_INSERT, TRIGGER_TABLE};
+
+use std::path::Path;
+
+use tracing::{error, info};
+
+const NO_MAC: &str = "00:00:00:00:00:00";
+
+pub struct Inventory {
+    pub inventory_spec: String,
+    pub trigger_spec: String,
+    pub learn: u64,
+}
+
+impl Inventory {
+    pub fn asset_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("asset")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let sql_command = format!(
+            "ATTACH '{inventory}' AS inventory;
+             CREATE TABLE IF NOT EXISTS inventory.asset (
+                observ VARCHAR, addr VARCHAR, mac VARCHAR,
+                first_seen TIMESTAMP, last_seen TIMESTAMP, flows UBIGINT,
+                PRIMARY KEY (observ, addr));
+             CREATE TABLE IF NOT EXISTS inventory.meta (started TIMESTAMP);
+             CREATE TABLE batch AS {source};
+             CREATE TABLE seen AS
+                SELECT observ, addr, arg_max(mac, last_seen) FILTER (WHERE mac IS NOT NULL) AS mac,
+                    min(first_seen) AS first_seen, max(last_seen) AS last_seen, sum(flows)::UBIGINT AS flows,
+                    any_value(tenant) AS tenant
+                FROM (SELECT observ, tenant, saddr AS addr, nullif(smac, '{no_mac}') AS mac,
+                            min(stime) AS first_seen, max(etime) AS last_seen, count() AS flows
+                        FROM batch WHERE scountry = 'private' GROUP BY ALL
+                      UNION ALL
+                      SELECT observ, tenant, daddr, nullif(dmac, '{no_mac}'), min(stime), max(etime), count()
+                        FROM batch WHERE dcountry = 'private' GROUP BY ALL)
+                GROUP BY observ, addr;
+             INSERT INTO inventory.meta SELECT min(first_seen) FROM seen
+                HAVING min(first_seen) IS NOT NULL AND NOT EXISTS (SELECT 1 FROM inventory.meta);
+             {trigger_table};",
+            inventory = self.inventory_spec,
+            source = source,
+            no_mac = NO_MAC,
+            trigger_table = TRIGGER_TABLE,
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        let learning: bool = conn
+            .query_row(
+                &format!(
+                    "SELECT coalesce(max(last_seen) < (SELECT min(started) FROM inventory.meta)
+                        + INTERVAL '{} hours', true) FROM seen;",
+                    self.learn
+                ),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(true);
+        let mut triggers = 0;
+        if !learning {
+            let sql_command = format!(
+                "{insert}
+                    SELECT s.first_seen, s.last_seen, s.observ, s.tenant, 'asset', 'new-asset', s.addr, NULL, NULL,
+                        printf('addr=%s, mac=%s', s.addr, coalesce(s.mac, '')), s.flows, NULL, s.mac
+                    FROM seen s ANTI JOIN inventory.asset a USING (observ, addr);
+                 {insert}
+                    SELECT s.first_seen, s.last_seen, s.observ, s.tenant, 'asset', 'mac-change', s.addr, NULL, NULL,
+                        printf('addr=%s, mac=%s', s.addr, s.mac), NULL, NULL, printf('%s -> %s', a.mac, s.mac)
+                    FROM seen s JOIN inventory.asset a USING (observ, addr) WHERE s.mac <> a.mac;",
+                insert = TRIGGER_INSERT
+            );
+            if let Err(e) = conn.execute_batch(&sql_command) {
+                error!("matching {} - {:?}", input_spec, e);
+                return Err(e.into());
+            }
+            let file_name = Path::new(input_spec)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            triggers = match trigger::write(&conn, &self.trigger_spec, "asset", &file_name) {
+                Ok(count) => count,
+                Err(e) => {
+                    error!("writing triggers for {} - {:?}", input_spec, e);
+                    return Err(e);
+                }
+            };
+        }
+
+        let sql_command = format!(
+            "COPY (SELECT * FROM batch) TO '{}' ({});",
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+
+        //
+        // update the inventory last, so a retry raises the same triggers
+        //
+        let sql_command = "INSERT INTO inventory.asset SELECT observ, addr, mac, first_seen, last_seen, flows FROM seen
+            ON CONFLICT (observ, addr) DO UPDATE SET
+                mac = coalesce(excluded.mac, mac),
+                first_seen = least(first_seen, excluded.first_seen),
+                last_seen = greatest(last_seen, excluded.last_seen),
+                flows = flows + excluded.flows;
+            DETACH inventory;";
+        if let Err(e) = conn.execute_batch(sql_command) {
+            error!("updating {} - {:?}", self.inventory_spec, e);
+            return Err(e.into());
+        }
+        info!("asset: {} [{} triggers]", input_spec, triggers);
+        Ok(())
+    }
+}
+
+pub fn asset(
+    inventory: Inventory,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+) -> Result<(), std::io::Error> {
+    info!("inventory spec: {}", inventory.inventory_spec);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("trigger spec: {}", inventory.trigger_spec);
+    info!("polling: {}", polling);
+    info!("learn: {} hours", inventory.learn);
+
+    process_directory(
+        "asset",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        |src_path, tmp_path| inventory.asset_file(src_path, tmp_path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    use std::fs;
+
+    // flows at hour past midnight, as (saddr, smac, scountry, daddr, dmac, dcountry)
+    fn write_input(path: &str, hour: u32, flows: &[(&str, &str, &str, &str, &str, &str)]) {
+        let rows: Vec<String> = flows
+            .iter()
+            .map(|(saddr, smac, scountry, daddr, dmac, dcountry)| {
+                format!(
+                    "('s1', TIMESTAMP '2024-01-01' + INTERVAL '{0} hours',
+                        TIMESTAMP '2024-01-01' + INTERVAL '{0} hours' + INTERVAL '1 minute',
+                        '{1}', '{2}', '{3}', '{4}', '{5}', '{6}')",
+                    hour, saddr, smac, scountry, daddr, dmac, dcountry
+                )
+            })
+            .collect();
+        schema::write_test_flows(
+            path,
+            &format!(
+                "SELECT * FROM (VALUES {}) t(observ, stime, etime, saddr, smac, scountry, daddr, dmac, dcountry)",
+                rows.join(", ")
+            ),
+        );
+    }
+
+    // (name, saddr, detail) of the triggers written to trigger_spec
+    fn triggers(trigger_spec: &str) -> Vec<(String, String, Option<String>)> {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let Ok(mut stmt) = conn.prepare(&format!(
+            "SELECT name, saddr, detail FROM read_parquet('{}/trigger.asset.*') ORDER BY ALL;",
+            trigger_spec
+        )) else {
+            return Vec::new();
+        };
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn new_assets_and_mac_changes_after_learning() {
+        let dir = test_dir("asset-inventory");
+        let trigger_spec = format!("{}/trigger", dir);
+        fs::create_dir_all(&trigger_spec).unwrap();
+        let inventory = Inventory {
+            inventory_spec: format!("{}/inventory.duckdb", dir),
+            trigger_spec: trigger_spec.clone(),
+            learn: 1,
+        };
+        let run = |name: &str, hour: u32, flows: &[(&str, &str, &str, &str, &str, &str)]| {
+            let input_spec = format!("{}/{}.parquet", dir, name);
+            let output_spec = format!("{}/{}.out.parquet", dir, name);
+            write_input(&input_spec, hour, flows);
+            inventory.asset_file(&input_spec, &output_spec).unwrap();
+        };
+
+        // learning
+        run("a", 0, &[("10.0.0.1", "aa", "private", "8.8.8.8", "ff", "us")]);
+        assert!(triggers(&trigger_spec).is_empty());
+
+        run(
+            "b",
+            2,
+            &[
+                ("10.0.0.1", "bb", "private", "8.8.8.8", "ff", "us"),
+                ("8.8.8.8", "ff", "us", "10.0.0.2", "cc", "private"),
+                ("10.0.0.3", NO_MAC, "private", "8.8.8.8", "ff", "us"),
+            ],
+        );
+        let string = |s: &str| String::from(s);
+        assert_eq!(
+            triggers(&trigger_spec),
+            vec![
+                (string("mac-change"), string("10.0.0.1"), Some(string("aa -> bb"))),
+                (string("new-asset"), string("10.0.0.2"), Some(string("cc"))),
+                (string("new-asset"), string("10.0.0.3"), None),
+            ]
+        );
+
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!("ATTACH '{}' AS inventory (READ_ONLY);", inventory.inventory_spec))
+            .unwrap();
+        let mut stmt = conn
+            .prepare("SELECT addr, mac, flows FROM inventory.asset ORDER BY addr;")
+            .unwrap();
+        let assets: Vec<(String, Option<String>, u64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            assets,
+            vec![
+                (string("10.0.0.1"), Some(string("bb")), 2),
+                (string("10.0.0.2"), Some(string("cc")), 1),
+                (string("10.0.0.3"), None, 1),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -9,79 +9,245 @@
 use chrono::Datelike;
 use chrono::Timelike;
 use chrono::Utc;
-use duckdb::Connection;
-use std::env;
+use crate::core::lineage;
+use crate::core::logging;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::shutdown;
+use crate::core::watermark;
+use std::ffi::CString;
 use std::fs;
+use std::io::ErrorKind;
 use std::path::Path;
-use std::thread;
 use std::time::Duration;
-use std::time::SystemTime;
+use std::time::Instant;
+use tracing::{error, info};
 
-pub fn batch_files(output_spec: &String, tag: &String) {
-    let conn = match Connection::open_in_memory() {
+// merged flows are written in this order, so a merge of the same files
+// gives the same output whatever order they arrived in
+const FLOW_ORDER: &str = "stime, etime, observ, saddr, daddr, sport, dport, proto";
+
+//
+// Rename from to to, failing with AlreadyExists rather than replacing to
+//
+fn rename_noreplace(from: &str, to: &str) -> Result<(), std::io::Error> {
+    let (c_from, c_to) = (CString::new(from)?, CString::new(to)?);
+    let rc = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            c_from.as_ptr(),
+            libc::AT_FDCWD,
+            c_to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if rc == 0 {
+        return Ok(());
+    }
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() != Some(libc::EINVAL) {
+        return Err(e);
+    }
+    // file systems without RENAME_NOREPLACE
+    if Path::new(to).exists() {
+        return Err(ErrorKind::AlreadyExists.into());
+    }
+    fs::rename(from, to)
+}
+
+//
+// Move tmp_filename to final_filename in the output directory, or to
+// final_filename with a -<n> suffix while an output of that name hasn't
+// been consumed yet
+//
+fn publish(tmp_filename: &str, final_filename: &str) -> Result<(), std::io::Error> {
+    let stem = final_filename.trim_end_matches(".parquet");
+    let mut target = String::from(final_filename);
+    let mut suffix = 0;
+    loop {
+        match rename_noreplace(tmp_filename, &target) {
+            Ok(()) => break,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                suffix += 1;
+                target = format!("{}-{}.parquet", stem, suffix);
+            }
+            Err(e) => {
+                error!("renaming {} {} - {:?}", tmp_filename, target, e);
+                return Err(e);
+            }
+        }
+    }
+    info!("Batch: generated {}", target);
+    Ok(())
+}
+
+//
+// Publish the outputs in order; on a failure the ones not yet published are
+// removed
+//
+fn publish_all(outputs: &[(String, String)]) -> Result<(), std::io::Error> {
+    for (i, (tmp_filename, final_filename)) in outputs.iter().enumerate() {
+        if let Err(e) = publish(tmp_filename, final_filename) {
+            for (tmp_filename, _) in outputs[i..].iter() {
+                let _ = fs::remove_file(tmp_filename);
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+//
+// Merge the claimed files into one output, or with bucket_minutes > 0 into
+// one output per aligned window of stime, named by a hash of the merged
+// file names (and the window); the windows are all written before any is
+// published, so a failed merge publishes none of them
+//
+pub fn batch_files(
+    input_spec: &str,
+    output_spec: &str,
+    tag: &str,
+    bucket_minutes: u32,
+) -> Result<(), std::io::Error> {
+    let conn = match scratch::open_in_memory("batch") {
         Ok(s) => s,
         Err(e) => panic!("Error: open_in_memory() - {}", e),
     };
 
-    let epoch = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .expect("UNIX_EPOCH");
-    let tmp_filename = format!(".duck_batch-{}.parquet", epoch.as_millis());
-    let final_filename = format!("{}/{}{}", output_spec, tag, tmp_filename);
+    //
+    // upgrade each input to the current schema so files written before a
+    // schema change merge with newer ones
+    //
+    let mut sources: Vec<String> = Vec::new();
+    let mut inputs: Vec<(String, String)> = Vec::new();
+    for entry in fs::read_dir(input_spec).unwrap() {
+        let file: fs::DirEntry = entry.unwrap();
+        let file_name = String::from(file.file_name().to_string_lossy());
+        if file_name.starts_with(".gnat_batch") && file_name.ends_with(".parquet") {
+            let src_path = String::from(file.path().to_string_lossy());
+            match schema::select(&conn, &src_path) {
+                Ok(s) => sources.push(s),
+                Err(e) => panic!("Error: reading {} {:?}", file_name, e),
+            }
+            let source_name = file_name.trim_start_matches(".gnat_batch-");
+            inputs.push((String::from(source_name), src_path));
+        }
+    }
+
+    // the same inputs are always published under the same names
+    let mut names: Vec<&String> = inputs.iter().map(|(name, _)| name).collect();
+    names.sort();
+    let names = names.iter().map(|name| name.as_str()).collect::<Vec<&str>>().join("|");
+    let digest: String = match conn.query_row("SELECT md5(?);", [&names], |row| row.get(0)) {
+        Ok(d) => d,
+        Err(e) => panic!("Error: hashing batch names {:?}", e),
+    };
+    let batch_name = format!(".duck_batch-{}.parquet", digest);
+    let tmp_filename = format!("{}/{}", input_spec, batch_name);
+    let final_filename = format!("{}/{}{}", output_spec, tag, batch_name);
+    let _batch = logging::batch(&final_filename);
+    let _lineage = lineage::begin("batch", &inputs);
 
-    println!("Batch: merging...");
+    info!("Batch: merging...");
 
-    let sql_command = format!(
-        "COPY (SELECT * FROM read_parquet('.gnat_batch*.parquet')) TO '{}' (FORMAT 'parquet', CODEC 'snappy', ROW_GROUP_SIZE 100_000);",
-        tmp_filename
-    );
-    match conn.execute_batch(&sql_command) {
-        Ok(c) => c,
-        Err(e) => {
+    if bucket_minutes == 0 {
+        let sql_command = format!(
+            "COPY (SELECT * FROM ({}) ORDER BY {}) TO '{}' ({});",
+            sources.join(" UNION ALL BY NAME "),
+            FLOW_ORDER,
+            tmp_filename,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
             panic!("Error: batching files {:?}", e);
         }
-    };
+        return publish_all(&[(tmp_filename, final_filename)]);
+    }
+
+    let bucket = format!(
+        "time_bucket(INTERVAL '{} minutes', coalesce(stime, TIMESTAMP '1970-01-01'))",
+        bucket_minutes
+    );
+    let sql_command = format!(
+        "CREATE TABLE memtable AS SELECT *, {} AS gnat_bucket FROM ({});",
+        bucket,
+        sources.join(" UNION ALL BY NAME ")
+    );
+    if let Err(e) = conn.execute_batch(&sql_command) {
+        panic!("Error: batching files {:?}", e);
+    }
 
-    match fs::rename(tmp_filename.clone(), final_filename.clone()) {
-        Ok(_s) => println!("Batch: generated {}", final_filename),
-        Err(error) => panic!(
-            "Error: renaming {} {}: {:?}",
-            tmp_filename.clone(),
-            final_filename.clone(),
-            error
-        ),
+    let windows: Vec<String> = match conn
+        .prepare("SELECT DISTINCT strftime(gnat_bucket, '%Y%m%dT%H%M') FROM memtable ORDER BY 1;")
+        .and_then(|mut stmt| stmt.query_map([], |row| row.get(0))?.collect())
+    {
+        Ok(w) => w,
+        Err(e) => panic!("Error: bucketing files {:?}", e),
     };
+    let mut outputs: Vec<(String, String)> = Vec::new();
+    for window in windows.iter() {
+        let batch_name = format!(".duck_batch-{}-{}.parquet", window, digest);
+        let tmp_filename = format!("{}/{}", input_spec, batch_name);
+        let final_filename = format!("{}/{}{}", output_spec, tag, batch_name);
+        let sql_command = format!(
+            "COPY (SELECT * EXCLUDE (gnat_bucket) FROM memtable
+                WHERE strftime(gnat_bucket, '%Y%m%dT%H%M') = '{}' ORDER BY {})
+                TO '{}' ({});",
+            window,
+            FLOW_ORDER,
+            tmp_filename,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            panic!("Error: batching window {} {:?}", window, e);
+        }
+        outputs.push((tmp_filename, final_filename));
+    }
+    publish_all(&outputs)?;
+    info!("Batch: {} windows of {} min", windows.len(), bucket_minutes);
+    Ok(())
 }
 
-fn sleep_minutes(minutes: u32) {
+//
+// returns false if shutdown was requested before the interval elapsed;
+// returns early once the files waiting in input_spec reach target_bytes
+// (0 = no target)
+//
+fn sleep_minutes(input_spec: &String, minutes: u32, target_bytes: u64) -> bool {
+    let start = Instant::now();
     let mut last = Utc::now();
     let sleep_interval = Duration::from_secs(5);
 
     loop {
-        thread::sleep(sleep_interval);
-        
+        if !shutdown::sleep(sleep_interval) {
+            return false;
+        }
+
+        if target_bytes > 0 && watermark::depth(input_spec).1 >= target_bytes {
+            info!("Batch: input reached the target size");
+            return true;
+        }
+
         let now = Utc::now();
         match minutes {
             1 => {
                 if now.minute() != last.minute() {
-                    return;
+                    return true;
                 }
             }
             60 => {
                 if now.hour() != last.hour() {
-                    return;
+                    return true;
                 }
             }
             1440 => {
                 if now.day() != last.day() {
-                    return;
+                    return true;
                 }
             }
             _ => {
-                let delta = now.minute() - last.minute();
-                if delta >= minutes {
-                    // wait for hour to change
-                    return;
+                if start.elapsed() >= Duration::from_secs(minutes as u64 * 60) {
+                    return true;
                 }
             }
         }
@@ -92,50 +258,100 @@ fn sleep_minutes(minutes: u32) {
 pub fn batch(
     tag_spec: String,
     minutes: u32,
+    target_mb: u64,
+    bucket_minutes: u32,
     input_spec: String,
     output_spec: String,
 ) -> Result<(), std::io::Error> {
-    println!("\tbatch interval: {} min", minutes);
-    println!("\tinput spec: {}", input_spec);
-    println!("\toutput spec: {}", output_spec);
-    println!("\ttag spec: {}", tag_spec);
-
-    let input_dir = Path::new(input_spec.as_str());
-    if !env::set_current_dir(&input_dir).is_ok() {
-        panic!(
-            "Error: unable to set working directory to {}",
-            input_dir.display()
-        );
-    }
+    info!("batch interval: {} min", minutes);
+    info!("batch target: {} MB", target_mb);
+    info!("batch buckets: {} min", bucket_minutes);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("tag spec: {}", tag_spec);
 
+    let _pool = scratch::pool();
     loop {
+        // on shutdown, merge whatever has arrived before exiting
+        let running = sleep_minutes(&input_spec, minutes, target_mb.saturating_mul(1024 * 1024));
 
-        sleep_minutes(minutes);
-
-        println!("Batch: scanning...");
+        info!("Batch: scanning...");
         let mut counter = 0;
-        for entry in fs::read_dir(".").unwrap() {
+        for entry in fs::read_dir(&input_spec).unwrap() {
             let file: fs::DirEntry = entry.unwrap();
             let file_name = String::from(file.file_name().to_string_lossy());
 
             if !file_name.starts_with(".") && file_name.ends_with(".parquet") {
-                let new_name = format!(".gnat_batch-{}", file_name);
-                fs::rename(file_name.clone(), new_name).unwrap();
+                let new_path = format!("{}/.gnat_batch-{}", input_spec, file_name);
+                fs::rename(file.path(), new_path).unwrap();
+                counter += 1;
+            } else if file_name.starts_with(".gnat_batch") && file_name.ends_with(".parquet") {
+                // claimed before a failed publish
                 counter += 1;
             }
         }
 
         if counter > 0 {
-            batch_files(&output_spec, &tag_spec);
+            // the merge still runs on shutdown so claimed files aren't lost;
+            // a failed publish keeps them for the next start
+            let _ = watermark::wait("batch", &output_spec);
+            batch_files(&input_spec, &output_spec, &tag_spec, bucket_minutes)?;
 
-            for entry in fs::read_dir(".").unwrap() {
+            for entry in fs::read_dir(&input_spec).unwrap() {
                 let file: fs::DirEntry = entry.unwrap();
                 let file_name = String::from(file.file_name().to_string_lossy());
 
                 if file_name.starts_with(".gnat_batch") && file_name.ends_with(".parquet") {
-                    fs::remove_file(file_name).unwrap();
+                    fs::remove_file(file.path()).unwrap();
                 }
             }
         }
+
+        if !running {
+            info!("Batch: shutting down");
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    #[test]
+    fn publish_keeps_unconsumed_outputs() {
+        let dir = test_dir("publish");
+        let final_filename = format!("{}/out.parquet", dir);
+        for content in ["first", "second"] {
+            let tmp_filename = format!("{}/.tmp.parquet", dir);
+            fs::write(&tmp_filename, content).unwrap();
+            publish(&tmp_filename, &final_filename).unwrap();
+            assert!(!Path::new(&tmp_filename).exists());
+        }
+        assert_eq!(fs::read_to_string(&final_filename).unwrap(), "first");
+        assert_eq!(fs::read_to_string(format!("{}/out-1.parquet", dir)).unwrap(), "second");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn publish_all_removes_unpublished() {
+        let dir = test_dir("publish-all");
+        let mut outputs = Vec::new();
+        for (window, output_dir) in [("a", dir.clone()), ("b", format!("{}/missing", dir))] {
+            let tmp_filename = format!("{}/.tmp-{}.parquet", dir, window);
+            fs::write(&tmp_filename, window).unwrap();
+            outputs.push((tmp_filename, format!("{}/{}.parquet", output_dir, window)));
+        }
+        outputs.push((format!("{}/.tmp-c.parquet", dir), format!("{}/c.parquet", dir)));
+        fs::write(&outputs[2].0, "c").unwrap();
+
+        assert!(publish_all(&outputs).is_err());
+        assert!(Path::new(&format!("{}/a.parquet", dir)).exists());
+        for (tmp_filename, _) in outputs.iter() {
+            assert!(!Path::new(tmp_filename).exists());
+        }
+        assert!(!Path::new(&format!("{}/c.parquet", dir)).exists());
+        let _ = fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,321 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Beaconing detection stage (gnat_beacon)
+//
+// Command and control implants tend to call home on a timer: many small
+// flows between the same pair, evenly spaced. Per (observ, saddr, daddr,
+// proto, dport) the stage takes the gaps between the start times of the
+// flows in a sliding window and scores their regularity as
+//
+//   score = max(0, 1 - stddev(gap) / mean(gap))
+//
+// so 1.0 is a perfect timer and jittered beacons still score high. A pair
+// raises a "beacon" trigger (see trigger.rs) when it has at least min_flows
+// flows in the window, averages no more than max_bytes per flow, its mean
+// gap is at least MIN_INTERVAL and the score reaches the threshold.
+//
+// The window ending at each step boundary is evaluated once the newest
+// flow seen is the grace period past that boundary. The start times of the
+// flows the next window needs are held in a hidden pending file in the
+// trigger directory. Input files are passed to the output unchanged.
+//
+
+use crate::core::error::GnatError;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::spool::process_directory;
+use crate::core::suppress;
+use crate::core::trigger::{self, TRIGGER_INSERT, TRIGGER_TABLE};
+
+use std::fs;
+use std::path::Path;
+
+use tracing::{error, info};
+
+// mean gap (seconds) below which a run of flows is a burst, not a beacon
+const MIN_INTERVAL: u64 = 10;
+
+pub struct Beacon {
+    pub trigger_spec: String,
+    pub suppress_spec: String,
+    pub window: u64,
+    pub step: u64,
+    pub grace: u64,
+    pub min_flows: u64,
+    pub max_bytes: u64,
+    pub threshold: f64,
+}
+
+impl Beacon {
+    fn pending_spec(&self) -> String {
+        format!("{}/.beacon-pending.parquet", self.trigger_spec)
+    }
+
+    //
+    // The step boundary the newest flow of a table is the grace period past
+    //
+    fn boundary(&self, conn: &duckdb::Connection, table: &String) -> Option<String> {
+        conn.query_row(
+            &format!(
+                "SELECT strftime(time_bucket(INTERVAL '{} minutes', max(stime) - INTERVAL '{} seconds'),
+                    '%Y-%m-%d %H:%M:%S') FROM {};",
+                self.step, self.grace, table
+            ),
+            [],
+            |row| row.get::<_, Option<String>>(0),
+        )
+        .ok()
+        .flatten()
+    }
+
+    fn insert(&self, end: &String) -> String {
+        format!(
+            "{insert}
+                SELECT TIMESTAMP '{end}' - INTERVAL '{window} hours', TIMESTAMP '{end}', observ, tenant,
+                    'beacon', 'periodicity', saddr, daddr, dport,
+                    printf('saddr=%s, daddr=%s, proto=%s, dport=%d', saddr, daddr, proto, dport),
+                    score, {threshold},
+                    printf('flows=%d, interval=%.0fs, jitter=%.1fs, bytes=%.0f', flows, mean, jitter, bytes)
+                FROM (SELECT observ, tenant, saddr, daddr, proto, dport, count() AS flows,
+                        avg(gap) / 1e6 AS mean, stddev_pop(gap) / 1e6 AS jitter, avg(bytes) AS bytes,
+                        greatest(0, 1 - stddev_pop(gap) / avg(gap)) AS score
+                    FROM (SELECT *, epoch_us(stime) - epoch_us(lag(stime) OVER
+                                (PARTITION BY observ, tenant, saddr, daddr, proto, dport ORDER BY stime)) AS gap
+                            FROM memtable
+                            WHERE stime >= TIMESTAMP '{end}' - INTERVAL '{window} hours' AND stime < TIMESTAMP '{end}')
+                    GROUP BY ALL
+                    HAVING count() >= {min_flows} AND avg(bytes) <= {max_bytes} AND avg(gap) >= {min_interval} * 1e6)
+                WHERE score >= {threshold};",
+            insert = TRIGGER_INSERT,
+            end = end,
+            window = self.window,
+            threshold = self.threshold,
+            min_flows = self.min_flows,
+            max_bytes = self.max_bytes,
+            min_interval = MIN_INTERVAL,
+        )
+    }
+
+    pub fn beacon_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("beacon")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let pending_spec = self.pending_spec();
+        let pending = Path::new(&pending_spec).exists();
+        let kept = suppress::exclude(&conn, &self.suppress_spec, "beacon", "batch");
+        let mut sql_command = format!(
+            "CREATE TABLE batch AS {};
+             CREATE TABLE memtable AS SELECT observ, tenant, saddr, daddr, proto, dport, stime,
+                (sbytes + dbytes)::UBIGINT AS bytes FROM batch WHERE {}",
+            source, kept
+        );
+        if pending {
+            sql_command.push_str(&format!(" UNION ALL BY NAME SELECT * FROM '{}'", pending_spec));
+        }
+        sql_command.push_str(&format!("; {};", TRIGGER_TABLE));
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        let sql_command = format!(
+            "COPY (SELECT * FROM batch) TO '{}' ({});",
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+        let Some(end) = self.boundary(&conn, &String::from("memtable")) else {
+            // an empty batch with nothing held
+            return Ok(());
+        };
+
+        //
+        // evaluate the window ending at the newest boundary, unless the
+        // previous batch already reached it
+        //
+        let previous = if pending {
+            self.boundary(&conn, &format!("'{}'", pending_spec))
+        } else {
+            None
+        };
+        let mut triggers = 0;
+        if previous.as_ref().is_none_or(|previous| *previous < end) {
+            if let Err(e) = conn.execute_batch(&self.insert(&end)) {
+                error!("scoring {} - {:?}", input_spec, e);
+                return Err(e.into());
+            }
+            let file_name = Path::new(input_spec)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            triggers = match trigger::write(&conn, &self.trigger_spec, "beacon", &file_name) {
+                Ok(count) => count,
+                Err(e) => {
+                    error!("writing triggers for {} - {:?}", input_spec, e);
+                    return Err(e);
+                }
+            };
+        }
+
+        //
+        // the next window starts no earlier than a step past this one's
+        // start; the pending file is replaced last, as in detect.rs
+        //
+        let tmp_spec = format!("{}.tmp", pending_spec);
+        let sql_command = format!(
+            "COPY (SELECT * FROM memtable
+                WHERE stime >= TIMESTAMP '{}' + INTERVAL '{} minutes' - INTERVAL '{} hours'
+                ORDER BY stime) TO '{}' ({});",
+            end,
+            self.step,
+            self.window,
+            tmp_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", tmp_spec, e);
+            return Err(e.into());
+        }
+        if let Err(e) = fs::rename(&tmp_spec, &pending_spec) {
+            error!("moving {} -> {} - {:?}", tmp_spec, pending_spec, e);
+            return Err(e.into());
+        }
+        info!("beacon: {} [{} triggers]", input_spec, triggers);
+        Ok(())
+    }
+}
+
+pub fn beacon(
+    beacon: Beacon,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+) -> Result<(), std::io::Error> {
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("trigger spec: {}", beacon.trigger_spec);
+    if !beacon.suppress_spec.is_empty() {
+        info!("suppression spec: {}", beacon.suppress_spec);
+    }
+    info!("polling: {}", polling);
+    info!("window: {} hours", beacon.window);
+    info!("step: {} minutes", beacon.step);
+    info!("grace: {}", beacon.grace);
+    info!("min flows: {}", beacon.min_flows);
+    info!("max bytes: {}", beacon.max_bytes);
+    info!("threshold: {}", beacon.threshold);
+
+    process_directory(
+        "beacon",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        |src_path, tmp_path| beacon.beacon_file(src_path, tmp_path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    // a flow a minute from each saddr, of sbytes bytes, at the given
+    // minutes past midnight
+    fn write_input(path: &str, sources: &[(&str, u64)], minutes: std::ops::Range<u32>) {
+        let mut rows: Vec<String> = Vec::new();
+        for (saddr, sbytes) in sources {
+            for m in minutes.clone() {
+                rows.push(format!(
+                    "('s1', TIMESTAMP '2024-01-01' + INTERVAL '{} minutes', '{}', '10.0.0.9', '6', 443, {}, 0)",
+                    m, saddr, sbytes
+                ));
+            }
+        }
+        schema::write_test_flows(
+            path,
+            &format!(
+                "SELECT * FROM (VALUES {}) t(observ, stime, saddr, daddr, proto, dport, sbytes, dbytes)",
+                rows.join(", ")
+            ),
+        );
+    }
+
+    // (etime, saddr, detail) of the triggers written to trigger_spec
+    fn triggers(trigger_spec: &str) -> Vec<(String, String, String)> {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let Ok(mut stmt) = conn.prepare(&format!(
+            "SELECT strftime(etime, '%H:%M'), saddr, detail FROM read_parquet('{}/trigger.beacon.*') ORDER BY ALL;",
+            trigger_spec
+        )) else {
+            return Vec::new();
+        };
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn windows_span_input_files() {
+        let dir = test_dir("beacon-windows");
+        let trigger_spec = format!("{}/trigger", dir);
+        fs::create_dir_all(&trigger_spec).unwrap();
+        let beacon = Beacon {
+            trigger_spec: trigger_spec.clone(),
+            suppress_spec: String::new(),
+            window: 1,
+            step: 10,
+            grace: 0,
+            min_flows: 10,
+            max_bytes: 1000,
+            threshold: 0.9,
+        };
+        let sources = [("10.0.0.1", 100), ("10.0.0.2", 5000)];
+        let run = |name: &str, minutes: std::ops::Range<u32>| {
+            let input_spec = format!("{}/{}.parquet", dir, name);
+            let output_spec = format!("{}/{}.out.parquet", dir, name);
+            write_input(&input_spec, &sources, minutes);
+            beacon.beacon_file(&input_spec, &output_spec).unwrap();
+        };
+
+        // nothing before the first boundary
+        run("a", 0..9);
+        assert!(triggers(&trigger_spec).is_empty());
+
+        // the window ending 00:10 counts the flows held from the first file;
+        // the second pair's flows are too large
+        run("b", 9..13);
+        assert_eq!(
+            triggers(&trigger_spec),
+            vec![(
+                String::from("00:10"),
+                String::from("10.0.0.1"),
+                String::from("flows=10, interval=60s, jitter=0.0s, bytes=100")
+            )]
+        );
+
+        // the same boundary isn't evaluated again
+        run("c", 13..15);
+        assert_eq!(triggers(&trigger_spec).len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
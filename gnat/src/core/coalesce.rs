@@ -0,0 +1,486 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Output coalescing (--coalesce-mb, --coalesce-minutes)
+//
+// The spool writes one output per input. With --coalesce-mb set, outputs
+// are instead moved into a hidden directory of --output owned by this
+// instance, and merged into one file, named after the oldest, once they
+// add up to --coalesce-mb or the oldest has waited --coalesce-minutes.
+// Downstream stages and the archive then see fewer, larger files. What is
+// held is published when the stage stops.
+//
+// The held outputs are already committed: their inputs have moved on. A
+// merge is recorded in a FLUSH_RECORD before the merged file is renamed
+// into place, and the directory is locked like a claim (see spool.rs), so
+// an instance starting after a crash finishes the merge of a stopped one,
+// or publishes what it held, without losing or duplicating flows.
+//
+
+use crate::core::context::Local;
+use crate::core::lineage;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::spool;
+
+use chrono::Utc;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const LOCK: &str = ".lock";
+const MERGED: &str = ".merged.parquet";
+const FLUSH_RECORD: &str = ".flush";
+const DEFAULT_MINUTES: u64 = 5;
+
+#[derive(Debug, clap::Args)]
+pub struct CoalesceArgs {
+    /// merge outputs into files of about this many MB (0 = one output per input)
+    #[arg(long)]
+    pub coalesce_mb: Option<u64>,
+
+    /// publish merged outputs once the oldest has waited this long
+    #[arg(long)]
+    pub coalesce_minutes: Option<u64>,
+}
+
+struct Policy {
+    target_bytes: u64,
+    max_age: Duration,
+}
+
+static POLICY: Local<Policy> = Local::new();
+
+//
+// 0 disables coalescing
+//
+pub fn set_coalesce(target_mb: u64, max_minutes: u64) {
+    info!("coalesce MB: {}", target_mb);
+    info!("coalesce minutes: {}", max_minutes);
+    let _ = POLICY.set(Policy {
+        target_bytes: target_mb.saturating_mul(1024 * 1024),
+        max_age: Duration::from_secs(max_minutes.saturating_mul(60)),
+    });
+}
+
+//
+// Set the coalescing of a stage's --coalesce-* arguments
+//
+pub fn configure(args: &CoalesceArgs) {
+    set_coalesce(
+        args.coalesce_mb.unwrap_or(0),
+        args.coalesce_minutes.unwrap_or(DEFAULT_MINUTES),
+    );
+}
+
+fn prefix(stage: &str) -> String {
+    format!(".coalesce-{}-{}-", stage, spool::hostname())
+}
+
+//
+// Where a stage's outputs are published: output_spec itself, or the
+// instance directory of a Held when coalescing is on. Shared by the
+// workers of process_directory_parallel().
+//
+pub(crate) struct Coalescer {
+    pub(crate) output_spec: String,
+    held: Option<Mutex<Held>>,
+}
+
+impl Coalescer {
+    pub(crate) fn open(stage: &str, output_spec: &str) -> Result<Coalescer, std::io::Error> {
+        Ok(Coalescer {
+            output_spec: String::from(output_spec),
+            held: match POLICY.get().filter(|policy| policy.target_bytes > 0) {
+                Some(policy) => Some(Mutex::new(Held::open(stage, output_spec, policy)?)),
+                None => None,
+            },
+        })
+    }
+
+    //
+    // Path the output of file_name is renamed to once it is written
+    //
+    pub(crate) fn dst_path(&self, file_name: &str) -> String {
+        match &self.held {
+            Some(held) => held.lock().unwrap().path(file_name),
+            None => format!("{}/{}", self.output_spec, file_name),
+        }
+    }
+
+    //
+    // Called once the output of file_name is at dst_path(), if it was
+    // written at all
+    //
+    pub(crate) fn add(&self, file_name: &str) -> Result<(), std::io::Error> {
+        match &self.held {
+            Some(held) => {
+                let mut held = held.lock().unwrap();
+                if Path::new(&held.path(file_name)).exists() {
+                    held.add(file_name)?;
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn tick(&self) -> Result<(), std::io::Error> {
+        match &self.held {
+            Some(held) => held.lock().unwrap().tick(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn close(self) -> Result<(), std::io::Error> {
+        match self.held {
+            Some(held) => held.into_inner().unwrap().close(),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Held {
+    stage: String,
+    output_spec: String,
+    spec: String,
+    target_bytes: u64,
+    max_age: Duration,
+    // held outputs, oldest first, by file name
+    files: Vec<String>,
+    bytes: u64,
+    oldest: Option<Instant>,
+    _lock: fs::File,
+}
+
+impl Held {
+    //
+    // This instance's directory in output_spec, after taking over those of
+    // stopped instances
+    //
+    fn open(stage: &str, output_spec: &str, policy: &Policy) -> Result<Held, std::io::Error> {
+        static DIRS: AtomicUsize = AtomicUsize::new(0);
+        let id = format!(
+            "{}-{}-{}",
+            std::process::id(),
+            Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+            DIRS.fetch_add(1, Ordering::Relaxed)
+        );
+        // locked before it is renamed into place, as claims are
+        let staging_spec = format!("{}/.coalescing-{}-{}", output_spec, stage, id);
+        fs::create_dir(&staging_spec)?;
+        let lock = fs::File::create(format!("{}/{}", staging_spec, LOCK))?;
+        if !spool::try_lock(&lock) {
+            return Err(std::io::Error::other(format!("locking {}", staging_spec)));
+        }
+        let spec = format!("{}/{}{}", output_spec, prefix(stage), id);
+        fs::rename(&staging_spec, &spec)?;
+
+        let mut held = Held {
+            stage: String::from(stage),
+            output_spec: String::from(output_spec),
+            spec,
+            target_bytes: policy.target_bytes,
+            max_age: policy.max_age,
+            files: Vec::new(),
+            bytes: 0,
+            oldest: None,
+            _lock: lock,
+        };
+        held.recover()?;
+        Ok(held)
+    }
+
+    //
+    // Path in this instance's directory for the output of file_name
+    //
+    fn path(&self, file_name: &str) -> String {
+        format!("{}/{}", self.spec, file_name)
+    }
+
+    //
+    // Hold the output moved to path(file_name), then merge if due
+    //
+    fn add(&mut self, file_name: &str) -> Result<(), std::io::Error> {
+        self.bytes += fs::metadata(self.path(file_name))?.len();
+        self.files.push(String::from(file_name));
+        self.oldest.get_or_insert_with(Instant::now);
+        if self.bytes >= self.target_bytes {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    //
+    // Merge once the oldest held output has waited max_age
+    //
+    fn tick(&mut self) -> Result<(), std::io::Error> {
+        match self.oldest {
+            Some(oldest) if oldest.elapsed() >= self.max_age => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    //
+    // Publish the held outputs as one file named after the oldest
+    //
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        let files = std::mem::take(&mut self.files);
+        self.bytes = 0;
+        self.oldest = None;
+        let Some(first) = files.first() else {
+            return Ok(());
+        };
+        let dst_path = format!("{}/{}", self.output_spec, first);
+        if files.len() == 1 {
+            return fs::rename(self.path(first), &dst_path);
+        }
+
+        let merged_path = format!("{}/{}", self.spec, MERGED);
+        let sources: Vec<(String, String)> = files
+            .iter()
+            .map(|file_name| (file_name.clone(), self.path(file_name)))
+            .collect();
+        {
+            let _lineage = lineage::begin(&self.stage, &sources);
+            let conn = scratch::open_in_memory(&self.stage)?;
+            let paths: Vec<String> = sources
+                .iter()
+                .map(|(_, path)| format!("'{}'", path))
+                .collect();
+            conn.execute_batch(&format!(
+                "COPY (SELECT * FROM read_parquet([{}], union_by_name = true)) TO '{}' ({});",
+                paths.join(", "),
+                merged_path,
+                schema::copy_options()
+            ))
+            .map_err(std::io::Error::other)?;
+        }
+        fs::File::open(&merged_path)?.sync_all()?;
+
+        let record_path = format!("{}/{}", self.spec, FLUSH_RECORD);
+        let mut record = fs::File::create(&record_path)?;
+        writeln!(record, "{}", dst_path)?;
+        for file_name in files.iter() {
+            writeln!(record, "{}", file_name)?;
+        }
+        record.sync_all()?;
+        finish(&self.spec)?;
+        info!(
+            "{} coalesced {} outputs into {}",
+            self.stage,
+            files.len(),
+            dst_path
+        );
+        Ok(())
+    }
+
+    //
+    // Publish what is held and remove the directory
+    //
+    fn close(mut self) -> Result<(), std::io::Error> {
+        self.flush()?;
+        let _ = fs::remove_file(format!("{}/{}", self.spec, LOCK));
+        fs::remove_dir(&self.spec)
+    }
+
+    //
+    // Take over the directories of stopped instances of this stage on this
+    // host, finishing their merges, and publish what they held
+    //
+    fn recover(&mut self) -> Result<(), std::io::Error> {
+        let prefix = prefix(&self.stage);
+        for entry in fs::read_dir(&self.output_spec)? {
+            let dir = entry?;
+            let dir_spec = String::from(dir.path().to_string_lossy());
+            if dir_spec == self.spec || !dir.file_name().to_string_lossy().starts_with(&prefix) {
+                continue;
+            }
+            let _lock = match fs::File::open(dir.path().join(LOCK)) {
+                Ok(lock) if spool::try_lock(&lock) => lock,
+                _ => continue,
+            };
+            finish(&dir_spec)?;
+            let _ = fs::remove_file(dir.path().join(MERGED));
+            for file_name in held(&dir_spec)? {
+                fs::rename(dir.path().join(&file_name), self.path(&file_name))?;
+                self.add(&file_name)?;
+            }
+            let _ = fs::remove_file(dir.path().join(LOCK));
+            fs::remove_dir(dir.path())?;
+            warn!("{} took over the outputs held in {}", self.stage, dir_spec);
+        }
+        self.flush()
+    }
+}
+
+//
+// Outputs held in spec, oldest first
+//
+fn held(spec: &str) -> Result<Vec<String>, std::io::Error> {
+    let mut files: Vec<String> = fs::read_dir(spec)?
+        .filter_map(Result::ok)
+        .map(|entry| String::from(entry.file_name().to_string_lossy()))
+        .filter(|file_name| !file_name.starts_with('.'))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+//
+// Complete the merge recorded in spec, if any: publish the merged file and
+// remove the outputs it holds
+//
+fn finish(spec: &str) -> Result<(), std::io::Error> {
+    let record_path = format!("{}/{}", spec, FLUSH_RECORD);
+    let Ok(record) = fs::read_to_string(&record_path) else {
+        return Ok(());
+    };
+    let mut lines = record.lines();
+    if let Some(dst_path) = lines.next() {
+        let merged_path = format!("{}/{}", spec, MERGED);
+        if Path::new(&merged_path).exists() {
+            fs::rename(&merged_path, dst_path)?;
+        }
+    }
+    for file_name in lines {
+        let _ = fs::remove_file(format!("{}/{}", spec, file_name));
+    }
+    fs::remove_file(&record_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    fn write_output(held: &Held, file_name: &str, id: i64) {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!(
+            "COPY (SELECT {} AS id) TO '{}' (FORMAT parquet);",
+            id,
+            held.path(file_name)
+        ))
+        .unwrap();
+    }
+
+    fn ids(path: &str) -> Vec<i64> {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!("SELECT id FROM '{}' ORDER BY id;", path))
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn published(output_spec: &str) -> Vec<String> {
+        held(output_spec).unwrap()
+    }
+
+    #[test]
+    fn merge_by_size() {
+        let output_spec = test_dir("coalesce-size");
+        let policy = Policy {
+            target_bytes: 1,
+            max_age: Duration::from_secs(3600),
+        };
+        let mut held = Held::open("test", &output_spec, &policy).unwrap();
+        write_output(&held, "a.parquet", 1);
+        held.add("a.parquet").unwrap();
+        assert_eq!(published(&output_spec), vec!["a.parquet"]);
+
+        held.target_bytes = u64::MAX;
+        write_output(&held, "b.parquet", 2);
+        held.add("b.parquet").unwrap();
+        write_output(&held, "c.parquet", 3);
+        held.add("c.parquet").unwrap();
+        assert_eq!(published(&output_spec), vec!["a.parquet"]);
+        held.close().unwrap();
+        assert_eq!(published(&output_spec), vec!["a.parquet", "b.parquet"]);
+        assert_eq!(ids(&format!("{}/b.parquet", output_spec)), vec![2, 3]);
+        // nothing hidden left behind
+        assert_eq!(fs::read_dir(&output_spec).unwrap().count(), 2);
+        let _ = fs::remove_dir_all(&output_spec);
+    }
+
+    #[test]
+    fn merge_by_age() {
+        let output_spec = test_dir("coalesce-age");
+        let policy = Policy {
+            target_bytes: u64::MAX,
+            max_age: Duration::ZERO,
+        };
+        let mut held = Held::open("test", &output_spec, &policy).unwrap();
+        write_output(&held, "a.parquet", 1);
+        held.files.push(String::from("a.parquet"));
+        held.tick().unwrap();
+        assert!(published(&output_spec).is_empty());
+        held.oldest = Some(Instant::now());
+        held.tick().unwrap();
+        assert_eq!(published(&output_spec), vec!["a.parquet"]);
+        held.close().unwrap();
+        let _ = fs::remove_dir_all(&output_spec);
+    }
+
+    #[test]
+    fn take_over_stopped_instances() {
+        let output_spec = test_dir("coalesce-recover");
+        let policy = Policy {
+            target_bytes: u64::MAX,
+            max_age: Duration::from_secs(3600),
+        };
+        let mut stopped = Held::open("test", &output_spec, &policy).unwrap();
+        for (id, file_name) in ["a.parquet", "b.parquet"].iter().enumerate() {
+            write_output(&stopped, file_name, id as i64);
+            stopped.add(file_name).unwrap();
+        }
+        // stopped before publishing what it held
+        drop(stopped);
+
+        let mut interrupted = Held::open("test", &output_spec, &policy).unwrap();
+        assert_eq!(published(&output_spec), vec!["a.parquet"]);
+        assert_eq!(ids(&format!("{}/a.parquet", output_spec)), vec![0, 1]);
+        for (id, file_name) in ["c.parquet", "d.parquet"].iter().enumerate() {
+            write_output(&interrupted, file_name, id as i64 + 2);
+            interrupted.add(file_name).unwrap();
+        }
+        // stopped after recording a merge, before publishing it
+        let files = std::mem::take(&mut interrupted.files);
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!(
+            "COPY (SELECT * FROM read_parquet(['{}', '{}'])) TO '{}/{}' (FORMAT parquet);",
+            interrupted.path(&files[0]),
+            interrupted.path(&files[1]),
+            interrupted.spec,
+            MERGED
+        ))
+        .unwrap();
+        let mut record = format!("{}/c.parquet\n", output_spec);
+        for file_name in files.iter() {
+            record.push_str(&format!("{}\n", file_name));
+        }
+        fs::write(format!("{}/{}", interrupted.spec, FLUSH_RECORD), record).unwrap();
+        drop(interrupted);
+
+        Held::open("test", &output_spec, &policy)
+            .unwrap()
+            .close()
+            .unwrap();
+        assert_eq!(published(&output_spec), vec!["a.parquet", "c.parquet"]);
+        assert_eq!(ids(&format!("{}/c.parquet", output_spec)), vec![2, 3]);
+        assert_eq!(fs::read_dir(&output_spec).unwrap().count(), 2);
+        let _ = fs::remove_dir_all(&output_spec);
+    }
+}
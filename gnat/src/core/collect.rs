@@ -6,6 +6,7 @@
  * See license information in LICENSE.
  */
 use crate::ipfix::libfixbuf::unsafe_ifpix_socket_import;
+use tracing::info;
 
 pub fn collect(
     observation_tag: &String,
@@ -19,31 +20,39 @@ pub fn collect(
     rotate_interval: u32,
     verbose_mode: bool,
     output_spec: &String,
+    dns_output_spec: &String,
     asn_spec: &String,
     country_spec: &String,
+    city_spec: &String,
+    geo_refresh_hours: u32,
 ) -> Result<(), std::io::Error> {
 
 
-    println!("\tobservation: {}", observation_tag);
-    println!("\thost spec: {}", host_spec);
-    println!("\tport spec: {}", port_spec);
-    println!("\ttransport spec: {}", transport_spec);
-    if ssl_ca_file.is_empty() {
-        println!("\tssl_ca_file: {}", ssl_ca_file);
+    info!("observation: {}", observation_tag);
+    info!("host spec: {}", host_spec);
+    info!("port spec: {}", port_spec);
+    info!("transport spec: {}", transport_spec);
+    if !ssl_ca_file.is_empty() {
+        info!("ssl_ca_file: {}", ssl_ca_file);
     }
-    if ssl_cert_file.is_empty() {
-        println!("\tssl_cert_file: {}", ssl_cert_file);
+    if !ssl_cert_file.is_empty() {
+        info!("ssl_cert_file: {}", ssl_cert_file);
     }
-    if ssl_key_file.is_empty() {
-        println!("\tssl_key_file: {}", ssl_key_file);
+    if !ssl_key_file.is_empty() {
+        info!("ssl_key_file: {}", ssl_key_file);
     }
-    if ssl_key_pass.is_empty() {
-        println!("\tssl_key_pass: {}", ssl_key_pass);
-    }            
-    println!("\toutput spec: {}", output_spec);
-    println!("\tasn file: {}", asn_spec);
-    println!("\tcountry file: {}", country_spec);
-    println!("\rotate_interval: {}", rotate_interval);
+    if !ssl_key_pass.is_empty() {
+        info!("ssl_key_pass: ********");
+    }
+    info!("output spec: {}", output_spec);
+    if !dns_output_spec.is_empty() {
+        info!("dns output spec: {}", dns_output_spec);
+    }
+    info!("asn file: {}", asn_spec);
+    info!("country file: {}", country_spec);
+    info!("city file: {}", city_spec);
+    info!("geo refresh: {} hours", geo_refresh_hours);
+    info!("rotate interval: {}", rotate_interval);
 
     let status = unsafe_ifpix_socket_import(
         &observation_tag,
@@ -57,12 +66,14 @@ pub fn collect(
         rotate_interval,
         verbose_mode,
         &output_spec,
+        &dns_output_spec,
         &asn_spec,
         &country_spec,
+        &city_spec,
+        geo_refresh_hours.saturating_mul(3600),
     );
-    if status < 0 {
-        eprintln!("Error: collector failure");
-      
-    } 
+    if status != 0 {
+        return Err(std::io::Error::other("collector failure"));
+    }
     Ok(())
 }
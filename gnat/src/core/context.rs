@@ -0,0 +1,144 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Stages sharing a process
+//
+// gnat_run runs each stage of a pipeline on its own thread, so the settings
+// a stage binary keeps for the life of its process (scratch root, failure
+// policy, parquet options, ...) are kept per stage instead: a Local holds
+// one value for each stage, found by the id of the thread's stage. Threads
+// a stage starts enter its id with enter(). A standalone binary is stage 0.
+//
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+thread_local! {
+    static CURRENT: Cell<usize> = const { Cell::new(0) };
+}
+
+static STAGES: AtomicUsize = AtomicUsize::new(1);
+
+//
+// Id for a new stage of this process
+//
+pub fn next() -> usize {
+    STAGES.fetch_add(1, Ordering::Relaxed)
+}
+
+//
+// Make this thread part of stage id
+//
+pub fn enter(id: usize) {
+    CURRENT.with(|current| current.set(id));
+}
+
+//
+// Id of this thread's stage
+//
+pub fn current() -> usize {
+    CURRENT.with(|current| current.get())
+}
+
+//
+// A value per stage, set once like a OnceLock. Values live for the rest of
+// the process, which runs a bounded number of stages.
+//
+pub struct Local<T: 'static> {
+    values: Mutex<Vec<(usize, &'static T)>>,
+}
+
+impl<T: Sync + 'static> Local<T> {
+    pub const fn new() -> Local<T> {
+        Local {
+            values: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&'static T> {
+        let id = current();
+        self.values
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(stage, _)| *stage == id)
+            .map(|(_, value)| *value)
+    }
+
+    //
+    // Set this stage's value; Err when it was already set
+    //
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let id = current();
+        let mut values = self.values.lock().unwrap();
+        if values.iter().any(|(stage, _)| *stage == id) {
+            return Err(value);
+        }
+        values.push((id, Box::leak(Box::new(value))));
+        Ok(())
+    }
+
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> &'static T {
+        self.get_or_init_for(current(), init)
+    }
+
+    //
+    // get_or_init() for stage id, from a thread of any stage
+    //
+    pub fn get_or_init_for(&self, id: usize, init: impl FnOnce() -> T) -> &'static T {
+        let mut values = self.values.lock().unwrap();
+        if let Some((_, value)) = values.iter().find(|(stage, _)| *stage == id) {
+            return value;
+        }
+        let value: &'static T = Box::leak(Box::new(init()));
+        values.push((id, value));
+        value
+    }
+}
+
+impl<T: Sync + 'static> Default for Local<T> {
+    fn default() -> Local<T> {
+        Local::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_per_stage() {
+        static VALUE: Local<u32> = Local::new();
+        let id = next();
+        std::thread::spawn(move || {
+            enter(id);
+            assert!(VALUE.set(1).is_ok());
+            assert_eq!(VALUE.set(2), Err(2));
+            assert_eq!(VALUE.get(), Some(&1));
+            // threads of the stage see its value
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    enter(id);
+                    assert_eq!(VALUE.get(), Some(&1));
+                });
+            });
+        })
+        .join()
+        .unwrap();
+        let other = next();
+        std::thread::spawn(move || {
+            enter(other);
+            assert_eq!(VALUE.get(), None);
+            assert_eq!(*VALUE.get_or_init(|| 3), 3);
+        })
+        .join()
+        .unwrap();
+    }
+}
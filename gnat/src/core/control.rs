@@ -0,0 +1,359 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// HTTP control API of the pipeline (gnat_run --control <address:port>)
+//
+//   GET  /status                  the stages, as JSON
+//   POST /stages/<name>/process   scan the stage's input now (as SIGUSR1)
+//   POST /stages/<name>/reload    reload its rules and mapping files (as SIGHUP)
+//
+// The status of a stage holds its pid (gnat_run's for the stages on its
+// threads), the files pending in its input, the
+// time of the newest file in its output (or --processed) directory as
+// last_batch, the modification time of each option naming a file (rules,
+// indicators, sites, models), and the number of rules of a detect stage.
+//
+// The API has no authentication; bind it to a loopback or otherwise
+// private address.
+//
+
+use crate::core::detect::Rules;
+use crate::core::shutdown;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+const REQUEST_LIMIT: usize = 8192;
+
+//
+// How a stage runs: a gnat_run thread, with its context id and whether it
+// is still running, or a child process
+//
+#[derive(Debug, Clone)]
+pub enum Runner {
+    Thread { id: usize, running: Arc<AtomicBool> },
+    Process { pid: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlStage {
+    pub name: String,
+    pub kind: String,
+    pub runner: Runner,
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub options: toml::Table,
+}
+
+#[derive(Debug, Serialize)]
+struct StageStatus {
+    name: String,
+    kind: String,
+    pid: u32,
+    running: bool,
+    pending: Option<usize>,
+    last_batch: Option<String>,
+    files: BTreeMap<String, Option<String>>,
+    rules: Option<usize>,
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+//
+// Flow files waiting in dir_spec, skipping the hidden ones in progress
+//
+fn pending(dir_spec: &str) -> Option<usize> {
+    let entries = fs::read_dir(dir_spec).ok()?;
+    Some(
+        entries
+            .flatten()
+            .filter(|entry| {
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                !file_name.starts_with('.') && file_name.ends_with(".parquet")
+            })
+            .count(),
+    )
+}
+
+fn newest(dir_spec: &str) -> Option<SystemTime> {
+    fs::read_dir(dir_spec)
+        .ok()?
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .max()
+}
+
+impl ControlStage {
+    fn status(&self) -> StageStatus {
+        let processed = self.options.get("processed").and_then(|p| p.as_str());
+        let last_batch = [self.output.as_deref(), processed]
+            .into_iter()
+            .flatten()
+            .filter_map(newest)
+            .max()
+            .map(timestamp);
+
+        let mut files = BTreeMap::new();
+        for (option, value) in self.options.iter() {
+            let Some(value) = value.as_str() else {
+                continue;
+            };
+            if Path::new(value).is_file() {
+                files.insert(option.clone(), modified(Path::new(value)).map(timestamp));
+            }
+        }
+
+        let rules = match (self.kind.as_str(), self.options.get("rules").and_then(|r| r.as_str())) {
+            ("detect", Some(rules_spec)) => fs::read_to_string(rules_spec)
+                .ok()
+                .and_then(|contents| toml::from_str::<Rules>(&contents).ok())
+                .map(|rules| rules.thresholds.len()),
+            _ => None,
+        };
+
+        let (pid, running) = match &self.runner {
+            Runner::Thread { running, .. } => (std::process::id(), running.load(Ordering::SeqCst)),
+            Runner::Process { pid } => (*pid, unsafe { libc::kill(*pid as libc::pid_t, 0) } == 0),
+        };
+        StageStatus {
+            name: self.name.clone(),
+            kind: self.kind.clone(),
+            pid,
+            running,
+            pending: self.input.as_deref().and_then(pending),
+            last_batch,
+            files,
+            rules,
+        }
+    }
+}
+
+pub(crate) fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        warn!("responding - {:?}", e);
+    }
+}
+
+pub(crate) fn message(text: &str) -> String {
+    serde_json::json!({ "message": text }).to_string()
+}
+
+//
+// Method and path of the request on stream; the headers and any body
+// are ignored
+//
+pub(crate) fn read_request(stream: &mut TcpStream) -> Option<(String, String)> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < REQUEST_LIMIT {
+        let count = stream.read(&mut buffer).ok()?;
+        if count == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..count]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.lines().next()?.split_whitespace();
+    let method = words.next()?.to_string();
+    let path = words.next()?.split('?').next()?.to_string();
+    Some((method, path))
+}
+
+fn signal(stage: &ControlStage, signal: libc::c_int) -> bool {
+    match &stage.runner {
+        Runner::Thread { id, .. } => {
+            if signal == libc::SIGHUP {
+                shutdown::reload(*id);
+            } else {
+                shutdown::wake(*id);
+            }
+            true
+        }
+        Runner::Process { pid } => unsafe { libc::kill(*pid as libc::pid_t, signal) == 0 },
+    }
+}
+
+fn handle(stream: &mut TcpStream, stages: &[ControlStage]) {
+    let Some((method, path)) = read_request(stream) else {
+        respond(stream, "400 Bad Request", &message("malformed request"));
+        return;
+    };
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method.as_str(), segments.as_slice()) {
+        ("GET", ["status"]) => {
+            let status: Vec<StageStatus> = stages.iter().map(|stage| stage.status()).collect();
+            let body = serde_json::json!({ "stages": status }).to_string();
+            respond(stream, "200 OK", &body);
+        }
+        ("POST", ["stages", name, action]) => {
+            let Some(stage) = stages.iter().find(|stage| stage.name == *name) else {
+                respond(stream, "404 Not Found", &message(&format!("no stage {}", name)));
+                return;
+            };
+            let number = match *action {
+                "process" => libc::SIGUSR1,
+                "reload" => libc::SIGHUP,
+                _ => {
+                    respond(stream, "404 Not Found", &message(&format!("no action {}", action)));
+                    return;
+                }
+            };
+            if signal(stage, number) {
+                info!("control: {} [{}]", action, stage.name);
+                respond(stream, "202 Accepted", &message(&format!("{} {}", action, stage.name)));
+            } else {
+                let e = std::io::Error::last_os_error();
+                error!("control: {} [{}] - {:?}", action, stage.name, e);
+                respond(stream, "500 Internal Server Error", &message(&e.to_string()));
+            }
+        }
+        (_, ["status"]) | (_, ["stages", _, _]) => {
+            respond(stream, "405 Method Not Allowed", &message("method not allowed"));
+        }
+        _ => respond(stream, "404 Not Found", &message("not found")),
+    }
+}
+
+//
+// Serve the control API on control_spec from a background thread
+//
+pub fn serve(control_spec: &String, stages: Vec<ControlStage>) -> Result<(), std::io::Error> {
+    let listener = TcpListener::bind(control_spec)?;
+    info!("control: listening on {}", control_spec);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+                    handle(&mut stream, &stages);
+                }
+                Err(e) => warn!("control: accepting - {:?}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context;
+    use crate::core::testutil::test_dir;
+
+    // status line and body of the response to request
+    fn call(stages: &[ControlStage], request: &str) -> (String, serde_json::Value) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        handle(&mut server, stages);
+        drop(server);
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn status_and_actions() {
+        let dir = test_dir("control");
+        let (input, output) = (format!("{}/input", dir), format!("{}/output", dir));
+        fs::create_dir_all(&input).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        for name in ["a.parquet", "b.parquet", ".c.parquet", "d.txt"] {
+            fs::write(format!("{}/{}", input, name), b"").unwrap();
+        }
+        fs::write(format!("{}/a.parquet", output), b"").unwrap();
+        let rules_spec = format!("{}/rules.toml", dir);
+        fs::write(
+            &rules_spec,
+            "[[threshold]]\nname = \"a\"\nmetric = \"count()\"\nthreshold = 1\n\
+             [[threshold]]\nname = \"b\"\nmetric = \"count()\"\nthreshold = 2\n",
+        )
+        .unwrap();
+        let mut options = toml::Table::new();
+        options.insert(String::from("rules"), toml::Value::String(rules_spec));
+        options.insert(String::from("polling"), toml::Value::Boolean(true));
+        let id = context::next();
+        let stages = vec![ControlStage {
+            name: String::from("detect1"),
+            kind: String::from("detect"),
+            runner: Runner::Thread {
+                id,
+                running: Arc::new(AtomicBool::new(true)),
+            },
+            input: Some(input),
+            output: Some(output),
+            options,
+        }];
+
+        let (status, body) = call(&stages, "GET /status?pretty HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let stage = &body["stages"][0];
+        assert_eq!(stage["name"], "detect1");
+        assert_eq!(stage["pid"], std::process::id());
+        assert_eq!(stage["running"], true);
+        assert_eq!(stage["pending"], 2);
+        assert_eq!(stage["rules"], 2);
+        assert!(stage["last_batch"].is_string());
+        assert!(stage["files"]["rules"].is_string());
+        assert!(stage["files"].get("polling").is_none());
+
+        let (status, _) = call(&stages, "POST /stages/detect1/reload HTTP/1.1\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 202 Accepted");
+        let (status, _) = call(&stages, "POST /stages/detect1/process HTTP/1.1\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 202 Accepted");
+        std::thread::spawn(move || {
+            context::enter(id);
+            assert!(shutdown::reload_requested());
+            assert!(shutdown::woken());
+        })
+        .join()
+        .unwrap();
+
+        for (request, expected) in [
+            ("POST /stages/other/reload", "404 Not Found"),
+            ("POST /stages/detect1/stop", "404 Not Found"),
+            ("GET /stages/detect1/reload", "405 Method Not Allowed"),
+            ("DELETE /status", "405 Method Not Allowed"),
+            ("GET /metrics", "404 Not Found"),
+            ("", "400 Bad Request"),
+        ] {
+            let (status, _) = call(&stages, &format!("{} HTTP/1.1\r\n\r\n", request));
+            assert_eq!(status, format!("HTTP/1.1 {}", expected), "{}", request);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,425 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Suricata alert correlation stage
+//
+// Alerts are read from a Suricata eve.json (one JSON event per line; only
+// event_type "alert" is used). A flow matches an alert when the protocol
+// and addresses agree, in either direction, the ports agree for protocols
+// that have them, and the alert time falls between stime and etime give or
+// take the tolerance. The signature ids of the matching alerts are merged
+// into the "ids_alerts" list column.
+//
+// Suricata appends to eve.json, so only the lines added since the last
+// batch are read; a file that shrank (rotated or truncated) is read again
+// from the start. Alerts older than the retention before the newest alert
+// are dropped.
+//
+
+use crate::core::error::GnatError;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::spool::process_directory_parallel;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+use chrono::DateTime;
+use duckdb::params;
+use duckdb::types::{TimeUnit, Value};
+use tracing::{error, info, warn};
+
+struct Alert {
+    // microseconds since the epoch
+    time: i64,
+    proto: String,
+    saddr: String,
+    daddr: String,
+    sport: Option<u16>,
+    dport: Option<u16>,
+    signature_id: u32,
+}
+
+fn parse_alert(line: &str) -> Option<Alert> {
+    let event: serde_json::Value = serde_json::from_str(line).ok()?;
+    if event["event_type"] != "alert" {
+        return None;
+    }
+    let time = DateTime::parse_from_str(event["timestamp"].as_str()?, "%Y-%m-%dT%H:%M:%S%.f%z")
+        .ok()?
+        .timestamp_micros();
+    // addresses are compared as the flow records print them
+    let address = |key: &str| -> Option<String> {
+        Some(event[key].as_str()?.parse::<IpAddr>().ok()?.to_string())
+    };
+    let port = |key: &str| event[key].as_u64().map(|p| p as u16);
+    Some(Alert {
+        time,
+        proto: event["proto"].as_str()?.to_lowercase(),
+        saddr: address("src_ip")?,
+        daddr: address("dest_ip")?,
+        sport: port("src_port"),
+        dport: port("dest_port"),
+        signature_id: event["alert"]["signature_id"].as_u64()? as u32,
+    })
+}
+
+pub struct Alerts {
+    pub alert_spec: String,
+    retention: i64,
+    offset: u64,
+    alerts: Vec<Alert>,
+}
+
+impl Alerts {
+    pub fn new(alert_spec: &str, retention_hours: u64) -> Alerts {
+        Alerts {
+            alert_spec: alert_spec.to_string(),
+            retention: retention_hours as i64 * 3_600_000_000,
+            offset: 0,
+            alerts: Vec::new(),
+        }
+    }
+
+    //
+    // Read the alerts appended since the last batch
+    //
+    pub fn refresh(&mut self) -> Result<(), std::io::Error> {
+        let mut file = File::open(&self.alert_spec)?;
+        let length = file.metadata()?.len();
+        if length < self.offset {
+            info!(
+                "correlate: {} was rotated; reading it again",
+                self.alert_spec
+            );
+            self.offset = 0;
+            self.alerts.clear();
+        }
+        if length == self.offset {
+            return Ok(());
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut reader = BufReader::new(file);
+        let (mut loaded, mut skipped) = (0, 0);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            // leave a partly written last line for the next batch
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            self.offset += read as u64;
+            if !line.contains("\"alert\"") {
+                continue;
+            }
+            match parse_alert(&line) {
+                Some(alert) => {
+                    self.alerts.push(alert);
+                    loaded += 1;
+                }
+                None => skipped += 1,
+            }
+        }
+        if let Some(newest) = self.alerts.iter().map(|a| a.time).max() {
+            let oldest = newest - self.retention;
+            self.alerts.retain(|a| a.time >= oldest);
+        }
+        if loaded > 0 {
+            info!(
+                "correlate: loaded {} alerts from {} [holding {}]",
+                loaded,
+                self.alert_spec,
+                self.alerts.len()
+            );
+        }
+        if skipped > 0 {
+            warn!(
+                "correlate: {} alert events in {} could not be parsed",
+                skipped, self.alert_spec
+            );
+        }
+        Ok(())
+    }
+
+    pub fn correlate_file(
+        &self,
+        input_spec: &String,
+        output_spec: &String,
+        tolerance: u64,
+    ) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("correlate")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let sql_command = format!(
+            "CREATE TABLE memtable AS {};
+             ALTER TABLE memtable ADD COLUMN IF NOT EXISTS ids_alerts UINTEGER[];
+             CREATE TABLE alert (time TIMESTAMP, proto VARCHAR, saddr VARCHAR, daddr VARCHAR,
+                sport USMALLINT, dport USMALLINT, signature_id UINTEGER);",
+            source
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        //
+        // only the alerts within the time span of the batch
+        //
+        let span: Result<(Option<i64>, Option<i64>), duckdb::Error> = conn.query_row(
+            "SELECT epoch_us(min(stime)), epoch_us(max(etime)) FROM memtable;",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        );
+        let (first, last) = match span {
+            Ok((Some(first), Some(last))) => (first, last),
+            Ok(_) => (0, -1),
+            Err(e) => {
+                error!("reading {} - {:?}", input_spec, e);
+                return Err(e.into());
+            }
+        };
+        let slack = tolerance as i64 * 1_000_000;
+        {
+            let mut appender = conn.appender("alert")?;
+            for a in self
+                .alerts
+                .iter()
+                .filter(|a| a.time >= first - slack && a.time <= last + slack)
+            {
+                if let Err(e) = appender.append_row(params![
+                    Value::Timestamp(TimeUnit::Microsecond, a.time),
+                    a.proto,
+                    a.saddr,
+                    a.daddr,
+                    a.sport,
+                    a.dport,
+                    a.signature_id,
+                ]) {
+                    error!("matching {} - {:?}", input_spec, e);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let sql_command = format!(
+            "CREATE TABLE hit AS
+                SELECT m.rowid AS row_id, list(DISTINCT a.signature_id) AS signature_ids
+                FROM memtable m JOIN alert a
+                    ON a.proto = lower(m.proto)
+                    AND a.time BETWEEN m.stime - INTERVAL '{0} seconds'
+                        AND m.etime + INTERVAL '{0} seconds'
+                    AND ((a.saddr = m.saddr AND a.daddr = m.daddr
+                            AND coalesce(a.sport = m.sport AND a.dport = m.dport, true))
+                        OR (a.saddr = m.daddr AND a.daddr = m.saddr
+                            AND coalesce(a.sport = m.dport AND a.dport = m.sport, true)))
+                GROUP BY m.rowid;
+             UPDATE memtable SET ids_alerts = list_sort(list_distinct(
+                    list_concat(coalesce(memtable.ids_alerts, []::UINTEGER[]), hit.signature_ids)))
+                FROM hit WHERE memtable.rowid = hit.row_id;",
+            tolerance
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("correlating {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+        let matched: i64 = conn
+            .query_row("SELECT count(*) FROM hit;", [], |row| row.get(0))
+            .unwrap_or(0);
+        let sql_command = format!(
+            "COPY memtable TO '{}' ({});",
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+        info!("correlate: {} [{} flows with alerts]", input_spec, matched);
+        Ok(())
+    }
+}
+
+//
+// Options of gnat_correlate, as parsed and checked by main()
+//
+pub struct CorrelateConfig {
+    pub alert_spec: String,
+    pub input_spec: String,
+    pub output_spec: String,
+    pub processed_spec: String,
+    pub polling: bool,
+    pub workers: usize,
+    pub tolerance: u64,
+    pub retention_hours: u64,
+}
+
+pub fn correlate(config: &CorrelateConfig) -> Result<(), std::io::Error> {
+    let CorrelateConfig {
+        ref alert_spec,
+        ref input_spec,
+        ref output_spec,
+        ref processed_spec,
+        polling,
+        workers,
+        tolerance,
+        retention_hours,
+    } = *config;
+    info!("alert spec: {}", alert_spec);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("polling: {}", polling);
+    info!("workers: {}", workers);
+    info!("tolerance: {}", tolerance);
+    info!("retention: {}", retention_hours);
+
+    let mut alerts = Alerts::new(alert_spec, retention_hours);
+    alerts.refresh()?;
+    let alerts = RwLock::new(alerts);
+
+    process_directory_parallel(
+        "correlate",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        workers,
+        |src_path, tmp_path| {
+            {
+                // keep the alerts already read if the file is unreadable
+                let mut alerts = alerts.write().unwrap();
+                if let Err(e) = alerts.refresh() {
+                    error!("reading {} - {:?}", alerts.alert_spec, e);
+                }
+            }
+            alerts
+                .read()
+                .unwrap()
+                .correlate_file(src_path, tmp_path, tolerance)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    use std::fs;
+    use std::io::Write;
+
+    fn alert(second: u32, saddr: &str, sport: u16, daddr: &str, dport: u16, signature_id: u32) -> String {
+        format!(
+            "{{\"timestamp\":\"2024-01-01T00:00:{:02}.000000+0000\",\"event_type\":\"alert\",\
+             \"src_ip\":\"{}\",\"src_port\":{},\"dest_ip\":\"{}\",\"dest_port\":{},\"proto\":\"TCP\",\
+             \"alert\":{{\"signature_id\":{}}}}}\n",
+            second, saddr, sport, daddr, dport, signature_id
+        )
+    }
+
+    fn append(path: &str, text: &str) {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn refresh_reads_appended_lines() {
+        let dir = test_dir("correlate-refresh");
+        let alert_spec = format!("{}/eve.json", dir);
+        let first = alert(1, "10.0.0.1", 1234, "10.0.0.9", 80, 1);
+        let second = alert(2, "10.0.0.1", 1234, "10.0.0.9", 80, 2);
+        append(&alert_spec, &first);
+        append(&alert_spec, "{\"timestamp\":\"2024-01-01T00:00:01.000000+0000\",\"event_type\":\"flow\"}\n");
+        // a line still being written
+        append(&alert_spec, &second[..20]);
+        let mut alerts = Alerts::new(&alert_spec, 24);
+        alerts.refresh().unwrap();
+        assert_eq!(alerts.alerts.len(), 1);
+
+        append(&alert_spec, &second[20..]);
+        alerts.refresh().unwrap();
+        let ids: Vec<u32> = alerts.alerts.iter().map(|a| a.signature_id).collect();
+        assert_eq!(ids, vec![1, 2]);
+
+        // a rotated file is read from the start
+        fs::write(&alert_spec, alert(3, "10.0.0.1", 1234, "10.0.0.9", 80, 3)).unwrap();
+        alerts.refresh().unwrap();
+        let ids: Vec<u32> = alerts.alerts.iter().map(|a| a.signature_id).collect();
+        assert_eq!(ids, vec![3]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn refresh_drops_alerts_past_the_retention() {
+        let dir = test_dir("correlate-retention");
+        let alert_spec = format!("{}/eve.json", dir);
+        append(&alert_spec, &alert(1, "10.0.0.1", 1234, "10.0.0.9", 80, 1));
+        append(
+            &alert_spec,
+            &alert(1, "10.0.0.1", 1234, "10.0.0.9", 80, 2).replace("2024-01-01T00", "2024-01-01T02"),
+        );
+        let mut alerts = Alerts::new(&alert_spec, 1);
+        alerts.refresh().unwrap();
+        let ids: Vec<u32> = alerts.alerts.iter().map(|a| a.signature_id).collect();
+        assert_eq!(ids, vec![2]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn flows_get_the_alerts_that_match() {
+        let dir = test_dir("correlate-match");
+        let alert_spec = format!("{}/eve.json", dir);
+        // the reverse direction, another port, and outside the flow's time
+        append(&alert_spec, &alert(30, "10.0.0.9", 80, "10.0.0.1", 1234, 1));
+        append(&alert_spec, &alert(30, "10.0.0.1", 1234, "10.0.0.9", 81, 2));
+        append(
+            &alert_spec,
+            &alert(30, "10.0.0.1", 1234, "10.0.0.9", 80, 3).replace("T00:00", "T00:10"),
+        );
+        append(&alert_spec, &alert(40, "10.0.0.1", 1234, "10.0.0.9", 80, 4));
+        let mut alerts = Alerts::new(&alert_spec, 24);
+        alerts.refresh().unwrap();
+
+        let input_spec = format!("{}/flows.parquet", dir);
+        let output_spec = format!("{}/flows.out.parquet", dir);
+        schema::write_test_flows(
+            &input_spec,
+            "SELECT * FROM (VALUES
+                (1, TIMESTAMP '2024-01-01 00:00:00', TIMESTAMP '2024-01-01 00:01:00', 'tcp',
+                    '10.0.0.1', 1234, '10.0.0.9', 80),
+                (2, TIMESTAMP '2024-01-01 00:00:00', TIMESTAMP '2024-01-01 00:01:00', 'tcp',
+                    '10.0.0.2', 1234, '10.0.0.9', 80))
+                t(dur, stime, etime, proto, saddr, sport, daddr, dport)",
+        );
+        alerts.correlate_file(&input_spec, &output_spec, 5).unwrap();
+
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT ids_alerts::VARCHAR FROM '{}' ORDER BY dur;",
+                output_spec
+            ))
+            .unwrap();
+        let matched: Vec<Option<String>> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(matched, vec![Some(String::from("[1, 4]")), None]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
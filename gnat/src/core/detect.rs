@@ -0,0 +1,580 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Threshold detection stage (gnat_detect)
+//
+// Rules come from a TOML file; each evaluates a SQL aggregate over fixed
+// windows of flows, grouped by observ, tenant and the group_by columns, and raises
+// a trigger (see trigger.rs) for each group whose value is above the
+// threshold:
+//
+//   [[threshold]]
+//   name = "dns-flows-per-host"
+//   window = 60                 # minutes, default 60
+//   filter = "appid = 'dns'"    # optional, see filter.rs
+//   group_by = ["saddr"]        # default ["saddr"]
+//   metric = "count()"
+//   threshold = 1000
+//
+//   [[threshold]]
+//   name = "outbound-bytes-cn"
+//   filter = "dcountry = 'CN'"
+//   metric = "sum(sbytes)"
+//   threshold = 1e9
+//
+// A ratio is just another aggregate, e.g. "sum(sbytes) / nullif(sum(dbytes), 0)".
+// Filters and metrics are the expressions of filter.rs rather than SQL,
+// group_by names flow columns, and the rule name, tenant and window times
+// are bound as parameters.
+//
+// tenant = "acme" limits a rule to the flows of one tenant, so tenants can
+// be given their own thresholds under the same rule name. Observations can
+// be given their own threshold of a rule with --overrides (see overrides.rs).
+//
+// A window is evaluated once the newest flow seen is the grace period past
+// its end. Flows of windows still open are held in a hidden pending file in
+// the trigger directory, so windows span input files and stage restarts.
+// Input files are passed to the output unchanged. The rules are reloaded on
+// SIGHUP, keeping the previous ones if the file no longer loads.
+//
+
+use crate::core::error::GnatError;
+use crate::core::filter::{self, Filter};
+use crate::core::overrides::Overrides;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::shutdown;
+use crate::core::spool::process_directory;
+use crate::core::suppress;
+use crate::core::trigger::{self, TRIGGER_INSERT, TRIGGER_TABLE};
+
+use std::fs;
+use std::path::Path;
+
+use duckdb::{params_from_iter, Connection};
+use serde::Deserialize;
+use tracing::{error, info};
+
+fn default_window() -> u64 {
+    60
+}
+
+fn default_group_by() -> Vec<String> {
+    vec![String::from("saddr")]
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThresholdRule {
+    pub name: String,
+    #[serde(default = "default_window")]
+    pub window: u64,
+    pub filter: Option<String>,
+    #[serde(default = "default_group_by")]
+    pub group_by: Vec<String>,
+    pub metric: String,
+    pub threshold: f64,
+    pub tenant: Option<String>,
+    // the filter, metric and per-observation threshold as SQL, set by
+    // Rules::load
+    #[serde(skip)]
+    predicate: String,
+    #[serde(skip)]
+    aggregate: String,
+    #[serde(skip)]
+    limit: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Rules {
+    #[serde(default, rename = "threshold")]
+    pub thresholds: Vec<ThresholdRule>,
+}
+
+impl ThresholdRule {
+    fn groups(&self) -> Vec<&String> {
+        self.group_by
+            .iter()
+            .filter(|c| *c != "observ" && *c != "tenant")
+            .collect()
+    }
+
+    //
+    // Parse the filter and metric, check the group_by columns and apply
+    // the threshold overrides
+    //
+    fn prepare(&mut self, overrides: &Overrides) -> Result<(), String> {
+        for column in self.group_by.iter_mut() {
+            *column = filter::column(column)?;
+        }
+        self.predicate = match &self.filter {
+            Some(text) => Filter::parse(text).map_err(|e| format!("filter {} - {}", text, e))?.to_sql(),
+            None => String::from("true"),
+        };
+        self.aggregate = filter::metric(&self.metric).map_err(|e| format!("metric {} - {}", self.metric, e))?;
+        self.limit = overrides.case(|o| o.threshold.get(&self.name).copied(), self.threshold);
+        Ok(())
+    }
+
+    //
+    // Insert the triggers of the windows that closed after prev_closed and
+    // by closed
+    //
+    fn evaluate(&self, conn: &Connection, prev_closed: Option<&str>, closed: &str) -> Result<usize, duckdb::Error> {
+        let (sql_command, params) = self.insert(prev_closed, closed);
+        conn.execute(&sql_command, params_from_iter(params.iter()))
+    }
+
+    //
+    // The insert statement, with its parameters
+    //
+    fn insert(&self, prev_closed: Option<&str>, closed: &str) -> (String, Vec<String>) {
+        let groups = self.groups();
+        let column = |name: &str, sql_type: &str| -> String {
+            if groups.iter().any(|c| *c == name) {
+                format!("\"{}\"::{}", name, sql_type)
+            } else {
+                format!("NULL::{}", sql_type)
+            }
+        };
+        let key: Vec<String> = groups
+            .iter()
+            .map(|c| format!("'{0}=' || coalesce(\"{0}\"::VARCHAR, '')", c))
+            .collect();
+        let group_list: String = groups.iter().map(|c| format!(", \"{}\"", c)).collect();
+        let window_end = format!("bucket + INTERVAL '{} minutes'", self.window);
+        let mut sql_command = format!(
+            "{insert}
+                SELECT bucket, {window_end}, observ, tenant, 'threshold', ?::VARCHAR,
+                    {saddr}, {daddr}, {dport}, {key}, value, {threshold}, ?::VARCHAR
+                FROM (SELECT time_bucket(INTERVAL '{window} minutes', stime) AS bucket, observ, tenant{group_list},
+                        ({metric})::DOUBLE AS value
+                    FROM memtable WHERE {filter} GROUP BY ALL)
+                WHERE value > {threshold} AND {window_end} <= ?::TIMESTAMP",
+            insert = TRIGGER_INSERT,
+            window_end = window_end,
+            saddr = column("saddr", "VARCHAR"),
+            daddr = column("daddr", "VARCHAR"),
+            dport = column("dport", "USMALLINT"),
+            key = if key.is_empty() {
+                String::from("NULL")
+            } else {
+                format!("concat_ws(', ', {})", key.join(", "))
+            },
+            threshold = self.limit,
+            metric = self.aggregate,
+            window = self.window,
+            group_list = group_list,
+            filter = match &self.tenant {
+                Some(_) => format!("tenant = ?::VARCHAR AND {}", self.predicate),
+                None => self.predicate.clone(),
+            },
+        );
+        let mut params = vec![self.name.clone(), self.metric.clone()];
+        if let Some(tenant) = &self.tenant {
+            params.push(tenant.clone());
+        }
+        params.push(closed.to_string());
+        if let Some(prev_closed) = prev_closed {
+            sql_command.push_str(&format!(" AND {} > ?::TIMESTAMP", window_end));
+            params.push(prev_closed.to_string());
+        }
+        (sql_command, params)
+    }
+}
+
+impl Rules {
+    //
+    // Load the rules, with the threshold overrides of overrides_spec, and
+    // check each one against an empty flow table
+    //
+    pub fn load(rules_spec: &String, overrides_spec: &String) -> Result<Rules, GnatError> {
+        let contents = fs::read_to_string(rules_spec)?;
+        let mut rules: Rules = toml::from_str(&contents)
+            .map_err(|e| GnatError::Config(format!("parsing {} - {}", rules_spec, e)))?;
+        let overrides = Overrides::load(overrides_spec)?;
+        if let Some(name) = overrides
+            .rules()
+            .into_iter()
+            .find(|name| !rules.thresholds.iter().any(|rule| rule.name == **name))
+        {
+            return Err(GnatError::Config(format!(
+                "{}: threshold of unknown rule {}",
+                overrides_spec, name
+            )));
+        }
+        let conn = Connection::open_in_memory()?;
+        let sql_command = format!(
+            "{}; ALTER TABLE flow RENAME TO memtable; {};",
+            schema::FLOW_TABLE,
+            TRIGGER_TABLE
+        );
+        conn.execute_batch(&sql_command)?;
+        for rule in rules.thresholds.iter_mut() {
+            if rule.window == 0 {
+                return Err(GnatError::Config(format!(
+                    "rule {}: window must be greater than 0",
+                    rule.name
+                )));
+            }
+            rule.prepare(&overrides)
+                .map_err(|e| GnatError::Config(format!("rule {} - {}", rule.name, e)))?;
+            rule.evaluate(&conn, None, "2000-01-01")
+                .map_err(|e| GnatError::Config(format!("rule {} - {}", rule.name, e)))?;
+        }
+        Ok(rules)
+    }
+}
+
+pub struct Detector {
+    rules: Rules,
+    trigger_spec: String,
+    pending_spec: String,
+    suppress_spec: String,
+    grace: u64,
+}
+
+impl Detector {
+    pub fn new(rules: Rules, trigger_spec: &str, suppress_spec: &str, grace: u64) -> Detector {
+        Detector {
+            rules,
+            trigger_spec: trigger_spec.to_string(),
+            pending_spec: format!("{}/.detect-pending.parquet", trigger_spec),
+            suppress_spec: suppress_spec.to_string(),
+            grace,
+        }
+    }
+
+    pub fn detect_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("detect")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let pending = Path::new(&self.pending_spec).exists();
+        let kept = suppress::exclude(&conn, &self.suppress_spec, "detect", "batch");
+        let sql_command = if pending {
+            format!(
+                "CREATE TABLE batch AS {};
+                 CREATE TABLE memtable AS SELECT * FROM batch WHERE {}
+                    UNION ALL BY NAME SELECT * FROM '{}';
+                 {};",
+                source, kept, self.pending_spec, TRIGGER_TABLE
+            )
+        } else {
+            format!(
+                "CREATE TABLE batch AS {};
+                 CREATE TABLE memtable AS SELECT * FROM batch WHERE {};
+                 {};",
+                source, kept, TRIGGER_TABLE
+            )
+        };
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        //
+        // windows close once the newest flow is the grace period past them;
+        // the newest flow of the previous batch is the newest held
+        //
+        let closed_at = |table: &String| -> Option<String> {
+            conn.query_row(
+                &format!(
+                    "SELECT strftime(max(stime) - INTERVAL '{} seconds', '%Y-%m-%d %H:%M:%S.%f') FROM {};",
+                    self.grace, table
+                ),
+                [],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .ok()
+            .flatten()
+        };
+        let prev_closed = if pending {
+            closed_at(&format!("'{}'", self.pending_spec))
+        } else {
+            None
+        };
+        let Some(closed) = closed_at(&String::from("memtable")) else {
+            // an empty batch with nothing held
+            return self.pass(&conn, output_spec);
+        };
+
+        for rule in self.rules.thresholds.iter() {
+            if let Err(e) = rule.evaluate(&conn, prev_closed.as_deref(), &closed) {
+                error!("rule {} on {} - {:?}", rule.name, input_spec, e);
+                return Err(e.into());
+            }
+        }
+        let file_name = Path::new(input_spec)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let triggers = match trigger::write(&conn, &self.trigger_spec, "detect", &file_name) {
+            Ok(count) => count,
+            Err(e) => {
+                error!("writing triggers for {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        self.pass(&conn, output_spec)?;
+
+        //
+        // hold the flows of the windows still open; the pending file is
+        // replaced last, so a retry starts again from the same flows
+        //
+        let open: Vec<String> = self
+            .rules
+            .thresholds
+            .iter()
+            .map(|rule| {
+                format!(
+                    "time_bucket(INTERVAL '{} minutes', TIMESTAMP '{}')",
+                    rule.window, closed
+                )
+            })
+            .collect();
+        let tmp_spec = format!("{}.tmp", self.pending_spec);
+        let sql_command = if open.is_empty() {
+            format!(
+                "COPY (SELECT * FROM memtable ORDER BY stime DESC LIMIT 1) TO '{}' ({});",
+                tmp_spec,
+                schema::copy_options()
+            )
+        } else {
+            format!(
+                "COPY (SELECT * FROM memtable WHERE stime >= least({}) ORDER BY stime) TO '{}' ({});",
+                open.join(", "),
+                tmp_spec,
+                schema::copy_options()
+            )
+        };
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", tmp_spec, e);
+            return Err(e.into());
+        }
+        if let Err(e) = fs::rename(&tmp_spec, &self.pending_spec) {
+            error!("moving {} -> {} - {:?}", tmp_spec, self.pending_spec, e);
+            return Err(e.into());
+        }
+        info!("detect: {} [{} triggers]", input_spec, triggers);
+        Ok(())
+    }
+
+    //
+    // The input, unchanged apart from the schema upgrade, to the output
+    //
+    fn pass(&self, conn: &Connection, output_spec: &String) -> Result<(), GnatError> {
+        let sql_command = format!(
+            "COPY (SELECT * FROM batch) TO '{}' ({});",
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+//
+// Options of gnat_detect, as parsed and checked by main()
+//
+pub struct DetectConfig {
+    pub rules_spec: String,
+    pub input_spec: String,
+    pub output_spec: String,
+    pub processed_spec: String,
+    pub trigger_spec: String,
+    pub suppress_spec: String,
+    pub overrides_spec: String,
+    pub polling: bool,
+    pub grace: u64,
+}
+
+pub fn detect(config: &DetectConfig) -> Result<(), std::io::Error> {
+    let DetectConfig {
+        ref rules_spec,
+        ref input_spec,
+        ref output_spec,
+        ref processed_spec,
+        ref trigger_spec,
+        ref suppress_spec,
+        ref overrides_spec,
+        polling,
+        grace,
+    } = *config;
+    info!("rules spec: {}", rules_spec);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("trigger spec: {}", trigger_spec);
+    if !suppress_spec.is_empty() {
+        info!("suppression spec: {}", suppress_spec);
+    }
+    if !overrides_spec.is_empty() {
+        info!("overrides spec: {}", overrides_spec);
+    }
+    info!("polling: {}", polling);
+    info!("grace: {}", grace);
+
+    let rules = Rules::load(rules_spec, overrides_spec)?;
+    info!("detect: {} threshold rules", rules.thresholds.len());
+    let mut detector = Detector::new(rules, trigger_spec, suppress_spec, grace);
+
+    process_directory(
+        "detect",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        |src_path, tmp_path| {
+            if shutdown::reload_requested() {
+                // keep detecting with the previous rules if the new ones don't load
+                match Rules::load(rules_spec, overrides_spec) {
+                    Ok(rules) => {
+                        info!("detect: reloaded {} threshold rules", rules.thresholds.len());
+                        detector.rules = rules;
+                    }
+                    Err(e) => error!("reloading {} - {:?}", rules_spec, e),
+                }
+            }
+            detector.detect_file(src_path, tmp_path)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    fn rules(dir: &str, contents: &str) -> Rules {
+        let rules_spec = format!("{}/rules.toml", dir);
+        fs::write(&rules_spec, contents).unwrap();
+        Rules::load(&rules_spec, &String::new()).unwrap()
+    }
+
+    // flows from saddr at the given minutes past midnight
+    fn write_input(path: &str, saddr: &str, minutes: &[u32]) {
+        let rows: Vec<String> = minutes
+            .iter()
+            .map(|m| format!("('s1', TIMESTAMP '2024-01-01' + INTERVAL '{} minutes', '{}')", m, saddr))
+            .collect();
+        schema::write_test_flows(
+            path,
+            &format!("SELECT * FROM (VALUES {}) t(observ, stime, saddr)", rows.join(", ")),
+        );
+    }
+
+    // (time, saddr, value) of the triggers written to trigger_spec
+    fn triggers(trigger_spec: &str) -> Vec<(String, String, f64)> {
+        let conn = Connection::open_in_memory().unwrap();
+        let Ok(mut stmt) = conn.prepare(&format!(
+            "SELECT strftime(time, '%H:%M'), saddr, value FROM read_parquet('{}/trigger.detect.*') ORDER BY ALL;",
+            trigger_spec
+        )) else {
+            return Vec::new();
+        };
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn count(path: &str) -> i64 {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.query_row(&format!("SELECT count(*) FROM '{}';", path), [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn load_rejects_invalid_rules() {
+        let dir = test_dir("detect-load");
+        let rules_spec = format!("{}/rules.toml", dir);
+        for contents in [
+            "[[threshold]]\nname = \"a\"\nwindow = 0\nmetric = \"count()\"\nthreshold = 1",
+            "[[threshold]]\nname = \"a\"\nmetric = \"count(\"\nthreshold = 1",
+            "[[threshold]]\nname = \"a\"\ngroup_by = [\"nope\"]\nmetric = \"count()\"\nthreshold = 1",
+        ] {
+            fs::write(&rules_spec, contents).unwrap();
+            assert!(Rules::load(&rules_spec, &String::new()).is_err(), "{}", contents);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn windows_span_input_files() {
+        let dir = test_dir("detect-windows");
+        let trigger_spec = format!("{}/trigger", dir);
+        fs::create_dir_all(&trigger_spec).unwrap();
+        let rules = rules(
+            &dir,
+            "[[threshold]]\nname = \"flows\"\nwindow = 10\nmetric = \"count()\"\nthreshold = 2",
+        );
+        let detector = Detector::new(rules, &trigger_spec, "", 0);
+        let run = |name: &str, minutes: &[u32]| {
+            let input_spec = format!("{}/{}.parquet", dir, name);
+            let output_spec = format!("{}/{}.out.parquet", dir, name);
+            write_input(&input_spec, "10.0.0.1", minutes);
+            detector.detect_file(&input_spec, &output_spec).unwrap();
+            // the input passes unchanged
+            assert_eq!(count(&output_spec), minutes.len() as i64);
+        };
+
+        // the window of 00:00 is still open
+        run("a", &[1, 2, 3]);
+        assert!(triggers(&trigger_spec).is_empty());
+        assert_eq!(count(&detector.pending_spec), 3);
+
+        // a later file closes it, with the flows held from the first
+        run("b", &[12]);
+        assert_eq!(
+            triggers(&trigger_spec),
+            vec![(String::from("00:00"), String::from("10.0.0.1"), 3.0)]
+        );
+        assert_eq!(count(&detector.pending_spec), 1);
+
+        // closed windows are evaluated once
+        run("c", &[25]);
+        assert_eq!(triggers(&trigger_spec).len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn grace_delays_windows() {
+        let dir = test_dir("detect-grace");
+        let trigger_spec = format!("{}/trigger", dir);
+        fs::create_dir_all(&trigger_spec).unwrap();
+        let rules = rules(
+            &dir,
+            "[[threshold]]\nname = \"flows\"\nwindow = 10\nmetric = \"count()\"\nthreshold = 2",
+        );
+        let detector = Detector::new(rules, &trigger_spec, "", 300);
+        let input_spec = format!("{}/a.parquet", dir);
+        let output_spec = format!("{}/a.out.parquet", dir);
+
+        // 00:12 is within 5 minutes of the end of the window
+        write_input(&input_spec, "10.0.0.1", &[1, 2, 3, 12]);
+        detector.detect_file(&input_spec, &output_spec).unwrap();
+        assert!(triggers(&trigger_spec).is_empty());
+
+        write_input(&input_spec, "10.0.0.2", &[16]);
+        detector.detect_file(&input_spec, &output_spec).unwrap();
+        assert_eq!(
+            triggers(&trigger_spec),
+            vec![(String::from("00:00"), String::from("10.0.0.1"), 3.0)]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
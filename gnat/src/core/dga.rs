@@ -0,0 +1,217 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// DGA scoring stage (gnat_dga)
+//
+// Sets dga_score (see model/dga.rs) on the files of a spool:
+//
+//   flow files  the score of sni, else of httphost
+//   dns files   the score of qname, in a dga_score column added to the
+//               stream (gnat_stream 'dns')
+//
+// Flows without a name keep the score they have, so flows scored at pcap
+// import are rescored only when they carry a name.
+//
+
+use crate::core::error::GnatError;
+use crate::core::parquet;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::spool::process_directory_parallel;
+use crate::model::dga::Model;
+
+use duckdb::{params, Connection};
+use tracing::{error, info};
+
+const DNS_STREAM: &str = "dns";
+
+fn stream(conn: &Connection, input_spec: &String) -> Option<String> {
+    let sql_command = format!(
+        "SELECT decode(value) FROM parquet_kv_metadata('{}') WHERE decode(key) = 'gnat_stream';",
+        input_spec
+    );
+    conn.query_row(&sql_command, [], |row| row.get(0)).ok()
+}
+
+pub fn dga_file(model: &Model, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+    let conn = scratch::open_in_memory("dga")?;
+    let dns = stream(&conn, input_spec).as_deref() == Some(DNS_STREAM);
+    // the name scored, as selected and as matched in the UPDATE
+    let (sql_command, name, key, options) = if dns {
+        (
+            format!(
+                "CREATE TABLE memtable AS SELECT * FROM '{}';
+                 ALTER TABLE memtable ADD COLUMN IF NOT EXISTS dga_score FLOAT;",
+                input_spec
+            ),
+            "qname",
+            "memtable.qname",
+            format!("{}, KV_METADATA {{gnat_stream: '{}'}}", parquet::options(), DNS_STREAM),
+        )
+    } else {
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        (
+            format!("CREATE TABLE memtable AS {};", source),
+            "coalesce(sni, httphost)",
+            "coalesce(memtable.sni, memtable.httphost)",
+            schema::copy_options(),
+        )
+    };
+    let sql_command = format!(
+        "{} CREATE TABLE scores (name VARCHAR, score FLOAT);",
+        sql_command
+    );
+    if let Err(e) = conn.execute_batch(&sql_command) {
+        error!("loading {} - {:?}", input_spec, e);
+        return Err(e.into());
+    }
+
+    //
+    // score the distinct names of the batch
+    //
+    let names: Vec<String> = {
+        let mut stmt = conn.prepare(&format!("SELECT DISTINCT {} FROM memtable;", name))?;
+        match stmt.query_map([], |row| row.get::<_, Option<String>>(0)) {
+            Ok(rows) => rows.filter_map(|r| r.ok().flatten()).collect(),
+            Err(e) => {
+                error!("reading {} - {:?}", input_spec, e);
+                return Err(e.into());
+            }
+        }
+    };
+    {
+        let mut appender = conn.appender("scores")?;
+        for name in names.iter() {
+            let Some(score) = model.score(name) else {
+                continue;
+            };
+            if let Err(e) = appender.append_row(params![name, score]) {
+                error!("scoring {} - {:?}", input_spec, e);
+                return Err(e.into());
+            }
+        }
+    }
+
+    let sql_command = format!(
+        "UPDATE memtable SET dga_score = s.score FROM scores s WHERE s.name = {};
+         COPY memtable TO '{}' ({});",
+        key,
+        output_spec,
+        options
+    );
+    if let Err(e) = conn.execute_batch(&sql_command) {
+        error!("writing {} - {:?}", output_spec, e);
+        return Err(e.into());
+    }
+    info!("dga: {} [{} names]", input_spec, names.len());
+    Ok(())
+}
+
+pub fn dga(
+    train_spec: &String,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+    workers: usize,
+) -> Result<(), std::io::Error> {
+    info!("train spec: {}", train_spec);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("polling: {}", polling);
+    info!("workers: {}", workers);
+
+    let model = if train_spec.is_empty() {
+        Model::new()
+    } else {
+        Model::load(train_spec)?
+    };
+
+    process_directory_parallel(
+        "dga",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        workers,
+        |src_path, tmp_path| dga_file(&model, src_path, tmp_path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    use std::fs;
+
+    // dga_score of the rows of output_spec, in order of their id column
+    fn scores(conn: &Connection, output_spec: &str, id: &str) -> Vec<Option<f32>> {
+        let mut stmt = conn
+            .prepare(&format!("SELECT dga_score FROM '{}' ORDER BY {};", output_spec, id))
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn flows_are_scored_by_name() {
+        let dir = test_dir("dga-flows");
+        let model = Model::new();
+        let input_spec = format!("{}/flows.parquet", dir);
+        let output_spec = format!("{}/flows.out.parquet", dir);
+        schema::write_test_flows(
+            &input_spec,
+            "SELECT * FROM (VALUES
+                (1, 'www.google.com', 'xjw9k2qvzp7h.net', NULL),
+                (2, NULL, 'xjw9k2qvzp7h.net', NULL),
+                (3, NULL, NULL, 0.5))
+                t(dur, sni, httphost, dga_score)",
+        );
+        dga_file(&model, &input_spec, &output_spec).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        assert_eq!(
+            scores(&conn, &output_spec, "dur"),
+            vec![
+                model.score("www.google.com"),
+                model.score("xjw9k2qvzp7h.net"),
+                Some(0.5)
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn dns_files_get_a_score_column() {
+        let dir = test_dir("dga-dns");
+        let model = Model::new();
+        let input_spec = format!("{}/dns.parquet", dir);
+        let output_spec = format!("{}/dns.out.parquet", dir);
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!(
+            "COPY (SELECT * FROM (VALUES (1, 'www.google.com'), (2, '10.0.0.1')) t(id, qname))
+                TO '{}' (FORMAT parquet, KV_METADATA {{gnat_stream: 'dns'}});",
+            input_spec
+        ))
+        .unwrap();
+        dga_file(&model, &input_spec, &output_spec).unwrap();
+        assert_eq!(scores(&conn, &output_spec, "id"), vec![model.score("www.google.com"), None]);
+        assert_eq!(stream(&conn, &output_spec).as_deref(), Some(DNS_STREAM));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
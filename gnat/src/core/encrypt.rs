@@ -0,0 +1,168 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Parquet modular encryption (gnat_export --encrypt, gnat_report --decrypt)
+//
+// The footer and every column are encrypted with AES-GCM under one key,
+// read from GNAT_PARQUET_KEY or, when that is unset, from the standard
+// output of GNAT_PARQUET_KEY_COMMAND run through sh. The command is how a
+// KMS or secrets manager hands over the key, e.g. a data key decrypted with
+//
+//   aws kms decrypt --ciphertext-blob fileb:///etc/gnat/flow.key.enc \
+//       --query Plaintext --output text
+//
+// The key is 16, 24 or 32 bytes (AES-128, -192 or -256), as is or base64
+// encoded. DuckDB only encrypts securely with the OpenSSL of its httpfs
+// extension, so httpfs is loaded on the connections that use the key.
+//
+
+use crate::core::context::Local;
+use crate::core::error::GnatError;
+
+use std::process::Command;
+
+use duckdb::Connection;
+use tracing::info;
+
+const KEY_NAME: &str = "gnat_flow";
+const KEY_ENV: &str = "GNAT_PARQUET_KEY";
+const KEY_COMMAND_ENV: &str = "GNAT_PARQUET_KEY_COMMAND";
+const KEY_LENGTHS: [usize; 3] = [16, 24, 32];
+
+static KEY: Local<String> = Local::new();
+
+//
+// Bytes the key decodes to: its length as is, else as base64
+//
+fn key_length(key: &str) -> Option<usize> {
+    if KEY_LENGTHS.contains(&key.len()) {
+        return Some(key.len());
+    }
+    let base64 = key.len().is_multiple_of(4)
+        && key
+            .trim_end_matches('=')
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/');
+    if !base64 {
+        return None;
+    }
+    let padding = key.len() - key.trim_end_matches('=').len();
+    Some(key.len() / 4 * 3 - padding)
+}
+
+fn read_key() -> Result<String, GnatError> {
+    if let Ok(key) = std::env::var(KEY_ENV) {
+        return Ok(key);
+    }
+    let command = std::env::var(KEY_COMMAND_ENV).map_err(|_| {
+        GnatError::Config(format!("{} or {} must be set", KEY_ENV, KEY_COMMAND_ENV))
+    })?;
+    let output = Command::new("sh").arg("-c").arg(&command).output()?;
+    if !output.status.success() {
+        return Err(GnatError::Config(format!(
+            "{} failed ({}): {}",
+            KEY_COMMAND_ENV,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+//
+// Read and check the key; connections opened by the stage from now on can
+// encrypt and decrypt with it
+//
+pub fn enable() -> Result<(), GnatError> {
+    let key = read_key()?;
+    match key_length(&key) {
+        Some(length) if KEY_LENGTHS.contains(&length) => {
+            info!("parquet encryption: AES-{}", length * 8);
+        }
+        _ => {
+            return Err(GnatError::Config(String::from(
+                "the parquet key must be 16, 24 or 32 bytes, as is or base64 encoded",
+            )))
+        }
+    }
+    let _ = KEY.set(key);
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    KEY.get().is_some()
+}
+
+//
+// Register the key with conn
+//
+pub fn add_key(conn: &Connection) -> Result<(), duckdb::Error> {
+    let Some(key) = KEY.get() else {
+        return Ok(());
+    };
+    let sql_command = format!(
+        "INSTALL httpfs; LOAD httpfs; PRAGMA add_parquet_key('{}', '{}');",
+        KEY_NAME,
+        key.replace('\'', "''")
+    );
+    conn.execute_batch(&sql_command)
+}
+
+//
+// COPY option encrypting a parquet output with the key
+//
+pub fn copy_option() -> String {
+    format!("ENCRYPTION_CONFIG {{footer_key: '{}'}}", KEY_NAME)
+}
+
+//
+// read_parquet() parameter decrypting with the key
+//
+pub fn read_option() -> String {
+    format!("encryption_config = {{footer_key: '{}'}}", KEY_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context;
+
+    // 32 bytes, base64 encoded
+    const KEY_BASE64: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn key_lengths() {
+        assert_eq!(key_length("0123456789abcdef"), Some(16));
+        assert_eq!(key_length(KEY_BASE64), Some(32));
+        assert_eq!(key_length(&format!("{}=", "A".repeat(43))), Some(32));
+        assert_eq!(key_length("MDEyMzQ1Njc4OWFiY2RlZg="), None);
+        assert_eq!(key_length("not a key"), None);
+    }
+
+    #[test]
+    fn enable_reads_the_key_command() {
+        let id = context::next();
+        std::thread::spawn(move || {
+            context::enter(id);
+            std::env::remove_var(KEY_ENV);
+            for command in ["exit 3", "echo short"] {
+                std::env::set_var(KEY_COMMAND_ENV, command);
+                assert!(enable().is_err(), "{}", command);
+            }
+            assert!(!enabled());
+            std::env::set_var(KEY_COMMAND_ENV, format!("echo {}", KEY_BASE64));
+            enable().unwrap();
+            std::env::remove_var(KEY_COMMAND_ENV);
+            assert!(enabled());
+            assert_eq!(KEY.get().map(String::as_str), Some(KEY_BASE64));
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,625 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Enrichment stage (gnat_enrich)
+//
+// Adds context to each batch of flows through a chain of enrichers, read
+// from a TOML file and applied in order:
+//
+//   [[enricher]]
+//   kind = "asn"                      # sasn, sasnorg, dasn, dasnorg
+//   file = "GeoLite2-ASN.mmdb"
+//
+//   [[enricher]]
+//   kind = "geo"                      # scountry, dcountry
+//   country = "GeoLite2-Country.mmdb"
+//   city = "GeoLite2-City.mmdb"       # optional; scity, dcity and locations
+//
+//   [[enricher]]
+//   kind = "site"                     # site, see site.rs
+//   file = "sites.csv"
+//
+//   [[enricher]]
+//   kind = "wasm"                     # with the wasm feature
+//   file = "owner.wasm"
+//   columns = { owner = "VARCHAR", criticality = "INTEGER" }
+//
+//   [[enricher]]
+//   kind = "plugin"
+//   file = "/opt/gnat/plugins/libcmdb.so"
+//   options = "region=us-east"
+//
+// asn and geo look up each distinct address of a batch once, with the
+// values the import sets (lowercase, "private", "unk").
+//
+// A wasm enricher is a transform module (see transform.rs) that exports
+// gnat_enrich(ptr: i32, len: i32) -> i64 in place of gnat_transform. It
+// is passed each flow as a JSON object and returns a JSON object of values
+// of its columns, or -1 to leave the flow as it is. The columns must be
+// new ones, of the COLUMN_TYPES.
+//
+// A plugin enricher is a gnat_plugin library (include/gnat_plugin.h) whose
+// gnat_plugin_process() is passed the batch as a parquet file and writes it
+// back with the same flows and columns, plus the ones it adds.
+//
+// Other enrichers implement Enricher. On SIGHUP, every enricher reloads
+// the files it reads.
+//
+
+use crate::core::error::GnatError;
+#[cfg(feature = "wasm")]
+use crate::core::filter;
+use crate::core::plugin::Plugin;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::shutdown;
+use crate::core::site::Sites;
+use crate::core::spool::process_directory;
+#[cfg(feature = "wasm")]
+use crate::core::transform::Transform;
+use crate::ipfix::geo::GeoDatabase;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use duckdb::{params, Connection};
+use serde::Deserialize;
+use tracing::{error, info};
+
+// types of the columns a wasm enricher can add
+#[cfg(feature = "wasm")]
+const COLUMN_TYPES: [&str; 9] = [
+    "VARCHAR", "BOOLEAN", "INTEGER", "BIGINT", "UBIGINT", "FLOAT", "DOUBLE", "TIMESTAMP", "VARCHAR[]",
+];
+
+pub trait Enricher {
+    fn name(&self) -> &str;
+
+    //
+    // Enrich the flows of memtable; work_spec is a path prefix for any
+    // temporary files
+    //
+    fn enrich(&mut self, conn: &Connection, work_spec: &str) -> Result<(), GnatError>;
+
+    //
+    // Load the files the enricher reads again
+    //
+    fn reload(&mut self) -> Result<(), GnatError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnricherSpec {
+    pub kind: String,
+    pub file: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    #[serde(default)]
+    pub columns: BTreeMap<String, String>,
+    pub options: Option<String>,
+    // wasm limits per batch, as for gnat_transform --fuel and --memory
+    pub fuel: Option<u64>,
+    pub memory: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct EnricherFile {
+    #[serde(default, rename = "enricher")]
+    enrichers: Vec<EnricherSpec>,
+}
+
+//
+// The distinct source and destination addresses of memtable
+//
+fn addresses(conn: &Connection) -> Result<Vec<String>, duckdb::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT saddr FROM memtable WHERE saddr IS NOT NULL
+            UNION SELECT daddr FROM memtable WHERE daddr IS NOT NULL;",
+    )?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    rows.collect()
+}
+
+fn open_geo(file: &Option<String>, kind: &str, key: &str) -> Result<GeoDatabase, GnatError> {
+    match file {
+        Some(file) => Ok(GeoDatabase::open(file)?),
+        None => Err(GnatError::Config(format!("enricher {} needs {}", kind, key))),
+    }
+}
+
+pub struct AsnEnricher {
+    asn_spec: String,
+    asn: GeoDatabase,
+}
+
+impl Enricher for AsnEnricher {
+    fn name(&self) -> &str {
+        "asn"
+    }
+
+    fn enrich(&mut self, conn: &Connection, _work_spec: &str) -> Result<(), GnatError> {
+        conn.execute_batch("CREATE TABLE asns (address VARCHAR, asn UINTEGER, asnorg VARCHAR);")?;
+        {
+            let mut appender = conn.appender("asns")?;
+            for address in addresses(conn)? {
+                let (asn, asnorg) = self.asn.asn(&address);
+                appender.append_row(params![address, asn, asnorg])?;
+            }
+        }
+        conn.execute_batch(
+            "UPDATE memtable SET sasn = a.asn, sasnorg = a.asnorg FROM asns a WHERE a.address = memtable.saddr;
+             UPDATE memtable SET dasn = a.asn, dasnorg = a.asnorg FROM asns a WHERE a.address = memtable.daddr;
+             DROP TABLE asns;",
+        )?;
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<(), GnatError> {
+        self.asn = GeoDatabase::open(&self.asn_spec)?;
+        Ok(())
+    }
+}
+
+pub struct GeoEnricher {
+    country_spec: String,
+    city_spec: Option<String>,
+    country: GeoDatabase,
+    city: Option<GeoDatabase>,
+}
+
+impl Enricher for GeoEnricher {
+    fn name(&self) -> &str {
+        "geo"
+    }
+
+    fn enrich(&mut self, conn: &Connection, _work_spec: &str) -> Result<(), GnatError> {
+        conn.execute_batch(
+            "CREATE TABLE geo (address VARCHAR, country VARCHAR, city VARCHAR, lat DOUBLE, lon DOUBLE);",
+        )?;
+        {
+            let mut appender = conn.appender("geo")?;
+            for address in addresses(conn)? {
+                let country = self.country.country(&address);
+                let (city, location) = match &self.city {
+                    Some(city) => {
+                        let (name, location) = city.city(&address);
+                        (Some(name), location)
+                    }
+                    None => (None, None),
+                };
+                appender.append_row(params![
+                    address,
+                    country,
+                    city,
+                    location.map(|l| l.0),
+                    location.map(|l| l.1)
+                ])?;
+            }
+        }
+        let sql_command = if self.city.is_some() {
+            "UPDATE memtable SET scountry = g.country, scity = g.city, slat = g.lat, slon = g.lon
+                FROM geo g WHERE g.address = memtable.saddr;
+             UPDATE memtable SET dcountry = g.country, dcity = g.city, dlat = g.lat, dlon = g.lon
+                FROM geo g WHERE g.address = memtable.daddr;
+             DROP TABLE geo;"
+        } else {
+            "UPDATE memtable SET scountry = g.country FROM geo g WHERE g.address = memtable.saddr;
+             UPDATE memtable SET dcountry = g.country FROM geo g WHERE g.address = memtable.daddr;
+             DROP TABLE geo;"
+        };
+        conn.execute_batch(sql_command)?;
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<(), GnatError> {
+        self.country = GeoDatabase::open(&self.country_spec)?;
+        if let Some(city_spec) = &self.city_spec {
+            self.city = Some(GeoDatabase::open(city_spec)?);
+        }
+        Ok(())
+    }
+}
+
+pub struct SiteEnricher {
+    sites: Sites,
+}
+
+impl Enricher for SiteEnricher {
+    fn name(&self) -> &str {
+        "site"
+    }
+
+    fn enrich(&mut self, conn: &Connection, work_spec: &str) -> Result<(), GnatError> {
+        // keep mapping with the previous file if the new one is unreadable
+        if let Err(e) = self.sites.refresh() {
+            error!("reloading {} - {:?}", self.sites.site_spec, e);
+        }
+        self.sites.map(conn, work_spec).map(|_| ())
+    }
+
+    fn reload(&mut self) -> Result<(), GnatError> {
+        self.sites.reload();
+        self.sites.refresh()
+    }
+}
+
+#[cfg(feature = "wasm")]
+pub struct WasmEnricher {
+    module_spec: String,
+    fuel: u64,
+    memory_limit: usize,
+    transform: Transform,
+    columns: BTreeMap<String, String>,
+}
+
+#[cfg(feature = "wasm")]
+impl Enricher for WasmEnricher {
+    fn name(&self) -> &str {
+        "wasm"
+    }
+
+    fn enrich(&mut self, conn: &Connection, work_spec: &str) -> Result<(), GnatError> {
+        let (rows, records): (Vec<i64>, Vec<String>) = {
+            let mut stmt = conn.prepare("SELECT rowid, to_json(m)::VARCHAR FROM memtable m;")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+            rows.collect::<Result<Vec<_>, _>>()?.into_iter().unzip()
+        };
+
+        let ndjson_spec = format!("{}.enrich.ndjson", work_spec);
+        let mut ndjson = fs::File::create(&ndjson_spec)?;
+        let result = self.transform.each("gnat_enrich", records, |index, buffer| {
+            let mut values: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(buffer)?;
+            values.insert(String::from("gnat_row"), serde_json::Value::from(rows[index]));
+            serde_json::to_writer(&mut ndjson, &values)?;
+            std::io::Write::write_all(&mut ndjson, b"\n")?;
+            Ok(())
+        });
+        drop(ndjson);
+        let enriched = match result {
+            Ok((enriched, _)) => enriched,
+            Err(e) => {
+                let _ = fs::remove_file(&ndjson_spec);
+                // the module fails the same way on every attempt
+                return Err(GnatError::Config(format!("enricher wasm {} - {}", self.module_spec, e)));
+            }
+        };
+
+        let mut sql_command = String::new();
+        for (column, sql_type) in self.columns.iter() {
+            sql_command.push_str(&format!(
+                "ALTER TABLE memtable ADD COLUMN IF NOT EXISTS \"{}\" {};",
+                column, sql_type
+            ));
+        }
+        if enriched > 0 {
+            let types: Vec<String> = self
+                .columns
+                .iter()
+                .map(|(column, sql_type)| format!("{}: {}", filter::quote(column), filter::quote(sql_type)))
+                .collect();
+            let assignments: Vec<String> = self
+                .columns
+                .keys()
+                .map(|column| format!("\"{0}\" = e.\"{0}\"", column))
+                .collect();
+            sql_command.push_str(&format!(
+                "CREATE TABLE enriched AS SELECT * FROM read_json('{}', format = 'newline_delimited',
+                    columns = {{'gnat_row': 'BIGINT', {}}});
+                 UPDATE memtable SET {} FROM enriched e WHERE memtable.rowid = e.gnat_row;
+                 DROP TABLE enriched;",
+                ndjson_spec,
+                types.join(", "),
+                assignments.join(", ")
+            ));
+        }
+        let status = conn.execute_batch(&sql_command);
+        let _ = fs::remove_file(&ndjson_spec);
+        status?;
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<(), GnatError> {
+        self.transform = Transform::load(&self.module_spec, self.fuel, self.memory_limit)?;
+        Ok(())
+    }
+}
+
+pub struct PluginEnricher {
+    plugin: Plugin,
+    options: String,
+}
+
+impl Enricher for PluginEnricher {
+    fn name(&self) -> &str {
+        "plugin"
+    }
+
+    fn enrich(&mut self, conn: &Connection, work_spec: &str) -> Result<(), GnatError> {
+        let batch_spec = format!("{}.enrich-in.parquet", work_spec);
+        let enriched_spec = format!("{}.enrich-out.parquet", work_spec);
+        let remove = || {
+            let _ = fs::remove_file(&batch_spec);
+            let _ = fs::remove_file(&enriched_spec);
+        };
+        if let Err(e) = conn.execute_batch(&format!("COPY memtable TO '{}' (FORMAT parquet);", batch_spec)) {
+            remove();
+            return Err(e.into());
+        }
+        let status = self.plugin.process(&batch_spec, &enriched_spec, &self.options);
+        if status < 0 {
+            remove();
+            return Err(GnatError::Upstream(format!(
+                "plugin {} exited with {}",
+                self.plugin.name, status
+            )));
+        }
+
+        //
+        // the plugin may add columns, but must keep every flow and column
+        //
+        let sql_command = format!(
+            "CREATE TABLE enriched AS SELECT * FROM '{}';
+             CREATE TABLE dropped AS SELECT column_name FROM information_schema.columns
+                WHERE table_name = 'memtable' EXCEPT
+                SELECT column_name FROM information_schema.columns WHERE table_name = 'enriched';",
+            enriched_spec
+        );
+        let loaded = conn.execute_batch(&sql_command);
+        remove();
+        loaded?;
+        let flows: (i64, i64) = conn.query_row(
+            "SELECT (SELECT count(*) FROM memtable), (SELECT count(*) FROM enriched);",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let dropped: i64 = conn.query_row("SELECT count(*) FROM dropped;", [], |row| row.get(0))?;
+        if flows.0 != flows.1 || dropped > 0 {
+            return Err(GnatError::Schema(format!(
+                "plugin {} returned {} of {} flows and dropped {} columns",
+                self.plugin.name, flows.1, flows.0, dropped
+            )));
+        }
+        conn.execute_batch(
+            "DROP TABLE memtable; DROP TABLE dropped; ALTER TABLE enriched RENAME TO memtable;",
+        )?;
+        Ok(())
+    }
+}
+
+fn file(spec: &EnricherSpec) -> Result<&String, GnatError> {
+    spec.file
+        .as_ref()
+        .ok_or_else(|| GnatError::Config(format!("enricher {} needs a file", spec.kind)))
+}
+
+//
+// Build the enricher of spec, loading its files
+//
+pub fn build(spec: &EnricherSpec) -> Result<Box<dyn Enricher>, GnatError> {
+    match spec.kind.as_str() {
+        "asn" => {
+            let asn_spec = file(spec)?.clone();
+            let asn = GeoDatabase::open(&asn_spec)?;
+            Ok(Box::new(AsnEnricher { asn_spec, asn }))
+        }
+        "geo" => {
+            let country = open_geo(&spec.country, "geo", "a country file")?;
+            let city = match &spec.city {
+                Some(city_spec) => Some(GeoDatabase::open(city_spec)?),
+                None => None,
+            };
+            Ok(Box::new(GeoEnricher {
+                country_spec: spec.country.clone().unwrap_or_default(),
+                city_spec: spec.city.clone(),
+                country,
+                city,
+            }))
+        }
+        "site" => {
+            let mut sites = Sites::new(file(spec)?);
+            sites.refresh()?;
+            Ok(Box::new(SiteEnricher { sites }))
+        }
+        #[cfg(feature = "wasm")]
+        "wasm" => {
+            let module_spec = file(spec)?.clone();
+            if spec.columns.is_empty() {
+                return Err(GnatError::Config(String::from("enricher wasm needs columns")));
+            }
+            let flow_columns = schema::flow_columns();
+            for (column, sql_type) in spec.columns.iter() {
+                let valid = column.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+                    && column.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+                if !valid || flow_columns.contains(&column.as_str()) || column == "gnat_row" {
+                    return Err(GnatError::Config(format!("enricher wasm: invalid column {}", column)));
+                }
+                if !COLUMN_TYPES.contains(&sql_type.as_str()) {
+                    return Err(GnatError::Config(format!(
+                        "enricher wasm: column {} has invalid type {} ({})",
+                        column,
+                        sql_type,
+                        COLUMN_TYPES.join("|")
+                    )));
+                }
+            }
+            let fuel = spec.fuel.unwrap_or(1_000_000_000);
+            let memory_limit = spec.memory.unwrap_or(64) * 1024 * 1024;
+            let transform = Transform::load(&module_spec, fuel, memory_limit)?;
+            Ok(Box::new(WasmEnricher {
+                module_spec,
+                fuel,
+                memory_limit,
+                transform,
+                columns: spec.columns.clone(),
+            }))
+        }
+        "plugin" => {
+            let plugin = Plugin::load(Path::new(file(spec)?))?;
+            info!("enrich: loaded plugin [{}] {}", plugin.name, plugin.path);
+            Ok(Box::new(PluginEnricher {
+                plugin,
+                options: spec.options.clone().unwrap_or_default(),
+            }))
+        }
+        kind => Err(GnatError::Config(format!("invalid enricher kind {}", kind))),
+    }
+}
+
+//
+// Load the enrichers of enrichers_spec, in order
+//
+pub fn load(enrichers_spec: &String) -> Result<Vec<Box<dyn Enricher>>, GnatError> {
+    let contents = fs::read_to_string(enrichers_spec)?;
+    let file: EnricherFile = toml::from_str(&contents)
+        .map_err(|e| GnatError::Config(format!("parsing {} - {}", enrichers_spec, e)))?;
+    if file.enrichers.is_empty() {
+        return Err(GnatError::Config(format!("{}: no [[enricher]] tables", enrichers_spec)));
+    }
+    file.enrichers.iter().map(build).collect()
+}
+
+fn enrich_file(
+    enrichers: &mut [Box<dyn Enricher>],
+    input_spec: &String,
+    output_spec: &String,
+) -> Result<(), GnatError> {
+    let conn = scratch::open_in_memory("enrich")?;
+    let source = match schema::select(&conn, input_spec) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e);
+        }
+    };
+    if let Err(e) = conn.execute_batch(&format!("CREATE TABLE memtable AS {};", source)) {
+        error!("loading {} - {:?}", input_spec, e);
+        return Err(e.into());
+    }
+    for enricher in enrichers.iter_mut() {
+        if let Err(e) = enricher.enrich(&conn, output_spec) {
+            error!("enricher {} on {} - {:?}", enricher.name(), input_spec, e);
+            return Err(e);
+        }
+    }
+    let sql_command = format!("COPY memtable TO '{}' ({});", output_spec, schema::copy_options());
+    if let Err(e) = conn.execute_batch(&sql_command) {
+        error!("writing {} - {:?}", output_spec, e);
+        return Err(e.into());
+    }
+    info!("enrich: {} [{} enrichers]", input_spec, enrichers.len());
+    Ok(())
+}
+
+pub fn enrich(
+    enrichers_spec: &String,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+) -> Result<(), std::io::Error> {
+    info!("enrichers spec: {}", enrichers_spec);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("polling: {}", polling);
+
+    let mut enrichers = load(enrichers_spec)?;
+    let names: Vec<&str> = enrichers.iter().map(|e| e.name()).collect();
+    info!("enrich: {}", names.join(", "));
+
+    process_directory(
+        "enrich",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        |src_path, tmp_path| {
+            if shutdown::reload_requested() {
+                // an enricher whose files no longer load keeps the previous ones
+                for enricher in enrichers.iter_mut() {
+                    if let Err(e) = enricher.reload() {
+                        error!("reloading enricher {} - {:?}", enricher.name(), e);
+                    }
+                }
+            }
+            enrich_file(&mut enrichers, src_path, tmp_path)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    // marks the site each flow has when it runs
+    struct Marker;
+
+    impl Enricher for Marker {
+        fn name(&self) -> &str {
+            "marker"
+        }
+
+        fn enrich(&mut self, conn: &Connection, _work_spec: &str) -> Result<(), GnatError> {
+            conn.execute_batch("UPDATE memtable SET site = coalesce(site, 'none') || '+marked';")?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn load_rejects_invalid_enrichers() {
+        let dir = test_dir("enrich-load");
+        let enrichers_spec = format!("{}/enrichers.toml", dir);
+        for contents in [
+            "",
+            "[[enricher]]\nkind = \"nope\"",
+            "[[enricher]]\nkind = \"asn\"",
+            "[[enricher]]\nkind = \"geo\"",
+            "[[enricher]]\nkind = \"site\"\nfile = \"missing.csv\"",
+        ] {
+            fs::write(&enrichers_spec, contents).unwrap();
+            assert!(load(&enrichers_spec).is_err(), "{}", contents);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn enrichers_run_in_order() {
+        let dir = test_dir("enrich-order");
+        let site_spec = format!("{}/sites.csv", dir);
+        fs::write(&site_spec, "hq,,hq\n").unwrap();
+        let enrichers_spec = format!("{}/enrichers.toml", dir);
+        fs::write(
+            &enrichers_spec,
+            format!("[[enricher]]\nkind = \"site\"\nfile = \"{}\"\n", site_spec),
+        )
+        .unwrap();
+        let mut enrichers = load(&enrichers_spec).unwrap();
+        enrichers.push(Box::new(Marker));
+
+        let input_spec = format!("{}/flows.parquet", dir);
+        let output_spec = format!("{}/flows.out.parquet", dir);
+        schema::write_test_flows(&input_spec, "SELECT * FROM (VALUES ('hq'), ('lab')) t(observ)");
+        enrich_file(&mut enrichers, &input_spec, &output_spec).unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!("SELECT site FROM '{}' ORDER BY observ;", output_spec))
+            .unwrap();
+        let sites: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(sites, vec!["hq+marked", "none+marked"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
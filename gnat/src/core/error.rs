@@ -0,0 +1,81 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Errors of the stages' file processors
+//
+// A processor returns the kind of failure along with its cause, so the
+// spool can tell the ones worth retrying (I/O, an upstream service, a
+// busy database) from the fatal ones that fail the same way on every
+// attempt (a bad configuration, a file that doesn't fit the flow schema,
+// SQL that doesn't bind), which are quarantined right away.
+//
+
+use thiserror::Error;
+
+// DuckDB error types raised by the query or its input, not the environment
+const FATAL_DUCKDB_ERRORS: [&str; 7] = [
+    "Binder Error",
+    "Catalog Error",
+    "Constraint Error",
+    "Conversion Error",
+    "Invalid Input Error",
+    "Not implemented Error",
+    "Parser Error",
+];
+
+#[derive(Debug, Error)]
+pub enum GnatError {
+    #[error("duckdb: {0}")]
+    DuckDb(#[from] duckdb::Error),
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("config: {0}")]
+    Config(String),
+    #[error("schema: {0}")]
+    Schema(String),
+    #[error("upstream: {0}")]
+    Upstream(String),
+}
+
+impl GnatError {
+    pub fn retryable(&self) -> bool {
+        match self {
+            GnatError::DuckDb(duckdb::Error::DuckDBFailure(_, message)) => {
+                let message = message.as_deref().unwrap_or("");
+                !FATAL_DUCKDB_ERRORS
+                    .iter()
+                    .any(|prefix| message.starts_with(prefix))
+            }
+            // conversions and the like fail the same on every attempt
+            GnatError::DuckDb(_) => false,
+            GnatError::Io(e) => !matches!(
+                e.kind(),
+                std::io::ErrorKind::InvalidData
+                    | std::io::ErrorKind::InvalidInput
+                    | std::io::ErrorKind::Unsupported
+            ),
+            GnatError::Config(_) | GnatError::Schema(_) => false,
+            GnatError::Upstream(_) => true,
+        }
+    }
+
+    pub fn fatal(&self) -> bool {
+        !self.retryable()
+    }
+}
+
+// the stages' entry points still return std::io::Error
+impl From<GnatError> for std::io::Error {
+    fn from(e: GnatError) -> std::io::Error {
+        match e {
+            GnatError::Io(e) => e,
+            e => std::io::Error::other(e),
+        }
+    }
+}
@@ -6,71 +6,325 @@
  * See license information in LICENSE.
  */
 
+use crate::core::anonymize;
+use crate::core::encrypt;
+use crate::core::lineage;
+use crate::core::logging;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::shutdown;
+use crate::core::tenant;
+use crate::core::watch::{self, Watch};
+use crate::core::watermark;
+
 use std::fs;
-use std::path::PathBuf;
-use std::thread;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use duckdb::Connection;
+use tracing::{error, info};
+
+fn execute_command(conn: &Connection, sql_command: &str, input_spec: &str) -> bool {
+    match conn.execute_batch(sql_command) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("exporting file {} -- {:?}", input_spec, e);
+            return false;
+        }
+    };
+    true
+}
+
+fn chunk_name(output_spec: &String, sequence: u64, suffix: &str) -> String {
+    format!("{}.{:04}{}", output_spec, sequence, suffix)
+}
+
+//
+// Write the records of memtable under the parent of output_spec in
+// tenant=<tenant>/year=<yyyy>/month=<mm>/day=<dd>/ directories, each
+// file named after output_spec; flows without a tenant go to tenant=NULL,
+// which hive-partitioned reads take as a NULL tenant
+//
+fn export_partitioned(conn: &Connection, output_spec: &String, copy_options: &String, suffix: &str) -> bool {
+    let path = Path::new(output_spec);
+    let root = path.parent().unwrap_or(Path::new("."));
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    let partitions: Vec<(Option<String>, String, String, String)> = {
+        let mut stmt = match conn.prepare(
+            "SELECT DISTINCT tenant, strftime(stime, '%Y'), strftime(stime, '%m'), strftime(stime, '%d')
+                FROM memtable ORDER BY ALL;",
+        ) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("partitioning {} -- {:?}", output_spec, e);
+                return false;
+            }
+        };
+        match stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))) {
+            Ok(rows) => rows.filter_map(|r| r.ok()).collect(),
+            Err(e) => {
+                error!("partitioning {} -- {:?}", output_spec, e);
+                return false;
+            }
+        }
+    };
+    for (tenant, year, month, day) in partitions.iter() {
+        let (tenant_dir, tenant_sql) = match tenant {
+            Some(t) if tenant::valid_name(t) => (t.clone(), format!("'{}'", t)),
+            Some(t) => {
+                error!("partitioning {} -- invalid tenant name {}", output_spec, t);
+                return false;
+            }
+            None => (String::from("NULL"), String::from("NULL")),
+        };
+        let partition_dir = root
+            .join(format!("tenant={}", tenant_dir))
+            .join(format!("year={}", year))
+            .join(format!("month={}", month))
+            .join(format!("day={}", day));
+        if let Err(e) = fs::create_dir_all(&partition_dir) {
+            error!("creating {} -- {:?}", partition_dir.display(), e);
+            return false;
+        }
+        let sql_command = format!(
+            "COPY (SELECT * FROM memtable
+                    WHERE tenant IS NOT DISTINCT FROM {} AND stime::DATE = DATE '{}-{}-{}')
+                TO '{}/{}{}' ({});",
+            tenant_sql,
+            year,
+            month,
+            day,
+            partition_dir.display(),
+            file_name,
+            suffix,
+            copy_options
+        );
+        if !execute_command(conn, &sql_command, output_spec) {
+            return false;
+        }
+    }
+    info!("exported: {} [{} partitions]", output_spec, partitions.len());
+    true
+}
+
+//
+// Options of gnat_export, as parsed and checked by main()
+//
+pub struct ExportConfig {
+    pub input_spec: String,
+    pub output_spec: String,
+    pub processed_spec: String,
+    pub polling: bool,
+    pub format: String,
+    pub max_rows: u64,
+    pub max_bytes: u64,
+    pub compression: String,
+    pub partition: bool,
+}
 
-pub fn export_file(input_spec: &String, output_spec: &String, format: &String) -> bool {
-    let conn = match Connection::open_in_memory() {
+pub fn export_file(input_spec: &String, output_spec: &String, config: &ExportConfig) -> bool {
+    let ExportConfig {
+        ref format,
+        max_rows,
+        max_bytes,
+        ref compression,
+        partition,
+        ..
+    } = *config;
+    let conn = match scratch::open_in_memory("export") {
         Ok(s) => s,
         Err(e) => panic!("Error:  open_in_memory() - {}", e),
     };
+    let source = match schema::select(&conn, input_spec) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("reading {} -- {:?}", input_spec, e);
+            return false;
+        }
+    };
+    let source = match anonymize::apply(&conn, &source) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("anonymizing {} -- {:?}", input_spec, e);
+            return false;
+        }
+    };
 
-    let sql_command: String;
+    let mut copy_options: String;
     match format.as_str() {
         "csv" => {
-            sql_command = format!(
-                "COPY (SELECT * FROM '{}') TO '{}.csv' (HEADER, DELIMITER ',');",
-                input_spec, output_spec
-            );
-            println!("exported: {} => {}", input_spec, output_spec);
+            copy_options = String::from("FORMAT 'csv', HEADER, DELIMITER ','");
         }
-        "json" => {
-            sql_command = format!(
-                "COPY (SELECT * FROM '{}') TO '{}';",
-                input_spec, output_spec
-            );
+        "parquet" => {
+            // re-encoded with the codec selected by --compression
+            copy_options = schema::copy_options();
+            if encrypt::enabled() {
+                if let Err(e) = encrypt::add_key(&conn) {
+                    error!("loading the parquet key -- {:?}", e);
+                    return false;
+                }
+                copy_options.push_str(&format!(", {}", encrypt::copy_option()));
+            }
         }
         _ => {
-            // default is JSON
-            sql_command = format!(
-                "COPY (SELECT * FROM '{}') TO '{}';",
-                input_spec, output_spec
-            );
-            println!("exported: {} => {}", input_spec, output_spec);
+            // default is JSON, written one object per line (NDJSON)
+            copy_options = String::from("FORMAT 'json'");
         }
     }
 
-    match conn.execute_batch(&sql_command) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("Error: exporting file {} -- {:?}", input_spec, e);
+    let mut suffix = "";
+    if format != "parquet" {
+        match compression.as_str() {
+            "gzip" => {
+                copy_options.push_str(", COMPRESSION 'gzip'");
+                suffix = ".gz";
+            }
+            "zstd" => {
+                copy_options.push_str(", COMPRESSION 'zstd'");
+                suffix = ".zst";
+            }
+            _ => {}
+        }
+    }
+
+    if partition {
+        let sql_command = format!("CREATE TABLE memtable AS {};", source);
+        if !execute_command(&conn, &sql_command, input_spec) {
+            return false;
+        }
+        if !export_partitioned(&conn, output_spec, &copy_options, suffix) {
+            return false;
+        }
+    } else if max_rows > 0 {
+        //
+        // split the export into files of at most max_rows records
+        //
+        let sql_command = format!("CREATE TABLE memtable AS {};", source);
+        if !execute_command(&conn, &sql_command, input_spec) {
+            return false;
+        }
+        let record_count: u64 =
+            match conn.query_row("SELECT count(*) FROM memtable;", [], |row| row.get(0)) {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("counting records {} -- {:?}", input_spec, e);
+                    return false;
+                }
+            };
+
+        let mut sequence = 0;
+        let mut offset = 0;
+        while offset < record_count {
+            let chunk_spec = chunk_name(output_spec, sequence, suffix);
+            let sql_command = format!(
+                "COPY (SELECT * FROM memtable WHERE rowid >= {} AND rowid < {}) TO '{}' ({});",
+                offset,
+                offset + max_rows,
+                chunk_spec,
+                copy_options
+            );
+            if !execute_command(&conn, &sql_command, input_spec) {
+                return false;
+            }
+            offset += max_rows;
+            sequence += 1;
+        }
+        info!("exported: {} => {} [{} files]", input_spec, output_spec, sequence);
+    } else if max_bytes > 0 {
+        //
+        // let DuckDB split the export by size into a scratch directory,
+        // then move the chunks next to the output spec in sequence order
+        //
+        let chunk_dir = format!("{}.chunks", output_spec);
+        let sql_command = format!(
+            "COPY ({}) TO '{}' ({}, FILE_SIZE_BYTES {}, FILENAME_PATTERN 'chunk_{{i}}');",
+            source, chunk_dir, copy_options, max_bytes
+        );
+        if !execute_command(&conn, &sql_command, input_spec) {
+            let _ = fs::remove_dir_all(&chunk_dir);
             return false;
         }
-    };
+
+        let directory = match fs::read_dir(&chunk_dir) {
+            Ok(d) => d,
+            Err(e) => {
+                error!("reading directory {} -- {:?}", chunk_dir, e);
+                return false;
+            }
+        };
+        let mut counter = 0;
+        for entry in directory {
+            if shutdown::requested() {
+                break;
+            }
+            let file = entry.unwrap();
+            let file_name = String::from(file.file_name().to_string_lossy());
+            let sequence: u64 = match file_name
+                .trim_start_matches("chunk_")
+                .split('.')
+                .next()
+                .unwrap_or("")
+                .parse()
+            {
+                Ok(n) => n,
+                Err(_) => continue,
+            };
+            let chunk_spec = chunk_name(output_spec, sequence, suffix);
+            match fs::rename(file.path(), chunk_spec.clone()) {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("moving {} -> {}: {:?}", file_name, chunk_spec, e);
+                    return false;
+                }
+            };
+            counter += 1;
+        }
+        let _ = fs::remove_dir_all(&chunk_dir);
+        info!("exported: {} => {} [{} files]", input_spec, output_spec, counter);
+    } else {
+        let sql_command = format!(
+            "COPY ({}) TO '{}{}' ({});",
+            source, output_spec, suffix, copy_options
+        );
+        if !execute_command(&conn, &sql_command, input_spec) {
+            return false;
+        }
+        info!("exported: {} => {}{}", input_spec, output_spec, suffix);
+    }
 
     true
 }
 
-pub fn export(
-    input_spec: &String,
-    output_spec: &String,
-    processed_spec: &String,
-    polling: bool,
-    format: &String,
-) -> Result<(), std::io::Error> {
+pub fn export(config: &ExportConfig) -> Result<(), std::io::Error> {
+    let ExportConfig {
+        ref input_spec,
+        ref output_spec,
+        ref processed_spec,
+        polling,
+        ref format,
+        max_rows,
+        max_bytes,
+        ref compression,
+        partition,
+    } = *config;
     if PathBuf::from(input_spec.clone()).is_dir() {
-        println!("\tinput spec: {}", input_spec);
-        println!("\toutput spec: {}", output_spec);
-        println!("\tprocessed spec: {}", processed_spec);
-        println!("\texport format: {}", format);
-        println!("\tpolling: {}", polling);
+        info!("input spec: {}", input_spec);
+        info!("output spec: {}", output_spec);
+        info!("processed spec: {}", processed_spec);
+        info!("export format: {}", format);
+        info!("polling: {}", polling);
+        info!("max rows: {}", max_rows);
+        info!("max bytes: {}", max_bytes);
+        info!("compression: {}", compression);
+        info!("partition: {}", partition);
+        info!("encrypt: {}", encrypt::enabled());
+        info!("anonymize: {}", anonymize::enabled());
 
         let poll_interval = Duration::from_millis(1000);
-        println!("export scanner: running [{}]", input_spec);
+        let watch = Watch::new(input_spec);
+        let _pool = scratch::pool();
+        info!("export scanner: running [{}]", input_spec);
         loop {
             let mut counter = 0;
             let directory = match fs::read_dir(input_spec) {
@@ -97,14 +351,21 @@ pub fn export(
                 }
 
                 if !file_name.starts_with(".") && file_name.ends_with(".parquet") {
+                    if !watermark::wait("export", output_spec) {
+                        break;
+                    }
+                    let _batch = logging::batch(&file_name);
+                    let _lineage = lineage::begin("export", &[(file_name.clone(), src_path.clone())]);
                     let dst_spec;
                     if format == "questdb" {
                         dst_spec = output_spec.clone();
+                    } else if format == "parquet" {
+                        dst_spec = format!("{}/{}", output_spec, file_name);
                     } else {
                         dst_spec = format!("{}/{}.{}", output_spec, file_name, format);
                     }
 
-                    if export_file(&src_path, &dst_spec, format) {
+                    if export_file(&src_path, &dst_spec, config) {
                         if !processed_spec.is_empty() {
                             let processed_path =
                                 format!("{}/{}", &processed_spec, file_name.to_string());
@@ -118,21 +379,23 @@ pub fn export(
                             };
                         }
                     } else {
-                        eprintln!("Error: exporting {} => {}", src_path, dst_spec);
-                        std::process::exit(exitcode::PROTOCOL);
+                        error!("exporting {} => {}", src_path, dst_spec);
+                        shutdown::exit(exitcode::PROTOCOL);
                     }
                     counter += 1;
                 }
             }
-            if !polling {
+            if !polling || shutdown::requested() {
                 break;
             }
-            if counter == 0 {
-                thread::sleep(poll_interval);
+            if counter == 0 && !watch::wait(&watch, poll_interval) {
+                break;
             }
         }
     } else {
-        export_file(input_spec, output_spec, format);
+        let file_name = input_spec.rsplit('/').next().unwrap_or(input_spec);
+        let _lineage = lineage::begin("export", &[(String::from(file_name), input_spec.clone())]);
+        export_file(input_spec, output_spec, config);
     }
     Ok(())
 }
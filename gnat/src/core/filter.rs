@@ -0,0 +1,509 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Flow filter and metric expressions
+//
+// Rule filters are a small expression language over the flow columns
+// rather than SQL fragments:
+//
+//   filter   := term ("or" term)*
+//   term     := factor ("and" factor)*
+//   factor   := "not" factor | "(" filter ")" | compare
+//   compare  := column op value
+//             | column ["not"] "in" "(" value ("," value)* ")"
+//             | column ["not"] "like" string
+//             | column "is" ["not"] "null"
+//   op       := = | != | <> | < | <= | > | >=
+//   value    := 'string' | ["-"] number | true | false
+//
+// e.g. appid = 'dns' and dport in (53, 853)
+//      not (dcountry in ('US', 'CA') or dasn is null)
+//
+// Keywords are case-insensitive and a quote inside a string is doubled,
+// as in SQL ('o''brien'). A filter is parsed into a Filter, whose columns
+// are checked against the flow schema, and written back as SQL with the
+// columns quoted and the values escaped, so it can only select flows.
+//
+// Rule metrics are arithmetic (+ - * /) over numbers, flow columns and
+// calls of the FUNCTIONS, e.g. sum(sbytes) / nullif(sum(dbytes), 0) or
+// count(distinct daddr), and are written back the same way.
+//
+
+use crate::core::schema;
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Number(String),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Compare { column: String, op: &'static str, value: Value },
+    In { column: String, negated: bool, values: Vec<Value> },
+    Like { column: String, negated: bool, pattern: String },
+    Null { column: String, negated: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 14] = [
+    "<=", ">=", "<>", "!=", "=", "<", ">", "(", ")", ",", "+", "-", "*", "/",
+];
+const COMPARISONS: [&str; 7] = ["=", "!=", "<>", "<", "<=", ">", ">="];
+
+// functions a metric can call
+const FUNCTIONS: [&str; 16] = [
+    "count", "sum", "avg", "min", "max", "median", "stddev", "approx_count_distinct",
+    "nullif", "coalesce", "greatest", "least", "abs", "ln", "log10", "sqrt",
+];
+
+//
+// SQL string literal of text
+//
+pub fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+//
+// A flow column, lowercased, or an error naming it when the flow schema
+// has no such column
+//
+pub fn column(name: &str) -> Result<String, String> {
+    let name = name.to_ascii_lowercase();
+    if schema::flow_columns().contains(&name.as_str()) {
+        Ok(name)
+    } else {
+        Err(format!("unknown column {}", name))
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' {
+            let mut literal = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(String::from("unterminated string")),
+                    Some('\'') if chars.get(i + 1) == Some(&'\'') => {
+                        literal.push('\'');
+                        i += 2;
+                    }
+                    Some('\'') => {
+                        i += 1;
+                        break;
+                    }
+                    Some(c) => {
+                        literal.push(*c);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(Token::Text(literal));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric()
+                    || chars[i] == '.'
+                    || ((chars[i] == '-' || chars[i] == '+') && matches!(chars[i - 1], 'e' | 'E')))
+            {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            if number.parse::<f64>().is_err() {
+                return Err(format!("bad number {}", number));
+            }
+            tokens.push(Token::Number(number));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) else {
+                return Err(format!("unexpected {}", c));
+            };
+            tokens.push(Token::Symbol(symbol));
+            i += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.next += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.symbol(symbol) {
+            Ok(())
+        } else {
+            Err(format!("expected {} {}", symbol, self.found()))
+        }
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            None => String::from("at the end"),
+            Some(Token::Word(word)) => format!("at {}", word),
+            Some(Token::Text(text)) => format!("at {}", quote(text)),
+            Some(Token::Number(number)) => format!("at {}", number),
+            Some(Token::Symbol(symbol)) => format!("at {}", symbol),
+        }
+    }
+
+    fn filter(&mut self) -> Result<Filter, String> {
+        let mut filter = self.term()?;
+        while self.keyword("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.term()?));
+        }
+        Ok(filter)
+    }
+
+    fn term(&mut self) -> Result<Filter, String> {
+        let mut filter = self.factor()?;
+        while self.keyword("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.factor()?));
+        }
+        Ok(filter)
+    }
+
+    fn factor(&mut self) -> Result<Filter, String> {
+        if self.keyword("not") {
+            return Ok(Filter::Not(Box::new(self.factor()?)));
+        }
+        if self.symbol("(") {
+            let filter = self.filter()?;
+            self.expect(")")?;
+            return Ok(filter);
+        }
+        self.compare()
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        let found = self.found();
+        match self.advance() {
+            Some(Token::Text(text)) => Ok(Value::Text(text)),
+            Some(Token::Number(number)) => Ok(Value::Number(number)),
+            Some(Token::Symbol("-")) => match self.advance() {
+                Some(Token::Number(number)) => Ok(Value::Number(format!("-{}", number))),
+                _ => Err(format!("expected a number {}", found)),
+            },
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("true") => Ok(Value::Bool(true)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("false") => Ok(Value::Bool(false)),
+            _ => Err(format!("expected a value {}", found)),
+        }
+    }
+
+    fn compare(&mut self) -> Result<Filter, String> {
+        let found = self.found();
+        let column = match self.advance() {
+            Some(Token::Word(word)) => column(&word)?,
+            _ => return Err(format!("expected a column {}", found)),
+        };
+        if self.keyword("is") {
+            let negated = self.keyword("not");
+            if !self.keyword("null") {
+                return Err(format!("expected null {}", self.found()));
+            }
+            return Ok(Filter::Null { column, negated });
+        }
+        let negated = self.keyword("not");
+        if self.keyword("in") {
+            self.expect("(")?;
+            let mut values = vec![self.value()?];
+            while self.symbol(",") {
+                values.push(self.value()?);
+            }
+            self.expect(")")?;
+            return Ok(Filter::In { column, negated, values });
+        }
+        if self.keyword("like") {
+            let found = self.found();
+            return match self.advance() {
+                Some(Token::Text(pattern)) => Ok(Filter::Like { column, negated, pattern }),
+                _ => Err(format!("expected a string {}", found)),
+            };
+        }
+        if negated {
+            return Err(format!("expected in or like {}", self.found()));
+        }
+        let found = self.found();
+        let op = match self.advance() {
+            Some(Token::Symbol(op)) if COMPARISONS.contains(&op) => op,
+            _ => return Err(format!("expected a comparison {}", found)),
+        };
+        Ok(Filter::Compare { column, op, value: self.value()? })
+    }
+
+    fn sum(&mut self) -> Result<String, String> {
+        let mut sql = self.product()?;
+        loop {
+            if self.symbol("+") {
+                sql = format!("{} + {}", sql, self.product()?);
+            } else if self.symbol("-") {
+                sql = format!("{} - {}", sql, self.product()?);
+            } else {
+                return Ok(sql);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<String, String> {
+        let mut sql = self.unary()?;
+        loop {
+            if self.symbol("*") {
+                sql = format!("{} * {}", sql, self.unary()?);
+            } else if self.symbol("/") {
+                sql = format!("{} / {}", sql, self.unary()?);
+            } else {
+                return Ok(sql);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<String, String> {
+        if self.symbol("-") {
+            return Ok(format!("-{}", self.unary()?));
+        }
+        let found = self.found();
+        match self.advance() {
+            Some(Token::Number(number)) => Ok(number),
+            Some(Token::Symbol("(")) => {
+                let sql = self.sum()?;
+                self.expect(")")?;
+                Ok(format!("({})", sql))
+            }
+            Some(Token::Word(word)) if self.symbol("(") => self.call(&word),
+            Some(Token::Word(word)) => Ok(format!("\"{}\"", column(&word)?)),
+            _ => Err(format!("expected a number, column or function {}", found)),
+        }
+    }
+
+    fn call(&mut self, function: &str) -> Result<String, String> {
+        let function = function.to_ascii_lowercase();
+        if !FUNCTIONS.contains(&function.as_str()) {
+            return Err(format!("unknown function {}", function));
+        }
+        if self.symbol(")") {
+            return Ok(format!("{}()", function));
+        }
+        if function == "count" && self.symbol("*") {
+            self.expect(")")?;
+            return Ok(String::from("count(*)"));
+        }
+        let distinct = if self.keyword("distinct") { "DISTINCT " } else { "" };
+        let mut args = vec![self.sum()?];
+        while self.symbol(",") {
+            args.push(self.sum()?);
+        }
+        self.expect(")")?;
+        Ok(format!("{}({}{})", function, distinct, args.join(", ")))
+    }
+}
+
+//
+// A metric expression as SQL
+//
+pub fn metric(text: &str) -> Result<String, String> {
+    let mut parser = Parser { tokens: tokenize(text)?, next: 0 };
+    let sql = parser.sum()?;
+    if parser.peek().is_some() {
+        return Err(format!("unexpected {}", parser.found()));
+    }
+    Ok(sql)
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Result<Filter, String> {
+        let mut parser = Parser { tokens: tokenize(text)?, next: 0 };
+        let filter = parser.filter()?;
+        if parser.peek().is_some() {
+            return Err(format!("unexpected {}", parser.found()));
+        }
+        Ok(filter)
+    }
+
+    //
+    // The filter as a SQL predicate
+    //
+    pub fn to_sql(&self) -> String {
+        match self {
+            Filter::And(left, right) => format!("({} AND {})", left.to_sql(), right.to_sql()),
+            Filter::Or(left, right) => format!("({} OR {})", left.to_sql(), right.to_sql()),
+            Filter::Not(filter) => format!("(NOT {})", filter.to_sql()),
+            Filter::Compare { column, op, value } => format!("\"{}\" {} {}", column, op, value),
+            Filter::In { column, negated, values } => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                format!(
+                    "\"{}\" {}IN ({})",
+                    column,
+                    if *negated { "NOT " } else { "" },
+                    values.join(", ")
+                )
+            }
+            Filter::Like { column, negated, pattern } => format!(
+                "\"{}\" {}LIKE {}",
+                column,
+                if *negated { "NOT " } else { "" },
+                quote(pattern)
+            ),
+            Filter::Null { column, negated } => {
+                format!("\"{}\" IS {}NULL", column, if *negated { "NOT " } else { "" })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Text(text) => write!(f, "{}", quote(text)),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_symbols_words_and_numbers() {
+        assert_eq!(
+            tokenize("dport>=1024 AND sbytes<>1.5e-3").unwrap(),
+            vec![
+                Token::Word(String::from("dport")),
+                Token::Symbol(">="),
+                Token::Number(String::from("1024")),
+                Token::Word(String::from("AND")),
+                Token::Word(String::from("sbytes")),
+                Token::Symbol("<>"),
+                Token::Number(String::from("1.5e-3")),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_doubled_quotes() {
+        assert_eq!(
+            tokenize("'o''brien' ''").unwrap(),
+            vec![Token::Text(String::from("o'brien")), Token::Text(String::new())]
+        );
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        assert_eq!(tokenize("appid = 'dns"), Err(String::from("unterminated string")));
+        assert_eq!(tokenize("appid = 'it''s"), Err(String::from("unterminated string")));
+        assert_eq!(tokenize("dport = 53x"), Err(String::from("bad number 53x")));
+        assert_eq!(tokenize("appid ; 1"), Err(String::from("unexpected ;")));
+    }
+
+    #[test]
+    fn quote_doubles_quotes() {
+        assert_eq!(quote("o'brien"), "'o''brien'");
+        assert_eq!(quote("'; DROP TABLE flow; --"), "'''; DROP TABLE flow; --'");
+    }
+
+    #[test]
+    fn filter_to_sql() {
+        let filter = Filter::parse("appid = 'dns' and dport in (53, 853)").unwrap();
+        assert_eq!(filter.to_sql(), "(\"appid\" = 'dns' AND \"dport\" IN (53, 853))");
+
+        let filter = Filter::parse("NOT (dcountry NOT IN ('US', 'CA') OR dasn IS NULL)").unwrap();
+        assert_eq!(
+            filter.to_sql(),
+            "(NOT (\"dcountry\" NOT IN ('US', 'CA') OR \"dasn\" IS NULL))"
+        );
+
+        let filter = Filter::parse("sni like '%.example.com' and dport != -1").unwrap();
+        assert_eq!(filter.to_sql(), "(\"sni\" LIKE '%.example.com' AND \"dport\" != -1)");
+    }
+
+    #[test]
+    fn filter_escapes_strings() {
+        let filter = Filter::parse("httphost = 'x'' OR true --'").unwrap();
+        assert_eq!(filter.to_sql(), "\"httphost\" = 'x'' OR true --'");
+    }
+
+    #[test]
+    fn filter_errors() {
+        assert_eq!(Filter::parse("nosuch = 1"), Err(String::from("unknown column nosuch")));
+        assert_eq!(Filter::parse("appid"), Err(String::from("expected a comparison at the end")));
+        assert_eq!(Filter::parse("dport in (53"), Err(String::from("expected ) at the end")));
+        assert_eq!(Filter::parse("appid = 'dns' dport"), Err(String::from("unexpected at dport")));
+        assert_eq!(Filter::parse("appid not = 'dns'"), Err(String::from("expected in or like at =")));
+    }
+
+    #[test]
+    fn metric_to_sql() {
+        assert_eq!(
+            metric("sum(sbytes) / nullif(sum(dbytes), 0)").unwrap(),
+            "sum(\"sbytes\") / nullif(sum(\"dbytes\"), 0)"
+        );
+        assert_eq!(metric("count(distinct daddr)").unwrap(), "count(DISTINCT \"daddr\")");
+        assert_eq!(metric("COUNT(*)").unwrap(), "count(*)");
+        assert_eq!(metric("system('ls')"), Err(String::from("unknown function system")));
+    }
+}
@@ -0,0 +1,239 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Flow schema definition and migrations, without dependencies on the rest
+// of gnat: schema.rs includes it, and so does gnat_client/src/schema.rs so
+// that the client reads with the same columns and upgrades
+//
+
+use duckdb::Connection;
+
+pub const FLOW_SCHEMA_VERSION: u32 = 8;
+pub const VERSION_KEY: &str = "gnat_schema_version";
+
+// FLOW_SCHEMA of export_parquet.h, for flow records produced in Rust
+pub const FLOW_TABLE: &str = "CREATE TABLE flow (
+    observ VARCHAR, stime TIMESTAMP, etime TIMESTAMP, dur UINTEGER, rtt UINTEGER, pcr FLOAT,
+    proto VARCHAR, saddr VARCHAR, daddr VARCHAR, sport USMALLINT, dport USMALLINT,
+    iflags VARCHAR, uflags VARCHAR,
+    stcpseq UINTEGER, dtcpseq UINTEGER,
+    svlan USMALLINT, dvlan USMALLINT,
+    spkts UBIGINT, dpkts UBIGINT,
+    sbytes UBIGINT, dbytes UBIGINT,
+    sentropy UTINYINT, dentropy UTINYINT,
+    siat UBIGINT, diat UBIGINT,
+    sstdev UBIGINT, dstdev UBIGINT,
+    stcpurg UINTEGER, dtcpurg UINTEGER,
+    ssmallpktcnt UINTEGER, dsmallpktcnt UINTEGER,
+    slargpktcnt UINTEGER, dlargpktcnt UINTEGER,
+    snonemptypktcnt UINTEGER, dnonemptypktcnt UINTEGER,
+    sfirstnonemptycnt USMALLINT, dfirstnonemptycnt USMALLINT,
+    sstdevpayload USMALLINT, dstdevpayload USMALLINT,
+    smaxpktsize USMALLINT, dmaxpktsize USMALLINT,
+    spd VARCHAR, appid VARCHAR, reason VARCHAR,
+    smac VARCHAR, dmac VARCHAR,
+    scountry VARCHAR, dcountry VARCHAR,
+    sasn UINTEGER, dasn UINTEGER,
+    sasnorg VARCHAR, dasnorg VARCHAR,
+    scity VARCHAR, dcity VARCHAR,
+    slat DOUBLE, slon DOUBLE, dlat DOUBLE, dlon DOUBLE,
+    sni VARCHAR, tlsissuer VARCHAR, tlssubject VARCHAR, tlsnotafter TIMESTAMP,
+    ja3 VARCHAR, ja3s VARCHAR, ja4 VARCHAR, ja4s VARCHAR,
+    httpmethod VARCHAR, httphost VARCHAR, httpuseragent VARCHAR, httpstatus USMALLINT,
+    dga_score FLOAT,
+    orient VARCHAR,
+    site VARCHAR,
+    tenant VARCHAR,
+    model VARCHAR, score FLOAT
+)";
+
+struct Column {
+    name: &'static str,
+    sql_type: &'static str,
+    default: &'static str,
+    // placed after this column (or last when it's missing)
+    after: &'static str,
+}
+
+struct Migration {
+    version: u32,
+    description: &'static str,
+    renamed: &'static [(&'static str, &'static str)],
+    added: &'static [Column],
+}
+
+// oldest first; version 1 is the unversioned original schema
+const MIGRATIONS: [Migration; 7] = [
+    Migration {
+        version: 2,
+        description: "GeoIP2 city and location",
+        renamed: &[],
+        added: &[
+            Column { name: "scity", sql_type: "VARCHAR", default: "'unk'", after: "dasnorg" },
+            Column { name: "dcity", sql_type: "VARCHAR", default: "'unk'", after: "scity" },
+            Column { name: "slat", sql_type: "DOUBLE", default: "NULL", after: "dcity" },
+            Column { name: "slon", sql_type: "DOUBLE", default: "NULL", after: "slat" },
+            Column { name: "dlat", sql_type: "DOUBLE", default: "NULL", after: "slon" },
+            Column { name: "dlon", sql_type: "DOUBLE", default: "NULL", after: "dlat" },
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "TLS server name, certificate and fingerprints",
+        renamed: &[],
+        added: &[
+            Column { name: "sni", sql_type: "VARCHAR", default: "NULL", after: "dlon" },
+            Column { name: "tlsissuer", sql_type: "VARCHAR", default: "NULL", after: "sni" },
+            Column { name: "tlssubject", sql_type: "VARCHAR", default: "NULL", after: "tlsissuer" },
+            Column { name: "tlsnotafter", sql_type: "TIMESTAMP", default: "NULL", after: "tlssubject" },
+            Column { name: "ja3", sql_type: "VARCHAR", default: "NULL", after: "tlsnotafter" },
+            Column { name: "ja3s", sql_type: "VARCHAR", default: "NULL", after: "ja3" },
+            Column { name: "ja4", sql_type: "VARCHAR", default: "NULL", after: "ja3s" },
+            Column { name: "ja4s", sql_type: "VARCHAR", default: "NULL", after: "ja4" },
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "HTTP method, host, user-agent and status",
+        renamed: &[],
+        added: &[
+            Column { name: "httpmethod", sql_type: "VARCHAR", default: "NULL", after: "ja4s" },
+            Column { name: "httphost", sql_type: "VARCHAR", default: "NULL", after: "httpmethod" },
+            Column { name: "httpuseragent", sql_type: "VARCHAR", default: "NULL", after: "httphost" },
+            Column { name: "httpstatus", sql_type: "USMALLINT", default: "NULL", after: "httpuseragent" },
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "DGA score",
+        renamed: &[],
+        added: &[
+            Column { name: "dga_score", sql_type: "FLOAT", default: "NULL", after: "httpstatus" },
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "flow orientation",
+        renamed: &[],
+        added: &[
+            Column { name: "orient", sql_type: "VARCHAR", default: "NULL", after: "dga_score" },
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "site name",
+        renamed: &[],
+        added: &[
+            Column { name: "site", sql_type: "VARCHAR", default: "NULL", after: "orient" },
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "tenant",
+        renamed: &[],
+        added: &[
+            Column { name: "tenant", sql_type: "VARCHAR", default: "NULL", after: "site" },
+        ],
+    },
+];
+
+//
+// Column names of FLOW_TABLE, in order
+//
+pub fn flow_columns() -> Vec<&'static str> {
+    let body = FLOW_TABLE.split_once('(').map(|(_, body)| body).unwrap_or_default();
+    body.trim_end()
+        .trim_end_matches(')')
+        .split(',')
+        .filter_map(|column| column.split_whitespace().next())
+        .collect()
+}
+
+fn columns(conn: &Connection, input_spec: &str) -> Result<Vec<String>, duckdb::Error> {
+    let mut stmt = conn.prepare(&format!("DESCRIBE SELECT * FROM '{}';", input_spec))?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    rows.collect()
+}
+
+//
+// Version recorded in the file, else the newest version whose columns are
+// all present
+//
+pub fn version(conn: &Connection, input_spec: &str) -> Result<u32, duckdb::Error> {
+    let sql_command = format!(
+        "SELECT decode(value) FROM parquet_kv_metadata('{}') WHERE decode(key) = '{}';",
+        input_spec, VERSION_KEY
+    );
+    let recorded: Option<String> = conn.query_row(&sql_command, [], |row| row.get(0)).ok();
+    if let Some(version) = recorded.and_then(|v| v.parse::<u32>().ok()) {
+        return Ok(version);
+    }
+    let present = columns(conn, input_spec)?;
+    let mut version = 1;
+    for migration in MIGRATIONS.iter() {
+        let complete = migration
+            .added
+            .iter()
+            .all(|c| present.iter().any(|p| p == c.name));
+        if !complete {
+            break;
+        }
+        version = migration.version;
+    }
+    Ok(version)
+}
+//
+// Version and description of the migrations that upgrade a file at
+// from_version
+//
+pub fn migrations(from_version: u32) -> Vec<(u32, &'static str)> {
+    MIGRATIONS
+        .iter()
+        .filter(|m| m.version > from_version)
+        .map(|m| (m.version, m.description))
+        .collect()
+}
+
+//
+// Select expressions reading input_spec, a file at from_version, upgraded
+// to FLOW_SCHEMA_VERSION: renamed columns are aliased and added columns are
+// filled with their default
+//
+pub fn upgraded_columns(
+    conn: &Connection,
+    input_spec: &str,
+    from_version: u32,
+) -> Result<Vec<String>, duckdb::Error> {
+    // (output name, select expression)
+    let mut select_list: Vec<(String, String)> = columns(conn, input_spec)?
+        .into_iter()
+        .map(|c| (c.clone(), format!("\"{}\"", c)))
+        .collect();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from_version) {
+        for (old, new) in migration.renamed.iter() {
+            if let Some(entry) = select_list.iter_mut().find(|(name, _)| name == old) {
+                *entry = (String::from(*new), format!("\"{}\" AS \"{}\"", old, new));
+            }
+        }
+        for column in migration.added.iter() {
+            if select_list.iter().any(|(name, _)| name == column.name) {
+                continue;
+            }
+            let entry = (
+                String::from(column.name),
+                format!("{}::{} AS \"{}\"", column.default, column.sql_type, column.name),
+            );
+            match select_list.iter().position(|(name, _)| name == column.after) {
+                Some(position) => select_list.insert(position + 1, entry),
+                None => select_list.push(entry),
+            }
+        }
+    }
+    Ok(select_list.into_iter().map(|(_, e)| e).collect())
+}
@@ -0,0 +1,294 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Liveness and readiness probes (--healthcheck-port <port>)
+//
+//   GET /live    200 while the run loop makes progress, 503 once it has
+//                gone STALL_TIMEOUT without starting a file or waiting idle
+//   GET /ready   200 when the stage can work, 503 with the failed checks
+//
+// Readiness checks, on every request, that the stage's directories are
+// writable (files readable), that a DuckDB connection opens, and that each
+// s3:// path can be listed with the stage's S3 credentials. A stage whose
+// run loop never reports progress (the IPFIX collector, inside libfixbuf)
+// is live for as long as it answers.
+//
+
+use crate::core::context::{self, Local};
+use crate::core::control::{message, read_request, respond};
+use crate::core::scratch;
+use crate::core::shutdown;
+
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+const STALL_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+// a probe sends its request at once; a slow client only holds its own thread
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+// seconds since the epoch of the last progress; 0 until the first
+static LAST_BEAT: Local<AtomicU64> = Local::new();
+static STAGE: Local<String> = Local::new();
+
+#[derive(Debug, clap::Args)]
+pub struct HealthArgs {
+    /// port serving the /live and /ready probes (0 = none)
+    #[arg(long)]
+    pub healthcheck_port: Option<u16>,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    check: String,
+    ok: bool,
+    error: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn last_beat() -> &'static AtomicU64 {
+    LAST_BEAT.get_or_init(|| AtomicU64::new(0))
+}
+
+//
+// Record progress of the run loop
+//
+pub fn beat() {
+    last_beat().store(now(), Ordering::Relaxed);
+}
+
+fn live() -> bool {
+    let last = last_beat().load(Ordering::Relaxed);
+    last == 0 || now().saturating_sub(last) < STALL_TIMEOUT.as_secs()
+}
+
+pub(crate) fn check_path(path_spec: &str) -> Result<(), String> {
+    if path_spec.starts_with("s3://") {
+        let conn = scratch::open_s3().map_err(|e| e.to_string())?;
+        let sql_command = format!(
+            "SELECT count(*) FROM glob('{}/*');",
+            path_spec.trim_end_matches('/').replace('\'', "''")
+        );
+        return conn
+            .query_row(&sql_command, [], |row| row.get::<_, i64>(0))
+            .map(|_| ())
+            .map_err(|e| e.to_string());
+    }
+    let path = Path::new(path_spec);
+    if path.is_file() {
+        return fs::File::open(path).map(|_| ()).map_err(|e| e.to_string());
+    }
+    if !path.is_dir() {
+        return Err(String::from("not a directory"));
+    }
+    let probe = path.join(format!(".gnat-ready-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|e| e.to_string())?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+pub(crate) fn check_duckdb() -> Result<(), String> {
+    let conn = scratch::open_in_memory("health").map_err(|e| e.to_string())?;
+    conn.query_row("SELECT 1;", [], |row| row.get::<_, i32>(0))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn ready(paths: &[String]) -> (bool, Vec<Check>) {
+    let mut checks = Vec::new();
+    for path_spec in paths.iter() {
+        let result = check_path(path_spec);
+        checks.push(Check {
+            check: format!("path {}", path_spec),
+            ok: result.is_ok(),
+            error: result.err(),
+        });
+    }
+    let result = check_duckdb();
+    checks.push(Check {
+        check: String::from("duckdb"),
+        ok: result.is_ok(),
+        error: result.err(),
+    });
+    (checks.iter().all(|check| check.ok), checks)
+}
+
+fn handle(stream: &mut TcpStream, paths: &[String]) {
+    let Some((method, path)) = read_request(stream) else {
+        respond(stream, "400 Bad Request", &message("malformed request"));
+        return;
+    };
+    let stage = STAGE.get().map(|s| s.as_str()).unwrap_or("");
+    match (method.as_str(), path.as_str()) {
+        ("GET", "/live") => {
+            let body = serde_json::json!({ "stage": stage, "live": live() }).to_string();
+            if live() {
+                respond(stream, "200 OK", &body);
+            } else {
+                warn!("health: no progress for {:?}", STALL_TIMEOUT);
+                respond(stream, "503 Service Unavailable", &body);
+            }
+        }
+        ("GET", "/ready") => {
+            let (ok, checks) = ready(paths);
+            let body = serde_json::json!({ "stage": stage, "ready": ok, "checks": checks }).to_string();
+            if ok {
+                respond(stream, "200 OK", &body);
+            } else {
+                respond(stream, "503 Service Unavailable", &body);
+            }
+        }
+        (_, "/live") | (_, "/ready") => {
+            respond(stream, "405 Method Not Allowed", &message("method not allowed"))
+        }
+        _ => respond(stream, "404 Not Found", &message("not found")),
+    }
+}
+
+//
+// Serve the probes of stage on port from a background thread, checking
+// the non-empty paths for readiness; a port of 0 serves nothing
+//
+pub fn serve(stage: &str, port: u16, paths: &[&String]) -> Result<(), std::io::Error> {
+    if port == 0 {
+        return Ok(());
+    }
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    info!("healthcheck port: {}", port);
+    let _ = STAGE.set(String::from(stage));
+    let paths: Vec<String> = paths
+        .iter()
+        .filter(|path_spec| !path_spec.is_empty())
+        .map(|path_spec| (*path_spec).clone())
+        .collect();
+    let paths = Arc::new(paths);
+    let id = context::current();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    let paths = Arc::clone(&paths);
+                    thread::spawn(move || {
+                        context::enter(id);
+                        let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                        handle(&mut stream, &paths);
+                    });
+                }
+                Err(e) => warn!("health: accepting - {:?}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+//
+// Serve the probes of a stage's --healthcheck-port, exiting when the port
+// can't be bound
+//
+pub fn configure(stage: &str, args: &HealthArgs, paths: &[&String]) {
+    let port = args.healthcheck_port.unwrap_or(0);
+    if let Err(e) = serve(stage, port, paths) {
+        error!("--healthcheck-port {} - {}", port, e);
+        shutdown::exit(exitcode::CONFIG)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+
+    // status line and body of the response to request
+    fn call(paths: &[String], request: &str) -> (String, serde_json::Value) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        handle(&mut server, paths);
+        drop(server);
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn check_paths() {
+        let dir = PathBuf::from(test_dir("health"));
+        let file = dir.join("rules.toml");
+        fs::write(&file, b"").unwrap();
+        assert!(check_path(&dir.to_string_lossy()).is_ok());
+        assert!(check_path(&file.to_string_lossy()).is_ok());
+        assert_eq!(
+            check_path(&dir.join("missing").to_string_lossy()),
+            Err(String::from("not a directory"))
+        );
+        // the probe file is cleaned up
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(check_duckdb().is_ok());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn probes() {
+        thread::spawn(|| {
+            context::enter(context::next());
+            let _ = STAGE.set(String::from("tag"));
+            let dir = std::env::temp_dir().to_string_lossy().to_string();
+            let missing = format!("{}/gnat-{}-health-missing", dir, std::process::id());
+
+            // live before the first beat, and after one
+            let (status, body) = call(&[], "GET /live HTTP/1.1\r\n\r\n");
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            assert_eq!(body["stage"], "tag");
+            beat();
+            assert_eq!(call(&[], "GET /live HTTP/1.1\r\n\r\n").0, "HTTP/1.1 200 OK");
+            // stalled
+            last_beat().store(now() - STALL_TIMEOUT.as_secs(), Ordering::Relaxed);
+            let (status, body) = call(&[], "GET /live HTTP/1.1\r\n\r\n");
+            assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+            assert_eq!(body["live"], false);
+
+            let (status, body) = call(std::slice::from_ref(&dir), "GET /ready HTTP/1.1\r\n\r\n");
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            assert_eq!(body["checks"].as_array().unwrap().len(), 2);
+            let (status, body) = call(&[dir, missing], "GET /ready HTTP/1.1\r\n\r\n");
+            assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+            assert_eq!(body["checks"][1]["ok"], false);
+            assert_eq!(body["checks"][1]["error"], "not a directory");
+
+            assert_eq!(
+                call(&[], "POST /live HTTP/1.1\r\n\r\n").0,
+                "HTTP/1.1 405 Method Not Allowed"
+            );
+            assert_eq!(
+                call(&[], "GET / HTTP/1.1\r\n\r\n").0,
+                "HTTP/1.1 404 Not Found"
+            );
+        })
+        .join()
+        .unwrap();
+    }
+}
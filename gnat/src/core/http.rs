@@ -0,0 +1,96 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// HTTP/1.x request and status lines for the pcap import: the httpmethod,
+// httphost, httpuseragent and httpstatus columns, from the first non-empty
+// packet of each side of a tcp flow
+//
+
+const METHODS: [&str; 9] = [
+    "GET", "POST", "HEAD", "PUT", "DELETE", "OPTIONS", "CONNECT", "TRACE", "PATCH",
+];
+
+#[derive(Clone, Default)]
+pub struct Http {
+    pub method: Option<String>,
+    pub host: Option<String>,
+    pub user_agent: Option<String>,
+    pub status: Option<u16>,
+}
+
+//
+// Method, Host and User-Agent of a request; None when the payload doesn't
+// start with a request line
+//
+pub fn request(payload: &[u8]) -> Option<Http> {
+    let text = String::from_utf8_lossy(payload);
+    let mut lines = text.split("\r\n");
+    let (method, target) = lines.next()?.split_once(' ')?;
+    if !METHODS.contains(&method) || !target.contains(" HTTP/") {
+        return None;
+    }
+    let mut http = Http {
+        method: Some(method.to_string()),
+        ..Default::default()
+    };
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if name.eq_ignore_ascii_case("host") {
+            http.host = Some(value.trim().to_string());
+        } else if name.eq_ignore_ascii_case("user-agent") {
+            http.user_agent = Some(value.trim().to_string());
+        }
+    }
+    Some(http)
+}
+
+//
+// Status code of a response ("HTTP/1.1 200 OK")
+//
+pub fn status(payload: &[u8]) -> Option<u16> {
+    let rest = payload.strip_prefix(b"HTTP/")?;
+    let space = rest.iter().position(|b| *b == b' ')?;
+    let code = rest.get(space + 1..space + 4)?;
+    if !code.iter().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    std::str::from_utf8(code).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_lines() {
+        let http =
+            request(b"GET /index.html HTTP/1.1\r\nhost: example.com\r\nUser-Agent:  curl/8.0 \r\n\r\nHost: body")
+                .unwrap();
+        assert_eq!(http.method.as_deref(), Some("GET"));
+        assert_eq!(http.host.as_deref(), Some("example.com"));
+        assert_eq!(http.user_agent.as_deref(), Some("curl/8.0"));
+        // headers cut short by the capture
+        let http = request(b"POST /api HTTP/1.0\r\nHost: api.exa").unwrap();
+        assert_eq!(http.host.as_deref(), Some("api.exa"));
+        assert!(request(b"FETCH / HTTP/1.1\r\n").is_none());
+        assert!(request(b"GET /\r\n").is_none());
+        assert!(request(b"\x16\x03\x01\x02\x00").is_none());
+    }
+
+    #[test]
+    fn status_lines() {
+        assert_eq!(status(b"HTTP/1.1 404 Not Found\r\n"), Some(404));
+        assert_eq!(status(b"HTTP/1.0 200"), Some(200));
+        assert_eq!(status(b"HTTP/1.1 20"), None);
+        assert_eq!(status(b"HTTP/1.1 abc OK"), None);
+        assert_eq!(status(b"SSH-2.0-OpenSSH_9.6"), None);
+    }
+}
@@ -6,80 +6,289 @@
  * See license information in LICENSE.
  */
 
+use crate::core::error::GnatError;
+use crate::core::logging;
+use crate::core::orient::Orientation;
+use crate::core::pcap;
+use crate::core::shutdown;
+use crate::core::tenant::Tenants;
+use crate::core::watch::{self, Watch};
+use crate::core::watermark;
 use crate::ipfix::libfixbuf::unsafe_ipfix_file_import;
 
-use std::env;
 use std::fs;
 use std::path::Path;
-use std::thread;
 use std::time::Duration;
+use tracing::{error, info};
 
-pub fn import(
-    observation_tag: &String,
-    input_spec: &String,
-    output_spec: &String,
-    processed_spec: &String,
-    polling: bool,
-    asn_spec: &String,
-    country_spec: &String,
-) -> Result<(), std::io::Error> {
-    println!("\tobservation: {}", observation_tag);
-    println!("\tinput spec: {}", input_spec);
-    println!("\toutput spec: {}", output_spec);
-    println!("\tprocessed spec: {}", processed_spec);
-    println!("\tasn file: {}", asn_spec);
-    println!("\tcountry file: {}", country_spec);
-    println!("\tpolling: {}", polling);
+//
+// Import one capture; -1 on failure, like unsafe_ipfix_file_import()
+//
+fn import_file(input_spec: &String, output_spec: &String, config: &ImportConfig) -> i32 {
+    let ImportConfig {
+        ref format_spec,
+        ref observation_tag,
+        ref dns_output_spec,
+        ref asn_spec,
+        ref country_spec,
+        ref city_spec,
+        idle_timeout,
+        active_timeout,
+        ..
+    } = *config;
+    if format_spec == "pcap" {
+        return match pcap::import_file(
+            observation_tag,
+            input_spec,
+            output_spec,
+            idle_timeout,
+            active_timeout,
+        ) {
+            Ok(_) => 0,
+            Err(e) => {
+                error!("importing {} - {:?}", input_spec, e);
+                -1
+            }
+        };
+    }
+    unsafe_ipfix_file_import(
+        observation_tag,
+        input_spec,
+        output_spec,
+        dns_output_spec,
+        asn_spec,
+        country_spec,
+        city_spec,
+    )
+}
 
-    if Path::new(input_spec).is_file() {
-        let status = unsafe_ipfix_file_import(
-            &observation_tag,
-            &input_spec,
-            &output_spec,
-            &asn_spec,
-            &country_spec,
-        );
-        if status < 0 {
-            eprintln!("Error: processing {}", input_spec);
-            std::process::exit(exitcode::DATAERR);
+//
+// Columns set on flow files after they are imported: orient with
+// --networks, tenant with --tenant or --tenants
+//
+struct Stamps {
+    orientation: Option<Orientation>,
+    tenants: Option<Tenants>,
+}
+
+impl Stamps {
+    fn is_empty(&self) -> bool {
+        self.orientation.is_none() && self.tenants.is_none()
+    }
+
+    fn stamp_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        match (&self.orientation, &self.tenants) {
+            (Some(orientation), Some(tenants)) => {
+                let oriented_spec = format!("{}.orient", output_spec);
+                let stamped = orientation
+                    .orient_file(input_spec, &oriented_spec)
+                    .and_then(|_| tenants.stamp_file(&oriented_spec, output_spec));
+                let _ = fs::remove_file(&oriented_spec);
+                stamped
+            }
+            (Some(orientation), None) => orientation.orient_file(input_spec, output_spec),
+            (None, Some(tenants)) => tenants.stamp_file(input_spec, output_spec),
+            (None, None) => Ok(()),
         }
+    }
+}
+
+//
+// Stamp the flow files imported into stage_spec and move them to
+// output_spec; staged files are removed either way
+//
+fn stamp_staged(stamps: &Stamps, stage_spec: &String, output_spec: &String) -> bool {
+    let entries = match fs::read_dir(stage_spec) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("reading {} - {:?}", stage_spec, e);
+            return false;
+        }
+    };
+    let mut succeeded = true;
+    for entry in entries.flatten() {
+        let file_name = String::from(entry.file_name().to_string_lossy());
+        if file_name.starts_with(".") || !file_name.ends_with(".parquet") {
+            continue;
+        }
+        let staged_path = String::from(entry.path().to_string_lossy());
+        let tmp_path = format!("{}/.stamp-{}", output_spec, file_name);
+        let dst_path = format!("{}/{}", output_spec, file_name);
+        if stamps.stamp_file(&staged_path, &tmp_path).is_ok() {
+            if let Err(e) = fs::rename(&tmp_path, &dst_path) {
+                error!("moving {} -> {} - {:?}", tmp_path, dst_path, e);
+                succeeded = false;
+            }
+        } else {
+            let _ = fs::remove_file(&tmp_path);
+            succeeded = false;
+        }
+        let _ = fs::remove_file(&staged_path);
+    }
+    succeeded
+}
+
+//
+// Stamp a single output file in place
+//
+fn stamp_output(stamps: &Stamps, output_spec: &String) -> bool {
+    let path = Path::new(output_spec);
+    let tmp_path = format!(
+        "{}/.stamp-{}",
+        path.parent().unwrap_or(Path::new(".")).display(),
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    if stamps.stamp_file(output_spec, &tmp_path).is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return false;
+    }
+    if let Err(e) = fs::rename(&tmp_path, output_spec) {
+        error!("moving {} -> {} - {:?}", tmp_path, output_spec, e);
+        return false;
+    }
+    true
+}
+
+fn is_capture(format_spec: &String, file_name: &str) -> bool {
+    if format_spec == "pcap" {
+        file_name.ends_with(".pcap") || file_name.ends_with(".pcapng")
+    } else {
+        file_name.ends_with(".yaf")
+    }
+}
+
+//
+// Options of gnat_import, as parsed and checked by main()
+//
+pub struct ImportConfig {
+    pub format_spec: String,
+    pub observation_tag: String,
+    pub input_spec: String,
+    pub output_spec: String,
+    pub processed_spec: String,
+    pub dns_output_spec: String,
+    pub polling: bool,
+    pub asn_spec: String,
+    pub country_spec: String,
+    pub city_spec: String,
+    pub idle_timeout: u64,
+    pub active_timeout: u64,
+    pub networks_spec: String,
+    pub tenant: String,
+    pub tenants_spec: String,
+}
+
+pub fn import(config: &ImportConfig) -> Result<(), std::io::Error> {
+    let ImportConfig {
+        ref format_spec,
+        ref observation_tag,
+        ref input_spec,
+        ref output_spec,
+        ref processed_spec,
+        ref dns_output_spec,
+        polling,
+        ref asn_spec,
+        ref country_spec,
+        ref city_spec,
+        idle_timeout,
+        active_timeout,
+        ref networks_spec,
+        ref tenant,
+        ref tenants_spec,
+    } = *config;
+    info!("format: {}", format_spec);
+    info!("observation: {}", observation_tag);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    if !dns_output_spec.is_empty() {
+        info!("dns output spec: {}", dns_output_spec);
+    }
+    info!("asn file: {}", asn_spec);
+    info!("country file: {}", country_spec);
+    info!("city file: {}", city_spec);
+    info!("polling: {}", polling);
+    if format_spec == "pcap" {
+        info!("idle timeout: {}", idle_timeout);
+        info!("active timeout: {}", active_timeout);
+    }
+    let orientation = if networks_spec.is_empty() {
+        None
     } else {
+        info!("networks file: {}", networks_spec);
+        Some(Orientation::load(networks_spec)?)
+    };
+    let tenants = if tenant.is_empty() && tenants_spec.is_empty() {
+        None
+    } else {
+        if !tenant.is_empty() {
+            info!("tenant: {}", tenant);
+        }
+        if !tenants_spec.is_empty() {
+            info!("tenants file: {}", tenants_spec);
+        }
+        Some(Tenants::load(tenant, tenants_spec)?)
+    };
+    let stamps = Stamps {
+        orientation,
+        tenants,
+    };
 
-        let input_dir = Path::new(input_spec.as_str());
-        if !env::set_current_dir(&input_dir).is_ok() {
-            panic!(
-                "Error: unable to set working directory to {}",
-                input_dir.display()
-            );
+    if Path::new(input_spec).is_file() {
+        let status = import_file(input_spec, output_spec, config);
+        if status < 0 || (!stamps.is_empty() && !stamp_output(&stamps, output_spec)) {
+            error!("processing {}", input_spec);
+            shutdown::exit(exitcode::DATAERR);
+        }
+    } else {
+        //
+        // with --networks, --tenant or --tenants, captures are imported
+        // into a hidden staging directory and their flow files stamped
+        // into output_spec, so the next stage never sees a file without
+        // orient or tenant
+        //
+        let stage_spec = format!("{}/.staged", output_spec);
+        if !stamps.is_empty() {
+            fs::create_dir_all(&stage_spec)?;
         }
+        let import_spec = if stamps.is_empty() {
+            output_spec
+        } else {
+            &stage_spec
+        };
 
         let poll_interval = Duration::from_secs(1);
-        println!("import scanner: running [{}]", input_spec);
+        let watch = Watch::new(input_spec);
+        info!("import scanner: running [{}]", input_spec);
         loop {
             let mut counter = 0;
             let mut processed_path;
 
-            for entry in fs::read_dir(".")? {
+            for entry in fs::read_dir(input_spec)? {
+                if shutdown::requested() {
+                    break;
+                }
                 let file: fs::DirEntry = entry.unwrap();
                 let file_name = String::from(file.file_name().to_string_lossy());
                 let src_path = String::from(file.path().to_string_lossy());
 
-                if file_name.starts_with(observation_tag) && file_name.ends_with(".yaf") {
+                if file_name.starts_with(observation_tag) && is_capture(format_spec, &file_name) {
                     let lock_path = format!("{}.lock", src_path);
                     if Path::new(lock_path.as_str()).exists() {
                         continue;
                     }
+                    if !watermark::wait("import", output_spec) {
+                        break;
+                    }
+                    let _batch = logging::batch(&file_name);
                     //println!("import scanner: processing [{}]", src_path);
-                    let status = unsafe_ipfix_file_import(
-                        &observation_tag,
-                        &src_path,
-                        &output_spec,
-                        &asn_spec,
-                        &country_spec,
-                    );
+                    let mut status = import_file(&src_path, import_spec, config);
+                    if !stamps.is_empty() && !stamp_staged(&stamps, &stage_spec, output_spec) {
+                        status = -1;
+                    }
                     if status < 0 {
-                        eprintln!(
-                            "Error: processing {}; moving to {}",
+                        error!(
+                            "processing {}; moving to {}",
                             src_path, processed_spec
                         );
                         processed_path = format!("{}/{}.err", processed_spec, file_name);
@@ -100,11 +309,11 @@ pub fn import(
                 }
             }
 
-            if !polling {
+            if !polling || shutdown::requested() {
                 break;
             }
-            if counter == 0 {
-                thread::sleep(poll_interval);
+            if counter == 0 && !watch::wait(&watch, poll_interval) {
+                break;
             }
         }
     }
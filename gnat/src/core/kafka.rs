@@ -0,0 +1,121 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Kafka sink: publishes each flow record as a JSON message keyed by
+// observation so records from one sensor stay in order on a partition
+//
+
+use crate::core::error::GnatError;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::spool::process_directory;
+
+use std::time::Duration;
+
+use kafka::producer::{Producer, Record, RequiredAcks};
+use tracing::{error, info};
+
+// records per produce request
+const SEND_BATCH: usize = 1000;
+
+pub fn publish_file(producer: &mut Producer, input_spec: &String, topic: &String) -> Result<(), GnatError> {
+    let conn = scratch::open_in_memory("kafka")?;
+    let source = match schema::select(&conn, input_spec) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("reading {} - {:?}", input_spec, e);
+            return Err(e);
+        }
+    };
+    let sql_command = format!("SELECT observ, to_json(m)::VARCHAR FROM ({}) m;", source);
+    let mut stmt = match conn.prepare(&sql_command) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("reading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+    };
+    let record_iter = match stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    }) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("reading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+    };
+
+    let mut pending: Vec<(String, String)> = Vec::with_capacity(SEND_BATCH);
+    let mut count = 0;
+    for r in record_iter {
+        match r {
+            Ok(record) => pending.push(record),
+            Err(e) => {
+                error!("reading {} - {:?}", input_spec, e);
+                return Err(e.into());
+            }
+        }
+        if pending.len() >= SEND_BATCH {
+            send(producer, topic, &pending)?;
+            count += pending.len();
+            pending.clear();
+        }
+    }
+    if !pending.is_empty() {
+        send(producer, topic, &pending)?;
+        count += pending.len();
+    }
+    info!("kafka: {} => {} [{} records]", input_spec, topic, count);
+    Ok(())
+}
+
+fn send(producer: &mut Producer, topic: &String, pending: &[(String, String)]) -> Result<(), GnatError> {
+    let records: Vec<Record<&str, &str>> = pending
+        .iter()
+        .map(|(observ, value)| Record::from_key_value(topic, observ.as_str(), value.as_str()))
+        .collect();
+    match producer.send_all(&records) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("publishing to {} - {:?}", topic, e);
+            Err(GnatError::Upstream(format!("publishing to {} - {:?}", topic, e)))
+        }
+    }
+}
+
+pub fn kafka(
+    brokers_spec: &String,
+    topic: &String,
+    input_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+) -> Result<(), std::io::Error> {
+    info!("brokers spec: {}", brokers_spec);
+    info!("topic: {}", topic);
+    info!("input spec: {}", input_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("polling: {}", polling);
+
+    let hosts: Vec<String> = brokers_spec.split(',').map(String::from).collect();
+    let mut producer = Producer::from_hosts(hosts)
+        .with_ack_timeout(Duration::from_secs(5))
+        .with_required_acks(RequiredAcks::All)
+        .create()
+        .map_err(|e| GnatError::Upstream(format!("connecting to {} - {:?}", brokers_spec, e)))?;
+
+    // a sink writes no output files, so the spool output is never populated
+    process_directory(
+        "kafka",
+        input_spec,
+        input_spec,
+        processed_spec,
+        polling,
+        |src_path, _tmp_path| publish_file(&mut producer, src_path, topic),
+    )
+}
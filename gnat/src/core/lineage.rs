@@ -0,0 +1,206 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Batch lineage (gnat_lineage)
+//
+// Flow files carry their provenance in the "gnat_lineage" key of their
+// parquet metadata: a JSON array of steps, oldest first, one per batch
+// that produced the file or one of its inputs:
+//
+//   [{"batch": "<uuid>", "stage": "tag", "time": "<rfc3339>",
+//     "sources": ["sensor1-20240101120000.parquet"]}, ...]
+//
+// The spool, gnat_batch and gnat_export open a batch for each output they
+// write; the batch reads the chains of its inputs and appends its own
+// step, and schema::copy_options() records the result in the output. A
+// merge keeps the steps of every input once, and chains are cut to their
+// newest LINEAGE_STEPS steps.
+//
+
+use crate::core::scratch;
+
+use std::cell::RefCell;
+
+use chrono::Utc;
+use duckdb::Connection;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+pub const LINEAGE_KEY: &str = "gnat_lineage";
+const LINEAGE_STEPS: usize = 100;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Step {
+    pub batch: String,
+    pub stage: String,
+    pub time: String,
+    pub sources: Vec<String>,
+}
+
+thread_local! {
+    // chain of the batch in progress on this thread, as JSON
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+//
+// The batch in progress; its chain is recorded in the outputs written on
+// this thread until it is dropped
+//
+pub struct Batch {
+    previous: Option<String>,
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+//
+// Chain recorded in input_spec; empty for files without one
+//
+pub fn read(conn: &Connection, input_spec: &str) -> Result<Vec<Step>, duckdb::Error> {
+    let sql_command = format!(
+        "SELECT decode(value) FROM parquet_kv_metadata('{}') WHERE decode(key) = '{}';",
+        input_spec, LINEAGE_KEY
+    );
+    let recorded: Option<String> = conn.query_row(&sql_command, [], |row| row.get(0)).ok();
+    Ok(recorded
+        .and_then(|chain| serde_json::from_str(&chain).ok())
+        .unwrap_or_default())
+}
+
+//
+// Open a batch of stage over sources, as (file_name, path); the upstream
+// chains are best effort, so an input that can't be read starts afresh
+//
+pub fn begin(stage: &str, sources: &[(String, String)]) -> Batch {
+    let mut chain: Vec<Step> = Vec::new();
+    let mut batch = String::new();
+    if let Ok(conn) = scratch::open_in_memory("lineage") {
+        for (file_name, path) in sources.iter() {
+            match read(&conn, path) {
+                Ok(steps) => {
+                    for step in steps {
+                        if !chain.iter().any(|s| s.batch == step.batch) {
+                            chain.push(step);
+                        }
+                    }
+                }
+                Err(e) => debug!("lineage of {} - {:?}", file_name, e),
+            }
+        }
+        batch = conn
+            .query_row("SELECT uuid()::VARCHAR;", [], |row| row.get(0))
+            .unwrap_or_default();
+    }
+    chain.push(Step {
+        batch,
+        stage: String::from(stage),
+        time: Utc::now().to_rfc3339(),
+        sources: sources.iter().map(|(file_name, _)| file_name.clone()).collect(),
+    });
+    if chain.len() > LINEAGE_STEPS {
+        chain.drain(..chain.len() - LINEAGE_STEPS);
+    }
+    let encoded = serde_json::to_string(&chain).ok();
+    let previous = CURRENT.with(|current| current.replace(encoded));
+    Batch { previous }
+}
+
+//
+// KV_METADATA entry for the batch in progress, if any
+//
+pub fn metadata() -> Option<String> {
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(|chain| format!("{}: '{}'", LINEAGE_KEY, chain.replace('\'', "''")))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::schema;
+    use crate::core::testutil::test_dir;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn chains_follow_the_batches() {
+        let dir = PathBuf::from(test_dir("lineage"));
+        let file = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let select = "SELECT 's1' AS observ";
+        assert!(metadata().is_none());
+
+        // a file of a batch records its step
+        {
+            let _batch = begin("import", &[]);
+            assert!(metadata().is_some());
+            schema::write_test_flows(&file("a.parquet"), select);
+        }
+        assert!(metadata().is_none());
+        schema::write_test_flows(&file("plain.parquet"), select);
+        let conn = Connection::open_in_memory().unwrap();
+        let a = read(&conn, &file("a.parquet")).unwrap();
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].stage, "import");
+        assert!(a[0].sources.is_empty());
+        assert!(read(&conn, &file("plain.parquet")).unwrap().is_empty());
+
+        // a merge keeps the shared upstream step once
+        {
+            let _batch = begin(
+                "tag",
+                &[
+                    (String::from("a.parquet"), file("a.parquet")),
+                    (String::from("plain.parquet"), file("plain.parquet")),
+                ],
+            );
+            schema::write_test_flows(&file("b.parquet"), select);
+        }
+        {
+            let _batch = begin(
+                "merge",
+                &[
+                    (String::from("a.parquet"), file("a.parquet")),
+                    (String::from("b.parquet"), file("b.parquet")),
+                    (String::from("gone.parquet"), file("gone.parquet")),
+                ],
+            );
+            schema::write_test_flows(&file("c.parquet"), select);
+        }
+        let c = read(&conn, &file("c.parquet")).unwrap();
+        let stages: Vec<&str> = c.iter().map(|s| s.stage.as_str()).collect();
+        assert_eq!(stages, vec!["import", "tag", "merge"]);
+        assert_eq!(c[0].batch, a[0].batch);
+        assert_eq!(c[1].sources, vec!["a.parquet", "plain.parquet"]);
+        assert_eq!(c[2].sources.len(), 3);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn chains_are_cut_to_the_newest_steps() {
+        let path = std::env::temp_dir()
+            .join(format!("gnat-{}-lineage-cut.parquet", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        for _ in 0..LINEAGE_STEPS + 5 {
+            let _batch = begin("tag", &[(String::from("f"), path.clone())]);
+            schema::write_test_flows(&path, "SELECT 's1' AS observ");
+        }
+        let conn = Connection::open_in_memory().unwrap();
+        let chain = read(&conn, &path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(chain.len(), LINEAGE_STEPS);
+    }
+}
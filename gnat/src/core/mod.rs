@@ -6,7 +6,57 @@
  * See license information in LICENSE.
  */
 
+ pub mod anonymize;
+ pub mod asset;
  pub mod batch;
+ pub mod beacon;
+ pub mod coalesce;
  pub mod collect;
+ pub mod context;
+ pub mod control;
+ pub mod correlate;
+ pub mod detect;
+ pub mod dga;
+ pub mod encrypt;
+ pub mod enrich;
+ pub mod error;
  pub mod export;
- pub mod import;
\ No newline at end of file
+ pub mod filter;
+ pub mod health;
+ pub mod http;
+ pub mod import;
+ #[cfg(feature = "kafka")]
+ pub mod kafka;
+ pub mod lineage;
+ pub mod logging;
+ pub mod netflow;
+ pub mod network;
+ pub mod orient;
+ pub mod overrides;
+ pub mod packet;
+ pub mod parquet;
+ pub mod pcap;
+ pub mod pipeline;
+ pub mod plugin;
+ pub mod record;
+ pub mod report;
+ pub mod sample;
+ pub mod scan;
+ pub mod schema;
+ pub mod scratch;
+ pub mod shutdown;
+ pub mod site;
+ pub mod spool;
+  pub mod stitch;
+ pub mod suppress;
+ pub mod tag;
+ pub mod tenant;
+ #[cfg(test)]
+ pub mod testutil;
+ pub mod tls;
+ pub mod trigger;
+ pub mod validate;
+ #[cfg(feature = "wasm")]
+ pub mod transform;
+ pub mod watch;
+ pub mod watermark;
\ No newline at end of file
@@ -0,0 +1,758 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// NetFlow v5/v9 and sFlow v5 collector (gnat_collect --format)
+//
+// Datagrams are decoded into the flow schema and written to a parquet file
+// per rotate interval, named like the IPFIX collector's output. NetFlow v9
+// templates are cached per (exporter, source id, template id); data sets
+// that arrive before their template are dropped and counted, as are
+// truncated datagrams, none of whose flows are kept. Each sFlow flow
+// sample stands for sampling-rate packets, and samples of the same 5-tuple
+// and vlan are summed over the interval.
+//
+// NetFlow and sFlow are unidirectional, so the reverse counters are 0 and
+// the YAF-only columns (rtt, entropy, payload statistics, appid) take
+// their defaults.
+//
+
+use crate::core::health;
+use crate::core::packet::{self, Reader};
+use crate::core::record::{FlowRecord, FlowWriter};
+use crate::core::shutdown;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::{error, info, warn};
+
+type SampleKey = (IpAddr, IpAddr, u16, u16, u8, u16);
+
+struct Template {
+    fields: Vec<(u16, u16)>,
+    // options data describes the exporter rather than flows
+    options: bool,
+}
+
+type TemplateKey = (IpAddr, u32, u16);
+
+#[derive(Default)]
+struct Counters {
+    datagrams: u64,
+    flows: u64,
+    malformed: u64,
+    untemplated: u64,
+}
+
+fn uint(bytes: &[u8]) -> u64 {
+    let start = bytes.len().saturating_sub(8);
+    bytes[start..].iter().fold(0, |v, b| (v << 8) | *b as u64)
+}
+
+//
+// An exporter's time field of `scale` ms units, in ms; None when it doesn't
+// fit an i64
+//
+fn millis(value: &[u8], scale: i64) -> Option<i64> {
+    i64::try_from(uint(value)).ok()?.checked_mul(scale)
+}
+
+//
+// Absolute time (ms) of a sysUpTime stamp; the uptime counter wraps after
+// 49.7 days
+//
+fn switched(boot: i64, uptime: i64, stamp: u32) -> i64 {
+    let stamp = stamp as i64;
+    if stamp > uptime {
+        boot + stamp - (1 << 32)
+    } else {
+        boot + stamp
+    }
+}
+
+fn decode_v5(datagram: &[u8], flows: &mut Vec<FlowRecord>) -> Option<u64> {
+    let mut r = Reader::new(datagram);
+    r.take(2)?; // version
+    let count = r.u16()?;
+    let uptime = r.u32()? as i64;
+    let secs = r.u32()? as i64;
+    let nsecs = r.u32()? as i64;
+    r.take(6)?; // flow sequence, engine type and id
+    let sampling = (r.u16()? & 0x3fff).max(1) as u64;
+    let boot = secs * 1000 + nsecs / 1_000_000 - uptime;
+
+    // a truncated datagram is dropped whole
+    let mut decoded = Vec::new();
+    for _ in 0..count {
+        let mut record = Reader::new(r.take(48)?);
+        let mut flow = FlowRecord::new(0);
+        flow.saddr = record.ipv4()?;
+        flow.daddr = record.ipv4()?;
+        record.take(8)?; // next hop, input and output interfaces
+        flow.forward.pkts = record.u32()? as u64 * sampling;
+        flow.forward.bytes = record.u32()? as u64 * sampling;
+        flow.stime = switched(boot, uptime, record.u32()?) * 1000;
+        flow.etime = switched(boot, uptime, record.u32()?) * 1000;
+        flow.sport = record.u16()?;
+        flow.dport = record.u16()?;
+        record.take(1)?;
+        flow.forward.union_flags = record.u8()?;
+        flow.proto = record.u8()?;
+        decoded.push(flow);
+    }
+    flows.extend(decoded);
+    Some(count as u64)
+}
+
+fn v9_record(fields: &[(u16, u16)], record: &[u8], boot: i64, uptime: i64) -> Option<FlowRecord> {
+    let mut r = Reader::new(record);
+    let mut flow = FlowRecord::new(0);
+    let (mut first, mut last) = (None, None);
+    let (mut out_pkts, mut out_bytes) = (0, 0);
+    for (field_type, length) in fields.iter() {
+        let value = r.take(*length as usize)?;
+        let mut v = Reader::new(value);
+        match (*field_type, value.len()) {
+            (1, _) => flow.forward.bytes = uint(value),
+            (2, _) => flow.forward.pkts = uint(value),
+            (4, _) => flow.proto = uint(value) as u8,
+            (6, _) => flow.forward.union_flags = uint(value) as u8,
+            (7, _) => flow.sport = uint(value) as u16,
+            (8, 4) => flow.saddr = v.ipv4()?,
+            (11, _) => flow.dport = uint(value) as u16,
+            (12, 4) => flow.daddr = v.ipv4()?,
+            (21, _) => last = Some(switched(boot, uptime, uint(value) as u32)),
+            (22, _) => first = Some(switched(boot, uptime, uint(value) as u32)),
+            (23, _) => out_bytes = uint(value),
+            (24, _) => out_pkts = uint(value),
+            (27, 16) => flow.saddr = v.ipv6()?,
+            (28, 16) => flow.daddr = v.ipv6()?,
+            (56, 6) => flow.smac.copy_from_slice(value),
+            (58, _) => flow.forward.vlan = uint(value) as u16 & 0x0fff,
+            (59, _) => flow.reverse.vlan = uint(value) as u16 & 0x0fff,
+            (80, 6) => flow.dmac.copy_from_slice(value),
+            // IPFIX flowStart/flowEnd seconds and milliseconds; a time out
+            // of range drops the record
+            (150, _) => first = Some(millis(value, 1000)?),
+            (151, _) => last = Some(millis(value, 1000)?),
+            (152, _) => first = Some(millis(value, 1)?),
+            (153, _) => last = Some(millis(value, 1)?),
+            _ => {}
+        }
+    }
+    // egress-only exporters
+    if flow.forward.pkts == 0 && flow.forward.bytes == 0 {
+        flow.forward.pkts = out_pkts;
+        flow.forward.bytes = out_bytes;
+    }
+    let received = boot + uptime;
+    let etime = last.unwrap_or(received);
+    flow.etime = etime.checked_mul(1000)?;
+    flow.stime = first.unwrap_or(etime).checked_mul(1000)?;
+    Some(flow)
+}
+
+fn decode_v9(
+    exporter: IpAddr,
+    datagram: &[u8],
+    templates: &mut HashMap<TemplateKey, Template>,
+    flows: &mut Vec<FlowRecord>,
+    counters: &mut Counters,
+) -> Option<u64> {
+    let mut r = Reader::new(datagram);
+    r.take(4)?; // version, record count
+    let uptime = r.u32()? as i64;
+    let secs = r.u32()? as i64;
+    r.take(4)?; // sequence
+    let source_id = r.u32()?;
+    let boot = secs * 1000 - uptime;
+
+    // a truncated datagram is dropped whole
+    let mut decoded = Vec::new();
+    while r.remaining() >= 4 {
+        let set_id = r.u16()?;
+        let length = r.u16()? as usize;
+        let mut set = Reader::new(r.take(length.checked_sub(4)?)?);
+        match set_id {
+            0 => {
+                while set.remaining() >= 4 {
+                    let template_id = set.u16()?;
+                    let field_count = set.u16()?;
+                    let mut fields = Vec::with_capacity(field_count as usize);
+                    for _ in 0..field_count {
+                        fields.push((set.u16()?, set.u16()?));
+                    }
+                    let template = Template {
+                        fields,
+                        options: false,
+                    };
+                    templates.insert((exporter, source_id, template_id), template);
+                }
+            }
+            1 => {
+                // sets are padded to 4 bytes
+                while set.remaining() >= 6 {
+                    let template_id = set.u16()?;
+                    let scope_length = set.u16()? as usize;
+                    let option_length = set.u16()? as usize;
+                    let mut fields = Vec::new();
+                    for _ in 0..(scope_length + option_length) / 4 {
+                        fields.push((set.u16()?, set.u16()?));
+                    }
+                    let template = Template {
+                        fields,
+                        options: true,
+                    };
+                    templates.insert((exporter, source_id, template_id), template);
+                }
+            }
+            _ if set_id >= 256 => {
+                let Some(template) = templates.get(&(exporter, source_id, set_id)) else {
+                    counters.untemplated += 1;
+                    continue;
+                };
+                if template.options {
+                    continue;
+                }
+                let record_length: usize = template.fields.iter().map(|(_, l)| *l as usize).sum();
+                if record_length == 0 {
+                    continue;
+                }
+                while set.remaining() >= record_length {
+                    let record = set.take(record_length)?;
+                    if let Some(flow) = v9_record(&template.fields, record, boot, uptime) {
+                        decoded.push(flow);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let count = decoded.len() as u64;
+    flows.extend(decoded);
+    Some(count)
+}
+
+fn decode_sflow(
+    datagram: &[u8],
+    received: i64,
+    samples: &mut HashMap<SampleKey, FlowRecord>,
+) -> Option<u64> {
+    let mut r = Reader::new(datagram);
+    if r.u32()? != 5 {
+        return None;
+    }
+    match r.u32()? {
+        1 => r.take(4)?,
+        2 => r.take(16)?,
+        _ => return None,
+    };
+    r.take(12)?; // sub agent id, sequence, uptime
+    let count = r.u32()?;
+
+    let mut decoded = Vec::new();
+    for _ in 0..count {
+        let format = r.u32()?;
+        let length = r.u32()? as usize;
+        let mut sample = Reader::new(r.take(length)?);
+        let (rate, records) = match format {
+            // flow sample
+            1 => {
+                sample.take(8)?;
+                let rate = sample.u32()?;
+                sample.take(16)?;
+                (rate, sample.u32()?)
+            }
+            // expanded flow sample
+            3 => {
+                sample.take(12)?;
+                let rate = sample.u32()?;
+                sample.take(24)?;
+                (rate, sample.u32()?)
+            }
+            // counter samples
+            _ => continue,
+        };
+
+        let mut flow = FlowRecord::new(received);
+        let mut frame_length = 0;
+        let mut found = false;
+        for _ in 0..records {
+            let record_format = sample.u32()?;
+            let record_length = sample.u32()? as usize;
+            let mut record = Reader::new(sample.take(record_length)?);
+            match record_format {
+                // sampled header
+                1 => {
+                    let protocol = record.u32()?;
+                    frame_length = record.u32()?;
+                    record.take(4)?; // stripped
+                    let header_length = record.u32()? as usize;
+                    let header = record.take(header_length)?;
+                    if protocol != 1 {
+                        continue;
+                    }
+                    if let Some(p) = packet::decode(packet::LINKTYPE_ETHERNET, header) {
+                        flow.smac = p.smac;
+                        flow.dmac = p.dmac;
+                        flow.forward.vlan = p.vlan;
+                        flow.proto = p.proto;
+                        flow.saddr = p.saddr;
+                        flow.daddr = p.daddr;
+                        flow.sport = p.sport;
+                        flow.dport = p.dport;
+                        flow.forward.union_flags = p.flags;
+                        found = true;
+                    }
+                }
+                // sampled ipv4 / ipv6
+                3 | 4 => {
+                    frame_length = record.u32()?;
+                    flow.proto = record.u32()? as u8;
+                    if record_format == 3 {
+                        flow.saddr = record.ipv4()?;
+                        flow.daddr = record.ipv4()?;
+                    } else {
+                        flow.saddr = record.ipv6()?;
+                        flow.daddr = record.ipv6()?;
+                    }
+                    flow.sport = record.u32()? as u16;
+                    flow.dport = record.u32()? as u16;
+                    flow.forward.union_flags = record.u32()? as u8;
+                    found = true;
+                }
+                // extended switch
+                1001 => {
+                    flow.forward.vlan = record.u32()? as u16;
+                    record.take(4)?;
+                    flow.reverse.vlan = record.u32()? as u16;
+                }
+                _ => {}
+            }
+        }
+        if !found {
+            continue;
+        }
+        flow.forward.pkts = rate.max(1) as u64;
+        flow.forward.bytes = frame_length as u64 * flow.forward.pkts;
+        decoded.push(flow);
+    }
+
+    let count = decoded.len() as u64;
+    for flow in decoded {
+        let key = (
+            flow.saddr,
+            flow.daddr,
+            flow.sport,
+            flow.dport,
+            flow.proto,
+            flow.forward.vlan,
+        );
+        match samples.get_mut(&key) {
+            Some(total) => {
+                total.forward.pkts += flow.forward.pkts;
+                total.forward.bytes += flow.forward.bytes;
+                total.forward.union_flags |= flow.forward.union_flags;
+                total.etime = total.etime.max(flow.etime);
+            }
+            None => {
+                samples.insert(key, flow);
+            }
+        }
+    }
+    Some(count)
+}
+
+fn now_us() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64
+}
+
+fn write(
+    observation: &String,
+    output_spec: &String,
+    flows: &[FlowRecord],
+) -> Result<(), std::io::Error> {
+    let mut writer = FlowWriter::new("collect", observation)?;
+    writer.append(flows)?;
+    let parquet_spec = format!(
+        "{}/gnat.{}.{}.parquet",
+        output_spec,
+        observation,
+        now_us() / 1_000_000
+    );
+    let count = writer.finish(&parquet_spec)?;
+    info!("collect: wrote {} flows to {}", count, parquet_spec);
+    Ok(())
+}
+
+pub fn collect_datagrams(
+    format_spec: &String,
+    observation_tag: &String,
+    host_spec: &String,
+    port: u16,
+    rotate_interval: u32,
+    output_spec: &String,
+) -> Result<(), std::io::Error> {
+    info!("format: {}", format_spec);
+    info!("observation: {}", observation_tag);
+    info!("host spec: {}", host_spec);
+    info!("port: {}", port);
+    info!("output spec: {}", output_spec);
+    info!("rotate interval: {}", rotate_interval);
+
+    let socket = UdpSocket::bind((host_spec.as_str(), port))?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    info!("collect: listening [{}]", socket.local_addr()?);
+
+    let interval = Duration::from_secs(rotate_interval as u64);
+    let mut rotate_at = Instant::now() + interval;
+    let mut buffer = vec![0u8; 65536];
+    let mut templates: HashMap<TemplateKey, Template> = HashMap::new();
+    let mut flows: Vec<FlowRecord> = Vec::new();
+    let mut samples: HashMap<SampleKey, FlowRecord> = HashMap::new();
+    let mut counters = Counters::default();
+    loop {
+        // the read timeout brings the loop around at least every second
+        health::beat();
+        match socket.recv_from(&mut buffer) {
+            Ok((length, peer)) => {
+                counters.datagrams += 1;
+                let datagram = &buffer[..length];
+                let version = datagram.get(..2).map(|v| u16::from_be_bytes([v[0], v[1]]));
+                let decoded = match (format_spec.as_str(), version) {
+                    ("netflow", Some(5)) => decode_v5(datagram, &mut flows),
+                    ("netflow", Some(9)) => decode_v9(
+                        peer.ip(),
+                        datagram,
+                        &mut templates,
+                        &mut flows,
+                        &mut counters,
+                    ),
+                    ("sflow", _) => decode_sflow(datagram, now_us(), &mut samples),
+                    _ => None,
+                };
+                match decoded {
+                    Some(count) => counters.flows += count,
+                    None => counters.malformed += 1,
+                }
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+
+        let stopping = shutdown::requested();
+        if !stopping && Instant::now() < rotate_at {
+            continue;
+        }
+        flows.extend(samples.drain().map(|(_, flow)| flow));
+        if !flows.is_empty() {
+            if let Err(e) = write(observation_tag, output_spec, &flows) {
+                error!("writing {} flows to {} - {:?}", flows.len(), output_spec, e);
+            }
+        }
+        info!(
+            "collect: {} datagrams, {} flows, {} malformed",
+            counters.datagrams, counters.flows, counters.malformed
+        );
+        if counters.untemplated > 0 {
+            warn!(
+                "collect: dropped {} netflow v9 data sets received before their template",
+                counters.untemplated
+            );
+        }
+        flows.clear();
+        counters = Counters::default();
+        rotate_at = Instant::now() + interval;
+        if stopping {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPTIME: u32 = 10_000;
+    const SECS: u32 = 1_700_000_000;
+    const BOOT: i64 = SECS as i64 * 1000 - UPTIME as i64;
+
+    fn v5_header(count: u16) -> Vec<u8> {
+        let mut datagram = Vec::new();
+        datagram.extend_from_slice(&5u16.to_be_bytes());
+        datagram.extend_from_slice(&count.to_be_bytes());
+        datagram.extend_from_slice(&UPTIME.to_be_bytes());
+        datagram.extend_from_slice(&SECS.to_be_bytes());
+        datagram.extend_from_slice(&0u32.to_be_bytes()); // nsecs
+        datagram.extend_from_slice(&[0; 6]); // flow sequence, engine type and id
+        datagram.extend_from_slice(&(0x4000u16 | 10).to_be_bytes()); // sampling 1 in 10
+        datagram
+    }
+
+    fn v5_record(datagram: &mut Vec<u8>, sport: u16) {
+        datagram.extend_from_slice(&[10, 0, 0, 1]);
+        datagram.extend_from_slice(&[192, 0, 2, 7]);
+        datagram.extend_from_slice(&[0; 8]); // next hop, input and output interfaces
+        datagram.extend_from_slice(&3u32.to_be_bytes()); // packets
+        datagram.extend_from_slice(&300u32.to_be_bytes()); // bytes
+        datagram.extend_from_slice(&4_000u32.to_be_bytes()); // first
+        datagram.extend_from_slice(&9_000u32.to_be_bytes()); // last
+        datagram.extend_from_slice(&sport.to_be_bytes());
+        datagram.extend_from_slice(&443u16.to_be_bytes());
+        datagram.extend_from_slice(&[0, 0x12, 6, 0]); // pad, flags, proto, tos
+        datagram.extend_from_slice(&[0; 8]); // AS numbers, masks, pad
+    }
+
+    fn v9_header() -> Vec<u8> {
+        let mut datagram = Vec::new();
+        datagram.extend_from_slice(&9u16.to_be_bytes());
+        datagram.extend_from_slice(&1u16.to_be_bytes());
+        datagram.extend_from_slice(&UPTIME.to_be_bytes());
+        datagram.extend_from_slice(&SECS.to_be_bytes());
+        datagram.extend_from_slice(&0u32.to_be_bytes()); // sequence
+        datagram.extend_from_slice(&7u32.to_be_bytes()); // source id
+        datagram
+    }
+
+    // saddr, daddr, sport, dport, proto, packets, bytes, first, last: 29 bytes
+    const V9_FIELDS: [(u16, u16); 9] = [
+        (8, 4),
+        (12, 4),
+        (7, 2),
+        (11, 2),
+        (4, 1),
+        (2, 4),
+        (1, 4),
+        (22, 4),
+        (21, 4),
+    ];
+
+    //
+    // A template set (set id 0) or an options template set (set id 1, the
+    // first field as its scope)
+    //
+    fn v9_template(datagram: &mut Vec<u8>, set_id: u16, template_id: u16) {
+        let fields_length = 4 * V9_FIELDS.len() as u16;
+        datagram.extend_from_slice(&set_id.to_be_bytes());
+        if set_id == 1 {
+            // padded to 4 bytes
+            datagram.extend_from_slice(&(12 + fields_length).to_be_bytes());
+            datagram.extend_from_slice(&template_id.to_be_bytes());
+            datagram.extend_from_slice(&4u16.to_be_bytes());
+            datagram.extend_from_slice(&(fields_length - 4).to_be_bytes());
+        } else {
+            datagram.extend_from_slice(&(8 + fields_length).to_be_bytes());
+            datagram.extend_from_slice(&template_id.to_be_bytes());
+            datagram.extend_from_slice(&(V9_FIELDS.len() as u16).to_be_bytes());
+        }
+        for (field_type, length) in V9_FIELDS {
+            datagram.extend_from_slice(&field_type.to_be_bytes());
+            datagram.extend_from_slice(&length.to_be_bytes());
+        }
+        if set_id == 1 {
+            datagram.extend_from_slice(&[0; 2]);
+        }
+    }
+
+    fn v9_data(datagram: &mut Vec<u8>, template_id: u16, sports: &[u16], padding: usize) {
+        datagram.extend_from_slice(&template_id.to_be_bytes());
+        datagram.extend_from_slice(&((4 + 29 * sports.len() + padding) as u16).to_be_bytes());
+        for sport in sports {
+            datagram.extend_from_slice(&[10, 0, 0, 1]);
+            datagram.extend_from_slice(&[192, 0, 2, 7]);
+            datagram.extend_from_slice(&sport.to_be_bytes());
+            datagram.extend_from_slice(&53u16.to_be_bytes());
+            datagram.push(17);
+            datagram.extend_from_slice(&2u32.to_be_bytes());
+            datagram.extend_from_slice(&120u32.to_be_bytes());
+            datagram.extend_from_slice(&4_000u32.to_be_bytes());
+            datagram.extend_from_slice(&9_000u32.to_be_bytes());
+        }
+        datagram.resize(datagram.len() + padding, 0);
+    }
+
+    #[derive(Default)]
+    struct State {
+        templates: HashMap<TemplateKey, Template>,
+        flows: Vec<FlowRecord>,
+        counters: Counters,
+    }
+
+    impl State {
+        fn decode(&mut self, exporter: IpAddr, datagram: &[u8]) -> Option<u64> {
+            decode_v9(
+                exporter,
+                datagram,
+                &mut self.templates,
+                &mut self.flows,
+                &mut self.counters,
+            )
+        }
+    }
+
+    fn exporter() -> IpAddr {
+        IpAddr::from([198, 51, 100, 1])
+    }
+
+    #[test]
+    fn switched_wraps() {
+        assert_eq!(switched(BOOT, UPTIME as i64, 4_000), BOOT + 4_000);
+        assert_eq!(switched(BOOT, UPTIME as i64, 0xffff_f000), BOOT - 0x1000);
+    }
+
+    #[test]
+    fn v5_records() {
+        let mut datagram = v5_header(2);
+        v5_record(&mut datagram, 50_000);
+        v5_record(&mut datagram, 50_001);
+        let mut flows = Vec::new();
+        assert_eq!(decode_v5(&datagram, &mut flows), Some(2));
+        assert_eq!(flows.len(), 2);
+
+        let flow = &flows[0];
+        assert_eq!(flow.saddr, IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(flow.daddr, IpAddr::from([192, 0, 2, 7]));
+        assert_eq!((flow.sport, flow.dport, flow.proto), (50_000, 443, 6));
+        assert_eq!(flow.forward.union_flags, 0x12);
+        assert_eq!((flow.forward.pkts, flow.forward.bytes), (30, 3000));
+        assert_eq!(flow.stime, (BOOT + 4_000) * 1000);
+        assert_eq!(flow.etime, (BOOT + 9_000) * 1000);
+        assert_eq!(flows[1].sport, 50_001);
+    }
+
+    #[test]
+    fn v5_truncated() {
+        let mut datagram = v5_header(2);
+        v5_record(&mut datagram, 50_000);
+        let mut flows = Vec::new();
+        assert_eq!(decode_v5(&datagram, &mut flows), None);
+        assert!(flows.is_empty());
+
+        let mut datagram = v5_header(1);
+        v5_record(&mut datagram, 50_000);
+        datagram.truncate(datagram.len() - 1);
+        assert_eq!(decode_v5(&datagram, &mut Vec::new()), None);
+
+        assert_eq!(decode_v5(&datagram[..20], &mut Vec::new()), None);
+    }
+
+    #[test]
+    fn v9_template_and_data() {
+        let mut datagram = v9_header();
+        v9_template(&mut datagram, 0, 256);
+        v9_data(&mut datagram, 256, &[5353, 5354], 2);
+        let mut state = State::default();
+        assert_eq!(state.decode(exporter(), &datagram), Some(2));
+        assert_eq!(state.counters.untemplated, 0);
+
+        let flow = &state.flows[0];
+        assert_eq!(flow.saddr, IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(flow.daddr, IpAddr::from([192, 0, 2, 7]));
+        assert_eq!((flow.sport, flow.dport, flow.proto), (5353, 53, 17));
+        assert_eq!((flow.forward.pkts, flow.forward.bytes), (2, 120));
+        assert_eq!(flow.stime, (BOOT + 4_000) * 1000);
+        assert_eq!(flow.etime, (BOOT + 9_000) * 1000);
+        assert_eq!(state.flows[1].sport, 5354);
+    }
+
+    #[test]
+    fn v9_data_before_template() {
+        let mut state = State::default();
+        let mut data = v9_header();
+        v9_data(&mut data, 256, &[5353], 3);
+        assert_eq!(state.decode(exporter(), &data), Some(0));
+        assert_eq!(state.counters.untemplated, 1);
+
+        let mut template = v9_header();
+        v9_template(&mut template, 0, 256);
+        assert_eq!(state.decode(exporter(), &template), Some(0));
+        assert_eq!(state.decode(exporter(), &data), Some(1));
+
+        // templates are per exporter
+        assert_eq!(
+            state.decode(IpAddr::from([198, 51, 100, 2]), &data),
+            Some(0)
+        );
+        assert_eq!(state.counters.untemplated, 2);
+        assert_eq!(state.flows.len(), 1);
+    }
+
+    #[test]
+    fn v9_options_template() {
+        let mut datagram = v9_header();
+        v9_template(&mut datagram, 1, 257);
+        v9_data(&mut datagram, 257, &[5353], 3);
+        let mut state = State::default();
+        assert_eq!(state.decode(exporter(), &datagram), Some(0));
+        assert_eq!(state.counters.untemplated, 0);
+        assert!(state.templates[&(exporter(), 7, 257)].options);
+    }
+
+    #[test]
+    fn v9_times_out_of_range() {
+        let record = |field_type: u16, value: u64| {
+            v9_record(
+                &[(field_type, 8)],
+                &value.to_be_bytes(),
+                BOOT,
+                UPTIME as i64,
+            )
+        };
+        assert_eq!(
+            record(152, 1_700_000_000_000).map(|f| f.stime),
+            Some(1_700_000_000_000_000)
+        );
+        assert_eq!(
+            record(150, 1_700_000_000).map(|f| f.stime),
+            Some(1_700_000_000_000_000)
+        );
+        for field_type in [150, 151, 152, 153] {
+            assert!(record(field_type, u64::MAX).is_none());
+        }
+        assert!(record(150, i64::MAX as u64 / 100).is_none());
+        assert!(record(153, i64::MAX as u64).is_none());
+    }
+
+    #[test]
+    fn v9_truncated() {
+        // a set longer than the datagram, after a complete one
+        let mut datagram = v9_header();
+        v9_template(&mut datagram, 0, 256);
+        v9_data(&mut datagram, 256, &[5353], 3);
+        v9_data(&mut datagram, 256, &[5354], 3);
+        datagram.truncate(datagram.len() - 4);
+        let mut state = State::default();
+        assert_eq!(state.decode(exporter(), &datagram), None);
+        assert!(state.flows.is_empty());
+
+        // a set length shorter than its header
+        let mut datagram = v9_header();
+        datagram.extend_from_slice(&[0, 0, 0, 2]);
+        assert_eq!(State::default().decode(exporter(), &datagram), None);
+
+        // a template with more fields than the set holds
+        let mut datagram = v9_header();
+        datagram.extend_from_slice(&[0, 0, 0, 12, 1, 0, 0, 2, 0, 8, 0, 4]);
+        let mut state = State::default();
+        assert_eq!(state.decode(exporter(), &datagram), None);
+        assert!(state.templates.is_empty());
+
+        // a header cut short
+        assert_eq!(
+            State::default().decode(exporter(), &v9_header()[..16]),
+            None
+        );
+    }
+}
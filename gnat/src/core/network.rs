@@ -0,0 +1,89 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// IPv4/IPv6 networks in CIDR notation; a bare address is a /32 or /128
+//
+
+use std::net::IpAddr;
+
+pub struct Network {
+    v4: bool,
+    base: u128,
+    prefix: u32,
+}
+
+impl Network {
+    pub fn parse(spec: &str) -> Option<Network> {
+        let (address, prefix) = match spec.split_once('/') {
+            Some((a, p)) => (a, Some(p.parse::<u32>().ok()?)),
+            None => (spec, None),
+        };
+        let (v4, bits, width) = address_bits(&address.parse().ok()?);
+        let prefix = prefix.unwrap_or(width);
+        if prefix > width {
+            return None;
+        }
+        Some(Network {
+            v4,
+            base: bits & mask(prefix, width),
+            prefix,
+        })
+    }
+
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        let (v4, bits, width) = address_bits(address);
+        v4 == self.v4 && bits & mask(self.prefix, width) == self.base
+    }
+}
+
+fn address_bits(address: &IpAddr) -> (bool, u128, u32) {
+    match address {
+        IpAddr::V4(a) => (true, u32::from(*a) as u128, 32),
+        IpAddr::V6(a) => (false, u128::from(*a), 128),
+    }
+}
+
+fn mask(prefix: u32, width: u32) -> u128 {
+    if prefix == 0 {
+        return 0;
+    }
+    let all = if width == 32 { u32::MAX as u128 } else { u128::MAX };
+    all & !((1u128 << (width - prefix)) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(spec: &str, address: &str) -> bool {
+        Network::parse(spec).unwrap().contains(&address.parse().unwrap())
+    }
+
+    #[test]
+    fn parse_and_contains() {
+        assert!(contains("10.0.0.0/8", "10.255.0.1"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        // host bits of the spec are ignored
+        assert!(contains("192.168.1.77/24", "192.168.1.1"));
+        assert!(contains("10.0.0.1", "10.0.0.1"));
+        assert!(!contains("10.0.0.1", "10.0.0.2"));
+        assert!(contains("0.0.0.0/0", "8.8.8.8"));
+        assert!(contains("fd00::/8", "fd12::1"));
+        assert!(!contains("fd00::/8", "fe80::1"));
+        assert!(contains("::/0", "2001:db8::1"));
+        // families don't mix
+        assert!(!contains("0.0.0.0/0", "::ffff:8.8.8.8"));
+        assert!(!contains("::/0", "8.8.8.8"));
+
+        assert!(Network::parse("10.0.0.0/33").is_none());
+        assert!(Network::parse("fd00::/129").is_none());
+        assert!(Network::parse("10.0.0.0/x").is_none());
+        assert!(Network::parse("example.com").is_none());
+    }
+}
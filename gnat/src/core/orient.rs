@@ -0,0 +1,324 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Flow orientation (gnat_import --networks)
+//
+// The networks file is TOML:
+//
+//   internal = ["10.0.0.0/8", "192.168.0.0/16", "fd00::/8"]
+//   dmz = ["203.0.113.0/24"]
+//   canonical = false
+//
+// Each address falls in the first zone, from most to least internal, that
+// holds it: internal, dmz, else external. orient is set per flow from the
+// zones of saddr and daddr:
+//
+//   inbound    saddr is in a less internal zone than daddr
+//   outbound   saddr is in a more internal zone than daddr
+//   internal   both in the same zone, internal or dmz
+//   external   both external
+//
+// With canonical = true, inbound flows are turned around so saddr is always
+// the more internal end: every s<x>/d<x> column pair is swapped, as are the
+// forward/reverse halves of iflags and uflags, and pcr is negated. orient
+// still names the direction the flow was initiated in.
+//
+
+use crate::core::error::GnatError;
+use crate::core::network::Network;
+use crate::core::schema;
+use crate::core::scratch;
+
+use std::fs;
+use std::net::IpAddr;
+
+use duckdb::{params, Connection};
+use serde::Deserialize;
+use tracing::{error, info};
+
+// zones, from least to most internal
+const EXTERNAL: u8 = 0;
+const DMZ: u8 = 1;
+const INTERNAL: u8 = 2;
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    #[serde(default)]
+    internal: Vec<String>,
+    #[serde(default)]
+    dmz: Vec<String>,
+    #[serde(default)]
+    canonical: bool,
+}
+
+pub struct Orientation {
+    internal: Vec<Network>,
+    dmz: Vec<Network>,
+    pub canonical: bool,
+}
+
+fn parse_networks(networks_spec: &String, zone: &str, specs: &[String]) -> Result<Vec<Network>, GnatError> {
+    specs
+        .iter()
+        .map(|spec| {
+            Network::parse(spec.trim()).ok_or_else(|| {
+                GnatError::Config(format!("{}: invalid {} network {}", networks_spec, zone, spec))
+            })
+        })
+        .collect()
+}
+
+impl Orientation {
+    pub fn load(networks_spec: &String) -> Result<Orientation, GnatError> {
+        let contents = fs::read_to_string(networks_spec)?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| GnatError::Config(format!("parsing {} - {}", networks_spec, e)))?;
+        let orientation = Orientation {
+            internal: parse_networks(networks_spec, "internal", &config.internal)?,
+            dmz: parse_networks(networks_spec, "dmz", &config.dmz)?,
+            canonical: config.canonical,
+        };
+        info!(
+            "networks: {} internal, {} dmz, canonical: {}",
+            orientation.internal.len(),
+            orientation.dmz.len(),
+            orientation.canonical
+        );
+        Ok(orientation)
+    }
+
+    fn zone(&self, address: &str) -> u8 {
+        let Ok(address) = address.parse::<IpAddr>() else {
+            return EXTERNAL;
+        };
+        if self.internal.iter().any(|n| n.contains(&address)) {
+            INTERNAL
+        } else if self.dmz.iter().any(|n| n.contains(&address)) {
+            DMZ
+        } else {
+            EXTERNAL
+        }
+    }
+
+    //
+    // SELECT list turning inbound flows around, for the columns of memtable
+    //
+    fn canonical_select(&self, columns: &[String]) -> String {
+        let swap = "orient = 'inbound'";
+        columns
+            .iter()
+            .map(|column| {
+                let peer = match column.split_at_checked(1) {
+                    Some(("s", rest)) => format!("d{}", rest),
+                    Some(("d", rest)) => format!("s{}", rest),
+                    _ => String::new(),
+                };
+                if columns.contains(&peer) {
+                    format!("CASE WHEN {} THEN {} ELSE {} END AS {}", swap, peer, column, column)
+                } else if column == "iflags" || column == "uflags" {
+                    format!("CASE WHEN {} THEN swap_flags({}) ELSE {} END AS {}", swap, column, column, column)
+                } else if column == "pcr" {
+                    format!("CASE WHEN {} THEN -pcr ELSE pcr END AS pcr", swap)
+                } else {
+                    column.clone()
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    pub fn orient_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("orient")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let sql_command = format!(
+            "CREATE TABLE memtable AS {};
+             CREATE TABLE zones (addr VARCHAR, zone UTINYINT);",
+            source
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        //
+        // zone the distinct addresses of the batch
+        //
+        let addresses: Vec<String> = {
+            let mut stmt = conn
+                .prepare("SELECT saddr FROM memtable UNION SELECT daddr FROM memtable;")?;
+            match stmt.query_map([], |row| row.get::<_, Option<String>>(0)) {
+                Ok(rows) => rows.filter_map(|r| r.ok().flatten()).collect(),
+                Err(e) => {
+                    error!("reading {} - {:?}", input_spec, e);
+                    return Err(e.into());
+                }
+            }
+        };
+        {
+            let mut appender = conn.appender("zones")?;
+            for address in addresses.iter() {
+                if let Err(e) = appender.append_row(params![address, self.zone(address)]) {
+                    error!("orienting {} - {:?}", input_spec, e);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let sql_command = format!(
+            "UPDATE memtable SET orient = CASE
+                    WHEN s.zone < d.zone THEN 'inbound'
+                    WHEN s.zone > d.zone THEN 'outbound'
+                    WHEN s.zone > {external} THEN 'internal'
+                    ELSE 'external' END
+                FROM zones s, zones d WHERE s.addr = memtable.saddr AND d.addr = memtable.daddr;",
+            external = EXTERNAL
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("orienting {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        let select = if self.canonical {
+            let columns = match table_columns(&conn) {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("reading {} - {:?}", input_spec, e);
+                    return Err(e.into());
+                }
+            };
+            // iflags/uflags interleave a forward (upper case) and reverse
+            // (lower case) character per flag, as in record::tcp_flags()
+            if let Err(e) = conn.execute_batch(
+                "CREATE MACRO swap_flags(f) AS array_to_string(
+                    list_transform(range(1, length(f), 2), i -> upper(f[i + 1]) || lower(f[i])), '');",
+            ) {
+                error!("orienting {} - {:?}", input_spec, e);
+                return Err(e.into());
+            }
+            self.canonical_select(&columns)
+        } else {
+            String::from("*")
+        };
+        let sql_command = format!(
+            "COPY (SELECT {} FROM memtable) TO '{}' ({});",
+            select,
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+        info!("orient: {} [{} addresses]", input_spec, addresses.len());
+        Ok(())
+    }
+}
+
+fn table_columns(conn: &Connection) -> Result<Vec<String>, duckdb::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT column_name FROM information_schema.columns
+            WHERE table_name = 'memtable' ORDER BY ordinal_position;",
+    )?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, duckdb::Error>>()?;
+    Ok(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    fn load(dir: &str, contents: &str) -> Result<Orientation, GnatError> {
+        let networks_spec = format!("{}/networks.toml", dir);
+        fs::write(&networks_spec, contents).unwrap();
+        Orientation::load(&networks_spec)
+    }
+
+    #[test]
+    fn zones() {
+        let dir = test_dir("orient-zones");
+        let orientation = load(
+            &dir,
+            "internal = [\"10.0.0.0/8\", \"fd00::/8\"]\ndmz = [\"10.9.0.0/16\"]",
+        )
+        .unwrap();
+        assert_eq!(orientation.zone("10.0.0.1"), INTERNAL);
+        // the first zone that holds an address
+        assert_eq!(orientation.zone("10.9.0.1"), INTERNAL);
+        assert_eq!(orientation.zone("fd00::1"), INTERNAL);
+        assert_eq!(orientation.zone("8.8.8.8"), EXTERNAL);
+        assert_eq!(orientation.zone("not an address"), EXTERNAL);
+        assert!(load(&dir, "internal = [\"10.0.0.0/40\"]").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn canonical_turns_inbound_flows_around() {
+        let dir = test_dir("orient-file");
+        let orientation = load(
+            &dir,
+            "internal = [\"10.0.0.0/8\"]\ndmz = [\"203.0.113.0/24\"]\ncanonical = true",
+        )
+        .unwrap();
+        let input_spec = format!("{}/flows.parquet", dir);
+        let output_spec = format!("{}/flows.out.parquet", dir);
+        schema::write_test_flows(
+            &input_spec,
+            "SELECT * FROM (VALUES
+                (1, '8.8.8.8', 53, '10.0.0.1', 5000, 100, 900, 0.5, 'Sa'),
+                (2, '10.0.0.1', 5000, '203.0.113.5', 443, 100, 900, 0.5, 'Sa'),
+                (3, '10.0.0.1', 5000, '10.0.0.2', 22, 100, 900, 0.5, 'Sa'),
+                (4, '8.8.8.8', 5000, '1.1.1.1', 53, 100, 900, 0.5, 'Sa'))
+                t(dur, saddr, sport, daddr, dport, sbytes, dbytes, pcr, iflags)",
+        );
+        orientation.orient_file(&input_spec, &output_spec).unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT orient, saddr, sport, daddr, sbytes, pcr, iflags FROM '{}' ORDER BY dur;",
+                output_spec
+            ))
+            .unwrap();
+        let flows: Vec<String> = stmt
+            .query_map([], |row| {
+                Ok(format!(
+                    "{} {}:{} {} {} {} {}",
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u16>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, u64>(4)?,
+                    row.get::<_, f32>(5)?,
+                    row.get::<_, String>(6)?
+                ))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            flows,
+            vec![
+                "inbound 10.0.0.1:5000 8.8.8.8 900 -0.5 As",
+                "outbound 10.0.0.1:5000 203.0.113.5 100 0.5 Sa",
+                "internal 10.0.0.1:5000 10.0.0.2 100 0.5 Sa",
+                "external 8.8.8.8:5000 1.1.1.1 100 0.5 Sa",
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,177 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Per-observation overrides of stage options (--overrides <file>)
+//
+// One pipeline can serve sensors that need different settings. The file
+// is TOML, keyed by observation name:
+//
+//   [observ.sensor1]
+//   percent = 5                     # gnat_sample --percent
+//   cap = 50                        # gnat_sample --cap
+//
+//   [observ.sensor1.threshold]      # gnat_detect, by rule name
+//   dns-flows-per-host = 5000
+//
+// Observations without an entry, and the options an entry leaves out,
+// keep the stage's own settings. Overrides are applied in the batch's SQL,
+// as a CASE over observ, so a file mixing sensors is handled in one pass.
+//
+
+use crate::core::error::GnatError;
+use crate::core::filter;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+
+use serde::Deserialize;
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Override {
+    pub percent: Option<f64>,
+    pub cap: Option<u64>,
+    #[serde(default)]
+    pub threshold: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overrides {
+    #[serde(default)]
+    pub observ: BTreeMap<String, Override>,
+}
+
+impl Overrides {
+    //
+    // Load the overrides of overrides_spec; none for an empty spec
+    //
+    pub fn load(overrides_spec: &String) -> Result<Overrides, GnatError> {
+        if overrides_spec.is_empty() {
+            return Ok(Overrides::default());
+        }
+        let contents = fs::read_to_string(overrides_spec)?;
+        let overrides: Overrides = toml::from_str(&contents)
+            .map_err(|e| GnatError::Config(format!("parsing {} - {}", overrides_spec, e)))?;
+        for (observ, entry) in overrides.observ.iter() {
+            let invalid = |option: &str| {
+                GnatError::Config(format!("{}: observ {}: invalid {}", overrides_spec, observ, option))
+            };
+            if entry.percent.is_some_and(|p| !(p > 0.0 && p <= 100.0)) {
+                return Err(invalid("percent"));
+            }
+            if entry.cap == Some(0) {
+                return Err(invalid("cap"));
+            }
+            if let Some((name, _)) = entry.threshold.iter().find(|(_, t)| !t.is_finite()) {
+                return Err(invalid(&format!("threshold {}", name)));
+            }
+        }
+        Ok(overrides)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observ.is_empty()
+    }
+
+    //
+    // Rule names given a threshold by any observation
+    //
+    pub fn rules(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = self.observ.values().flat_map(|o| o.threshold.keys()).collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    //
+    // SQL for an option: the value pick() gives each observation's entry,
+    // else default
+    //
+    pub fn case<T: Display>(&self, pick: impl Fn(&Override) -> Option<T>, default: T) -> String {
+        let arms: Vec<String> = self
+            .observ
+            .iter()
+            .filter_map(|(observ, entry)| {
+                pick(entry).map(|value| format!("WHEN {} THEN {}", filter::quote(observ), value))
+            })
+            .collect();
+        if arms.is_empty() {
+            default.to_string()
+        } else {
+            format!("(CASE observ {} ELSE {} END)", arms.join(" "), default)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("gnat-{}-{}.toml", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn load_rejects_invalid_overrides() {
+        assert!(Overrides::load(&String::new()).unwrap().is_empty());
+        for contents in [
+            "[observ.s1]\npercent = 0",
+            "[observ.s1]\npercent = 101",
+            "[observ.s1]\ncap = 0",
+            "[observ.s1.threshold]\nrule = nan",
+            "[observ.s1]\nwindow = 5",
+        ] {
+            let overrides_spec = test_file("overrides-invalid", contents);
+            assert!(Overrides::load(&overrides_spec).is_err(), "{}", contents);
+            let _ = fs::remove_file(&overrides_spec);
+        }
+    }
+
+    #[test]
+    fn case_picks_each_observation() {
+        let overrides_spec = test_file(
+            "overrides-case",
+            "[observ.s1]\npercent = 5\n[observ.s1.threshold]\nb = 10\na = 20\n\
+             [observ.\"it's\"]\ncap = 7\n[observ.\"it's\".threshold]\na = 30\n",
+        );
+        let overrides = Overrides::load(&overrides_spec).unwrap();
+        let _ = fs::remove_file(&overrides_spec);
+        assert_eq!(overrides.rules(), vec!["a", "b"]);
+        // no entry sets the option
+        assert_eq!(overrides.case(|o| o.threshold.get("c").copied(), 1.0), "1");
+
+        let percent = overrides.case(|o| o.percent, 50.0);
+        let threshold = overrides.case(|o| o.threshold.get("a").copied(), 1.0);
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT observ, {}::DOUBLE, {}::DOUBLE
+                    FROM (VALUES ('s1'), ('it''s'), ('s2')) t(observ) ORDER BY observ;",
+                percent, threshold
+            ))
+            .unwrap();
+        let values: Vec<(String, f64, f64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                (String::from("it's"), 50.0, 30.0),
+                (String::from("s1"), 5.0, 20.0),
+                (String::from("s2"), 50.0, 1.0),
+            ]
+        );
+    }
+}
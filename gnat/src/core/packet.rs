@@ -0,0 +1,376 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Link, network and transport header decoding for captured frames (pcap
+// import and sFlow sampled headers). Frames may be truncated at the capture
+// length: counters use the lengths from the headers, while only the
+// captured part of the payload is returned.
+//
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// pcap link types
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+pub struct Packet<'a> {
+    pub smac: [u8; 6],
+    pub dmac: [u8; 6],
+    pub vlan: u16,
+    pub proto: u8,
+    pub saddr: IpAddr,
+    pub daddr: IpAddr,
+    // icmp type << 8 | code in dport, as YAF reports it
+    pub sport: u16,
+    pub dport: u16,
+    pub flags: u8,
+    pub seq: u32,
+    // IP packet and payload lengths from the headers
+    pub ip_length: u32,
+    pub payload_length: u32,
+    pub payload: &'a [u8],
+    // a non-first fragment, which carries no transport header
+    pub fragment: bool,
+}
+
+//
+// Bounds-checked big-endian reader
+//
+pub struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, offset: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.offset..];
+        self.offset = self.data.len();
+        rest
+    }
+
+    pub fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.offset.checked_add(len)?;
+        let bytes = self.data.get(self.offset..end)?;
+        self.offset = end;
+        Some(bytes)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub fn ipv4(&mut self) -> Option<IpAddr> {
+        Some(IpAddr::V4(Ipv4Addr::from(self.u32()?)))
+    }
+
+    pub fn ipv6(&mut self) -> Option<IpAddr> {
+        let bytes: [u8; 16] = self.take(16)?.try_into().ok()?;
+        Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+    }
+}
+
+fn empty<'a>() -> Packet<'a> {
+    Packet {
+        smac: [0; 6],
+        dmac: [0; 6],
+        vlan: 0,
+        proto: 0,
+        saddr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        daddr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        sport: 0,
+        dport: 0,
+        flags: 0,
+        seq: 0,
+        ip_length: 0,
+        payload_length: 0,
+        payload: &[],
+        fragment: false,
+    }
+}
+
+//
+// Decode a frame of the given link type; None for non-IP frames
+//
+pub fn decode(linktype: u32, frame: &[u8]) -> Option<Packet<'_>> {
+    let mut packet = empty();
+    let mut r = Reader::new(frame);
+    let ethertype = match linktype {
+        LINKTYPE_ETHERNET => {
+            packet.dmac.copy_from_slice(r.take(6)?);
+            packet.smac.copy_from_slice(r.take(6)?);
+            let mut ethertype = r.u16()?;
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                let tci = r.u16()?;
+                if packet.vlan == 0 {
+                    packet.vlan = tci & 0x0fff;
+                }
+                ethertype = r.u16()?;
+            }
+            ethertype
+        }
+        LINKTYPE_LINUX_SLL => {
+            r.take(14)?;
+            r.u16()?
+        }
+        LINKTYPE_LINUX_SLL2 => {
+            let ethertype = r.u16()?;
+            r.take(18)?;
+            ethertype
+        }
+        // address family in host byte order
+        LINKTYPE_NULL => {
+            let family = r.take(4)?;
+            match u32::from_ne_bytes(family.try_into().ok()?) {
+                2 => 0x0800,
+                24 | 28 | 30 => 0x86dd,
+                _ => return None,
+            }
+        }
+        LINKTYPE_RAW | 12 | 14 => match frame.first()? >> 4 {
+            4 => 0x0800,
+            6 => 0x86dd,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let network = r.rest();
+    let transport = match ethertype {
+        0x0800 => decode_ipv4(network, &mut packet)?,
+        0x86dd => decode_ipv6(network, &mut packet)?,
+        _ => return None,
+    };
+    if !packet.fragment {
+        decode_transport(transport, &mut packet);
+    }
+    Some(packet)
+}
+
+fn decode_ipv4<'a>(network: &'a [u8], packet: &mut Packet<'a>) -> Option<&'a [u8]> {
+    let mut r = Reader::new(network);
+    let header_length = (r.u8()? & 0x0f) as usize * 4;
+    r.take(1)?; // tos
+    packet.ip_length = r.u16()? as u32;
+    r.take(2)?; // id
+    packet.fragment = r.u16()? & 0x1fff != 0;
+    r.take(1)?; // ttl
+    packet.proto = r.u8()?;
+    r.take(2)?; // checksum
+    packet.saddr = r.ipv4()?;
+    packet.daddr = r.ipv4()?;
+    packet.payload_length = packet.ip_length.saturating_sub(header_length as u32);
+    let end = network.len().min(packet.ip_length as usize);
+    Some(network.get(header_length..end).unwrap_or(&[]))
+}
+
+fn decode_ipv6<'a>(network: &'a [u8], packet: &mut Packet<'a>) -> Option<&'a [u8]> {
+    let mut r = Reader::new(network);
+    r.take(4)?;
+    let payload_length = r.u16()? as u32;
+    let mut next_header = r.u8()?;
+    r.take(1)?; // hop limit
+    packet.saddr = r.ipv6()?;
+    packet.daddr = r.ipv6()?;
+    packet.ip_length = payload_length + 40;
+
+    // hop-by-hop, routing, fragment and destination options headers
+    let mut header_bytes = 0;
+    while matches!(next_header, 0 | 43 | 44 | 60) {
+        let header = next_header;
+        next_header = r.u8()?;
+        let length = r.u8()? as usize;
+        if header == 44 {
+            packet.fragment = r.u16()? >> 3 != 0;
+            r.take(4)?;
+            header_bytes += 8;
+        } else {
+            r.take(length * 8 + 6)?;
+            header_bytes += length * 8 + 8;
+        }
+    }
+    packet.proto = next_header;
+    packet.payload_length = payload_length.saturating_sub(header_bytes as u32);
+    let captured = r.rest();
+    let end = captured.len().min(packet.payload_length as usize);
+    Some(&captured[..end])
+}
+
+fn decode_transport<'a>(transport: &'a [u8], packet: &mut Packet<'a>) {
+    let mut r = Reader::new(transport);
+    let header_length = match packet.proto {
+        // tcp
+        6 => {
+            let (Some(sport), Some(dport), Some(seq)) = (r.u16(), r.u16(), r.u32()) else {
+                return;
+            };
+            packet.sport = sport;
+            packet.dport = dport;
+            packet.seq = seq;
+            r.take(4);
+            let offset = r.u8().map(|o| (o >> 4) as usize * 4).unwrap_or(20);
+            packet.flags = r.u8().unwrap_or(0);
+            offset
+        }
+        // udp, sctp
+        17 | 132 => {
+            let (Some(sport), Some(dport)) = (r.u16(), r.u16()) else {
+                return;
+            };
+            packet.sport = sport;
+            packet.dport = dport;
+            if packet.proto == 17 {
+                8
+            } else {
+                12
+            }
+        }
+        // icmp, icmpv6
+        1 | 58 => {
+            let (Some(kind), Some(code)) = (r.u8(), r.u8()) else {
+                return;
+            };
+            packet.dport = (kind as u16) << 8 | code as u16;
+            8
+        }
+        _ => return,
+    };
+    packet.payload_length = packet.payload_length.saturating_sub(header_length as u32);
+    packet.payload = transport.get(header_length..).unwrap_or(&[]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp_segment(payload: &[u8]) -> Vec<u8> {
+        let mut segment = Vec::new();
+        segment.extend(1234u16.to_be_bytes());
+        segment.extend(80u16.to_be_bytes());
+        segment.extend(1000u32.to_be_bytes());
+        segment.extend([0; 4]);
+        segment.extend([5 << 4, 0x18]);
+        segment.extend([0; 6]);
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    fn ipv4(proto: u8, fragment: u16, transport: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0];
+        packet.extend((20 + transport.len() as u16).to_be_bytes());
+        packet.extend([0, 0]);
+        packet.extend(fragment.to_be_bytes());
+        packet.extend([64, proto, 0, 0]);
+        packet.extend([10, 0, 0, 1, 10, 0, 0, 2]);
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    fn ethernet(vlans: &[u16], ethertype: u16, network: &[u8]) -> Vec<u8> {
+        let mut frame = vec![2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1];
+        for vlan in vlans {
+            frame.extend(0x8100u16.to_be_bytes());
+            frame.extend(vlan.to_be_bytes());
+        }
+        frame.extend(ethertype.to_be_bytes());
+        frame.extend_from_slice(network);
+        frame
+    }
+
+    #[test]
+    fn ethernet_tcp() {
+        let frame = ethernet(&[0x2014, 30], 0x0800, &ipv4(6, 0, &tcp_segment(b"hello")));
+        let packet = decode(LINKTYPE_ETHERNET, &frame).unwrap();
+        assert_eq!(packet.smac, [2, 0, 0, 0, 0, 1]);
+        assert_eq!(packet.dmac, [2, 0, 0, 0, 0, 2]);
+        // the outer tag, without its priority bits
+        assert_eq!(packet.vlan, 20);
+        assert_eq!((packet.proto, packet.sport, packet.dport), (6, 1234, 80));
+        assert_eq!(packet.saddr.to_string(), "10.0.0.1");
+        assert_eq!(packet.daddr.to_string(), "10.0.0.2");
+        assert_eq!((packet.seq, packet.flags), (1000, 0x18));
+        assert_eq!((packet.ip_length, packet.payload_length), (45, 5));
+        assert_eq!(packet.payload, b"hello");
+    }
+
+    #[test]
+    fn truncated_frames_keep_the_header_lengths() {
+        let frame = ethernet(&[], 0x0800, &ipv4(6, 0, &tcp_segment(&[7; 100])));
+        let packet = decode(LINKTYPE_ETHERNET, &frame[..14 + 20 + 20 + 10]).unwrap();
+        assert_eq!(packet.payload_length, 100);
+        assert_eq!(packet.payload, &[7; 10]);
+        // cut inside the IP header
+        assert!(decode(LINKTYPE_ETHERNET, &frame[..20]).is_none());
+    }
+
+    #[test]
+    fn fragments_and_ipv6() {
+        // a non-first fragment has no transport header
+        let fragment = ipv4(17, 185, &[1, 2, 3, 4]);
+        let packet = decode(LINKTYPE_RAW, &fragment).unwrap();
+        assert!(packet.fragment);
+        assert_eq!((packet.sport, packet.dport), (0, 0));
+
+        let mut udp = Vec::new();
+        udp.extend(5353u16.to_be_bytes());
+        udp.extend(53u16.to_be_bytes());
+        udp.extend([0, 12, 0, 0]);
+        udp.extend(b"dns!");
+        // a destination options header before udp
+        let mut network = vec![0x60, 0, 0, 0];
+        network.extend((8 + udp.len() as u16).to_be_bytes());
+        network.extend([60, 64]);
+        network.extend("fd00::1".parse::<Ipv6Addr>().unwrap().octets());
+        network.extend("fd00::2".parse::<Ipv6Addr>().unwrap().octets());
+        network.extend([17, 0, 0, 0, 0, 0, 0, 0]);
+        network.extend(&udp);
+        let frame = ethernet(&[], 0x86dd, &network);
+        let packet = decode(LINKTYPE_ETHERNET, &frame).unwrap();
+        assert_eq!(packet.proto, 17);
+        assert_eq!(packet.saddr.to_string(), "fd00::1");
+        assert_eq!((packet.sport, packet.dport), (5353, 53));
+        assert_eq!((packet.ip_length, packet.payload_length), (60, 4));
+        assert_eq!(packet.payload, b"dns!");
+    }
+
+    #[test]
+    fn link_types() {
+        let mut icmp = ipv4(1, 0, &[8, 0, 0, 0, 0, 1, 0, 1]);
+        let packet = decode(LINKTYPE_RAW, &icmp).unwrap();
+        // echo request, as YAF reports it
+        assert_eq!(packet.dport, 8 << 8);
+
+        let mut null = 2u32.to_ne_bytes().to_vec();
+        null.append(&mut icmp);
+        assert!(decode(LINKTYPE_NULL, &null).is_some());
+        null[..4].copy_from_slice(&7u32.to_ne_bytes());
+        assert!(decode(LINKTYPE_NULL, &null).is_none());
+
+        // arp, and an unknown link type
+        assert!(decode(LINKTYPE_ETHERNET, &ethernet(&[], 0x0806, &[0; 28])).is_none());
+        assert!(decode(999, &null).is_none());
+    }
+}
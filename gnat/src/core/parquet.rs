@@ -0,0 +1,230 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Parquet writer options (--parquet-codec, --parquet-level,
+// --parquet-row-group-size, --parquet-dictionary-limit)
+//
+// Every parquet file a stage writes (flows, DNS, triggers, reports) is
+// written with options(), so the codec and row-group size are set once per
+// stage, from the ParquetArgs flattened into its arguments. The defaults
+// (snappy, 100_000 rows) are the toolkit's historical settings. The
+// importers write from C and are given the same options with
+// parquet_set_copy_options().
+//
+
+use crate::core::context::Local;
+use crate::core::shutdown;
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+
+use tracing::{error, info};
+
+pub const CODECS: [&str; 6] = ["snappy", "zstd", "gzip", "lz4", "brotli", "uncompressed"];
+pub const DEFAULT_ROW_GROUP_SIZE: u64 = 100_000;
+
+extern "C" {
+    fn parquet_set_copy_options(options: *const c_char);
+}
+
+#[derive(Debug, Clone)]
+pub struct WriterOptions {
+    pub codec: String,
+    // zstd only; 0 = DuckDB's default level
+    pub level: i32,
+    pub row_group_size: u64,
+    // bytes; 0 = DuckDB's default limit
+    pub dictionary_limit: u64,
+}
+
+static WRITER_OPTIONS: Local<WriterOptions> = Local::new();
+
+#[derive(Debug, clap::Args)]
+pub struct ParquetArgs {
+    /// parquet codec: snappy, zstd, gzip, lz4, brotli or uncompressed
+    #[arg(long)]
+    pub parquet_codec: Option<String>,
+
+    /// zstd compression level (0 = DuckDB default)
+    #[arg(long)]
+    pub parquet_level: Option<i32>,
+
+    /// rows per parquet row group
+    #[arg(long)]
+    pub parquet_row_group_size: Option<u64>,
+
+    /// parquet dictionary size limit in bytes (0 = DuckDB default)
+    #[arg(long)]
+    pub parquet_dictionary_limit: Option<u64>,
+}
+
+impl ParquetArgs {
+    pub fn options(&self) -> WriterOptions {
+        let defaults = WriterOptions::default();
+        WriterOptions {
+            codec: self.parquet_codec.clone().unwrap_or(defaults.codec),
+            level: self.parquet_level.unwrap_or(defaults.level),
+            row_group_size: self.parquet_row_group_size.unwrap_or(defaults.row_group_size),
+            dictionary_limit: self.parquet_dictionary_limit.unwrap_or(defaults.dictionary_limit),
+        }
+    }
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        WriterOptions {
+            codec: String::from("snappy"),
+            level: 0,
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+            dictionary_limit: 0,
+        }
+    }
+}
+
+impl WriterOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !CODECS.contains(&self.codec.as_str()) {
+            return Err(format!("invalid --parquet-codec {} [{}]", self.codec, CODECS.join("|")));
+        }
+        if self.level != 0 && self.codec != "zstd" {
+            return Err(String::from("--parquet-level requires the zstd codec"));
+        }
+        if !(-7..=22).contains(&self.level) {
+            return Err(format!("invalid --parquet-level {} [-7..22]", self.level));
+        }
+        if self.row_group_size == 0 {
+            return Err(String::from("invalid --parquet-row-group-size 0"));
+        }
+        Ok(())
+    }
+
+    //
+    // COPY options, without KV_METADATA
+    //
+    pub fn to_sql(&self) -> String {
+        let mut sql = format!("FORMAT 'parquet', CODEC '{}'", self.codec);
+        if self.level != 0 {
+            sql.push_str(&format!(", COMPRESSION_LEVEL {}", self.level));
+        }
+        sql.push_str(&format!(", ROW_GROUP_SIZE {}", self.row_group_size));
+        if self.dictionary_limit > 0 {
+            sql.push_str(&format!(", DICTIONARY_SIZE_LIMIT {}", self.dictionary_limit));
+        }
+        sql
+    }
+}
+
+//
+// Set the options for this stage, including the C writers of the importers
+//
+pub fn set_options(options: WriterOptions) -> Result<(), String> {
+    options.validate()?;
+    info!("parquet options: {}", options.to_sql());
+    if let Ok(sql) = CString::new(options.to_sql()) {
+        unsafe { parquet_set_copy_options(sql.as_ptr()) };
+    }
+    let _ = WRITER_OPTIONS.set(options);
+    Ok(())
+}
+
+//
+// Set the options of a stage's --parquet-* arguments, exiting on invalid ones
+//
+pub fn configure(args: &ParquetArgs) {
+    if let Err(e) = set_options(args.options()) {
+        error!("{}", e);
+        shutdown::exit(exitcode::CONFIG)
+    }
+}
+
+//
+// COPY options for a parquet output; add KV_METADATA as needed
+//
+pub fn options() -> String {
+    WRITER_OPTIONS.get_or_init(WriterOptions::default).to_sql()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context;
+
+    fn args(codec: Option<&str>, level: Option<i32>) -> ParquetArgs {
+        ParquetArgs {
+            parquet_codec: codec.map(String::from),
+            parquet_level: level,
+            parquet_row_group_size: None,
+            parquet_dictionary_limit: None,
+        }
+    }
+
+    #[test]
+    fn validate_and_to_sql() {
+        let defaults = args(None, None).options();
+        assert!(defaults.validate().is_ok());
+        assert_eq!(
+            defaults.to_sql(),
+            "FORMAT 'parquet', CODEC 'snappy', ROW_GROUP_SIZE 100000"
+        );
+        assert!(args(Some("lzo"), None).options().validate().is_err());
+        assert!(args(Some("snappy"), Some(3)).options().validate().is_err());
+        assert!(args(Some("zstd"), Some(23)).options().validate().is_err());
+        let mut empty = defaults.clone();
+        empty.row_group_size = 0;
+        assert!(empty.validate().is_err());
+
+        let mut zstd = args(Some("zstd"), Some(-7)).options();
+        zstd.dictionary_limit = 1024;
+        assert!(zstd.validate().is_ok());
+        assert_eq!(
+            zstd.to_sql(),
+            "FORMAT 'parquet', CODEC 'zstd', COMPRESSION_LEVEL -7, ROW_GROUP_SIZE 100000, \
+             DICTIONARY_SIZE_LIMIT 1024"
+        );
+
+        // DuckDB takes the options as written
+        let path = std::env::temp_dir()
+            .join(format!("gnat-{}-parquet.parquet", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!(
+            "COPY (SELECT 1 AS a) TO '{}' ({});",
+            path,
+            zstd.to_sql()
+        ))
+        .unwrap();
+        let codec: String = conn
+            .query_row(
+                &format!("SELECT compression FROM parquet_metadata('{}');", path),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(codec, "ZSTD");
+    }
+
+    #[test]
+    fn options_per_stage() {
+        std::thread::spawn(|| {
+            context::enter(context::next());
+            WRITER_OPTIONS.set(args(Some("gzip"), None).options()).unwrap();
+            assert!(options().contains("CODEC 'gzip'"));
+        })
+        .join()
+        .unwrap();
+        std::thread::spawn(|| {
+            context::enter(context::next());
+            assert!(options().contains("CODEC 'snappy'"));
+        })
+        .join()
+        .unwrap();
+    }
+}
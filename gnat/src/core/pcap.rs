@@ -0,0 +1,851 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// pcap/pcapng import (gnat_import --format pcap)
+//
+// Packets are assembled into biflows the way YAF assembles them: the first
+// packet of a 5-tuple and vlan sets the forward direction, a flow ends
+// after idle-timeout seconds without packets ("idle"), is cut every
+// active-timeout seconds ("active"), ends on a tcp RST or once both sides
+// have sent FIN ("."), and flows still open at the end of the capture end
+// with "eof". Entropy, small/large/non-empty packet counts and spd follow
+// YAF's definitions; non-first IP fragments are skipped and counted.
+//
+// The start of each side of a tcp flow that opens with a TLS handshake
+// record is kept, in sequence, for the sni and certificate columns and the
+// JA3/JA4 fingerprints (see tls.rs). The first non-empty packets of a tcp
+// flow give its HTTP request and status (see http.rs).
+//
+// Packet times come from the capture, so flows keep the time they were
+// seen rather than the time of the import.
+//
+
+use crate::core::error::GnatError;
+use crate::core::http;
+use crate::core::packet::{self, Packet};
+use crate::core::record::{Direction, FlowRecord, FlowWriter};
+use crate::core::tls;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::net::IpAddr;
+use std::path::Path;
+
+use tracing::{info, warn};
+
+// YAF packet size thresholds (payload bytes)
+const SMALL_PAYLOAD: u32 = 60;
+const LARGE_PAYLOAD: u32 = 225;
+
+// larger than any snap length, so corrupt lengths fail rather than allocate
+const MAX_FRAME: usize = 256 * 1024;
+
+// records written to the flow table at a time
+const CHUNK_SIZE: usize = 10000;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_RST: u8 = 0x04;
+const TCP_URG: u8 = 0x20;
+
+const TLS_HANDSHAKE: u8 = 22;
+
+// stream bytes kept for the TLS handshake: the ClientHello, and the
+// ServerHello with the leaf certificate
+const HELLO_BYTES: [usize; 2] = [4 * 1024, 8 * 1024];
+
+//
+// One captured frame: time in microseconds, link type of its interface
+//
+struct Frame {
+    time: i64,
+    linktype: u32,
+    data: Vec<u8>,
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+//
+// Fill buffer, or false at the end of the input; a capture ending part way
+// through buffer is truncated (UnexpectedEof), as one ending inside a frame is
+//
+fn read_exact_or_eof(input: &mut impl Read, buffer: &mut [u8]) -> Result<bool, std::io::Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(length) => filled += length,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+//
+// Reads frames from a classic pcap or a pcapng file
+//
+enum Capture {
+    Pcap {
+        input: BufReader<File>,
+        big_endian: bool,
+        nanoseconds: bool,
+        linktype: u32,
+    },
+    PcapNg {
+        input: BufReader<File>,
+        big_endian: bool,
+        // link type and timestamp units per second of each interface
+        interfaces: Vec<(u32, u64)>,
+    },
+}
+
+impl Capture {
+    fn open(input_spec: &str) -> Result<Capture, std::io::Error> {
+        let mut input = BufReader::new(File::open(input_spec)?);
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        match magic {
+            [0x0a, 0x0d, 0x0d, 0x0a] => {
+                let mut capture = Capture::PcapNg {
+                    input,
+                    big_endian: false,
+                    interfaces: Vec::new(),
+                };
+                capture.section_header()?;
+                Ok(capture)
+            }
+            _ => {
+                let (big_endian, nanoseconds) = match magic {
+                    [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+                    [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+                    [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+                    [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+                    _ => return Err(invalid("not a pcap or pcapng file")),
+                };
+                let mut header = [0u8; 20];
+                input.read_exact(&mut header)?;
+                let linktype = word(big_endian, &header[16..20]) & 0x0fffffff;
+                Ok(Capture::Pcap {
+                    input,
+                    big_endian,
+                    nanoseconds,
+                    linktype,
+                })
+            }
+        }
+    }
+
+    //
+    // Rest of a pcapng section header block, after its block type
+    //
+    fn section_header(&mut self) -> Result<(), std::io::Error> {
+        let Capture::PcapNg {
+            input,
+            big_endian,
+            interfaces,
+        } = self
+        else {
+            return Ok(());
+        };
+        let mut header = [0u8; 8];
+        input.read_exact(&mut header)?;
+        *big_endian = match header[4..8] {
+            [0x1a, 0x2b, 0x3c, 0x4d] => true,
+            [0x4d, 0x3c, 0x2b, 0x1a] => false,
+            _ => return Err(invalid("bad pcapng byte-order magic")),
+        };
+        let length = word(*big_endian, &header[0..4]) as usize;
+        if !(12..=MAX_FRAME).contains(&length) {
+            return Err(invalid("bad pcapng section header length"));
+        }
+        let mut rest = vec![0u8; length - 12];
+        input.read_exact(&mut rest)?;
+        interfaces.clear();
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Frame>, std::io::Error> {
+        match self {
+            Capture::Pcap {
+                input,
+                big_endian,
+                nanoseconds,
+                linktype,
+            } => {
+                let mut header = [0u8; 16];
+                if !read_exact_or_eof(input, &mut header)? {
+                    return Ok(None);
+                }
+                let secs = word(*big_endian, &header[0..4]) as i64;
+                let fraction = word(*big_endian, &header[4..8]) as i64;
+                let captured = word(*big_endian, &header[8..12]) as usize;
+                if captured > MAX_FRAME {
+                    return Err(invalid("pcap record longer than any frame"));
+                }
+                let mut data = vec![0u8; captured];
+                input.read_exact(&mut data)?;
+                let time = if *nanoseconds {
+                    secs * 1_000_000 + fraction / 1000
+                } else {
+                    secs * 1_000_000 + fraction
+                };
+                Ok(Some(Frame {
+                    time,
+                    linktype: *linktype,
+                    data,
+                }))
+            }
+            Capture::PcapNg { .. } => self.next_block(),
+        }
+    }
+
+    fn next_block(&mut self) -> Result<Option<Frame>, std::io::Error> {
+        loop {
+            let Capture::PcapNg {
+                input,
+                big_endian,
+                interfaces,
+            } = self
+            else {
+                return Ok(None);
+            };
+            let mut block_type = [0u8; 4];
+            if !read_exact_or_eof(input, &mut block_type)? {
+                return Ok(None);
+            }
+            if block_type == [0x0a, 0x0d, 0x0d, 0x0a] {
+                self.section_header()?;
+                continue;
+            }
+            let big_endian = *big_endian;
+            let mut length = [0u8; 4];
+            input.read_exact(&mut length)?;
+            let length = word(big_endian, &length) as usize;
+            if !(12..=MAX_FRAME + 64 * 1024).contains(&length) {
+                return Err(invalid("bad pcapng block length"));
+            }
+            let mut body = vec![0u8; length - 8];
+            input.read_exact(&mut body)?;
+            // the trailing copy of the block length
+            body.truncate(length - 12);
+
+            match word(big_endian, &block_type) {
+                // interface description
+                1 if body.len() >= 8 => {
+                    let linktype = half(big_endian, &body[0..2]) as u32;
+                    let resolution = interface_resolution(big_endian, &body[8..]);
+                    interfaces.push((linktype, resolution));
+                }
+                // enhanced packet, obsolete packet
+                kind @ (6 | 2) if body.len() >= 20 => {
+                    let interface = if kind == 6 {
+                        word(big_endian, &body[0..4])
+                    } else {
+                        half(big_endian, &body[0..2]) as u32
+                    } as usize;
+                    let Some((linktype, resolution)) = interfaces.get(interface).copied() else {
+                        continue;
+                    };
+                    let stamp = (word(big_endian, &body[4..8]) as u64) << 32
+                        | word(big_endian, &body[8..12]) as u64;
+                    let captured = word(big_endian, &body[12..16]) as usize;
+                    let Some(data) = body.get(20..20 + captured) else {
+                        return Err(invalid("truncated pcapng packet block"));
+                    };
+                    return Ok(Some(Frame {
+                        time: (stamp as u128 * 1_000_000 / resolution as u128) as i64,
+                        linktype,
+                        data: data.to_vec(),
+                    }));
+                }
+                // simple packet: no timestamp, first interface
+                3 if body.len() >= 4 => {
+                    let Some((linktype, _)) = interfaces.first().copied() else {
+                        continue;
+                    };
+                    let original = word(big_endian, &body[0..4]) as usize;
+                    let end = body.len().min(4 + original);
+                    return Ok(Some(Frame {
+                        time: 0,
+                        linktype,
+                        data: body[4..end].to_vec(),
+                    }));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn word(big_endian: bool, bytes: &[u8]) -> u32 {
+    let bytes: [u8; 4] = bytes[..4].try_into().unwrap_or_default();
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+fn half(big_endian: bool, bytes: &[u8]) -> u16 {
+    let bytes: [u8; 2] = bytes[..2].try_into().unwrap_or_default();
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+//
+// Timestamp units per second from the if_tsresol option; microseconds
+// when absent
+//
+fn interface_resolution(big_endian: bool, mut options: &[u8]) -> u64 {
+    while options.len() >= 4 {
+        let code = half(big_endian, &options[0..2]);
+        let length = half(big_endian, &options[2..4]) as usize;
+        let padded = (length + 3) & !3;
+        if code == 0 {
+            break;
+        }
+        if code == 9 && length >= 1 && options.len() > 4 {
+            let resolution = options[4];
+            let exponent = (resolution & 0x7f) as u32;
+            let base: u64 = if resolution & 0x80 != 0 { 2 } else { 10 };
+            return base.checked_pow(exponent).unwrap_or(1_000_000).max(1);
+        }
+        options = options.get(4 + padded..).unwrap_or(&[]);
+    }
+    1_000_000
+}
+
+//
+// Shannon entropy of a payload scaled to 0..255, as YAF's entropy plugin
+// reports it
+//
+fn entropy(payload: &[u8]) -> u8 {
+    if payload.is_empty() {
+        return 0;
+    }
+    let mut counts = [0u32; 256];
+    for b in payload.iter() {
+        counts[*b as usize] += 1;
+    }
+    let total = payload.len() as f64;
+    let bits: f64 = counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / total;
+            -p * p.log2()
+        })
+        .sum();
+    (bits * 255.0 / 8.0).round().min(255.0) as u8
+}
+
+type FlowKey = (u8, IpAddr, u16, IpAddr, u16, u16);
+
+//
+// Running statistics of one direction
+//
+#[derive(Default)]
+struct Stats {
+    last_time: i64,
+    // interarrival sum and sum of squares (ms)
+    iat_sum: f64,
+    iat_squares: f64,
+    // non-empty payload sum and sum of squares
+    payload_sum: f64,
+    payload_squares: f64,
+    fin: bool,
+    // start of the tcp stream while it is a TLS handshake, and the next
+    // sequence number expected
+    hello: Vec<u8>,
+    next_seq: u32,
+}
+
+struct Assembly {
+    record: FlowRecord,
+    stats: [Stats; 2],
+    spd_count: u8,
+    answered: bool,
+}
+
+impl Assembly {
+    fn new(packet: &Packet, time: i64) -> Assembly {
+        let mut record = FlowRecord::new(time);
+        record.proto = packet.proto;
+        record.saddr = packet.saddr;
+        record.daddr = packet.daddr;
+        record.sport = packet.sport;
+        record.dport = packet.dport;
+        record.smac = packet.smac;
+        record.dmac = packet.dmac;
+        record.forward.vlan = packet.vlan;
+        record.reverse.vlan = packet.vlan;
+        Assembly {
+            record,
+            stats: [Stats::default(), Stats::default()],
+            spd_count: 0,
+            answered: false,
+        }
+    }
+
+    fn add(&mut self, packet: &Packet, time: i64, reverse: bool) {
+        let index = reverse as usize;
+        let first = if reverse {
+            self.record.reverse.pkts == 0
+        } else {
+            self.record.forward.pkts == 0
+        };
+        if reverse && !self.answered {
+            self.answered = true;
+            self.record.rtt = ((time - self.record.stime).max(0) / 1000) as u32;
+        }
+        self.record.etime = self.record.etime.max(time);
+
+        let stats = &mut self.stats[index];
+        let direction: &mut Direction = if reverse {
+            &mut self.record.reverse
+        } else {
+            &mut self.record.forward
+        };
+        if !first {
+            let iat = (time - stats.last_time).max(0) as f64 / 1000.0;
+            stats.iat_sum += iat;
+            stats.iat_squares += iat * iat;
+        }
+        stats.last_time = time;
+
+        direction.pkts += 1;
+        direction.bytes += packet.ip_length as u64;
+        direction.data_bytes += packet.payload_length as u64;
+        if first {
+            direction.initial_flags = packet.flags;
+            direction.isn = packet.seq;
+        } else {
+            direction.union_flags |= packet.flags;
+        }
+        if packet.flags & TCP_URG != 0 {
+            direction.urg += 1;
+        }
+        if packet.flags & TCP_FIN != 0 {
+            stats.fin = true;
+        }
+
+        let size = packet.payload_length;
+        if size > 0 {
+            if size < SMALL_PAYLOAD {
+                direction.small += 1;
+            }
+            if size > LARGE_PAYLOAD {
+                direction.large += 1;
+            }
+            if direction.nonempty == 0 {
+                direction.first_nonempty = size.min(u16::MAX as u32) as u16;
+                direction.entropy = entropy(packet.payload);
+                if packet.proto == 6 && !reverse {
+                    self.record.http = http::request(packet.payload).map(Box::new);
+                } else if let (6, Some(h)) = (packet.proto, self.record.http.as_mut()) {
+                    h.status = http::status(packet.payload);
+                }
+            }
+            direction.nonempty += 1;
+            direction.max_size = direction.max_size.max(size.min(u16::MAX as u32) as u16);
+            stats.payload_sum += size as f64;
+            stats.payload_squares += (size as f64) * (size as f64);
+
+            if packet.proto == 6 && stats.hello.len() < HELLO_BYTES[index] {
+                let start =
+                    direction.nonempty == 1 && packet.payload.first() == Some(&TLS_HANDSHAKE);
+                if start || (!stats.hello.is_empty() && packet.seq == stats.next_seq) {
+                    let room = HELLO_BYTES[index] - stats.hello.len();
+                    stats
+                        .hello
+                        .extend_from_slice(&packet.payload[..packet.payload.len().min(room)]);
+                    stats.next_seq = packet.seq.wrapping_add(size);
+                }
+            }
+
+            if self.spd_count < 8 {
+                if reverse {
+                    self.record.spd |= 0x80 >> self.spd_count;
+                }
+                self.spd_count += 1;
+            }
+        }
+    }
+
+    fn closed(&self) -> bool {
+        let flags = self.record.forward.union_flags
+            | self.record.forward.initial_flags
+            | self.record.reverse.union_flags
+            | self.record.reverse.initial_flags;
+        flags & TCP_RST != 0 || (self.stats[0].fin && self.stats[1].fin)
+    }
+
+    fn finish(mut self, reason: &'static str) -> FlowRecord {
+        for (stats, direction) in [
+            (&self.stats[0], &mut self.record.forward),
+            (&self.stats[1], &mut self.record.reverse),
+        ] {
+            let (mean, stdev) = moments(
+                stats.iat_sum,
+                stats.iat_squares,
+                direction.pkts.saturating_sub(1),
+            );
+            direction.iat = mean as u64;
+            direction.iat_stdev = stdev as u64;
+            let (_, stdev) = moments(
+                stats.payload_sum,
+                stats.payload_squares,
+                direction.nonempty as u64,
+            );
+            direction.payload_stdev = stdev.min(u16::MAX as f64) as u16;
+        }
+        self.record.tls = tls::decode(&self.stats[0].hello, &self.stats[1].hello).map(Box::new);
+        self.record.reason = reason;
+        self.record
+    }
+}
+
+fn moments(sum: f64, squares: f64, count: u64) -> (f64, f64) {
+    if count == 0 {
+        return (0.0, 0.0);
+    }
+    let n = count as f64;
+    let mean = sum / n;
+    let variance = (squares / n - mean * mean).max(0.0);
+    (mean, variance.sqrt())
+}
+
+//
+// Biflow table keyed by the forward direction's key
+//
+struct Assembler {
+    idle_timeout: i64,
+    active_timeout: i64,
+    flows: HashMap<FlowKey, Assembly>,
+    done: Vec<FlowRecord>,
+    last_sweep: i64,
+}
+
+impl Assembler {
+    fn new(idle_timeout: u64, active_timeout: u64) -> Assembler {
+        Assembler {
+            idle_timeout: idle_timeout as i64 * 1_000_000,
+            active_timeout: active_timeout as i64 * 1_000_000,
+            flows: HashMap::new(),
+            done: Vec::new(),
+            last_sweep: 0,
+        }
+    }
+
+    fn add(&mut self, packet: &Packet, time: i64) {
+        let forward: FlowKey = (
+            packet.proto,
+            packet.saddr,
+            packet.sport,
+            packet.daddr,
+            packet.dport,
+            packet.vlan,
+        );
+        let reverse_key: FlowKey = (
+            packet.proto,
+            packet.daddr,
+            packet.dport,
+            packet.saddr,
+            packet.sport,
+            packet.vlan,
+        );
+        let (key, reverse) = if self.flows.contains_key(&forward) {
+            (forward, false)
+        } else if self.flows.contains_key(&reverse_key) {
+            (reverse_key, true)
+        } else {
+            (forward, false)
+        };
+
+        // end the flow first when this packet would extend it past a timeout
+        if let Some(flow) = self.flows.get(&key) {
+            if time - flow.record.etime > self.idle_timeout {
+                let flow = self.flows.remove(&key).unwrap();
+                self.done.push(flow.finish("idle"));
+            } else if time - flow.record.stime > self.active_timeout {
+                let flow = self.flows.remove(&key).unwrap();
+                self.done.push(flow.finish("active"));
+            }
+        }
+        let reverse = reverse && self.flows.contains_key(&key);
+        let flow = self
+            .flows
+            .entry(key)
+            .or_insert_with(|| Assembly::new(packet, time));
+        flow.add(packet, time, reverse);
+        if packet.proto == 6 && flow.closed() {
+            let flow = self.flows.remove(&key).unwrap();
+            self.done.push(flow.finish("."));
+        }
+
+        if time - self.last_sweep > 1_000_000 {
+            self.sweep(time);
+            self.last_sweep = time;
+        }
+    }
+
+    fn sweep(&mut self, time: i64) {
+        let idle_timeout = self.idle_timeout;
+        let expired: Vec<FlowKey> = self
+            .flows
+            .iter()
+            .filter(|(_, flow)| time - flow.record.etime > idle_timeout)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired.iter() {
+            if let Some(flow) = self.flows.remove(key) {
+                self.done.push(flow.finish("idle"));
+            }
+        }
+    }
+
+    fn drain(&mut self) -> Vec<FlowRecord> {
+        std::mem::take(&mut self.done)
+    }
+
+    fn flush(&mut self) -> Vec<FlowRecord> {
+        let mut records = self.drain();
+        records.extend(self.flows.drain().map(|(_, flow)| flow.finish("eof")));
+        records
+    }
+}
+
+//
+// Assemble the flows of one capture into a parquet file; output_spec is
+// either the file to write or a directory for gnat.<capture name>.parquet
+//
+pub fn import_file(
+    observation_tag: &str,
+    input_spec: &str,
+    output_spec: &str,
+    idle_timeout: u64,
+    active_timeout: u64,
+) -> Result<u64, GnatError> {
+    let parquet_spec = if Path::new(output_spec).is_dir() {
+        let stem = Path::new(input_spec)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        format!("{}/gnat.{}.parquet", output_spec, stem)
+    } else {
+        output_spec.to_string()
+    };
+
+    let mut capture = Capture::open(input_spec)?;
+    let mut writer = FlowWriter::new("import", observation_tag)?;
+    let mut assembler = Assembler::new(idle_timeout, active_timeout);
+    let mut frames: u64 = 0;
+    let mut skipped: u64 = 0;
+    let mut fragments: u64 = 0;
+    while let Some(frame) = capture.next()? {
+        frames += 1;
+        let Some(packet) = packet::decode(frame.linktype, &frame.data) else {
+            skipped += 1;
+            continue;
+        };
+        if packet.fragment {
+            fragments += 1;
+            continue;
+        }
+        assembler.add(&packet, frame.time);
+        if assembler.done.len() >= CHUNK_SIZE {
+            writer.append(&assembler.drain())?;
+        }
+    }
+    writer.append(&assembler.flush())?;
+
+    if fragments > 0 {
+        warn!(
+            "import: skipped {} non-first IP fragments in {}",
+            fragments, input_spec
+        );
+    }
+    info!(
+        "import: {} [{} frames, {} non-IP, {} flows]",
+        input_spec,
+        frames,
+        skipped,
+        writer.count()
+    );
+    writer.finish(&parquet_spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    use std::fs;
+    use std::io::ErrorKind;
+
+    fn capture_file(name: &str, bytes: &[u8]) -> String {
+        let path = format!("{}/capture", test_dir(name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn error_kind(result: Result<Option<Frame>, std::io::Error>) -> Option<ErrorKind> {
+        result.err().map(|e| e.kind())
+    }
+
+    // little-endian, microseconds, ethernet
+    fn pcap_header() -> Vec<u8> {
+        let mut bytes = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        bytes.extend_from_slice(&[0; 8]); // thiszone, sigfigs
+        bytes.extend_from_slice(&65535u32.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes
+    }
+
+    fn pcap_record(bytes: &mut Vec<u8>, secs: u32, usecs: u32, captured: u32, data: &[u8]) {
+        bytes.extend_from_slice(&secs.to_le_bytes());
+        bytes.extend_from_slice(&usecs.to_le_bytes());
+        bytes.extend_from_slice(&captured.to_le_bytes());
+        bytes.extend_from_slice(&captured.to_le_bytes());
+        bytes.extend_from_slice(data);
+    }
+
+    fn pcapng_block(bytes: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+        let padded = (body.len() + 3) & !3;
+        let length = (12 + padded) as u32;
+        bytes.extend_from_slice(&block_type.to_le_bytes());
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes.resize(bytes.len() + padded - body.len(), 0);
+        bytes.extend_from_slice(&length.to_le_bytes());
+    }
+
+    // section header, then an ethernet interface with nanosecond timestamps
+    fn pcapng_header() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut section = vec![0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0];
+        section.extend_from_slice(&[0xff; 8]); // section length
+        pcapng_block(&mut bytes, 0x0a0d0d0a, &section);
+        let mut interface = vec![1, 0, 0, 0];
+        interface.extend_from_slice(&65535u32.to_le_bytes());
+        interface.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0]); // if_tsresol 10^-9
+        interface.extend_from_slice(&[0; 4]); // opt_endofopt
+        pcapng_block(&mut bytes, 1, &interface);
+        bytes
+    }
+
+    fn enhanced_packet(stamp: u64, captured: u32, data: &[u8]) -> Vec<u8> {
+        let mut body = 0u32.to_le_bytes().to_vec();
+        body.extend_from_slice(&((stamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(stamp as u32).to_le_bytes());
+        body.extend_from_slice(&captured.to_le_bytes());
+        body.extend_from_slice(&captured.to_le_bytes());
+        body.extend_from_slice(data);
+        body
+    }
+
+    #[test]
+    fn pcap_records() {
+        let mut bytes = pcap_header();
+        pcap_record(&mut bytes, 1_700_000_000, 250_000, 4, &[1, 2, 3, 4]);
+        pcap_record(&mut bytes, 1_700_000_001, 0, 2, &[5, 6]);
+        let spec = capture_file("records.pcap", &bytes);
+        let mut capture = Capture::open(&spec).unwrap();
+
+        let frame = capture.next().unwrap().unwrap();
+        assert_eq!(frame.time, 1_700_000_000_250_000);
+        assert_eq!(frame.linktype, packet::LINKTYPE_ETHERNET);
+        assert_eq!(frame.data, [1, 2, 3, 4]);
+        let frame = capture.next().unwrap().unwrap();
+        assert_eq!(frame.time, 1_700_000_001_000_000);
+        assert_eq!(frame.data, [5, 6]);
+        assert!(capture.next().unwrap().is_none());
+        let _ = fs::remove_file(&spec);
+    }
+
+    #[test]
+    fn pcap_truncated() {
+        // inside the data of a record
+        let mut bytes = pcap_header();
+        pcap_record(&mut bytes, 1_700_000_000, 0, 4, &[1, 2, 3, 4]);
+        pcap_record(&mut bytes, 1_700_000_001, 0, 60, &[5, 6]);
+        let spec = capture_file("truncated-data.pcap", &bytes);
+        let mut capture = Capture::open(&spec).unwrap();
+        assert!(capture.next().unwrap().is_some());
+        assert_eq!(error_kind(capture.next()), Some(ErrorKind::UnexpectedEof));
+        let _ = fs::remove_file(&spec);
+
+        // inside the header of a record
+        let mut bytes = pcap_header();
+        bytes.extend_from_slice(&[0; 10]);
+        let spec = capture_file("truncated-header.pcap", &bytes);
+        let mut capture = Capture::open(&spec).unwrap();
+        assert_eq!(error_kind(capture.next()), Some(ErrorKind::UnexpectedEof));
+        let _ = fs::remove_file(&spec);
+
+        // a length longer than any frame
+        let mut bytes = pcap_header();
+        pcap_record(&mut bytes, 1_700_000_000, 0, u32::MAX, &[]);
+        let spec = capture_file("corrupt.pcap", &bytes);
+        let mut capture = Capture::open(&spec).unwrap();
+        assert_eq!(error_kind(capture.next()), Some(ErrorKind::InvalidData));
+        let _ = fs::remove_file(&spec);
+    }
+
+    #[test]
+    fn pcapng_blocks() {
+        let mut bytes = pcapng_header();
+        let stamp = 1_700_000_000_123_456_789;
+        pcapng_block(&mut bytes, 6, &enhanced_packet(stamp, 5, &[1, 2, 3, 4, 5]));
+        let spec = capture_file("blocks.pcapng", &bytes);
+        let mut capture = Capture::open(&spec).unwrap();
+
+        let frame = capture.next().unwrap().unwrap();
+        assert_eq!(frame.time, 1_700_000_000_123_456);
+        assert_eq!(frame.linktype, packet::LINKTYPE_ETHERNET);
+        assert_eq!(frame.data, [1, 2, 3, 4, 5]);
+        assert!(capture.next().unwrap().is_none());
+        let _ = fs::remove_file(&spec);
+    }
+
+    #[test]
+    fn pcapng_truncated() {
+        // a captured length past the end of its block
+        let mut bytes = pcapng_header();
+        pcapng_block(&mut bytes, 6, &enhanced_packet(0, 60, &[1, 2, 3, 4]));
+        let spec = capture_file("truncated-block.pcapng", &bytes);
+        let mut capture = Capture::open(&spec).unwrap();
+        assert_eq!(error_kind(capture.next()), Some(ErrorKind::InvalidData));
+        let _ = fs::remove_file(&spec);
+
+        // inside the body of a block
+        let mut bytes = pcapng_header();
+        pcapng_block(&mut bytes, 6, &enhanced_packet(0, 4, &[1, 2, 3, 4]));
+        bytes.truncate(bytes.len() - 6);
+        let spec = capture_file("truncated-body.pcapng", &bytes);
+        let mut capture = Capture::open(&spec).unwrap();
+        assert_eq!(error_kind(capture.next()), Some(ErrorKind::UnexpectedEof));
+        let _ = fs::remove_file(&spec);
+
+        // inside the type of a block
+        let mut bytes = pcapng_header();
+        bytes.extend_from_slice(&[6, 0]);
+        let spec = capture_file("truncated-type.pcapng", &bytes);
+        let mut capture = Capture::open(&spec).unwrap();
+        assert_eq!(error_kind(capture.next()), Some(ErrorKind::UnexpectedEof));
+        let _ = fs::remove_file(&spec);
+    }
+}
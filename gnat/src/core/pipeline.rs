@@ -0,0 +1,572 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Config-file driven pipeline (gnat_run)
+//
+// A TOML file lists the stages; a stage whose input is another stage's
+// output runs downstream of it:
+//
+//   [[stage]]
+//   name = "import"
+//   kind = "import"
+//   input = "/var/gnat/yaf"
+//   output = "/var/gnat/import"
+//   [stage.options]
+//   observation = "sensor1"
+//   polling = true
+//
+//   [[stage]]
+//   name = "batch"
+//   kind = "batch"
+//   input = "/var/gnat/import"
+//   output = "/var/gnat/batch"
+//   [stage.options]
+//   minutes = 5
+//
+// Each stage runs on a thread of gnat_run, calling the stage() that its
+// gnat_<kind> binary runs (see stages/) with options passed as
+// --<option> <value>; what a stage binary keeps for the life of its
+// process is kept per stage (see context.rs). Kinds without an
+// entrypoint run as child processes of their binaries: db from the
+// gnat_db crate, and collect, whose libfixbuf loop installs its own
+// signal handlers and never sees a shutdown request. With --control, the
+// stages are also managed over HTTP; see control.rs.
+//
+
+use crate::core::context;
+use crate::core::control::{self, ControlStage, Runner};
+use crate::core::error::GnatError;
+use crate::core::shutdown;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::{error, info};
+
+const STAGE_KINDS: [&str; 20] = [
+    "collect",
+    "import",
+    "batch",
+    "export",
+    "plugin",
+    "transform",
+    "tag",
+    "site",
+    "enrich",
+    "dga",
+    "sample",
+    "correlate",
+    "detect",
+    "beacon",
+    "scan",
+    "asset",
+    "stitch",
+    "kafka",
+    "report",
+    "db",
+];
+
+// as the main thread of a stage binary
+const STAGE_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+//
+// Stage function of a gnat_<kind> binary (stages::<kind>::stage), called
+// with its command line
+//
+pub type Entrypoint = fn(Vec<String>);
+
+//
+// Parser of a gnat_<kind> binary's command line (stages::<kind>::check),
+// run on a stage's options without starting it
+//
+pub type Check = fn(Vec<String>) -> Result<(), String>;
+
+#[derive(Debug, Deserialize)]
+pub struct StageConfig {
+    pub name: String,
+    pub kind: String,
+    pub input: Option<String>,
+    pub output: Option<String>,
+    #[serde(default)]
+    pub options: toml::Table,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PipelineConfig {
+    #[serde(rename = "stage")]
+    pub stages: Vec<StageConfig>,
+}
+
+impl StageConfig {
+    fn binary(&self) -> String {
+        format!("gnat_{}", self.kind)
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let mut argv = vec![self.binary()];
+        argv.extend(self.arguments());
+        argv
+    }
+
+    fn arguments(&self) -> Vec<String> {
+        let mut arguments = Vec::new();
+        if let Some(input) = &self.input {
+            arguments.push(String::from("--input"));
+            arguments.push(input.clone());
+        }
+        if let Some(output) = &self.output {
+            arguments.push(String::from("--output"));
+            arguments.push(output.clone());
+        }
+        for (option, value) in self.options.iter() {
+            arguments.push(format!("--{}", option.replace('_', "-")));
+            arguments.push(match value {
+                toml::Value::String(s) => s.clone(),
+                v => v.to_string(),
+            });
+        }
+        arguments
+    }
+}
+
+impl PipelineConfig {
+    pub fn load(config_spec: &String) -> Result<PipelineConfig, GnatError> {
+        let contents = fs::read_to_string(config_spec)?;
+        toml::from_str(&contents).map_err(|e| {
+            GnatError::Config(format!("parsing {} - {}", config_spec, e))
+        })
+    }
+
+    //
+    // Check the stage graph and return the stages in upstream-first order
+    //
+    pub fn validate(&self) -> Result<Vec<usize>, String> {
+        let mut names = HashSet::new();
+        let mut producers: HashMap<&String, usize> = HashMap::new();
+        let mut consumers: HashMap<&String, usize> = HashMap::new();
+        for (index, stage) in self.stages.iter().enumerate() {
+            if !names.insert(&stage.name) {
+                return Err(format!("duplicate stage name {}", stage.name));
+            }
+            if !STAGE_KINDS.contains(&stage.kind.as_str()) {
+                return Err(format!(
+                    "stage {}: invalid kind {} [{}]",
+                    stage.name,
+                    stage.kind,
+                    STAGE_KINDS.join("|")
+                ));
+            }
+            if let Some(output) = &stage.output {
+                if let Some(other) = producers.insert(output, index) {
+                    return Err(format!(
+                        "stages {} and {} share output {}",
+                        self.stages[other].name, stage.name, output
+                    ));
+                }
+            }
+            // stages claim the files of their input, so each file would
+            // reach only one of them
+            if let Some(input) = &stage.input {
+                if let Some(other) = consumers.insert(input, index) {
+                    return Err(format!(
+                        "stages {} and {} share input {}",
+                        self.stages[other].name, stage.name, input
+                    ));
+                }
+            }
+        }
+
+        // Kahn's algorithm over the output -> input edges
+        let mut upstream: Vec<Option<usize>> = self
+            .stages
+            .iter()
+            .map(|stage| stage.input.as_ref().and_then(|i| producers.get(i).copied()))
+            .collect();
+        let mut order = Vec::new();
+        while order.len() < self.stages.len() {
+            let Some(ready) = (0..self.stages.len())
+                .find(|i| !order.contains(i) && upstream[*i].is_none())
+            else {
+                return Err(String::from("stage graph contains a cycle"));
+            };
+            order.push(ready);
+            for edge in upstream.iter_mut() {
+                if *edge == Some(ready) {
+                    *edge = None;
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    //
+    // Parse the command line of each stage with an entrypoint, so a
+    // mistyped option fails the pipeline before any stage starts
+    //
+    pub fn check(&self, entrypoints: &[(&str, Entrypoint, Check)]) -> Result<(), String> {
+        for stage in self.stages.iter() {
+            if let Some((_, _, check)) = entrypoints.iter().find(|(kind, _, _)| *kind == stage.kind) {
+                check(stage.argv()).map_err(|e| format!("stage {}: {}", stage.name, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn binary_path(binary: &String) -> PathBuf {
+    // prefer the stage binaries installed next to gnat_run
+    if let Ok(exe) = std::env::current_exe() {
+        if let Some(dir) = exe.parent() {
+            let candidate = dir.join(binary);
+            if candidate.is_file() {
+                return candidate;
+            }
+        }
+    }
+    PathBuf::from(binary)
+}
+
+//
+// A started stage: a thread running its entrypoint, or a child process
+//
+enum Started {
+    Thread(JoinHandle<()>),
+    Process(Child),
+}
+
+// clears the running flag of a stage thread however the thread ends
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Started {
+    fn exited(&mut self) -> Result<bool, std::io::Error> {
+        match self {
+            Started::Thread(handle) => Ok(handle.is_finished()),
+            Started::Process(child) => Ok(child.try_wait()?.is_some()),
+        }
+    }
+}
+
+fn start(
+    stage: &StageConfig,
+    entrypoints: &[(&str, Entrypoint, Check)],
+) -> Result<(Started, Runner), std::io::Error> {
+    let arguments = stage.arguments();
+    info!(
+        "pipeline: starting [{}] {} {}",
+        stage.name,
+        stage.binary(),
+        arguments.join(" ")
+    );
+    let Some((_, entrypoint, _)) = entrypoints.iter().find(|(kind, _, _)| *kind == stage.kind) else {
+        let child = Command::new(binary_path(&stage.binary())).args(&arguments).spawn()?;
+        let pid = child.id();
+        return Ok((Started::Process(child), Runner::Process { pid }));
+    };
+    let entrypoint = *entrypoint;
+    let argv = stage.argv();
+    let id = context::next();
+    let running = Arc::new(AtomicBool::new(true));
+    let flag = Running(Arc::clone(&running));
+    let handle = thread::Builder::new()
+        .name(stage.name.clone())
+        .stack_size(STAGE_STACK_SIZE)
+        .spawn(move || {
+            context::enter(id);
+            let _running = flag;
+            entrypoint(argv)
+        })?;
+    Ok((Started::Thread(handle), Runner::Thread { id, running }))
+}
+
+//
+// Stop the stages and wait for them to finish the batches in flight
+//
+fn terminate(started: &mut Vec<(String, Started)>) {
+    shutdown::request();
+    for (_, stage) in started.iter() {
+        if let Started::Process(child) = stage {
+            unsafe {
+                libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+            }
+        }
+    }
+    for (name, stage) in started.drain(..) {
+        match stage {
+            Started::Thread(handle) => match handle.join() {
+                Ok(()) => info!("pipeline: stage [{}] stopped", name),
+                Err(payload) => match payload.downcast_ref::<shutdown::Exit>() {
+                    Some(shutdown::Exit(code)) => {
+                        info!("pipeline: stage [{}] exited {}", name, code)
+                    }
+                    None => error!("pipeline: stage [{}] panicked", name),
+                },
+            },
+            Started::Process(mut child) => match child.wait() {
+                Ok(status) => info!("pipeline: stage [{}] exited {}", name, status),
+                Err(e) => error!("waiting for stage {} - {:?}", name, e),
+            },
+        }
+    }
+}
+
+//
+// Run the pipeline of config_spec; kinds with an entrypoint run on threads,
+// the others as child processes of their binaries
+//
+pub fn run(
+    config_spec: &String,
+    control_spec: &String,
+    entrypoints: &[(&str, Entrypoint, Check)],
+) -> Result<(), std::io::Error> {
+    info!("config spec: {}", config_spec);
+    if !control_spec.is_empty() {
+        info!("control spec: {}", control_spec);
+    }
+
+    let config = PipelineConfig::load(config_spec)?;
+    let order = config.validate().map_err(GnatError::Config)?;
+    config.check(entrypoints).map_err(GnatError::Config)?;
+
+    // intermediate spool directories are owned by the pipeline
+    for stage in config.stages.iter() {
+        let processed = stage.options.get("processed").and_then(|p| p.as_str());
+        let deadletter = stage.options.get("deadletter").and_then(|d| d.as_str());
+        for dir in [stage.output.as_deref(), processed, deadletter]
+            .into_iter()
+            .flatten()
+        {
+            if !Path::new(dir).exists() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+    }
+
+    // start downstream stages first so nothing accumulates unconsumed
+    let mut started: Vec<(String, Started)> = Vec::new();
+    let mut control_stages = Vec::new();
+    for index in order.iter().rev() {
+        let stage = &config.stages[*index];
+        match start(stage, entrypoints) {
+            Ok((running, runner)) => {
+                started.push((stage.name.clone(), running));
+                control_stages.push(ControlStage {
+                    name: stage.name.clone(),
+                    kind: stage.kind.clone(),
+                    runner,
+                    input: stage.input.clone(),
+                    output: stage.output.clone(),
+                    options: stage.options.clone(),
+                });
+            }
+            Err(e) => {
+                terminate(&mut started);
+                return Err(std::io::Error::other(format!(
+                    "starting stage {} - {:?}",
+                    stage.name, e
+                )));
+            }
+        }
+    }
+
+    if !control_spec.is_empty() {
+        if let Err(e) = control::serve(control_spec, control_stages) {
+            terminate(&mut started);
+            return Err(std::io::Error::other(format!(
+                "control api on {} - {:?}",
+                control_spec, e
+            )));
+        }
+    }
+
+    //
+    // supervise: a stage stopping on its own stops the pipeline
+    //
+    let poll_interval = Duration::from_secs(1);
+    loop {
+        let mut exited = None;
+        for (name, stage) in started.iter_mut() {
+            if stage.exited()? {
+                exited = Some(name.clone());
+                break;
+            }
+        }
+        if let Some(name) = exited {
+            error!("stage [{}] stopped", name);
+            terminate(&mut started);
+            return Err(std::io::Error::other(format!("stage {} stopped", name)));
+        }
+        if !shutdown::sleep(poll_interval) {
+            info!("pipeline: shutting down");
+            terminate(&mut started);
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(stages: &str) -> PipelineConfig {
+        toml::from_str(stages).unwrap()
+    }
+
+    #[test]
+    fn validate_orders_upstream_first() {
+        let config = pipeline(
+            r#"
+            [[stage]]
+            name = "export"
+            kind = "export"
+            input = "/spool/batch"
+            [[stage]]
+            name = "import"
+            kind = "import"
+            input = "/spool/yaf"
+            output = "/spool/import"
+            [[stage]]
+            name = "batch"
+            kind = "batch"
+            input = "/spool/import"
+            output = "/spool/batch"
+            "#,
+        );
+        assert_eq!(config.validate().unwrap(), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn validate_rejects_shared_input() {
+        let config = pipeline(
+            r#"
+            [[stage]]
+            name = "tag"
+            kind = "tag"
+            input = "/spool/import"
+            output = "/spool/tag"
+            [[stage]]
+            name = "detect"
+            kind = "detect"
+            input = "/spool/import"
+            output = "/spool/detect"
+            "#,
+        );
+        assert_eq!(
+            config.validate().unwrap_err(),
+            "stages tag and detect share input /spool/import"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_shared_output_and_cycles() {
+        let shared = pipeline(
+            r#"
+            [[stage]]
+            name = "tag"
+            kind = "tag"
+            input = "/spool/a"
+            output = "/spool/c"
+            [[stage]]
+            name = "site"
+            kind = "site"
+            input = "/spool/b"
+            output = "/spool/c"
+            "#,
+        );
+        assert!(shared.validate().unwrap_err().contains("share output"));
+        let cycle = pipeline(
+            r#"
+            [[stage]]
+            name = "tag"
+            kind = "tag"
+            input = "/spool/a"
+            output = "/spool/b"
+            [[stage]]
+            name = "site"
+            kind = "site"
+            input = "/spool/b"
+            output = "/spool/a"
+            "#,
+        );
+        assert_eq!(cycle.validate().unwrap_err(), "stage graph contains a cycle");
+    }
+
+    #[test]
+    fn check_parses_stage_options() {
+        fn entrypoint(_: Vec<String>) {}
+        fn check(arguments: Vec<String>) -> Result<(), String> {
+            match arguments.iter().any(|a| a == "--indicator") {
+                true => Err(String::from("unexpected argument '--indicator' found")),
+                false => Ok(()),
+            }
+        }
+        let entrypoints: [(&str, Entrypoint, Check); 1] = [("tag", entrypoint, check)];
+        let config = pipeline(
+            r#"
+            [[stage]]
+            name = "tag"
+            kind = "tag"
+            input = "/spool/a"
+            output = "/spool/b"
+            [stage.options]
+            indicator = "/etc/gnat/indicators.csv"
+            [[stage]]
+            name = "db"
+            kind = "db"
+            input = "/spool/b"
+            "#,
+        );
+        assert_eq!(
+            config.check(&entrypoints).unwrap_err(),
+            "stage tag: unexpected argument '--indicator' found"
+        );
+    }
+
+    #[test]
+    fn stages_run_on_threads() {
+        fn entrypoint(arguments: Vec<String>) {
+            assert_ne!(context::current(), 0);
+            assert_eq!(
+                arguments,
+                ["gnat_tag", "--input", "/spool/a", "--output", "/spool/b", "--workers", "2"]
+            );
+        }
+        let config = pipeline(
+            r#"
+            [[stage]]
+            name = "tag"
+            kind = "tag"
+            input = "/spool/a"
+            output = "/spool/b"
+            [stage.options]
+            workers = 2
+            "#,
+        );
+        let entrypoints: [(&str, Entrypoint, Check); 1] = [("tag", entrypoint, |_| Ok(()))];
+        let (started, runner) = start(&config.stages[0], &entrypoints).unwrap();
+        let (Started::Thread(handle), Runner::Thread { id, running }) = (started, runner) else {
+            panic!("tag did not start on a thread");
+        };
+        handle.join().unwrap();
+        assert_ne!(id, context::current());
+        assert!(!running.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,265 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Dynamic plugin stages loaded through the C ABI in include/gnat_plugin.h
+//
+
+use crate::core::error::GnatError;
+use crate::core::spool::process_directory;
+
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+use tracing::{error, info};
+
+pub const PLUGIN_ABI_VERSION: i32 = 1;
+
+type AbiVersionFn = unsafe extern "C" fn() -> i32;
+type NameFn = unsafe extern "C" fn() -> *const c_char;
+type ProcessFn = unsafe extern "C" fn(*const c_char, *const c_char, *const c_char) -> i32;
+
+pub struct Plugin {
+    pub name: String,
+    pub path: String,
+    handle: *mut c_void,
+    process_fn: ProcessFn,
+}
+
+fn dl_error() -> String {
+    unsafe {
+        let error = libc::dlerror();
+        if error.is_null() {
+            return String::from("unknown error");
+        }
+        CStr::from_ptr(error).to_string_lossy().into_owned()
+    }
+}
+
+unsafe fn dl_symbol(handle: *mut c_void, symbol: &str) -> Result<*mut c_void, GnatError> {
+    let c_symbol = CString::new(symbol).expect("converting to c_string");
+    let address = libc::dlsym(handle, c_symbol.as_ptr());
+    if address.is_null() {
+        return Err(GnatError::Config(format!(
+            "missing symbol {} - {}",
+            symbol,
+            dl_error()
+        )));
+    }
+    Ok(address)
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Plugin, GnatError> {
+        let path_spec = String::from(path.to_string_lossy());
+        let c_path = CString::new(path_spec.as_str()).expect("converting to c_string");
+        unsafe {
+            let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(GnatError::Config(format!(
+                    "loading plugin {} - {}",
+                    path_spec,
+                    dl_error()
+                )));
+            }
+
+            let symbols = dl_symbol(handle, "gnat_plugin_abi_version").and_then(|version| {
+                Ok((
+                    version,
+                    dl_symbol(handle, "gnat_plugin_name")?,
+                    dl_symbol(handle, "gnat_plugin_process")?,
+                ))
+            });
+            let (version_sym, name_sym, process_sym) = match symbols {
+                Ok(s) => s,
+                Err(e) => {
+                    libc::dlclose(handle);
+                    return Err(GnatError::Config(format!("plugin {} - {}", path_spec, e)));
+                }
+            };
+
+            let version_fn: AbiVersionFn = std::mem::transmute(version_sym);
+            let version = version_fn();
+            if version != PLUGIN_ABI_VERSION {
+                libc::dlclose(handle);
+                return Err(GnatError::Config(format!(
+                    "plugin {} - unsupported ABI version {} (expected {})",
+                    path_spec, version, PLUGIN_ABI_VERSION
+                )));
+            }
+
+            let name_fn: NameFn = std::mem::transmute(name_sym);
+            let name_ptr = name_fn();
+            if name_ptr.is_null() {
+                libc::dlclose(handle);
+                return Err(GnatError::Config(format!("plugin {} - missing name", path_spec)));
+            }
+            let name = CStr::from_ptr(name_ptr).to_string_lossy().into_owned();
+
+            Ok(Plugin {
+                name,
+                path: path_spec,
+                handle,
+                process_fn: std::mem::transmute::<*mut c_void, ProcessFn>(process_sym),
+            })
+        }
+    }
+
+    pub fn process(&self, input_file: &str, output_file: &str, options: &str) -> i32 {
+        let c_input_file = CString::new(input_file).expect("converting to c_string");
+        let c_output_file = CString::new(output_file).expect("converting to c_string");
+        let c_options = CString::new(options).expect("converting to c_string");
+        unsafe {
+            (self.process_fn)(
+                c_input_file.as_ptr(),
+                c_output_file.as_ptr(),
+                c_options.as_ptr(),
+            )
+        }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+//
+// Load every *.so in the plugin directory; invalid plugins are reported and skipped
+//
+pub fn discover(plugin_spec: &String) -> Result<Vec<Plugin>, GnatError> {
+    let mut plugins = Vec::new();
+    for entry in fs::read_dir(plugin_spec)? {
+        let file = entry?;
+        let file_name = String::from(file.file_name().to_string_lossy());
+        if !file_name.ends_with(".so") {
+            continue;
+        }
+        match Plugin::load(&file.path()) {
+            Ok(p) => {
+                info!("plugin: loaded [{}] {}", p.name, p.path);
+                plugins.push(p);
+            }
+            Err(e) => error!("{}", e),
+        }
+    }
+    Ok(plugins)
+}
+
+pub fn plugin(
+    plugin_spec: &String,
+    name: &String,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+    options: &String,
+) -> Result<(), std::io::Error> {
+    info!("plugin spec: {}", plugin_spec);
+    info!("plugin name: {}", name);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("options: {}", options);
+    info!("polling: {}", polling);
+
+    let plugins = discover(plugin_spec)?;
+    let Some(plugin) = plugins.iter().find(|p| p.name == *name) else {
+        return Err(GnatError::Config(format!(
+            "plugin [{}] not found in {}",
+            name, plugin_spec
+        ))
+        .into());
+    };
+
+    process_directory(
+        "plugin",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        |src_path, tmp_path| match plugin.process(src_path, tmp_path, options) {
+            status if status >= 0 => Ok(()),
+            status => Err(GnatError::Upstream(format!(
+                "plugin {} exited with {}",
+                plugin.name, status
+            ))),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+    use std::process::Command;
+
+    // writes the options to the output file, and fails on "fail"
+    const ECHO: &str = r#"
+        #include <stdio.h>
+        #include <string.h>
+        #include "gnat_plugin.h"
+
+        int gnat_plugin_abi_version(void) { return VERSION; }
+        const char *gnat_plugin_name(void) { return "echo"; }
+        int gnat_plugin_process(const char *input_file, const char *output_file,
+                                const char *options) {
+            if (strcmp(options, "fail") == 0)
+                return -2;
+            FILE *output = fopen(output_file, "w");
+            if (output == NULL)
+                return -1;
+            fprintf(output, "%s %s", input_file, options);
+            fclose(output);
+            return 0;
+        }
+    "#;
+
+    // build ECHO as lib_name with the given ABI version
+    fn build(dir: &str, lib_name: &str, version: i32) {
+        let source_spec = format!("{}/echo.c", dir);
+        fs::write(&source_spec, ECHO).unwrap();
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC"])
+            .arg(format!("-I{}/include", env!("CARGO_MANIFEST_DIR")))
+            .arg(format!("-DVERSION={}", version))
+            .args(["-o", &format!("{}/{}", dir, lib_name), &source_spec])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::remove_file(&source_spec).unwrap();
+    }
+
+    #[test]
+    fn discover_loads_valid_plugins() {
+        let dir = test_dir("plugin-discover");
+        build(&dir, "echo.so", PLUGIN_ABI_VERSION);
+        build(&dir, "future.so", PLUGIN_ABI_VERSION + 1);
+        fs::write(format!("{}/broken.so", dir), b"not a library").unwrap();
+        fs::write(format!("{}/README", dir), b"").unwrap();
+
+        // the other ABI version and the broken library are skipped
+        let plugins = discover(&dir).unwrap();
+        assert_eq!(plugins.len(), 1);
+        assert_eq!(plugins[0].name, "echo");
+        assert!(Plugin::load(Path::new(&format!("{}/future.so", dir))).is_err());
+
+        let output_spec = format!("{}/out.txt", dir);
+        assert_eq!(plugins[0].process("in.parquet", &output_spec, "level=2"), 0);
+        let written = fs::read_to_string(&output_spec).unwrap();
+        assert_eq!(written, "in.parquet level=2");
+        assert_eq!(plugins[0].process("in.parquet", &output_spec, "fail"), -2);
+        drop(plugins);
+        assert!(discover(&format!("{}/missing", dir)).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
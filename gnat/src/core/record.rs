@@ -0,0 +1,437 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Flow records built in Rust (NetFlow/sFlow collection, pcap import) and
+// their parquet writer. Columns follow FLOW_TABLE and are formatted as the
+// IPFIX exporter in export_parquet.c formats them. Addresses are not looked
+// up in the MaxMind databases: geo columns are "private" for private
+// addresses and "unk" otherwise. TLS fingerprints are hashed with DuckDB's
+// md5() and sha256(). dga_score is the built-in model's score of the SNI,
+// else of the HTTP Host.
+//
+
+use crate::core::error::GnatError;
+use crate::core::http::Http;
+use crate::core::schema;
+use crate::core::scratch::{self, PooledConnection};
+use crate::core::tls::Handshake;
+use crate::model::dga;
+
+use std::ffi::CStr;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+use duckdb::types::{TimeUnit, Value};
+use duckdb::params;
+
+//
+// Counters of one direction; the forward direction is the flow initiator
+//
+#[derive(Clone, Default)]
+pub struct Direction {
+    pub pkts: u64,
+    pub bytes: u64,
+    // payload bytes, for pcr
+    pub data_bytes: u64,
+    pub initial_flags: u8,
+    pub union_flags: u8,
+    pub isn: u32,
+    pub vlan: u16,
+    pub entropy: u8,
+    // interarrival time mean and standard deviation (ms)
+    pub iat: u64,
+    pub iat_stdev: u64,
+    pub urg: u32,
+    pub small: u32,
+    pub large: u32,
+    pub nonempty: u32,
+    pub first_nonempty: u16,
+    pub payload_stdev: u16,
+    pub max_size: u16,
+}
+
+#[derive(Clone)]
+pub struct FlowRecord {
+    // microseconds since the epoch
+    pub stime: i64,
+    pub etime: i64,
+    // delay of the first reverse packet (ms)
+    pub rtt: u32,
+    pub proto: u8,
+    pub saddr: IpAddr,
+    pub daddr: IpAddr,
+    pub sport: u16,
+    pub dport: u16,
+    pub forward: Direction,
+    pub reverse: Direction,
+    // directions of the first eight non-empty packets, bit set for reverse
+    pub spd: u8,
+    pub reason: &'static str,
+    pub smac: [u8; 6],
+    pub dmac: [u8; 6],
+    pub tls: Option<Box<Handshake>>,
+    pub http: Option<Box<Http>>,
+}
+
+impl FlowRecord {
+    pub fn new(time: i64) -> FlowRecord {
+        FlowRecord {
+            stime: time,
+            etime: time,
+            rtt: 0,
+            proto: 0,
+            saddr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            daddr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            sport: 0,
+            dport: 0,
+            forward: Direction::default(),
+            reverse: Direction::default(),
+            spd: 0,
+            reason: ".",
+            smac: [0; 6],
+            dmac: [0; 6],
+            tls: None,
+            http: None,
+        }
+    }
+}
+
+//
+// TCP flags in the "SsAaRrFfEeCcUuPp" layout of PrintTCPFlags()
+//
+pub fn tcp_flags(flags: u8, rflags: u8) -> String {
+    const LAYOUT: [(u8, char, char); 8] = [
+        (0x02, 'S', 's'),
+        (0x10, 'A', 'a'),
+        (0x04, 'R', 'r'),
+        (0x01, 'F', 'f'),
+        (0x40, 'E', 'e'),
+        (0x80, 'C', 'c'),
+        (0x20, 'U', 'u'),
+        (0x08, 'P', 'p'),
+    ];
+    let mut text = String::with_capacity(16);
+    for (bit, forward, reverse) in LAYOUT.iter() {
+        text.push(if flags & bit != 0 { *forward } else { '.' });
+        text.push(if rflags & bit != 0 { *reverse } else { '.' });
+    }
+    text
+}
+
+pub fn protocol_name(proto: u8) -> String {
+    let entry = unsafe { libc::getprotobynumber(proto as libc::c_int) };
+    if entry.is_null() {
+        return proto.to_string();
+    }
+    unsafe { CStr::from_ptr((*entry).p_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn mac(address: &[u8; 6]) -> String {
+    address
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+fn geo(address: &IpAddr) -> &'static str {
+    let private = match address {
+        IpAddr::V4(a) => a.is_private() || a.is_loopback() || a.is_link_local(),
+        IpAddr::V6(a) => a.is_loopback() || (a.segments()[0] & 0xfe00) == 0xfc00,
+    };
+    if private {
+        "private"
+    } else {
+        "unk"
+    }
+}
+
+//
+// Collects records into a flow table and writes it out as one parquet file
+//
+pub struct FlowWriter {
+    conn: PooledConnection,
+    observation: String,
+    count: u64,
+}
+
+impl FlowWriter {
+    pub fn new(stage: &str, observation: &str) -> Result<FlowWriter, GnatError> {
+        let conn = scratch::open_in_memory(stage)?;
+        conn.execute_batch(&format!("{};", schema::FLOW_TABLE))?;
+        Ok(FlowWriter {
+            conn,
+            observation: observation.to_string(),
+            count: 0,
+        })
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn digest(&self, function: &str, text: &str) -> Result<String, duckdb::Error> {
+        self.conn
+            .prepare_cached(&format!("SELECT {}(?)", function))?
+            .query_row([text], |row| row.get(0))
+    }
+
+    pub fn append(&mut self, records: &[FlowRecord]) -> Result<(), GnatError> {
+        let mut appender = self.conn.appender("flow")?;
+        for r in records.iter() {
+            let (f, v) = (&r.forward, &r.reverse);
+            let pcr = if f.data_bytes + v.data_bytes > 0 {
+                (f.data_bytes as f64 - v.data_bytes as f64) / (f.data_bytes + v.data_bytes) as f64
+            } else {
+                0.0
+            };
+            let (sgeo, dgeo) = (geo(&r.saddr), geo(&r.daddr));
+            let tls = r.tls.as_deref();
+            let http = r.http.as_deref();
+            let client = tls.and_then(|t| t.client.as_ref());
+            let server = tls.and_then(|t| t.server.as_ref());
+            let certificate = tls.and_then(|t| t.certificate.as_ref());
+            let sha256 = |text: &str| self.digest("sha256", text);
+            let (ja3, ja4) = match client {
+                Some(c) => (Some(self.digest("md5", &c.ja3())), Some(c.ja4(sha256))),
+                None => (None, None),
+            };
+            let (ja3s, ja4s) = match server {
+                Some(s) => (Some(self.digest("md5", &s.ja3s())), Some(s.ja4s(sha256))),
+                None => (None, None),
+            };
+            let ja3 = ja3.transpose()?;
+            let ja3s = ja3s.transpose()?;
+            let ja4 = ja4.transpose()?;
+            let ja4s = ja4s.transpose()?;
+            let dga_score = client
+                .and_then(|c| c.sni.as_deref())
+                .or_else(|| http.and_then(|h| h.host.as_deref()))
+                .and_then(|name| dga::builtin().score(name));
+            appender
+                .append_row(params![
+                    self.observation,
+                    Value::Timestamp(TimeUnit::Microsecond, r.stime),
+                    Value::Timestamp(TimeUnit::Microsecond, r.etime),
+                    ((r.etime - r.stime).max(0) / 1000) as u32,
+                    r.rtt,
+                    pcr as f32,
+                    protocol_name(r.proto),
+                    r.saddr.to_string(),
+                    r.daddr.to_string(),
+                    r.sport,
+                    r.dport,
+                    tcp_flags(f.initial_flags, v.initial_flags),
+                    tcp_flags(f.union_flags, v.union_flags),
+                    f.isn,
+                    v.isn,
+                    f.vlan,
+                    v.vlan,
+                    f.pkts,
+                    v.pkts,
+                    f.bytes,
+                    v.bytes,
+                    f.entropy,
+                    v.entropy,
+                    f.iat,
+                    v.iat,
+                    f.iat_stdev,
+                    v.iat_stdev,
+                    f.urg,
+                    v.urg,
+                    f.small,
+                    v.small,
+                    f.large,
+                    v.large,
+                    f.nonempty,
+                    v.nonempty,
+                    f.first_nonempty,
+                    v.first_nonempty,
+                    f.payload_stdev,
+                    v.payload_stdev,
+                    f.max_size,
+                    v.max_size,
+                    format!("{:08b}|", r.spd),
+                    "unknown",
+                    r.reason,
+                    mac(&r.smac),
+                    mac(&r.dmac),
+                    sgeo,
+                    dgeo,
+                    0u32,
+                    0u32,
+                    sgeo,
+                    dgeo,
+                    sgeo,
+                    dgeo,
+                    None::<f64>,
+                    None::<f64>,
+                    None::<f64>,
+                    None::<f64>,
+                    client.and_then(|c| c.sni.clone()),
+                    certificate.and_then(|c| c.issuer.clone()),
+                    certificate.and_then(|c| c.subject.clone()),
+                    certificate
+                        .and_then(|c| c.not_after)
+                        .map(|t| Value::Timestamp(TimeUnit::Microsecond, t)),
+                    ja3,
+                    ja3s,
+                    ja4,
+                    ja4s,
+                    http.and_then(|h| h.method.clone()),
+                    http.and_then(|h| h.host.clone()),
+                    http.and_then(|h| h.user_agent.clone()),
+                    http.and_then(|h| h.status),
+                    dga_score,
+                    None::<String>,
+                    None::<String>,
+                    None::<String>,
+                    "na",
+                    0f32,
+                ])?;
+        }
+        self.count += records.len() as u64;
+        Ok(())
+    }
+
+    //
+    // Write the records to parquet_spec through a hidden file in the same
+    // directory, so scanners never pick up a partial file
+    //
+    pub fn finish(self, parquet_spec: &String) -> Result<u64, GnatError> {
+        let path = Path::new(parquet_spec);
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp_spec = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => {
+                format!("{}/.{}", dir.to_string_lossy(), file_name)
+            }
+            _ => format!(".{}", file_name),
+        };
+        let sql_command = format!(
+            "COPY (SELECT * FROM flow ORDER BY stime) TO '{}' ({});",
+            tmp_spec,
+            schema::copy_options()
+        );
+        self.conn
+            .execute_batch(&sql_command)?;
+        fs::rename(&tmp_spec, parquet_spec)?;
+        Ok(self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_and_geo() {
+        assert_eq!(tcp_flags(0x02, 0x12), "Ss.a............");
+        assert_eq!(tcp_flags(0x1b, 0x00), "S.A...F.......P.");
+        assert_eq!(tcp_flags(0, 0), "................");
+        assert_eq!(geo(&"192.168.1.1".parse().unwrap()), "private");
+        assert_eq!(geo(&"169.254.0.1".parse().unwrap()), "private");
+        assert_eq!(geo(&"fd00::1".parse().unwrap()), "private");
+        assert_eq!(geo(&"8.8.8.8".parse().unwrap()), "unk");
+        assert_eq!(geo(&"2001:db8::1".parse().unwrap()), "unk");
+        assert_eq!(mac(&[0, 0x1b, 0x21, 0xab, 0xcd, 0xef]), "00:1b:21:ab:cd:ef");
+    }
+
+    #[test]
+    fn writer_formats_records() {
+        let parquet_spec = std::env::temp_dir()
+            .join(format!("gnat-{}-record.parquet", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut record = FlowRecord::new(1_700_000_000_000_000);
+        record.etime = record.stime + 2_500_000;
+        record.proto = 6;
+        record.saddr = "10.0.0.1".parse().unwrap();
+        record.daddr = "93.184.216.34".parse().unwrap();
+        record.sport = 50000;
+        record.dport = 80;
+        record.forward = Direction {
+            pkts: 4,
+            bytes: 400,
+            data_bytes: 300,
+            initial_flags: 0x02,
+            union_flags: 0x1b,
+            ..Default::default()
+        };
+        record.reverse = Direction {
+            pkts: 3,
+            bytes: 300,
+            data_bytes: 100,
+            initial_flags: 0x12,
+            union_flags: 0x1b,
+            ..Default::default()
+        };
+        record.spd = 0b0000_0010;
+        record.reason = "eof";
+        record.http = Some(Box::new(Http {
+            method: Some(String::from("GET")),
+            host: Some(String::from("example.com")),
+            ..Default::default()
+        }));
+        let mut writer = FlowWriter::new("test", "s1").unwrap();
+        writer.append(&[record.clone(), record]).unwrap();
+        assert_eq!(writer.finish(&parquet_spec).unwrap(), 2);
+
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let version: String = conn
+            .query_row(
+                &format!(
+                    "SELECT decode(value) FROM parquet_kv_metadata('{}') WHERE decode(key) = '{}';",
+                    parquet_spec,
+                    schema::VERSION_KEY
+                ),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(version, schema::FLOW_SCHEMA_VERSION.to_string());
+        let row: (String, u32, f32, String, String, String, String, String, Option<String>, bool) = conn
+            .query_row(
+                &format!(
+                    "SELECT observ, dur, pcr, proto, uflags, spd, scountry || '/' || dcountry, httphost,
+                        ja3, dga_score IS NOT NULL FROM '{}' LIMIT 1;",
+                    parquet_spec
+                ),
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                        row.get(7)?,
+                        row.get(8)?,
+                        row.get(9)?,
+                    ))
+                },
+            )
+            .unwrap();
+        let _ = fs::remove_file(&parquet_spec);
+        assert_eq!(row.0, "s1");
+        assert_eq!(row.1, 2500);
+        assert_eq!(row.2, 0.5);
+        assert_eq!(row.3, protocol_name(6));
+        assert_eq!(row.4, "SsAa..Ff......Pp");
+        assert_eq!(row.5, "00000010|");
+        assert_eq!(row.6, "private/unk");
+        assert_eq!(row.7, "example.com");
+        assert_eq!(row.8, None);
+        assert!(row.9);
+    }
+}
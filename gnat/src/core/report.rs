@@ -0,0 +1,393 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Top-N summary reports (gnat_report)
+//
+// A report covers one hour or one day (UTC) of the flow files under the
+// input, read recursively, so hive-partitioned archives
+// (.../date=2024-06-01/...) can be read directly. Files of different
+// schema versions are unioned by name. The sections are
+//
+//   talkers       sources by bytes (sbytes + dbytes)
+//   destinations  destinations by flow count
+//   appid         applications by bytes, with their share of the total
+//   countries     source x destination country pairs by bytes
+//
+// written as report.<interval>.<start>.<section>.csv|parquet, or together
+// as report.<interval>.<start>.html.
+//
+// When polling, each interval is reported once REPORT_DELAY has passed
+// after its end, so the batches covering its last minutes have landed.
+//
+
+use crate::core::encrypt;
+use crate::core::logging;
+use crate::core::parquet;
+use crate::core::scratch;
+use crate::core::shutdown;
+
+use std::fs;
+
+use chrono::{DateTime, Duration, DurationRound, NaiveDate, NaiveDateTime, Utc};
+use duckdb::Connection;
+use tracing::{error, info};
+
+const REPORT_DELAY: Duration = Duration::minutes(10);
+
+const SECTIONS: [(&str, &str, &str); 4] = [
+    (
+        "talkers",
+        "Top talkers by bytes",
+        "SELECT saddr, count() AS flows, sum(sbytes + dbytes)::UBIGINT AS bytes
+            FROM memtable GROUP BY saddr ORDER BY bytes DESC, saddr LIMIT {top}",
+    ),
+    (
+        "destinations",
+        "Top destinations by flows",
+        "SELECT daddr, count() AS flows, sum(sbytes + dbytes)::UBIGINT AS bytes
+            FROM memtable GROUP BY daddr ORDER BY flows DESC, daddr LIMIT {top}",
+    ),
+    (
+        "appid",
+        "Applications by bytes",
+        "SELECT appid, count() AS flows, sum(sbytes + dbytes)::UBIGINT AS bytes,
+                round(100 * sum(sbytes + dbytes) / sum(sum(sbytes + dbytes)) OVER (), 2) AS percent
+            FROM memtable GROUP BY appid ORDER BY bytes DESC, appid LIMIT {top}",
+    ),
+    (
+        "countries",
+        "Country pairs by bytes",
+        "SELECT scountry, dcountry, count() AS flows, sum(sbytes + dbytes)::UBIGINT AS bytes
+            FROM memtable GROUP BY scountry, dcountry ORDER BY bytes DESC, scountry, dcountry LIMIT {top}",
+    ),
+];
+
+fn length(interval_spec: &str) -> Duration {
+    if interval_spec == "hour" {
+        Duration::hours(1)
+    } else {
+        Duration::days(1)
+    }
+}
+
+//
+// Start of the interval holding a --period of "YYYY-MM-DD" or
+// "YYYY-MM-DDTHH[:MM[:SS]]", or of the last complete interval when empty
+//
+pub fn period_start(interval_spec: &str, period_spec: &str) -> Option<DateTime<Utc>> {
+    let time = if period_spec.is_empty() {
+        Utc::now() - length(interval_spec)
+    } else if let Ok(date) = NaiveDate::parse_from_str(period_spec, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0)?.and_utc()
+    } else {
+        // chrono won't parse a time of hours alone
+        let period_spec = match period_spec.split_once('T') {
+            Some((_, time)) if !time.contains(':') => format!("{}:00", period_spec),
+            _ => period_spec.to_string(),
+        };
+        ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(&period_spec, format).ok())?
+            .and_utc()
+    };
+    time.duration_trunc(length(interval_spec)).ok()
+}
+
+fn source(input_spec: &str) -> String {
+    let glob = if input_spec.ends_with(".parquet") || input_spec.contains('*') {
+        input_spec.to_string()
+    } else {
+        format!("{}/**/*.parquet", input_spec.trim_end_matches('/'))
+    };
+    let decrypt = if encrypt::enabled() {
+        format!(", {}", encrypt::read_option())
+    } else {
+        String::new()
+    };
+    format!(
+        "read_parquet('{}', hive_partitioning = true, union_by_name = true{})",
+        glob, decrypt
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+//
+// Column names and rows of a query, as text
+//
+fn table(
+    conn: &Connection,
+    query: &str,
+) -> Result<(Vec<String>, Vec<Vec<String>>), duckdb::Error> {
+    let mut stmt = conn.prepare(&format!("DESCRIBE {}", query))?;
+    let names = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    let mut stmt = conn.prepare(&format!("SELECT COLUMNS(*)::VARCHAR FROM ({})", query))?;
+    let rows = stmt
+        .query_map([], |row| {
+            (0..names.len())
+                .map(|i| Ok(row.get::<_, Option<String>>(i)?.unwrap_or_default()))
+                .collect::<Result<Vec<String>, duckdb::Error>>()
+        })?
+        .collect::<Result<Vec<Vec<String>>, _>>()?;
+    Ok((names, rows))
+}
+
+fn html(conn: &Connection, title: &str, top: u64) -> Result<String, duckdb::Error> {
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n\
+         <style>body{{font-family:sans-serif}} table{{border-collapse:collapse;margin-bottom:2em}} \
+         th,td{{border:1px solid #ccc;padding:2px 8px}} td{{text-align:right}}</style>\n\
+         </head>\n<body>\n<h1>{0}</h1>\n",
+        escape(title)
+    );
+    for (_, heading, query) in SECTIONS.iter() {
+        let (names, rows) = table(conn, &query.replace("{top}", &top.to_string()))?;
+        page.push_str(&format!("<h2>{}</h2>\n<table>\n<tr>", escape(heading)));
+        for name in names.iter() {
+            page.push_str(&format!("<th>{}</th>", escape(name)));
+        }
+        page.push_str("</tr>\n");
+        for row in rows.iter() {
+            page.push_str("<tr>");
+            for value in row.iter() {
+                page.push_str(&format!("<td>{}</td>", escape(value)));
+            }
+            page.push_str("</tr>\n");
+        }
+        page.push_str("</table>\n");
+    }
+    page.push_str("</body>\n</html>\n");
+    Ok(page)
+}
+
+//
+// Write the report of the interval starting at start; false on failure.
+// An interval without flows is logged and skipped.
+//
+pub fn report_interval(
+    input_spec: &String,
+    output_spec: &String,
+    interval_spec: &String,
+    format_spec: &String,
+    top: u64,
+    start: DateTime<Utc>,
+) -> bool {
+    let end = start + length(interval_spec);
+    let stamp = if interval_spec == "hour" {
+        start.format("%Y%m%d%H").to_string()
+    } else {
+        start.format("%Y%m%d").to_string()
+    };
+    let name = format!("report.{}.{}", interval_spec, stamp);
+    let _batch = logging::batch(&name);
+
+    let conn = match scratch::open_in_memory("report") {
+        Ok(s) => s,
+        Err(e) => {
+            error!("open_in_memory() - {}", e);
+            return false;
+        }
+    };
+    if let Err(e) = encrypt::add_key(&conn) {
+        error!("loading the parquet key -- {:?}", e);
+        return false;
+    }
+    let sql_command = format!(
+        "CREATE TABLE memtable AS SELECT saddr, daddr, sbytes, dbytes, appid, scountry, dcountry
+            FROM {} WHERE stime >= '{}' AND stime < '{}';",
+        source(input_spec),
+        start.format("%Y-%m-%d %H:%M:%S"),
+        end.format("%Y-%m-%d %H:%M:%S")
+    );
+    if let Err(e) = conn.execute_batch(&sql_command) {
+        error!("reading {} -- {:?}", input_spec, e);
+        return false;
+    }
+    let flows: u64 = conn
+        .query_row("SELECT count() FROM memtable", [], |row| row.get(0))
+        .unwrap_or(0);
+    if flows == 0 {
+        info!("report: no flows for {}", name);
+        return true;
+    }
+
+    let outputs: Vec<(String, String)> = if format_spec == "html" {
+        vec![(format!("{}.html", name), String::new())]
+    } else {
+        SECTIONS
+            .iter()
+            .map(|(section, _, query)| {
+                (
+                    format!("{}.{}.{}", name, section, format_spec),
+                    query.replace("{top}", &top.to_string()),
+                )
+            })
+            .collect()
+    };
+    for (file_name, query) in outputs.iter() {
+        let tmp_spec = format!("{}/.{}", output_spec, file_name);
+        let result = if format_spec == "html" {
+            let title = format!("{} {} ({} flows)", interval_spec, stamp, flows);
+            html(&conn, &title, top)
+                .map_err(std::io::Error::other)
+                .and_then(|page| fs::write(&tmp_spec, page))
+        } else {
+            let options = if format_spec == "csv" {
+                String::from("FORMAT 'csv', HEADER")
+            } else {
+                parquet::options()
+            };
+            conn.execute_batch(&format!(
+                "COPY ({}) TO '{}' ({});",
+                query, tmp_spec, options
+            ))
+            .map_err(std::io::Error::other)
+        };
+        let result =
+            result.and_then(|_| fs::rename(&tmp_spec, format!("{}/{}", output_spec, file_name)));
+        if let Err(e) = result {
+            error!("writing {} -- {:?}", file_name, e);
+            let _ = fs::remove_file(&tmp_spec);
+            return false;
+        }
+    }
+    info!("report: {} [{} flows]", name, flows);
+    true
+}
+
+pub fn report(
+    input_spec: &String,
+    output_spec: &String,
+    interval_spec: &String,
+    format_spec: &String,
+    top: u64,
+    start: DateTime<Utc>,
+    polling: bool,
+) -> Result<(), std::io::Error> {
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("interval: {}", interval_spec);
+    info!("format: {}", format_spec);
+    info!("top: {}", top);
+    info!("start: {}", start);
+    info!("polling: {}", polling);
+
+    if input_spec.starts_with("s3://") {
+        scratch::enable_s3();
+    }
+
+    let _pool = scratch::pool();
+    let mut start = start;
+    loop {
+        let due = start + length(interval_spec) + REPORT_DELAY;
+        if polling && Utc::now() < due {
+            let wait = (due - Utc::now()).to_std().unwrap_or_default();
+            if !shutdown::sleep(wait.min(std::time::Duration::from_secs(60))) {
+                break;
+            }
+            continue;
+        }
+        if !report_interval(
+            input_spec,
+            output_spec,
+            interval_spec,
+            format_spec,
+            top,
+            start,
+        ) && !polling
+        {
+            return Err(std::io::Error::other(format!("report of {} failed", start)));
+        }
+        if !polling || shutdown::requested() {
+            break;
+        }
+        start += length(interval_spec);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::schema;
+    use crate::core::testutil::test_dir;
+    use std::path::PathBuf;
+
+    #[test]
+    fn periods() {
+        let start = |interval: &str, period: &str| {
+            period_start(interval, period).map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        };
+        for (interval, period, expected) in [
+            ("day", "2024-06-01", "2024-06-01 00:00"),
+            ("day", "2024-06-01T13", "2024-06-01 00:00"),
+            ("hour", "2024-06-01T13:45", "2024-06-01 13:00"),
+            ("hour", "2024-06-01T13:45:10", "2024-06-01 13:00"),
+        ] {
+            assert_eq!(start(interval, period).as_deref(), Some(expected));
+        }
+        assert_eq!(start("hour", "June 1st"), None);
+        // the last complete interval
+        let ago = Utc::now() - period_start("hour", "").unwrap();
+        assert!(ago >= Duration::hours(1) && ago < Duration::hours(2));
+    }
+
+    #[test]
+    fn sections_of_an_interval() {
+        let dir = PathBuf::from(test_dir("report"));
+        let input = dir.join("input/date=2024-06-01");
+        let output = dir.join("output");
+        fs::create_dir_all(&input).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        schema::write_test_flows(
+            &input.join("a.parquet").to_string_lossy(),
+            "SELECT * FROM (VALUES
+                (TIMESTAMP '2024-06-01 10:05:00', '10.0.0.1', '10.0.0.9', 100, 0, 'dns'),
+                (TIMESTAMP '2024-06-01 10:10:00', '10.0.0.2', '10.0.0.9', 300, 100, 'tls'),
+                (TIMESTAMP '2024-06-01 10:20:00', '10.0.0.1', '10.0.0.8', 50, 50, '<b>'),
+                (TIMESTAMP '2024-06-01 11:00:00', '10.0.0.3', '10.0.0.9', 9999, 0, 'tls'))
+             t(stime, saddr, daddr, sbytes, dbytes, appid)",
+        );
+        let input_spec = dir.join("input").to_string_lossy().to_string();
+        let output_spec = output.to_string_lossy().to_string();
+        let run = |format: &str, top: u64, period: &str| {
+            let start = period_start("hour", period).unwrap();
+            let (hour, format) = (String::from("hour"), String::from(format));
+            report_interval(&input_spec, &output_spec, &hour, &format, top, start)
+        };
+        let read = |file_name: &str| fs::read_to_string(output.join(file_name)).unwrap();
+
+        assert!(run("csv", 10, "2024-06-01T10"));
+        assert_eq!(
+            read("report.hour.2024060110.talkers.csv"),
+            "saddr,flows,bytes\n10.0.0.2,1,400\n10.0.0.1,2,200\n"
+        );
+        let appid = read("report.hour.2024060110.appid.csv");
+        assert_eq!(appid.lines().nth(1), Some("tls,1,400,66.67"));
+
+        assert!(run("html", 10, "2024-06-01T10"));
+        let page = read("report.hour.2024060110.html");
+        assert!(page.contains("<title>hour 2024060110 (3 flows)</title>"));
+        assert!(page.contains("<td>10.0.0.2</td>"));
+        assert!(page.contains("<td>&lt;b&gt;</td>"));
+
+        // an hour without flows writes nothing
+        let written = fs::read_dir(&output).unwrap().count();
+        assert!(run("csv", 10, "2024-06-01T12"));
+        assert_eq!(fs::read_dir(&output).unwrap().count(), written);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
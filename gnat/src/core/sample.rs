@@ -0,0 +1,239 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Flow sampling stage (gnat_sample)
+//
+// Each input file is written to the output with only the sampled flows:
+//
+//   flat        percent of all flows
+//   stratified  at most cap flows per category of each file
+//   adaptive    categories of up to cap flows in full; larger ones keep
+//               cap / count of their flows, but never less than percent
+//
+// A category is the observ and the `by` columns of a flow, e.g. appid or
+// dport. Adaptive sampling keeps the rare categories whole while the top
+// talkers are cut back, so models still see unusual traffic.
+//
+// Flows are picked by a hash of their key rather than at random, so a
+// retried file samples the same flows. Counters are not rescaled. percent
+// and cap can be set per observation with --overrides (see overrides.rs).
+//
+
+use crate::core::error::GnatError;
+use crate::core::filter;
+use crate::core::overrides::Overrides;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::spool::process_directory_parallel;
+
+use duckdb::Connection;
+use tracing::{error, info};
+
+pub const SAMPLE_MODES: [&str; 3] = ["flat", "stratified", "adaptive"];
+
+// the flows of a category are ranked by this, in 1 / HASH_SCALE steps
+const FLOW_HASH: &str = "hash(observ, stime, saddr, daddr, sport, dport, proto) % 10000";
+const HASH_SCALE: u64 = 10000;
+
+pub struct Sampler {
+    pub mode: String,
+    pub by: Vec<String>,
+    pub percent: f64,
+    pub cap: u64,
+    pub overrides: Overrides,
+}
+
+impl Sampler {
+    //
+    // Clause selecting the sampled flows of memtable
+    //
+    fn clause(&self) -> String {
+        let partition = format!("PARTITION BY observ, {}", self.by.join(", "));
+        let percent = self.overrides.case(|o| o.percent, self.percent);
+        let cap = self.overrides.case(|o| o.cap, self.cap);
+        match self.mode.as_str() {
+            "stratified" => format!(
+                "QUALIFY row_number() OVER ({} ORDER BY {}) <= {}",
+                partition, FLOW_HASH, cap
+            ),
+            "adaptive" => format!(
+                "QUALIFY {} < {} * greatest({} / 100, least(1, {} / count() OVER ({})))",
+                FLOW_HASH, HASH_SCALE, percent, cap, partition
+            ),
+            _ => format!("WHERE {} < {} * {} / 100", FLOW_HASH, HASH_SCALE, percent),
+        }
+    }
+
+    //
+    // Check the by columns against the flow schema
+    //
+    pub fn validate(&self) -> Result<(), GnatError> {
+        for column in self.by.iter() {
+            filter::column(column).map_err(|e| GnatError::Config(format!("--by {}", e)))?;
+        }
+        let conn = Connection::open_in_memory()?;
+        let sql_command = format!(
+            "{}; ALTER TABLE flow RENAME TO memtable; SELECT * FROM memtable {};",
+            schema::FLOW_TABLE,
+            self.clause()
+        );
+        conn.execute_batch(&sql_command)
+            .map_err(|e| GnatError::Config(format!("--by {} - {}", self.by.join(","), e)))
+    }
+
+    pub fn sample_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("sample")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let sql_command = format!(
+            "CREATE TABLE memtable AS {};
+             CREATE TABLE sampled AS SELECT * FROM memtable {};",
+            source,
+            self.clause()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("sampling {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT count(*) FROM {};", table), [], |row| row.get(0))
+                .unwrap_or(0)
+        };
+        let (flows, kept) = (count("memtable"), count("sampled"));
+
+        let sql_command = format!(
+            "COPY (SELECT * FROM sampled ORDER BY stime) TO '{}' ({});",
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+        info!("sample: {} [kept {} of {} flows]", input_spec, kept, flows);
+        Ok(())
+    }
+}
+
+pub fn sample(
+    sampler: Sampler,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+    workers: usize,
+) -> Result<(), std::io::Error> {
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("polling: {}", polling);
+    info!("workers: {}", workers);
+    info!("mode: {}", sampler.mode);
+    if sampler.mode != "flat" {
+        info!("by: {}", sampler.by.join(","));
+        info!("cap: {}", sampler.cap);
+    }
+    if sampler.mode != "stratified" {
+        info!("percent: {}", sampler.percent);
+    }
+    if !sampler.overrides.is_empty() {
+        info!("overrides: {} observations", sampler.overrides.observ.len());
+    }
+
+    sampler.validate()?;
+
+    process_directory_parallel(
+        "sample",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        workers,
+        |src_path, tmp_path| sampler.sample_file(src_path, tmp_path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+    use std::fs;
+
+    fn sampler(mode: &str, percent: f64, cap: u64) -> Sampler {
+        Sampler {
+            mode: String::from(mode),
+            by: vec![String::from("dport")],
+            percent,
+            cap,
+            overrides: Overrides::default(),
+        }
+    }
+
+    // (dport, flows) kept from 100 flows to port 80 and 5 to port 53
+    fn kept(dir: &str, sampler: &Sampler) -> Vec<(u16, i64)> {
+        let input_spec = format!("{}/in.parquet", dir);
+        let output_spec = format!("{}/out.parquet", dir);
+        schema::write_test_flows(
+            &input_spec,
+            "SELECT 's1' AS observ, TIMESTAMP '2024-01-01' + to_seconds(i) AS stime, '10.0.0.1' AS saddr,
+                '10.0.0.2' AS daddr, 1024 + i AS sport, if(i < 100, 80, 53) AS dport, '6' AS proto
+             FROM range(105) t(i)",
+        );
+        sampler.sample_file(&input_spec, &output_spec).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT dport, count() FROM '{}' GROUP BY dport ORDER BY dport;",
+                output_spec
+            ))
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn validate_checks_the_by_columns() {
+        assert!(sampler("stratified", 10.0, 10).validate().is_ok());
+        let mut bad = sampler("stratified", 10.0, 10);
+        bad.by = vec![String::from("nosuch")];
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn modes() {
+        let dir = test_dir("sample-modes");
+        assert_eq!(
+            kept(&dir, &sampler("flat", 100.0, 0)),
+            vec![(53, 5), (80, 100)]
+        );
+        assert!(kept(&dir, &sampler("flat", 0.0, 0)).is_empty());
+        assert_eq!(
+            kept(&dir, &sampler("stratified", 0.0, 10)),
+            vec![(53, 5), (80, 10)]
+        );
+
+        // the rare category is kept whole, the large one cut back to
+        // about cap flows
+        let adaptive = kept(&dir, &sampler("adaptive", 0.0, 10));
+        assert_eq!(adaptive[0], (53, 5));
+        assert!(adaptive[1].0 == 80 && (2..25).contains(&adaptive[1].1));
+        // flows are picked by their hash, so a retry keeps the same ones
+        assert_eq!(kept(&dir, &sampler("adaptive", 0.0, 10)), adaptive);
+        let flat = kept(&dir, &sampler("flat", 30.0, 0));
+        assert_eq!(kept(&dir, &sampler("flat", 30.0, 0)), flat);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
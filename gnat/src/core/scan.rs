@@ -0,0 +1,321 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Port scan and sweep detection stage (gnat_scan)
+//
+// Over fixed windows of flows, per observ, saddr and proto:
+//
+//   vertical    one daddr, at least `ports` distinct dports
+//   horizontal  one dport, at least `hosts` distinct daddrs
+//
+// Each scan is a "scan" trigger (see trigger.rs) with scan_type set, the
+// scanned daddr or dport, and the distinct count as its value.
+//
+// Windows are evaluated once the newest flow seen is the grace period past
+// their end, and the flows of the open window are held in a hidden pending
+// file in the trigger directory, as in detect.rs. Input files are passed to
+// the output unchanged.
+//
+
+use crate::core::error::GnatError;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::spool::process_directory;
+use crate::core::suppress;
+use crate::core::trigger::{self, TRIGGER_TABLE};
+
+use std::fs;
+use std::path::Path;
+
+use tracing::{error, info};
+
+pub struct Scan {
+    pub trigger_spec: String,
+    pub suppress_spec: String,
+    pub window: u64,
+    pub grace: u64,
+    pub ports: u64,
+    pub hosts: u64,
+}
+
+impl Scan {
+    fn pending_spec(&self) -> String {
+        format!("{}/.scan-pending.parquet", self.trigger_spec)
+    }
+
+    //
+    // The newest flow of a table less the grace period
+    //
+    fn closed(&self, conn: &duckdb::Connection, table: &String) -> Option<String> {
+        conn.query_row(
+            &format!(
+                "SELECT strftime(max(stime) - INTERVAL '{} seconds', '%Y-%m-%d %H:%M:%S.%f') FROM {};",
+                self.grace, table
+            ),
+            [],
+            |row| row.get::<_, Option<String>>(0),
+        )
+        .ok()
+        .flatten()
+    }
+
+    //
+    // Scans of the windows that closed after prev_closed and by closed;
+    // target is the column held fixed and spread the one counted
+    //
+    fn insert(
+        &self,
+        scan_type: &str,
+        target: &str,
+        spread: &str,
+        threshold: u64,
+        prev_closed: &Option<String>,
+        closed: &String,
+    ) -> String {
+        let (daddr, dport) = if target == "daddr" {
+            ("daddr", "NULL")
+        } else {
+            ("NULL", "dport")
+        };
+        let window_end = format!("bucket + INTERVAL '{} minutes'", self.window);
+        let mut sql_command = format!(
+            "INSERT INTO trigger (time, etime, observ, tenant, detector, name, saddr, daddr, dport,
+                    key, value, threshold, detail, scan_type)
+                SELECT bucket, {window_end}, observ, tenant, 'scan', '{scan_type}-scan', saddr, {daddr}, {dport},
+                    printf('saddr=%s, {target}=%s, proto=%s', saddr, {target}::VARCHAR, proto), spread, {threshold},
+                    printf('%d {spread}s, %d flows', spread, flows), '{scan_type}'
+                FROM (SELECT time_bucket(INTERVAL '{window} minutes', stime) AS bucket, observ, tenant, saddr, proto, {target},
+                        count(DISTINCT {spread}) AS spread, count() AS flows
+                    FROM memtable GROUP BY ALL)
+                WHERE spread >= {threshold} AND {window_end} <= TIMESTAMP '{closed}'",
+            window_end = window_end,
+            scan_type = scan_type,
+            daddr = daddr,
+            dport = dport,
+            target = target,
+            spread = spread,
+            threshold = threshold,
+            window = self.window,
+            closed = closed,
+        );
+        if let Some(prev_closed) = prev_closed {
+            sql_command.push_str(&format!(" AND {} > TIMESTAMP '{}'", window_end, prev_closed));
+        }
+        sql_command.push(';');
+        sql_command
+    }
+
+    pub fn scan_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("scan")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let pending_spec = self.pending_spec();
+        let pending = Path::new(&pending_spec).exists();
+        let kept = suppress::exclude(&conn, &self.suppress_spec, "scan", "batch");
+        let mut sql_command = format!(
+            "CREATE TABLE batch AS {};
+             CREATE TABLE memtable AS SELECT observ, tenant, saddr, daddr, proto, dport, stime
+                FROM batch WHERE proto IN ('tcp', 'udp') AND {}",
+            source, kept
+        );
+        if pending {
+            sql_command.push_str(&format!(" UNION ALL BY NAME SELECT * FROM '{}'", pending_spec));
+        }
+        sql_command.push_str(&format!("; {};", TRIGGER_TABLE));
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        let sql_command = format!(
+            "COPY (SELECT * FROM batch) TO '{}' ({});",
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+        let Some(closed) = self.closed(&conn, &String::from("memtable")) else {
+            // no tcp or udp flows, and nothing held
+            return Ok(());
+        };
+        let prev_closed = if pending {
+            self.closed(&conn, &format!("'{}'", pending_spec))
+        } else {
+            None
+        };
+
+        for sql_command in [
+            self.insert("vertical", "daddr", "dport", self.ports, &prev_closed, &closed),
+            self.insert("horizontal", "dport", "daddr", self.hosts, &prev_closed, &closed),
+        ] {
+            if let Err(e) = conn.execute_batch(&sql_command) {
+                error!("scanning {} - {:?}", input_spec, e);
+                return Err(e.into());
+            }
+        }
+        let file_name = Path::new(input_spec)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let triggers = match trigger::write(&conn, &self.trigger_spec, "scan", &file_name) {
+            Ok(count) => count,
+            Err(e) => {
+                error!("writing triggers for {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+
+        //
+        // hold the flows of the open window; the pending file is replaced
+        // last, as in detect.rs
+        //
+        let tmp_spec = format!("{}.tmp", pending_spec);
+        let sql_command = format!(
+            "COPY (SELECT * FROM memtable
+                WHERE stime >= time_bucket(INTERVAL '{} minutes', TIMESTAMP '{}')
+                ORDER BY stime) TO '{}' ({});",
+            self.window,
+            closed,
+            tmp_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", tmp_spec, e);
+            return Err(e.into());
+        }
+        if let Err(e) = fs::rename(&tmp_spec, &pending_spec) {
+            error!("moving {} -> {} - {:?}", tmp_spec, pending_spec, e);
+            return Err(e.into());
+        }
+        info!("scan: {} [{} triggers]", input_spec, triggers);
+        Ok(())
+    }
+}
+
+pub fn scan(
+    scan: Scan,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+) -> Result<(), std::io::Error> {
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("trigger spec: {}", scan.trigger_spec);
+    if !scan.suppress_spec.is_empty() {
+        info!("suppression spec: {}", scan.suppress_spec);
+    }
+    info!("polling: {}", polling);
+    info!("window: {} minutes", scan.window);
+    info!("grace: {}", scan.grace);
+    info!("ports: {}", scan.ports);
+    info!("hosts: {}", scan.hosts);
+
+    process_directory(
+        "scan",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        |src_path, tmp_path| scan.scan_file(src_path, tmp_path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    // flows as (minutes past midnight, saddr, daddr, proto, dport)
+    fn write_input(path: &str, flows: &[(u32, &str, String, &str, u16)]) {
+        let rows: Vec<String> = flows
+            .iter()
+            .map(|(m, saddr, daddr, proto, dport)| {
+                format!(
+                    "('s1', TIMESTAMP '2024-01-01' + INTERVAL '{} minutes', '{}', '{}', '{}', {})",
+                    m, saddr, daddr, proto, dport
+                )
+            })
+            .collect();
+        schema::write_test_flows(
+            path,
+            &format!(
+                "SELECT * FROM (VALUES {}) t(observ, stime, saddr, daddr, proto, dport)",
+                rows.join(", ")
+            ),
+        );
+    }
+
+    // (scan_type, saddr, daddr, dport, value) of a trigger
+    type Scanned = (String, String, Option<String>, Option<u16>, f64);
+
+    fn triggers(trigger_spec: &str) -> Vec<Scanned> {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let Ok(mut stmt) = conn.prepare(&format!(
+            "SELECT scan_type, saddr, daddr, dport, value FROM read_parquet('{}/trigger.scan.*') ORDER BY ALL;",
+            trigger_spec
+        )) else {
+            return Vec::new();
+        };
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn vertical_and_horizontal_scans() {
+        let dir = test_dir("scan-windows");
+        let trigger_spec = format!("{}/trigger", dir);
+        fs::create_dir_all(&trigger_spec).unwrap();
+        let scan = Scan {
+            trigger_spec: trigger_spec.clone(),
+            suppress_spec: String::new(),
+            window: 10,
+            grace: 0,
+            ports: 5,
+            hosts: 4,
+        };
+        let mut flows = Vec::new();
+        for i in 1..=5 {
+            // ports of one host, the same port of many hosts, and pings
+            flows.push((i, "10.0.0.1", String::from("10.0.0.9"), "tcp", i as u16));
+            flows.push((i, "10.0.0.2", format!("10.0.1.{}", i), "tcp", 22));
+            flows.push((i, "10.0.0.3", format!("10.0.2.{}", i), "icmp", 0));
+        }
+        let input_spec = format!("{}/a.parquet", dir);
+        let output_spec = format!("{}/a.out.parquet", dir);
+        write_input(&input_spec, &flows);
+        scan.scan_file(&input_spec, &output_spec).unwrap();
+        // the window is still open
+        assert!(triggers(&trigger_spec).is_empty());
+
+        let input_spec = format!("{}/b.parquet", dir);
+        let output_spec = format!("{}/b.out.parquet", dir);
+        write_input(&input_spec, &[(11, "10.0.0.4", String::from("10.0.0.9"), "udp", 53)]);
+        scan.scan_file(&input_spec, &output_spec).unwrap();
+        assert_eq!(
+            triggers(&trigger_spec),
+            vec![
+                (String::from("horizontal"), String::from("10.0.0.2"), None, Some(22), 5.0),
+                (String::from("vertical"), String::from("10.0.0.1"), Some(String::from("10.0.0.9")), None, 5.0),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Flow schema versions
+//
+// Flow parquet files carry their schema version in the gnat_schema_version
+// key of the parquet key/value metadata (files written before versioning
+// are identified by their columns). Stages read inputs through select(),
+// which applies the migrations newer than the file, so older spool and
+// archive files are upgraded on read; outputs are written with
+// copy_options() and so carry the current version (and the stage's parquet
+// writer options).
+//
+// To change the schema, update FLOW_SCHEMA in export_parquet.h and
+// FLOW_TABLE in flow_schema.rs, bump FLOW_SCHEMA_VERSION in both, and append
+// a migration there.
+//
+
+use crate::core::error::GnatError;
+use crate::core::lineage;
+use crate::core::parquet;
+
+use tracing::debug;
+
+include!("flow_schema.rs");
+
+//
+// SELECT reading input_spec upgraded to FLOW_SCHEMA_VERSION; use it in place
+// of SELECT * FROM '<input_spec>'. A file written by a newer toolkit can't
+// be read.
+//
+pub fn select(conn: &Connection, input_spec: &String) -> Result<String, GnatError> {
+    let from_version = version(conn, input_spec)?;
+    if from_version > FLOW_SCHEMA_VERSION {
+        return Err(GnatError::Schema(format!(
+            "{}: schema version {} is newer than {}",
+            input_spec, from_version, FLOW_SCHEMA_VERSION
+        )));
+    }
+    let select_list = upgraded_columns(conn, input_spec, from_version)?;
+    for (version, description) in migrations(from_version) {
+        debug!("{}: schema migration {} ({})", input_spec, version, description);
+    }
+    Ok(format!("SELECT {} FROM '{}'", select_list.join(", "), input_spec))
+}
+
+//
+// COPY options for flow parquet outputs, with the lineage of the batch in
+// progress
+//
+pub fn copy_options() -> String {
+    let lineage = match lineage::metadata() {
+        Some(entry) => format!(", {}", entry),
+        None => String::new(),
+    };
+    format!(
+        "{}, KV_METADATA {{{}: '{}'{}}}",
+        parquet::options(),
+        VERSION_KEY,
+        FLOW_SCHEMA_VERSION,
+        lineage
+    )
+}
+
+//
+// Write the rows of select, by column name with the other columns NULL, to
+// the flow parquet file path; for the tests of the stages
+//
+#[cfg(test)]
+pub(crate) fn write_test_flows(path: &str, select: &str) {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(&format!(
+        "{}; INSERT INTO flow BY NAME {}; COPY flow TO '{}' ({});",
+        FLOW_TABLE,
+        select,
+        path,
+        copy_options()
+    ))
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+    use std::path::PathBuf;
+
+    // (name, type) of the columns of a query
+    fn describe(conn: &Connection, query: &str) -> Vec<(String, String)> {
+        let mut stmt = conn.prepare(&format!("DESCRIBE {};", query)).unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    // columns the migrations newer than from_version add
+    fn added(from_version: u32) -> Vec<&'static str> {
+        MIGRATIONS
+            .iter()
+            .filter(|m| m.version > from_version)
+            .flat_map(|m| m.added.iter().map(|c| c.name))
+            .collect()
+    }
+
+    #[test]
+    fn old_files_are_upgraded_on_read() {
+        let dir = PathBuf::from(test_dir("schema"));
+        let spec = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let conn = Connection::open_in_memory().unwrap();
+        let sql_command = format!("{}; INSERT INTO flow (observ) VALUES ('s1');", FLOW_TABLE);
+        conn.execute_batch(&sql_command).unwrap();
+        let flow = describe(&conn, "SELECT * FROM flow");
+        let names: Vec<&str> = flow.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, flow_columns());
+
+        // unversioned files are identified by their columns, and read with
+        // the columns and types of the current schema
+        for from_version in [1, 2, 7] {
+            let old_spec = spec(&format!("v{}.parquet", from_version));
+            conn.execute_batch(&format!(
+                "COPY (SELECT * EXCLUDE ({}) FROM flow) TO '{}' (FORMAT parquet);",
+                added(from_version).join(", "),
+                old_spec
+            ))
+            .unwrap();
+            assert_eq!(version(&conn, &old_spec).unwrap(), from_version);
+            let upgrades = (FLOW_SCHEMA_VERSION - from_version) as usize;
+            assert_eq!(migrations(from_version).len(), upgrades);
+            let source = select(&conn, &old_spec).unwrap();
+            assert_eq!(describe(&conn, &source), flow);
+        }
+        let source = select(&conn, &spec("v1.parquet")).unwrap();
+        let (observ, scity): (String, String) = conn
+            .query_row(
+                &format!("SELECT observ, scity FROM ({});", source),
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((observ.as_str(), scity.as_str()), ("s1", "unk"));
+
+        // outputs record the current version; newer files are refused
+        let current_spec = spec("current.parquet");
+        write_test_flows(&current_spec, "SELECT 's1' AS observ");
+        assert_eq!(version(&conn, &current_spec).unwrap(), FLOW_SCHEMA_VERSION);
+        let newer_spec = spec("newer.parquet");
+        conn.execute_batch(&format!(
+            "COPY flow TO '{}' (FORMAT parquet, KV_METADATA {{{}: '{}'}});",
+            newer_spec,
+            VERSION_KEY,
+            FLOW_SCHEMA_VERSION + 1
+        ))
+        .unwrap();
+        assert!(matches!(
+            select(&conn, &newer_spec),
+            Err(GnatError::Schema(_))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,403 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Pooled DuckDB connections and their scratch directories for spill files
+//
+// Each connection gets {root}/gnat-{stage}-{pid}-{context}-{seq}, passed to
+// DuckDB as its temp_directory and removed with the connection, so spill
+// files never land in (or collide within) the spool directories. The
+// context is the stage's id within the process (see context.rs), so stages
+// of the same kind on threads of gnat_run keep apart.
+//
+// Connections are reused across batches rather than opened for each one:
+// a batch runs in a fresh in-memory database that is detached when it is
+// done, so tables never leak from one batch to the next, while the DuckDB
+// instance, its loaded extensions and the S3 secret are kept. The pool
+// belongs to the processor running the batches (see pool()); connections
+// opened outside one are closed after their batch.
+//
+// A reused connection keeps its scratch directory: DuckDB can't switch
+// temp_directory once it has spilled. Batches still don't share spill
+// files, since a connection serves one batch at a time and the spill
+// blocks of a batch are freed when its database is detached.
+//
+
+use crate::core::context::{self, Local};
+use crate::core::error::GnatError;
+use crate::core::shutdown;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::ops::Deref;
+use std::sync::Mutex;
+
+use duckdb::Connection;
+use tracing::{debug, error, info};
+
+#[derive(Debug, clap::Args)]
+pub struct ScratchArgs {
+    /// root for per-batch DuckDB scratch directories
+    #[arg(long)]
+    pub scratch: Option<String>,
+}
+
+static SCRATCH_ROOT: Local<String> = Local::new();
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+static S3: Local<AtomicBool> = Local::new();
+
+// connections kept for reuse; a stage uses at most one per worker
+const POOL_SIZE: usize = 16;
+const BATCH_DATABASE: &str = "gnat_batch";
+static POOL: Local<Mutex<Idle>> = Local::new();
+
+struct Idle {
+    // live Pool guards
+    owners: usize,
+    connections: Vec<Pooled>,
+}
+
+//
+// Held by a processor while it runs: connections are pooled as long as one
+// is alive, and closed, with their scratch directories, when the last is
+// dropped
+//
+pub struct Pool {
+    _owner: (),
+}
+
+pub struct ScratchDir {
+    pub path: PathBuf,
+}
+
+struct Pooled {
+    // dropped before its scratch directory
+    conn: Connection,
+    // httpfs and the S3 secret are loaded
+    s3: bool,
+    _scratch: ScratchDir,
+}
+
+pub struct PooledConnection {
+    pooled: Option<Pooled>,
+}
+
+fn idle() -> &'static Mutex<Idle> {
+    POOL.get_or_init(|| {
+        Mutex::new(Idle {
+            owners: 0,
+            connections: Vec::new(),
+        })
+    })
+}
+
+pub fn pool() -> Pool {
+    idle().lock().unwrap().owners += 1;
+    Pool { _owner: () }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        let drained = {
+            let mut idle = idle().lock().unwrap();
+            idle.owners -= 1;
+            if idle.owners > 0 {
+                return;
+            }
+            std::mem::take(&mut idle.connections)
+        };
+        debug!("closing {} pooled connections", drained.len());
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+//
+// Set the scratch root of a stage's --scratch argument; exits on an
+// invalid directory
+//
+pub fn configure(stage: &str, args: &ScratchArgs) {
+    let scratch_spec = args.scratch.clone().unwrap_or_default();
+    if !scratch_spec.is_empty() && !Path::new(&scratch_spec).is_dir() {
+        error!("invalid --scratch directory {}", scratch_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+    set_root(stage, &scratch_spec);
+}
+
+//
+// Set the scratch root (defaults to the system temp directory) and remove
+// directories left behind by earlier runs of this stage: those of a pid
+// that is gone, and those under this process's own pid and this stage's
+// context (a restarted container usually gets the same pid). Called before
+// the stage opens any connection, and context ids aren't reused within a
+// process, so the directories of other stages of this process are kept.
+//
+pub fn set_root(stage: &str, root_spec: &str) {
+    let root = if root_spec.is_empty() {
+        String::from(std::env::temp_dir().to_string_lossy())
+    } else {
+        root_spec.to_string()
+    };
+    info!("scratch spec: {}", root);
+
+    if let Ok(directory) = fs::read_dir(&root) {
+        let prefix = format!("gnat-{}-", stage);
+        for entry in directory.flatten() {
+            let file_name = String::from(entry.file_name().to_string_lossy());
+            let Some(mut fields) = file_name.strip_prefix(&prefix).map(|s| s.split('-')) else {
+                continue;
+            };
+            let (Some(pid), Some(id)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let own = pid == std::process::id().to_string() && id == context::current().to_string();
+            if own || !Path::new(&format!("/proc/{}", pid)).exists() {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+    let _ = SCRATCH_ROOT.set(root);
+}
+
+//
+// Load httpfs with an S3 secret in every connection this stage opens from
+// now on; other stages of gnat_run are left as they are. Credentials come from AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY (plus
+// AWS_SESSION_TOKEN, AWS_REGION and AWS_ENDPOINT_URL for S3-compatible
+// stores) or, when those are unset, the AWS credential chain.
+//
+pub fn enable_s3() {
+    s3().store(true, Ordering::Relaxed);
+}
+
+fn s3() -> &'static AtomicBool {
+    S3.get_or_init(|| AtomicBool::new(false))
+}
+
+//
+// An unpooled connection with httpfs and the S3 secret loaded, for a
+// one-off check that must not turn S3 on for the stage's connections
+//
+pub fn open_s3() -> Result<Connection, GnatError> {
+    let conn = Connection::open_in_memory()?;
+    let sql_command = format!("INSTALL httpfs; LOAD httpfs; {}", s3_secret());
+    conn.execute_batch(&sql_command)?;
+    Ok(conn)
+}
+
+fn s3_secret() -> String {
+    let env = |name: &str| std::env::var(name).unwrap_or_default().replace('\'', "''");
+    if env("AWS_ACCESS_KEY_ID").is_empty() {
+        return String::from(
+            "INSTALL aws; LOAD aws;
+             CREATE OR REPLACE SECRET gnat_s3 (TYPE s3, PROVIDER credential_chain);",
+        );
+    }
+    let mut options = format!(
+        "TYPE s3, KEY_ID '{}', SECRET '{}'",
+        env("AWS_ACCESS_KEY_ID"),
+        env("AWS_SECRET_ACCESS_KEY")
+    );
+    for (variable, option) in [
+        ("AWS_SESSION_TOKEN", "SESSION_TOKEN"),
+        ("AWS_REGION", "REGION"),
+    ] {
+        if !env(variable).is_empty() {
+            options.push_str(&format!(", {} '{}'", option, env(variable)));
+        }
+    }
+    let endpoint = env("AWS_ENDPOINT_URL");
+    if !endpoint.is_empty() {
+        let host = endpoint.trim_start_matches("https://").trim_start_matches("http://");
+        options.push_str(&format!(
+            ", ENDPOINT '{}', URL_STYLE 'path', USE_SSL {}",
+            host,
+            !endpoint.starts_with("http://")
+        ));
+    }
+    format!("CREATE OR REPLACE SECRET gnat_s3 ({});", options)
+}
+
+//
+// Open an in-memory DuckDB connection spilling to a fresh scratch directory
+//
+fn open(stage: &str) -> Result<Pooled, GnatError> {
+    let root = SCRATCH_ROOT
+        .get_or_init(|| String::from(std::env::temp_dir().to_string_lossy()))
+        .clone();
+    let path = PathBuf::from(root).join(format!(
+        "gnat-{}-{}-{}-{}",
+        stage,
+        std::process::id(),
+        context::current(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&path)?;
+    let scratch = ScratchDir { path };
+
+    let conn = Connection::open_in_memory()?;
+    let sql_command = format!(
+        "PRAGMA temp_directory='{}';",
+        scratch.path.to_string_lossy()
+    );
+    conn.execute_batch(&sql_command)?;
+    Ok(Pooled {
+        conn,
+        s3: false,
+        _scratch: scratch,
+    })
+}
+
+//
+// A connection for one batch, taken from the pool (or opened when the pool
+// is empty) with a fresh in-memory database attached as BATCH_DATABASE and
+// in use; dropping it detaches that database, and any other the batch
+// attached, and returns the connection to the pool
+//
+pub fn open_in_memory(stage: &str) -> Result<PooledConnection, GnatError> {
+    let pooled = idle().lock().unwrap().connections.pop();
+    let mut pooled = match pooled {
+        Some(p) => p,
+        None => open(stage)?,
+    };
+    if s3().load(Ordering::Relaxed) && !pooled.s3 {
+        let sql_command = format!("INSTALL httpfs; LOAD httpfs; {}", s3_secret());
+        pooled
+            .conn
+            .execute_batch(&sql_command)?;
+        pooled.s3 = true;
+    }
+    let sql_command = format!("ATTACH ':memory:' AS {0}; USE {0};", BATCH_DATABASE);
+    pooled
+        .conn
+        .execute_batch(&sql_command)?;
+    Ok(PooledConnection {
+        pooled: Some(pooled),
+    })
+}
+
+//
+// Detach what a batch left attached, so the connection starts the next one
+// empty
+//
+fn reset(conn: &Connection) -> Result<(), duckdb::Error> {
+    let attached: Vec<String> = {
+        let mut stmt = conn.prepare(
+            "SELECT database_name FROM duckdb_databases()
+                WHERE NOT internal AND database_name <> 'memory';",
+        )?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect::<Result<Vec<String>, duckdb::Error>>()?
+    };
+    let temporary: Vec<String> = {
+        let mut stmt = conn.prepare("SELECT table_name FROM duckdb_tables() WHERE database_name = 'temp';")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect::<Result<Vec<String>, duckdb::Error>>()?
+    };
+    conn.execute_batch("USE memory;")?;
+    for database in attached.iter() {
+        conn.execute_batch(&format!("DETACH \"{}\";", database))?;
+    }
+    for table in temporary.iter() {
+        conn.execute_batch(&format!("DROP TABLE temp.\"{}\";", table))?;
+    }
+    Ok(())
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.pooled.as_ref().unwrap().conn
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let Some(pooled) = self.pooled.take() else {
+            return;
+        };
+        // a connection that can't be reset is closed, with its scratch directory
+        if let Err(e) = reset(&pooled.conn) {
+            debug!("closing pooled connection - {:?}", e);
+            return;
+        }
+        let mut idle = idle().lock().unwrap();
+        if idle.owners > 0 && idle.connections.len() < POOL_SIZE {
+            idle.connections.push(pooled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    #[test]
+    fn batches_start_empty() {
+        let _pool = pool();
+        for _ in 0..2 {
+            let conn = open_in_memory("test").unwrap();
+            let tables: i64 = conn
+                .query_row("SELECT count() FROM duckdb_tables() WHERE NOT internal;", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(tables, 0);
+            conn.execute_batch("CREATE TABLE flows AS SELECT 1 AS x; CREATE TEMP TABLE scores AS SELECT 2 AS y;")
+                .unwrap();
+            assert!(conn.pooled.as_ref().unwrap()._scratch.path.is_dir());
+        }
+    }
+
+    #[test]
+    fn s3_is_enabled_per_stage() {
+        let (id, other) = (context::next(), context::next());
+        std::thread::spawn(move || {
+            context::enter(id);
+            enable_s3();
+            assert!(s3().load(Ordering::Relaxed));
+        })
+        .join()
+        .unwrap();
+        std::thread::spawn(move || {
+            context::enter(other);
+            assert!(!s3().load(Ordering::Relaxed));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn sweep_keeps_other_stages_of_the_process() {
+        let root = PathBuf::from(test_dir("scratch-sweep"));
+        let (id, sibling) = (context::next(), context::next());
+        let pid = std::process::id();
+        let stale = root.join(format!("gnat-sweep-{}-{}-0", pid, id));
+        let live = root.join(format!("gnat-sweep-{}-{}-0", pid, sibling));
+        let dead = root.join(format!("gnat-sweep-{}-{}-0", u32::MAX, sibling));
+        for dir in [&stale, &live, &dead] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        let root_spec = String::from(root.to_string_lossy());
+        std::thread::spawn(move || {
+            context::enter(id);
+            set_root("sweep", &root_spec);
+        })
+        .join()
+        .unwrap();
+        assert!(!stale.exists());
+        assert!(live.is_dir());
+        assert!(!dead.exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
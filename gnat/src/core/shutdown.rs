@@ -0,0 +1,223 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// SIGTERM/SIGINT handling for the stage run loops
+//
+// The handler only sets a flag; run loops check requested() between files
+// and while idle so the batch in flight completes (and its output is renamed
+// into place) before the process exits.
+//
+// SIGUSR1 and SIGHUP come from the gnat_run control API: SIGUSR1 cuts an
+// idle wait short so the stage scans its input right away, and SIGHUP
+// asks the stages with rule or mapping files to reload them before the
+// next file. A signal reaches every stage of the process; the control API
+// wakes one stage of gnat_run with wake() and reload() instead.
+//
+// A stage that stops calls exit(). A standalone binary exits the process;
+// a stage on a gnat_run thread unwinds that thread with an Exit, which
+// gnat_run reports before stopping the other stages.
+//
+
+use crate::core::context::{self, Local};
+use crate::core::health;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+// signals received, which each stage compares with the last it saw
+static WAKES: AtomicU64 = AtomicU64::new(0);
+static RELOADS: AtomicU64 = AtomicU64::new(0);
+static REQUESTS: Local<Requests> = Local::new();
+
+struct Requests {
+    wakes_seen: AtomicU64,
+    reloads_seen: AtomicU64,
+    wake: AtomicBool,
+    reload: AtomicBool,
+}
+
+//
+// Payload of a stage thread unwound by exit()
+//
+#[derive(Debug)]
+pub struct Exit(pub i32);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    match signal {
+        libc::SIGUSR1 => {
+            WAKES.fetch_add(1, Ordering::SeqCst);
+        }
+        libc::SIGHUP => {
+            RELOADS.fetch_add(1, Ordering::SeqCst);
+            WAKES.fetch_add(1, Ordering::SeqCst);
+        }
+        _ => SHUTDOWN.store(true, Ordering::SeqCst),
+    }
+}
+
+fn requests(id: usize) -> &'static Requests {
+    REQUESTS.get_or_init_for(id, || Requests {
+        wakes_seen: AtomicU64::new(WAKES.load(Ordering::SeqCst)),
+        reloads_seen: AtomicU64::new(RELOADS.load(Ordering::SeqCst)),
+        wake: AtomicBool::new(false),
+        reload: AtomicBool::new(false),
+    })
+}
+
+pub fn install() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGUSR1, handler);
+        libc::signal(libc::SIGHUP, handler);
+    }
+}
+
+pub fn requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+//
+// Stop every stage of the process, as SIGTERM does
+//
+pub fn request() {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+//
+// Wake stage id, as SIGUSR1 does
+//
+pub fn wake(id: usize) {
+    requests(id).wake.store(true, Ordering::SeqCst);
+}
+
+//
+// Make stage id reload its files, as SIGHUP does
+//
+pub fn reload(id: usize) {
+    let requests = requests(id);
+    requests.reload.store(true, Ordering::SeqCst);
+    requests.wake.store(true, Ordering::SeqCst);
+}
+
+//
+// Whether a wake-up was requested since the last call
+//
+pub fn woken() -> bool {
+    let requests = requests(context::current());
+    let wakes = WAKES.load(Ordering::SeqCst);
+    let signaled = requests.wakes_seen.swap(wakes, Ordering::SeqCst) != wakes;
+    requests.wake.swap(false, Ordering::SeqCst) || signaled
+}
+
+//
+// Whether a reload was requested since the last call
+//
+pub fn reload_requested() -> bool {
+    let requests = requests(context::current());
+    let reloads = RELOADS.load(Ordering::SeqCst);
+    let signaled = requests.reloads_seen.swap(reloads, Ordering::SeqCst) != reloads;
+    requests.reload.swap(false, Ordering::SeqCst) || signaled
+}
+
+//
+// Stop the stage with code: exit a standalone binary, or unwind the
+// gnat_run thread of the stage
+//
+pub fn exit(code: i32) -> ! {
+    if context::current() == 0 {
+        std::process::exit(code)
+    }
+    std::panic::resume_unwind(Box::new(Exit(code)))
+}
+
+//
+// Sleep for the interval in short steps, or until woken; returns false if
+// shutdown was requested
+//
+pub fn sleep(interval: Duration) -> bool {
+    let step = Duration::from_millis(250);
+    let mut remaining = interval;
+    while !remaining.is_zero() {
+        if requested() {
+            return false;
+        }
+        if woken() {
+            break;
+        }
+        health::beat();
+        let delay = remaining.min(step);
+        thread::sleep(delay);
+        remaining -= delay;
+    }
+    !requested()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn requests_reach_one_stage() {
+        let (id, other) = (context::next(), context::next());
+        thread::spawn(move || {
+            context::enter(id);
+            assert!(!woken());
+            assert!(!reload_requested());
+            wake(id);
+            // another stage's requests aren't seen
+            reload(other);
+            assert!(woken());
+            assert!(!woken());
+            assert!(!reload_requested());
+            reload(id);
+            assert!(reload_requested());
+            assert!(woken());
+        })
+        .join()
+        .unwrap();
+        thread::spawn(move || {
+            context::enter(other);
+            assert!(reload_requested());
+            assert!(woken());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn wake_cuts_sleep_short() {
+        let id = context::next();
+        let sleeper = thread::spawn(move || {
+            context::enter(id);
+            let start = Instant::now();
+            let slept = sleep(Duration::from_secs(60));
+            (slept, start.elapsed())
+        });
+        thread::sleep(Duration::from_millis(300));
+        wake(id);
+        let (slept, elapsed) = sleeper.join().unwrap();
+        assert!(slept);
+        assert!(elapsed < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn exit_unwinds_a_stage_thread() {
+        let stage = thread::spawn(|| {
+            context::enter(context::next());
+            exit(3)
+        });
+        let payload = stage.join().unwrap_err();
+        assert_eq!(payload.downcast_ref::<Exit>().map(|e| e.0), Some(3));
+    }
+}
@@ -0,0 +1,395 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Site mapping stage (gnat_site)
+//
+// Sets the "site" column from a mapping of observ and VLAN to a name, read
+// from one of:
+//
+//   CSV    observ,vlan,site rows, with an optional header row
+//   TOML   a .toml file of [[site]] tables:
+//
+//            [[site]]
+//            observ = "hq"       # optional
+//            vlan = 20           # optional
+//            name = "corp-wifi"
+//
+// An empty or "*" observ or vlan matches any. The most specific entry wins:
+// observ and svlan, observ and dvlan, svlan, dvlan, then observ alone.
+// Flows without a match keep the site they have. The mapping is reloaded
+// whenever the file changes, or on SIGHUP.
+//
+
+use crate::core::error::GnatError;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::shutdown;
+use crate::core::spool::process_directory_parallel;
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use duckdb::{params, Connection};
+use serde::Deserialize;
+use tracing::{error, info};
+
+#[derive(Debug, Deserialize)]
+struct SiteEntry {
+    observ: Option<String>,
+    vlan: Option<u16>,
+    name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SiteFile {
+    #[serde(default)]
+    site: Vec<SiteEntry>,
+}
+
+pub struct Sites {
+    pub site_spec: String,
+    last_modified: Option<SystemTime>,
+    // (observ, vlan) -> site; None matches any
+    sites: HashMap<(Option<String>, Option<u16>), String>,
+}
+
+fn wildcard(field: &str) -> Option<&str> {
+    match field.trim() {
+        "" | "*" => None,
+        f => Some(f),
+    }
+}
+
+impl Sites {
+    pub fn new(site_spec: &str) -> Sites {
+        Sites {
+            site_spec: site_spec.to_string(),
+            last_modified: None,
+            sites: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, observ: Option<&str>, vlan: Option<u16>, name: &str) -> Result<(), GnatError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(GnatError::Config(format!(
+                "{}: empty site name for observ {:?}, vlan {:?}",
+                self.site_spec, observ, vlan
+            )));
+        }
+        if observ.is_none() && vlan.is_none() {
+            return Err(GnatError::Config(format!(
+                "{}: site {} needs an observ or a vlan",
+                self.site_spec, name
+            )));
+        }
+        self.sites
+            .insert((observ.map(String::from), vlan), String::from(name));
+        Ok(())
+    }
+
+    fn load_csv(&mut self) -> Result<(), GnatError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(&self.site_spec)
+            .map_err(|e| GnatError::Config(format!("reading {} - {}", self.site_spec, e)))?;
+        for (line, record) in reader.records().enumerate() {
+            let record =
+                record.map_err(|e| GnatError::Config(format!("parsing {} - {}", self.site_spec, e)))?;
+            let (Some(observ), Some(vlan), Some(name)) = (record.get(0), record.get(1), record.get(2)) else {
+                return Err(GnatError::Config(format!(
+                    "{}: line {} is not observ,vlan,site",
+                    self.site_spec,
+                    line + 1
+                )));
+            };
+            let vlan = match wildcard(vlan).map(|v| v.parse::<u16>()) {
+                None => None,
+                Some(Ok(v)) => Some(v),
+                // header row
+                Some(Err(_)) if line == 0 => continue,
+                Some(Err(_)) => {
+                    return Err(GnatError::Config(format!(
+                        "{}: line {}: invalid vlan {}",
+                        self.site_spec,
+                        line + 1,
+                        vlan
+                    )))
+                }
+            };
+            self.add(wildcard(observ), vlan, name)?;
+        }
+        Ok(())
+    }
+
+    fn load_toml(&mut self) -> Result<(), GnatError> {
+        let contents = fs::read_to_string(&self.site_spec)?;
+        let file: SiteFile = toml::from_str(&contents)
+            .map_err(|e| GnatError::Config(format!("parsing {} - {}", self.site_spec, e)))?;
+        for entry in file.site.iter() {
+            let observ = entry.observ.as_deref().and_then(wildcard);
+            self.add(observ, entry.vlan, &entry.name)?;
+        }
+        Ok(())
+    }
+
+    //
+    // Load the mapping file again on the next refresh, changed or not
+    //
+    pub fn reload(&mut self) {
+        self.last_modified = None;
+    }
+
+    //
+    // (Re)load the mapping file if it changed since the last batch
+    //
+    pub fn refresh(&mut self) -> Result<(), GnatError> {
+        let modified = fs::metadata(&self.site_spec)?.modified()?;
+        if self.last_modified == Some(modified) {
+            return Ok(());
+        }
+        // load into a fresh mapping so a bad file leaves the current one in place
+        let mut fresh = Sites::new(&self.site_spec);
+        if fresh.site_spec.ends_with(".toml") {
+            fresh.load_toml()?;
+        } else {
+            fresh.load_csv()?;
+        }
+        fresh.last_modified = Some(modified);
+        *self = fresh;
+        info!("site: loaded {} sites from {}", self.sites.len(), self.site_spec);
+        Ok(())
+    }
+
+    fn lookup(&self, observ: &str, svlan: Option<u16>, dvlan: Option<u16>) -> Option<&String> {
+        let observ = Some(String::from(observ));
+        // a flow without a vlan matches no vlan entry
+        let vlans = [svlan, dvlan];
+        vlans
+            .iter()
+            .flatten()
+            .map(|vlan| (observ.clone(), Some(*vlan)))
+            .chain(vlans.iter().flatten().map(|vlan| (None, Some(*vlan))))
+            .chain(std::iter::once((observ.clone(), None)))
+            .find_map(|key| self.sites.get(&key))
+    }
+
+    pub fn site_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("site")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let sql_command = format!("CREATE TABLE memtable AS {};", source);
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+        let pairs = self.map(&conn, input_spec)?;
+        let sql_command = format!(
+            "COPY memtable TO '{}' ({});",
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+        info!("site: {} [{} vlan pairs]", input_spec, pairs);
+        Ok(())
+    }
+
+    //
+    // Set the site of the flows in memtable; returns the number of observ
+    // and VLAN pairs seen
+    //
+    pub fn map(&self, conn: &Connection, input_spec: &str) -> Result<usize, GnatError> {
+        if let Err(e) = conn.execute_batch(
+            "CREATE TABLE sites (observ VARCHAR, svlan USMALLINT, dvlan USMALLINT, site VARCHAR);",
+        ) {
+            error!("mapping {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        //
+        // map the distinct observ and VLAN pairs of the batch
+        //
+        let keys: Vec<(String, Option<u16>, Option<u16>)> = {
+            let mut stmt = conn.prepare("SELECT DISTINCT observ, svlan, dvlan FROM memtable;")?;
+            match stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))) {
+                Ok(rows) => rows.filter_map(|r| r.ok()).collect(),
+                Err(e) => {
+                    error!("reading {} - {:?}", input_spec, e);
+                    return Err(e.into());
+                }
+            }
+        };
+        {
+            let mut appender = conn.appender("sites")?;
+            for (observ, svlan, dvlan) in keys.iter() {
+                let Some(site) = self.lookup(observ, *svlan, *dvlan) else {
+                    continue;
+                };
+                if let Err(e) = appender.append_row(params![observ, svlan, dvlan, site]) {
+                    error!("mapping {} - {:?}", input_spec, e);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let sql_command = "UPDATE memtable SET site = s.site FROM sites s
+            WHERE s.observ = memtable.observ
+                AND s.svlan IS NOT DISTINCT FROM memtable.svlan
+                AND s.dvlan IS NOT DISTINCT FROM memtable.dvlan;
+            DROP TABLE sites;";
+        if let Err(e) = conn.execute_batch(sql_command) {
+            error!("mapping {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+        Ok(keys.len())
+    }
+}
+
+pub fn site(
+    site_spec: &String,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+    workers: usize,
+) -> Result<(), std::io::Error> {
+    info!("site spec: {}", site_spec);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("polling: {}", polling);
+    info!("workers: {}", workers);
+
+    let mut sites = Sites::new(site_spec);
+    sites.refresh()?;
+    let sites = RwLock::new(sites);
+
+    process_directory_parallel(
+        "site",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        workers,
+        |src_path, tmp_path| {
+            {
+                // keep mapping with the previous file if the new one is unreadable
+                let mut sites = sites.write().unwrap();
+                if shutdown::reload_requested() {
+                    sites.reload();
+                }
+                if let Err(e) = sites.refresh() {
+                    error!("reloading {} - {:?}", sites.site_spec, e);
+                }
+            }
+            sites.read().unwrap().site_file(src_path, tmp_path)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    fn loaded(site_spec: &str, contents: &str) -> Sites {
+        fs::write(site_spec, contents).unwrap();
+        let mut sites = Sites::new(site_spec);
+        sites.refresh().unwrap();
+        sites
+    }
+
+    #[test]
+    fn most_specific_entry_wins() {
+        let dir = test_dir("site-lookup");
+        let sites = loaded(
+            &format!("{}/sites.csv", dir),
+            "observ,vlan,site\nhq,,hq\nhq,20,hq-wifi\n*,30,any-voice\n,40,any-guest\n",
+        );
+        let site = |observ: &str, svlan: Option<u16>, dvlan: Option<u16>| {
+            sites.lookup(observ, svlan, dvlan).map(String::as_str)
+        };
+        assert_eq!(site("hq", Some(20), None), Some("hq-wifi"));
+        assert_eq!(site("hq", Some(30), Some(20)), Some("hq-wifi"));
+        assert_eq!(site("hq", Some(30), None), Some("any-voice"));
+        assert_eq!(site("lab", None, Some(40)), Some("any-guest"));
+        assert_eq!(site("hq", None, None), Some("hq"));
+        assert_eq!(site("lab", Some(20), None), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn load_rejects_invalid_entries() {
+        let dir = test_dir("site-load");
+        let toml = loaded(
+            &format!("{}/sites.toml", dir),
+            "[[site]]\nobserv = \"hq\"\nname = \"hq\"\n\n[[site]]\nvlan = 20\nname = \"wifi\"\n",
+        );
+        assert_eq!(toml.sites.len(), 2);
+
+        let site_spec = format!("{}/bad.csv", dir);
+        for contents in ["*,*,nowhere\n", "hq,20,\n", "hq,20\n", "hq,20,hq\nhq,vlan,hq\n"] {
+            fs::write(&site_spec, contents).unwrap();
+            assert!(Sites::new(&site_spec).refresh().is_err(), "{}", contents);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn site_file_sets_mapped_sites() {
+        let dir = test_dir("site-file");
+        let mut sites = loaded(&format!("{}/sites.csv", dir), "hq,20,hq-wifi\n");
+        let input_spec = format!("{}/flows.parquet", dir);
+        let output_spec = format!("{}/flows.out.parquet", dir);
+        schema::write_test_flows(
+            &input_spec,
+            "SELECT * FROM (VALUES
+                (1, 'hq', 20, NULL, NULL),
+                (2, 'hq', 10, 20, NULL),
+                (3, 'hq', 10, NULL, 'old'))
+                t(dur, observ, svlan, dvlan, site)",
+        );
+
+        // a mapping that no longer loads leaves the previous one in place
+        fs::write(&sites.site_spec, "hq,20\n").unwrap();
+        sites.reload();
+        assert!(sites.refresh().is_err());
+        sites.site_file(&input_spec, &output_spec).unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!("SELECT site FROM '{}' ORDER BY dur;", output_spec))
+            .unwrap();
+        let mapped: Vec<Option<String>> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            mapped,
+            vec![
+                Some(String::from("hq-wifi")),
+                Some(String::from("hq-wifi")),
+                Some(String::from("old"))
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,904 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Spool directory scanner shared by parquet-to-parquet stages
+//
+
+use crate::core::coalesce::Coalescer;
+use crate::core::context;
+use crate::core::context::Local;
+use crate::core::error::GnatError;
+use crate::core::lineage;
+use crate::core::logging;
+use crate::core::scratch;
+use crate::core::shutdown;
+use crate::core::watch::{self, Watch};
+use crate::core::watermark;
+
+use chrono::Utc;
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+//
+// What to do with a file process() rejects: retry it up to `retries` more
+// times, then quarantine it (with an .error sidecar) in the dead-letter
+// directory, or move it to processed_spec as .err when none is set. With
+// neither, it stays in the input directory as .err next to the sidecar.
+// Fatal errors (see GnatError::retryable) are not retried.
+//
+struct FailurePolicy {
+    retries: u32,
+    deadletter_spec: String,
+}
+
+static FAILURE_POLICY: Local<FailurePolicy> = Local::new();
+
+pub fn set_failure_policy(retries: u32, deadletter_spec: &String) {
+    info!("retries: {}", retries);
+    info!("deadletter spec: {}", deadletter_spec);
+    let _ = FAILURE_POLICY.set(FailurePolicy {
+        retries,
+        deadletter_spec: deadletter_spec.clone(),
+    });
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SpoolArgs {
+    /// extra attempts for a file that fails to process
+    #[arg(long)]
+    pub retries: Option<u32>,
+
+    /// quarantine directory for files that still fail
+    #[arg(long)]
+    pub deadletter: Option<String>,
+}
+
+//
+// Set the failure policy of a stage's --retries and --deadletter arguments
+// and return the dead-letter directory; exits on an invalid one
+//
+pub fn configure(args: &SpoolArgs) -> String {
+    let deadletter_spec = args.deadletter.clone().unwrap_or_default();
+    if !deadletter_spec.is_empty() && !Path::new(&deadletter_spec).is_dir() {
+        error!("invalid --deadletter directory {}", deadletter_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+    set_failure_policy(args.retries.unwrap_or(0), &deadletter_spec);
+    deadletter_spec
+}
+
+fn quarantine(
+    stage: &str,
+    src_path: &String,
+    deadletter_path: &String,
+    attempts: u32,
+    error: &GnatError,
+) -> Result<(), std::io::Error> {
+    let sidecar_path = format!("{}.error", deadletter_path);
+    let sidecar = format!(
+        "stage: {}\nsource: {}\nattempts: {}\nfailed: {}\nerror: {}\n",
+        stage,
+        src_path,
+        attempts,
+        Utc::now().to_rfc3339(),
+        error
+    );
+    fs::write(&sidecar_path, sidecar)?;
+    fs::rename(src_path, deadletter_path)?;
+    warn!("{} quarantined {} -> {}", stage, src_path, deadletter_path);
+    Ok(())
+}
+
+//
+// Quarantine a claimed file in the dead-letter directory or, without one,
+// back in the input directory as .err, so the only copy is never removed
+//
+fn set_aside(
+    stage: &str,
+    policy: &FailurePolicy,
+    src_path: &String,
+    input_path: &String,
+    file_name: &String,
+    attempts: u32,
+    error: &GnatError,
+) -> Result<(), std::io::Error> {
+    let deadletter_path = if policy.deadletter_spec.is_empty() {
+        format!("{}.err", input_path)
+    } else {
+        format!("{}/{}", policy.deadletter_spec, file_name)
+    };
+    quarantine(stage, src_path, &deadletter_path, attempts, error)
+}
+
+fn failure_policy() -> &'static FailurePolicy {
+    FAILURE_POLICY.get_or_init(|| FailurePolicy {
+        retries: 0,
+        deadletter_spec: String::new(),
+    })
+}
+
+//
+// Run process() with the configured retries; returns (result, attempts)
+//
+fn attempt<F>(
+    stage: &str,
+    policy: &FailurePolicy,
+    process: &mut F,
+    src_path: &String,
+    tmp_path: &String,
+) -> (Result<(), GnatError>, u32)
+where
+    F: FnMut(&String, &String) -> Result<(), GnatError>,
+{
+    let retry_interval = Duration::from_secs(5);
+    let mut attempts = 1;
+    let mut result = process(src_path, tmp_path);
+    while let Err(e) = &result {
+        if attempts > policy.retries {
+            break;
+        }
+        if e.fatal() {
+            warn!("{} not retrying {} - {}", stage, src_path, e);
+            break;
+        }
+        let _ = fs::remove_file(tmp_path);
+        warn!("{} retrying {} [attempt {}]", stage, src_path, attempts + 1);
+        if !shutdown::sleep(retry_interval) {
+            break;
+        }
+        attempts += 1;
+        result = process(src_path, tmp_path);
+    }
+    (result, attempts)
+}
+
+//
+// Non-hidden parquet files currently in input_spec, as (file_name, src_path)
+//
+fn list_files(input_spec: &String) -> Result<Vec<(String, String)>, std::io::Error> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(input_spec)? {
+        let file = entry?;
+        let file_name = String::from(file.file_name().to_string_lossy());
+        if file_name.starts_with(".") || !file_name.ends_with(".parquet") {
+            continue;
+        }
+        files.push((file_name, String::from(file.path().to_string_lossy())));
+    }
+    // oldest first, since spool files are named by time
+    files.sort();
+    Ok(files)
+}
+
+pub(crate) fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let status =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if status != 0 {
+        return String::from("localhost");
+    }
+    let len = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+//
+// Files are claimed by renaming them into a hidden directory owned by this
+// instance, so stages sharing a spool directory never process a file twice:
+// only one rename of a given file can succeed. The instance holds a flock on
+// the CLAIM_LOCK file of its directory while it runs; the kernel drops it
+// when the process exits, however it exits, so a claim is stale exactly when
+// its lock can be taken. Pids are not relied on: they are reused, and
+// instances in different containers often share one.
+//
+const CLAIM_LOCK: &str = ".lock";
+
+struct Claim {
+    spec: String,
+    _lock: fs::File,
+}
+
+fn claim_prefix(stage: &str) -> String {
+    format!(".claim-{}-{}-", stage, hostname())
+}
+
+pub(crate) fn try_lock(file: &fs::File) -> bool {
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 }
+}
+
+//
+// Create this instance's claim directory, locked before it is renamed into
+// place so recover_claims() never sees it unlocked
+//
+fn claim_dir(stage: &str, input_spec: &String) -> Result<Claim, std::io::Error> {
+    static CLAIMS: AtomicUsize = AtomicUsize::new(0);
+    let id = format!(
+        "{}-{}-{}",
+        std::process::id(),
+        Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        CLAIMS.fetch_add(1, Ordering::Relaxed)
+    );
+    let staging_spec = format!("{}/.claiming-{}-{}", input_spec, stage, id);
+    fs::create_dir(&staging_spec)?;
+    let lock = fs::File::create(format!("{}/{}", staging_spec, CLAIM_LOCK))?;
+    if !try_lock(&lock) {
+        return Err(std::io::Error::other(format!("locking {}", staging_spec)));
+    }
+    let spec = format!("{}/{}{}", input_spec, claim_prefix(stage), id);
+    fs::rename(&staging_spec, &spec)?;
+    Ok(Claim { spec, _lock: lock })
+}
+
+impl Claim {
+    //
+    // Remove the directory unless a file is still claimed in it
+    //
+    fn release(self) {
+        let claimed = fs::read_dir(&self.spec)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .any(|entry| entry.file_name() != CLAIM_LOCK)
+            })
+            .unwrap_or(true);
+        if !claimed {
+            let _ = fs::remove_file(format!("{}/{}", self.spec, CLAIM_LOCK));
+            let _ = fs::remove_dir(&self.spec);
+        }
+    }
+}
+
+//
+// Whether a claim directory of an earlier version, named by pid alone and
+// without a lock, belongs to a running process
+//
+fn legacy_running(pid: libc::pid_t) -> bool {
+    pid as u32 != std::process::id()
+        && (unsafe { libc::kill(pid, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+//
+// Batch commit records: once process() succeeds, "<claimed input>.commit"
+// is written and synced next to the claimed input, naming the output it
+// produced, before the output is renamed into place. It is removed once
+// the input has been moved along. An instance stopping in between leaves
+// the record behind, and recover_claims() then completes the batch rather
+// than processing the input again and duplicating its output downstream.
+//
+fn commit_path(claimed_path: &String) -> String {
+    format!("{}.commit", claimed_path)
+}
+
+fn write_commit(
+    claimed_path: &String,
+    tmp_path: &String,
+    dst_path: &String,
+) -> Result<(), std::io::Error> {
+    if Path::new(tmp_path).exists() {
+        fs::File::open(tmp_path)?.sync_all()?;
+    }
+    let mut record = fs::File::create(commit_path(claimed_path))?;
+    writeln!(record, "{}\t{}", tmp_path, dst_path)?;
+    record.sync_all()
+}
+
+//
+// Finish the batch of a commit record left by a stopped instance: publish
+// the output if it is still hidden and move the input to processed_spec
+//
+fn complete(
+    stage: &str,
+    processed_spec: &String,
+    claimed_path: &Path,
+    file_name: &String,
+    record: &str,
+) -> Result<(), std::io::Error> {
+    if let Some((tmp_path, dst_path)) = record.trim_end().split_once('\t') {
+        if Path::new(tmp_path).exists() {
+            match fs::rename(tmp_path, dst_path) {
+                // an instance directory of coalesce.rs already taken over
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    let fallback_path = Path::new(tmp_path).with_file_name(file_name);
+                    fs::rename(tmp_path, fallback_path)?;
+                }
+                result => result?,
+            }
+        }
+    }
+    if processed_spec.is_empty() {
+        fs::remove_file(claimed_path)?;
+    } else {
+        fs::rename(claimed_path, format!("{}/{}", processed_spec, file_name))?;
+    }
+    warn!(
+        "{} completed {} committed by a stopped instance",
+        stage, file_name
+    );
+    Ok(())
+}
+
+//
+// Return files claimed by instances of this stage on this host that are no
+// longer running, e.g. after a crash, or complete their batches when they
+// were committed; claims from other hosts are left alone. The lock of a
+// stale claim is held while it is recovered, so two instances starting
+// together don't both recover it.
+//
+fn recover_claims(
+    stage: &str,
+    input_spec: &String,
+    processed_spec: &String,
+) -> Result<(), std::io::Error> {
+    let prefix = claim_prefix(stage);
+    for entry in fs::read_dir(input_spec)? {
+        let dir = entry?;
+        let dir_name = String::from(dir.file_name().to_string_lossy());
+        let Some(id) = dir_name.strip_prefix(&prefix) else {
+            continue;
+        };
+        let _lock = match fs::File::open(dir.path().join(CLAIM_LOCK)) {
+            Ok(lock) if try_lock(&lock) => Some(lock),
+            Ok(_) => continue,
+            Err(_) => match id.parse::<libc::pid_t>() {
+                Ok(pid) if legacy_running(pid) => continue,
+                // a directory being released, or one of an earlier version
+                _ => None,
+            },
+        };
+        for entry in fs::read_dir(dir.path())? {
+            let file = entry?;
+            let file_name = String::from(file.file_name().to_string_lossy());
+            if file_name.ends_with(".commit") || file_name == CLAIM_LOCK {
+                continue;
+            }
+            let claimed_path = String::from(file.path().to_string_lossy());
+            if let Ok(record) = fs::read_to_string(commit_path(&claimed_path)) {
+                complete(stage, processed_spec, &file.path(), &file_name, &record)?;
+                continue;
+            }
+            let src_path = format!("{}/{}", input_spec, file_name);
+            fs::rename(file.path(), &src_path)?;
+            warn!(
+                "{} recovered {} from stopped instance {}",
+                stage, src_path, id
+            );
+        }
+        // commit records whose input was already moved along
+        fs::remove_dir_all(dir.path())?;
+    }
+    Ok(())
+}
+
+//
+// Move src_path into claim_spec; None when another instance claimed it first
+//
+fn claim(
+    src_path: &String,
+    file_name: &String,
+    claim_spec: &String,
+) -> Result<Option<String>, std::io::Error> {
+    let claimed_path = format!("{}/{}", claim_spec, file_name);
+    match fs::rename(src_path, &claimed_path) {
+        Ok(_) => Ok(Some(claimed_path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+//
+// Claim one input, run process() on it and move it along; returns false
+// when the file was handed back because a shutdown interrupted it
+//
+fn process_file<F>(
+    stage: &str,
+    output: &Coalescer,
+    processed_spec: &String,
+    claim_spec: &String,
+    process: &mut F,
+    file_name: &String,
+    input_path: &String,
+) -> Result<bool, std::io::Error>
+where
+    F: FnMut(&String, &String) -> Result<(), GnatError>,
+{
+    let policy = failure_policy();
+    let Some(src_path) = claim(input_path, file_name, claim_spec)? else {
+        return Ok(true);
+    };
+    let src_path = &src_path;
+    let _batch = logging::batch(file_name);
+    let _lineage = lineage::begin(stage, &[(file_name.clone(), src_path.clone())]);
+
+    let tmp_path = format!("{}/.{}-{}", output.output_spec, stage, file_name);
+    let dst_path = output.dst_path(file_name);
+    let (result, attempts) = attempt(stage, policy, process, src_path, &tmp_path);
+
+    let processed_path = match result {
+        Ok(()) => {
+            write_commit(src_path, &tmp_path, &dst_path)?;
+            if Path::new(&tmp_path).exists() {
+                fs::rename(&tmp_path, &dst_path)?;
+            }
+            output.add(file_name)?;
+            format!("{}/{}", processed_spec, file_name)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            if shutdown::requested() {
+                // hand the file back for the next run to retry
+                fs::rename(src_path, input_path)?;
+                return Ok(false);
+            }
+            error!(
+                "{} processing {} [{} attempts] - {}",
+                stage, src_path, attempts, e
+            );
+            if !policy.deadletter_spec.is_empty() || processed_spec.is_empty() {
+                set_aside(stage, policy, src_path, input_path, file_name, attempts, &e)?;
+                return Ok(true);
+            }
+            format!("{}/{}.err", processed_spec, file_name)
+        }
+    };
+
+    let moved = if processed_spec.is_empty() {
+        fs::remove_file(src_path)
+    } else {
+        fs::rename(src_path, &processed_path)
+    };
+    if let Err(e) = moved {
+        error!("{} moving {} along - {}", stage, src_path, e);
+        set_aside(stage, policy, src_path, input_path, file_name, attempts, &GnatError::Io(e))?;
+    }
+    let _ = fs::remove_file(commit_path(src_path));
+    Ok(true)
+}
+
+//
+// Scan input_spec for parquet files and call process(src_path, tmp_path)
+// for each one. Output is written to a hidden tmp_path in output_spec and
+// renamed into place on success so downstream stages never read partial
+// files; inputs are claimed first (see claim_prefix) and then moved to
+// processed_spec or removed. Failures follow the
+// policy from set_failure_policy() so one bad file never stops the stage.
+// An s3:// input_spec is read through DuckDB httpfs (see process_bucket).
+//
+pub fn process_directory<F>(
+    stage: &str,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+    mut process: F,
+) -> Result<(), std::io::Error>
+where
+    F: FnMut(&String, &String) -> Result<(), GnatError>,
+{
+    // the batches' connections, closed when the stage stops
+    let _pool = scratch::pool();
+    if input_spec.starts_with("s3://") {
+        return process_bucket(
+            stage,
+            input_spec,
+            output_spec,
+            processed_spec,
+            polling,
+            process,
+        );
+    }
+    let poll_interval = Duration::from_secs(1);
+    recover_claims(stage, input_spec, processed_spec)?;
+    let claim = claim_dir(stage, input_spec)?;
+    let output = Coalescer::open(stage, output_spec)?;
+    let watch = Watch::new(input_spec);
+    info!("{} scanner: running [{}]", stage, input_spec);
+    loop {
+        let mut counter = 0;
+        for (file_name, src_path) in list_files(input_spec)? {
+            if shutdown::requested() {
+                break;
+            }
+            if output_spec != input_spec && !watermark::wait(stage, output_spec) {
+                break;
+            }
+            if !process_file(
+                stage,
+                &output,
+                processed_spec,
+                &claim.spec,
+                &mut process,
+                &file_name,
+                &src_path,
+            )? {
+                break;
+            }
+            counter += 1;
+            output.tick()?;
+        }
+        output.tick()?;
+
+        if !polling || shutdown::requested() {
+            break;
+        }
+        if counter == 0 && !watch::wait(&watch, poll_interval) {
+            break;
+        }
+    }
+    output.close()?;
+    // left behind only when a file is still claimed
+    claim.release();
+    Ok(())
+}
+
+#[derive(Debug, clap::Args)]
+pub struct WorkersArgs {
+    /// files processed concurrently
+    #[arg(long)]
+    pub workers: Option<usize>,
+}
+
+impl WorkersArgs {
+    //
+    // Workers of process_directory_parallel(); exits on 0
+    //
+    pub fn count(&self) -> usize {
+        let workers = self.workers.unwrap_or(1);
+        if workers == 0 {
+            error!("invalid --workers {}", workers);
+            shutdown::exit(exitcode::CONFIG)
+        }
+        workers
+    }
+}
+
+//
+// process_directory() with each scan's files shared out to `workers`
+// threads. Every file still maps to one output file, so nothing needs
+// merging afterwards; process() must be safe to call concurrently, each
+// call opening its own DuckDB connection. S3 inputs are processed serially.
+//
+pub fn process_directory_parallel<F>(
+    stage: &str,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+    workers: usize,
+    process: F,
+) -> Result<(), std::io::Error>
+where
+    F: Fn(&String, &String) -> Result<(), GnatError> + Sync,
+{
+    let _pool = scratch::pool();
+    if workers <= 1 || input_spec.starts_with("s3://") {
+        return process_directory(
+            stage,
+            input_spec,
+            output_spec,
+            processed_spec,
+            polling,
+            process,
+        );
+    }
+    let poll_interval = Duration::from_secs(1);
+    recover_claims(stage, input_spec, processed_spec)?;
+    let claim = claim_dir(stage, input_spec)?;
+    let output = Coalescer::open(stage, output_spec)?;
+    let watch = Watch::new(input_spec);
+    info!(
+        "{} scanner: running [{}] with {} workers",
+        stage, input_spec, workers
+    );
+    loop {
+        let files = list_files(input_spec)?;
+        let next = AtomicUsize::new(0);
+        let id = context::current();
+        let counter = thread::scope(|scope| -> Result<usize, std::io::Error> {
+            let handles: Vec<_> = (0..workers.min(files.len()))
+                .map(|_| {
+                    scope.spawn(|| -> Result<usize, std::io::Error> {
+                        context::enter(id);
+                        let mut process = |src: &String, tmp: &String| process(src, tmp);
+                        let mut counter = 0;
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            if index >= files.len() || shutdown::requested() {
+                                break;
+                            }
+                            if !watermark::wait(stage, output_spec) {
+                                break;
+                            }
+                            let (file_name, src_path) = &files[index];
+                            if !process_file(
+                                stage,
+                                &output,
+                                processed_spec,
+                                &claim.spec,
+                                &mut process,
+                                file_name,
+                                src_path,
+                            )? {
+                                break;
+                            }
+                            counter += 1;
+                            output.tick()?;
+                        }
+                        Ok(counter)
+                    })
+                })
+                .collect();
+            let mut counter = 0;
+            for handle in handles {
+                counter += handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))?;
+            }
+            Ok(counter)
+        })?;
+        output.tick()?;
+
+        if !polling || shutdown::requested() {
+            break;
+        }
+        if counter == 0 && !watch::wait(&watch, poll_interval) {
+            break;
+        }
+    }
+    output.close()?;
+    // left behind only when a file is still claimed
+    claim.release();
+    Ok(())
+}
+
+//
+// S3 inputs: objects under the prefix are listed with glob() and handed to
+// process() as s3:// paths. Objects can't be moved through httpfs, so the
+// keys already handled are recorded in a ledger (processed_spec, or the
+// output directory when unset) instead; a failed key is recorded with its
+// attempts and not retried on later scans.
+//
+fn process_bucket<F>(
+    stage: &str,
+    input_spec: &str,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+    mut process: F,
+) -> Result<(), std::io::Error>
+where
+    F: FnMut(&String, &String) -> Result<(), GnatError>,
+{
+    let poll_interval = Duration::from_secs(60);
+    let policy = failure_policy();
+    let ledger_dir = if processed_spec.is_empty() {
+        output_spec
+    } else {
+        processed_spec
+    };
+    if ledger_dir.starts_with("s3://") {
+        return Err(std::io::Error::other(format!(
+            "{}: S3 input requires a local processed or output directory",
+            stage
+        )));
+    }
+    let ledger_path = format!("{}/.{}-s3.ledger", ledger_dir, stage);
+    let mut handled: HashSet<String> = match fs::read_to_string(&ledger_path) {
+        Ok(contents) => contents
+            .lines()
+            .filter_map(|line| line.split('\t').nth(1))
+            .map(String::from)
+            .collect(),
+        Err(_) => HashSet::new(),
+    };
+    let mut ledger = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&ledger_path)?;
+
+    let output = Coalescer::open(stage, output_spec)?;
+    scratch::enable_s3();
+    let glob_spec = format!("{}/*.parquet", input_spec.trim_end_matches('/'));
+    info!("{} scanner: running [{}]", stage, glob_spec);
+    loop {
+        let objects: Vec<String> = {
+            let conn = scratch::open_in_memory(stage)?;
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT file FROM glob('{}') ORDER BY file;",
+                    glob_spec
+                ))
+                .map_err(std::io::Error::other)?;
+            let rows = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(std::io::Error::other)?;
+            rows.filter_map(|r| r.ok()).collect()
+        };
+
+        let mut counter = 0;
+        for src_path in objects.iter() {
+            if handled.contains(src_path) {
+                continue;
+            }
+            if shutdown::requested() || !watermark::wait(stage, output_spec) {
+                break;
+            }
+            let file_name = String::from(src_path.rsplit('/').next().unwrap_or(src_path));
+            let _batch = logging::batch(&file_name);
+            let _lineage = lineage::begin(stage, &[(file_name.clone(), src_path.clone())]);
+
+            let tmp_path = format!("{}/.{}-{}", output_spec, stage, file_name);
+            let dst_path = output.dst_path(&file_name);
+            let (result, attempts) = attempt(stage, policy, &mut process, src_path, &tmp_path);
+            let status = match result {
+                Ok(()) => {
+                    if Path::new(&tmp_path).exists() {
+                        fs::rename(&tmp_path, &dst_path)?;
+                    }
+                    output.add(&file_name)?;
+                    String::from("ok")
+                }
+                Err(e) => {
+                    let _ = fs::remove_file(&tmp_path);
+                    if shutdown::requested() {
+                        break;
+                    }
+                    error!(
+                        "{} processing {} [{} attempts] - {}",
+                        stage, src_path, attempts, e
+                    );
+                    format!("err:{}", attempts)
+                }
+            };
+            writeln!(
+                ledger,
+                "{}\t{}\t{}",
+                status,
+                src_path,
+                Utc::now().to_rfc3339()
+            )?;
+            handled.insert(src_path.clone());
+            counter += 1;
+            output.tick()?;
+        }
+        output.tick()?;
+
+        if !polling || shutdown::requested() {
+            break;
+        }
+        if counter == 0 && !shutdown::sleep(poll_interval) {
+            break;
+        }
+    }
+    output.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    #[test]
+    fn recover_claims_of_stopped_instances() {
+        let input_spec = test_dir("spool-recover");
+        let running = claim_dir("test", &input_spec).unwrap();
+        let stopped = claim_dir("test", &input_spec).unwrap();
+        assert_ne!(running.spec, stopped.spec);
+        fs::write(format!("{}/a.parquet", running.spec), "a").unwrap();
+        fs::write(format!("{}/b.parquet", stopped.spec), "b").unwrap();
+        let stopped_spec = stopped.spec.clone();
+        drop(stopped);
+
+        recover_claims("test", &input_spec, &String::new()).unwrap();
+        assert!(Path::new(&format!("{}/a.parquet", running.spec)).exists());
+        assert!(Path::new(&format!("{}/b.parquet", input_spec)).exists());
+        assert!(!Path::new(&stopped_spec).exists());
+
+        // still claiming a.parquet
+        let running_spec = running.spec.clone();
+        running.release();
+        assert!(Path::new(&running_spec).exists());
+        let _ = fs::remove_dir_all(&input_spec);
+    }
+
+    #[test]
+    fn recover_completes_committed_claims() {
+        let input_spec = test_dir("spool-commit");
+        let output_spec = test_dir("spool-commit-output");
+        let stopped = claim_dir("test", &input_spec).unwrap();
+        let claimed_path = format!("{}/a.parquet", stopped.spec);
+        let tmp_path = format!("{}/.test-a.parquet", output_spec);
+        let dst_path = format!("{}/a.parquet", output_spec);
+        fs::write(&claimed_path, "input").unwrap();
+        fs::write(&tmp_path, "output").unwrap();
+        write_commit(&claimed_path, &tmp_path, &dst_path).unwrap();
+        drop(stopped);
+
+        recover_claims("test", &input_spec, &String::new()).unwrap();
+        assert_eq!(fs::read_to_string(&dst_path).unwrap(), "output");
+        assert!(!Path::new(&format!("{}/a.parquet", input_spec)).exists());
+        assert_eq!(list_files(&input_spec).unwrap().len(), 0);
+        let _ = fs::remove_dir_all(&input_spec);
+        let _ = fs::remove_dir_all(&output_spec);
+    }
+
+    #[test]
+    fn claim_once() {
+        let input_spec = test_dir("spool-claim");
+        let (first, second) = (
+            claim_dir("test", &input_spec).unwrap(),
+            claim_dir("test", &input_spec).unwrap(),
+        );
+        let src_path = format!("{}/a.parquet", input_spec);
+        let file_name = String::from("a.parquet");
+        fs::write(&src_path, "a").unwrap();
+        assert!(claim(&src_path, &file_name, &first.spec).unwrap().is_some());
+        assert!(claim(&src_path, &file_name, &second.spec).unwrap().is_none());
+        let _ = fs::remove_dir_all(&input_spec);
+    }
+
+    //
+    // process_directory() on a thread of its own stage, whose failure
+    // policy no other test shares; process() fails every file when fail
+    //
+    fn process_once(input_spec: &str, output_spec: &str, processed_spec: &str, deadletter_spec: &str, fail: bool) {
+        let (input_spec, output_spec) = (input_spec.to_string(), output_spec.to_string());
+        let (processed_spec, deadletter_spec) = (processed_spec.to_string(), deadletter_spec.to_string());
+        thread::spawn(move || {
+            context::enter(context::next());
+            set_failure_policy(0, &deadletter_spec);
+            process_directory("test", &input_spec, &output_spec, &processed_spec, false, |_, _| {
+                match fail {
+                    true => Err(GnatError::Config(String::from("bad file"))),
+                    false => Ok(()),
+                }
+            })
+            .unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn failed_input_is_kept_without_processed() {
+        let input_spec = test_dir("spool-keep");
+        let output_spec = test_dir("spool-keep-output");
+        fs::write(format!("{}/a.parquet", input_spec), "a").unwrap();
+
+        process_once(&input_spec, &output_spec, "", "", true);
+        assert_eq!(fs::read_to_string(format!("{}/a.parquet.err", input_spec)).unwrap(), "a");
+        let sidecar = fs::read_to_string(format!("{}/a.parquet.err.error", input_spec)).unwrap();
+        assert!(sidecar.contains("bad file"));
+        assert_eq!(list_files(&input_spec).unwrap().len(), 0);
+        let _ = fs::remove_dir_all(&input_spec);
+        let _ = fs::remove_dir_all(&output_spec);
+    }
+
+    #[test]
+    fn unmovable_input_is_quarantined() {
+        let input_spec = test_dir("spool-unmovable");
+        let output_spec = test_dir("spool-unmovable-output");
+        let deadletter_spec = test_dir("spool-unmovable-deadletter");
+        let processed_spec = format!("{}/missing", output_spec);
+        fs::write(format!("{}/a.parquet", input_spec), "a").unwrap();
+
+        process_once(&input_spec, &output_spec, &processed_spec, &deadletter_spec, false);
+        assert_eq!(fs::read_to_string(format!("{}/a.parquet", deadletter_spec)).unwrap(), "a");
+        assert!(Path::new(&format!("{}/a.parquet.error", deadletter_spec)).exists());
+        for dir in [input_spec, output_spec, deadletter_spec] {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
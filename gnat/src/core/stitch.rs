@@ -0,0 +1,459 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Session stitching stage
+//
+// YAF exports a long-lived session as a chunk per active timeout (reason
+// "active"), each chunk counting only its own packets. This stage merges
+// the chunks back into one record per session. A chunk continues the
+// previous one with the same observ, proto, addresses, ports and vlans
+// when it starts within the idle timeout after that chunk ended and, for
+// tcp, its sequence number follows on from the previous chunk's bytes.
+//
+// Counters are summed, stime/etime/dur span the session, uflags, tags and
+// ids_alerts are merged, averages are weighted by packets and maxima kept;
+// the other columns come from the first chunk.
+//
+// A session whose last chunk still ended "active" is held back in a hidden
+// pending file in the output directory until its next chunk arrives, or
+// until the newest flow seen is more than active + idle timeout past it.
+// Files are stitched in name order by a single instance.
+//
+
+use crate::core::error::GnatError;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::shutdown;
+use crate::core::spool::process_directory;
+
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use duckdb::Connection;
+use tracing::{error, info};
+
+const SESSION_KEY: [&str; 8] = [
+    "observ", "proto", "saddr", "daddr", "sport", "dport", "svlan", "dvlan",
+];
+
+const SUMMED: [&str; 12] = [
+    "spkts",
+    "dpkts",
+    "sbytes",
+    "dbytes",
+    "stcpurg",
+    "dtcpurg",
+    "ssmallpktcnt",
+    "dsmallpktcnt",
+    "slargpktcnt",
+    "dlargpktcnt",
+    "snonemptypktcnt",
+    "dnonemptypktcnt",
+];
+
+const MAXIMA: [&str; 7] = [
+    "sstdev",
+    "dstdev",
+    "sstdevpayload",
+    "dstdevpayload",
+    "smaxpktsize",
+    "dmaxpktsize",
+    "score",
+];
+
+// (column, packet count it is averaged over)
+const WEIGHTED: [(&str, &str); 4] = [
+    ("siat", "spkts"),
+    ("diat", "dpkts"),
+    ("sentropy", "spkts"),
+    ("dentropy", "dpkts"),
+];
+
+pub struct Stitcher {
+    pending_spec: String,
+    idle_timeout: u64,
+    active_timeout: u64,
+}
+
+fn columns(conn: &Connection, table: &str) -> Result<Vec<(String, String)>, duckdb::Error> {
+    let mut stmt = conn.prepare(&format!("DESCRIBE {};", table))?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+//
+// Aggregate expression merging the chunks of a session into column;
+// flag_width is the length of the uflags strings
+//
+fn merge(column: &str, sql_type: &str, flag_width: i64) -> String {
+    if SUMMED.contains(&column) {
+        return format!("sum(\"{}\")::{}", column, sql_type);
+    }
+    if MAXIMA.contains(&column) {
+        return format!("max(\"{}\")", column);
+    }
+    if let Some((_, weight)) = WEIGHTED.iter().find(|(c, _)| *c == column) {
+        return format!(
+            "coalesce(sum(\"{0}\"::DOUBLE * \"{1}\") / nullif(sum(\"{1}\"), 0), 0)::{2}",
+            column, weight, sql_type
+        );
+    }
+    match column {
+        "stime" => String::from("min(stime)"),
+        "etime" => String::from("max(etime)"),
+        "dur" => format!(
+            "(epoch_ms(max(etime)) - epoch_ms(min(stime)))::{}",
+            sql_type
+        ),
+        // ratio over the session's bytes
+        "pcr" => String::from(
+            "coalesce(sum(pcr * (sbytes + dbytes)) / nullif(sum(sbytes + dbytes), 0), 0)::FLOAT",
+        ),
+        // one character per flag, '.' when unset, so keep the highest
+        "uflags" if flag_width > 0 => {
+            let positions: Vec<String> = (1..=flag_width)
+                .map(|i| format!("max(substr(uflags, {}, 1))", i))
+                .collect();
+            format!("concat({})", positions.join(", "))
+        }
+        "reason" => String::from("arg_max(reason, etime)"),
+        "model" => String::from("arg_max(model, score)"),
+        "tag" => String::from(
+            "nullif(array_to_string(list_sort(list_distinct(flatten(
+                list(string_split(tag, ',')) FILTER (WHERE tag IS NOT NULL)))), ','), '')",
+        ),
+        "ids_alerts" => String::from(
+            "CASE WHEN count(ids_alerts) > 0 THEN list_sort(list_distinct(flatten(
+                list(ids_alerts) FILTER (WHERE ids_alerts IS NOT NULL)))) END",
+        ),
+        _ => format!("arg_min(\"{}\", stime)", column),
+    }
+}
+
+impl Stitcher {
+    pub fn new(output_spec: &String, idle_timeout: u64, active_timeout: u64) -> Stitcher {
+        Stitcher {
+            pending_spec: format!("{}/.stitch-pending.parquet", output_spec),
+            idle_timeout,
+            active_timeout,
+        }
+    }
+
+    pub fn stitch_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("stitch")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let sql_command = if Path::new(&self.pending_spec).exists() {
+            format!(
+                "CREATE TABLE chunk AS {} UNION ALL BY NAME SELECT * FROM '{}';",
+                source, self.pending_spec
+            )
+        } else {
+            format!("CREATE TABLE chunk AS {};", source)
+        };
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+        let chunk_columns = match columns(&conn, "chunk") {
+            Ok(c) => c,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e.into());
+            }
+        };
+
+        //
+        // number the sessions within each key, then merge their chunks
+        //
+        let flag_width: i64 = if chunk_columns.iter().any(|(c, _)| c == "uflags") {
+            conn.query_row(
+                "SELECT coalesce(max(length(uflags)), 0) FROM chunk;",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+        } else {
+            0
+        };
+        let key = SESSION_KEY.join(", ");
+        let select_list: Vec<String> = chunk_columns
+            .iter()
+            .map(|(column, sql_type)| {
+                if SESSION_KEY.contains(&column.as_str()) {
+                    format!("\"{}\"", column)
+                } else {
+                    format!("{} AS \"{}\"", merge(column, sql_type, flag_width), column)
+                }
+            })
+            .collect();
+        let sql_command = format!(
+            "CREATE TABLE linked AS
+                SELECT *, coalesce(
+                    lag(reason) OVER w = 'active'
+                    AND stime <= lag(etime) OVER w + INTERVAL '{idle} seconds'
+                    AND (proto <> 'tcp' OR
+                        (stcpseq::BIGINT - lag(stcpseq) OVER w + 4294967296) % 4294967296
+                            <= lag(sbytes) OVER w + 1),
+                    false) AS continues
+                FROM chunk
+                WINDOW w AS (PARTITION BY {key} ORDER BY stime, etime);
+             CREATE TABLE session AS
+                SELECT *, sum(CASE WHEN continues THEN 0 ELSE 1 END) OVER (
+                    PARTITION BY {key} ORDER BY stime, etime ROWS UNBOUNDED PRECEDING) AS session_id
+                FROM linked;
+             CREATE TABLE stitched AS
+                SELECT {select_list} FROM session GROUP BY {key}, session_id;
+             CREATE TABLE result AS
+                SELECT *, coalesce(reason = 'active'
+                    AND etime > (SELECT max(etime) FROM stitched) - INTERVAL '{hold} seconds',
+                    false) AS held
+                FROM stitched;",
+            idle = self.idle_timeout,
+            hold = self.active_timeout + self.idle_timeout,
+            key = key,
+            select_list = select_list.join(", "),
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("stitching {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+        let count = |sql_command: &str| -> i64 {
+            conn.query_row(sql_command, [], |row| row.get(0))
+                .unwrap_or(0)
+        };
+        let chunks = count("SELECT count(*) FROM chunk;");
+        let sessions = count("SELECT count(*) FROM result;");
+        let held = count("SELECT count(*) FROM result WHERE held;");
+
+        if sessions > held {
+            let sql_command = format!(
+                "COPY (SELECT * EXCLUDE (held) FROM result WHERE NOT held ORDER BY stime)
+                    TO '{}' ({});",
+                output_spec,
+                schema::copy_options()
+            );
+            if let Err(e) = conn.execute_batch(&sql_command) {
+                error!("writing {} - {:?}", output_spec, e);
+                return Err(e.into());
+            }
+        }
+
+        //
+        // replace the pending file only once the output is written, so a
+        // retry starts again from the same sessions
+        //
+        if held > 0 {
+            let tmp_spec = format!("{}.tmp", self.pending_spec);
+            let sql_command = format!(
+                "COPY (SELECT * EXCLUDE (held) FROM result WHERE held) TO '{}' ({});",
+                tmp_spec,
+                schema::copy_options()
+            );
+            if let Err(e) = conn.execute_batch(&sql_command) {
+                error!("writing {} - {:?}", tmp_spec, e);
+                return Err(e.into());
+            }
+            if let Err(e) = fs::rename(&tmp_spec, &self.pending_spec) {
+                error!("moving {} -> {} - {:?}", tmp_spec, self.pending_spec, e);
+                return Err(e.into());
+            }
+        } else if let Err(e) = fs::remove_file(&self.pending_spec) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                error!("removing {} - {:?}", self.pending_spec, e);
+                return Err(e.into());
+            }
+        }
+        info!(
+            "stitch: {} [{} flows, {} sessions, {} held]",
+            input_spec,
+            chunks,
+            sessions - held,
+            held
+        );
+        Ok(())
+    }
+
+    //
+    // Release the held sessions as a final output file
+    //
+    pub fn flush(&self, output_spec: &String) -> Result<(), std::io::Error> {
+        if !Path::new(&self.pending_spec).exists() {
+            return Ok(());
+        }
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let dst_spec = format!("{}/stitch-{}.parquet", output_spec, seconds);
+        info!("stitch: releasing held sessions to {}", dst_spec);
+        fs::rename(&self.pending_spec, &dst_spec)
+    }
+}
+
+pub fn stitch(
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+    idle_timeout: u64,
+    active_timeout: u64,
+) -> Result<(), std::io::Error> {
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("polling: {}", polling);
+    info!("idle timeout: {}", idle_timeout);
+    info!("active timeout: {}", active_timeout);
+
+    let stitcher = Stitcher::new(output_spec, idle_timeout, active_timeout);
+
+    process_directory(
+        "stitch",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        |src_path, tmp_path| stitcher.stitch_file(src_path, tmp_path),
+    )?;
+
+    // a stopped polling stage keeps its held sessions for the next run
+    if !polling && !shutdown::requested() {
+        stitcher.flush(output_spec)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    // chunks as (proto, sport, seconds from, seconds to, reason, stcpseq, sbytes)
+    fn write_input(path: &str, chunks: &[(&str, u16, u32, u32, &str, u32, u64)]) {
+        let rows: Vec<String> = chunks
+            .iter()
+            .map(|(proto, sport, from, to, reason, stcpseq, sbytes)| {
+                format!(
+                    "('s1', '{}', '10.0.0.1', '10.0.0.9', {}, 80,
+                        TIMESTAMP '2024-01-01' + INTERVAL '{} seconds',
+                        TIMESTAMP '2024-01-01' + INTERVAL '{} seconds', '{}', {}, {}, {})",
+                    proto,
+                    sport,
+                    from,
+                    to,
+                    reason,
+                    stcpseq,
+                    sbytes,
+                    sbytes / 100
+                )
+            })
+            .collect();
+        schema::write_test_flows(
+            path,
+            &format!(
+                "SELECT * FROM (VALUES {}) t(observ, proto, saddr, daddr, sport, dport,
+                    stime, etime, reason, stcpseq, sbytes, spkts)",
+                rows.join(", ")
+            ),
+        );
+    }
+
+    // (sport, stime, dur, reason, sbytes, spkts) of the sessions in path
+    fn sessions(path: &str) -> Vec<(u16, String, u32, String, u64, u64)> {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT sport, strftime(stime, '%H:%M:%S'), dur, reason, sbytes, spkts FROM '{}' ORDER BY ALL;",
+                path
+            ))
+            .unwrap();
+        stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+        })
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+    }
+
+    #[test]
+    fn chunks_merge_across_input_files() {
+        let dir = test_dir("stitch-merge");
+        let stitcher = Stitcher::new(&dir, 30, 60);
+
+        // the active chunk is held for its next one
+        let input_spec = format!("{}/a.parquet", dir);
+        let output_spec = format!("{}/a.out.parquet", dir);
+        write_input(
+            &input_spec,
+            &[
+                ("tcp", 1234, 0, 60, "active", 1000, 500),
+                ("udp", 53, 10, 20, "idle", 0, 100),
+                ("tcp", 4321, 90, 100, "eof", 0, 100),
+            ],
+        );
+        stitcher.stitch_file(&input_spec, &output_spec).unwrap();
+        assert_eq!(sessions(&output_spec).len(), 2);
+        assert!(Path::new(&stitcher.pending_spec).exists());
+
+        // its sequence numbers follow on from the first chunk's bytes
+        let input_spec = format!("{}/b.parquet", dir);
+        let output_spec = format!("{}/b.out.parquet", dir);
+        write_input(&input_spec, &[("tcp", 1234, 65, 110, "eof", 1500, 300)]);
+        stitcher.stitch_file(&input_spec, &output_spec).unwrap();
+        assert_eq!(
+            sessions(&output_spec),
+            vec![(1234, String::from("00:00:00"), 110000, String::from("eof"), 800, 8)]
+        );
+        assert!(!Path::new(&stitcher.pending_spec).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unrelated_chunks_stay_apart() {
+        let dir = test_dir("stitch-apart");
+        let stitcher = Stitcher::new(&dir, 30, 60);
+        let input_spec = format!("{}/a.parquet", dir);
+        let output_spec = format!("{}/a.out.parquet", dir);
+        write_input(&input_spec, &[("tcp", 1234, 0, 60, "active", 1000, 500)]);
+        stitcher.stitch_file(&input_spec, &output_spec).unwrap();
+        assert!(!Path::new(&output_spec).exists());
+
+        // the sequence numbers don't follow on
+        let input_spec = format!("{}/b.parquet", dir);
+        let output_spec = format!("{}/b.out.parquet", dir);
+        write_input(&input_spec, &[("tcp", 1234, 65, 110, "eof", 9000, 300)]);
+        stitcher.stitch_file(&input_spec, &output_spec).unwrap();
+        assert_eq!(
+            sessions(&output_spec),
+            vec![(1234, String::from("00:01:05"), 45000, String::from("eof"), 300, 3)]
+        );
+
+        // the first chunk, still within the timeouts, is released by flush()
+        stitcher.flush(&dir).unwrap();
+        let released: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("stitch-"))
+            .collect();
+        assert_eq!(released.len(), 1);
+        assert_eq!(
+            sessions(&format!("{}/{}", dir, released[0])),
+            vec![(1234, String::from("00:00:00"), 60000, String::from("active"), 500, 5)]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,403 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Operational suppressions with expiry (gnat_suppress)
+//
+// Suppressions live in a DuckDB file:
+//
+//   suppression (id, observ, saddr, daddr, proto, dport, appid, stage,
+//                added, expires, author, reason)
+//   audit       (time, action, id, author, detail)
+//
+// Each suppression matches the flows whose columns equal the ones it sets,
+// so a flow missing a set column (NULL) is not matched; unset (NULL)
+// columns of the suppression match any, and stage limits it to one detector
+// stage (detect, beacon or scan). gnat_detect, gnat_beacon and gnat_scan
+// leave matching flows out of their detections until the suppression
+// expires; flow files are still passed to the output whole. Every add,
+// removal and expiry is recorded in audit.
+//
+
+use crate::core::error::GnatError;
+
+use std::thread;
+use std::time::Duration;
+
+use duckdb::{params, Connection};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+const STORE_TABLES: &str = "CREATE SEQUENCE IF NOT EXISTS suppression_id;
+    CREATE TABLE IF NOT EXISTS suppression (
+        id UBIGINT DEFAULT nextval('suppression_id') PRIMARY KEY,
+        observ VARCHAR, saddr VARCHAR, daddr VARCHAR, proto VARCHAR, dport USMALLINT,
+        appid VARCHAR, stage VARCHAR,
+        added TIMESTAMP, expires TIMESTAMP, author VARCHAR, reason VARCHAR);
+    CREATE TABLE IF NOT EXISTS audit (
+        time TIMESTAMP, action VARCHAR, id UBIGINT, author VARCHAR, detail VARCHAR);";
+
+// a read-only attach fails while gnat_suppress has the file open
+const ATTACH_ATTEMPTS: u32 = 5;
+const ATTACH_BACKOFF: Duration = Duration::from_millis(200);
+
+// the stages that honor suppressions
+pub const SUPPRESS_STAGES: [&str; 3] = ["detect", "beacon", "scan"];
+
+// flow columns a suppression can set
+const MATCH_COLUMNS: [&str; 6] = ["observ", "saddr", "daddr", "proto", "dport", "appid"];
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Suppression {
+    pub observ: Option<String>,
+    pub saddr: Option<String>,
+    pub daddr: Option<String>,
+    pub proto: Option<String>,
+    pub dport: Option<u16>,
+    pub appid: Option<String>,
+    pub stage: Option<String>,
+    // lifetime in days, default 7
+    pub days: Option<u64>,
+    pub author: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl Suppression {
+    fn describe(&self) -> String {
+        let fields = [
+            ("observ", self.observ.clone()),
+            ("saddr", self.saddr.clone()),
+            ("daddr", self.daddr.clone()),
+            ("proto", self.proto.clone()),
+            ("dport", self.dport.map(|p| p.to_string())),
+            ("appid", self.appid.clone()),
+            ("stage", self.stage.clone()),
+        ];
+        fields
+            .iter()
+            .filter_map(|(name, value)| value.as_ref().map(|v| format!("{}={}", name, v)))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    pub fn open(store_spec: &String) -> Result<Store, GnatError> {
+        let conn = Connection::open(store_spec)?;
+        conn.execute_batch(STORE_TABLES)?;
+        Ok(Store { conn })
+    }
+
+    fn audit(&self, action: &str, id: u64, author: &str, detail: &str) -> Result<(), GnatError> {
+        self.conn
+            .execute(
+                "INSERT INTO audit VALUES (now()::TIMESTAMP, ?, ?, ?, ?);",
+                params![action, id, author, detail],
+            )?;
+        Ok(())
+    }
+
+    pub fn add(&self, suppression: &Suppression, author: &str) -> Result<u64, GnatError> {
+        let matches_any = suppression.observ.is_none()
+            && suppression.saddr.is_none()
+            && suppression.daddr.is_none()
+            && suppression.proto.is_none()
+            && suppression.dport.is_none()
+            && suppression.appid.is_none();
+        if matches_any {
+            return Err(GnatError::Config(String::from(
+                "a suppression needs at least one of observ, saddr, daddr, proto, dport or appid",
+            )));
+        }
+        if suppression.days == Some(0) {
+            return Err(GnatError::Config(String::from("days must be greater than 0")));
+        }
+        if let Some(stage) = &suppression.stage {
+            if !SUPPRESS_STAGES.contains(&stage.as_str()) {
+                return Err(GnatError::Config(format!(
+                    "invalid stage {} (detect|beacon|scan)",
+                    stage
+                )));
+            }
+        }
+        let author = suppression.author.as_deref().unwrap_or(author);
+        let id: u64 = self
+            .conn
+            .query_row(
+                "INSERT INTO suppression (observ, saddr, daddr, proto, dport, appid, stage,
+                        added, expires, author, reason)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, now()::TIMESTAMP,
+                        now()::TIMESTAMP + to_days(?::INTEGER), ?, ?)
+                    RETURNING id;",
+                params![
+                    suppression.observ,
+                    suppression.saddr,
+                    suppression.daddr,
+                    suppression.proto,
+                    suppression.dport,
+                    suppression.appid,
+                    suppression.stage,
+                    suppression.days.unwrap_or(7),
+                    author,
+                    suppression.reason,
+                ],
+                |row| row.get(0),
+            )?;
+        let detail = suppression.describe();
+        let detail = match &suppression.reason {
+            Some(reason) => format!("{} for {} days: {}", detail, suppression.days.unwrap_or(7), reason),
+            None => format!("{} for {} days", detail, suppression.days.unwrap_or(7)),
+        };
+        self.audit("add", id, author, &detail)?;
+        info!("suppress: added {} [{}]", id, detail);
+        Ok(id)
+    }
+
+    //
+    // Add the suppressions of a JSON array; author is the default for
+    // entries without one
+    //
+    pub fn import(&self, json_spec: &String, author: &str) -> Result<usize, GnatError> {
+        let contents = std::fs::read_to_string(json_spec)?;
+        let suppressions: Vec<Suppression> = serde_json::from_str(&contents)
+            .map_err(|e| GnatError::Config(format!("parsing {} - {}", json_spec, e)))?;
+        self.conn
+            .execute_batch("BEGIN TRANSACTION;")?;
+        for suppression in suppressions.iter() {
+            if let Err(e) = self.add(suppression, author) {
+                let _ = self.conn.execute_batch("ROLLBACK;");
+                return Err(e);
+            }
+        }
+        self.conn
+            .execute_batch("COMMIT;")?;
+        Ok(suppressions.len())
+    }
+
+    pub fn remove(&self, id: u64, author: &str) -> Result<bool, GnatError> {
+        let removed = self
+            .conn
+            .execute("DELETE FROM suppression WHERE id = ?;", params![id])?;
+        if removed > 0 {
+            self.audit("remove", id, author, "")?;
+        }
+        Ok(removed > 0)
+    }
+
+    //
+    // Delete the suppressions that have expired, recording each in audit
+    //
+    pub fn expire(&self) -> Result<usize, GnatError> {
+        let sql_command = "BEGIN TRANSACTION;
+            INSERT INTO audit SELECT now()::TIMESTAMP, 'expire', id, 'gnat_suppress',
+                    'added ' || strftime(added, '%Y-%m-%d %H:%M') || ' by ' || coalesce(author, '')
+                FROM suppression WHERE expires <= now()::TIMESTAMP;
+            DELETE FROM suppression WHERE expires <= now()::TIMESTAMP;
+            COMMIT;";
+        let before = self.count()?;
+        self.conn
+            .execute_batch(sql_command)?;
+        Ok(before - self.count()?)
+    }
+
+    fn count(&self) -> Result<usize, GnatError> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT count(*) FROM suppression;", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    //
+    // The active suppressions, one line each
+    //
+    pub fn list(&self) -> Result<Vec<String>, GnatError> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT concat_ws(' ', id, strftime(expires, '%Y-%m-%dT%H:%M'), coalesce(author, '-'),
+                        concat_ws(', ', 'observ=' || observ, 'saddr=' || saddr, 'daddr=' || daddr,
+                            'proto=' || proto, 'dport=' || dport, 'appid=' || appid, 'stage=' || stage),
+                        '- ' || reason)
+                    FROM suppression WHERE expires > now()::TIMESTAMP ORDER BY id;",
+            )?;
+        let lines = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, duckdb::Error>>()?;
+        Ok(lines)
+    }
+}
+
+//
+// Attach store_spec read-only to a stage's connection as "suppress" and
+// return a predicate on `table` that is false for suppressed flows; with
+// no store, or one that can't be attached, every flow is kept
+//
+pub fn exclude(conn: &Connection, store_spec: &String, stage: &str, table: &str) -> String {
+    if store_spec.is_empty() {
+        return String::from("true");
+    }
+    let sql_command = format!("ATTACH '{}' AS suppress (READ_ONLY);", store_spec);
+    let mut attempt = 1;
+    while let Err(e) = conn.execute_batch(&sql_command) {
+        if attempt == ATTACH_ATTEMPTS {
+            warn!("attaching {} - {:?}; suppressions skipped", store_spec, e);
+            return String::from("true");
+        }
+        attempt += 1;
+        thread::sleep(ATTACH_BACKOFF);
+    }
+    let matches: Vec<String> = MATCH_COLUMNS
+        .iter()
+        .map(|column| format!("(s.{0} IS NULL OR s.{0} = {1}.{0})", column, table))
+        .collect();
+    format!(
+        "NOT EXISTS (SELECT 1 FROM suppress.suppression s
+            WHERE s.expires > now()::TIMESTAMP AND (s.stage IS NULL OR s.stage = '{}') AND {})",
+        stage,
+        matches.join(" AND ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_store(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("gnat-{}-{}.duckdb", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().to_string()
+    }
+
+    // the flows kept by stage, as (saddr, daddr, dport)
+    fn kept(store_spec: &String, stage: &str) -> Vec<(Option<String>, Option<String>, Option<u16>)> {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE flow AS SELECT * FROM (VALUES
+                ('s1', '10.0.0.1', '10.0.0.8', '6', 80::USMALLINT, 'http'),
+                ('s1', NULL, '10.0.0.8', '6', 80::USMALLINT, 'http'),
+                ('s1', '10.0.0.2', '10.0.0.9', '6', 443::USMALLINT, 'tls'),
+                ('s1', '10.0.0.2', '10.0.0.9', '6', NULL, 'tls'),
+                ('s1', '10.0.0.2', '10.0.0.9', '6', 80::USMALLINT, 'http'))
+                t(observ, saddr, daddr, proto, dport, appid);",
+        )
+        .unwrap();
+        let predicate = exclude(&conn, store_spec, stage, "flow");
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT saddr, daddr, dport FROM flow WHERE {} ORDER BY ALL;",
+                predicate
+            ))
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn row(saddr: Option<&str>, daddr: &str, dport: Option<u16>) -> (Option<String>, Option<String>, Option<u16>) {
+        (saddr.map(String::from), Some(String::from(daddr)), dport)
+    }
+
+    #[test]
+    fn add_rejects_invalid_suppressions() {
+        let store = Store::open(&test_store("suppress-invalid")).unwrap();
+        assert!(store.add(&Suppression::default(), "test").is_err());
+        let zero_days = Suppression {
+            saddr: Some(String::from("10.0.0.1")),
+            days: Some(0),
+            ..Default::default()
+        };
+        assert!(store.add(&zero_days, "test").is_err());
+        let bad_stage = Suppression {
+            saddr: Some(String::from("10.0.0.1")),
+            stage: Some(String::from("tag")),
+            ..Default::default()
+        };
+        assert!(store.add(&bad_stage, "test").is_err());
+        assert_eq!(store.count().unwrap(), 0);
+    }
+
+    #[test]
+    fn exclude_matches_set_columns_only() {
+        let store_spec = test_store("suppress-exclude");
+        {
+            let store = Store::open(&store_spec).unwrap();
+            let by_source = Suppression {
+                saddr: Some(String::from("10.0.0.1")),
+                stage: Some(String::from("detect")),
+                ..Default::default()
+            };
+            let by_service = Suppression {
+                daddr: Some(String::from("10.0.0.9")),
+                dport: Some(443),
+                ..Default::default()
+            };
+            store.add(&by_source, "test").unwrap();
+            store.add(&by_service, "test").unwrap();
+        }
+
+        // flows missing saddr or dport are not matched by a suppression setting it
+        assert_eq!(
+            kept(&store_spec, "detect"),
+            vec![
+                row(Some("10.0.0.2"), "10.0.0.9", Some(80)),
+                row(Some("10.0.0.2"), "10.0.0.9", None),
+                row(None, "10.0.0.8", Some(80)),
+            ]
+        );
+        // the saddr suppression is limited to detect
+        assert_eq!(kept(&store_spec, "beacon").len(), 4);
+        assert_eq!(kept(&String::new(), "detect").len(), 5);
+        let _ = std::fs::remove_file(&store_spec);
+    }
+
+    #[test]
+    fn expired_suppressions_are_removed_and_audited() {
+        let store_spec = test_store("suppress-expire");
+        {
+            let store = Store::open(&store_spec).unwrap();
+            let suppression = Suppression {
+                saddr: Some(String::from("10.0.0.1")),
+                reason: Some(String::from("scanner")),
+                ..Default::default()
+            };
+            let id = store.add(&suppression, "test").unwrap();
+            store.add(&suppression, "test").unwrap();
+            assert_eq!(store.list().unwrap().len(), 2);
+            store
+                .conn
+                .execute(
+                    "UPDATE suppression SET expires = now()::TIMESTAMP - INTERVAL 1 HOUR WHERE id = ?;",
+                    params![id],
+                )
+                .unwrap();
+            assert_eq!(store.list().unwrap().len(), 1);
+            // an expired suppression no longer excludes flows
+            drop(store);
+            assert_eq!(kept(&store_spec, "detect").len(), 4);
+
+            let store = Store::open(&store_spec).unwrap();
+            assert_eq!(store.expire().unwrap(), 1);
+            assert_eq!(store.count().unwrap(), 1);
+            let actions: Vec<String> = store
+                .conn
+                .prepare("SELECT action FROM audit ORDER BY time, action;")
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(actions, ["add", "add", "expire"]);
+        }
+        let _ = std::fs::remove_file(&store_spec);
+    }
+}
@@ -0,0 +1,419 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Threat-intel tagging stage
+//
+// Indicators are read from one of:
+//
+//   CSV        indicator,tag rows; indicator is an IP address or CIDR
+//   MISP CSV   the MISP attribute CSV export (header with type and value);
+//              ip-src/ip-dst attributes, tagged with attribute_tag or
+//              misp-event-<event_id>
+//   STIX 2.1   a .json bundle; indicator objects with ipv4-addr/ipv6-addr
+//              value patterns, tagged with the indicator name
+//
+// Flows whose saddr or daddr match get the indicator tags appended to the
+// "tag" column. Domain indicators are skipped since flow records carry
+// only addresses. The list is reloaded whenever the file changes, or on SIGHUP.
+//
+
+use crate::core::error::GnatError;
+use crate::core::network::Network;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::shutdown;
+use crate::core::spool::process_directory_parallel;
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::net::IpAddr;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use duckdb::params;
+use tracing::{error, info, warn};
+
+pub struct Indicators {
+    pub indicator_spec: String,
+    last_modified: Option<SystemTime>,
+    exact: HashMap<IpAddr, Vec<String>>,
+    networks: Vec<(Network, String)>,
+}
+
+impl Indicators {
+    pub fn new(indicator_spec: &str) -> Indicators {
+        Indicators {
+            indicator_spec: indicator_spec.to_string(),
+            last_modified: None,
+            exact: HashMap::new(),
+            networks: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, indicator: &str, tag: &str) -> bool {
+        let indicator = indicator.trim();
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return false;
+        }
+        if let Ok(address) = indicator.parse::<IpAddr>() {
+            self.exact
+                .entry(address)
+                .or_default()
+                .push(String::from(tag));
+            return true;
+        }
+        match Network::parse(indicator) {
+            Some(network) => {
+                self.networks.push((network, String::from(tag)));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn invalid(&self, e: impl std::fmt::Display) -> GnatError {
+        GnatError::Config(format!("parsing {} - {}", self.indicator_spec, e))
+    }
+
+    fn load_csv(&mut self) -> Result<(usize, usize), GnatError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(&self.indicator_spec)
+            .map_err(|e| GnatError::Config(format!("reading {} - {}", self.indicator_spec, e)))?;
+        let mut records = reader.records();
+        let Some(first) = records.next() else {
+            return Ok((0, 0));
+        };
+        let first = first.map_err(|e| self.invalid(e))?;
+        let columns: Vec<String> = first.iter().map(|c| c.trim().to_lowercase()).collect();
+        let column = |name: &str| columns.iter().position(|c| c == name);
+
+        let (mut loaded, mut skipped) = (0, 0);
+        if let (Some(type_column), Some(value_column)) = (column("type"), column("value")) {
+            // MISP attribute export
+            let tag_column = column("attribute_tag");
+            let event_column = column("event_id");
+            for record in records {
+                let record = record.map_err(|e| self.invalid(e))?;
+                let kind = record.get(type_column).unwrap_or("");
+                if !kind.starts_with("ip-") && !kind.ends_with("|ip") {
+                    skipped += 1;
+                    continue;
+                }
+                let tag = match tag_column.and_then(|c| record.get(c)) {
+                    Some(t) if !t.is_empty() => String::from(t),
+                    _ => format!(
+                        "misp-event-{}",
+                        event_column.and_then(|c| record.get(c)).unwrap_or("")
+                    ),
+                };
+                // composite types such as ip-dst|port or domain|ip
+                let value = record.get(value_column).unwrap_or("");
+                match value.split('|').find(|v| v.parse::<IpAddr>().is_ok()) {
+                    Some(v) if self.add(v, &tag) => loaded += 1,
+                    _ => skipped += 1,
+                }
+            }
+        } else {
+            // indicator,tag with an optional header row
+            for record in std::iter::once(Ok(first)).chain(records) {
+                let record = record.map_err(|e| self.invalid(e))?;
+                match (record.get(0), record.get(1)) {
+                    (Some(indicator), Some(tag)) if self.add(indicator, tag) => loaded += 1,
+                    _ => skipped += 1,
+                }
+            }
+        }
+        Ok((loaded, skipped))
+    }
+
+    fn load_stix(&mut self) -> Result<(usize, usize), GnatError> {
+        let contents = fs::read_to_string(&self.indicator_spec)?;
+        let bundle: serde_json::Value =
+            serde_json::from_str(&contents).map_err(|e| self.invalid(e))?;
+        let (mut loaded, mut skipped) = (0, 0);
+        let objects = bundle["objects"].as_array().cloned().unwrap_or_default();
+        for object in objects.iter().filter(|o| o["type"] == "indicator") {
+            let pattern = object["pattern"].as_str().unwrap_or("");
+            let tag = object["name"]
+                .as_str()
+                .or(object["id"].as_str())
+                .unwrap_or("stix");
+            let mut found = false;
+            for term in ["ipv4-addr:value", "ipv6-addr:value"] {
+                for (start, _) in pattern.match_indices(term) {
+                    let rest = &pattern[start + term.len()..];
+                    let Some(value) = rest.split('\'').nth(1) else {
+                        continue;
+                    };
+                    if self.add(value, tag) {
+                        loaded += 1;
+                        found = true;
+                    }
+                }
+            }
+            if !found {
+                skipped += 1;
+            }
+        }
+        Ok((loaded, skipped))
+    }
+
+    //
+    // (Re)load the indicator file if it changed since the last batch
+    //
+    pub fn refresh(&mut self) -> Result<(), GnatError> {
+        let modified = fs::metadata(&self.indicator_spec)?.modified()?;
+        if self.last_modified == Some(modified) {
+            return Ok(());
+        }
+        // load into a fresh list so a bad file leaves the current one in place
+        let mut fresh = Indicators::new(&self.indicator_spec);
+        let (loaded, skipped) = if fresh.indicator_spec.ends_with(".json") {
+            fresh.load_stix()?
+        } else {
+            fresh.load_csv()?
+        };
+        fresh.last_modified = Some(modified);
+        *self = fresh;
+        info!(
+            "tag: loaded {} indicators from {} [skipped {}]",
+            loaded, self.indicator_spec, skipped
+        );
+        if skipped > 0 {
+            warn!(
+                "tag: {} entries in {} are not IP/CIDR indicators",
+                skipped, self.indicator_spec
+            );
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, address: &IpAddr) -> BTreeSet<&String> {
+        let mut tags: BTreeSet<&String> = BTreeSet::new();
+        if let Some(t) = self.exact.get(address) {
+            tags.extend(t.iter());
+        }
+        for (network, tag) in self.networks.iter() {
+            if network.contains(address) {
+                tags.insert(tag);
+            }
+        }
+        tags
+    }
+
+    pub fn tag_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("tag")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let sql_command = format!(
+            "CREATE TABLE memtable AS {};
+             ALTER TABLE memtable ADD COLUMN IF NOT EXISTS tag VARCHAR;
+             CREATE TABLE indicator (addr VARCHAR, tag VARCHAR);",
+            source
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        //
+        // match the distinct addresses of the batch against the indicators
+        //
+        let addresses: Vec<String> = {
+            let mut stmt = conn
+                .prepare("SELECT saddr FROM memtable UNION SELECT daddr FROM memtable;")?;
+            match stmt.query_map([], |row| row.get::<_, Option<String>>(0)) {
+                Ok(rows) => rows.filter_map(|r| r.ok().flatten()).collect(),
+                Err(e) => {
+                    error!("reading {} - {:?}", input_spec, e);
+                    return Err(e.into());
+                }
+            }
+        };
+        {
+            let mut appender = conn.appender("indicator")?;
+            for address in addresses.iter() {
+                let Ok(ip) = address.parse::<IpAddr>() else {
+                    continue;
+                };
+                for tag in self.lookup(&ip) {
+                    if let Err(e) = appender.append_row(params![address, tag]) {
+                        error!("matching {} - {:?}", input_spec, e);
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+
+        let tagged: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM memtable
+                    WHERE saddr IN (SELECT addr FROM indicator) OR daddr IN (SELECT addr FROM indicator);",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+        let sql_command = format!(
+            "COPY (SELECT * REPLACE (
+                    nullif(concat_ws(',', m.tag,
+                        (SELECT string_agg(DISTINCT i.tag, ',' ORDER BY i.tag) FROM indicator i
+                            WHERE i.addr = m.saddr OR i.addr = m.daddr)), '') AS tag)
+                  FROM memtable m)
+                TO '{}' ({});",
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+        info!("tag: {} [tagged {} flows]", input_spec, tagged);
+        Ok(())
+    }
+}
+
+pub fn tag(
+    indicator_spec: &String,
+    input_spec: &String,
+    output_spec: &String,
+    processed_spec: &String,
+    polling: bool,
+    workers: usize,
+) -> Result<(), std::io::Error> {
+    info!("indicator spec: {}", indicator_spec);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("polling: {}", polling);
+    info!("workers: {}", workers);
+
+    let mut indicators = Indicators::new(indicator_spec);
+    indicators.refresh()?;
+    let indicators = RwLock::new(indicators);
+
+    process_directory_parallel(
+        "tag",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        workers,
+        |src_path, tmp_path| {
+            {
+                // keep tagging with the previous list if the new one is unreadable
+                let mut indicators = indicators.write().unwrap();
+                if shutdown::reload_requested() {
+                    indicators.last_modified = None;
+                }
+                if let Err(e) = indicators.refresh() {
+                    error!("reloading {} - {:?}", indicators.indicator_spec, e);
+                }
+            }
+            indicators.read().unwrap().tag_file(src_path, tmp_path)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    fn loaded(indicator_spec: &str, contents: &str) -> Indicators {
+        fs::write(indicator_spec, contents).unwrap();
+        let mut indicators = Indicators::new(indicator_spec);
+        indicators.refresh().unwrap();
+        indicators
+    }
+
+    fn tags(indicators: &Indicators, address: &str) -> Vec<String> {
+        indicators
+            .lookup(&address.parse().unwrap())
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn load_indicator_formats() {
+        let dir = test_dir("tag-formats");
+        let csv = loaded(
+            &format!("{}/intel.csv", dir),
+            "indicator,tag\n10.0.0.1,c2\n10.1.0.0/16,tor\nevil.example,phish\n",
+        );
+        assert_eq!(tags(&csv, "10.0.0.1"), vec!["c2"]);
+        assert_eq!(tags(&csv, "10.1.2.3"), vec!["tor"]);
+        assert!(tags(&csv, "10.2.0.1").is_empty());
+
+        let misp = loaded(
+            &format!("{}/misp.csv", dir),
+            "uuid,event_id,category,type,value,attribute_tag\n\
+             a,17,Network activity,ip-dst,10.0.0.2,\n\
+             b,17,Network activity,ip-dst|port,10.0.0.3|443,apt\n\
+             c,17,Network activity,domain,evil.example,\n",
+        );
+        assert_eq!(tags(&misp, "10.0.0.2"), vec!["misp-event-17"]);
+        assert_eq!(tags(&misp, "10.0.0.3"), vec!["apt"]);
+
+        let stix = loaded(
+            &format!("{}/bundle.json", dir),
+            r#"{"type": "bundle", "objects": [
+                {"type": "indicator", "name": "botnet",
+                    "pattern": "[ipv4-addr:value = '10.0.0.4'] OR [ipv6-addr:value = 'fd00::/8']"},
+                {"type": "indicator", "name": "phish", "pattern": "[domain-name:value = 'evil.example']"},
+                {"type": "malware", "name": "other"}]}"#,
+        );
+        assert_eq!(tags(&stix, "10.0.0.4"), vec!["botnet"]);
+        assert_eq!(tags(&stix, "fd00::1"), vec!["botnet"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tag_file_appends_tags() {
+        let dir = test_dir("tag-file");
+        let indicators = loaded(
+            &format!("{}/intel.csv", dir),
+            "10.0.0.1,c2\n10.0.0.0/24,internal\n10.0.0.9,c2\n",
+        );
+        let input_spec = format!("{}/flows.parquet", dir);
+        let output_spec = format!("{}/flows.out.parquet", dir);
+        schema::write_test_flows(
+            &input_spec,
+            "SELECT * FROM (VALUES
+                (1, '10.0.0.1', '10.0.0.9'),
+                (2, '192.168.0.1', '10.0.0.9'),
+                (3, '192.168.0.1', '192.168.0.2'))
+                t(dur, saddr, daddr)",
+        );
+        indicators.tag_file(&input_spec, &output_spec).unwrap();
+
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!("SELECT tag FROM '{}' ORDER BY dur;", output_spec))
+            .unwrap();
+        let tags: Vec<Option<String>> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            tags,
+            vec![Some(String::from("c2,internal")), Some(String::from("c2,internal")), None]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,274 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Tenant stamping (gnat_import --tenant, --tenants)
+//
+// Sets the "tenant" column of imported flows, so sensors of several
+// customers can share one pipeline. --tenant names the tenant of every
+// flow; --tenants maps observations to tenants, from one of:
+//
+//   CSV    observ,tenant rows, with an optional header row
+//   TOML   a .toml file of [[tenant]] tables:
+//
+//            [[tenant]]
+//            name = "acme"
+//            observ = ["acme-hq", "acme-dc"]
+//
+// An observation missing from the mapping gets the --tenant name, or no
+// tenant without one. Tenant names are used as directory names by
+// gnat_export --partition, so they are limited to letters, digits, '-',
+// '_' and '.'.
+//
+
+use crate::core::error::GnatError;
+use crate::core::schema;
+use crate::core::scratch;
+
+use std::collections::HashMap;
+use std::fs;
+
+use duckdb::params;
+use serde::Deserialize;
+use tracing::{error, info};
+
+#[derive(Debug, Deserialize)]
+struct TenantEntry {
+    name: String,
+    #[serde(default)]
+    observ: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TenantFile {
+    #[serde(default)]
+    tenant: Vec<TenantEntry>,
+}
+
+pub struct Tenants {
+    default: Option<String>,
+    // observ -> tenant
+    tenants: HashMap<String, String>,
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+impl Tenants {
+    pub fn load(tenant: &String, tenants_spec: &String) -> Result<Tenants, GnatError> {
+        let mut tenants = Tenants {
+            default: None,
+            tenants: HashMap::new(),
+        };
+        if !tenant.is_empty() {
+            if !valid_name(tenant) {
+                return Err(GnatError::Config(format!("invalid tenant name {}", tenant)));
+            }
+            tenants.default = Some(tenant.clone());
+        }
+        if tenants_spec.ends_with(".toml") {
+            tenants.load_toml(tenants_spec)?;
+        } else if !tenants_spec.is_empty() {
+            tenants.load_csv(tenants_spec)?;
+        }
+        info!(
+            "tenants: {} observations mapped, default: {}",
+            tenants.tenants.len(),
+            tenants.default.as_deref().unwrap_or("none")
+        );
+        Ok(tenants)
+    }
+
+    fn add(&mut self, tenants_spec: &String, observ: &str, name: &str) -> Result<(), GnatError> {
+        let (observ, name) = (observ.trim(), name.trim());
+        if observ.is_empty() {
+            return Err(GnatError::Config(format!(
+                "{}: empty observ for tenant {}",
+                tenants_spec, name
+            )));
+        }
+        if !valid_name(name) {
+            return Err(GnatError::Config(format!(
+                "{}: invalid tenant name {} for observ {}",
+                tenants_spec, name, observ
+            )));
+        }
+        self.tenants.insert(String::from(observ), String::from(name));
+        Ok(())
+    }
+
+    fn load_csv(&mut self, tenants_spec: &String) -> Result<(), GnatError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(tenants_spec)
+            .map_err(|e| GnatError::Config(format!("reading {} - {}", tenants_spec, e)))?;
+        for (line, record) in reader.records().enumerate() {
+            let record =
+                record.map_err(|e| GnatError::Config(format!("parsing {} - {}", tenants_spec, e)))?;
+            let (Some(observ), Some(name)) = (record.get(0), record.get(1)) else {
+                return Err(GnatError::Config(format!(
+                    "{}: line {} is not observ,tenant",
+                    tenants_spec,
+                    line + 1
+                )));
+            };
+            // header row
+            if line == 0 && observ.trim() == "observ" {
+                continue;
+            }
+            self.add(tenants_spec, observ, name)?;
+        }
+        Ok(())
+    }
+
+    fn load_toml(&mut self, tenants_spec: &String) -> Result<(), GnatError> {
+        let contents = fs::read_to_string(tenants_spec)?;
+        let file: TenantFile = toml::from_str(&contents)
+            .map_err(|e| GnatError::Config(format!("parsing {} - {}", tenants_spec, e)))?;
+        for entry in file.tenant.iter() {
+            for observ in entry.observ.iter() {
+                self.add(tenants_spec, observ, &entry.name)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stamp_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("tenant")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let sql_command = format!(
+            "CREATE TABLE memtable AS {};
+             CREATE TABLE tenants (observ VARCHAR, tenant VARCHAR);",
+            source
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+        {
+            let mut appender = conn.appender("tenants")?;
+            for (observ, tenant) in self.tenants.iter() {
+                if let Err(e) = appender.append_row(params![observ, tenant]) {
+                    error!("stamping {} - {:?}", input_spec, e);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let default = match &self.default {
+            Some(tenant) => format!("'{}'", tenant),
+            None => String::from("NULL"),
+        };
+        let sql_command = format!(
+            "UPDATE memtable SET tenant = coalesce(
+                    (SELECT t.tenant FROM tenants t WHERE t.observ = memtable.observ), {});
+             COPY memtable TO '{}' ({});",
+            default,
+            output_spec,
+            schema::copy_options()
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("writing {} - {:?}", output_spec, e);
+            return Err(e.into());
+        }
+        info!("tenant: {}", input_spec);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    fn load(tenant: &str, tenants_spec: &str, contents: &str) -> Result<Tenants, GnatError> {
+        fs::write(tenants_spec, contents).unwrap();
+        Tenants::load(&String::from(tenant), &String::from(tenants_spec))
+    }
+
+    #[test]
+    fn valid_names() {
+        assert!(valid_name("acme"));
+        assert!(valid_name("acme-east_1.b"));
+        assert!(!valid_name(""));
+        assert!(!valid_name(".hidden"));
+        assert!(!valid_name("a/b"));
+        assert!(!valid_name("a b"));
+    }
+
+    #[test]
+    fn load_mappings() {
+        let dir = test_dir("tenant-load");
+        let csv = load(
+            "",
+            &format!("{}/tenants.csv", dir),
+            "observ,tenant\nhq,acme\ndc, acme\nlab,beta\n",
+        )
+        .unwrap();
+        assert_eq!(csv.tenants.len(), 3);
+        assert_eq!(csv.tenants["dc"], "acme");
+        assert_eq!(csv.default, None);
+
+        let toml = load(
+            "other",
+            &format!("{}/tenants.toml", dir),
+            "[[tenant]]\nname = \"acme\"\nobserv = [\"hq\", \"dc\"]\n",
+        )
+        .unwrap();
+        assert_eq!(toml.tenants["hq"], "acme");
+        assert_eq!(toml.default.as_deref(), Some("other"));
+
+        assert!(load("", &format!("{}/bad.csv", dir), "hq,../acme\n").is_err());
+        assert!(load("", &format!("{}/bad.csv", dir), "hq\n").is_err());
+        assert!(load("a/b", &format!("{}/tenants.csv", dir), "hq,acme\n").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stamp_maps_observations() {
+        let dir = test_dir("tenant-stamp");
+        let tenants = load("other", &format!("{}/tenants.csv", dir), "hq,acme\n").unwrap();
+        let input_spec = format!("{}/flows.parquet", dir);
+        let output_spec = format!("{}/flows.out.parquet", dir);
+        schema::write_test_flows(
+            &input_spec,
+            "SELECT * FROM (VALUES ('hq', 'stale'), ('lab', NULL)) t(observ, tenant)",
+        );
+        tenants.stamp_file(&input_spec, &output_spec).unwrap();
+
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!("SELECT observ, tenant FROM '{}' ORDER BY observ;", output_spec))
+            .unwrap();
+        let stamped: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            stamped,
+            vec![
+                (String::from("hq"), String::from("acme")),
+                (String::from("lab"), String::from("other"))
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
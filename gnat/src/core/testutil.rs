@@ -0,0 +1,33 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Helpers shared by the unit tests
+//
+
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+//
+// An empty directory under the system temp directory. Each call gets a
+// new one, so tests running in parallel never share a directory even when
+// they pass the same name.
+//
+pub(crate) fn test_dir(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!(
+        "gnat-{}-{}-{}",
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.to_string_lossy().to_string()
+}
@@ -0,0 +1,625 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// TLS handshake decoding for the pcap import: the ClientHello, the
+// ServerHello and, before TLS 1.3, the server certificate. They give the
+// sni, tlsissuer, tlssubject and tlsnotafter columns and the JA3/JA3S and
+// JA4/JA4S fingerprints. The digests are computed by the caller, which has
+// a DuckDB connection for md5() and sha256().
+//
+// Each side is the reassembled start of its tcp stream. A side may be cut
+// short; the messages that are complete are still used.
+//
+
+use crate::core::packet::Reader;
+
+use chrono::NaiveDate;
+
+const HANDSHAKE: u8 = 22;
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const CERTIFICATE: u8 = 11;
+
+const SERVER_NAME: u16 = 0;
+const SUPPORTED_GROUPS: u16 = 10;
+const EC_POINT_FORMATS: u16 = 11;
+const SIGNATURE_ALGORITHMS: u16 = 13;
+const ALPN: u16 = 16;
+const SUPPORTED_VERSIONS: u16 = 43;
+
+#[derive(Clone, Default)]
+pub struct ClientHello {
+    pub version: u16,
+    pub ciphers: Vec<u16>,
+    pub extensions: Vec<u16>,
+    pub groups: Vec<u16>,
+    pub point_formats: Vec<u8>,
+    pub signature_algorithms: Vec<u16>,
+    pub versions: Vec<u16>,
+    pub sni: Option<String>,
+    pub alpn: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Default)]
+pub struct ServerHello {
+    pub version: u16,
+    pub cipher: u16,
+    pub extensions: Vec<u16>,
+    pub selected_version: Option<u16>,
+    pub alpn: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Default)]
+pub struct Certificate {
+    pub issuer: Option<String>,
+    pub subject: Option<String>,
+    // microseconds since the epoch
+    pub not_after: Option<i64>,
+}
+
+#[derive(Clone, Default)]
+pub struct Handshake {
+    pub client: Option<ClientHello>,
+    pub server: Option<ServerHello>,
+    pub certificate: Option<Certificate>,
+}
+
+// reserved values that clients send to keep servers tolerant (RFC 8701)
+fn grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+//
+// Handshake messages (type, body) of the handshake records at the start of
+// a stream; a message cut short at the end is returned as far as it goes
+//
+fn messages(stream: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut data = Vec::new();
+    let mut r = Reader::new(stream);
+    while let (Some(HANDSHAKE), Some(_), Some(length)) = (r.u8(), r.u16(), r.u16()) {
+        let length = (length as usize).min(r.remaining());
+        data.extend_from_slice(r.take(length).unwrap_or_default());
+    }
+    let mut messages = Vec::new();
+    let mut r = Reader::new(&data);
+    while let (Some(kind), Some(length)) = (r.u8(), r.take(3)) {
+        let length = u32::from_be_bytes([0, length[0], length[1], length[2]]) as usize;
+        let body = r.take(length.min(r.remaining())).unwrap_or_default();
+        messages.push((kind, body.to_vec()));
+    }
+    messages
+}
+
+fn u16_list(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect()
+}
+
+// (type, data) of the extensions block that ends a hello
+fn extensions<'a>(r: &mut Reader<'a>) -> Vec<(u16, &'a [u8])> {
+    let mut list = Vec::new();
+    let Some(block) = r.u16().and_then(|length| r.take(length as usize)) else {
+        return list;
+    };
+    let mut r = Reader::new(block);
+    while let (Some(kind), Some(length)) = (r.u16(), r.u16()) {
+        match r.take(length as usize) {
+            Some(data) => list.push((kind, data)),
+            None => break,
+        }
+    }
+    list
+}
+
+// the first protocol of an ALPN extension
+fn first_alpn(data: &[u8]) -> Option<Vec<u8>> {
+    let mut r = Reader::new(data);
+    r.u16()?;
+    let length = r.u8()? as usize;
+    Some(r.take(length)?.to_vec())
+}
+
+fn client_hello(body: &[u8]) -> Option<ClientHello> {
+    let mut r = Reader::new(body);
+    let mut hello = ClientHello {
+        version: r.u16()?,
+        ..Default::default()
+    };
+    r.take(32)?;
+    let session = r.u8()? as usize;
+    r.take(session)?;
+    let ciphers = r.u16()? as usize;
+    hello.ciphers = u16_list(r.take(ciphers)?);
+    let compression = r.u8()? as usize;
+    r.take(compression)?;
+    for (kind, data) in extensions(&mut r) {
+        hello.extensions.push(kind);
+        let mut e = Reader::new(data);
+        match kind {
+            SERVER_NAME => {
+                e.u16();
+                if let (Some(0), Some(length)) = (e.u8(), e.u16()) {
+                    hello.sni = e
+                        .take(length as usize)
+                        .map(|name| String::from_utf8_lossy(name).into_owned());
+                }
+            }
+            SUPPORTED_GROUPS => hello.groups = u16_list(e.rest().get(2..).unwrap_or_default()),
+            EC_POINT_FORMATS => {
+                hello.point_formats = e.rest().get(1..).unwrap_or_default().to_vec()
+            }
+            SIGNATURE_ALGORITHMS => {
+                hello.signature_algorithms = u16_list(e.rest().get(2..).unwrap_or_default())
+            }
+            ALPN => hello.alpn = first_alpn(data),
+            SUPPORTED_VERSIONS => hello.versions = u16_list(e.rest().get(1..).unwrap_or_default()),
+            _ => {}
+        }
+    }
+    Some(hello)
+}
+
+fn server_hello(body: &[u8]) -> Option<ServerHello> {
+    let mut r = Reader::new(body);
+    let mut hello = ServerHello {
+        version: r.u16()?,
+        ..Default::default()
+    };
+    r.take(32)?;
+    let session = r.u8()? as usize;
+    r.take(session)?;
+    hello.cipher = r.u16()?;
+    r.u8()?;
+    for (kind, data) in extensions(&mut r) {
+        hello.extensions.push(kind);
+        match kind {
+            ALPN => hello.alpn = first_alpn(data),
+            SUPPORTED_VERSIONS => hello.selected_version = Reader::new(data).u16(),
+            _ => {}
+        }
+    }
+    Some(hello)
+}
+
+//
+// DER (tag, contents)
+//
+fn der<'a>(r: &mut Reader<'a>) -> Option<(u8, &'a [u8])> {
+    let tag = r.u8()?;
+    let first = r.u8()?;
+    let length = if first & 0x80 == 0 {
+        first as usize
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        r.take(count)?
+            .iter()
+            .fold(0usize, |length, b| length << 8 | *b as usize)
+    };
+    Some((tag, r.take(length)?))
+}
+
+fn der_string(tag: u8, value: &[u8]) -> String {
+    match tag {
+        // BMPString
+        0x1e => String::from_utf16_lossy(&u16_list(value)),
+        _ => String::from_utf8_lossy(value).into_owned(),
+    }
+}
+
+//
+// Name as "C=US, O=Example, CN=example.com", in certificate order, keeping
+// the attributes the YAF import reports
+//
+fn name(contents: &[u8]) -> Option<String> {
+    let mut parts = Vec::new();
+    let mut rdns = Reader::new(contents);
+    while let Some((_, set)) = der(&mut rdns) {
+        let mut attributes = Reader::new(set);
+        while let Some((_, attribute)) = der(&mut attributes) {
+            let mut a = Reader::new(attribute);
+            let (Some((0x06, oid)), Some((tag, value))) = (der(&mut a), der(&mut a)) else {
+                continue;
+            };
+            let label = match oid {
+                [0x55, 0x04, 3] => "CN",
+                [0x55, 0x04, 6] => "C",
+                [0x55, 0x04, 7] => "L",
+                [0x55, 0x04, 8] => "ST",
+                [0x55, 0x04, 10] => "O",
+                [0x55, 0x04, 11] => "OU",
+                _ => continue,
+            };
+            parts.push(format!("{}={}", label, der_string(tag, value)));
+        }
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
+// UTCTime (YYMMDDHHMMSSZ) or GeneralizedTime (YYYYMMDDHHMMSSZ)
+fn time(tag: u8, value: &[u8]) -> Option<i64> {
+    let text = std::str::from_utf8(value).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match (tag, text.len()) {
+        (0x17, 12) => {
+            let year: i32 = text.get(..2)?.parse().ok()?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &text[2..],
+            )
+        }
+        (0x18, 14) => (text.get(..4)?.parse().ok()?, &text[4..]),
+        _ => return None,
+    };
+    let field = |i: usize| -> Option<u32> { rest.get(i * 2..i * 2 + 2)?.parse().ok() };
+    let time = NaiveDate::from_ymd_opt(year, field(0)?, field(1)?)?.and_hms_opt(
+        field(2)?,
+        field(3)?,
+        field(4)?,
+    )?;
+    Some(time.and_utc().timestamp_micros())
+}
+
+fn certificate(body: &[u8]) -> Option<Certificate> {
+    let mut r = Reader::new(body);
+    r.take(3)?;
+    let length = r.take(3)?;
+    let length = u32::from_be_bytes([0, length[0], length[1], length[2]]) as usize;
+    let mut certificate = Reader::new(r.take(length)?);
+    let (_, certificate) = der(&mut certificate)?;
+    let (_, tbs) = der(&mut Reader::new(certificate))?;
+    let mut tbs = Reader::new(tbs);
+    let (mut tag, _) = der(&mut tbs)?;
+    // explicit version
+    if tag == 0xa0 {
+        (tag, _) = der(&mut tbs)?;
+    }
+    // serial number, then the signature algorithm
+    if tag != 0x02 {
+        return None;
+    }
+    der(&mut tbs)?;
+    let (_, issuer) = der(&mut tbs)?;
+    let (_, validity) = der(&mut tbs)?;
+    let (_, subject) = der(&mut tbs)?;
+    let mut validity = Reader::new(validity);
+    der(&mut validity)?;
+    let not_after = der(&mut validity).and_then(|(tag, value)| time(tag, value));
+    Some(Certificate {
+        issuer: name(issuer),
+        subject: name(subject),
+        not_after,
+    })
+}
+
+//
+// Decode the handshake from the start of the client and server streams;
+// None when neither side starts with a TLS hello
+//
+pub fn decode(client: &[u8], server: &[u8]) -> Option<Handshake> {
+    let mut handshake = Handshake::default();
+    for (kind, body) in messages(client) {
+        if kind == CLIENT_HELLO && handshake.client.is_none() {
+            handshake.client = client_hello(&body);
+        }
+    }
+    for (kind, body) in messages(server) {
+        match kind {
+            SERVER_HELLO if handshake.server.is_none() => handshake.server = server_hello(&body),
+            CERTIFICATE if handshake.certificate.is_none() => {
+                handshake.certificate = certificate(&body)
+            }
+            _ => {}
+        }
+    }
+    if handshake.client.is_none() && handshake.server.is_none() {
+        None
+    } else {
+        Some(handshake)
+    }
+}
+
+fn joined<T: ToString>(values: impl Iterator<Item = T>, separator: &str) -> String {
+    values
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join(separator)
+}
+
+fn ja4_version(version: u16) -> &'static str {
+    match version {
+        0x0304 => "13",
+        0x0303 => "12",
+        0x0302 => "11",
+        0x0301 => "10",
+        0x0300 => "s3",
+        0x0002 => "s2",
+        _ => "00",
+    }
+}
+
+// first and last character of the protocol, or of its hex when they are not alphanumeric
+fn ja4_alpn(alpn: &Option<Vec<u8>>) -> String {
+    match alpn.as_deref() {
+        Some([first, .., last]) | Some([first @ last]) => {
+            if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                format!("{}{}", *first as char, *last as char)
+            } else {
+                let hex = format!("{:02x}{:02x}", first, last);
+                format!("{}{}", &hex[..1], &hex[3..])
+            }
+        }
+        _ => String::from("00"),
+    }
+}
+
+// the first 12 hex digits of the sha256 of a list, or zeros for an empty one
+fn ja4_hash<E>(text: &str, sha256: &impl Fn(&str) -> Result<String, E>) -> Result<String, E> {
+    if text.is_empty() {
+        return Ok(String::from("000000000000"));
+    }
+    Ok(sha256(text)?.chars().take(12).collect())
+}
+
+impl ClientHello {
+    //
+    // JA3 string, before its md5
+    //
+    pub fn ja3(&self) -> String {
+        let list = |values: &[u16]| joined(values.iter().filter(|v| !grease(**v)), "-");
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            list(&self.ciphers),
+            list(&self.extensions),
+            list(&self.groups),
+            joined(self.point_formats.iter(), "-")
+        )
+    }
+
+    pub fn ja4<E>(&self, sha256: impl Fn(&str) -> Result<String, E>) -> Result<String, E> {
+        let version = self
+            .versions
+            .iter()
+            .filter(|v| !grease(**v))
+            .max()
+            .copied()
+            .unwrap_or(self.version);
+        let mut ciphers: Vec<u16> = self
+            .ciphers
+            .iter()
+            .copied()
+            .filter(|v| !grease(*v))
+            .collect();
+        let mut extensions: Vec<u16> = self
+            .extensions
+            .iter()
+            .copied()
+            .filter(|v| !grease(*v))
+            .collect();
+        let prefix = format!(
+            "t{}{}{:02}{:02}{}",
+            ja4_version(version),
+            if self.sni.is_some() { 'd' } else { 'i' },
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            ja4_alpn(&self.alpn)
+        );
+        ciphers.sort_unstable();
+        extensions.retain(|e| *e != SERVER_NAME && *e != ALPN);
+        extensions.sort_unstable();
+        let mut extension_text = joined(extensions.iter().map(|e| format!("{:04x}", e)), ",");
+        if !extension_text.is_empty() && !self.signature_algorithms.is_empty() {
+            extension_text.push('_');
+            extension_text.push_str(&joined(
+                self.signature_algorithms
+                    .iter()
+                    .map(|a| format!("{:04x}", a)),
+                ",",
+            ));
+        }
+        Ok(format!(
+            "{}_{}_{}",
+            prefix,
+            ja4_hash(
+                &joined(ciphers.iter().map(|c| format!("{:04x}", c)), ","),
+                &sha256
+            )?,
+            ja4_hash(&extension_text, &sha256)?
+        ))
+    }
+}
+
+impl ServerHello {
+    //
+    // JA3S string, before its md5
+    //
+    pub fn ja3s(&self) -> String {
+        format!(
+            "{},{},{}",
+            self.version,
+            self.cipher,
+            joined(self.extensions.iter(), "-")
+        )
+    }
+
+    pub fn ja4s<E>(&self, sha256: impl Fn(&str) -> Result<String, E>) -> Result<String, E> {
+        Ok(format!(
+            "t{}{:02}{}_{:04x}_{}",
+            ja4_version(self.selected_version.unwrap_or(self.version)),
+            self.extensions.len().min(99),
+            ja4_alpn(&self.alpn),
+            self.cipher,
+            ja4_hash(
+                &joined(self.extensions.iter().map(|e| format!("{:04x}", e)), ","),
+                &sha256
+            )?
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_bytes(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    // data prefixed with its length in width bytes
+    fn prefixed(width: usize, data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_be_bytes()[4 - width..].to_vec();
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn extension(kind: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = kind.to_be_bytes().to_vec();
+        out.extend(prefixed(2, data));
+        out
+    }
+
+    // handshake records of one message, split into records of at most split bytes
+    fn records(kind: u8, body: &[u8], split: usize) -> Vec<u8> {
+        let mut message = vec![kind];
+        message.extend(prefixed(3, body));
+        let mut out = Vec::new();
+        for chunk in message.chunks(split) {
+            out.extend([HANDSHAKE, 3, 1]);
+            out.extend(prefixed(2, chunk));
+        }
+        out
+    }
+
+    fn client_stream() -> Vec<u8> {
+        let mut body = u16_bytes(&[0x0303]);
+        body.extend([0; 32]);
+        body.push(0);
+        body.extend(prefixed(2, &u16_bytes(&[0x0a0a, 0x1301, 0x1302, 0xc02b])));
+        body.extend([1, 0]);
+        let mut server_name = vec![0];
+        server_name.extend(prefixed(2, b"example.com"));
+        let mut extensions = extension(0x0a0a, &[]);
+        extensions.extend(extension(SERVER_NAME, &prefixed(2, &server_name)));
+        extensions.extend(extension(SUPPORTED_GROUPS, &prefixed(2, &u16_bytes(&[0x001d, 0x0017]))));
+        extensions.extend(extension(EC_POINT_FORMATS, &prefixed(1, &[0])));
+        extensions.extend(extension(SIGNATURE_ALGORITHMS, &prefixed(2, &u16_bytes(&[0x0403, 0x0804]))));
+        extensions.extend(extension(ALPN, &prefixed(2, &prefixed(1, b"h2"))));
+        extensions.extend(extension(SUPPORTED_VERSIONS, &prefixed(1, &u16_bytes(&[0x0304, 0x0303]))));
+        body.extend(prefixed(2, &extensions));
+        records(CLIENT_HELLO, &body, 100)
+    }
+
+    fn der_bytes(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match contents.len() {
+            n if n < 0x80 => out.push(n as u8),
+            n if n < 0x100 => out.extend([0x81, n as u8]),
+            n => out.extend([0x82, (n >> 8) as u8, n as u8]),
+        }
+        out.extend_from_slice(contents);
+        out
+    }
+
+    fn der_name(attributes: &[(u8, &str)]) -> Vec<u8> {
+        let mut rdns = Vec::new();
+        for (kind, value) in attributes {
+            let mut attribute = der_bytes(0x06, &[0x55, 0x04, *kind]);
+            attribute.extend(der_bytes(0x0c, value.as_bytes()));
+            rdns.extend(der_bytes(0x31, &der_bytes(0x30, &attribute)));
+        }
+        der_bytes(0x30, &rdns)
+    }
+
+    fn server_stream() -> Vec<u8> {
+        let mut body = u16_bytes(&[0x0303]);
+        body.extend([0; 32]);
+        body.push(0);
+        body.extend(u16_bytes(&[0x1301]));
+        body.push(0);
+        let mut extensions = extension(SUPPORTED_VERSIONS, &u16_bytes(&[0x0304]));
+        extensions.extend(extension(ALPN, &prefixed(2, &prefixed(1, b"h2"))));
+        body.extend(prefixed(2, &extensions));
+        let mut stream = records(SERVER_HELLO, &body, 1000);
+
+        let mut tbs = der_bytes(0xa0, &der_bytes(0x02, &[2]));
+        tbs.extend(der_bytes(0x02, &[0x01, 0x23]));
+        tbs.extend(der_bytes(0x30, &der_bytes(0x06, &[0x2a, 0x86, 0x48])));
+        tbs.extend(der_name(&[(6, "US"), (10, "Example CA")]));
+        let mut validity = der_bytes(0x17, b"240101000000Z");
+        validity.extend(der_bytes(0x17, b"300101000000Z"));
+        tbs.extend(der_bytes(0x30, &validity));
+        tbs.extend(der_name(&[(3, "example.com")]));
+        let certificate = der_bytes(0x30, &der_bytes(0x30, &tbs));
+        stream.extend(records(CERTIFICATE, &prefixed(3, &prefixed(3, &certificate)), 1000));
+        stream
+    }
+
+    // a stand-in for sha256() that keeps the text, without separators
+    fn digest(text: &str) -> Result<String, ()> {
+        Ok(text.replace(',', ""))
+    }
+
+    #[test]
+    fn client_hello_fingerprints() {
+        let handshake = decode(&client_stream(), &[]).unwrap();
+        let client = handshake.client.unwrap();
+        assert_eq!(client.sni.as_deref(), Some("example.com"));
+        assert_eq!(client.alpn.as_deref(), Some(&b"h2"[..]));
+        assert_eq!(client.ja3(), "771,4865-4866-49195,0-10-11-13-16-43,29-23,0");
+        assert_eq!(client.ja4(digest).unwrap(), "t13d0306h2_13011302c02b_000a000b000d");
+        assert!(handshake.server.is_none());
+    }
+
+    #[test]
+    fn server_hello_and_certificate() {
+        let handshake = decode(&[], &server_stream()).unwrap();
+        let server = handshake.server.unwrap();
+        assert_eq!(server.selected_version, Some(0x0304));
+        assert_eq!(server.ja3s(), "771,4865,43-16");
+        assert_eq!(server.ja4s(digest).unwrap(), "t1302h2_1301_002b0010");
+        let certificate = handshake.certificate.unwrap();
+        assert_eq!(certificate.issuer.as_deref(), Some("C=US, O=Example CA"));
+        assert_eq!(certificate.subject.as_deref(), Some("CN=example.com"));
+        assert_eq!(certificate.not_after, Some(1_893_456_000_000_000));
+    }
+
+    #[test]
+    fn streams_cut_short() {
+        // the hello is kept up to its extensions
+        let client = decode(&client_stream()[..60], &[]).unwrap().client.unwrap();
+        assert_eq!(client.ciphers, vec![0x0a0a, 0x1301, 0x1302, 0xc02b]);
+        assert!(client.sni.is_none());
+        assert!(decode(&[], &[]).is_none());
+        assert!(decode(b"GET / HTTP/1.1\r\n", b"HTTP/1.1 200 OK\r\n").is_none());
+    }
+
+    #[test]
+    fn certificate_times() {
+        assert_eq!(time(0x17, b"491231235959Z"), Some(2_524_607_999_000_000));
+        assert_eq!(time(0x17, b"500101000000Z"), Some(-631_152_000_000_000));
+        assert_eq!(time(0x18, b"20500101000000Z"), Some(2_524_608_000_000_000));
+        assert_eq!(time(0x17, b"20500101000000Z"), None);
+        assert_eq!(time(0x17, b"241301000000Z"), None);
+    }
+
+    #[test]
+    fn grease_values() {
+        assert!(grease(0x0a0a));
+        assert!(grease(0xfafa));
+        assert!(!grease(0x0a1a));
+        assert!(!grease(0x1301));
+    }
+}
@@ -0,0 +1,358 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// WASM-sandboxed user transforms
+//
+// A transform module must not import anything and must export:
+//
+//   memory                                   linear memory
+//   gnat_alloc(len: i32) -> i32              buffer for an input record
+//   gnat_transform(ptr: i32, len: i32) -> i64
+//
+// Each flow record is passed to gnat_transform as a JSON object. The
+// module returns -1 to drop the record, or (ptr << 32 | len) of the
+// JSON object to emit in its place. Emitted records keep the flow
+// schema plus an optional "tag" string field.
+//
+// Every batch runs in a fresh instance limited by fuel (CPU) and memory.
+//
+
+use crate::core::error::GnatError;
+use crate::core::schema;
+use crate::core::scratch;
+use crate::core::spool::process_directory_parallel;
+
+use std::fs;
+use std::io::Write;
+
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+use tracing::{error, info};
+
+pub struct Transform {
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+    memory_limit: usize,
+}
+
+struct TransformState {
+    limits: StoreLimits,
+}
+
+impl Transform {
+    pub fn load(
+        module_spec: &String,
+        fuel: u64,
+        memory_limit: usize,
+    ) -> Result<Transform, GnatError> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| GnatError::Config(format!("wasm engine - {:?}", e)))?;
+        let module = Module::from_file(&engine, module_spec).map_err(|e| {
+            GnatError::Config(format!("loading transform {} - {:?}", module_spec, e))
+        })?;
+        Ok(Transform {
+            engine,
+            module,
+            fuel,
+            memory_limit,
+        })
+    }
+
+    //
+    // Pass each record to the module's export, in a fresh instance, and
+    // hand emit the index of the record and the JSON returned for it;
+    // returns the records emitted and dropped
+    //
+    pub(crate) fn each<F>(
+        &self,
+        export: &str,
+        records: Vec<String>,
+        mut emit: F,
+    ) -> Result<(u64, u64), wasmtime::Error>
+    where
+        F: FnMut(usize, &[u8]) -> Result<(), wasmtime::Error>,
+    {
+        let state = TransformState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.memory_limit)
+                .instances(1)
+                .build(),
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.fuel)?;
+
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or(wasmtime::Error::msg("missing memory export"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "gnat_alloc")?;
+        let transform = instance.get_typed_func::<(i32, i32), i64>(&mut store, export)?;
+
+        let mut kept = 0;
+        let mut dropped = 0;
+        for (index, record) in records.into_iter().enumerate() {
+            let len = record.len() as i32;
+            let ptr = alloc.call(&mut store, len)?;
+            memory.write(&mut store, ptr as usize, record.as_bytes())?;
+
+            let result = transform.call(&mut store, (ptr, len))?;
+            if result < 0 {
+                dropped += 1;
+                continue;
+            }
+            let out_ptr = (result >> 32) as usize;
+            let out_len = (result & 0xffff_ffff) as usize;
+            let mut buffer = vec![0u8; out_len];
+            memory.read(&store, out_ptr, &mut buffer)?;
+            emit(index, &buffer)?;
+            kept += 1;
+        }
+        Ok((kept, dropped))
+    }
+
+    fn run(
+        &self,
+        records: Vec<String>,
+        output: &mut fs::File,
+    ) -> Result<(u64, u64), wasmtime::Error> {
+        self.each("gnat_transform", records, |_, buffer| {
+            output.write_all(buffer)?;
+            output.write_all(b"\n")?;
+            Ok(())
+        })
+    }
+
+    pub fn transform_file(&self, input_spec: &String, output_spec: &String) -> Result<(), GnatError> {
+        let conn = scratch::open_in_memory("transform")?;
+        let source = match schema::select(&conn, input_spec) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading {} - {:?}", input_spec, e);
+                return Err(e);
+            }
+        };
+        let sql_command = format!(
+            "CREATE TABLE memtable AS {};
+             ALTER TABLE memtable ADD COLUMN IF NOT EXISTS tag VARCHAR;",
+            source
+        );
+        if let Err(e) = conn.execute_batch(&sql_command) {
+            error!("loading {} - {:?}", input_spec, e);
+            return Err(e.into());
+        }
+
+        let records: Vec<String> = {
+            let mut stmt = conn.prepare("SELECT to_json(m)::VARCHAR FROM memtable m;")?;
+            match stmt.query_map([], |row| row.get(0)) {
+                Ok(rows) => rows.collect::<Result<Vec<String>, _>>()?,
+                Err(e) => {
+                    error!("reading {} - {:?}", input_spec, e);
+                    return Err(e.into());
+                }
+            }
+        };
+
+        let ndjson_spec = format!("{}.ndjson", output_spec);
+        let mut ndjson = match fs::File::create(&ndjson_spec) {
+            Ok(f) => f,
+            Err(e) => {
+                error!("creating {} - {:?}", ndjson_spec, e);
+                return Err(e.into());
+            }
+        };
+        let (kept, dropped) = match self.run(records, &mut ndjson) {
+            Ok(c) => c,
+            Err(e) => {
+                error!("transform {} - {:?}", input_spec, e);
+                let _ = fs::remove_file(&ndjson_spec);
+                // the module fails the same way on every attempt
+                return Err(GnatError::Config(format!("transform {} - {}", input_spec, e)));
+            }
+        };
+        drop(ndjson);
+
+        let mut status = Ok(());
+        if kept > 0 {
+            let sql_command = format!(
+                "CREATE TABLE outtable AS SELECT * FROM memtable LIMIT 0;
+                 INSERT INTO outtable BY NAME SELECT * FROM read_json_auto('{}', format = 'newline_delimited');
+                 COPY outtable TO '{}' ({});",
+                ndjson_spec,
+                output_spec,
+                schema::copy_options()
+            );
+            if let Err(e) = conn.execute_batch(&sql_command) {
+                error!("writing {} - {:?}", output_spec, e);
+                status = Err(e.into());
+            }
+        }
+        let _ = fs::remove_file(&ndjson_spec);
+        info!(
+            "transform: {} [kept {}, dropped {}]",
+            input_spec, kept, dropped
+        );
+        status
+    }
+}
+
+//
+// Options of gnat_transform, as parsed and checked by main()
+//
+pub struct TransformConfig {
+    pub module_spec: String,
+    pub fuel: u64,
+    pub memory_limit: usize,
+    pub input_spec: String,
+    pub output_spec: String,
+    pub processed_spec: String,
+    pub polling: bool,
+    pub workers: usize,
+}
+
+pub fn transform(config: &TransformConfig) -> Result<(), std::io::Error> {
+    let TransformConfig {
+        ref module_spec,
+        fuel,
+        memory_limit,
+        ref input_spec,
+        ref output_spec,
+        ref processed_spec,
+        polling,
+        workers,
+    } = *config;
+    info!("module spec: {}", module_spec);
+    info!("fuel: {}", fuel);
+    info!("memory limit: {}", memory_limit);
+    info!("input spec: {}", input_spec);
+    info!("output spec: {}", output_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("polling: {}", polling);
+    info!("workers: {}", workers);
+
+    let transform = Transform::load(module_spec, fuel, memory_limit)?;
+
+    process_directory_parallel(
+        "transform",
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        workers,
+        |src_path, tmp_path| transform.transform_file(src_path, tmp_path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+    use std::path::Path;
+
+    // keeps every other record as it was passed
+    const ALTERNATE: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $calls (mut i32) (i32.const 0))
+        (func (export "gnat_alloc") (param i32) (result i32)
+            (i32.const 1024))
+        (func (export "gnat_transform") (param $ptr i32) (param $len i32) (result i64)
+            (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+            (if (result i64) (i32.and (global.get $calls) (i32.const 1))
+                (then (i64.or
+                    (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len))))
+                (else (i64.const -1)))))"#;
+
+    const SPIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (func (export "gnat_alloc") (param i32) (result i32)
+            (i32.const 1024))
+        (func (export "gnat_transform") (param i32 i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const -1)))"#;
+
+    fn load(dir: &str, name: &str, wat: &str) -> Transform {
+        let module_spec = format!("{}/{}.wat", dir, name);
+        fs::write(&module_spec, wat).unwrap();
+        Transform::load(&module_spec, 1_000_000, 1 << 20).unwrap()
+    }
+
+    #[test]
+    fn records_round_trip_the_module() {
+        let dir = test_dir("transform-records");
+        let transform = load(&dir, "alternate", ALTERNATE);
+        let records: Vec<String> = (0..3).map(|i| format!(r#"{{"n":{}}}"#, i)).collect();
+        let mut emitted = Vec::new();
+        let counts = transform
+            .each("gnat_transform", records, |index, buffer| {
+                emitted.push((index, String::from_utf8(buffer.to_vec()).unwrap()));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(counts, (2, 1));
+        assert_eq!(
+            emitted,
+            vec![
+                (0, String::from(r#"{"n":0}"#)),
+                (2, String::from(r#"{"n":2}"#))
+            ]
+        );
+
+        // runaway modules run out of fuel
+        let spin = load(&dir, "spin", SPIN);
+        let result = spin.each("gnat_transform", vec![String::from("{}")], |_, _| Ok(()));
+        assert!(result.is_err());
+        assert!(Transform::load(&format!("{}/missing.wat", dir), 1, 1).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_keep_the_emitted_flows() {
+        let dir = test_dir("transform-file");
+        let input_spec = format!("{}/flows.parquet", dir);
+        let output_spec = format!("{}/flows.out.parquet", dir);
+        schema::write_test_flows(
+            &input_spec,
+            "SELECT * FROM (VALUES ('10.0.0.1'), ('10.0.0.2'), ('10.0.0.3')) t(saddr)",
+        );
+        let transform = load(&dir, "alternate", ALTERNATE);
+        transform.transform_file(&input_spec, &output_spec).unwrap();
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT saddr, tag FROM '{}' ORDER BY saddr;",
+                output_spec
+            ))
+            .unwrap();
+        let rows: Vec<(String, Option<String>)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (String::from("10.0.0.1"), None),
+                (String::from("10.0.0.3"), None)
+            ]
+        );
+
+        // a module that fails is a configuration error, and leaves no output
+        let spin = load(&dir, "spin", SPIN);
+        let spun_spec = format!("{}/spun.parquet", dir);
+        assert!(matches!(
+            spin.transform_file(&input_spec, &spun_spec),
+            Err(GnatError::Config(_))
+        ));
+        assert!(!Path::new(&spun_spec).exists());
+        assert!(!Path::new(&format!("{}.ndjson", spun_spec)).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
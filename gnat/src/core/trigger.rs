@@ -0,0 +1,233 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Trigger records: detections over windows of flows rather than single
+// flows, written as their own parquet stream (gnat_stream 'trigger') so
+// they can be collected in one place whichever detector raised them.
+//
+// time and etime bound the window, detector names the kind of detection
+// and name the rule. tenant is the tenant of the flows the trigger was
+// raised over. saddr, daddr and dport are set when the detection is
+// about them; key holds the full group the rule was evaluated over. value
+// is what the rule measured and threshold the level it exceeded.
+// scan_type is set by the scan detector (vertical or horizontal). id is a
+// hash of the observ, detector, name, key and time of the trigger.
+//
+// Analysts label triggers by dropping CSV, JSON or parquet files with
+// detector, name, key and label columns in the labels directory under the
+// trigger directory, e.g. trigger rows picked by id with a label added.
+// New triggers that match a false-positive label are suppressed, unless a
+// true-positive label matches them as well.
+//
+
+use crate::core::error::GnatError;
+use crate::core::parquet;
+
+use std::fs;
+use std::path::Path;
+
+use duckdb::Connection;
+use tracing::{info, warn};
+
+pub const TRIGGER_STREAM: &str = "trigger";
+
+pub const TRIGGER_TABLE: &str = "CREATE TABLE trigger (
+    time TIMESTAMP, etime TIMESTAMP, observ VARCHAR, tenant VARCHAR,
+    detector VARCHAR, name VARCHAR,
+    saddr VARCHAR, daddr VARCHAR, dport USMALLINT, key VARCHAR,
+    value DOUBLE, threshold DOUBLE, detail VARCHAR, scan_type VARCHAR, id VARCHAR
+)";
+
+pub const LABEL_DIR: &str = "labels";
+const FALSE_POSITIVE: &str = "('fp', 'false-positive')";
+const TRUE_POSITIVE: &str = "('tp', 'true-positive')";
+
+//
+// SELECT of the label files in <trigger_spec>/labels, or None for none
+//
+fn labels(trigger_spec: &String) -> Option<String> {
+    let entries = fs::read_dir(Path::new(trigger_spec).join(LABEL_DIR)).ok()?;
+    let mut selects: Vec<String> = Vec::new();
+    for entry in entries.flatten() {
+        let path = String::from(entry.path().to_string_lossy());
+        let reader = if path.ends_with(".csv") {
+            "read_csv"
+        } else if path.ends_with(".json") {
+            "read_json"
+        } else if path.ends_with(".parquet") {
+            "read_parquet"
+        } else {
+            continue;
+        };
+        selects.push(format!(
+            "SELECT detector::VARCHAR AS detector, name::VARCHAR AS name, key::VARCHAR AS key,
+                lower(trim(label::VARCHAR)) AS label FROM {}('{}')",
+            reader, path
+        ));
+    }
+    if selects.is_empty() {
+        None
+    } else {
+        Some(selects.join(" UNION ALL "))
+    }
+}
+
+//
+// Delete the triggers labelled false positives; returns how many
+//
+fn suppress(conn: &Connection, trigger_spec: &String) -> usize {
+    let Some(labels) = labels(trigger_spec) else {
+        return 0;
+    };
+    let sql_command = format!(
+        "CREATE OR REPLACE TEMP TABLE labels AS {};
+         DELETE FROM trigger t WHERE EXISTS
+                (SELECT 1 FROM labels l WHERE l.label IN {fp}
+                    AND l.detector = t.detector AND l.name = t.name AND l.key = t.key)
+            AND NOT EXISTS
+                (SELECT 1 FROM labels l WHERE l.label IN {tp}
+                    AND l.detector = t.detector AND l.name = t.name AND l.key = t.key);",
+        labels,
+        fp = FALSE_POSITIVE,
+        tp = TRUE_POSITIVE
+    );
+    let before = count(conn).unwrap_or(0);
+    if let Err(e) = conn.execute_batch(&sql_command) {
+        // raise the triggers rather than lose them to a bad label file
+        warn!("reading labels in {}/{} - {:?}", trigger_spec, LABEL_DIR, e);
+        return 0;
+    }
+    (before - count(conn).unwrap_or(0)) as usize
+}
+
+fn count(conn: &Connection) -> Result<u64, GnatError> {
+    Ok(conn.query_row("SELECT count(*) FROM trigger;", [], |row| row.get(0))?)
+}
+
+// the columns every detector sets
+pub const TRIGGER_INSERT: &str = "INSERT INTO trigger (time, etime, observ, tenant, detector, name,
+    saddr, daddr, dport, key, value, threshold, detail)";
+
+//
+// Write the trigger table to trigger.<stage>.<name> in trigger_spec through
+// a hidden file, so stages can share the directory; returns the number of
+// triggers left once labelled false positives are suppressed, and writes
+// nothing for none
+//
+pub fn write(
+    conn: &Connection,
+    trigger_spec: &String,
+    stage: &str,
+    name: &str,
+) -> Result<u64, GnatError> {
+    let suppressed = suppress(conn, trigger_spec);
+    if suppressed > 0 {
+        info!("{}: suppressed {} labelled triggers", stage, suppressed);
+    }
+    let count = count(conn)?;
+    if count == 0 {
+        return Ok(0);
+    }
+    let file_name = format!("trigger.{}.{}", stage, name);
+    let tmp_spec = format!("{}/.{}", trigger_spec, file_name);
+    let sql_command = format!(
+        "UPDATE trigger SET id = md5(concat_ws('|', observ, detector, name, key, time));
+         COPY (SELECT * FROM trigger ORDER BY time, detector, name) TO '{}'
+            ({}, KV_METADATA {{gnat_stream: '{}'}});",
+        tmp_spec,
+        parquet::options(),
+        TRIGGER_STREAM
+    );
+    conn.execute_batch(&sql_command)?;
+    fs::rename(&tmp_spec, Path::new(trigger_spec).join(file_name))?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::test_dir;
+
+    fn raise(keys: &[&str]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!("{};", TRIGGER_TABLE)).unwrap();
+        for key in keys.iter() {
+            conn.execute_batch(&format!(
+                "{} VALUES (TIMESTAMP '2024-01-01', TIMESTAMP '2024-01-01 01:00:00', 's1', NULL,
+                    'rule', 'many', NULL, NULL, NULL, '{}', 10, 5, NULL);",
+                TRIGGER_INSERT, key
+            ))
+            .unwrap();
+        }
+        conn
+    }
+
+    // (key, id is set, stream) of the triggers in trigger_spec
+    fn written(trigger_spec: &str) -> Vec<(String, bool, String)> {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT key, id IS NOT NULL,
+                    (SELECT decode(value) FROM parquet_kv_metadata('{spec}/trigger.*')
+                        WHERE decode(key) = 'gnat_stream' LIMIT 1)
+                 FROM read_parquet('{spec}/trigger.*') ORDER BY key;",
+                spec = trigger_spec
+            ))
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn labels_suppress_false_positives() {
+        let dir = test_dir("trigger-labels");
+        fs::create_dir_all(format!("{}/{}", dir, LABEL_DIR)).unwrap();
+        fs::write(
+            format!("{}/{}/labels.csv", dir, LABEL_DIR),
+            "detector,name,key,label\nrule,many,a,FP\nrule,many,b,false-positive\nrule,many,b,tp\nrule,other,c,fp\n",
+        )
+        .unwrap();
+        // files of other types are skipped
+        fs::write(format!("{}/{}/README", dir, LABEL_DIR), "labels").unwrap();
+
+        let conn = raise(&["a", "b", "c"]);
+        assert_eq!(write(&conn, &dir, "detect", "x.parquet").unwrap(), 2);
+        let stream = String::from(TRIGGER_STREAM);
+        assert_eq!(
+            written(&dir),
+            vec![
+                (String::from("b"), true, stream.clone()),
+                (String::from("c"), true, stream)
+            ]
+        );
+        assert!(!Path::new(&format!("{}/.trigger.detect.x.parquet", dir)).exists());
+
+        // nothing is written when every trigger is suppressed
+        let conn = raise(&["a"]);
+        assert_eq!(write(&conn, &dir, "detect", "y.parquet").unwrap(), 0);
+        assert!(!Path::new(&format!("{}/trigger.detect.y.parquet", dir)).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn bad_labels_suppress_nothing() {
+        let dir = test_dir("trigger-bad-labels");
+        fs::create_dir_all(format!("{}/{}", dir, LABEL_DIR)).unwrap();
+        fs::write(
+            format!("{}/{}/labels.csv", dir, LABEL_DIR),
+            "detector,label\nrule,fp\n",
+        )
+        .unwrap();
+        let conn = raise(&["a"]);
+        assert_eq!(write(&conn, &dir, "detect", "x.parquet").unwrap(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,151 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Dry run of a stage (--validate true)
+//
+// Once its options pass the usual checks, a stage run with --validate true
+// checks what it would otherwise only find out mid-run, then exits with a
+// report instead of processing any data: that its directories are writable
+// (s3:// paths listed with its credentials), that DuckDB opens, and that
+// its rule, indicator, mapping, model and plugin files load; rules are
+// compiled to their SQL against an empty flow table. The exit code is 0
+// when every check passed and CONFIG (78) otherwise.
+//
+
+use crate::core::health;
+use crate::core::shutdown;
+
+use std::fmt::Display;
+
+use tracing::{error, info};
+
+#[derive(Debug, clap::Args)]
+pub struct ValidateArgs {
+    /// check the options, directories and files, then exit without processing
+    #[arg(long)]
+    pub validate: Option<bool>,
+}
+
+impl ValidateArgs {
+    pub fn enabled(&self) -> bool {
+        self.validate.unwrap_or(false)
+    }
+}
+
+pub struct Report {
+    stage: String,
+    checks: Vec<(String, Result<(), String>)>,
+}
+
+impl Report {
+    //
+    // Start the report of stage with the checks of its non-empty paths
+    // and DuckDB
+    //
+    pub fn new(stage: &str, paths: &[&String]) -> Report {
+        let mut report = Report {
+            stage: String::from(stage),
+            checks: Vec::new(),
+        };
+        for path_spec in paths.iter().filter(|path_spec| !path_spec.is_empty()) {
+            report.check(&format!("path {}", path_spec), health::check_path(path_spec));
+        }
+        report.check("duckdb", health::check_duckdb());
+        report
+    }
+
+    pub fn check<T, E: Display>(&mut self, name: &str, result: Result<T, E>) {
+        self.checks
+            .push((String::from(name), result.map(|_| ()).map_err(|e| e.to_string())));
+    }
+
+    //
+    // Log the checks and exit
+    //
+    pub fn exit(self) -> ! {
+        let mut failed = 0;
+        for (name, result) in self.checks.iter() {
+            match result {
+                Ok(()) => info!("validate: ok {}", name),
+                Err(e) => {
+                    error!("validate: failed {} - {}", name, e);
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            error!(
+                "validate: {} [{} of {} checks failed]",
+                self.stage,
+                failed,
+                self.checks.len()
+            );
+            shutdown::exit(exitcode::CONFIG)
+        }
+        info!("validate: {} [{} checks passed]", self.stage, self.checks.len());
+        shutdown::exit(exitcode::OK)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context;
+    use crate::core::testutil::test_dir;
+    use std::path::PathBuf;
+
+    // exit code of the report made by build, from a stage thread
+    fn exit_code(build: impl FnOnce() -> Report + Send + 'static) -> i32 {
+        let stage = std::thread::spawn(move || {
+            context::enter(context::next());
+            build().exit()
+        });
+        let payload = stage.join().unwrap_err();
+        payload.downcast_ref::<shutdown::Exit>().unwrap().0
+    }
+
+    #[test]
+    fn checks_decide_the_exit_code() {
+        let dir = PathBuf::from(test_dir("validate"));
+        let dir_spec = dir.to_string_lossy().to_string();
+        let missing_spec = format!("{}/missing", dir_spec);
+
+        let report = Report::new("test", &[&dir_spec, &String::new()]);
+        let names: Vec<&str> = report
+            .checks
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, vec![format!("path {}", dir_spec).as_str(), "duckdb"]);
+        assert!(report.checks.iter().all(|(_, result)| result.is_ok()));
+
+        let spec = dir_spec.clone();
+        assert_eq!(
+            exit_code(move || {
+                let mut report = Report::new("test", &[&spec]);
+                report.check("rules", Ok::<(), String>(()));
+                report
+            }),
+            exitcode::OK
+        );
+        assert_eq!(
+            exit_code(move || Report::new("test", &[&missing_spec])),
+            exitcode::CONFIG
+        );
+        assert_eq!(
+            exit_code(move || {
+                let mut report = Report::new("test", &[&dir_spec]);
+                report.check("rules", Err::<(), &str>("line 1: bad rule"));
+                report
+            }),
+            exitcode::CONFIG
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
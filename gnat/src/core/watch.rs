@@ -0,0 +1,178 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Input directory watches for the scan loops (inotify)
+//
+// A scan loop that finds nothing to do waits on a watch of its input
+// directory instead of sleeping out its poll interval, so a file written
+// or renamed into place is picked up as soon as it lands and an idle stage
+// doesn't rescan every second. The directory is still rescanned every
+// RESCAN_INTERVAL, for changes inotify doesn't report (NFS, other hosts
+// writing to a shared directory); without a watch the loop polls as before.
+//
+// Events are debounced: a wait returns once the directory has been quiet
+// for DEBOUNCE, or at most DEBOUNCE_LIMIT after the first event, so a burst
+// of files is picked up by one scan.
+//
+// Stages block on DuckDB for the whole of a batch, so each stage runs its
+// scan loop on a thread of its own (the main thread of a stage binary, or
+// a thread of gnat_run) and waits here between scans, rather than as a
+// task of an async runtime.
+//
+
+use crate::core::health;
+use crate::core::shutdown;
+
+use std::ffi::CString;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+const WATCH_EVENTS: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+const RESCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+// how often a wait checks for shutdown
+const WAIT_STEP: Duration = Duration::from_millis(250);
+const DEBOUNCE: Duration = Duration::from_millis(100);
+const DEBOUNCE_LIMIT: Duration = Duration::from_secs(1);
+
+pub struct Watch {
+    fd: libc::c_int,
+}
+
+impl Watch {
+    //
+    // Watch dir_spec; None when inotify is unavailable, e.g. when the
+    // per-user watch limit is reached
+    //
+    pub fn new(dir_spec: &str) -> Option<Watch> {
+        let Ok(path) = CString::new(dir_spec) else {
+            return None;
+        };
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            warn!(
+                "watching {} - {}; polling instead",
+                dir_spec,
+                std::io::Error::last_os_error()
+            );
+            return None;
+        }
+        if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), WATCH_EVENTS) } < 0 {
+            warn!(
+                "watching {} - {}; polling instead",
+                dir_spec,
+                std::io::Error::last_os_error()
+            );
+            unsafe { libc::close(fd) };
+            return None;
+        }
+        debug!("watching {}", dir_spec);
+        Some(Watch { fd })
+    }
+
+    //
+    // Wait until a file lands in the directory, the interval passes or the
+    // stage is woken; returns false if shutdown was requested
+    //
+    pub fn wait(&self, interval: Duration) -> bool {
+        let deadline = Instant::now() + interval;
+        loop {
+            if shutdown::requested() {
+                return false;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || shutdown::woken() {
+                return true;
+            }
+            health::beat();
+            if self.ready(remaining.min(WAIT_STEP)) {
+                self.debounce();
+                return !shutdown::requested();
+            }
+        }
+    }
+
+    fn ready(&self, timeout: Duration) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) > 0 }
+    }
+
+    fn debounce(&self) {
+        let limit = Instant::now() + DEBOUNCE_LIMIT;
+        self.drain();
+        while Instant::now() < limit && self.ready(DEBOUNCE) {
+            self.drain();
+        }
+    }
+
+    // the events themselves don't matter: the loop rescans the directory
+    fn drain(&self) {
+        let mut buffer = [0u8; 4096];
+        while unsafe { libc::read(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) } > 0 {}
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+//
+// Wait on watch, or sleep out poll_interval without one; returns false if
+// shutdown was requested
+//
+pub fn wait(watch: &Option<Watch>, poll_interval: Duration) -> bool {
+    match watch {
+        Some(watch) => watch.wait(RESCAN_INTERVAL),
+        None => shutdown::sleep(poll_interval),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context;
+    use crate::core::testutil::test_dir;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::thread;
+
+    #[test]
+    fn files_landing_end_the_wait() {
+        let dir = PathBuf::from(test_dir("watch"));
+        let dir_spec = dir.to_string_lossy().to_string();
+        assert!(Watch::new(&format!("{}/missing", dir_spec)).is_none());
+
+        let id = context::next();
+        let waiter = thread::spawn(move || {
+            context::enter(id);
+            let watch = Watch::new(&dir_spec).unwrap();
+            // nothing lands: the wait runs out its interval
+            let start = Instant::now();
+            assert!(watch.wait(Duration::from_millis(300)));
+            assert!(start.elapsed() >= Duration::from_millis(300));
+
+            let start = Instant::now();
+            assert!(watch.wait(Duration::from_secs(30)));
+            start.elapsed()
+        });
+        thread::sleep(Duration::from_millis(800));
+        fs::write(dir.join(".a.parquet"), b"flows").unwrap();
+        fs::rename(dir.join(".a.parquet"), dir.join("a.parquet")).unwrap();
+        assert!(waiter.join().unwrap() < Duration::from_secs(10));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
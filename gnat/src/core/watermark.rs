@@ -0,0 +1,180 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Spool backpressure: a stage stops producing while its output directory
+// holds more than the high watermark (files or MB), so a stalled consumer
+// (e.g. gnat_db while QuestDB is down) backs files up into the upstream
+// spools instead of filling the disk. Each stage pausing in turn carries
+// the pressure back towards the importer.
+//
+
+use crate::core::context::Local;
+use crate::core::shutdown;
+
+use std::fs;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Debug, clap::Args)]
+pub struct WatermarkArgs {
+    /// pause while --output holds more files than this (0 = no limit)
+    #[arg(long)]
+    pub high_watermark_files: Option<u64>,
+
+    /// pause while --output holds more than this many MB (0 = no limit)
+    #[arg(long)]
+    pub high_watermark_mb: Option<u64>,
+}
+
+struct Watermark {
+    max_files: u64,
+    max_bytes: u64,
+}
+
+static HIGH_WATERMARK: Local<Watermark> = Local::new();
+
+//
+// 0 disables a limit
+//
+pub fn set_high_watermark(max_files: u64, max_mb: u64) {
+    info!("high watermark files: {}", max_files);
+    info!("high watermark MB: {}", max_mb);
+    let _ = HIGH_WATERMARK.set(Watermark {
+        max_files,
+        max_bytes: max_mb.saturating_mul(1024 * 1024),
+    });
+}
+
+//
+// Set the high watermark of a stage's --high-watermark-* arguments
+//
+pub fn configure(args: &WatermarkArgs) {
+    set_high_watermark(
+        args.high_watermark_files.unwrap_or(0),
+        args.high_watermark_mb.unwrap_or(0),
+    );
+}
+
+//
+// Number and total size of the (non-hidden) files waiting in spool_spec
+//
+pub fn depth(spool_spec: &String) -> (u64, u64) {
+    let mut files = 0;
+    let mut bytes = 0;
+    if let Ok(directory) = fs::read_dir(spool_spec) {
+        for entry in directory.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if let Ok(metadata) = entry.metadata() {
+                if metadata.is_file() {
+                    files += 1;
+                    bytes += metadata.len();
+                }
+            }
+        }
+    }
+    (files, bytes)
+}
+
+//
+// Block while output_spec is above the high watermark; returns false if a
+// shutdown was requested while waiting
+//
+pub fn wait(stage: &str, output_spec: &String) -> bool {
+    let Some(watermark) = HIGH_WATERMARK.get() else {
+        return true;
+    };
+    if watermark.max_files == 0 && watermark.max_bytes == 0 {
+        return true;
+    }
+    let poll_interval = Duration::from_secs(5);
+    let mut paused = false;
+    loop {
+        let (files, bytes) = depth(output_spec);
+        let above = (watermark.max_files > 0 && files > watermark.max_files)
+            || (watermark.max_bytes > 0 && bytes > watermark.max_bytes);
+        if !above {
+            if paused {
+                info!(
+                    "{}: {} below high watermark [{} files, {} MB]; resuming",
+                    stage,
+                    output_spec,
+                    files,
+                    bytes / (1024 * 1024)
+                );
+            }
+            return true;
+        }
+        if !paused {
+            warn!(
+                "{}: {} above high watermark [{} files, {} MB]; pausing",
+                stage,
+                output_spec,
+                files,
+                bytes / (1024 * 1024)
+            );
+            paused = true;
+        }
+        if !shutdown::sleep(poll_interval) {
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::context;
+    use crate::core::testutil::test_dir;
+    use std::path::PathBuf;
+    use std::thread;
+
+    #[test]
+    fn wait_pauses_above_the_watermark() {
+        let dir = PathBuf::from(test_dir("watermark"));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.parquet"), b"12345").unwrap();
+        fs::write(dir.join("b.parquet"), b"123").unwrap();
+        // hidden files and directories aren't counted
+        fs::write(dir.join(".c.parquet"), b"12345678").unwrap();
+        let spool_spec = dir.to_string_lossy().to_string();
+        assert_eq!(depth(&spool_spec), (2, 8));
+        assert_eq!(depth(&format!("{}/missing", spool_spec)), (0, 0));
+
+        let id = context::next();
+        let spec = spool_spec.clone();
+        let waiter = thread::spawn(move || {
+            context::enter(id);
+            set_high_watermark(1, 0);
+            wait("test", &spec)
+        });
+        // the waiter stays paused until a file is consumed
+        thread::sleep(Duration::from_millis(500));
+        assert!(!waiter.is_finished());
+        fs::remove_file(dir.join("a.parquet")).unwrap();
+        shutdown::wake(id);
+        assert!(waiter.join().unwrap());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn no_limit_never_waits() {
+        let id = context::next();
+        thread::spawn(move || {
+            context::enter(id);
+            // the stage hasn't set a watermark yet
+            assert!(wait("test", &String::from("/nonexistent")));
+            set_high_watermark(0, 0);
+            assert!(wait("test", &String::from("/nonexistent")));
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,585 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+/*
+ * DPI records exported by YAF with the dpacketplugin follow the flow in its
+ * subTemplateMultiList. The DNS records are written, one row per question
+ * and resource record, to a dns table next to the flow table and copied to
+ * dns.<observation>.<time>.parquet in the DNS output directory when the
+ * flow file is closed. The SSL/TLS record fills the sni and certificate
+ * columns of the flow itself, and the HTTP record its http columns.
+ *
+ * The templates and layouts follow the YAF project: ${YAF_PROJECT_DIR}/src/applabel/plugins/dpacketplugin.c
+ */
+
+#include <ctype.h>
+#include <errno.h>
+#include <limits.h>
+#include <string.h>
+#include <time.h>
+
+#include <airframe/airutil.h>
+
+#include "yaf_record.h"
+#include "io_context.h"
+#include "export_dpi.h"
+#include "export_parquet.h"
+
+static fbInfoElementSpec_t g_yaf_dns_spec[] = {
+    { "subTemplateList",                    FB_IE_VARLEN, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_dns_qr_spec[] = {
+    { "subTemplateList",                    FB_IE_VARLEN, 0 },
+    { "dnsQName",                           FB_IE_VARLEN, 0 },
+    { "dnsTTL",                             4, 0 },
+    { "dnsQRType",                          2, 0 },
+    { "dnsQueryResponse",                   1, 0 },
+    { "dnsAuthoritative",                   1, 0 },
+    { "dnsNXDomain",                        1, 0 },
+    { "dnsRRSection",                       1, 0 },
+    { "dnsID",                              2, 0 },
+    { "paddingOctets",                      4, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_dns_a_spec[] = {
+    { "sourceIPv4Address",                  4, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_dns_aaaa_spec[] = {
+    { "sourceIPv6Address",                  16, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_dns_cname_spec[] = {
+    { "dnsCName",                           FB_IE_VARLEN, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_dns_mx_spec[] = {
+    { "dnsMXExchange",                      FB_IE_VARLEN, 0 },
+    { "dnsMXPreference",                    2, 0 },
+    { "paddingOctets",                      6, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_dns_ns_spec[] = {
+    { "dnsNSDName",                         FB_IE_VARLEN, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_dns_ptr_spec[] = {
+    { "dnsPTRDName",                        FB_IE_VARLEN, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_dns_txt_spec[] = {
+    { "dnsTXTData",                         FB_IE_VARLEN, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_ssl_spec[] = {
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "sslServerCipher",                    4, 0 },
+    { "sslClientVersion",                   1, 0 },
+    { "sslCompressionMethod",               1, 0 },
+    { "sslRecordVersion",                   2, 0 },
+    { "subTemplateList",                    FB_IE_VARLEN, 0 },
+    { "sslServerName",                      FB_IE_VARLEN, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_ssl_cert_spec[] = {
+    { "subTemplateList",                    FB_IE_VARLEN, 0 },
+    { "subTemplateList",                    FB_IE_VARLEN, 0 },
+    { "subTemplateList",                    FB_IE_VARLEN, 0 },
+    { "sslCertSignature",                   FB_IE_VARLEN, 0 },
+    { "sslCertSerialNumber",                FB_IE_VARLEN, 0 },
+    { "sslCertValidityNotBefore",           FB_IE_VARLEN, 0 },
+    { "sslCertValidityNotAfter",            FB_IE_VARLEN, 0 },
+    { "sslPublicKeyAlgorithm",              FB_IE_VARLEN, 0 },
+    { "sslPublicKeyLength",                 2, 0 },
+    { "sslCertVersion",                     1, 0 },
+    { "paddingOctets",                      5, 0 },
+    FB_IESPEC_NULL
+};
+
+static fbInfoElementSpec_t g_yaf_ssl_object_spec[] = {
+    { "sslObjectValue",                     FB_IE_VARLEN, 0 },
+    { "sslObjectID",                        1, 0 },
+    { "paddingOctets",                      7, 0 },
+    FB_IESPEC_NULL
+};
+
+/* one basicList per header captured by YAF's HTTP rules */
+static fbInfoElementSpec_t g_yaf_http_spec[] = {
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    { "basicList",                          FB_IE_VARLEN, 0 },
+    FB_IESPEC_NULL
+};
+
+/* CERT (PEN 6871) elements of the HTTP lists */
+#define CERT_PEN 6871
+#define HTTP_USER_AGENT_IE 111
+#define HTTP_GET_IE 112
+#define HTTP_HOST_IE 117
+#define HTTP_RESPONSE_IE 123
+
+static gboolean
+AddTemplate(fbInfoModel_t *model, fbSession_t *session, uint16_t tid, fbInfoElementSpec_t *spec, GError **err)
+{
+    fbTemplate_t *template = fbTemplateAlloc(model);
+    if (template == NULL)
+        return FALSE;
+
+    if (fbTemplateAppendSpecArray(template, spec, 0, err) == FALSE ||
+        !fbSessionAddTemplate(session, TRUE, tid, template, NULL, err))
+    {
+        fbTemplateFreeUnused(template);
+        return FALSE;
+    }
+    return TRUE;
+}
+
+gboolean
+AddDpiTemplates(fbInfoModel_t *model, fbSession_t *session, GError **err)
+{
+    return AddTemplate(model, session, YAF_DNS_TID, g_yaf_dns_spec, err) &&
+           AddTemplate(model, session, YAF_DNS_QR_TID, g_yaf_dns_qr_spec, err) &&
+           AddTemplate(model, session, YAF_DNS_A_TID, g_yaf_dns_a_spec, err) &&
+           AddTemplate(model, session, YAF_DNS_AAAA_TID, g_yaf_dns_aaaa_spec, err) &&
+           AddTemplate(model, session, YAF_DNS_CNAME_TID, g_yaf_dns_cname_spec, err) &&
+           AddTemplate(model, session, YAF_DNS_MX_TID, g_yaf_dns_mx_spec, err) &&
+           AddTemplate(model, session, YAF_DNS_NS_TID, g_yaf_dns_ns_spec, err) &&
+           AddTemplate(model, session, YAF_DNS_PTR_TID, g_yaf_dns_ptr_spec, err) &&
+           AddTemplate(model, session, YAF_DNS_TXT_TID, g_yaf_dns_txt_spec, err) &&
+           AddTemplate(model, session, YAF_SSL_TID, g_yaf_ssl_spec, err) &&
+           AddTemplate(model, session, YAF_SSL_CERT_TID, g_yaf_ssl_cert_spec, err) &&
+           AddTemplate(model, session, YAF_SSL_OBJECT_TID, g_yaf_ssl_object_spec, err) &&
+           AddTemplate(model, session, YAF_HTTP_TID, g_yaf_http_spec, err);
+}
+
+gboolean
+OpenDnsSink(GNAT_CONTEXT *gnat)
+{
+    gnat->dns_records = 0;
+    if (gnat->dns_output_dir == NULL)
+        return TRUE;
+
+    duckdb_result db_result;
+    if (duckdb_query(gnat->con, DNS_SCHEMA, &db_result) == DuckDBError)
+    {
+        fprintf(stderr, "%s: failed to generating schema: \n%s\n", __FUNCTION__, duckdb_result_error(&db_result));
+        return FALSE;
+    }
+    if (duckdb_appender_create(gnat->con, NULL, "dns", &gnat->dns_appender) == DuckDBError)
+    {
+        fprintf(stderr, "%s: failed to create appender\n", __FUNCTION__);
+        return FALSE;
+    }
+    return TRUE;
+}
+
+static void
+AppendVarfield(duckdb_appender appender, const fbVarfield_t *field)
+{
+    if (field->len)
+        duckdb_append_varchar_length(appender, (const char *)field->buf, field->len);
+    else
+        duckdb_append_null(appender);
+}
+
+//
+// The answer of a resource record as text; FALSE for record types without one
+//
+static gboolean
+FormatAnswer(const YAF_DNS_QR_RECORD *qr, GString *answer)
+{
+    const void *rr = fbSubTemplateListGetDataPtr(&qr->dnsRRList);
+    if (rr == NULL)
+        return FALSE;
+
+    char abuf[64];
+    const YAF_DNS_NAME_RECORD *name = NULL;
+    switch (fbSubTemplateListGetTemplateID(&qr->dnsRRList))
+    {
+    case YAF_DNS_A_TID:
+        air_ipaddr_buf_print(abuf, ((const YAF_DNS_A_RECORD *)rr)->ip);
+        g_string_assign(answer, abuf);
+        return TRUE;
+    case YAF_DNS_AAAA_TID:
+        air_ip6addr_buf_print(abuf, ((const YAF_DNS_AAAA_RECORD *)rr)->ip);
+        g_string_assign(answer, abuf);
+        return TRUE;
+    case YAF_DNS_MX_TID:
+    {
+        const YAF_DNS_MX_RECORD *mx = (const YAF_DNS_MX_RECORD *)rr;
+        g_string_printf(answer, "%u %.*s", mx->preference, (int)mx->exchange.len, (const char *)mx->exchange.buf);
+        return TRUE;
+    }
+    case YAF_DNS_CNAME_TID:
+    case YAF_DNS_NS_TID:
+    case YAF_DNS_PTR_TID:
+    case YAF_DNS_TXT_TID:
+        name = (const YAF_DNS_NAME_RECORD *)rr;
+        g_string_printf(answer, "%.*s", (int)name->name.len, (const char *)name->name.buf);
+        return TRUE;
+    default:
+        return FALSE;
+    }
+}
+
+static int
+AppendDnsRecord(duckdb_appender appender,
+                const char *observation,
+                const YAF_FLOW_RECORD *flow,
+                const YAF_DNS_QR_RECORD *qr,
+                GString *answer)
+{
+    char sabuf[64], dabuf[64];
+
+    duckdb_append_varchar(appender, observation);
+    duckdb_timestamp start = {(flow->flowStartMilliseconds * 1000)};
+    duckdb_append_timestamp(appender, start);
+
+    sabuf[0] = (char)0;
+    dabuf[0] = (char)0;
+    if (flow->sourceIPv4Address || flow->destinationIPv4Address)
+    {
+        air_ipaddr_buf_print(sabuf, flow->sourceIPv4Address);
+        air_ipaddr_buf_print(dabuf, flow->destinationIPv4Address);
+    }
+    else
+    {
+        air_ip6addr_buf_print(sabuf, flow->sourceIPv6Address);
+        air_ip6addr_buf_print(dabuf, flow->destinationIPv6Address);
+    }
+    duckdb_append_varchar(appender, sabuf);
+    duckdb_append_varchar(appender, dabuf);
+    duckdb_append_uint16(appender, flow->sourceTransportPort);
+    duckdb_append_uint16(appender, flow->destinationTransportPort);
+
+    duckdb_append_uint16(appender, qr->dnsID);
+    duckdb_append_bool(appender, qr->dnsQueryResponse != 0);
+    duckdb_append_uint8(appender, qr->dnsRRSection);
+    AppendVarfield(appender, &qr->dnsQName);
+    duckdb_append_uint16(appender, qr->dnsQRType);
+    // YAF reports the response code in dnsNXDomain
+    duckdb_append_uint8(appender, qr->dnsNXDomain);
+    duckdb_append_bool(appender, qr->dnsAuthoritative != 0);
+    duckdb_append_uint32(appender, qr->dnsTTL);
+    if (FormatAnswer(qr, answer))
+        duckdb_append_varchar_length(appender, answer->str, answer->len);
+    else
+        duckdb_append_null(appender);
+
+    if (duckdb_appender_end_row(appender) == DuckDBError)
+    {
+        fprintf(stderr, "%s: %s\n", __FUNCTION__, duckdb_appender_error(appender));
+        return -1;
+    }
+    return 0;
+}
+
+int
+AppendDnsRecords(GNAT_CONTEXT *gnat, const YAF_FLOW_RECORD *flow)
+{
+    if (gnat->dns_appender == NULL)
+        return 0;
+
+    int count = 0;
+    GString *answer = g_string_sized_new(256);
+    fbSubTemplateMultiList_t *stml = (fbSubTemplateMultiList_t *)&flow->subTemplateMultiList;
+    fbSubTemplateMultiListEntry_t *entry = NULL;
+    while ((entry = fbSubTemplateMultiListGetNextEntry(stml, entry)))
+    {
+        if (fbSubTemplateMultiListEntryGetTemplateID(entry) != YAF_DNS_TID)
+            continue;
+
+        YAF_DNS_RECORD *dns = NULL;
+        while ((dns = fbSubTemplateMultiListEntryNextDataPtr(entry, dns)))
+        {
+            YAF_DNS_QR_RECORD *qr = NULL;
+            while ((qr = fbSubTemplateListGetNextPtr(&dns->dnsQRList, qr)))
+            {
+                if (AppendDnsRecord(gnat->dns_appender, gnat->observation, flow, qr, answer) < 0)
+                {
+                    g_string_free(answer, TRUE);
+                    return -1;
+                }
+                ++count;
+            }
+        }
+    }
+
+    /* release scratch buffers */
+    g_string_free(answer, TRUE);
+
+    gnat->dns_records += count;
+    return count;
+}
+
+//
+// Issuer or subject name as "C=US, O=Example, CN=example.com", in
+// certificate order; attributes other than these are left out
+//
+static gboolean
+FormatName(const fbSubTemplateList_t *name, GString *text)
+{
+    g_string_truncate(text, 0);
+    const YAF_SSL_OBJECT_RECORD *object = NULL;
+    while ((object = fbSubTemplateListGetNextPtr(name, object)))
+    {
+        const char *label = NULL;
+        switch (object->sslObjectID)
+        {
+        case 3:
+            label = "CN";
+            break;
+        case 6:
+            label = "C";
+            break;
+        case 7:
+            label = "L";
+            break;
+        case 8:
+            label = "ST";
+            break;
+        case 10:
+            label = "O";
+            break;
+        case 11:
+            label = "OU";
+            break;
+        default:
+            continue;
+        }
+        g_string_append_printf(text, "%s%s=%.*s", (text->len ? ", " : ""), label,
+                               (int)object->sslObjectValue.len, (const char *)object->sslObjectValue.buf);
+    }
+    return text->len > 0;
+}
+
+//
+// ASN.1 UTCTime (YYMMDDHHMMSSZ) or GeneralizedTime (YYYYMMDDHHMMSSZ)
+//
+static gboolean
+ParseCertTime(const fbVarfield_t *field, duckdb_timestamp *timestamp)
+{
+    size_t len = field->len;
+    if ((len != 13 && len != 15) || field->buf[len - 1] != 'Z')
+        return FALSE;
+
+    int value[7] = {0};
+    for (size_t i = 0; i < len - 1; ++i)
+    {
+        if (!isdigit(field->buf[i]))
+            return FALSE;
+    }
+    const uint8_t *digits = field->buf;
+    int year_digits = (len == 13 ? 2 : 4);
+    for (int i = 0; i < year_digits; ++i)
+        value[0] = value[0] * 10 + (digits[i] - '0');
+    digits += year_digits;
+    for (int i = 1; i < 6; ++i, digits += 2)
+        value[i] = (digits[0] - '0') * 10 + (digits[1] - '0');
+    if (year_digits == 2)
+        value[0] += (value[0] < 50 ? 2000 : 1900);
+
+    struct tm tm;
+    memset(&tm, 0, sizeof(tm));
+    tm.tm_year = value[0] - 1900;
+    tm.tm_mon = value[1] - 1;
+    tm.tm_mday = value[2];
+    tm.tm_hour = value[3];
+    tm.tm_min = value[4];
+    tm.tm_sec = value[5];
+    timestamp->micros = (int64_t)timegm(&tm) * 1000000;
+    return TRUE;
+}
+
+//
+// sni, tlsissuer, tlssubject and tlsnotafter from the SSL/TLS record, with
+// the certificate fields of the first (server) certificate. YAF does not
+// export the ClientHello extensions, so ja3, ja3s, ja4 and ja4s are NULL.
+//
+void
+AppendTlsColumns(duckdb_appender appender, const YAF_FLOW_RECORD *flow)
+{
+    const YAF_SSL_RECORD *ssl = NULL;
+    fbSubTemplateMultiList_t *stml = (fbSubTemplateMultiList_t *)&flow->subTemplateMultiList;
+    fbSubTemplateMultiListEntry_t *entry = NULL;
+    while (ssl == NULL && (entry = fbSubTemplateMultiListGetNextEntry(stml, entry)))
+    {
+        if (fbSubTemplateMultiListEntryGetTemplateID(entry) == YAF_SSL_TID)
+            ssl = fbSubTemplateMultiListEntryNextDataPtr(entry, NULL);
+    }
+    const YAF_SSL_CERT_RECORD *cert = (ssl ? fbSubTemplateListGetNextPtr(&ssl->sslCertList, NULL) : NULL);
+
+    if (ssl)
+        AppendVarfield(appender, &ssl->sslServerName);
+    else
+        duckdb_append_null(appender);
+
+    GString *name = g_string_sized_new(256);
+    if (cert && FormatName(&cert->issuer, name))
+        duckdb_append_varchar_length(appender, name->str, name->len);
+    else
+        duckdb_append_null(appender);
+    if (cert && FormatName(&cert->subject, name))
+        duckdb_append_varchar_length(appender, name->str, name->len);
+    else
+        duckdb_append_null(appender);
+    g_string_free(name, TRUE);
+
+    duckdb_timestamp not_after;
+    if (cert && ParseCertTime(&cert->sslCertValidityNotAfter, &not_after))
+        duckdb_append_timestamp(appender, not_after);
+    else
+        duckdb_append_null(appender);
+
+    // ja3, ja3s, ja4, ja4s
+    for (int i = 0; i < 4; ++i)
+        duckdb_append_null(appender);
+}
+
+//
+// First value of the HTTP list holding the given CERT element, or NULL
+//
+static const fbVarfield_t *
+HttpValue(const YAF_HTTP_RECORD *http, uint16_t ie)
+{
+    for (int i = 0; i < YAF_HTTP_LISTS; ++i)
+    {
+        const fbInfoElement_t *element = fbBasicListGetInfoElement(&http->list[i]);
+        if (element == NULL || element->ent != CERT_PEN || element->num != ie)
+            continue;
+        const fbVarfield_t *value = fbBasicListGetIndexedDataPtr(&http->list[i], 0);
+        if (value && value->len)
+            return value;
+    }
+    return NULL;
+}
+
+//
+// httpmethod, httphost, httpuseragent and httpstatus from the HTTP record,
+// using the first request and response YAF captured. The method is the
+// first word of the request line.
+//
+void
+AppendHttpColumns(duckdb_appender appender, const YAF_FLOW_RECORD *flow)
+{
+    const YAF_HTTP_RECORD *http = NULL;
+    fbSubTemplateMultiList_t *stml = (fbSubTemplateMultiList_t *)&flow->subTemplateMultiList;
+    fbSubTemplateMultiListEntry_t *entry = NULL;
+    while (http == NULL && (entry = fbSubTemplateMultiListGetNextEntry(stml, entry)))
+    {
+        if (fbSubTemplateMultiListEntryGetTemplateID(entry) == YAF_HTTP_TID)
+            http = fbSubTemplateMultiListEntryNextDataPtr(entry, NULL);
+    }
+
+    const fbVarfield_t *value = (http ? HttpValue(http, HTTP_GET_IE) : NULL);
+    size_t len = 0;
+    while (value && len < value->len && isupper(value->buf[len]))
+        ++len;
+    if (len > 0)
+        duckdb_append_varchar_length(appender, (const char *)value->buf, len);
+    else
+        duckdb_append_null(appender);
+
+    value = (http ? HttpValue(http, HTTP_HOST_IE) : NULL);
+    if (value)
+        AppendVarfield(appender, value);
+    else
+        duckdb_append_null(appender);
+
+    value = (http ? HttpValue(http, HTTP_USER_AGENT_IE) : NULL);
+    if (value)
+        AppendVarfield(appender, value);
+    else
+        duckdb_append_null(appender);
+
+    // the status code, with or without the status line's version
+    value = (http ? HttpValue(http, HTTP_RESPONSE_IE) : NULL);
+    size_t start = 0;
+    if (value && value->len > 5 && memcmp(value->buf, "HTTP/", 5) == 0)
+    {
+        while (start < value->len && value->buf[start] != ' ')
+            ++start;
+        ++start;
+    }
+    if (value && start + 3 <= value->len &&
+        isdigit(value->buf[start]) && isdigit(value->buf[start + 1]) && isdigit(value->buf[start + 2]))
+    {
+        uint16_t status = (value->buf[start] - '0') * 100 + (value->buf[start + 1] - '0') * 10 + (value->buf[start + 2] - '0');
+        duckdb_append_uint16(appender, status);
+    }
+    else
+        duckdb_append_null(appender);
+}
+
+gboolean
+CloseDnsSink(GNAT_CONTEXT *gnat)
+{
+    char file_name[PATH_MAX + 1];
+    char tmp_file[(PATH_MAX * 3) + 1];
+    char parquet_file[(PATH_MAX * 4) + 1];
+    char parquet_export_command[(PATH_MAX * 5) + 1];
+
+    if (gnat->dns_appender == NULL)
+        return TRUE;
+
+    duckdb_appender_flush(gnat->dns_appender);
+    duckdb_appender_destroy(&gnat->dns_appender);
+    gnat->dns_appender = NULL;
+
+    if (gnat->dns_records == 0)
+        return TRUE;
+
+    snprintf(file_name, sizeof(file_name) - 1, "dns.%s.%u", gnat->observation, gnat->outtime);
+    snprintf(tmp_file, sizeof(tmp_file) - 1, "%s/.%s", gnat->dns_output_dir, file_name);
+    snprintf(parquet_file, sizeof(parquet_file) - 1, "%s/%s.parquet", gnat->dns_output_dir, file_name);
+    snprintf(parquet_export_command, sizeof(parquet_export_command) - 1, " COPY (SELECT * FROM dns ORDER BY stime) TO '%s' (%s, KV_METADATA {gnat_stream: '" DNS_STREAM "'});", tmp_file, parquet_get_copy_options());
+
+    duckdb_result db_result;
+    // write to parquet
+    if (duckdb_query(gnat->con, parquet_export_command, &db_result) == DuckDBError)
+    {
+        fprintf(stderr, "%s: failed to generating parquet file: \n%s\n", __FUNCTION__, duckdb_result_error(&db_result));
+        return FALSE;
+    }
+
+    if (rename(tmp_file, parquet_file) != 0)
+    {
+        fprintf(stderr, "%s: failed to rename file %s - %s \n", __FUNCTION__, tmp_file, strerror(errno));
+        return FALSE;
+    }
+    gnat->dns_records = 0;
+    return TRUE;
+}
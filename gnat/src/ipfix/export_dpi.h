@@ -0,0 +1,65 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+#define _GNU_SOURCE
+#pragma once
+
+#include <stdlib.h>
+#include <stdint.h>
+
+#include <fixbuf/public.h>
+#include <duckdb.h>
+
+#include "yaf_record.h"
+#include "io_context.h"
+
+/* template ids of the YAF DPI plugin (dpacketplugin) */
+#define YAF_DNS_TID 0xCE00
+#define YAF_DNS_QR_TID 0xCF00
+#define YAF_DNS_A_TID 0xCE01
+#define YAF_DNS_AAAA_TID 0xCE02
+#define YAF_DNS_CNAME_TID 0xCE03
+#define YAF_DNS_MX_TID 0xCE04
+#define YAF_DNS_NS_TID 0xCE05
+#define YAF_DNS_PTR_TID 0xCE06
+#define YAF_DNS_TXT_TID 0xCE07
+#define YAF_SSL_TID 0xCA0A
+#define YAF_SSL_CERT_TID 0xCA0B
+#define YAF_SSL_OBJECT_TID 0xCE14
+#define YAF_HTTP_TID 0xC600
+
+/* value of the gnat_stream parquet key of dns files */
+#define DNS_STREAM "dns"
+
+/* one row per question and resource record */
+#define DNS_SCHEMA                                                      \
+    "CREATE TABLE dns ("                                                \
+    "observ VARCHAR,stime TIMESTAMP,"                                   \
+    "saddr VARCHAR,daddr VARCHAR,sport USMALLINT,dport USMALLINT,"      \
+    "id USMALLINT,response BOOLEAN,section UTINYINT,"                   \
+    "qname VARCHAR,qtype USMALLINT,rcode UTINYINT,authoritative BOOLEAN," \
+    "ttl UINTEGER,answer VARCHAR"                                       \
+    ")"
+
+gboolean
+AddDpiTemplates(fbInfoModel_t *model, fbSession_t *session, GError **err);
+
+gboolean
+OpenDnsSink(GNAT_CONTEXT *gnat);
+
+int
+AppendDnsRecords(GNAT_CONTEXT *gnat, const YAF_FLOW_RECORD *flow);
+
+void
+AppendTlsColumns(duckdb_appender appender, const YAF_FLOW_RECORD *flow);
+
+void
+AppendHttpColumns(duckdb_appender appender, const YAF_FLOW_RECORD *flow);
+
+gboolean
+CloseDnsSink(GNAT_CONTEXT *gnat);
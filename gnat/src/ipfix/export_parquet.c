@@ -10,8 +10,22 @@
 #include "yaf_record.h"
 #include "import_libfixbuf.h"
 #include "export_parquet.h"
+#include "export_dpi.h"
 #include "io_context.h"
 
+static char parquet_copy_options[512] = "FORMAT 'parquet', CODEC 'snappy', ROW_GROUP_SIZE 100_000";
+
+void parquet_set_copy_options(const char *options)
+{
+    strncpy(parquet_copy_options, options, sizeof(parquet_copy_options) - 1);
+    parquet_copy_options[sizeof(parquet_copy_options) - 1] = '\0';
+}
+
+const char *parquet_get_copy_options(void)
+{
+    return parquet_copy_options;
+}
+
 static void
 PrintTCPFlags(
     GString *str,
@@ -197,12 +211,68 @@ static const char *ToLowerString(char *str)
     return ptr;
 }
 
+//
+// City name (lowercase, "unk" when unresolved) and location of a public
+// address; returns TRUE when latitude/longitude were found
+//
+static gboolean LookupCity(MMDB_s *city_mmdb,
+                           const char *address,
+                           char *city,
+                           size_t city_len,
+                           double *latitude,
+                           double *longitude)
+{
+    int gai_error, mmdb_error;
+    MMDB_entry_data_s entry_data;
+
+    strncpy(city, "unk", city_len - 1);
+    MMDB_lookup_result_s result = MMDB_lookup_string(city_mmdb, address, &gai_error, &mmdb_error);
+    if (gai_error)
+    {
+        fprintf(stderr, "%s: city getaddrinfo failed: %s", __FUNCTION__, gai_strerror(gai_error));
+        return FALSE;
+    }
+    if (mmdb_error)
+    {
+        fprintf(stderr, "%s: city geopip lookup failed: %s", __FUNCTION__, MMDB_strerror(mmdb_error));
+        return FALSE;
+    }
+    if (!result.found_entry)
+    {
+        return FALSE;
+    }
+
+    if (MMDB_get_value(&result.entry, &entry_data, "city", "names", "en", NULL) == MMDB_SUCCESS &&
+        entry_data.has_data && entry_data.type == MMDB_DATA_TYPE_UTF8_STRING)
+    {
+        int len = entry_data.data_size >= city_len ? (city_len - 1) : entry_data.data_size;
+        strncpy(city, entry_data.utf8_string, len);
+        city[len] = '\0';
+        ToLowerString(city);
+    }
+
+    if (MMDB_get_value(&result.entry, &entry_data, "location", "latitude", NULL) != MMDB_SUCCESS ||
+        !entry_data.has_data || entry_data.type != MMDB_DATA_TYPE_DOUBLE)
+    {
+        return FALSE;
+    }
+    *latitude = entry_data.double_value;
+    if (MMDB_get_value(&result.entry, &entry_data, "location", "longitude", NULL) != MMDB_SUCCESS ||
+        !entry_data.has_data || entry_data.type != MMDB_DATA_TYPE_DOUBLE)
+    {
+        return FALSE;
+    }
+    *longitude = entry_data.double_value;
+    return TRUE;
+}
+
 static int AppendIpfixRecord(duckdb_appender appender,
                              const char *observation,
                              struct ndpi_detection_module_struct *ndpi_ctx,
                              const YAF_FLOW_RECORD *flow,
                              MMDB_s *asn_mmdb,
-                             MMDB_s *country_mmdb)
+                             MMDB_s *country_mmdb,
+                             MMDB_s *city_mmdb)
 {
     char sabuf[64], dabuf[64];
 
@@ -569,6 +639,51 @@ static int AppendIpfixRecord(duckdb_appender appender,
     duckdb_append_varchar(appender, sasnorg);
     duckdb_append_varchar(appender, dasnorg);
 
+    char scity[CITY_LEN] = {"private"};
+    char dcity[CITY_LEN] = {"private"};
+    double slat = 0.0, slon = 0.0, dlat = 0.0, dlon = 0.0;
+    gboolean slocation = FALSE, dlocation = FALSE;
+    if (city_mmdb)
+    {
+        if (!sprivate_address)
+        {
+            slocation = LookupCity(city_mmdb, sabuf, scity, sizeof(scity), &slat, &slon);
+        }
+        if (!dprivate_address)
+        {
+            dlocation = LookupCity(city_mmdb, dabuf, dcity, sizeof(dcity), &dlat, &dlon);
+        }
+    }
+    duckdb_append_varchar(appender, scity);
+    duckdb_append_varchar(appender, dcity);
+    if (slocation)
+    {
+        duckdb_append_double(appender, slat);
+        duckdb_append_double(appender, slon);
+    }
+    else
+    {
+        duckdb_append_null(appender);
+        duckdb_append_null(appender);
+    }
+    if (dlocation)
+    {
+        duckdb_append_double(appender, dlat);
+        duckdb_append_double(appender, dlon);
+    }
+    else
+    {
+        duckdb_append_null(appender);
+        duckdb_append_null(appender);
+    }
+
+    AppendTlsColumns(appender, flow);
+    AppendHttpColumns(appender, flow);
+    duckdb_append_null(appender); // dga_score, set by gnat_dga
+    duckdb_append_null(appender); // orient, set at import with --networks
+    duckdb_append_null(appender); // site, set by gnat_site
+    duckdb_append_null(appender); // tenant, set at import with --tenant or --tenants
+
     char model_name[4] = {"na"};
     float score = 0.0;
     duckdb_append_varchar(appender, model_name); // model name
@@ -585,7 +700,8 @@ static int WriteIpfixRecord(const char *observation,
                             struct ndpi_detection_module_struct *ndpi_ctx,
                             const YAF_FLOW_RECORD *flow,
                             MMDB_s *asn_mmdb,
-                            MMDB_s *country_mmdb)
+                            MMDB_s *country_mmdb,
+                            MMDB_s *city_mmdb)
 {
     if ((flow->protocolIdentifier == 0) && (flow->destinationIPv4Address == 0))
     {
@@ -593,7 +709,7 @@ static int WriteIpfixRecord(const char *observation,
         return 0;
     }
 
-    if (AppendIpfixRecord(appender, observation, ndpi_ctx, flow, asn_mmdb, country_mmdb) < 0)
+    if (AppendIpfixRecord(appender, observation, ndpi_ctx, flow, asn_mmdb, country_mmdb, city_mmdb) < 0)
     {
         fprintf(stderr, "%s: AppendIpfixRecord error\n", __FUNCTION__);
         return -1;
@@ -626,6 +742,9 @@ OpenFileSink(
         if (!gnat)
             break;
 
+        // long-running collectors pick up updated MaxMind files per output file
+        RefreshGeoDatabases(gnat);
+
         //
         //  initialize duckdb
         //
@@ -670,6 +789,9 @@ OpenFileSink(
             break;
         }
 
+        if (!OpenDnsSink(gnat))
+            break;
+
         gnat->outtime = time(NULL);
         ++gnat->ipfix_files;
 
@@ -717,7 +839,7 @@ RotateFileSink(MIOSource *source,
         snprintf(file_name, sizeof(file_name) - 1, ".%s.%u", gnat->observation, gnat->outtime);
         snprintf(tmp_file, sizeof(tmp_file) - 1, "%s/%s", gnat->output_dir, file_name);
         snprintf(parquet_file, sizeof(parquet_file) - 1, "%s/gnat%s.parquet", gnat->output_dir, file_name);
-        snprintf(parquet_export_command, sizeof(parquet_export_command) - 1, " COPY (SELECT * FROM flow) TO '%s' (FORMAT 'parquet', CODEC 'snappy', ROW_GROUP_SIZE 100_000);", tmp_file);
+        snprintf(parquet_export_command, sizeof(parquet_export_command) - 1, " COPY (SELECT * FROM flow) TO '%s' (%s, KV_METADATA {gnat_schema_version: '" FLOW_SCHEMA_VERSION "'});", tmp_file, parquet_get_copy_options());
 
         fprintf(stderr, "%s: output [%s]\n", __FUNCTION__, parquet_file);
 
@@ -777,7 +899,7 @@ CloseFileSink(
             snprintf(file_name, sizeof(file_name) - 1, ".%s.%u", gnat->observation, gnat->outtime);
             snprintf(tmp_file, sizeof(tmp_file) - 1, "%s/%s", gnat->output_dir, file_name);
             snprintf(parquet_file, sizeof(parquet_file) - 1, "%s/gnat%s.parquet", gnat->output_dir, file_name);
-            snprintf(parquet_export_command, sizeof(parquet_export_command) - 1, " COPY (SELECT * FROM flow) TO '%s' (FORMAT 'parquet', CODEC 'snappy', ROW_GROUP_SIZE 100_000);", tmp_file);
+            snprintf(parquet_export_command, sizeof(parquet_export_command) - 1, " COPY (SELECT * FROM flow) TO '%s' (%s, KV_METADATA {gnat_schema_version: '" FLOW_SCHEMA_VERSION "'});", tmp_file, parquet_get_copy_options());
         }
         else
         {
@@ -802,6 +924,9 @@ CloseFileSink(
             }
         }
 
+        if (!CloseDnsSink(gnat))
+            break;
+
         if (gnat->con)
             duckdb_disconnect(&gnat->con);
         if (gnat->db)
@@ -840,7 +965,8 @@ ReaderToFileSink(
                                       gnat->ndpi_ctx,
                                       &ipfix_record,
                                       gnat->asn_mmdb_ptr,
-                                      gnat->country_mmdb_ptr);
+                                      gnat->country_mmdb_ptr,
+                                      gnat->city_mmdb_ptr);
         if (status < 0)
         {
             fprintf(stderr, "%s: error\n", __FUNCTION__);
@@ -857,6 +983,15 @@ ReaderToFileSink(
         {
             ++gnat->ipfix_flows_skipped;
         }
+        if (status > 0 && AppendDnsRecords(gnat, &ipfix_record) < 0)
+        {
+            fprintf(stderr, "%s: error\n", __FUNCTION__);
+            sink->active = FALSE;
+            *flags |= (MIO_F_CTL_SINKCLOSE | MIO_F_CTL_ERROR);
+            return FALSE;
+        }
+        // release the DPI lists decoded into the record
+        fBufListFree(gnat->template, (uint8_t *)&ipfix_record);
         memset(&ipfix_record, 0, yaf_rec_len);
     }
 
@@ -933,7 +1068,8 @@ SocketToFileSink(
                              gnat->ndpi_ctx,
                              &ipfix_record,
                              &gnat->asn_mmdb,
-                             &gnat->country_mmdb) < 0)
+                             &gnat->country_mmdb,
+                             gnat->city_mmdb_ptr) < 0)
 
         {
             gnat->ipfix_flows = -1;
@@ -942,6 +1078,14 @@ SocketToFileSink(
             return FALSE;
         }
         ++gnat->ipfix_flows;
+        if (AppendDnsRecords(gnat, &ipfix_record) < 0)
+        {
+            sink->active = FALSE;
+            *flags |= MIO_F_CTL_ERROR;
+            return FALSE;
+        }
+        // release the DPI lists decoded into the record
+        fBufListFree(gnat->template, (uint8_t *)&ipfix_record);
         memset(&ipfix_record, 0, yaf_rec_len);
     }
     //}
@@ -984,3 +1128,124 @@ SocketToFileSink(
         }
     }
 }
+
+//
+// Open a GeoLite2 database for geo_lookup_*(); NULL when it can't be read
+//
+MMDB_s *geo_open(const char *file)
+{
+    MMDB_s *mmdb = calloc(1, sizeof(MMDB_s));
+    if (mmdb == NULL)
+    {
+        return NULL;
+    }
+    if (MMDB_SUCCESS != MMDB_open(file, MMDB_MODE_MMAP, mmdb))
+    {
+        fprintf(stderr, "%s: failed to load geolite - %s\n", __FUNCTION__, file);
+        free(mmdb);
+        return NULL;
+    }
+    return mmdb;
+}
+
+void geo_close(MMDB_s *mmdb)
+{
+    if (mmdb)
+    {
+        MMDB_close(mmdb);
+        free(mmdb);
+    }
+}
+
+static gboolean GeoLookup(MMDB_s *mmdb, const char *address, const char *label, MMDB_lookup_result_s *result)
+{
+    int gai_error, mmdb_error;
+
+    *result = MMDB_lookup_string(mmdb, address, &gai_error, &mmdb_error);
+    if (gai_error)
+    {
+        fprintf(stderr, "%s: %s getaddrinfo failed: %s", __FUNCTION__, label, gai_strerror(gai_error));
+        return FALSE;
+    }
+    if (mmdb_error)
+    {
+        fprintf(stderr, "%s: %s geopip lookup failed: %s", __FUNCTION__, label, MMDB_strerror(mmdb_error));
+        return FALSE;
+    }
+    return result->found_entry;
+}
+
+//
+// Copy a UTF-8 entry of result at path into value, lowercased; FALSE when
+// there is none
+//
+static gboolean GeoString(MMDB_lookup_result_s *result, char *value, size_t value_len, const char *const *path)
+{
+    MMDB_entry_data_s entry_data;
+
+    if (MMDB_aget_value(&result->entry, &entry_data, path) != MMDB_SUCCESS ||
+        !entry_data.has_data || entry_data.type != MMDB_DATA_TYPE_UTF8_STRING || entry_data.data_size == 0)
+    {
+        return FALSE;
+    }
+    size_t len = entry_data.data_size >= value_len ? (value_len - 1) : entry_data.data_size;
+    strncpy(value, entry_data.utf8_string, len);
+    value[len] = '\0';
+    ToLowerString(value);
+    return TRUE;
+}
+
+int geo_lookup_country(MMDB_s *country_mmdb, const char *address, char *country, size_t country_len)
+{
+    static const char *const iso_code[] = {"country", "iso_code", NULL};
+    MMDB_lookup_result_s result;
+
+    snprintf(country, country_len, "%s", IsPrivateAddress(address) ? "private" : "unk");
+    if (IsPrivateAddress(address) || !GeoLookup(country_mmdb, address, "country", &result))
+    {
+        return 0;
+    }
+    GeoString(&result, country, country_len, iso_code);
+    return 0;
+}
+
+int geo_lookup_asn(MMDB_s *asn_mmdb, const char *address, uint32_t *asn, char *asnorg, size_t asnorg_len)
+{
+    static const char *const number[] = {"autonomous_system_number", NULL};
+    static const char *const organization[] = {"autonomous_system_organization", NULL};
+    MMDB_lookup_result_s result;
+    MMDB_entry_data_s entry_data;
+
+    *asn = 0;
+    snprintf(asnorg, asnorg_len, "%s", IsPrivateAddress(address) ? "private" : "unk");
+    if (IsPrivateAddress(address) || !GeoLookup(asn_mmdb, address, "asn", &result))
+    {
+        return 0;
+    }
+    if (MMDB_aget_value(&result.entry, &entry_data, number) == MMDB_SUCCESS &&
+        entry_data.has_data && entry_data.type == MMDB_DATA_TYPE_UINT32)
+    {
+        *asn = entry_data.uint32;
+    }
+    GeoString(&result, asnorg, asnorg_len, organization);
+    return 0;
+}
+
+//
+// Returns 1 when the latitude and longitude were found
+//
+int geo_lookup_city(MMDB_s *city_mmdb,
+                    const char *address,
+                    char *city,
+                    size_t city_len,
+                    double *latitude,
+                    double *longitude)
+{
+    if (IsPrivateAddress(address))
+    {
+        snprintf(city, city_len, "%s", "private");
+        return 0;
+    }
+    memset(city, 0, city_len);
+    return LookupCity(city_mmdb, address, city, city_len, latitude, longitude) ? 1 : 0;
+}
@@ -27,6 +27,10 @@
 #include <maxminddb.h>
 
 #define ASNORG_LEN 32
+#define CITY_LEN 64
+
+/* bump with FLOW_SCHEMA changes, mirror them in FLOW_TABLE and add a migration in core/flow_schema.rs */
+#define FLOW_SCHEMA_VERSION "8"
 
 
 #define FLOW_SCHEMA                                                                        \
@@ -53,6 +57,15 @@
     "scountry VARCHAR,dcountry VARCHAR,"                                                   \
     "sasn UINTEGER,dasn UINTEGER,"                                                         \
     "sasnorg VARCHAR,dasnorg VARCHAR,"                                                     \
+    "scity VARCHAR,dcity VARCHAR,"                                                         \
+    "slat DOUBLE,slon DOUBLE,dlat DOUBLE,dlon DOUBLE,"                                     \
+    "sni VARCHAR,tlsissuer VARCHAR,tlssubject VARCHAR,tlsnotafter TIMESTAMP,"             \
+    "ja3 VARCHAR,ja3s VARCHAR,ja4 VARCHAR,ja4s VARCHAR,"                                   \
+    "httpmethod VARCHAR,httphost VARCHAR,httpuseragent VARCHAR,httpstatus USMALLINT,"      \
+    "dga_score FLOAT,"                                                                     \
+    "orient VARCHAR,"                                                                      \
+    "site VARCHAR,"                                                                        \
+    "tenant VARCHAR,"                                                                      \
     "model VARCHAR,score FLOAT"                                                            \
     ")"
 
@@ -88,3 +101,39 @@ SocketToFileSink(
     uint32_t *flags,
     GError **err);
 
+
+/*
+ * COPY options (FORMAT, CODEC, ROW_GROUP_SIZE ...) of the flow and DNS
+ * parquet outputs, set from core/parquet.rs
+ */
+void parquet_set_copy_options(const char *options);
+
+const char *parquet_get_copy_options(void);
+
+
+/*
+ * Single-address GeoLite2 lookups for the enrichers of core/enrich.rs;
+ * values follow the import: lowercase, "private" for private addresses,
+ * "unk" when unresolved
+ */
+MMDB_s *geo_open(const char *file);
+
+void geo_close(MMDB_s *mmdb);
+
+int geo_lookup_country(MMDB_s *country_mmdb,
+                       const char *address,
+                       char *country,
+                       size_t country_len);
+
+int geo_lookup_asn(MMDB_s *asn_mmdb,
+                   const char *address,
+                   uint32_t *asn,
+                   char *asnorg,
+                   size_t asnorg_len);
+
+int geo_lookup_city(MMDB_s *city_mmdb,
+                    const char *address,
+                    char *city,
+                    size_t city_len,
+                    double *latitude,
+                    double *longitude);
@@ -0,0 +1,115 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+
+// ASNORG_LEN and CITY_LEN of export_parquet.h
+const ASNORG_LEN: usize = 32;
+const CITY_LEN: usize = 64;
+const COUNTRY_LEN: usize = 32;
+
+// C functions wrappering GeoLite2 lookups of a single address
+
+extern "C" {
+    fn geo_open(file: *const c_char) -> *mut c_void;
+
+    fn geo_close(mmdb: *mut c_void);
+
+    fn geo_lookup_country(
+        country_mmdb: *mut c_void,
+        address: *const c_char,
+        country: *mut c_char,
+        country_len: usize,
+    ) -> i32;
+
+    fn geo_lookup_asn(
+        asn_mmdb: *mut c_void,
+        address: *const c_char,
+        asn: *mut u32,
+        asnorg: *mut c_char,
+        asnorg_len: usize,
+    ) -> i32;
+
+    fn geo_lookup_city(
+        city_mmdb: *mut c_void,
+        address: *const c_char,
+        city: *mut c_char,
+        city_len: usize,
+        latitude: *mut f64,
+        longitude: *mut f64,
+    ) -> i32;
+}
+
+pub struct GeoDatabase {
+    mmdb: *mut c_void,
+}
+
+fn text(buffer: &[c_char]) -> String {
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl GeoDatabase {
+    pub fn open(file: &String) -> Result<GeoDatabase, std::io::Error> {
+        let c_file = CString::new(file.as_str()).expect("converting to c_string");
+        let mmdb = unsafe { geo_open(c_file.as_ptr()) };
+        if mmdb.is_null() {
+            return Err(std::io::Error::other(format!("loading geolite {}", file)));
+        }
+        Ok(GeoDatabase { mmdb })
+    }
+
+    pub fn country(&self, address: &str) -> String {
+        let c_address = CString::new(address).unwrap_or_default();
+        let mut country = [0 as c_char; COUNTRY_LEN];
+        unsafe { geo_lookup_country(self.mmdb, c_address.as_ptr(), country.as_mut_ptr(), COUNTRY_LEN) };
+        text(&country)
+    }
+
+    pub fn asn(&self, address: &str) -> (u32, String) {
+        let c_address = CString::new(address).unwrap_or_default();
+        let mut asn = 0u32;
+        let mut asnorg = [0 as c_char; ASNORG_LEN];
+        unsafe {
+            geo_lookup_asn(self.mmdb, c_address.as_ptr(), &mut asn, asnorg.as_mut_ptr(), ASNORG_LEN)
+        };
+        (asn, text(&asnorg))
+    }
+
+    //
+    // City, and the location when it was found
+    //
+    pub fn city(&self, address: &str) -> (String, Option<(f64, f64)>) {
+        let c_address = CString::new(address).unwrap_or_default();
+        let mut city = [0 as c_char; CITY_LEN];
+        let (mut latitude, mut longitude) = (0.0, 0.0);
+        let found = unsafe {
+            geo_lookup_city(
+                self.mmdb,
+                c_address.as_ptr(),
+                city.as_mut_ptr(),
+                CITY_LEN,
+                &mut latitude,
+                &mut longitude,
+            )
+        };
+        (text(&city), (found == 1).then_some((latitude, longitude)))
+    }
+}
+
+impl Drop for GeoDatabase {
+    fn drop(&mut self) {
+        unsafe { geo_close(self.mmdb) };
+    }
+}
+
+// lookups only read the memory-mapped database
+unsafe impl Send for GeoDatabase {}
+unsafe impl Sync for GeoDatabase {}
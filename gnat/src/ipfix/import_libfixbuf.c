@@ -14,12 +14,14 @@
 
 #include <stdlib.h>
 #include <stdint.h>
+#include <sys/stat.h>
 
 #include "yaf_record.h"
 #include "yaf_template.h"
 
 #include "import_libfixbuf.h"
 #include "export_parquet.h"
+#include "export_dpi.h"
 #include "io_context.h"
 
 #define GLIB_ERROR_RETURN(e)                         \
@@ -36,7 +38,102 @@ static int processYafStatsRecord(const FILE *output_fp, const YAF_STATS_RECORD *
 }
 #endif
 
-static fbConnSpec_t g_collector_spec = FB_CONNSPEC_INIT;
+// unset options arrive from Rust as empty strings; libfixbuf expects NULL
+static char *
+optional_strdup(const char *value)
+{
+    return (value != NULL && strlen(value) > 0) ? strdup(value) : NULL;
+}
+
+//
+// MaxMind databases (optional; an empty file name leaves the lookup disabled)
+//
+static gboolean
+OpenGeoDatabase(const char *file, const char *label, MMDB_s *mmdb, MMDB_s **mmdb_ptr, time_t *mtime)
+{
+    struct stat st;
+
+    memset(mmdb, 0, sizeof(*mmdb));
+    *mmdb_ptr = NULL;
+    if (file == NULL || strlen(file) == 0)
+    {
+        return TRUE;
+    }
+    if (MMDB_SUCCESS != MMDB_open(file, MMDB_MODE_MMAP, mmdb))
+    {
+        fprintf(stderr, "%s: failed to load geolite - %s: %s\n", __FUNCTION__, label, file);
+        return FALSE;
+    }
+    *mmdb_ptr = mmdb;
+    *mtime = (stat(file, &st) == 0) ? st.st_mtime : 0;
+    return TRUE;
+}
+
+gboolean
+OpenGeoDatabases(GNAT_CONTEXT *gnat)
+{
+    gnat->geo_checked = time(NULL);
+    return OpenGeoDatabase(gnat->asn_file, "asn", &gnat->asn_mmdb, &gnat->asn_mmdb_ptr, &gnat->asn_mtime) &&
+           OpenGeoDatabase(gnat->country_file, "country", &gnat->country_mmdb, &gnat->country_mmdb_ptr, &gnat->country_mtime) &&
+           OpenGeoDatabase(gnat->city_file, "city", &gnat->city_mmdb, &gnat->city_mmdb_ptr, &gnat->city_mtime);
+}
+
+void CloseGeoDatabases(GNAT_CONTEXT *gnat)
+{
+    if (gnat->asn_mmdb_ptr)
+        MMDB_close(&gnat->asn_mmdb);
+    gnat->asn_mmdb_ptr = NULL;
+
+    if (gnat->country_mmdb_ptr)
+        MMDB_close(&gnat->country_mmdb);
+    gnat->country_mmdb_ptr = NULL;
+
+    if (gnat->city_mmdb_ptr)
+        MMDB_close(&gnat->city_mmdb);
+    gnat->city_mmdb_ptr = NULL;
+}
+
+//
+// Swap in a database whose file changed; the old reader stays in use if
+// the new file can't be opened (e.g. a download still in progress)
+//
+static void
+RefreshGeoDatabase(const char *file, const char *label, MMDB_s *mmdb, MMDB_s **mmdb_ptr, time_t *mtime)
+{
+    struct stat st;
+    MMDB_s fresh;
+
+    if (file == NULL || strlen(file) == 0 || stat(file, &st) != 0 || st.st_mtime == *mtime)
+    {
+        return;
+    }
+    if (MMDB_SUCCESS != MMDB_open(file, MMDB_MODE_MMAP, &fresh))
+    {
+        fprintf(stderr, "%s: failed to reload geolite - %s: %s\n", __FUNCTION__, label, file);
+        return;
+    }
+    if (*mmdb_ptr)
+    {
+        MMDB_close(mmdb);
+    }
+    *mmdb = fresh;
+    *mmdb_ptr = mmdb;
+    *mtime = st.st_mtime;
+    fprintf(stdout, "%s: reloaded geolite - %s: %s\n", __FUNCTION__, label, file);
+}
+
+void RefreshGeoDatabases(GNAT_CONTEXT *gnat)
+{
+    time_t now = time(NULL);
+    if (gnat->geo_refresh_interval == 0 || now < gnat->geo_checked + gnat->geo_refresh_interval)
+    {
+        return;
+    }
+    gnat->geo_checked = now;
+    RefreshGeoDatabase(gnat->asn_file, "asn", &gnat->asn_mmdb, &gnat->asn_mmdb_ptr, &gnat->asn_mtime);
+    RefreshGeoDatabase(gnat->country_file, "country", &gnat->country_mmdb, &gnat->country_mmdb_ptr, &gnat->country_mtime);
+    RefreshGeoDatabase(gnat->city_file, "city", &gnat->city_mmdb, &gnat->city_mmdb_ptr, &gnat->city_mtime);
+}
 
 static gboolean
 ycNewConnection(
@@ -83,6 +180,10 @@ ycOpenListener(
         if (!fbSessionAddTemplate(gnat->session, TRUE, YAF_FLOW_FULL_TID, gnat->template, NULL, err))
             break;
 
+        // decode the DPI records exported with the flows
+        if (!AddDpiTemplates(gnat->model, gnat->session, err))
+            break;
+
         gnat->input_buf = fBufAllocForCollection(gnat->session, gnat->collector);
         if (gnat->input_buf == NULL)
             break;
@@ -137,32 +238,9 @@ ycOpenListener(
         ndpi_finalize_initialization(gnat->ndpi_ctx);
 
         // GeoIP stuff
-
-        //
-        // maxmind ASN
-        //
-        memset(&gnat->asn_mmdb, 0, sizeof(gnat->asn_mmdb));
-        if (strlen(gnat->asn_file))
+        if (!OpenGeoDatabases(gnat))
         {
-            if (MMDB_SUCCESS != MMDB_open(gnat->asn_file, MMDB_MODE_MMAP, &gnat->asn_mmdb))
-            {
-                fprintf(stderr, "%s: failed to load geolite - asn: %s\n", __FUNCTION__, gnat->asn_file);
-                break;
-            }
-            gnat->asn_mmdb_ptr = &gnat->asn_mmdb;
-        }
-        //
-        // maxmind Country
-        //
-        memset(&gnat->country_mmdb, 0, sizeof(gnat->country_mmdb));
-        if (strlen(gnat->country_file))
-        {
-            if (MMDB_SUCCESS != MMDB_open(gnat->country_file, MMDB_MODE_MMAP, &gnat->country_mmdb))
-            {
-                fprintf(stderr, "%s: failed to load geolite - country: %s\n", __FUNCTION__, gnat->country_file);
-                break;
-            }
-            gnat->country_mmdb_ptr = &gnat->country_mmdb;
+            break;
         }
         return TRUE;
     } while (0);
@@ -180,11 +258,7 @@ ycCloseListener(
     GNAT_CONTEXT *gnat = (GNAT_CONTEXT *)ctx;
     if (gnat)
     {
-        if (gnat->asn_mmdb_ptr)
-            MMDB_close(&gnat->asn_mmdb);
-
-        if (gnat->country_mmdb_ptr)
-            MMDB_close(&gnat->country_mmdb);
+        CloseGeoDatabases(gnat);
 
         if (gnat->ndpi_ctx)
             ndpi_exit_detection_module(gnat->ndpi_ctx);
@@ -239,6 +313,10 @@ ycOpenReader(
         if (!fbSessionAddTemplate(gnat->session, TRUE, YAF_FLOW_FULL_TID, gnat->template, NULL, err))
             break;
 
+        // decode the DPI records exported with the flows
+        if (!AddDpiTemplates(gnat->model, gnat->session, err))
+            break;
+
         if (strlen(gnat->input_file) == 0)
         {
             fprintf(stderr, "%s: missing input file specifier\n", __FUNCTION__);
@@ -299,32 +377,9 @@ ycOpenReader(
         ndpi_finalize_initialization(gnat->ndpi_ctx);
 
         // GeoIP stuff
-
-        //
-        // maxmind ASN
-        //
-        memset(&gnat->asn_mmdb, 0, sizeof(gnat->asn_mmdb));
-        if (strlen(gnat->asn_file))
+        if (!OpenGeoDatabases(gnat))
         {
-            if (MMDB_SUCCESS != MMDB_open(gnat->asn_file, MMDB_MODE_MMAP, &gnat->asn_mmdb))
-            {
-                fprintf(stderr, "%s: failed to load geolite - asn: %s\n", __FUNCTION__, gnat->asn_file);
-                break;
-            }
-            gnat->asn_mmdb_ptr = &gnat->asn_mmdb;
-        }
-        //
-        // maxmind Country
-        //
-        memset(&gnat->country_mmdb, 0, sizeof(gnat->country_mmdb));
-        if (strlen(gnat->country_file))
-        {
-            if (MMDB_SUCCESS != MMDB_open(gnat->country_file, MMDB_MODE_MMAP, &gnat->country_mmdb))
-            {
-                fprintf(stderr, "%s: failed to load geolite - country: %s\n", __FUNCTION__, gnat->country_file);
-                break;
-            }
-            gnat->country_mmdb_ptr = &gnat->country_mmdb;
+            break;
         }
         return TRUE;
     } while (0);
@@ -342,11 +397,7 @@ ycCloseReader(
     GNAT_CONTEXT *gnat = (GNAT_CONTEXT *)ctx;
     if (gnat)
     {
-        if (gnat->asn_mmdb_ptr)
-            MMDB_close(&gnat->asn_mmdb);
-
-        if (gnat->country_mmdb_ptr)
-            MMDB_close(&gnat->country_mmdb);
+        CloseGeoDatabases(gnat);
 
         if (gnat->ndpi_ctx)
             ndpi_exit_detection_module(gnat->ndpi_ctx);
@@ -373,8 +424,10 @@ int libfixbuf_file_import(
     const char *observation,
     const char *input_file,
     const char *output_dir,
+    const char *dns_output_dir,
     const char *asn_file,
-    const char *country_file)
+    const char *country_file,
+    const char *city_file)
 {
     int rv = 0;
     GNAT_CONTEXT gnat;
@@ -402,8 +455,10 @@ int libfixbuf_file_import(
     gnat.outtime = 0;
     gnat.input_file = strdup(input_file);
     gnat.output_dir = strdup(output_dir);
+    gnat.dns_output_dir = optional_strdup(dns_output_dir);
     gnat.asn_file = strdup(asn_file);
     gnat.country_file = strdup(country_file);
+    gnat.city_file = strdup(city_file);
     gnat.observation = strdup(observation);
 
     /* set up an app driver */
@@ -439,10 +494,14 @@ int libfixbuf_file_import(
         free(gnat.input_file);
     if (gnat.output_dir)
         free(gnat.output_dir);
+    if (gnat.dns_output_dir)
+        free(gnat.dns_output_dir);
     if (gnat.asn_file)
         free(gnat.asn_file);
     if (gnat.country_file)
         free(gnat.country_file);
+    if (gnat.city_file)
+        free(gnat.city_file);
     if (gnat.observation)
         free(gnat.observation);
 
@@ -465,8 +524,11 @@ int libfixbuf_socket_import(
     int rotate_interval,
     int verbose,
     const char *output_dir,
+    const char *dns_output_dir,
     const char *asn_file,
-    const char *country_file)
+    const char *country_file,
+    const char *city_file,
+    int geo_refresh_interval)
 {
     int rv = 0;
     GNAT_CONTEXT gnat;
@@ -484,12 +546,12 @@ int libfixbuf_socket_import(
 
     gnat.connection_spec.host = strdup(host);
     gnat.connection_spec.svc = (port != NULL ? strdup(port) : strdup("4739"));
-    gnat.connection_spec.ssl_ca_file = strdup(ssl_ca_file);
-    gnat.connection_spec.ssl_cert_file = strdup(ssl_cert_file);
-    gnat.connection_spec.ssl_key_file = strdup(ssl_key_file);
-    gnat.connection_spec.ssl_key_pass = strdup(ssl_key_pass);
+    gnat.connection_spec.ssl_ca_file = optional_strdup(ssl_ca_file);
+    gnat.connection_spec.ssl_cert_file = optional_strdup(ssl_cert_file);
+    gnat.connection_spec.ssl_key_file = optional_strdup(ssl_key_file);
+    gnat.connection_spec.ssl_key_pass = optional_strdup(ssl_key_pass);
 
-    yac_tls = (ssl_cert_file != NULL ? TRUE : FALSE);
+    yac_tls = (gnat.connection_spec.ssl_cert_file != NULL ? TRUE : FALSE);
     if (strcmp(transport, "tcp") == 0)
     {
         if (yac_tls)
@@ -545,11 +607,14 @@ int libfixbuf_socket_import(
     gnat.input_buf_ready = FALSE;
     gnat.outtime = 0;
     gnat.output_dir = strdup(output_dir);
+    gnat.dns_output_dir = optional_strdup(dns_output_dir);
     gnat.asn_file = strdup(asn_file);
     gnat.country_file = strdup(country_file);
+    gnat.city_file = strdup(city_file);
     gnat.observation = strdup(observation);
     gnat.verbose = (verbose ? TRUE : FALSE);
     gnat.rotate_interval = (rotate_interval ? rotate_interval : 60);
+    gnat.geo_refresh_interval = (geo_refresh_interval > 0 ? geo_refresh_interval : 0);
 
     /* set up an app driver */
     adrv.app_open_source = ycOpenListener;
@@ -583,25 +648,29 @@ int libfixbuf_socket_import(
     }
 
     g_message("libfixbuf_socket_import: shutting down");
-    if (g_collector_spec.host)
-        free(g_collector_spec.host);
-    if (g_collector_spec.svc)
-        free(g_collector_spec.svc);
-    if (g_collector_spec.ssl_ca_file)
-        free(g_collector_spec.ssl_ca_file);
-    if (g_collector_spec.ssl_cert_file)
-        free(g_collector_spec.ssl_cert_file);
-    if (g_collector_spec.ssl_key_file)
-        free(g_collector_spec.ssl_key_file);
-    if (g_collector_spec.ssl_key_pass)
-        free(g_collector_spec.ssl_key_pass);
+    if (gnat.connection_spec.host)
+        free(gnat.connection_spec.host);
+    if (gnat.connection_spec.svc)
+        free(gnat.connection_spec.svc);
+    if (gnat.connection_spec.ssl_ca_file)
+        free(gnat.connection_spec.ssl_ca_file);
+    if (gnat.connection_spec.ssl_cert_file)
+        free(gnat.connection_spec.ssl_cert_file);
+    if (gnat.connection_spec.ssl_key_file)
+        free(gnat.connection_spec.ssl_key_file);
+    if (gnat.connection_spec.ssl_key_pass)
+        free(gnat.connection_spec.ssl_key_pass);
 
     if (gnat.output_dir)
         free(gnat.output_dir);
+    if (gnat.dns_output_dir)
+        free(gnat.dns_output_dir);
     if (gnat.asn_file)
         free(gnat.asn_file);
     if (gnat.country_file)
         free(gnat.country_file);
+    if (gnat.city_file)
+        free(gnat.city_file);
     if (gnat.observation)
         free(gnat.observation);
 
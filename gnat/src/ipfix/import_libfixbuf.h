@@ -26,8 +26,10 @@
 int ipfix_file_import(const char *observation,
                  const char *input_file,
                  const char *output_dir,
+                 const char *dns_output_dir,
                  const char *asn_file,
-                 const char *country_file);
+                 const char *country_file,
+                 const char *city_file);
 
 int ipfix_socket_import(
     const char *observation,
@@ -41,5 +43,8 @@ int ipfix_socket_import(
     int rotate_interval,
     int verbose,
     const char *output_dir,
+    const char *dns_output_dir,
     const char *asn_file,
-    const char *country_file);
+    const char *country_file,
+    const char *city_file,
+    int geo_refresh_interval);
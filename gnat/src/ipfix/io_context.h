@@ -31,14 +31,29 @@ typedef struct gnatContext_st
     duckdb_connection con;
     duckdb_result result;
     duckdb_appender appender;
+    duckdb_appender dns_appender;
+    uint64_t dns_records;
     struct ndpi_detection_module_struct *ndpi_ctx;
     MMDB_s asn_mmdb;
     MMDB_s *asn_mmdb_ptr;
     MMDB_s country_mmdb;
     MMDB_s *country_mmdb_ptr;
+    MMDB_s city_mmdb;
+    MMDB_s *city_mmdb_ptr;
+    time_t asn_mtime;
+    time_t country_mtime;
+    time_t city_mtime;
+    time_t geo_checked;
+    uint32_t geo_refresh_interval;
     char *input_file;
     char *observation;
     char *asn_file;
     char *country_file;
+    char *city_file;
     char *output_dir;
+    char *dns_output_dir;
 } GNAT_CONTEXT;
+
+gboolean OpenGeoDatabases(GNAT_CONTEXT *gnat);
+void RefreshGeoDatabases(GNAT_CONTEXT *gnat);
+void CloseGeoDatabases(GNAT_CONTEXT *gnat);
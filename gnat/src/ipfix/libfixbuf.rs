@@ -20,8 +20,10 @@ extern "C" {
         observation: *const c_char,
         input_file: *const c_char,
         output_file: *const c_char,
+        dns_output_file: *const c_char,
         asn_file: *const c_char,
         country_file: *const c_char,
+        city_file: *const c_char,
     ) -> i32;
 
     fn libfixbuf_socket_import(
@@ -36,8 +38,11 @@ extern "C" {
         rotate_interval: u32,
         verbose: u32,
         output_spec: *const c_char,
+        dns_output_spec: *const c_char,
         asn_file: *const c_char,
         country_file: *const c_char,
+        city_file: *const c_char,
+        geo_refresh_interval: u32,
     ) -> i32;
 }
 
@@ -46,21 +51,28 @@ pub fn unsafe_ipfix_file_import(
     observation: &String,
     input_file: &String,
     output_file: &String,
+    dns_output_file: &String,
     asn_file: &String,
     country_file: &String,
+    city_file: &String,
 ) -> i32 {
     let c_observation = CString::new(observation.as_str()).expect("converting to c_string");
     let c_input_file = CString::new(input_file.as_str()).expect("converting to c_string");
     let c_output_file = CString::new(output_file.as_str()).expect("converting to c_string");
+    let c_dns_output_file =
+        CString::new(dns_output_file.as_str()).expect("converting to c_string");
     let c_asn_file = CString::new(asn_file.as_str()).expect("converting to c_string");
     let c_country_file = CString::new(country_file.as_str()).expect("converting to c_string");
+    let c_city_file = CString::new(city_file.as_str()).expect("converting to c_string");
     unsafe {
         return libfixbuf_file_import(
             c_observation.as_c_str().as_ptr(),
             c_input_file.as_c_str().as_ptr(),
             c_output_file.as_c_str().as_ptr(),
+            c_dns_output_file.as_c_str().as_ptr(),
             c_asn_file.as_c_str().as_ptr(),
             c_country_file.as_c_str().as_ptr(),
+            c_city_file.as_c_str().as_ptr(),
         );
     };
 }
@@ -77,8 +89,11 @@ pub fn unsafe_ifpix_socket_import(
     rotate_interval: u32,
     verbose_mode: bool,
     output_spec: &String,
+    dns_output_spec: &String,
     asn_spec: &String,
     country_spec: &String,
+    city_spec: &String,
+    geo_refresh_interval: u32,
 ) -> i32 {
     let c_observation = CString::new(observation_tag.as_str()).expect("converting to c_string");
     let c_host_spec = CString::new(host_spec.as_str()).expect("converting to c_string");
@@ -89,8 +104,11 @@ pub fn unsafe_ifpix_socket_import(
     let c_ssl_key_file = CString::new(ssl_key_file.as_str()).expect("converting to c_string");
     let c_ssl_key_pass = CString::new(ssl_key_pass.as_str()).expect("converting to c_string");
     let c_output_spec = CString::new(output_spec.as_str()).expect("converting to c_string");
+    let c_dns_output_spec =
+        CString::new(dns_output_spec.as_str()).expect("converting to c_string");
     let c_asn_spec = CString::new(asn_spec.as_str()).expect("converting to c_string");
     let c_country_spec = CString::new(country_spec.as_str()).expect("converting to c_string");
+    let c_city_spec = CString::new(city_spec.as_str()).expect("converting to c_string");
 
     let mut verbose: u32 = 0;
     if verbose_mode {
@@ -110,8 +128,11 @@ pub fn unsafe_ifpix_socket_import(
             rotate_interval,
             verbose,
             c_output_spec.as_c_str().as_ptr(),
+            c_dns_output_spec.as_c_str().as_ptr(),
             c_asn_spec.as_c_str().as_ptr(),
             c_country_spec.as_c_str().as_ptr(),
+            c_city_spec.as_c_str().as_ptr(),
+            geo_refresh_interval,
         );
     };
 }
@@ -6,4 +6,6 @@
  * See license information in LICENSE.
  */
 
-pub mod libfixbuf;
\ No newline at end of file
+pub mod geo;
+pub mod libfixbuf;
+
@@ -127,3 +127,86 @@ typedef struct _YAF_FLOW_RECORD_ {
     fbSubTemplateMultiList_t    subTemplateMultiList;
 
 } YAF_FLOW_RECORD;
+
+/*
+ * DPI records of the YAF DNS plugin (dpacketplugin)
+ */
+typedef struct _YAF_DNS_RECORD_ {
+    fbSubTemplateList_t         dnsQRList;
+} YAF_DNS_RECORD;
+
+typedef struct _YAF_DNS_QR_RECORD_ {
+    fbSubTemplateList_t         dnsRRList;
+    fbVarfield_t                dnsQName;
+    uint32_t                    dnsTTL;
+    uint16_t                    dnsQRType;
+    uint8_t                     dnsQueryResponse;
+    uint8_t                     dnsAuthoritative;
+    uint8_t                     dnsNXDomain;
+    uint8_t                     dnsRRSection;
+    uint16_t                    dnsID;
+    uint8_t                     paddingOctets[4];
+} YAF_DNS_QR_RECORD;
+
+typedef struct _YAF_DNS_A_RECORD_ {
+    uint32_t                    ip;
+} YAF_DNS_A_RECORD;
+
+typedef struct _YAF_DNS_AAAA_RECORD_ {
+    uint8_t                     ip[16];
+} YAF_DNS_AAAA_RECORD;
+
+/* CNAME, NS, PTR and TXT */
+typedef struct _YAF_DNS_NAME_RECORD_ {
+    fbVarfield_t                name;
+} YAF_DNS_NAME_RECORD;
+
+typedef struct _YAF_DNS_MX_RECORD_ {
+    fbVarfield_t                exchange;
+    uint16_t                    preference;
+    uint8_t                     paddingOctets[6];
+} YAF_DNS_MX_RECORD;
+
+/*
+ * DPI records of the YAF SSL/TLS plugin (dpacketplugin)
+ */
+typedef struct _YAF_SSL_RECORD_ {
+    fbBasicList_t               sslCipherList;
+    uint32_t                    sslServerCipher;
+    uint8_t                     sslClientVersion;
+    uint8_t                     sslCompressionMethod;
+    uint16_t                    sslRecordVersion;
+    fbSubTemplateList_t         sslCertList;
+    fbVarfield_t                sslServerName;
+} YAF_SSL_RECORD;
+
+typedef struct _YAF_SSL_CERT_RECORD_ {
+    fbSubTemplateList_t         issuer;
+    fbSubTemplateList_t         subject;
+    fbSubTemplateList_t         extension;
+    fbVarfield_t                sslCertSignature;
+    fbVarfield_t                sslCertSerialNumber;
+    fbVarfield_t                sslCertValidityNotBefore;
+    fbVarfield_t                sslCertValidityNotAfter;
+    fbVarfield_t                sslPublicKeyAlgorithm;
+    uint16_t                    sslPublicKeyLength;
+    uint8_t                     sslCertVersion;
+    uint8_t                     paddingOctets[5];
+} YAF_SSL_CERT_RECORD;
+
+/* one attribute of an issuer or subject name; sslObjectID is the last arc of 2.5.4.x */
+typedef struct _YAF_SSL_OBJECT_RECORD_ {
+    fbVarfield_t                sslObjectValue;
+    uint8_t                     sslObjectID;
+    uint8_t                     paddingOctets[7];
+} YAF_SSL_OBJECT_RECORD;
+
+/*
+ * DPI record of the YAF HTTP plugin (dpacketplugin): one basicList of
+ * varfields per captured header, identified by the list's element
+ */
+#define YAF_HTTP_LISTS 20
+
+typedef struct _YAF_HTTP_RECORD_ {
+    fbBasicList_t               list[YAF_HTTP_LISTS];
+} YAF_HTTP_RECORD;
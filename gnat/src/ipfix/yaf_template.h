@@ -313,6 +313,9 @@ static fbInfoElementSpec_t g_yaf_flow_spec[] = {
     { "mplsLabelStackSection2",             3, YTF_MPLS },
     { "mplsLabelStackSection3",             3, YTF_MPLS },
 
+    /* DPI */
+    { "subTemplateMultiList",               FB_IE_VARLEN, 0 },
+
     FB_IESPEC_NULL
 };
 
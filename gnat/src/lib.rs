@@ -1,2 +1,4 @@
 pub mod core;
-pub mod ipfix;
\ No newline at end of file
+pub mod ipfix;
+pub mod model;
+pub mod stages;
@@ -0,0 +1,285 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// DGA (domain generation algorithm) scoring of domain names
+//
+// The registered label of a name (the one left of the public suffix, e.g.
+// "example" of www.example.co.uk) is scored from 0 to 1 as how unlike
+// ordinary words it reads, on three features:
+//
+//   bigram    mean negative log-likelihood of its character bigrams, under
+//             a model of the words in CORPUS (plus any trained names)
+//   entropy   Shannon entropy of its characters, in bits
+//   digits    share of digits
+//
+// combined by a logistic function. Labels shorter than MIN_LABEL score 0,
+// as there is too little to go on. Dictionary-word DGAs are out of reach
+// of a character model and score low.
+//
+
+use std::fs;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+const MIN_LABEL: usize = 6;
+
+// a-z, 0-9 and '-', with 0 marking the start and end of a label
+const SYMBOLS: usize = 38;
+
+// second-level labels that are part of a two-letter country suffix
+const SECOND_LEVEL: [&str; 7] = ["ac", "co", "com", "edu", "gov", "net", "org"];
+
+// logistic weights and offsets, fitted against the built-in corpus
+const BIGRAM_WEIGHT: f64 = 4.0;
+const BIGRAM_OFFSET: f64 = 3.4;
+const ENTROPY_WEIGHT: f64 = 1.0;
+const ENTROPY_OFFSET: f64 = 3.0;
+const DIGIT_WEIGHT: f64 = 2.0;
+
+const CORPUS: &str = "\
+    google facebook youtube amazon microsoft apple netflix twitter instagram linkedin \
+    wikipedia yahoo bing live office windows update cloud cloudflare akamai azure github \
+    gitlab stackoverflow reddit paypal ebay adobe oracle cisco mozilla firefox chrome \
+    android samsung spotify zoom slack dropbox icloud outlook hotmail gmail mail email \
+    login account accounts secure service services support help news weather sport \
+    sports shop store market online media video music photo image images static assets \
+    content cdn api apps app mobile web www server portal gateway proxy remote access \
+    vpn auth identity sign signin register download downloads upload files file docs \
+    document drive storage backup sync data analytics metrics telemetry tracking track \
+    ads advert advertising marketing click search find maps map travel hotel booking \
+    bank banking finance money pay payment card credit insurance health medical school \
+    university college library learn learning education student teacher research science \
+    tech technology software hardware network networks systems system security safe \
+    protect defense energy power solar green garden home house family kids games game \
+    play player stream streaming radio television channel movie movies theater book \
+    books blog forum community social chat message talk phone call voice text world \
+    global international national local city county state government public private \
+    company corporation group partners business enterprise solutions consulting digital \
+    internet domain hosting host site sites page pages link links about contact info \
+    information center central general standard report reports review reviews post press \
+    daily times journal herald tribune express today weekly magazine fashion style \
+    beauty food kitchen recipe coffee restaurant pizza burger tour tours flight flights \
+    airline air car cars auto motor parts repair rental real estate property homes land \
+    farm farming technologies fidelis galileo toolkit north south east west river lake \
+    mountain valley ocean island forest park street road avenue bridge tower castle \
+    village town capital first second third best great good new old big little small \
+    fast quick easy simple smart open free direct prime plus pro max one two three four \
+    five ten hundred thousand million alpha beta delta gamma omega star sun moon light \
+    dark blue red black white silver gold golden diamond crystal stone rock steel iron \
+    glass paper wood fire water earth wind storm thunder lightning rain snow winter \
+    summer spring autumn morning evening night day week month year time clock watch rest \
+    point line circle square shape form model design create build make made work works \
+    working worker job jobs career hire staff team people person user users client \
+    customer member members friend friends love life living care fitness running run \
+    walk bike cycle ride driver training course class lesson tutorial guide manual \
+    example sample test testing demo trial release version updates patch fix debug \
+    monitor status check management manager admin administrator control panel dashboard \
+    console config settings profile preferences notification notifications alert alerts \
+    event events calendar schedule meeting conference webinar summit expo show fair \
+    festival party wedding gift gifts deal deals sale sales discount coupon offer offers \
+    price prices cheap value quality premium official authentic original genuine trusted \
+    verified certified license legal law lawyer attorney court justice police rescue \
+    emergency hospital clinic doctor dental pharmacy drug grocery supermarket mall \
+    outlet wholesale retail supply supplies equipment tools machine machines industrial \
+    manufacturing factory engineering construction building architecture interior \
+    furniture decor lighting electric electronics computer computers laptop tablet \
+    device devices gadget camera cameras printer printing print ink wireless cellular \
+    telecom communications satellite broadband fiber cable";
+
+fn symbol(c: char) -> Option<usize> {
+    match c {
+        'a'..='z' => Some(c as usize - 'a' as usize + 1),
+        '0'..='9' => Some(c as usize - '0' as usize + 27),
+        '-' => Some(37),
+        _ => None,
+    }
+}
+
+//
+// The registered label of a DNS name, SNI or Host header; None for an
+// address or an empty name
+//
+pub fn registered_label(name: &str) -> Option<String> {
+    let name = name.trim().trim_end_matches('.').to_ascii_lowercase();
+    let host = match name.rsplit_once(':') {
+        Some((host, port))
+            if (!host.contains(':') || host.ends_with(']')) && port.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            host
+        }
+        _ => name.as_str(),
+    };
+    if host.parse::<IpAddr>().is_ok() || host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    let labels: Vec<&str> = host.split('.').filter(|l| !l.is_empty()).collect();
+    let label = match labels.len() {
+        0 => return None,
+        1 => labels[0],
+        n if n >= 3 && labels[n - 1].len() == 2 && SECOND_LEVEL.contains(&labels[n - 2]) => {
+            labels[n - 3]
+        }
+        n => labels[n - 2],
+    };
+    Some(label.to_string())
+}
+
+pub struct Model {
+    counts: Vec<[f64; SYMBOLS]>,
+    log_prob: Vec<[f64; SYMBOLS]>,
+}
+
+impl Model {
+    //
+    // The model of the built-in corpus
+    //
+    pub fn new() -> Model {
+        let mut model = Model {
+            counts: vec![[1.0; SYMBOLS]; SYMBOLS],
+            log_prob: vec![[0.0; SYMBOLS]; SYMBOLS],
+        };
+        for word in CORPUS.split_whitespace() {
+            model.count(word);
+        }
+        model.normalize();
+        model
+    }
+
+    //
+    // The built-in model, also trained on a list of benign names, one per
+    // line; "rank,name" lines (Tranco, Umbrella) are taken as the name
+    //
+    pub fn load(list_spec: &String) -> Result<Model, std::io::Error> {
+        let contents = fs::read_to_string(list_spec)?;
+        let mut model = Model::new();
+        for line in contents.lines() {
+            let name = line.rsplit(',').next().unwrap_or_default();
+            if let Some(label) = registered_label(name) {
+                model.count(&label);
+            }
+        }
+        model.normalize();
+        Ok(model)
+    }
+
+    fn count(&mut self, label: &str) {
+        let mut previous = 0;
+        for current in label.chars().filter_map(symbol) {
+            self.counts[previous][current] += 1.0;
+            previous = current;
+        }
+        self.counts[previous][0] += 1.0;
+    }
+
+    fn normalize(&mut self) {
+        for (counts, log_prob) in self.counts.iter().zip(self.log_prob.iter_mut()) {
+            let total: f64 = counts.iter().sum();
+            for (count, p) in counts.iter().zip(log_prob.iter_mut()) {
+                *p = (count / total).ln();
+            }
+        }
+    }
+
+    //
+    // Score of a name; None when it has no label to score
+    //
+    pub fn score(&self, name: &str) -> Option<f32> {
+        let label = registered_label(name)?;
+        let symbols: Vec<usize> = label.chars().filter_map(symbol).collect();
+        if symbols.len() < MIN_LABEL {
+            return Some(0.0);
+        }
+
+        let mut previous = 0;
+        let mut log_likelihood = 0.0;
+        for current in symbols.iter().copied().chain(std::iter::once(0)) {
+            log_likelihood += self.log_prob[previous][current];
+            previous = current;
+        }
+        let bigram = -log_likelihood / (symbols.len() + 1) as f64;
+
+        let length = symbols.len() as f64;
+        let mut frequency = [0usize; SYMBOLS];
+        for s in symbols.iter() {
+            frequency[*s] += 1;
+        }
+        let entropy: f64 = frequency
+            .iter()
+            .filter(|f| **f > 0)
+            .map(|f| {
+                let p = *f as f64 / length;
+                -p * p.log2()
+            })
+            .sum();
+        let digits = symbols.iter().filter(|s| (27..37).contains(*s)).count() as f64 / length;
+
+        let z = BIGRAM_WEIGHT * (bigram - BIGRAM_OFFSET)
+            + ENTROPY_WEIGHT * (entropy - ENTROPY_OFFSET)
+            + DIGIT_WEIGHT * digits;
+        Some((1.0 / (1.0 + (-z).exp())) as f32)
+    }
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Model::new()
+    }
+}
+
+//
+// The built-in model, built on first use
+//
+pub fn builtin() -> &'static Model {
+    static MODEL: OnceLock<Model> = OnceLock::new();
+    MODEL.get_or_init(Model::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_labels() {
+        assert_eq!(registered_label("www.example.com").as_deref(), Some("example"));
+        assert_eq!(registered_label("www.example.co.uk.").as_deref(), Some("example"));
+        assert_eq!(registered_label("Example.COM:8443").as_deref(), Some("example"));
+        assert_eq!(registered_label("localhost").as_deref(), Some("localhost"));
+        assert_eq!(registered_label("10.0.0.1"), None);
+        assert_eq!(registered_label("10.0.0.1:443"), None);
+        assert_eq!(registered_label("[fd00::1]:443"), None);
+        assert_eq!(registered_label("fd00::1"), None);
+        assert_eq!(registered_label(""), None);
+    }
+
+    #[test]
+    fn generated_names_score_higher() {
+        let model = Model::new();
+        let generated = ["xjw9k2qvzp7h.com", "qzkxvbnwrtpl.net", "a8f3k9x2m7q1.org"];
+        let ordinary = ["google.com", "weather.co.uk", "cloudflare.net", "facebook.com"];
+        for g in generated {
+            for o in ordinary {
+                assert!(model.score(g) > model.score(o), "{} <= {}", g, o);
+            }
+        }
+        // too short to score, and not a name
+        assert_eq!(model.score("xq9z.com"), Some(0.0));
+        assert_eq!(model.score("10.0.0.1"), None);
+    }
+
+    #[test]
+    fn trained_names_score_lower() {
+        let list_spec = std::env::temp_dir().join(format!("gnat-{}-dga-train.csv", std::process::id()));
+        let list: Vec<String> = (1..=200).map(|rank| format!("{},xjw9k2qvzp7h.com", rank)).collect();
+        fs::write(&list_spec, list.join("\n")).unwrap();
+        let list_spec = list_spec.to_string_lossy().to_string();
+        let trained = Model::load(&list_spec).unwrap();
+        let _ = fs::remove_file(&list_spec);
+        assert!(trained.score("xjw9k2qvzp7h.com") < Model::new().score("xjw9k2qvzp7h.com"));
+    }
+}
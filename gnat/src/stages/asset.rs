@@ -0,0 +1,171 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::asset::{asset, Inventory};
+use crate::core::coalesce::{self, CoalesceArgs};
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::scratch::{self, ScratchArgs};
+use crate::core::shutdown;
+use crate::core::spool::{self, SpoolArgs};
+use crate::core::validate::{self, ValidateArgs};
+use crate::core::watermark::{self, WatermarkArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// DuckDB file holding the asset inventory, created when missing
+    #[arg(long)]
+    inventory: String,
+
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    /// directory the trigger files are written to
+    #[arg(long)]
+    triggers: String,
+
+    #[arg(long)]
+    processed: Option<String>,
+
+    #[arg(long)]
+    polling: Option<bool>,
+
+    /// hours of flows taken in before triggers are raised
+    #[arg(long)]
+    learn: Option<u64>,
+
+    #[command(flatten)]
+    scratch: ScratchArgs,
+
+    #[command(flatten)]
+    spool: SpoolArgs,
+
+    #[command(flatten)]
+    coalesce: CoalesceArgs,
+
+    #[command(flatten)]
+    watermark: WatermarkArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_asset");
+    let inventory_spec = args.inventory.clone();
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let trigger_spec = args.triggers.clone();
+    let processed_spec = args.processed.unwrap_or(String::new()).clone();
+    let polling = args.polling.unwrap_or(false).clone();
+    let learn = args.learn.unwrap_or(24);
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !input_spec.starts_with("s3://") && !Path::new(&input_spec).is_dir() {
+        error!("invalid --input directory {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() {
+        error!("invalid --output directory {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&trigger_spec).is_dir() {
+        error!("invalid --triggers directory {}", trigger_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if polling == true && processed_spec.is_empty() {
+        error!("--processed_dir <dir spec> required when polling is active");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
+        error!(
+            "--processed_dir {} is not a valid directory",
+            processed_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    scratch::configure("asset", &args.scratch);
+    let deadletter_spec = spool::configure(&args.spool);
+    coalesce::configure(&args.coalesce);
+    watermark::configure(&args.watermark);
+
+    match Path::new(&inventory_spec).parent() {
+        Some(dir) if dir.as_os_str().is_empty() || dir.is_dir() => (),
+        _ => {
+            error!("invalid --inventory file {}", inventory_spec);
+            shutdown::exit(exitcode::CONFIG)
+        }
+    }
+
+    parquet::configure(&args.parquet);
+
+    if args.validate.enabled() {
+        validate::Report::new(
+            "asset",
+            &[&input_spec, &output_spec, &trigger_spec, &processed_spec, &deadletter_spec],
+        ).exit();
+    }
+
+    health::configure(
+        "asset",
+        &args.health,
+        &[
+            &input_spec,
+            &output_spec,
+            &trigger_spec,
+            &processed_spec,
+            &deadletter_spec,
+        ],
+    );
+
+    let inventory = Inventory {
+        inventory_spec,
+        trigger_spec,
+        learn,
+    };
+    if let Err(e) = asset(
+        inventory,
+        &input_spec,
+        &output_spec,
+        &processed_spec,
+        polling,
+    ) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
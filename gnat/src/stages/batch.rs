@@ -0,0 +1,113 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+use crate::core::batch::batch;
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::scratch::{self, ScratchArgs};
+use crate::core::shutdown;
+use crate::core::validate::{self, ValidateArgs};
+use crate::core::watermark::{self, WatermarkArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    minutes: Option<u32>,
+
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    #[arg(long)]
+    tag: Option<String>,
+
+    /// merge as soon as --input holds this many MB (0 = only every --minutes)
+    #[arg(long)]
+    target_mb: Option<u64>,
+
+    /// write one file per aligned window of this many minutes of stime (0 = one file)
+    #[arg(long)]
+    bucket_minutes: Option<u32>,
+
+    #[command(flatten)]
+    scratch: ScratchArgs,
+
+    #[command(flatten)]
+    watermark: WatermarkArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_batch");
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let minutes_spec = args.minutes.unwrap_or(1).clone();
+    let tag_spec = args.tag.unwrap_or("gnat".to_string()).clone();
+    let target_mb = args.target_mb.unwrap_or(0);
+    let bucket_minutes = args.bucket_minutes.unwrap_or(0);
+    //
+    // verify the combination of arguments are valid
+    //
+    if !Path::new(&input_spec).is_dir() {
+        error!("invalid --input directory {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() {
+        error!("invalid --output directory {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if minutes_spec <= 0 {
+        error!("invalid --interval value {}", minutes_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if bucket_minutes > 0 && 1440 % bucket_minutes != 0 {
+        error!("invalid --bucket-minutes {} (must divide a day)", bucket_minutes);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    scratch::configure("batch", &args.scratch);
+    watermark::configure(&args.watermark);
+
+    parquet::configure(&args.parquet);
+
+    if args.validate.enabled() {
+        validate::Report::new("batch", &[&input_spec, &output_spec]).exit();
+    }
+
+    health::configure("batch", &args.health, &[&input_spec, &output_spec]);
+
+    if let Err(e) = batch(tag_spec, minutes_spec, target_mb, bucket_minutes, input_spec, output_spec) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::beacon::{beacon, Beacon};
+use crate::core::coalesce::{self, CoalesceArgs};
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::scratch::{self, ScratchArgs};
+use crate::core::shutdown;
+use crate::core::spool::{self, SpoolArgs};
+use crate::core::suppress::Store;
+use crate::core::validate::{self, ValidateArgs};
+use crate::core::watermark::{self, WatermarkArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    /// directory the trigger files are written to
+    #[arg(long)]
+    triggers: String,
+
+    /// suppression store written by gnat_suppress; matching flows are not detected on
+    #[arg(long)]
+    suppressions: Option<String>,
+
+    #[arg(long)]
+    processed: Option<String>,
+
+    #[arg(long)]
+    polling: Option<bool>,
+
+    /// hours of flows scored for each pair
+    #[arg(long)]
+    window: Option<u64>,
+
+    /// minutes between evaluations of the window
+    #[arg(long)]
+    step: Option<u64>,
+
+    /// seconds past a step before its window is evaluated
+    #[arg(long)]
+    grace: Option<u64>,
+
+    /// fewest flows in the window for a pair to be scored
+    #[arg(long)]
+    min_flows: Option<u64>,
+
+    /// most bytes per flow, on average, for a pair to be scored
+    #[arg(long)]
+    max_bytes: Option<u64>,
+
+    /// periodicity score (0..1) that raises a trigger
+    #[arg(long)]
+    threshold: Option<f64>,
+
+    #[command(flatten)]
+    scratch: ScratchArgs,
+
+    #[command(flatten)]
+    spool: SpoolArgs,
+
+    #[command(flatten)]
+    coalesce: CoalesceArgs,
+
+    #[command(flatten)]
+    watermark: WatermarkArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_beacon");
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let trigger_spec = args.triggers.clone();
+    let suppress_spec = args.suppressions.unwrap_or(String::new()).clone();
+    let processed_spec = args.processed.unwrap_or(String::new()).clone();
+    let polling = args.polling.unwrap_or(false).clone();
+    let window = args.window.unwrap_or(6);
+    let step = args.step.unwrap_or(60);
+    let grace = args.grace.unwrap_or(300);
+    let min_flows = args.min_flows.unwrap_or(8);
+    let max_bytes = args.max_bytes.unwrap_or(10000);
+    let threshold = args.threshold.unwrap_or(0.9);
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !input_spec.starts_with("s3://") && !Path::new(&input_spec).is_dir() {
+        error!("invalid --input directory {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() {
+        error!("invalid --output directory {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&trigger_spec).is_dir() {
+        error!("invalid --triggers directory {}", trigger_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !suppress_spec.is_empty() && !Path::new(&suppress_spec).is_file() {
+        error!("invalid --suppressions file {}", suppress_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if polling == true && processed_spec.is_empty() {
+        error!("--processed_dir <dir spec> required when polling is active");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
+        error!(
+            "--processed_dir {} is not a valid directory",
+            processed_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    scratch::configure("beacon", &args.scratch);
+    let deadletter_spec = spool::configure(&args.spool);
+    coalesce::configure(&args.coalesce);
+    watermark::configure(&args.watermark);
+
+    if window == 0 || step == 0 || step > window * 60 {
+        error!("--window and --step must be greater than 0, with --step no longer than --window");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if min_flows < 3 {
+        error!("--min-flows must be at least 3");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !(0.0..=1.0).contains(&threshold) {
+        error!("--threshold must be between 0 and 1");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    parquet::configure(&args.parquet);
+
+    if args.validate.enabled() {
+        let mut report = validate::Report::new(
+            "beacon",
+            &[&input_spec, &output_spec, &trigger_spec, &processed_spec, &deadletter_spec],
+        );
+        if !suppress_spec.is_empty() {
+            report.check("suppressions", Store::open(&suppress_spec));
+        }
+        report.exit();
+    }
+
+    health::configure(
+        "beacon",
+        &args.health,
+        &[
+            &input_spec,
+            &output_spec,
+            &trigger_spec,
+            &processed_spec,
+            &deadletter_spec,
+        ],
+    );
+
+    let settings = Beacon {
+        trigger_spec,
+        suppress_spec,
+        window,
+        step,
+        grace,
+        min_flows,
+        max_bytes,
+        threshold,
+    };
+    if let Err(e) = beacon(
+        settings,
+        &input_spec,
+        &output_spec,
+        &processed_spec,
+        polling,
+    ) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
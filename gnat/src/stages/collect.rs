@@ -0,0 +1,254 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::collect::collect;
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::netflow::collect_datagrams;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::shutdown;
+use crate::core::validate::{self, ValidateArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// exporter protocol [ipfix|netflow|sflow]; netflow accepts v5 and v9
+    #[arg(long)]
+    format: Option<String>,
+
+    #[arg(long)]
+    host: Option<String>,
+
+    #[arg(long)]
+    port: Option<String>,
+
+    #[arg(long)]
+    transport: Option<String>,
+
+    #[arg(long)]
+    output: String,
+
+    /// directory for the DNS records of YAF DPI (dns.*.parquet)
+    #[arg(long)]
+    dns_output: Option<String>,
+
+    #[arg(long)]
+    observation: String,
+
+    #[arg(long)]
+    rotate_interval: Option<u32>,
+
+    #[arg(long)]
+    verbose: Option<bool>,
+
+    #[arg(long)]
+    ssl_ca_file: Option<String>,
+
+    #[arg(long)]
+    ssl_cert_file: Option<String>,
+
+    #[arg(long)]
+    ssl_key_file: Option<String>,
+
+    #[arg(long)]
+    ssl_key_pass: Option<String>,
+
+    #[arg(long)]
+    asn: Option<String>,
+
+    #[arg(long)]
+    country: Option<String>,
+
+    /// GeoIP2/GeoLite2 City database for scity/dcity and lat/lon
+    #[arg(long)]
+    city: Option<String>,
+
+    /// hours between checks for updated MaxMind files (0 disables)
+    #[arg(long)]
+    geo_refresh: Option<u32>,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    let _stage = logging::init("gnat_collect");
+    let format_spec = args.format.unwrap_or("ipfix".to_string()).clone();
+    let host_spec = args.host.unwrap_or("127.0.0.1".to_string()).clone();
+    let output_spec = args.output.clone();
+    let dns_output_spec = args.dns_output.unwrap_or(String::new()).clone();
+    let observation = args.observation.clone();
+    let asn_spec = args.asn.unwrap_or(String::new()).clone();
+    let country_spec = args.country.unwrap_or(String::new()).clone();
+    let city_spec = args.city.unwrap_or(String::new()).clone();
+    let geo_refresh_spec = args.geo_refresh.unwrap_or(24);
+    let rotate_spec = args.rotate_interval.unwrap_or(60).clone();
+    let verbose_spec = args.verbose.unwrap_or(false).clone();
+    let default_port = match format_spec.as_str() {
+        "netflow" => "2055",
+        "sflow" => "6343",
+        _ => "4739",
+    };
+    let default_transport = if format_spec == "ipfix" { "tcp" } else { "udp" };
+    let port_spec = args.port.unwrap_or(default_port.to_string()).clone();
+    let transport_spec = args.transport.unwrap_or(default_transport.to_string()).clone();
+    let ssl_ca_file_spec = args.ssl_ca_file.unwrap_or("".to_string()).clone();
+    let ssl_cert_file_spec = args.ssl_cert_file.unwrap_or("".to_string()).clone();
+    let ssl_key_file_spec = args.ssl_key_file.unwrap_or("".to_string()).clone();
+    let ssl_key_pass_spec = args.ssl_key_pass.unwrap_or("".to_string()).clone();
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if format_spec != "ipfix" && format_spec != "netflow" && format_spec != "sflow" {
+        error!("invalid --format {} [ipfix|netflow|sflow]", format_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if host_spec.is_empty() {
+        error!("invalid --host {}", host_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if port_spec.parse::<u16>().is_err() {
+        error!("invalid --port {}", port_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if transport_spec != "tcp" && transport_spec != "udp" && transport_spec != "sctp" {
+        error!("invalid --transport {} [tcp|udp|sctp]", transport_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() {
+        error!("invalid --output directory {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !dns_output_spec.is_empty() && !Path::new(&dns_output_spec).is_dir() {
+        error!("invalid --dns-output directory {}", dns_output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if ssl_cert_file_spec.is_empty() != ssl_key_file_spec.is_empty() {
+        error!("--ssl-cert-file and --ssl-key-file must be used together");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    for ssl_file in [&ssl_ca_file_spec, &ssl_cert_file_spec, &ssl_key_file_spec] {
+        if !ssl_file.is_empty() && !Path::new(ssl_file).is_file() {
+            error!("invalid ssl file {}", ssl_file);
+            shutdown::exit(exitcode::CONFIG)
+        }
+    }
+
+    if rotate_spec == 0 {
+        error!("invalid --rotate-interval {}", rotate_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    parquet::configure(&args.parquet);
+
+    if !city_spec.is_empty() && !Path::new(&city_spec).is_file() {
+        error!("invalid --city file {}", city_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if format_spec != "ipfix" {
+        if transport_spec != "udp" {
+            error!("--format {} is only collected over udp", format_spec);
+            shutdown::exit(exitcode::CONFIG)
+        }
+        let ipfix_only = [
+            &ssl_ca_file_spec,
+            &ssl_cert_file_spec,
+            &ssl_key_file_spec,
+            &asn_spec,
+            &country_spec,
+            &city_spec,
+            &dns_output_spec,
+        ];
+        if ipfix_only.iter().any(|spec| !spec.is_empty()) {
+            error!("--ssl-*, --dns-output and MaxMind options require --format ipfix");
+            shutdown::exit(exitcode::CONFIG)
+        }
+    }
+
+    if args.validate.enabled() {
+        validate::Report::new(
+            "collect",
+            &[
+                &output_spec,
+                &dns_output_spec,
+                &asn_spec,
+                &country_spec,
+                &city_spec,
+                &ssl_ca_file_spec,
+                &ssl_cert_file_spec,
+                &ssl_key_file_spec,
+            ],
+        ).exit();
+    }
+
+    health::configure("collect", &args.health, &[&output_spec, &dns_output_spec]);
+
+    if format_spec != "ipfix" {
+        shutdown::install();
+        if let Err(e) = collect_datagrams(
+            &format_spec,
+            &observation,
+            &host_spec,
+            port_spec.parse::<u16>().unwrap(),
+            rotate_spec,
+            &output_spec,
+        ) {
+            error!("{}", e);
+            shutdown::exit(exitcode::SOFTWARE)
+        }
+        return;
+    }
+
+    if let Err(e) = collect(
+        &observation,
+        &host_spec,
+        &port_spec,
+        &transport_spec,
+        &ssl_ca_file_spec,
+        &ssl_cert_file_spec,
+        &ssl_key_file_spec,
+        &ssl_key_pass_spec,
+        rotate_spec,
+        verbose_spec,
+        &output_spec,
+        &dns_output_spec,
+        &asn_spec,
+        &country_spec,
+        &city_spec,
+        geo_refresh_spec,
+    ) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
@@ -0,0 +1,168 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::coalesce::{self, CoalesceArgs};
+use crate::core::correlate::Alerts;
+use crate::core::correlate::correlate;
+use crate::core::correlate::CorrelateConfig;
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::scratch::{self, ScratchArgs};
+use crate::core::shutdown;
+use crate::core::spool::{self, SpoolArgs, WorkersArgs};
+use crate::core::validate::{self, ValidateArgs};
+use crate::core::watermark::{self, WatermarkArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Suricata eve.json with the alerts to correlate
+    #[arg(long)]
+    alerts: String,
+
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    #[arg(long)]
+    processed: Option<String>,
+
+    #[arg(long)]
+    polling: Option<bool>,
+
+    /// seconds an alert may fall outside a flow's stime..etime and still match
+    #[arg(long)]
+    tolerance: Option<u64>,
+
+    /// hours of alerts kept, counted back from the newest alert
+    #[arg(long)]
+    retention: Option<u64>,
+
+    #[command(flatten)]
+    scratch: ScratchArgs,
+
+    #[command(flatten)]
+    spool: SpoolArgs,
+
+    #[command(flatten)]
+    coalesce: CoalesceArgs,
+
+    #[command(flatten)]
+    workers: WorkersArgs,
+
+    #[command(flatten)]
+    watermark: WatermarkArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_correlate");
+    let alert_spec = args.alerts.clone();
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let processed_spec = args.processed.unwrap_or(String::new()).clone();
+    let polling = args.polling.unwrap_or(false).clone();
+    let tolerance = args.tolerance.unwrap_or(60);
+    let retention = args.retention.unwrap_or(24);
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !Path::new(&alert_spec).is_file() {
+        error!("invalid --alerts file {}", alert_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !input_spec.starts_with("s3://") && !Path::new(&input_spec).is_dir() {
+        error!("invalid --input directory {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() {
+        error!("invalid --output directory {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if polling == true && processed_spec.is_empty() {
+        error!("--processed_dir <dir spec> required when polling is active");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
+        error!(
+            "--processed_dir {} is not a valid directory",
+            processed_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    scratch::configure("correlate", &args.scratch);
+    let deadletter_spec = spool::configure(&args.spool);
+    coalesce::configure(&args.coalesce);
+    let workers = args.workers.count();
+    watermark::configure(&args.watermark);
+
+    if retention == 0 {
+        error!("--retention must be greater than 0");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    parquet::configure(&args.parquet);
+
+    if args.validate.enabled() {
+        let mut report = validate::Report::new(
+            "correlate",
+            &[&input_spec, &output_spec, &processed_spec, &deadletter_spec],
+        );
+        report.check("alerts", Alerts::new(&alert_spec, retention).refresh());
+        report.exit();
+    }
+
+    health::configure(
+        "correlate",
+        &args.health,
+        &[&input_spec, &output_spec, &processed_spec, &deadletter_spec],
+    );
+
+    if let Err(e) = correlate(&CorrelateConfig {
+        alert_spec,
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        workers,
+        tolerance,
+        retention_hours: retention,
+    }) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
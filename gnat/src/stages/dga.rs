@@ -0,0 +1,152 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::coalesce::{self, CoalesceArgs};
+use crate::core::dga::dga;
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::scratch::{self, ScratchArgs};
+use crate::core::shutdown;
+use crate::core::spool::{self, SpoolArgs, WorkersArgs};
+use crate::core::validate::{self, ValidateArgs};
+use crate::core::watermark::{self, WatermarkArgs};
+use crate::model::dga::Model;
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// benign domain list, one per line or rank,domain, added to the built-in model
+    #[arg(long)]
+    train: Option<String>,
+
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    #[arg(long)]
+    processed: Option<String>,
+
+    #[arg(long)]
+    polling: Option<bool>,
+
+    #[command(flatten)]
+    scratch: ScratchArgs,
+
+    #[command(flatten)]
+    spool: SpoolArgs,
+
+    #[command(flatten)]
+    coalesce: CoalesceArgs,
+
+    #[command(flatten)]
+    workers: WorkersArgs,
+
+    #[command(flatten)]
+    watermark: WatermarkArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_dga");
+    let train_spec = args.train.unwrap_or(String::new()).clone();
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let processed_spec = args.processed.unwrap_or(String::new()).clone();
+    let polling = args.polling.unwrap_or(false).clone();
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !train_spec.is_empty() && !Path::new(&train_spec).is_file() {
+        error!("invalid --train file {}", train_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !input_spec.starts_with("s3://") && !Path::new(&input_spec).is_dir() {
+        error!("invalid --input directory {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() {
+        error!("invalid --output directory {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if polling == true && processed_spec.is_empty() {
+        error!("--processed_dir <dir spec> required when polling is active");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
+        error!(
+            "--processed_dir {} is not a valid directory",
+            processed_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    scratch::configure("dga", &args.scratch);
+    let deadletter_spec = spool::configure(&args.spool);
+    coalesce::configure(&args.coalesce);
+    let workers = args.workers.count();
+    watermark::configure(&args.watermark);
+
+    parquet::configure(&args.parquet);
+
+    if args.validate.enabled() {
+        let mut report = validate::Report::new(
+            "dga",
+            &[&input_spec, &output_spec, &processed_spec, &deadletter_spec],
+        );
+        if !train_spec.is_empty() {
+            report.check("train", Model::load(&train_spec));
+        }
+        report.exit();
+    }
+
+    health::configure(
+        "dga",
+        &args.health,
+        &[&input_spec, &output_spec, &processed_spec, &deadletter_spec],
+    );
+
+    if let Err(e) = dga(
+        &train_spec,
+        &input_spec,
+        &output_spec,
+        &processed_spec,
+        polling,
+        workers,
+    ) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
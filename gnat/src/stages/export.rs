@@ -0,0 +1,243 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::anonymize;
+use crate::core::encrypt;
+use crate::core::export::{export, ExportConfig};
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::parquet::{self, ParquetArgs, WriterOptions};
+use crate::core::scratch::{self, ScratchArgs};
+use crate::core::shutdown;
+use crate::core::validate::{self, ValidateArgs};
+use crate::core::watermark::{self, WatermarkArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    #[arg(long)]
+    processed: Option<String>,
+
+    #[arg(long)]
+    rotate_interval: Option<u32>,
+
+    #[arg(long)]
+    polling: Option<bool>,
+
+    #[arg(long)]
+    verbose: Option<bool>,
+
+    #[arg(long)]
+    format: Option<String>,
+
+    #[arg(long)]
+    max_rows: Option<u64>,
+
+    #[arg(long)]
+    max_bytes: Option<u64>,
+
+    #[arg(long)]
+    compression: Option<String>,
+
+    /// write under tenant=/year=/month=/day= directories in --output
+    #[arg(long)]
+    partition: Option<bool>,
+
+    /// encrypt parquet output with the key of GNAT_PARQUET_KEY or GNAT_PARQUET_KEY_COMMAND
+    #[arg(long)]
+    encrypt: Option<bool>,
+
+    /// anonymize addresses and MACs: prefix (prefix-preserving) or hmac
+    #[arg(long)]
+    anonymize: Option<String>,
+
+    /// secret key file for --anonymize, at least 16 bytes
+    #[arg(long)]
+    anonymize_key: Option<String>,
+
+    #[command(flatten)]
+    scratch: ScratchArgs,
+
+    #[command(flatten)]
+    watermark: WatermarkArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_export");
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let processed_spec = args.processed.unwrap_or(String::new()).clone();
+    let format = args.format.clone().unwrap_or("json".to_string());
+    let polling = args.polling.unwrap_or(false).clone();
+    let max_rows = args.max_rows.unwrap_or(0);
+    let max_bytes = args.max_bytes.unwrap_or(0);
+    let compression = args.compression.unwrap_or("none".to_string()).clone();
+    let partition = args.partition.unwrap_or(false);
+    let encrypt = args.encrypt.unwrap_or(false);
+    let anonymize_mode = args.anonymize.unwrap_or(String::new()).clone();
+    let anonymize_key = args.anonymize_key.unwrap_or(String::new()).clone();
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !Path::new(&input_spec).is_dir() && !Path::new(&input_spec).is_file() {
+        error!("invalid --input {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if Path::new(&input_spec).is_file() && !Path::new(&output_spec).is_file() {
+        error!("--input <file spec> requires --output <file spec>");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if Path::new(&input_spec).is_dir() && !Path::new(&output_spec).is_dir() {
+        error!("--input <dir spec> requires --output <dir spec>");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() && !Path::new(&output_spec).is_file()
+    {
+        error!("invalid --output {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if polling == true && processed_spec.is_empty() {
+        error!("--processed_dir <dir spec> required when polling is active");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
+        error!(
+            "--processed_dir {} is not a valid directory",
+            processed_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if cfg!(not(feature = "questdb")) && format == "questdb" {
+        error!("--format questdb requires the questdb feature");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !["json", "ndjson", "csv", "parquet", "questdb"].contains(&format.as_str()) {
+        error!("invalid --format {} [json|ndjson|csv|parquet|questdb]", format);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if format == "parquet" {
+        if !["none", "snappy", "gzip", "zstd"].contains(&compression.as_str()) {
+            error!("invalid --compression {} [none|snappy|gzip|zstd]", compression);
+            shutdown::exit(exitcode::CONFIG)
+        }
+    } else if !["none", "gzip", "zstd"].contains(&compression.as_str()) {
+        error!("invalid --compression {} [none|gzip|zstd]", compression);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if max_rows > 0 && max_bytes > 0 {
+        error!("--max-rows and --max-bytes are mutually exclusive");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if partition && (format == "questdb" || !Path::new(&output_spec).is_dir()) {
+        error!("--partition requires --output <dir spec> and a file --format");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if partition && (max_rows > 0 || max_bytes > 0) {
+        error!("--partition can't be combined with --max-rows or --max-bytes");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if encrypt && format != "parquet" {
+        error!("--encrypt requires --format parquet");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if encrypt {
+        if let Err(e) = encrypt::enable() {
+            error!("--encrypt: {}", e);
+            shutdown::exit(exitcode::CONFIG)
+        }
+    }
+
+    if !anonymize_mode.is_empty() && !Path::new(&anonymize_key).is_file() {
+        error!("--anonymize requires --anonymize-key <file>");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !anonymize_mode.is_empty() {
+        if let Err(e) = anonymize::enable(&anonymize_mode, &anonymize_key) {
+            error!("--anonymize: {}", e);
+            shutdown::exit(exitcode::CONFIG)
+        }
+    }
+
+    scratch::configure("export", &args.scratch);
+    watermark::configure(&args.watermark);
+
+    // --compression picks the codec of parquet outputs
+    if args.parquet.parquet_codec.is_some() {
+        error!("--parquet-codec is not used by gnat_export; set the codec with --compression");
+        shutdown::exit(exitcode::CONFIG)
+    }
+    let parquet_codec = if compression == "none" { "uncompressed" } else { compression.as_str() };
+    if let Err(e) = parquet::set_options(WriterOptions {
+        codec: String::from(parquet_codec),
+        ..args.parquet.options()
+    }) {
+        error!("{}", e);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if args.validate.enabled() {
+        validate::Report::new("export", &[&input_spec, &output_spec, &processed_spec]).exit();
+    }
+
+    health::configure("export", &args.health, &[&input_spec, &output_spec, &processed_spec]);
+
+    let _ = export(&ExportConfig {
+        input_spec,
+        output_spec,
+        processed_spec,
+        polling,
+        format,
+        max_rows,
+        max_bytes,
+        compression,
+        partition,
+    });
+}
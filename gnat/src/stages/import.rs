@@ -0,0 +1,253 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+use crate::core::health::{self, HealthArgs};
+use crate::core::import::{import, ImportConfig};
+use crate::core::logging;
+use crate::core::orient::Orientation;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::shutdown;
+use crate::core::tenant::{self, Tenants};
+use crate::core::validate::{self, ValidateArgs};
+use crate::core::watermark::{self, WatermarkArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    #[arg(long)]
+    observation: String,
+
+    #[arg(long)]
+    processed: Option<String>,
+
+    /// directory for the DNS records of YAF DPI (dns.*.parquet)
+    #[arg(long)]
+    dns_output: Option<String>,
+
+    /// input format: yaf (IPFIX files written by YAF) or pcap (pcap/pcapng captures)
+    #[arg(long)]
+    format: Option<String>,
+
+    /// pcap flow idle timeout (seconds)
+    #[arg(long)]
+    idle_timeout: Option<u64>,
+
+    /// pcap flow active timeout (seconds)
+    #[arg(long)]
+    active_timeout: Option<u64>,
+
+    #[arg(long)]
+    rotate_interval: Option<u32>,
+
+    #[arg(long)]
+    polling: Option<bool>,
+
+    #[arg(long)]
+    verbose: Option<bool>,
+
+    #[arg(long)]
+    asn: Option<String>,
+
+    #[arg(long)]
+    country: Option<String>,
+
+    /// GeoIP2/GeoLite2 City database for scity/dcity and lat/lon
+    #[arg(long)]
+    city: Option<String>,
+
+    /// TOML file of internal and dmz networks; sets orient on each flow
+    #[arg(long)]
+    networks: Option<String>,
+
+    /// tenant of the imported flows
+    #[arg(long)]
+    tenant: Option<String>,
+
+    /// CSV or TOML file mapping observations to tenants
+    #[arg(long)]
+    tenants: Option<String>,
+
+    #[command(flatten)]
+    watermark: WatermarkArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_import");
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let processed_spec = args.processed.unwrap_or(String::new()).clone();
+    let dns_output_spec = args.dns_output.unwrap_or(String::new()).clone();
+    let observation = args.observation.clone();
+    let format = args.format.unwrap_or(String::from("yaf")).clone();
+    let idle_timeout = args.idle_timeout.unwrap_or(300);
+    let active_timeout = args.active_timeout.unwrap_or(1800);
+    let asn = args.asn.unwrap_or(String::new()).clone();
+    let country = args.country.unwrap_or(String::new()).clone();
+    let city = args.city.unwrap_or(String::new()).clone();
+    let networks = args.networks.unwrap_or(String::new()).clone();
+    let tenant = args.tenant.unwrap_or(String::new()).clone();
+    let tenants = args.tenants.unwrap_or(String::new()).clone();
+    let polling = args.polling.unwrap_or(false).clone();
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if format != "yaf" && format != "pcap" {
+        error!("invalid --format {} (yaf|pcap)", format);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if output_spec.is_empty() {
+        error!("--output <spec>  required",);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&input_spec).is_dir() && !Path::new(&input_spec).is_file() {
+        error!("invalid --input {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if Path::new(&input_spec).is_file() && !Path::new(&output_spec).is_file() {
+        error!("--input <file spec> requires --output <file spec>");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if Path::new(&input_spec).is_dir() && !Path::new(&output_spec).is_dir() {
+        error!("--input <dir spec> requires --output <dir spec>");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() && !Path::new(&output_spec).is_file() {
+        error!("invalid --output {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if polling == true && processed_spec.is_empty() {
+        error!("--processed_dir <dir spec> required when polling is active");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
+        error!(
+            "--processed_dir {} is not a valid directory",
+            processed_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !city.is_empty() && !Path::new(&city).is_file() {
+        error!("invalid --city file {}", city);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !networks.is_empty() && !Path::new(&networks).is_file() {
+        error!("invalid --networks file {}", networks);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !tenant.is_empty() && !tenant::valid_name(&tenant) {
+        error!("invalid --tenant {} (letters, digits, '-', '_' and '.')", tenant);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !tenants.is_empty() && !Path::new(&tenants).is_file() {
+        error!("invalid --tenants file {}", tenants);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if format == "pcap" && !(asn.is_empty() && country.is_empty() && city.is_empty()) {
+        error!("--asn, --country and --city are not supported with --format pcap");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !dns_output_spec.is_empty() && !Path::new(&dns_output_spec).is_dir() {
+        error!("invalid --dns-output directory {}", dns_output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if format == "pcap" && !dns_output_spec.is_empty() {
+        error!("--dns-output is not supported with --format pcap");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if idle_timeout == 0 || active_timeout == 0 {
+        error!("--idle-timeout and --active-timeout must be greater than 0");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    watermark::configure(&args.watermark);
+
+    parquet::configure(&args.parquet);
+
+    if args.validate.enabled() {
+        let mut report = validate::Report::new(
+            "import",
+            &[&input_spec, &output_spec, &processed_spec, &dns_output_spec, &asn, &country, &city],
+        );
+        if !networks.is_empty() {
+            report.check("networks", Orientation::load(&networks));
+        }
+        if !tenant.is_empty() || !tenants.is_empty() {
+            report.check("tenants", Tenants::load(&tenant, &tenants));
+        }
+        report.exit();
+    }
+
+    health::configure(
+        "import",
+        &args.health,
+        &[&input_spec, &output_spec, &processed_spec, &dns_output_spec],
+    );
+
+    if let Err(e) = import(&ImportConfig {
+        format_spec: format,
+        observation_tag: observation,
+        input_spec,
+        output_spec,
+        processed_spec,
+        dns_output_spec,
+        polling,
+        asn_spec: asn,
+        country_spec: country,
+        city_spec: city,
+        idle_timeout,
+        active_timeout,
+        networks_spec: networks,
+        tenant,
+        tenants_spec: tenants,
+    }) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
@@ -0,0 +1,118 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::health::{self, HealthArgs};
+use crate::core::kafka::kafka;
+use crate::core::logging;
+use crate::core::shutdown;
+use crate::core::spool::{self, SpoolArgs};
+use crate::core::validate::{self, ValidateArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// comma separated list of host:port
+    #[arg(long)]
+    brokers: String,
+
+    #[arg(long)]
+    topic: String,
+
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    processed: Option<String>,
+
+    #[arg(long)]
+    polling: Option<bool>,
+
+    #[command(flatten)]
+    spool: SpoolArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_kafka");
+    let brokers_spec = args.brokers.clone();
+    let topic = args.topic.clone();
+    let input_spec = args.input.clone();
+    let processed_spec = args.processed.unwrap_or(String::new()).clone();
+    let polling = args.polling.unwrap_or(false).clone();
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if brokers_spec.is_empty() || brokers_spec.split(',').any(|b| !b.contains(':')) {
+        error!("invalid --brokers {} [host:port,...]", brokers_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if topic.is_empty() {
+        error!("invalid --topic");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !input_spec.starts_with("s3://") && !Path::new(&input_spec).is_dir() {
+        error!("invalid --input directory {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if polling == true && processed_spec.is_empty() {
+        error!("--processed_dir <dir spec> required when polling is active");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
+        error!(
+            "--processed_dir {} is not a valid directory",
+            processed_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    let deadletter_spec = spool::configure(&args.spool);
+
+    if args.validate.enabled() {
+        validate::Report::new("kafka", &[&input_spec, &processed_spec, &deadletter_spec]).exit();
+    }
+
+    health::configure(
+        "kafka",
+        &args.health,
+        &[&input_spec, &processed_spec, &deadletter_spec],
+    );
+
+    if let Err(e) = kafka(
+        &brokers_spec,
+        &topic,
+        &input_spec,
+        &processed_spec,
+        polling,
+    ) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
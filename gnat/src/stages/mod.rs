@@ -0,0 +1,150 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// The stages of the gnat_<kind> binaries
+//
+// stage() of each module parses a stage's command line and runs it; the
+// main() of gnat_<kind> calls it with the process arguments, and gnat_run
+// calls it on the thread of each pipeline stage of that kind. check()
+// parses a command line without running the stage, for gnat_run --check.
+//
+
+pub mod asset;
+pub mod batch;
+pub mod beacon;
+pub mod collect;
+pub mod correlate;
+pub mod detect;
+pub mod dga;
+pub mod enrich;
+pub mod export;
+pub mod import;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod plugin;
+pub mod report;
+pub mod sample;
+pub mod scan;
+pub mod site;
+pub mod stitch;
+pub mod tag;
+#[cfg(feature = "wasm")]
+pub mod transform;
+
+use crate::core::shutdown;
+
+//
+// Parse a stage's command line; an invalid one stops this stage only,
+// rather than the process and every other stage of gnat_run with it
+//
+pub fn parse<A: clap::Parser>(arguments: Vec<String>) -> A {
+    match A::try_parse_from(arguments) {
+        Ok(args) => args,
+        Err(e) => {
+            let _ = e.print();
+            // --help and --version end here too
+            if e.use_stderr() {
+                shutdown::exit(exitcode::CONFIG)
+            }
+            shutdown::exit(exitcode::OK)
+        }
+    }
+}
+
+//
+// Parse a stage's command line without running it; Err holds the first
+// line of clap's message
+//
+pub fn check<A: clap::Parser>(arguments: Vec<String>) -> Result<(), String> {
+    A::try_parse_from(arguments).map(|_| ()).map_err(|e| {
+        let message = e.to_string();
+        let line = message.lines().next().unwrap_or_default();
+        String::from(line.trim_start_matches("error: "))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(arguments: &[&str]) -> Vec<String> {
+        arguments.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn check_reports_invalid_options() {
+        assert!(tag::check(argv(&[
+            "gnat_tag",
+            "--indicators",
+            "/etc/gnat/indicators.csv",
+            "--input",
+            "/spool/a",
+            "--output",
+            "/spool/b",
+        ]))
+        .is_ok());
+        let typo = tag::check(argv(&[
+            "gnat_tag",
+            "--indicator",
+            "/etc/gnat/indicators.csv",
+            "--input",
+            "/spool/a",
+            "--output",
+            "/spool/b",
+        ]))
+        .unwrap_err();
+        assert!(typo.contains("--indicator"), "{}", typo);
+        assert!(!typo.contains('\n'));
+    }
+
+    #[test]
+    fn parse_stops_only_its_stage() {
+        let stage = std::thread::spawn(|| {
+            crate::core::context::enter(crate::core::context::next());
+            tag::stage(argv(&["gnat_tag", "--bogus", "1"]))
+        });
+        let payload = stage.join().unwrap_err();
+        assert_eq!(
+            payload.downcast_ref::<shutdown::Exit>().map(|e| e.0),
+            Some(exitcode::CONFIG)
+        );
+    }
+
+    #[test]
+    fn collect_validates_datagram_formats() {
+        let output = crate::core::testutil::test_dir("collect-validate");
+        for format in ["netflow", "sflow"] {
+            let output = output.clone();
+            let stage = std::thread::spawn(move || {
+                crate::core::context::enter(crate::core::context::next());
+                collect::stage(argv(&[
+                    "gnat_collect",
+                    "--format",
+                    format,
+                    "--port",
+                    "0",
+                    "--observation",
+                    "sensor1",
+                    "--output",
+                    &output,
+                    "--validate",
+                    "true",
+                ]))
+            });
+            let payload = stage.join().unwrap_err();
+            assert_eq!(
+                payload.downcast_ref::<shutdown::Exit>().map(|e| e.0),
+                Some(exitcode::OK),
+                "{}",
+                format
+            );
+        }
+        let _ = std::fs::remove_dir_all(&output);
+    }
+}
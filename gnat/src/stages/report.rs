@@ -0,0 +1,156 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::encrypt;
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::report::{period_start, report};
+use crate::core::scratch::{self, ScratchArgs};
+use crate::core::shutdown;
+use crate::core::validate::{self, ValidateArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// flow parquet directory (read recursively), glob or s3:// prefix
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    /// hour | day
+    #[arg(long)]
+    interval: Option<String>,
+
+    /// html | csv | parquet
+    #[arg(long)]
+    format: Option<String>,
+
+    /// rows per section
+    #[arg(long)]
+    top: Option<u64>,
+
+    /// interval to report, YYYY-MM-DD or YYYY-MM-DDTHH (default: the last complete one)
+    #[arg(long)]
+    period: Option<String>,
+
+    /// keep reporting each interval as it completes
+    #[arg(long)]
+    polling: Option<bool>,
+
+    /// read parquet files encrypted by gnat_export --encrypt
+    #[arg(long)]
+    decrypt: Option<bool>,
+
+    #[command(flatten)]
+    scratch: ScratchArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_report");
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let interval_spec = args.interval.unwrap_or(String::from("day")).clone();
+    let format_spec = args.format.unwrap_or(String::from("html")).clone();
+    let top = args.top.unwrap_or(10);
+    let period_spec = args.period.unwrap_or(String::new()).clone();
+    let polling = args.polling.unwrap_or(false).clone();
+    let decrypt = args.decrypt.unwrap_or(false);
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !input_spec.starts_with("s3://")
+        && !input_spec.contains('*')
+        && !Path::new(&input_spec).exists()
+    {
+        error!("invalid --input {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() {
+        error!("invalid --output directory {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if interval_spec != "hour" && interval_spec != "day" {
+        error!("invalid --interval {} [hour|day]", interval_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !["html", "csv", "parquet"].contains(&format_spec.as_str()) {
+        error!("invalid --format {} [html|csv|parquet]", format_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if top == 0 {
+        error!("--top must be greater than 0");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    let Some(start) = period_start(&interval_spec, &period_spec) else {
+        error!(
+            "invalid --period {} [YYYY-MM-DD|YYYY-MM-DDTHH]",
+            period_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    };
+
+    scratch::configure("report", &args.scratch);
+
+    parquet::configure(&args.parquet);
+
+    if decrypt {
+        if let Err(e) = encrypt::enable() {
+            error!("--decrypt: {}", e);
+            shutdown::exit(exitcode::CONFIG)
+        }
+    }
+
+    if args.validate.enabled() {
+        validate::Report::new("report", &[&input_spec, &output_spec]).exit();
+    }
+
+    health::configure("report", &args.health, &[&input_spec, &output_spec]);
+
+    if let Err(e) = report(
+        &input_spec,
+        &output_spec,
+        &interval_spec,
+        &format_spec,
+        top,
+        start,
+        polling,
+    ) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
@@ -0,0 +1,206 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::coalesce::{self, CoalesceArgs};
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::overrides::Overrides;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::sample::{sample, Sampler, SAMPLE_MODES};
+use crate::core::scratch::{self, ScratchArgs};
+use crate::core::shutdown;
+use crate::core::spool::{self, SpoolArgs, WorkersArgs};
+use crate::core::validate::{self, ValidateArgs};
+use crate::core::watermark::{self, WatermarkArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    #[arg(long)]
+    processed: Option<String>,
+
+    #[arg(long)]
+    polling: Option<bool>,
+
+    /// sampling mode: flat, stratified or adaptive
+    #[arg(long)]
+    mode: Option<String>,
+
+    /// flow columns that make up a category, comma separated (default appid)
+    #[arg(long)]
+    by: Option<String>,
+
+    /// percent of flows kept (flat), or the least kept per category (adaptive)
+    #[arg(long)]
+    percent: Option<f64>,
+
+    /// flows kept per category (stratified), or the size of a rare category (adaptive)
+    #[arg(long)]
+    cap: Option<u64>,
+
+    /// TOML file of per-observation percent and cap
+    #[arg(long)]
+    overrides: Option<String>,
+
+    #[command(flatten)]
+    scratch: ScratchArgs,
+
+    #[command(flatten)]
+    spool: SpoolArgs,
+
+    #[command(flatten)]
+    coalesce: CoalesceArgs,
+
+    #[command(flatten)]
+    workers: WorkersArgs,
+
+    #[command(flatten)]
+    watermark: WatermarkArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_sample");
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let processed_spec = args.processed.unwrap_or(String::new()).clone();
+    let polling = args.polling.unwrap_or(false).clone();
+    let mode = args.mode.unwrap_or(String::from("flat")).clone();
+    let by = args.by.unwrap_or(String::from("appid")).clone();
+    let percent = args.percent.unwrap_or(10.0);
+    let cap = args.cap.unwrap_or(100);
+    let overrides_spec = args.overrides.unwrap_or(String::new()).clone();
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !SAMPLE_MODES.contains(&mode.as_str()) {
+        error!("invalid --mode {} (flat|stratified|adaptive)", mode);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    let by: Vec<String> = by
+        .split(',')
+        .map(|column| String::from(column.trim()))
+        .filter(|column| !column.is_empty())
+        .collect();
+    if by.is_empty() {
+        error!("--by requires at least one column");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !(percent > 0.0 && percent <= 100.0) {
+        error!("--percent must be greater than 0 and at most 100");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if cap == 0 {
+        error!("--cap must be greater than 0");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    let overrides = match Overrides::load(&overrides_spec) {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            error!("invalid --overrides {} - {}", overrides_spec, e);
+            shutdown::exit(exitcode::CONFIG)
+        }
+    };
+
+    if !input_spec.starts_with("s3://") && !Path::new(&input_spec).is_dir() {
+        error!("invalid --input directory {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() {
+        error!("invalid --output directory {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if polling == true && processed_spec.is_empty() {
+        error!("--processed_dir <dir spec> required when polling is active");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
+        error!(
+            "--processed_dir {} is not a valid directory",
+            processed_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    scratch::configure("sample", &args.scratch);
+    let deadletter_spec = spool::configure(&args.spool);
+    coalesce::configure(&args.coalesce);
+    let workers = args.workers.count();
+    watermark::configure(&args.watermark);
+
+    parquet::configure(&args.parquet);
+
+    let sampler = Sampler {
+        mode,
+        by,
+        percent,
+        cap,
+        overrides,
+    };
+
+    if args.validate.enabled() {
+        let mut report = validate::Report::new(
+            "sample",
+            &[&input_spec, &output_spec, &processed_spec, &deadletter_spec],
+        );
+        report.check("by", sampler.validate());
+        report.exit();
+    }
+
+    health::configure(
+        "sample",
+        &args.health,
+        &[&input_spec, &output_spec, &processed_spec, &deadletter_spec],
+    );
+
+    if let Err(e) = sample(
+        sampler,
+        &input_spec,
+        &output_spec,
+        &processed_spec,
+        polling,
+        workers,
+    ) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
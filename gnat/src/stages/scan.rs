@@ -0,0 +1,201 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+use crate::core::coalesce::{self, CoalesceArgs};
+use crate::core::health::{self, HealthArgs};
+use crate::core::logging;
+use crate::core::parquet::{self, ParquetArgs};
+use crate::core::scan::{scan, Scan};
+use crate::core::scratch::{self, ScratchArgs};
+use crate::core::shutdown;
+use crate::core::spool::{self, SpoolArgs};
+use crate::core::suppress::Store;
+use crate::core::validate::{self, ValidateArgs};
+use crate::core::watermark::{self, WatermarkArgs};
+use crate::stages;
+
+use clap::Parser;
+use std::path::Path;
+use tracing::error;
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+
+    /// directory the trigger files are written to
+    #[arg(long)]
+    triggers: String,
+
+    /// suppression store written by gnat_suppress; matching flows are not detected on
+    #[arg(long)]
+    suppressions: Option<String>,
+
+    #[arg(long)]
+    processed: Option<String>,
+
+    #[arg(long)]
+    polling: Option<bool>,
+
+    /// minutes per window
+    #[arg(long)]
+    window: Option<u64>,
+
+    /// seconds past its end before a window is evaluated
+    #[arg(long)]
+    grace: Option<u64>,
+
+    /// distinct dports of one daddr that make a vertical scan
+    #[arg(long)]
+    ports: Option<u64>,
+
+    /// distinct daddrs on one dport that make a horizontal scan
+    #[arg(long)]
+    hosts: Option<u64>,
+
+    #[command(flatten)]
+    scratch: ScratchArgs,
+
+    #[command(flatten)]
+    spool: SpoolArgs,
+
+    #[command(flatten)]
+    coalesce: CoalesceArgs,
+
+    #[command(flatten)]
+    watermark: WatermarkArgs,
+
+    #[command(flatten)]
+    health: HealthArgs,
+
+    #[command(flatten)]
+    validate: ValidateArgs,
+
+    #[command(flatten)]
+    parquet: ParquetArgs,
+}
+
+pub fn check(arguments: Vec<String>) -> Result<(), String> {
+    stages::check::<Args>(arguments)
+}
+
+pub fn stage(arguments: Vec<String>) {
+    let args: Args = stages::parse(arguments);
+    shutdown::install();
+    let _stage = logging::init("gnat_scan");
+    let input_spec = args.input.clone();
+    let output_spec = args.output.clone();
+    let trigger_spec = args.triggers.clone();
+    let suppress_spec = args.suppressions.unwrap_or(String::new()).clone();
+    let processed_spec = args.processed.unwrap_or(String::new()).clone();
+    let polling = args.polling.unwrap_or(false).clone();
+    let window = args.window.unwrap_or(5);
+    let grace = args.grace.unwrap_or(300);
+    let ports = args.ports.unwrap_or(100);
+    let hosts = args.hosts.unwrap_or(50);
+
+    //
+    // verify the combination of arguments are valid
+    //
+
+    if !input_spec.starts_with("s3://") && !Path::new(&input_spec).is_dir() {
+        error!("invalid --input directory {}", input_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&output_spec).is_dir() {
+        error!("invalid --output directory {}", output_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !Path::new(&trigger_spec).is_dir() {
+        error!("invalid --triggers directory {}", trigger_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !suppress_spec.is_empty() && !Path::new(&suppress_spec).is_file() {
+        error!("invalid --suppressions file {}", suppress_spec);
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if polling == true && processed_spec.is_empty() {
+        error!("--processed_dir <dir spec> required when polling is active");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
+        error!(
+            "--processed_dir {} is not a valid directory",
+            processed_spec
+        );
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    scratch::configure("scan", &args.scratch);
+    let deadletter_spec = spool::configure(&args.spool);
+    coalesce::configure(&args.coalesce);
+    watermark::configure(&args.watermark);
+
+    if window == 0 {
+        error!("--window must be greater than 0");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    if ports < 2 || hosts < 2 {
+        error!("--ports and --hosts must be at least 2");
+        shutdown::exit(exitcode::CONFIG)
+    }
+
+    parquet::configure(&args.parquet);
+
+    if args.validate.enabled() {
+        let mut report = validate::Report::new(
+            "scan",
+            &[&input_spec, &output_spec, &trigger_spec, &processed_spec, &deadletter_spec],
+        );
+        if !suppress_spec.is_empty() {
+            report.check("suppressions", Store::open(&suppress_spec));
+        }
+        report.exit();
+    }
+
+    health::configure(
+        "scan",
+        &args.health,
+        &[
+            &input_spec,
+            &output_spec,
+            &trigger_spec,
+            &processed_spec,
+            &deadletter_spec,
+        ],
+    );
+
+    let settings = Scan {
+        trigger_spec,
+        suppress_spec,
+        window,
+        grace,
+        ports,
+        hosts,
+    };
+    if let Err(e) = scan(
+        settings,
+        &input_spec,
+        &output_spec,
+        &processed_spec,
+        polling,
+    ) {
+        error!("{}", e);
+        shutdown::exit(exitcode::SOFTWARE)
+    }
+}
@@ -7,9 +7,11 @@
  */
 
 use clap::Parser;
+use gnat_ai::logging;
 use gnat_ai::models::hbos::*;
 use gnat_ai::models::memstream::*;
 use std::path::Path;
+use tracing::error;
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -32,6 +34,7 @@ struct Args {
 
 fn main() {
     let args = Args::parse();
+    let _stage = logging::init("gnat_anomaly");
     let model_spec = args.model.clone();
     let input_spec = args.input.clone();
     let output_spec = args.output.clone();
@@ -43,38 +46,38 @@ fn main() {
     //
 
     if output_spec.is_empty() {
-        eprintln!("error: --output <spec>  required",);
+        error!("--output <spec> required");
         std::process::exit(exitcode::CONFIG)
     }
 
     if !Path::new(&input_spec).is_dir() && !Path::new(&input_spec).is_file() {
-        eprintln!("error: invalid --input {}", input_spec);
+        error!("invalid --input {}", input_spec);
         std::process::exit(exitcode::CONFIG)
     }
 
     if !Path::new(&output_spec).is_dir() && !Path::new(&output_spec).is_file() {
-        eprintln!("error: invalid --output {}", output_spec);
+        error!("invalid --output {}", output_spec);
         std::process::exit(exitcode::CONFIG)
     }
 
     if Path::new(&input_spec).is_file() && !Path::new(&output_spec).is_file() {
-        eprintln!("error: --input <file spec> requires --output <file spec>");
+        error!("--input <file spec> requires --output <file spec>");
         std::process::exit(exitcode::CONFIG)
     }
 
     if Path::new(&input_spec).is_dir() && !Path::new(&output_spec).is_dir() {
-        eprintln!("error: --input <dir spec> requires --output <dir spec>");
+        error!("--input <dir spec> requires --output <dir spec>");
         std::process::exit(exitcode::CONFIG)
     }
 
     if polling == true && processed_spec.is_empty() {
-        eprintln!("error: --processed_dir <dir spec> required when polling is active");
+        error!("--processed_dir <dir spec> required when polling is active");
         std::process::exit(exitcode::CONFIG)
     }
 
     if !processed_spec.is_empty() && !Path::new(&processed_spec).is_dir() {
-        eprintln!(
-            "error: --processed_dir {} is not a valid directory",
+        error!(
+            "--processed_dir {} is not a valid directory",
             processed_spec
         );
         std::process::exit(exitcode::CONFIG)
@@ -7,6 +7,7 @@
  */
 
 
+pub mod logging;
 pub mod models;
 
 
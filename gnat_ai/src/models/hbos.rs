@@ -12,9 +12,10 @@ use tch::{nn, nn::Module, nn::OptimizerConfig, Kind, Reduction, Tensor};
 use std::sync::mpsc::{Receiver, SyncSender};
 */
 
+use tracing::info;
 
 pub fn hbos(input_spec: &String, output_spec: &String, processed_spec: &String, poll: bool) {
-    println!("input directory: {}", input_spec);
-    println!("output directory: {}", output_spec);
-    println!("archive directory: {}", processed_spec);
+    info!("input directory: {}", input_spec);
+    info!("output directory: {}", output_spec);
+    info!("archive directory: {}", processed_spec);
 }
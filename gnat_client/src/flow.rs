@@ -0,0 +1,360 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Flow parquet records; the columns and their upgrades come from
+// crate::schema, so files written at any schema version up to
+// FLOW_SCHEMA_VERSION read as the current one
+//
+
+use crate::schema;
+
+use chrono::{DateTime, Utc};
+use duckdb::{Connection, Row};
+
+#[derive(Debug, Clone)]
+pub struct Flow {
+    pub observ: String,
+    pub stime: DateTime<Utc>,
+    pub etime: DateTime<Utc>,
+    pub dur: u32,
+    pub rtt: u32,
+    pub pcr: f32,
+    pub proto: String,
+    pub saddr: String,
+    pub daddr: String,
+    pub sport: u16,
+    pub dport: u16,
+    pub iflags: String,
+    pub uflags: String,
+    pub stcpseq: u32,
+    pub dtcpseq: u32,
+    pub svlan: u16,
+    pub dvlan: u16,
+    pub spkts: u64,
+    pub dpkts: u64,
+    pub sbytes: u64,
+    pub dbytes: u64,
+    pub sentropy: u8,
+    pub dentropy: u8,
+    pub siat: u64,
+    pub diat: u64,
+    pub sstdev: u64,
+    pub dstdev: u64,
+    pub stcpurg: u32,
+    pub dtcpurg: u32,
+    pub ssmallpktcnt: u32,
+    pub dsmallpktcnt: u32,
+    pub slargpktcnt: u32,
+    pub dlargpktcnt: u32,
+    pub snonemptypktcnt: u32,
+    pub dnonemptypktcnt: u32,
+    pub sfirstnonemptycnt: u16,
+    pub dfirstnonemptycnt: u16,
+    pub sstdevpayload: u16,
+    pub dstdevpayload: u16,
+    pub smaxpktsize: u16,
+    pub dmaxpktsize: u16,
+    pub spd: String,
+    pub appid: String,
+    pub reason: String,
+    pub smac: String,
+    pub dmac: String,
+    pub scountry: String,
+    pub dcountry: String,
+    pub sasn: u32,
+    pub dasn: u32,
+    pub sasnorg: String,
+    pub dasnorg: String,
+    pub scity: String,
+    pub dcity: String,
+    // None unless gnat_import ran with --city and the address resolved
+    pub slat: Option<f64>,
+    pub slon: Option<f64>,
+    pub dlat: Option<f64>,
+    pub dlon: Option<f64>,
+    pub sni: Option<String>,
+    pub tlsissuer: Option<String>,
+    pub tlssubject: Option<String>,
+    pub tlsnotafter: Option<DateTime<Utc>>,
+    pub ja3: Option<String>,
+    pub ja3s: Option<String>,
+    pub ja4: Option<String>,
+    pub ja4s: Option<String>,
+    pub httpmethod: Option<String>,
+    pub httphost: Option<String>,
+    pub httpuseragent: Option<String>,
+    pub httpstatus: Option<u16>,
+    pub dga_score: Option<f32>,
+    pub orient: Option<String>,
+    pub site: Option<String>,
+    pub tenant: Option<String>,
+    pub model: String,
+    pub score: f32,
+}
+
+fn timestamp(micros: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_micros(micros).unwrap_or_default()
+}
+
+impl Flow {
+    //
+    // Fields are looked up by column name, so they don't depend on the
+    // order of the parquet file
+    //
+    fn from_row(row: &Row) -> duckdb::Result<Flow> {
+        Ok(Flow {
+            observ: row.get("observ")?,
+            stime: timestamp(row.get("stime")?),
+            etime: timestamp(row.get("etime")?),
+            dur: row.get("dur")?,
+            rtt: row.get("rtt")?,
+            pcr: row.get("pcr")?,
+            proto: row.get("proto")?,
+            saddr: row.get("saddr")?,
+            daddr: row.get("daddr")?,
+            sport: row.get("sport")?,
+            dport: row.get("dport")?,
+            iflags: row.get("iflags")?,
+            uflags: row.get("uflags")?,
+            stcpseq: row.get("stcpseq")?,
+            dtcpseq: row.get("dtcpseq")?,
+            svlan: row.get("svlan")?,
+            dvlan: row.get("dvlan")?,
+            spkts: row.get("spkts")?,
+            dpkts: row.get("dpkts")?,
+            sbytes: row.get("sbytes")?,
+            dbytes: row.get("dbytes")?,
+            sentropy: row.get("sentropy")?,
+            dentropy: row.get("dentropy")?,
+            siat: row.get("siat")?,
+            diat: row.get("diat")?,
+            sstdev: row.get("sstdev")?,
+            dstdev: row.get("dstdev")?,
+            stcpurg: row.get("stcpurg")?,
+            dtcpurg: row.get("dtcpurg")?,
+            ssmallpktcnt: row.get("ssmallpktcnt")?,
+            dsmallpktcnt: row.get("dsmallpktcnt")?,
+            slargpktcnt: row.get("slargpktcnt")?,
+            dlargpktcnt: row.get("dlargpktcnt")?,
+            snonemptypktcnt: row.get("snonemptypktcnt")?,
+            dnonemptypktcnt: row.get("dnonemptypktcnt")?,
+            sfirstnonemptycnt: row.get("sfirstnonemptycnt")?,
+            dfirstnonemptycnt: row.get("dfirstnonemptycnt")?,
+            sstdevpayload: row.get("sstdevpayload")?,
+            dstdevpayload: row.get("dstdevpayload")?,
+            smaxpktsize: row.get("smaxpktsize")?,
+            dmaxpktsize: row.get("dmaxpktsize")?,
+            spd: row.get("spd")?,
+            appid: row.get("appid")?,
+            reason: row.get("reason")?,
+            smac: row.get("smac")?,
+            dmac: row.get("dmac")?,
+            scountry: row.get("scountry")?,
+            dcountry: row.get("dcountry")?,
+            sasn: row.get("sasn")?,
+            dasn: row.get("dasn")?,
+            sasnorg: row.get("sasnorg")?,
+            dasnorg: row.get("dasnorg")?,
+            scity: row.get("scity")?,
+            dcity: row.get("dcity")?,
+            slat: row.get("slat")?,
+            slon: row.get("slon")?,
+            dlat: row.get("dlat")?,
+            dlon: row.get("dlon")?,
+            sni: row.get("sni")?,
+            tlsissuer: row.get("tlsissuer")?,
+            tlssubject: row.get("tlssubject")?,
+            tlsnotafter: row.get::<_, Option<i64>>("tlsnotafter")?.map(timestamp),
+            ja3: row.get("ja3")?,
+            ja3s: row.get("ja3s")?,
+            ja4: row.get("ja4")?,
+            ja4s: row.get("ja4s")?,
+            httpmethod: row.get("httpmethod")?,
+            httphost: row.get("httphost")?,
+            httpuseragent: row.get("httpuseragent")?,
+            httpstatus: row.get("httpstatus")?,
+            dga_score: row.get("dga_score")?,
+            orient: row.get("orient")?,
+            site: row.get("site")?,
+            tenant: row.get("tenant")?,
+            model: row.get("model")?,
+            score: row.get("score")?,
+        })
+    }
+}
+
+//
+// Outer SELECT list over the upgraded files; timestamps are read as epoch
+// microseconds under their own names
+//
+fn select_list() -> String {
+    schema::flow_columns()
+        .iter()
+        .map(|column| match *column {
+            "stime" | "etime" | "tlsnotafter" => format!("epoch_us({0}) AS {0}", column),
+            _ => String::from(*column),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+//
+// Read flows from a parquet file (or glob), optionally filtered by a SQL
+// predicate. Each file is upgraded on its own, so a glob may mix files of
+// different schema versions.
+//
+pub fn read_flows(input_spec: &str, filter: Option<&str>) -> anyhow::Result<Vec<Flow>> {
+    let conn = Connection::open_in_memory()?;
+    let files: Vec<String> = conn
+        .prepare("SELECT file FROM glob(?) ORDER BY file;")?
+        .query_map([input_spec], |row| row.get(0))?
+        .collect::<duckdb::Result<Vec<String>>>()?;
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sources = files
+        .iter()
+        .map(|file| schema::select(&conn, file))
+        .collect::<anyhow::Result<Vec<String>>>()?;
+    let sql_command = format!(
+        "SELECT {} FROM ({}) WHERE {};",
+        select_list(),
+        sources.join(" UNION ALL BY NAME "),
+        filter.unwrap_or("true")
+    );
+    let mut stmt = conn.prepare(&sql_command)?;
+    let flows = stmt
+        .query_map([], Flow::from_row)?
+        .collect::<duckdb::Result<Vec<Flow>>>()?;
+    Ok(flows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // columns added by migrations 2 through 8
+    const UPGRADED: &str = "scity, dcity, slat, slon, dlat, dlon,
+        sni, tlsissuer, tlssubject, tlsnotafter, ja3, ja3s, ja4, ja4s,
+        httpmethod, httphost, httpuseragent, httpstatus,
+        dga_score, orient, site, tenant";
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("gnat_client-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    //
+    // One flow row with every column set, in a table named flow
+    //
+    fn flow_table(conn: &Connection) {
+        conn.execute_batch(&format!("{};", schema::FLOW_TABLE))
+            .unwrap();
+        let types: Vec<String> = conn
+            .prepare("SELECT column_type FROM (DESCRIBE flow);")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<duckdb::Result<Vec<String>>>()
+            .unwrap();
+        let values: Vec<&str> = types
+            .iter()
+            .map(|t| match t.as_str() {
+                "VARCHAR" => "'gnat'",
+                "TIMESTAMP" => "TIMESTAMP '2024-06-01 12:00:00'",
+                _ => "1",
+            })
+            .collect();
+        conn.execute_batch(&format!("INSERT INTO flow VALUES ({});", values.join(", ")))
+            .unwrap();
+    }
+
+    #[test]
+    fn reads_current_schema() {
+        let dir = temp_dir("current");
+        let file = dir.join("flow.parquet");
+        let conn = Connection::open_in_memory().unwrap();
+        flow_table(&conn);
+        conn.execute_batch(&format!(
+            "COPY flow TO '{}' (FORMAT parquet, KV_METADATA {{{}: '{}'}});",
+            file.display(),
+            schema::VERSION_KEY,
+            schema::FLOW_SCHEMA_VERSION
+        ))
+        .unwrap();
+
+        let flows = read_flows(&file.to_string_lossy(), None).unwrap();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].observ, "gnat");
+        assert_eq!(flows[0].stime.to_rfc3339(), "2024-06-01T12:00:00+00:00");
+        assert_eq!(flows[0].tenant.as_deref(), Some("gnat"));
+        assert_eq!(flows[0].httpstatus, Some(1));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn upgrades_unversioned_files() {
+        let dir = temp_dir("upgrade");
+        let conn = Connection::open_in_memory().unwrap();
+        flow_table(&conn);
+        // written before versioning, with none of the later columns
+        conn.execute_batch(&format!(
+            "COPY (SELECT * EXCLUDE ({}) FROM flow) TO '{}' (FORMAT parquet);
+             COPY flow TO '{}' (FORMAT parquet, KV_METADATA {{{}: '{}'}});",
+            UPGRADED,
+            dir.join("a.parquet").display(),
+            dir.join("b.parquet").display(),
+            schema::VERSION_KEY,
+            schema::FLOW_SCHEMA_VERSION
+        ))
+        .unwrap();
+
+        let old = read_flows(&dir.join("a.parquet").to_string_lossy(), None).unwrap();
+        assert_eq!(old.len(), 1);
+        assert_eq!(old[0].scity, "unk");
+        assert_eq!(old[0].slat, None);
+        assert_eq!(old[0].tlsnotafter, None);
+        assert_eq!(old[0].tenant, None);
+
+        // a glob may mix versions
+        let all = read_flows(
+            &dir.join("*.parquet").to_string_lossy(),
+            Some("tenant IS NULL"),
+        )
+        .unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].scity, "unk");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let dir = temp_dir("newer");
+        let file = dir.join("flow.parquet");
+        let conn = Connection::open_in_memory().unwrap();
+        flow_table(&conn);
+        conn.execute_batch(&format!(
+            "COPY flow TO '{}' (FORMAT parquet, KV_METADATA {{{}: '{}'}});",
+            file.display(),
+            schema::VERSION_KEY,
+            schema::FLOW_SCHEMA_VERSION + 1
+        ))
+        .unwrap();
+
+        assert!(read_flows(&file.to_string_lossy(), None).is_err());
+        assert!(
+            read_flows(&dir.join("none-*.parquet").to_string_lossy(), None)
+                .unwrap()
+                .is_empty()
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,20 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Typed readers for the toolkit's outputs:
+//
+//   flow     flow parquet files written by the gnat stages
+//   model    model DuckDB files, such as gnat_hbos histograms
+//   metrics  QuestDB tables maintained by gnat_db (via the REST /exec API)
+//   schema   the flow parquet schema and its upgrades, shared with gnat
+//
+pub mod flow;
+pub mod metrics;
+pub mod model;
+pub mod schema;
@@ -0,0 +1,304 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Typed queries against the gnat_db QuestDB tables; column lists must
+// track the CREATE TABLE statements in gnat_db/src/table
+//
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+//
+// One row of a keyed count table: appid, asn (by dasnorg), country,
+// dns, doh, ip, proto, quic or ssh
+//
+#[derive(Debug, Clone)]
+pub struct CountMetric {
+    pub bucket: DateTime<Utc>,
+    pub observ: String,
+    pub key: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct HostMetric {
+    pub bucket: DateTime<Utc>,
+    pub observ: String,
+    pub host: String,
+    pub host_id: String,
+    pub flows: i64,
+    pub obytes: i64,
+    pub ibytes: i64,
+    pub peers: i64,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceMetric {
+    pub bucket: DateTime<Utc>,
+    pub observ: String,
+    pub subnet: String,
+    pub dport: i64,
+    pub appid: String,
+    pub flows: i64,
+    pub bytes: i64,
+    pub baseline: f64,
+    pub zscore: f64,
+}
+
+pub struct MetricsClient {
+    pub api_url: String,
+}
+
+fn key_column(table_name: &str) -> Option<&'static str> {
+    match table_name {
+        "appid" => Some("appid"),
+        "asn" => Some("dasnorg"),
+        "country" => Some("dcountry"),
+        "dns" => Some("dns"),
+        "doh" => Some("dohs"),
+        "ip" => Some("daddr"),
+        "proto" => Some("proto"),
+        "quic" => Some("quic"),
+        "ssh" => Some("ssh"),
+        _ => None,
+    }
+}
+
+fn as_timestamp(value: &Value) -> DateTime<Utc> {
+    value
+        .as_str()
+        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_default()
+}
+
+fn as_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+fn as_i64(value: &Value) -> i64 {
+    value.as_i64().unwrap_or(0)
+}
+
+fn as_f64(value: &Value) -> f64 {
+    value.as_f64().unwrap_or(0.0)
+}
+
+impl MetricsClient {
+    pub fn new(host_spec: &String, api_port: u16) -> MetricsClient {
+        MetricsClient {
+            api_url: format!("http://{}:{}/exec", host_spec, api_port),
+        }
+    }
+
+    //
+    // Run a query through the QuestDB /exec API and return the dataset rows
+    //
+    pub fn query(&self, sql_command: &String) -> anyhow::Result<Vec<Vec<Value>>> {
+        let url = url::Url::parse_with_params(&self.api_url, &[("query", sql_command)])?;
+        let response: Value = serde_json::from_str(&reqwest::blocking::get(url)?.text()?)?;
+        if let Some(error) = response.get("error") {
+            anyhow::bail!("query failed - {}", error);
+        }
+        let dataset = match response.get("dataset").and_then(|d| d.as_array()) {
+            Some(d) => d,
+            None => return Ok(Vec::new()),
+        };
+        Ok(dataset
+            .iter()
+            .filter_map(|row| row.as_array().cloned())
+            .collect())
+    }
+
+    fn since(table_name: &str, since: &DateTime<Utc>) -> String {
+        format!(
+            "FROM {} WHERE bucket >= '{}' ORDER BY bucket",
+            table_name,
+            since.format("%Y-%m-%dT%H:%M:%S%.6fZ")
+        )
+    }
+
+    pub fn counts(
+        &self,
+        table_name: &str,
+        since: &DateTime<Utc>,
+    ) -> anyhow::Result<Vec<CountMetric>> {
+        let Some(key) = key_column(table_name) else {
+            anyhow::bail!("{} is not a count table", table_name);
+        };
+        let sql_command = format!(
+            "SELECT bucket, observ, {}, count {};",
+            key,
+            Self::since(table_name, since)
+        );
+        Ok(self
+            .query(&sql_command)?
+            .iter()
+            .map(|row| CountMetric {
+                bucket: as_timestamp(&row[0]),
+                observ: as_string(&row[1]),
+                key: as_string(&row[2]),
+                count: as_i64(&row[3]),
+            })
+            .collect())
+    }
+
+    pub fn hosts(&self, since: &DateTime<Utc>) -> anyhow::Result<Vec<HostMetric>> {
+        let sql_command = format!(
+            "SELECT bucket, observ, host, host_id, flows, obytes, ibytes, peers, score {};",
+            Self::since("host", since)
+        );
+        Ok(self
+            .query(&sql_command)?
+            .iter()
+            .map(|row| HostMetric {
+                bucket: as_timestamp(&row[0]),
+                observ: as_string(&row[1]),
+                host: as_string(&row[2]),
+                host_id: as_string(&row[3]),
+                flows: as_i64(&row[4]),
+                obytes: as_i64(&row[5]),
+                ibytes: as_i64(&row[6]),
+                peers: as_i64(&row[7]),
+                score: as_f64(&row[8]),
+            })
+            .collect())
+    }
+
+    pub fn services(&self, since: &DateTime<Utc>) -> anyhow::Result<Vec<ServiceMetric>> {
+        let sql_command = format!(
+            "SELECT bucket, observ, subnet, dport, appid, flows, bytes, baseline, zscore {};",
+            Self::since("service", since)
+        );
+        Ok(self
+            .query(&sql_command)?
+            .iter()
+            .map(|row| ServiceMetric {
+                bucket: as_timestamp(&row[0]),
+                observ: as_string(&row[1]),
+                subnet: as_string(&row[2]),
+                dport: as_i64(&row[3]),
+                appid: as_string(&row[4]),
+                flows: as_i64(&row[5]),
+                bytes: as_i64(&row[6]),
+                baseline: as_f64(&row[7]),
+                zscore: as_f64(&row[8]),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    //
+    // A client of a local server answering one request with body; the
+    // server returns the query it was sent when joined
+    //
+    fn serve(body: &'static str) -> (MetricsClient, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+            write!(
+                reader.into_inner(),
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            let target = line.split_whitespace().nth(1).unwrap().to_string();
+            url::Url::parse(&format!("http://localhost{}", target))
+                .unwrap()
+                .query_pairs()
+                .find(|(name, _)| name == "query")
+                .map(|(_, value)| value.into_owned())
+                .unwrap()
+        });
+        (MetricsClient::new(&String::from("127.0.0.1"), port), server)
+    }
+
+    fn since() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn counts_by_key() {
+        let (client, server) = serve(
+            r#"{"dataset":[["2024-06-01T12:01:00.000000Z","s1","US",3],["2024-06-01T12:02:00.000000Z","s1",null,null]]}"#,
+        );
+        let counts = client.counts("country", &since()).unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            "SELECT bucket, observ, dcountry, count FROM country \
+             WHERE bucket >= '2024-06-01T12:00:00.000000Z' ORDER BY bucket;"
+        );
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[0].bucket.to_rfc3339(), "2024-06-01T12:01:00+00:00");
+        assert_eq!((counts[0].key.as_str(), counts[0].count), ("US", 3));
+        // missing values read as empty
+        assert_eq!((counts[1].key.as_str(), counts[1].count), ("", 0));
+        assert!(client.counts("host", &since()).is_err());
+    }
+
+    #[test]
+    fn hosts_and_services() {
+        let (client, server) = serve(
+            r#"{"dataset":[["2024-06-01T12:05:00.000000Z","s1","10.0.0.1","printer",4,100,200,2,0.5]]}"#,
+        );
+        let hosts = client.hosts(&since()).unwrap();
+        let query = server.join().unwrap();
+        assert!(query.starts_with("SELECT bucket, observ, host, host_id,"));
+        assert_eq!(hosts[0].host_id, "printer");
+        let host = &hosts[0];
+        assert_eq!((host.flows, host.peers, host.score), (4, 2, 0.5));
+
+        let (client, server) = serve(
+            r#"{"dataset":[["2024-06-01T12:05:00.000000Z","s1","10.0.0.0/24",443,"tls",9,900,4.5,2.1]]}"#,
+        );
+        let services = client.services(&since()).unwrap();
+        assert!(server.join().unwrap().contains("FROM service WHERE"));
+        assert_eq!(services[0].subnet, "10.0.0.0/24");
+        assert_eq!((services[0].dport, services[0].zscore), (443, 2.1));
+    }
+
+    #[test]
+    fn query_errors() {
+        let (client, server) = serve(r#"{"query":"SELECT","error":"table does not exist"}"#);
+        let result = client.query(&String::from("SELECT * FROM nope;"));
+        server.join().unwrap();
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("table does not exist"));
+        // a response without a dataset has no rows
+        let (client, server) = serve(r#"{"ddl":"OK"}"#);
+        let rows = client.query(&String::from("DROP TABLE x;")).unwrap();
+        assert!(rows.is_empty());
+        server.join().unwrap();
+    }
+}
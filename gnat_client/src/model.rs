@@ -0,0 +1,166 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Reader for model DuckDB files, such as the feature histograms gnat_hbos
+// trains. The file is opened read-only, so it can be inspected while the
+// model stage keeps it; its tables are described from the catalog and
+// read into rows of typed values by column name.
+//
+
+use duckdb::types::Value;
+use duckdb::{AccessMode, Config, Connection};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelColumn {
+    pub name: String,
+    pub sql_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelTable {
+    pub name: String,
+    pub columns: Vec<ModelColumn>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelRow {
+    pub values: Vec<(String, Value)>,
+}
+
+impl ModelRow {
+    pub fn get(&self, column: &str) -> Option<&Value> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == column)
+            .map(|(_, value)| value)
+    }
+}
+
+pub struct Model {
+    conn: Connection,
+}
+
+impl Model {
+    pub fn open(model_spec: &str) -> anyhow::Result<Model> {
+        let config = Config::default().access_mode(AccessMode::ReadOnly)?;
+        let conn = Connection::open_with_flags(model_spec, config)?;
+        Ok(Model { conn })
+    }
+
+    //
+    // Tables of the main schema with their columns, in catalog order
+    //
+    pub fn tables(&self) -> anyhow::Result<Vec<ModelTable>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT table_name, column_name, data_type FROM information_schema.columns
+             WHERE table_schema = 'main'
+             ORDER BY table_name, ordinal_position;",
+        )?;
+        let columns = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<duckdb::Result<Vec<(String, String, String)>>>()?;
+        let mut tables: Vec<ModelTable> = Vec::new();
+        for (table_name, name, sql_type) in columns {
+            if tables.last().map(|t| t.name != table_name).unwrap_or(true) {
+                tables.push(ModelTable {
+                    name: table_name,
+                    columns: Vec::new(),
+                });
+            }
+            if let Some(table) = tables.last_mut() {
+                table.columns.push(ModelColumn { name, sql_type });
+            }
+        }
+        Ok(tables)
+    }
+
+    //
+    // Rows of table_name, optionally filtered by a SQL predicate
+    //
+    pub fn read(&self, table_name: &str, filter: Option<&str>) -> anyhow::Result<Vec<ModelRow>> {
+        let table = match self.tables()?.into_iter().find(|t| t.name == table_name) {
+            Some(table) => table,
+            None => anyhow::bail!("model table {} not found", table_name),
+        };
+        let sql_command = format!(
+            "SELECT * FROM \"{}\" WHERE {};",
+            table.name,
+            filter.unwrap_or("true")
+        );
+        let mut stmt = self.conn.prepare(&sql_command)?;
+        let rows = stmt
+            .query_map([], |row| {
+                let mut values = Vec::with_capacity(table.columns.len());
+                for (index, column) in table.columns.iter().enumerate() {
+                    values.push((column.name.clone(), row.get::<_, Value>(index)?));
+                }
+                Ok(ModelRow { values })
+            })?
+            .collect::<duckdb::Result<Vec<ModelRow>>>()?;
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_model_tables() {
+        let file =
+            std::env::temp_dir().join(format!("gnat_client-{}-model.duckdb", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        {
+            let conn = Connection::open(&file).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE histogram (feature VARCHAR, bin INTEGER, density DOUBLE);
+                 INSERT INTO histogram VALUES ('sbytes', 0, 0.75), ('sbytes', 1, 0.25);
+                 CREATE TABLE meta (trained TIMESTAMP);",
+            )
+            .unwrap();
+        }
+
+        let model = Model::open(&file.to_string_lossy()).unwrap();
+        let tables = model.tables().unwrap();
+        assert_eq!(
+            tables
+                .iter()
+                .map(|t| t.name.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["histogram", "meta"]
+        );
+        assert_eq!(
+            tables[0].columns[2],
+            ModelColumn {
+                name: String::from("density"),
+                sql_type: String::from("DOUBLE"),
+            }
+        );
+
+        let rows = model.read("histogram", Some("bin = 1")).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].get("feature"),
+            Some(&Value::Text(String::from("sbytes")))
+        );
+        assert_eq!(rows[0].get("density"), Some(&Value::Double(0.25)));
+        assert!(model.read("missing", None).is_err());
+        // opened read-only
+        assert!(model.conn.execute_batch("DELETE FROM histogram;").is_err());
+
+        drop(model);
+        let _ = std::fs::remove_file(&file);
+    }
+}
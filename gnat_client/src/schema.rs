@@ -0,0 +1,36 @@
+/*
+ * Galileo Network Analytics (GNA) Toolkit
+ *
+ * Copyright 2024 Fidelis Farm & Technologies, LLC
+ * All Rights Reserved.
+ * See license information in LICENSE.
+ */
+
+//
+// Flow parquet schema and its migrations, the same source gnat's stages
+// read through (gnat/src/core/schema.rs); see select()
+//
+
+include!("../../gnat/src/core/flow_schema.rs");
+
+//
+// SELECT reading input_spec, one parquet file, upgraded to
+// FLOW_SCHEMA_VERSION; a file written by a newer toolkit can't be read
+//
+pub fn select(conn: &Connection, input_spec: &str) -> anyhow::Result<String> {
+    let from_version = version(conn, input_spec)?;
+    if from_version > FLOW_SCHEMA_VERSION {
+        anyhow::bail!(
+            "{}: schema version {} is newer than {}",
+            input_spec,
+            from_version,
+            FLOW_SCHEMA_VERSION
+        );
+    }
+    let select_list = upgraded_columns(conn, input_spec, from_version)?;
+    Ok(format!(
+        "SELECT {} FROM '{}'",
+        select_list.join(", "),
+        input_spec
+    ))
+}
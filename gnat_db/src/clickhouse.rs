@@ -0,0 +1,210 @@
+use crate::TableTrait;
+use std::fmt;
+use tracing::{error, info};
+
+//
+// ClickHouse backend over the HTTP interface. Tables that provide
+// clickhouse_columns()/clickhouse_query() are created as MergeTree tables
+// partitioned by day, with retention enforced by a TTL that drops whole
+// partitions; the DuckDB query results are inserted as JSONEachRow.
+//
+pub struct ClickHouse {
+    pub url: String,
+}
+
+//
+// A failed request; transient when ClickHouse couldn't be reached, timed
+// out or failed on its side (5xx), so the same request may succeed later.
+// Rejected requests (4xx: bad data, type mismatches) are not transient.
+//
+#[derive(Debug)]
+pub struct Error {
+    pub transient: bool,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ClickHouse {
+    pub fn new(host_spec: &String, http_port: u16) -> ClickHouse {
+        ClickHouse {
+            url: format!("http://{}:{}/", host_spec, http_port),
+        }
+    }
+
+    fn execute(&self, sql_command: &String, body: String) -> Result<(), Error> {
+        let client = reqwest::blocking::Client::new();
+        match client
+            .post(&self.url)
+            .query(&[("query", sql_command)])
+            .body(body)
+            .send()
+        {
+            Ok(r) => {
+                let status = r.status();
+                if status.is_success() {
+                    Ok(())
+                } else {
+                    Err(Error {
+                        transient: status.is_server_error(),
+                        message: format!("{} {}", status, r.text().unwrap_or_default()),
+                    })
+                }
+            }
+            Err(e) => Err(Error {
+                transient: e.is_connect() || e.is_timeout(),
+                message: format!("{:?}", e),
+            }),
+        }
+    }
+
+    pub fn create(&self, table: &dyn TableTrait, retention_days: u16) {
+        let Some(columns) = table.clickhouse_columns() else {
+            info!(
+                "Database importer: [{}] not supported by clickhouse backend",
+                table.table_name()
+            );
+            return;
+        };
+        let sql_create_table = format!(
+            "CREATE TABLE IF NOT EXISTS {}({})
+                ENGINE = MergeTree
+                PARTITION BY toYYYYMMDD(bucket)
+                ORDER BY (observ, bucket)
+                TTL bucket + INTERVAL {} DAY
+                SETTINGS ttl_only_drop_parts = 1;",
+            table.table_name(),
+            columns,
+            retention_days
+        );
+        match self.execute(&sql_create_table, String::new()) {
+            Ok(_) => info!("Database importer: verified [{}] table", table.table_name()),
+            Err(e) => panic!("Error: creating {} table - {}", table.table_name(), e),
+        };
+    }
+
+    pub fn insert(&self, table: &dyn TableTrait, source: &duckdb::Connection) -> anyhow::Result<()> {
+        let Some(query) = table.clickhouse_query() else {
+            return Ok(());
+        };
+        let sql_command = format!("SELECT to_json(q)::VARCHAR FROM ({}) q;", query);
+        let mut stmt = source.prepare(&sql_command)?;
+        let rows: Vec<String> = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let sql_insert = format!("INSERT INTO {} FORMAT JSONEachRow", table.table_name());
+        self.execute(&sql_insert, rows.join("\n"))
+            .map_err(|e| anyhow::Error::new(e).context(format!("inserting into {}", table.table_name())))?;
+        info!("Table [{}]: {} new records", table.table_name(), rows.len());
+        Ok(())
+    }
+
+    //
+    // apply the current retention; ClickHouse drops expired partitions itself
+    //
+    pub fn drop(&self, table: &dyn TableTrait, retention_days: u16) {
+        if table.clickhouse_columns().is_none() {
+            return;
+        }
+        let sql_modify_ttl = format!(
+            "ALTER TABLE {} MODIFY TTL bucket + INTERVAL {} DAY;",
+            table.table_name(),
+            retention_days
+        );
+        match self.execute(&sql_modify_ttl, String::new()) {
+            Ok(_) => info!(
+                "Database importer: retention {} days table [{}]",
+                retention_days,
+                table.table_name()
+            ),
+            Err(e) => error!("setting {} retention - {}", table.table_name(), e),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::flow::FlowTable;
+    use crate::table::host::HostTable;
+    use crate::{test_memtable, test_server};
+
+    const FLOW: FlowTable = FlowTable { table_name: "flow" };
+
+    #[test]
+    fn create_insert_and_retention() {
+        let (url, server) = test_server(3, |_| (200, String::new()));
+        let clickhouse = ClickHouse { url };
+        let source = test_memtable(
+            "SELECT * FROM (VALUES
+                ('s1', TIMESTAMP '2024-06-01 12:00:10'), ('s1', TIMESTAMP '2024-06-01 12:00:50'),
+                ('s2', TIMESTAMP '2024-06-01 12:01:00')) t(observ, stime)",
+        );
+        clickhouse.create(&FLOW, 30);
+        clickhouse.insert(&FLOW, &source).unwrap();
+        clickhouse.drop(&FLOW, 7);
+        // tables without ClickHouse columns send nothing
+        let hosts = HostTable {
+            table_name: "host",
+            identity_spec: String::new(),
+        };
+        clickhouse.create(&hosts, 30);
+        clickhouse.drop(&hosts, 7);
+        clickhouse.insert(&hosts, &source).unwrap();
+
+        let served = server.join().unwrap();
+        assert!(served[0]
+            .0
+            .starts_with("CREATE TABLE IF NOT EXISTS flow(bucket DateTime,"));
+        assert!(served[0].0.contains("TTL bucket + INTERVAL 30 DAY"));
+        assert_eq!(served[1].0, "INSERT INTO flow FORMAT JSONEachRow");
+        let mut rows: Vec<serde_json::Value> = served[1]
+            .1
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        rows.sort_by_key(|row| row["observ"].as_str().unwrap_or_default().to_string());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["observ"], "s1");
+        assert_eq!(rows[0]["count"], 2);
+        assert_eq!(
+            served[2].0,
+            "ALTER TABLE flow MODIFY TTL bucket + INTERVAL 7 DAY;"
+        );
+    }
+
+    #[test]
+    fn failures_are_transient_unless_rejected() {
+        let source = test_memtable("SELECT 's1' AS observ, TIMESTAMP '2024-06-01' AS stime");
+        let transient = |status: u16| {
+            let (url, server) = test_server(1, move |_| (status, String::from("nope")));
+            let error = ClickHouse { url }.insert(&FLOW, &source).unwrap_err();
+            // an error before the request would leave the server waiting
+            let transient = error.downcast_ref::<Error>().unwrap().transient;
+            server.join().unwrap();
+            transient
+        };
+        assert!(transient(503));
+        assert!(!transient(400));
+
+        // nothing listening
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let clickhouse = ClickHouse::new(&String::from("127.0.0.1"), port);
+        let error = clickhouse.insert(&FLOW, &source).unwrap_err();
+        assert!(error.downcast_ref::<Error>().unwrap().transient);
+    }
+}
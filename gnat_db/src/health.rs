@@ -0,0 +1,328 @@
+//
+// Liveness and readiness probes of the importer (--healthcheck-port <port>)
+//
+//   GET /live    200 while the importer loop makes progress, 503 once it
+//                has gone STALL_TIMEOUT without starting a file or waiting
+//   GET /ready   200 when the importer can work, 503 with the failed checks
+//
+// Readiness checks, on every request, that --input and --processed are
+// writable, that DuckDB opens, and that the database answers on its HTTP
+// port (with the QDB_USERNAME/QDB_PASSWORD credentials for QuestDB).
+// --validate true runs the same checks, plus the --annotations and
+// --identities files, once and exits with their report.
+//
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{error, info, warn};
+
+const STALL_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const REQUEST_LIMIT: usize = 8192;
+// a probe sends its request at once; a slow client only holds its own thread
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+// seconds since the epoch of the last progress; 0 until the first
+static LAST_BEAT: AtomicU64 = AtomicU64::new(0);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+//
+// Record progress of the importer loop
+//
+pub fn beat() {
+    LAST_BEAT.store(now(), Ordering::Relaxed);
+}
+
+fn live() -> bool {
+    let last = LAST_BEAT.load(Ordering::Relaxed);
+    last == 0 || now().saturating_sub(last) < STALL_TIMEOUT.as_secs()
+}
+
+fn check_path(path_spec: &str) -> Result<(), String> {
+    let path = Path::new(path_spec);
+    if path.is_file() {
+        return fs::File::open(path).map(|_| ()).map_err(|e| e.to_string());
+    }
+    if !path.is_dir() {
+        return Err(String::from("not a directory"));
+    }
+    let probe = path.join(format!(".gnat_db-ready-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|e| e.to_string())?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+fn check_duckdb() -> Result<(), String> {
+    let conn = duckdb::Connection::open_in_memory().map_err(|e| e.to_string())?;
+    conn.query_row("SELECT 1;", [], |row| row.get::<_, i32>(0))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn check_database(database_url: &str) -> Result<(), String> {
+    let response = reqwest::blocking::Client::new()
+        .get(database_url)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(response.status().to_string())
+    }
+}
+
+fn checks(paths: &[String], database_url: &str) -> Vec<(String, Result<(), String>)> {
+    let mut results: Vec<(String, Result<(), String>)> = paths
+        .iter()
+        .map(|path_spec| (format!("path {}", path_spec), check_path(path_spec)))
+        .collect();
+    results.push((String::from("duckdb"), check_duckdb()));
+    results.push((String::from("database"), check_database(database_url)));
+    results
+}
+
+fn ready(paths: &[String], database_url: &str) -> (bool, Vec<serde_json::Value>) {
+    let results = checks(paths, database_url);
+    let ok = results.iter().all(|(_, result)| result.is_ok());
+    let checks = results
+        .into_iter()
+        .map(|(check, result)| {
+            serde_json::json!({ "check": check, "ok": result.is_ok(), "error": result.err() })
+        })
+        .collect();
+    (ok, checks)
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        warn!("responding - {:?}", e);
+    }
+}
+
+//
+// Method and path of the request on stream; the headers are ignored
+//
+fn read_request(stream: &mut TcpStream) -> Option<(String, String)> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < REQUEST_LIMIT {
+        let count = stream.read(&mut buffer).ok()?;
+        if count == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..count]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.lines().next()?.split_whitespace();
+    let method = words.next()?.to_string();
+    let path = words.next()?.split('?').next()?.to_string();
+    Some((method, path))
+}
+
+fn handle(stream: &mut TcpStream, paths: &[String], database_url: &str) {
+    let Some((method, path)) = read_request(stream) else {
+        respond(stream, "400 Bad Request", r#"{"message":"malformed request"}"#);
+        return;
+    };
+    match (method.as_str(), path.as_str()) {
+        ("GET", "/live") => {
+            let body = serde_json::json!({ "stage": "gnat_db", "live": live() }).to_string();
+            if live() {
+                respond(stream, "200 OK", &body);
+            } else {
+                warn!("health: no progress for {:?}", STALL_TIMEOUT);
+                respond(stream, "503 Service Unavailable", &body);
+            }
+        }
+        ("GET", "/ready") => {
+            let (ok, checks) = ready(paths, database_url);
+            let body =
+                serde_json::json!({ "stage": "gnat_db", "ready": ok, "checks": checks }).to_string();
+            if ok {
+                respond(stream, "200 OK", &body);
+            } else {
+                respond(stream, "503 Service Unavailable", &body);
+            }
+        }
+        (_, "/live") | (_, "/ready") => {
+            respond(stream, "405 Method Not Allowed", r#"{"message":"method not allowed"}"#)
+        }
+        _ => respond(stream, "404 Not Found", r#"{"message":"not found"}"#),
+    }
+}
+
+//
+// Run the checks once and log them (--validate true); returns whether
+// all of them passed
+//
+pub fn validate(paths: &[&String], database_url: &str) -> bool {
+    let paths: Vec<String> = paths
+        .iter()
+        .filter(|path_spec| !path_spec.is_empty())
+        .map(|path_spec| (*path_spec).clone())
+        .collect();
+    let results = checks(&paths, database_url);
+    let mut failed = 0;
+    for (check, result) in results.iter() {
+        match result {
+            Ok(()) => info!("validate: ok {}", check),
+            Err(e) => {
+                error!("validate: failed {} - {}", check, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        error!("validate: gnat_db [{} of {} checks failed]", failed, results.len());
+    } else {
+        info!("validate: gnat_db [{} checks passed]", results.len());
+    }
+    failed == 0
+}
+
+//
+// Serve the probes on port from a background thread, checking the
+// non-empty paths and database_url for readiness; a port of 0 serves
+// nothing
+//
+pub fn serve(port: u16, paths: &[&String], database_url: String) -> Result<(), std::io::Error> {
+    if port == 0 {
+        return Ok(());
+    }
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    info!("healthcheck port: {}", port);
+    let paths: Vec<String> = paths
+        .iter()
+        .filter(|dir_spec| !dir_spec.is_empty())
+        .map(|dir_spec| (*dir_spec).clone())
+        .collect();
+    let paths = Arc::new(paths);
+    let database_url = Arc::new(database_url);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    let paths = Arc::clone(&paths);
+                    let database_url = Arc::clone(&database_url);
+                    thread::spawn(move || {
+                        let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                        handle(&mut stream, &paths, &database_url);
+                    });
+                }
+                Err(e) => warn!("health: accepting - {:?}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    // status line and body of the response to request
+    fn call(paths: &[String], database_url: &str, request: &str) -> (String, serde_json::Value) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        handle(&mut server, paths, database_url);
+        drop(server);
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    // a url nothing answers on
+    fn closed_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/", listener.local_addr().unwrap())
+    }
+
+    #[test]
+    fn check_paths() {
+        let dir = std::env::temp_dir().join(format!("gnat_db-{}-health", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("identities.csv");
+        fs::write(&file, b"").unwrap();
+        assert!(check_path(&dir.to_string_lossy()).is_ok());
+        assert!(check_path(&file.to_string_lossy()).is_ok());
+        assert_eq!(
+            check_path(&dir.join("missing").to_string_lossy()),
+            Err(String::from("not a directory"))
+        );
+        // the probe file is cleaned up
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(check_duckdb().is_ok());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn probes() {
+        let dir = std::env::temp_dir().to_string_lossy().to_string();
+        let missing = format!("{}/gnat_db-{}-health-missing", dir, std::process::id());
+
+        let (status, body) = call(&[], &closed_url(), "GET /live HTTP/1.1\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body["stage"], "gnat_db");
+
+        // ready once the database answers
+        let (database_url, server) = test_server(1, |_| (200, String::from("{}")));
+        let (status, body) = call(
+            std::slice::from_ref(&dir),
+            &database_url,
+            "GET /ready HTTP/1.1\r\n\r\n",
+        );
+        server.join().unwrap();
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body["checks"].as_array().unwrap().len(), 3);
+        let (database_url, server) = test_server(1, |_| (401, String::from("{}")));
+        let request = "GET /ready?verbose HTTP/1.1\r\n\r\n";
+        let (status, body) = call(&[dir, missing], &database_url, request);
+        server.join().unwrap();
+        assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+        assert_eq!(body["checks"][1]["error"], "not a directory");
+        assert_eq!(body["checks"][3]["check"], "database");
+        assert_eq!(body["checks"][3]["error"], "401 Unauthorized");
+
+        let (status, _) = call(&[], &closed_url(), "POST /ready HTTP/1.1\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+        let (status, _) = call(&[], &closed_url(), "GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        let (status, _) = call(&[], &closed_url(), "\r\n\r\n");
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn validate_skips_unset_paths() {
+        let dir = std::env::temp_dir().to_string_lossy().to_string();
+        let unset = String::new();
+        let (database_url, server) = test_server(1, |_| (200, String::from("{}")));
+        assert!(validate(&[&dir, &unset], &database_url));
+        server.join().unwrap();
+        // the database not answering fails validation
+        assert!(!validate(&[&dir], &closed_url()));
+    }
+}
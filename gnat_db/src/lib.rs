@@ -1,4 +1,12 @@
+pub mod clickhouse;
+pub mod health;
+pub mod logging;
+pub mod rollup;
+pub mod shutdown;
+pub mod window;
+
 pub mod table {
+    pub mod annotation;
     pub mod appid;
     pub mod asn;
     pub mod bytes;
@@ -6,17 +14,223 @@ pub mod table {
     pub mod dns;
     pub mod doh;
     pub mod flow;
+    pub mod host;
+    pub mod http;
     pub mod ip;    
     pub mod packets;
     pub mod proto;
     pub mod quic;
+    pub mod service;
     pub mod ssh;
+    pub mod traffic;
+}
+
+use tracing::{error, info, warn};
+
+//
+// SQL predicate matching internal (RFC1918 / IPv6 ULA) addresses,
+// mirroring IsPrivateAddress() used by the import stage
+//
+pub fn internal_address(column: &str) -> String {
+    format!(
+        "(starts_with({0}, '10.') OR starts_with({0}, '192.168.') OR regexp_matches({0}, '^172\\.(1[6-9]|2[0-9]|3[01])\\.') OR regexp_matches({0}, '^f[cd][0-9a-f]{{2}}:'))",
+        column
+    )
+}
+
+//
+// A record read from a DuckDB row by column name rather than position, so
+// a query's select list can be reordered or extended without breaking its
+// reader; implement it with from_row!
+//
+pub trait FromRow: Sized {
+    fn from_row(row: &duckdb::Row) -> duckdb::Result<Self>;
+}
+
+//
+// Implement FromRow for a record each of whose fields is read from the
+// column of the same name, e.g. from_row!(FlowRecord { bucket, observ, count })
+//
+#[macro_export]
+macro_rules! from_row {
+    ($record:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::FromRow for $record {
+            fn from_row(row: &duckdb::Row) -> duckdb::Result<$record> {
+                Ok($record {
+                    $($field: row.get(stringify!($field))?,)*
+                })
+            }
+        }
+    };
+}
+
+//
+// Column definitions of a CREATE TABLE statement, as (name, definition)
+//
+fn column_definitions(sql_create_table: &str) -> Vec<(String, String)> {
+    let Some(start) = sql_create_table.find('(') else {
+        return Vec::new();
+    };
+    let mut depth = 0;
+    let mut end = sql_create_table.len();
+    for (i, c) in sql_create_table[start..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    end = start + i;
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    //
+    // split at the top-level commas only, e.g. not within DECIMAL(10, 2)
+    //
+    let mut definitions = Vec::new();
+    let mut depth = 0;
+    let mut from = start + 1;
+    for (i, c) in sql_create_table[start + 1..end].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                definitions.push(&sql_create_table[from..start + 1 + i]);
+                from = start + 2 + i;
+            }
+            _ => {}
+        }
+    }
+    definitions.push(&sql_create_table[from..end]);
+    definitions
+        .into_iter()
+        .filter_map(|definition| {
+            let definition = definition.split_whitespace().collect::<Vec<&str>>().join(" ");
+            let name = definition.split(' ').next()?.to_string();
+            Some((name, definition))
+        })
+        .collect()
+}
+
+//
+// (name, type) of the columns of table_name, from SHOW COLUMNS
+//
+fn table_columns(api_url: &str, table_name: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let rows = query(api_url, &format!("SHOW COLUMNS FROM {};", table_name))?;
+    let columns = rows
+        .iter()
+        .filter_map(|row| {
+            Some((
+                row.get(0)?.as_str()?.to_string(),
+                row.get(1)?.as_str()?.to_string(),
+            ))
+        })
+        .collect();
+    Ok(columns)
+}
+
+//
+// Upgrade table_name in place to the columns of sql_create_table: CREATE
+// TABLE IF NOT EXISTS leaves a table created by an older release as it
+// was, so the columns it lacks are added with ALTER TABLE ADD COLUMN. The
+// types of the existing columns are the version check: a column whose
+// type changed can't be altered in place, and is reported instead.
+// Partitioning, WAL and DEDUP keys are not migrated.
+//
+pub fn migrate(api_url: &str, table_name: &str, sql_create_table: &str) {
+    let existing = match table_columns(api_url, table_name) {
+        Ok(columns) => columns,
+        Err(e) => {
+            error!("describing {} - {:?}", table_name, e);
+            return;
+        }
+    };
+    for (name, definition) in column_definitions(sql_create_table) {
+        let expected_type = definition
+            .split(' ')
+            .nth(1)
+            .unwrap_or_default()
+            .to_uppercase();
+        match existing.iter().find(|(column, _)| *column == name) {
+            Some((_, column_type)) if column_type.to_uppercase() != expected_type => {
+                warn!(
+                    "table {}: column {} is {}, expected {}; drop or rename the table to upgrade it",
+                    table_name, name, column_type, expected_type
+                );
+            }
+            Some(_) => {}
+            None => {
+                let sql_alter_table = format!("ALTER TABLE {} ADD COLUMN {};", table_name, definition);
+                let url = url::Url::parse_with_params(api_url, &[("query", sql_alter_table)])
+                    .expect("invalid url params");
+                match reqwest::blocking::get(url) {
+                    Ok(r) if r.status().is_success() => {
+                        info!("Database importer: migrated [{}] table: added {}", table_name, name)
+                    }
+                    Ok(r) => error!("adding {}.{} - {:?}", table_name, name, r.status()),
+                    Err(e) => error!("adding {}.{} - {:?}", table_name, name, e),
+                }
+            }
+        }
+    }
+}
+
+//
+// Enable DEDUP on a table created by an older release without it; it
+// needs a WAL table, so one created before WAL is only reported
+//
+pub fn enable_dedup(api_url: &str, table_name: &str, keys: &str) {
+    let sql_alter_table = format!("ALTER TABLE {} DEDUP ENABLE UPSERT KEYS({});", table_name, keys);
+    let url = url::Url::parse_with_params(api_url, &[("query", sql_alter_table)])
+        .expect("invalid url params");
+    match reqwest::blocking::get(url) {
+        Ok(r) if r.status().is_success() => {}
+        Ok(r) => warn!(
+            "table {}: enabling DEDUP - {:?}; drop or rename the table to upgrade it",
+            table_name,
+            r.status()
+        ),
+        Err(e) => error!("enabling DEDUP on {} - {:?}", table_name, e),
+    }
+}
+
+//
+// Rows of a query of the QuestDB REST API
+//
+pub fn query(api_url: &str, sql_command: &str) -> anyhow::Result<Vec<serde_json::Value>> {
+    let url = url::Url::parse_with_params(api_url, &[("query", sql_command)])?;
+    let response: serde_json::Value = serde_json::from_str(&reqwest::blocking::get(url)?.text()?)?;
+    if let Some(e) = response.get("error") {
+        return Err(anyhow::anyhow!("{}", e));
+    }
+    Ok(response["dataset"].as_array().cloned().unwrap_or_default())
 }
 
 pub trait TableTrait {
     fn table_name(&self) -> &'static str;
+    // memtable columns read by create/insert; only these are loaded per batch
+    fn columns(&self) -> &'static [&'static str];
+    // ClickHouse column definitions and the DuckDB query producing them;
+    // tables without these are QuestDB only
+    fn clickhouse_columns(&self) -> Option<&'static str> {
+        None
+    }
+    fn clickhouse_query(&self) -> Option<&'static str> {
+        None
+    }
     fn create(&self, api_url: &String);
-    fn insert(&self, sink: &mut questdb::ingress::Sender, source: &duckdb::Connection);
+    // add the columns of sql_create_table missing from a table created by
+    // an older release; called by create()
+    fn migrate(&self, api_url: &str, sql_create_table: &str) {
+        migrate(api_url, self.table_name(), sql_create_table);
+    }
+    fn insert(
+        &self,
+        sink: &mut questdb::ingress::Sender,
+        source: &duckdb::Connection,
+    ) -> anyhow::Result<()>;
 
     fn drop(&self, api_url: &String, retention_days: u16) {
         let sql_drop_partition = format!(
@@ -29,11 +243,11 @@ pub trait TableTrait {
 
     
         match reqwest::blocking::get(drop_url) {
-            Ok(_r) => println!(
+            Ok(_r) => info!(
                 "Database importer: dropped partition table [{:?}]",
                 self.table_name()
             ),
-            Err(_e) => panic!("Error: dropping {:?} partition(s)", self.table_name()),
+            Err(e) => error!("dropping {:?} partition(s) - {:?}", self.table_name(), e),
         };
 
         let sql_vacuum_table = format!("VACUUM TABLE {:?};", self.table_name());
@@ -41,11 +255,202 @@ pub trait TableTrait {
             .expect("invalid url");
 
         match reqwest::blocking::get(vacuum_url) {
-            Ok(_r) => println!(
+            Ok(_r) => info!(
                 "Database importer: vacuumed table [{:?}]",
                 self.table_name()
             ),
-            Err(_e) => panic!("Error: vacumming table)"),
+            Err(e) => error!("vacuuming table {:?} - {:?}", self.table_name(), e),
         };
     }
 }
+
+//
+// A memtable of the rows of select, as load_file() leaves it
+//
+#[cfg(test)]
+pub(crate) fn test_memtable(select: &str) -> duckdb::Connection {
+    let source = duckdb::Connection::open_in_memory().unwrap();
+    source
+        .execute_batch(&format!("CREATE TABLE memtable AS {};", select))
+        .unwrap();
+    source
+}
+
+//
+// The ILP lines table.insert() sends, read by a local listener standing
+// in for QuestDB
+//
+#[cfg(test)]
+pub(crate) fn test_insert(table: &dyn TableTrait, source: &duckdb::Connection) -> Vec<String> {
+    use std::io::Read;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let conf = format!("tcp::addr={};", listener.local_addr().unwrap());
+    let reader = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut text = String::new();
+        stream.read_to_string(&mut text).unwrap();
+        text
+    });
+    let mut sink = questdb::ingress::Sender::from_conf(conf).unwrap();
+    table.insert(&mut sink, source).unwrap();
+    drop(sink);
+    reader.join().unwrap().lines().map(String::from).collect()
+}
+
+//
+// A local HTTP server standing in for the database API: answers the next
+// requests with respond(query parameter) as (status, body), and returns
+// their (query parameter, body) when joined
+//
+#[cfg(test)]
+pub(crate) fn test_server(
+    requests: usize,
+    respond: impl Fn(&str) -> (u16, String) + Send + 'static,
+) -> (String, std::thread::JoinHandle<Vec<(String, String)>>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut served = Vec::new();
+        for _ in 0..requests {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let target = line.split_whitespace().nth(1).unwrap_or("/").to_string();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+            let query = url::Url::parse(&format!("http://localhost{}", target))
+                .unwrap()
+                .query_pairs()
+                .find(|(name, _)| name == "query")
+                .map(|(_, value)| value.into_owned())
+                .unwrap_or_default();
+            let (status, text) = respond(&query);
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 {} -\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                text.len(),
+                text
+            )
+            .unwrap();
+            served.push((query, String::from_utf8_lossy(&body).into_owned()));
+        }
+        served
+    });
+    (url, server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct CountRecord {
+        observ: String,
+        count: i64,
+    }
+
+    from_row!(CountRecord { observ, count });
+
+    #[test]
+    fn from_row_reads_by_name() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT 3::BIGINT AS count, 'gnat' AS observ, 'unused' AS extra;")
+            .unwrap();
+        let records = stmt
+            .query_map([], CountRecord::from_row)
+            .unwrap()
+            .collect::<duckdb::Result<Vec<CountRecord>>>()
+            .unwrap();
+        assert_eq!(
+            records,
+            vec![CountRecord {
+                observ: String::from("gnat"),
+                count: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn from_row_needs_every_column() {
+        let conn = duckdb::Connection::open_in_memory().unwrap();
+        let mut stmt = conn.prepare("SELECT 'gnat' AS observ;").unwrap();
+        let mut rows = stmt.query_map([], CountRecord::from_row).unwrap();
+        assert!(rows.next().unwrap().is_err());
+    }
+
+    const CREATE: &str = "CREATE TABLE IF NOT EXISTS t(
+            bucket TIMESTAMP,
+            observ SYMBOL CAPACITY 64 INDEX,
+            score DOUBLE,
+            price DECIMAL(10, 2),
+            count LONG)
+            TIMESTAMP(bucket) PARTITION BY HOUR;";
+
+    #[test]
+    fn column_definitions_split_at_depth_0() {
+        let definitions = column_definitions(CREATE);
+        assert_eq!(
+            definitions,
+            vec![
+                (String::from("bucket"), String::from("bucket TIMESTAMP")),
+                (
+                    String::from("observ"),
+                    String::from("observ SYMBOL CAPACITY 64 INDEX")
+                ),
+                (String::from("score"), String::from("score DOUBLE")),
+                (String::from("price"), String::from("price DECIMAL(10, 2)")),
+                (String::from("count"), String::from("count LONG")),
+            ]
+        );
+        assert!(column_definitions("SELECT 1;").is_empty());
+    }
+
+    #[test]
+    fn migrate_adds_missing_columns() {
+        // a table from an older release: price and count are missing and
+        // score has another type
+        let (api_url, server) = test_server(3, |query| {
+            if query.starts_with("SHOW COLUMNS") {
+                let dataset =
+                    r#"[["bucket", "TIMESTAMP"], ["observ", "SYMBOL"], ["score", "FLOAT"]]"#;
+                (200, format!(r#"{{"dataset": {}}}"#, dataset))
+            } else {
+                (200, String::from("{}"))
+            }
+        });
+        migrate(&api_url, "t", CREATE);
+        let queries: Vec<String> = server
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|(query, _)| query)
+            .collect();
+        // the changed type is only reported
+        assert_eq!(
+            queries,
+            vec![
+                String::from("SHOW COLUMNS FROM t;"),
+                String::from("ALTER TABLE t ADD COLUMN price DECIMAL(10, 2);"),
+                String::from("ALTER TABLE t ADD COLUMN count LONG;"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//
+// Structured logging for the importer
+//
+// GNAT_LOG sets levels per module in EnvFilter syntax (default "info"),
+// e.g. GNAT_LOG=info,gnat_db::rollup=debug; GNAT_LOG_FORMAT=json emits
+// one JSON object per event instead of text. Events are written to stderr
+// and carry the stage span (command name) and, while a file is being
+// processed, the batch span identifying that file.
+//
+
+use crate::health;
+
+use tracing::span::EnteredSpan;
+use tracing_subscriber::EnvFilter;
+
+//
+// Install the subscriber and enter the stage span; bind the result for the
+// lifetime of main()
+//
+pub fn init(stage: &'static str) -> EnteredSpan {
+    let filter = EnvFilter::try_from_env("GNAT_LOG").unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if std::env::var("GNAT_LOG_FORMAT").is_ok_and(|f| f == "json") {
+        builder
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .init();
+    } else {
+        builder.init();
+    }
+    tracing::info_span!("stage", name = stage).entered()
+}
+
+//
+// Span for one unit of work (an input file or a merged batch); starting
+// one is also the progress the liveness probe looks for
+//
+pub fn batch(id: &str) -> EnteredSpan {
+    health::beat();
+    tracing::info_span!("batch", id = id).entered()
+}
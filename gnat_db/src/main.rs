@@ -8,15 +8,23 @@
 use clap::Parser;
 
 use chrono::offset::Utc;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 use std::fs;
 use std::path::Path;
-use std::thread;
 use std::time::Duration;
 
 use duckdb::Connection;
 use questdb::ingress::Sender;
+use questdb::ErrorCode;
 
+use gnat_db::clickhouse::{self, ClickHouse};
+use gnat_db::health;
+use gnat_db::logging;
+use gnat_db::rollup::ROLLUPS;
+use gnat_db::shutdown;
+use gnat_db::table::annotation::{exclude_annotated, AnnotationTable};
 use gnat_db::table::appid::AppIdTable;
 use gnat_db::table::asn::AsnTable;
 use gnat_db::table::bytes::BytesTable;
@@ -24,12 +32,17 @@ use gnat_db::table::country::CountryTable;
 use gnat_db::table::dns::DnsTable;
 use gnat_db::table::doh::DohTable;
 use gnat_db::table::flow::FlowTable;
+use gnat_db::table::host::HostTable;
+use gnat_db::table::http::HttpTable;
 use gnat_db::table::ip::IpTable;
 use gnat_db::table::packets::PacketsTable;
 use gnat_db::table::proto::ProtoTable;
 use gnat_db::table::ssh::SshTable;
 use gnat_db::table::quic::QuicTable;
+use gnat_db::table::service::ServiceTable;
+use gnat_db::table::traffic::TrafficTable;
 use gnat_db::TableTrait;
+use tracing::{error, info, warn};
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -52,44 +65,328 @@ struct Args {
     #[arg(long)]
     retention: Option<u16>,
 
+    #[arg(long)]
+    retention_5m: Option<u16>,
+
+    #[arg(long)]
+    retention_1h: Option<u16>,
+
+    #[arg(long)]
+    retention_1d: Option<u16>,
+
     #[arg(long)]
     processed: Option<String>,
 
     #[arg(long)]
     tables: Option<String>,
+
+    #[arg(long)]
+    annotations: Option<String>,
+
+    #[arg(long)]
+    identities: Option<String>,
+
+    /// questdb | clickhouse
+    #[arg(long)]
+    backend: Option<String>,
+
+    /// QuestDB ILP transport: tcp | tcps | http | https
+    #[arg(long)]
+    protocol: Option<String>,
+
+    /// verify the QuestDB TLS certificate
+    #[arg(long)]
+    tls_verify: Option<bool>,
+
+    /// PEM file of CA certificates for QuestDB TLS
+    #[arg(long)]
+    tls_roots: Option<String>,
+
+    /// insert attempts per file after the first, with exponential backoff
+    #[arg(long)]
+    retries: Option<u32>,
+
+    /// port serving the /live and /ready probes (0 = none)
+    #[arg(long)]
+    healthcheck_port: Option<u16>,
+
+    /// check the options, directories, files and database, then exit without importing
+    #[arg(long)]
+    validate: Option<bool>,
 }
 
-fn questdb_insert(
-    polling_interval: u64,
-    input_spec: &String,
+//
+// QuestDB ILP configuration string. Credentials come from the environment
+// so they stay out of process listings: QDB_USERNAME/QDB_PASSWORD or
+// QDB_TOKEN for http(s), QDB_USERNAME with QDB_TOKEN/QDB_TOKEN_X/QDB_TOKEN_Y
+// (ECDSA key) for tcp(s). The keys of the other transport are left out,
+// since the sender rejects them and QDB_PASSWORD may be set for the REST
+// API alone.
+//
+fn questdb_conf(
+    protocol_spec: &String,
+    host_spec: &String,
+    ilp_port: u16,
+    tls_verify: bool,
+    tls_roots_spec: &String,
+) -> String {
+    let mut conf = format!("{}::addr={}:{};", protocol_spec, host_spec, ilp_port);
+    let http = protocol_spec.starts_with("http");
+    for (variable, key, used) in [
+        ("QDB_USERNAME", "username", true),
+        ("QDB_PASSWORD", "password", http),
+        ("QDB_TOKEN", "token", true),
+        ("QDB_TOKEN_X", "token_x", !http),
+        ("QDB_TOKEN_Y", "token_y", !http),
+    ] {
+        if !used {
+            continue;
+        }
+        if let Ok(value) = env::var(variable) {
+            if !value.is_empty() {
+                conf.push_str(&format!("{}={};", key, value.replace(';', ";;")));
+            }
+        }
+    }
+    if protocol_spec.ends_with('s') {
+        if !tls_verify {
+            conf.push_str("tls_verify=unsafe_off;");
+        }
+        if !tls_roots_spec.is_empty() {
+            conf.push_str(&format!("tls_ca=pem_file;tls_roots={};", tls_roots_spec));
+        }
+    }
+    conf
+}
+
+//
+// URL answering once the database is up, for the readiness checks
+//
+fn database_url(backend_spec: &str, api_url: &url::Url, clickhouse: &ClickHouse) -> String {
+    if backend_spec == "questdb" {
+        let mut url = api_url.clone();
+        url.query_pairs_mut().append_pair("query", "SELECT 1;");
+        url.to_string()
+    } else {
+        format!("{}ping", clickhouse.url)
+    }
+}
+
+//
+// URL of the QuestDB REST endpoint used for DDL; https with TLS transports,
+// and basic auth from QDB_USERNAME/QDB_PASSWORD when set
+//
+fn questdb_api_url(
+    protocol_spec: &str,
     host_spec: &String,
+    api_port: u16,
+) -> Result<url::Url, url::ParseError> {
+    let scheme = if protocol_spec.ends_with('s') { "https" } else { "http" };
+    let mut api_url = url::Url::parse(&format!("{}://{}:{}/exec", scheme, host_spec, api_port))?;
+    if let Ok(username) = env::var("QDB_USERNAME") {
+        if !username.is_empty() && env::var("QDB_PASSWORD").is_ok() {
+            let _ = api_url.set_username(&username);
+            let _ = api_url.set_password(env::var("QDB_PASSWORD").ok().as_deref());
+        }
+    }
+    Ok(api_url)
+}
+
+//
+// Load one spool file into an in-memory memtable, projected to the columns
+// the tables read and with annotated intervals removed. Columns missing from
+// files of older schema versions load as NULL.
+//
+fn load_file(
+    tmp_filename: &String,
+    projection: &[&str],
+    annotation_spec: &String,
+) -> anyhow::Result<Connection> {
+    let source = Connection::open_in_memory()?;
+    let present = {
+        let mut stmt = source.prepare(&format!("DESCRIBE SELECT * FROM '{}';", tmp_filename))?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect::<Result<BTreeSet<String>, _>>()?
+    };
+    let select_list = projection
+        .iter()
+        .map(|column| {
+            if present.contains(*column) {
+                String::from(*column)
+            } else {
+                format!("NULL AS {}", column)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    let sql_command = format!(
+        "CREATE TABLE memtable AS SELECT {} FROM '{}';",
+        select_list, tmp_filename
+    );
+    source.execute_batch(&sql_command)?;
+    //
+    // skip flows within excluded annotation intervals
+    //
+    exclude_annotated(&source, annotation_spec);
+    Ok(source)
+}
+
+fn insert_tables<'a>(
+    table_list: &[&'a dyn TableTrait],
+    sink: &mut Option<Sender>,
+    clickhouse: &ClickHouse,
+    source: &Connection,
+    done: &mut BTreeSet<&'a str>,
+) -> anyhow::Result<()> {
+    for table in table_list.iter() {
+        if done.contains(table.table_name()) {
+            continue;
+        }
+        match sink.as_mut() {
+            Some(s) => table.insert(s, source)?,
+            None => clickhouse.insert(*table, source)?,
+        }
+        done.insert(table.table_name());
+    }
+    Ok(())
+}
+
+//
+// Connection-level failures (and ClickHouse 5xx) leave the file for a later
+// scan; anything else (bad data, rejected rows) is specific to the file
+//
+fn transient(e: &anyhow::Error) -> bool {
+    if let Some(e) = e.downcast_ref::<questdb::Error>() {
+        return matches!(
+            e.code(),
+            ErrorCode::CouldNotResolveAddr | ErrorCode::SocketError | ErrorCode::TlsError
+        );
+    }
+    e.downcast_ref::<clickhouse::Error>().is_some_and(|e| e.transient)
+}
+
+//
+// Tables of a file handed back to the spool that were already written, kept
+// in a hidden sidecar so the next scan doesn't write them again
+//
+fn done_spec(filename: &str) -> String {
+    format!(".gnat_db-done-{}", filename)
+}
+
+fn load_done<'a>(filename: &str, table_list: &[&'a dyn TableTrait]) -> BTreeSet<&'a str> {
+    let written = fs::read_to_string(done_spec(filename)).unwrap_or_default();
+    table_list
+        .iter()
+        .map(|table| table.table_name())
+        .filter(|name| written.lines().any(|line| line == *name))
+        .collect()
+}
+
+fn save_done(filename: &str, done: &BTreeSet<&str>) {
+    let written: Vec<&str> = done.iter().copied().collect();
+    if let Err(e) = fs::write(done_spec(filename), written.join("\n")) {
+        error!("recording the tables written from {} - {:?}", filename, e);
+    }
+}
+
+//
+// Set a file that can't be imported aside as .err so it isn't rescanned
+//
+fn reject(tmp_filename: &String, filename: &String, processed_spec: &String) {
+    let error_path = if processed_spec.is_empty() {
+        format!("{}.err", filename)
+    } else {
+        format!("{}/{}.err", processed_spec, filename)
+    };
+    match fs::rename(tmp_filename, &error_path) {
+        Ok(_) => warn!("Database importer: {} moved to {}", filename, error_path),
+        Err(e) => error!("moving {} -> {}: {:?}", tmp_filename, error_path, e),
+    }
+}
+
+//
+// Options of the importer, as parsed and checked by main()
+//
+struct ImportConfig {
+    polling_interval: u64,
+    input_spec: String,
+    host_spec: String,
     ilp_port: u16,
     api_port: u16,
-    processed_spec: &String,
+    api_url: url::Url,
+    processed_spec: String,
     retention_days: u16,
-    table_spec: &String,
-) {
-    println!("\tinput spec: {}", input_spec);
-    println!("\tprocessed spec: {}", processed_spec);
-    println!("\tdb spec: {}", host_spec);
-    println!("\tilp port: {}", ilp_port);
-    println!("\tapi port: {}", api_port);
-    println!("\tretention days: {}", retention_days);
-    println!("\tpolling interval: {}", polling_interval);
-    println!("\ttable spec: {}", table_spec);
+    retention_5m_days: u16,
+    retention_1h_days: u16,
+    retention_1d_days: u16,
+    table_spec: String,
+    annotation_spec: String,
+    identity_spec: String,
+    backend_spec: String,
+    protocol_spec: String,
+    tls_verify: bool,
+    tls_roots_spec: String,
+    retries: u32,
+    healthcheck_port: u16,
+}
+
+fn questdb_insert(config: &ImportConfig) {
+    let ImportConfig {
+        polling_interval,
+        ref input_spec,
+        ref host_spec,
+        ilp_port,
+        api_port,
+        ref api_url,
+        ref processed_spec,
+        retention_days,
+        retention_5m_days,
+        retention_1h_days,
+        retention_1d_days,
+        ref table_spec,
+        ref annotation_spec,
+        ref identity_spec,
+        ref backend_spec,
+        ref protocol_spec,
+        tls_verify,
+        ref tls_roots_spec,
+        retries,
+        healthcheck_port,
+    } = *config;
+    info!("input spec: {}", input_spec);
+    info!("processed spec: {}", processed_spec);
+    info!("backend: {}", backend_spec);
+    info!("db spec: {}", host_spec);
+    info!("protocol: {}", protocol_spec);
+    info!("tls verify: {}", tls_verify);
+    info!("tls roots spec: {}", tls_roots_spec);
+    info!("retries: {}", retries);
+    info!("ilp port: {}", ilp_port);
+    info!("api port: {}", api_port);
+    info!("retention days: {}", retention_days);
+    info!("retention 5m days: {}", retention_5m_days);
+    info!("retention 1h days: {}", retention_1h_days);
+    info!("retention 1d days: {}", retention_1d_days);
+    info!("polling interval: {}", polling_interval);
+    info!("table spec: {}", table_spec);
+    info!("annotation spec: {}", annotation_spec);
+    info!("identity spec: {}", identity_spec);
     //
     // change working directory
     //
     let input_dir = Path::new(input_spec.as_str());
-    if !env::set_current_dir(&input_dir).is_ok() {
-        panic!(
-            "Error: unable to set working directory to {}",
-            input_dir.display()
-        );
+    if let Err(e) = env::set_current_dir(input_dir) {
+        error!("setting working directory to {} - {:?}", input_dir.display(), e);
+        std::process::exit(exitcode::CONFIG)
     }
     //
     // instantiate and load table objects
     //
+    let annotation: AnnotationTable = AnnotationTable {
+        table_name: "annotation",
+        annotation_spec: annotation_spec.clone(),
+        last_modified: Cell::new(None),
+    };
     let appid: AppIdTable = AppIdTable {
         table_name: "appid",
     };
@@ -103,6 +400,13 @@ fn questdb_insert(
     let dns: DnsTable = DnsTable { table_name: "dns" };
     let doh: DohTable = DohTable { table_name: "doh" };    
     let flow: FlowTable = FlowTable { table_name: "flow" };
+    let host: HostTable = HostTable {
+        table_name: "host",
+        identity_spec: identity_spec.clone(),
+    };
+    let http: HttpTable = HttpTable {
+        table_name: "http",
+    };
     let ip: IpTable = IpTable {
         table_name: "ip",
     };        
@@ -115,10 +419,18 @@ fn questdb_insert(
     let quic: QuicTable = QuicTable {
         table_name: "quic",
     };    
+    let service: ServiceTable = ServiceTable {
+        table_name: "service",
+        baseline: RefCell::new(HashMap::new()),
+    };
     let ssh: SshTable = SshTable {
         table_name: "ssh",
     };
+    let traffic: TrafficTable = TrafficTable {
+        table_name: "traffic",
+    };
     let mut table_list: Vec<&dyn TableTrait> = Vec::new();
+    table_list.push(&annotation);
     table_list.push(&appid);
     table_list.push(&asn);
     table_list.push(&bytes);
@@ -126,34 +438,111 @@ fn questdb_insert(
     table_list.push(&dns);
     table_list.push(&doh);    
     table_list.push(&flow);
+    table_list.push(&host);
+    table_list.push(&http);
     table_list.push(&ip);    
     table_list.push(&packets);
     table_list.push(&proto);
+    table_list.push(&service);
     table_list.push(&ssh);
     table_list.push(&quic);    
+    table_list.push(&traffic);
     //
-    // instantiate questdb connection
+    // keep only the tables selected with --tables
     //
-    let api_url = format!("http://{}:{}/exec", host_spec, api_port);
-    let Ok(mut sink) = Sender::from_conf(format!("tcp::addr={}:{};", host_spec, ilp_port)) else {
-        panic!("Error: connecting to QuestDB");
-    };
+    if table_spec != "all" {
+        let selected: Vec<&str> = table_spec.split(',').map(|t| t.trim()).collect();
+        for name in selected.iter() {
+            if !table_list.iter().any(|table| table.table_name() == *name) {
+                let names: Vec<&str> = table_list.iter().map(|table| table.table_name()).collect();
+                error!("invalid --tables entry {} [all|{}]", name, names.join(","));
+                std::process::exit(exitcode::CONFIG)
+            }
+        }
+        table_list.retain(|table| selected.contains(&table.table_name()));
+    }
+    //
+    // the tables computed from QuestDB queries (annotation, host, service)
+    // have no ClickHouse schema
+    //
+    if backend_spec == "clickhouse" {
+        let questdb_only: Vec<&str> = table_list
+            .iter()
+            .filter(|table| table.clickhouse_columns().is_none())
+            .map(|table| table.table_name())
+            .collect();
+        if table_spec != "all" && !questdb_only.is_empty() {
+            error!("--tables {} is not supported by --backend clickhouse", questdb_only.join(","));
+            std::process::exit(exitcode::CONFIG)
+        }
+        if !questdb_only.is_empty() {
+            info!("tables skipped with --backend clickhouse: {}", questdb_only.join(", "));
+        }
+        table_list.retain(|table| table.clickhouse_columns().is_some());
+    }
+    let rollup_list: Vec<_> = ROLLUPS
+        .iter()
+        .filter(|rollup| table_list.iter().any(|table| table.table_name() == rollup.table_name))
+        .collect();
+    //
+    // instantiate database connection
+    //
+    let clickhouse = ClickHouse::new(host_spec, api_port);
+    let conf = questdb_conf(protocol_spec, host_spec, ilp_port, tls_verify, tls_roots_spec);
+    let database_url = database_url(backend_spec, api_url, &clickhouse);
+    let api_url = api_url.to_string();
+    if let Err(e) = health::serve(healthcheck_port, &[input_spec, processed_spec], database_url) {
+        error!("--healthcheck-port {} - {}", healthcheck_port, e);
+        std::process::exit(exitcode::CONFIG)
+    }
+    let mut sink: Option<Sender> = None;
+    if backend_spec == "questdb" {
+        match Sender::from_conf(&conf) {
+            Ok(s) => sink = Some(s),
+            Err(e) => {
+                error!("connecting to QuestDB - {}", e);
+                std::process::exit(exitcode::UNAVAILABLE)
+            }
+        };
+    }
 
     //
     // CREATE tables if they don't exist
     //
-    for table in table_list.iter() {
-        table.create(&api_url);
+    if sink.is_some() {
+        for table in table_list.iter() {
+            table.create(&api_url);
+        }
+        for rollup in rollup_list.iter() {
+            rollup.create(&api_url);
+        }
+    } else {
+        for table in table_list.iter() {
+            clickhouse.create(*table, retention_days);
+        }
     }
 
+    //
+    // project only the columns the tables read
+    //
+    let projection = table_list
+        .iter()
+        .flat_map(|table| table.columns().iter().copied())
+        .collect::<BTreeSet<&str>>()
+        .into_iter()
+        .collect::<Vec<&str>>();
+    info!("projection: {}", projection.join(", "));
+
     let mut last = Utc::now();
+    // None until the first pass, which backfills the rollups on startup
+    let mut last_rollup = None;
     let sleep_interval = Duration::from_secs(polling_interval);
-    println!("Database importer: running [{}]", input_spec);
+    info!("Database importer: running [{}]", input_spec);
     loop {
         //
         // is it time to drop older days (partitions)?
         //
-        println!("Database importer: scanning...");
+        info!("Database importer: scanning...");
 
         let now = Utc::now();
         let duration = now.signed_duration_since(last);
@@ -162,20 +551,57 @@ fn questdb_insert(
             //
             // DROP partitions, check every hour
             //
-            for table in table_list.iter() {
-                table.drop(&api_url, retention_days);
+            if sink.is_some() {
+                for table in table_list.iter() {
+                    table.drop(&api_url, retention_days);
+                }
+                for rollup in rollup_list.iter() {
+                    rollup.drop(&api_url, retention_5m_days, retention_1h_days, retention_1d_days);
+                }
+            } else {
+                for table in table_list.iter() {
+                    clickhouse.drop(*table, retention_days);
+                }
+            }
+        }
+        //
+        // downsample into the 5m/1h/1d rollups, every 5 minutes
+        //
+        if sink.is_some()
+            && last_rollup.is_none_or(|t| now.signed_duration_since(t).num_minutes() >= 5)
+        {
+            last_rollup = Some(now);
+            for rollup in rollup_list.iter() {
+                rollup.update(&api_url);
             }
         }
 
         let directory = match fs::read_dir(input_spec) {
             Ok(d) => d,
-            Err(e) => panic!("Error: reading directory {} -- {:?}", input_spec, e),
+            Err(e) => {
+                // e.g. an NFS share gone for a moment; try again next poll
+                error!("reading directory {} - {:?}", input_spec, e);
+                if polling_interval == 0 || !shutdown::sleep(sleep_interval) {
+                    break;
+                }
+                continue;
+            }
         };
 
         let mut counter = 0;
+        let mut stalled = false;
 
         for entry in directory {
-            let file = entry.unwrap();
+            if shutdown::requested() {
+                break;
+            }
+            let file = match entry {
+                Ok(f) => f,
+                Err(e) => {
+                    error!("reading directory {} - {:?}", input_spec, e);
+                    continue;
+                }
+            };
             let filename = String::from(file.file_name().to_string_lossy());
 
             if let Ok(metadata) = file.metadata() {
@@ -186,34 +612,77 @@ fn questdb_insert(
             }
 
             if !filename.starts_with(".") && filename.ends_with(".parquet") {
-                println!("Database importer: processing {}", filename.clone());
+                let _batch = logging::batch(&filename);
+                info!("Database importer: processing {}", filename.clone());
                 // rename file so it isn't clobbered
                 let tmp_filename = format!(".gnat_db-{}", filename.clone());
-                fs::rename(filename.clone(), tmp_filename.clone()).unwrap();
+                if let Err(e) = fs::rename(filename.clone(), tmp_filename.clone()) {
+                    error!("claiming {} - {:?}", filename, e);
+                    continue;
+                }
 
-                let source = match Connection::open_in_memory() {
+                let source = match load_file(&tmp_filename, &projection, annotation_spec) {
                     Ok(s) => s,
-                    Err(e) => panic!("Error: open_in_memory() - {}", e),
-                };
-                let sql_command = format!(
-                    "CREATE TABLE memtable AS SELECT * FROM '{}';",
-                    tmp_filename.clone()
-                );
-
-                match source.execute_batch(&sql_command) {
-                    Ok(c) => c,
                     Err(e) => {
-                        panic!("Error: creating table from file {} - {:?}", tmp_filename, e);
+                        error!("loading {} - {:?}", filename, e);
+                        reject(&tmp_filename, &filename, processed_spec);
+                        counter += 1;
+                        continue;
                     }
                 };
                 //
-                // INSERT new data
+                // INSERT new data, retrying transient failures with backoff;
+                // tables already written, in this or an earlier scan, are
+                // skipped so no rows are duplicated
                 //
-                for table in table_list.iter() {
-                    table.insert(&mut sink, &source);
+                let mut done = load_done(&filename, &table_list);
+                if !done.is_empty() {
+                    info!("Database importer: {} tables of {} already written", done.len(), filename);
+                }
+                let mut attempts = 0;
+                let mut backoff = Duration::from_secs(1);
+                let failure = loop {
+                    let Err(e) = insert_tables(&table_list, &mut sink, &clickhouse, &source, &mut done)
+                    else {
+                        break None;
+                    };
+                    attempts += 1;
+                    error!("inserting {} [attempt {}] - {:?}", filename, attempts, e);
+                    if !transient(&e) {
+                        break Some(e);
+                    }
+                    if let Some(s) = sink.as_ref() {
+                        if s.must_close() {
+                            match Sender::from_conf(&conf) {
+                                Ok(s) => sink = Some(s),
+                                Err(e) => error!("reconnecting to QuestDB - {}", e),
+                            }
+                        }
+                    }
+                    if attempts > retries || !shutdown::sleep(backoff) {
+                        break Some(e);
+                    }
+                    backoff = (backoff * 2).min(Duration::from_secs(60));
+                };
+                let _ = source.close();
+
+                if let Some(e) = failure {
+                    if transient(&e) || shutdown::requested() {
+                        // hand the file back and wait for the database
+                        warn!("Database importer: {} returned to spool", filename);
+                        save_done(&filename, &done);
+                        if let Err(e) = fs::rename(tmp_filename.clone(), filename.clone()) {
+                            error!("restoring {} - {:?}", filename, e);
+                        }
+                        stalled = true;
+                        break;
+                    }
+                    reject(&tmp_filename, &filename, processed_spec);
+                    let _ = fs::remove_file(done_spec(&filename));
+                    counter += 1;
+                    continue;
                 }
-                
-                source.close().unwrap();
+                let _ = fs::remove_file(done_spec(&filename));
 
                 //
                 // move or remove the file
@@ -221,53 +690,148 @@ fn questdb_insert(
                 if !processed_spec.is_empty() {
                     let processed_path = format!("{}/{}", processed_spec, filename.to_string());
 
-                    match fs::rename(tmp_filename.clone(), processed_path.clone()) {
-                        Ok(c) => c,
-                        Err(e) => {
-                            panic!("Error: moving {} -> {}: {:?}", tmp_filename, processed_path, e)
-                        }
-                    };
-                } else {
-                    fs::remove_file(tmp_filename.clone()).unwrap();
+                    if let Err(e) = fs::rename(tmp_filename.clone(), processed_path.clone()) {
+                        error!("moving {} -> {}: {:?}", tmp_filename, processed_path, e);
+                    }
+                } else if let Err(e) = fs::remove_file(tmp_filename.clone()) {
+                    error!("removing {} - {:?}", tmp_filename, e);
                 }
                 counter += 1;
             }
         }
-        if counter == 0 {
-            thread::sleep(sleep_interval);
-        }
-        if polling_interval == 0 {
+        if polling_interval == 0 || shutdown::requested() {
             // one-shot scan
             break;
         }
+        if (counter == 0 || stalled) && !shutdown::sleep(sleep_interval) {
+            break;
+        }
+    }
+    if shutdown::requested() {
+        info!("Database importer: shutting down");
     }
 }
 
 fn main() {
     let args = Args::parse();
+    shutdown::install();
+    let _stage = logging::init("gnat_db");
 
     let polling_interval: u64 = args.polling.unwrap_or(60);
     let input_spec: String = args.input.clone();
     let host_spec: String = args.host.clone();
     let ilp_port: u16 = args.ilp.unwrap_or(9009);
-    let api_port: u16 = args.api.unwrap_or(9000);
+    let backend_spec: String = args.backend.unwrap_or(String::from("questdb")).clone();
+    let api_port: u16 = args
+        .api
+        .unwrap_or(if backend_spec == "clickhouse" { 8123 } else { 9000 });
     let retention_days: u16 = args.retention.unwrap_or(7);
+    // rollups left at their defaults are kept at least as long as the
+    // finer resolution, so a long --retention alone stays valid
+    let retention_5m_days: u16 = args.retention_5m.unwrap_or(retention_days.max(30));
+    let retention_1h_days: u16 = args.retention_1h.unwrap_or(retention_5m_days.max(90));
+    let retention_1d_days: u16 = args.retention_1d.unwrap_or(retention_1h_days.max(730));
     let processed_spec: String = args.processed.unwrap_or(String::new()).clone();
     let tables_spec: String = args.tables.unwrap_or(String::from("all")).clone();
+    let annotation_spec: String = args.annotations.unwrap_or(String::new()).clone();
+    let identity_spec: String = args.identities.unwrap_or(String::new()).clone();
+    let protocol_spec: String = args.protocol.unwrap_or(String::from("tcp")).clone();
+    let tls_verify: bool = args.tls_verify.unwrap_or(true);
+    let tls_roots_spec: String = args.tls_roots.unwrap_or(String::new()).clone();
+    let retries: u32 = args.retries.unwrap_or(5);
+    let healthcheck_port: u16 = args.healthcheck_port.unwrap_or(0);
+    let validate: bool = args.validate.unwrap_or(false);
 
     if !Path::new(&input_spec).is_dir() {
-        eprintln!("Error: invalid --input directory {}", input_spec);
+        error!("invalid --input directory {}", input_spec);
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if retention_5m_days < retention_days
+        || retention_1h_days < retention_5m_days
+        || retention_1d_days < retention_1h_days
+    {
+        error!("--retention-5m, --retention-1h and --retention-1d must not be shorter than the finer resolution");
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if !annotation_spec.is_empty() && !Path::new(&annotation_spec).is_file() {
+        error!("invalid --annotations file {}", annotation_spec);
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if backend_spec != "questdb" && backend_spec != "clickhouse" {
+        error!("invalid --backend {} [questdb|clickhouse]", backend_spec);
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    // rollups and the host table are kept in QuestDB only
+    if backend_spec == "clickhouse" {
+        for (option, set) in [
+            ("--retention-5m", args.retention_5m.is_some()),
+            ("--retention-1h", args.retention_1h.is_some()),
+            ("--retention-1d", args.retention_1d.is_some()),
+            ("--identities", !identity_spec.is_empty()),
+        ] {
+            if set {
+                error!("{} is not supported by --backend clickhouse", option);
+                std::process::exit(exitcode::CONFIG)
+            }
+        }
+    }
+
+    if !identity_spec.is_empty() && !Path::new(&identity_spec).is_file() {
+        error!("invalid --identities file {}", identity_spec);
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    if !["tcp", "tcps", "http", "https"].contains(&protocol_spec.as_str()) {
+        error!("invalid --protocol {} [tcp|tcps|http|https]", protocol_spec);
         std::process::exit(exitcode::CONFIG)
     }
 
-    questdb_insert(
+    if !tls_roots_spec.is_empty() && !Path::new(&tls_roots_spec).is_file() {
+        error!("invalid --tls-roots file {}", tls_roots_spec);
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    let api_url = match questdb_api_url(&protocol_spec, &host_spec, api_port) {
+        Ok(api_url) => api_url,
+        Err(e) => {
+            error!("invalid --host {} - {}", host_spec, e);
+            std::process::exit(exitcode::CONFIG)
+        }
+    };
+
+    if validate {
+        let clickhouse = ClickHouse::new(&host_spec, api_port);
+        let paths = [&input_spec, &processed_spec, &annotation_spec, &identity_spec];
+        if health::validate(&paths, &database_url(&backend_spec, &api_url, &clickhouse)) {
+            std::process::exit(exitcode::OK)
+        }
+        std::process::exit(exitcode::CONFIG)
+    }
+
+    questdb_insert(&ImportConfig {
         polling_interval,
-        &input_spec,
-        &host_spec,
+        input_spec,
+        host_spec,
         ilp_port,
         api_port,
-        &processed_spec,
+        api_url,
+        processed_spec,
         retention_days,
-        &tables_spec,
-    );
+        retention_5m_days,
+        retention_1h_days,
+        retention_1d_days,
+        table_spec: tables_spec,
+        annotation_spec,
+        identity_spec,
+        backend_spec,
+        protocol_spec,
+        tls_verify,
+        tls_roots_spec,
+        retries,
+        healthcheck_port,
+    });
 }
@@ -0,0 +1,294 @@
+//
+// Downsampled rollups of the 1-minute tables for long retention:
+//
+//   <table>      1 minute buckets (--retention days)
+//   <table>_5m   5 minute buckets rolled up from <table> (--retention-5m days)
+//   <table>_1h   1 hour buckets rolled up from <table>_5m (--retention-1h days)
+//   <table>_1d   1 day buckets rolled up from <table>_1h (--retention-1d days)
+//
+// Each pass re-aggregates the complete intervals from the last one already
+// rolled up onwards, so an empty rollup is backfilled from its source and a
+// rollup that fell behind (gnat_db down, a table added with --tables)
+// catches up. The rollup tables dedup on (timestamp, keys) so late data
+// simply replaces the earlier totals.
+//
+
+use crate::migrate;
+
+use tracing::{debug, error, info};
+
+pub struct Rollup {
+    pub table_name: &'static str,
+    // (column, QuestDB type)
+    pub keys: &'static [(&'static str, &'static str)],
+    // (column, aggregate, QuestDB type)
+    pub values: &'static [(&'static str, &'static str, &'static str)],
+}
+
+pub const ROLLUPS: &[Rollup] = &[
+    Rollup {
+        table_name: "appid",
+        keys: &[("observ", "SYMBOL"), ("appid", "SYMBOL")],
+        values: &[("count", "sum", "LONG")],
+    },
+    Rollup {
+        table_name: "asn",
+        keys: &[
+            ("observ", "SYMBOL"),
+            ("dasnorg", "SYMBOL"),
+            ("dasn", "LONG"),
+        ],
+        values: &[("count", "sum", "LONG")],
+    },
+    Rollup {
+        table_name: "bytes",
+        keys: &[("observ", "SYMBOL")],
+        values: &[("sbytes", "sum", "LONG"), ("dbytes", "sum", "LONG")],
+    },
+    Rollup {
+        table_name: "country",
+        keys: &[("observ", "SYMBOL"), ("dcountry", "SYMBOL")],
+        values: &[("count", "sum", "LONG")],
+    },
+    Rollup {
+        table_name: "flow",
+        keys: &[("observ", "SYMBOL")],
+        values: &[("count", "sum", "LONG")],
+    },
+    Rollup {
+        table_name: "host",
+        keys: &[("observ", "SYMBOL"), ("host", "SYMBOL"), ("host_id", "SYMBOL")],
+        values: &[
+            ("flows", "sum", "LONG"),
+            ("obytes", "sum", "LONG"),
+            ("ibytes", "sum", "LONG"),
+            ("peers", "max", "LONG"),
+            ("score", "max", "DOUBLE"),
+        ],
+    },
+    Rollup {
+        table_name: "packets",
+        keys: &[("observ", "SYMBOL")],
+        values: &[("spkts", "sum", "LONG"), ("dpkts", "sum", "LONG")],
+    },
+    Rollup {
+        table_name: "proto",
+        keys: &[("observ", "SYMBOL"), ("proto", "SYMBOL")],
+        values: &[("count", "sum", "LONG")],
+    },
+    Rollup {
+        table_name: "traffic",
+        keys: &[("observ", "SYMBOL"), ("vlan", "LONG")],
+        values: &[
+            ("flows", "sum", "LONG"),
+            ("bytes", "sum", "LONG"),
+            ("packets", "sum", "LONG"),
+        ],
+    },
+];
+
+// (suffix, source suffix, interval, interval unit and stride, partitioning)
+const RESOLUTIONS: [(&str, &str, &str, &str, u32, &str); 3] = [
+    ("_5m", "", "5m", "m", 5, "DAY"),
+    ("_1h", "_5m", "h", "h", 1, "DAY"),
+    ("_1d", "_1h", "d", "d", 1, "MONTH"),
+];
+
+fn execute(api_url: &str, sql_command: String) -> bool {
+    let url = url::Url::parse_with_params(api_url, &[("query", sql_command)])
+        .expect("invalid url params");
+    match reqwest::blocking::get(url) {
+        Ok(r) => r.status().is_success(),
+        Err(_e) => false,
+    }
+}
+
+//
+// Newest bucket of table_name; Ok(None) when it is empty
+//
+fn last_bucket(api_url: &str, table_name: &str) -> anyhow::Result<Option<String>> {
+    let url = url::Url::parse_with_params(
+        api_url,
+        &[("query", format!("SELECT max(bucket) FROM {};", table_name))],
+    )?;
+    let response: serde_json::Value = serde_json::from_str(&reqwest::blocking::get(url)?.text()?)?;
+    if let Some(e) = response.get("error") {
+        return Err(anyhow::anyhow!("{}", e));
+    }
+    Ok(response["dataset"][0][0].as_str().map(String::from))
+}
+
+impl Rollup {
+    fn key_list(&self) -> String {
+        self.keys
+            .iter()
+            .map(|(column, _)| *column)
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+
+    pub fn create(&self, api_url: &str) {
+        let mut columns: Vec<String> = vec![String::from("bucket TIMESTAMP")];
+        for (column, column_type) in self.keys {
+            if *column_type == "SYMBOL" {
+                columns.push(format!("{} SYMBOL CAPACITY 8192 INDEX", column));
+            } else {
+                columns.push(format!("{} {}", column, column_type));
+            }
+        }
+        for (column, _, column_type) in self.values {
+            columns.push(format!("{} {}", column, column_type));
+        }
+        columns.push(String::from("timestamp TIMESTAMP"));
+
+        for (suffix, _, _, _, _, partition) in RESOLUTIONS {
+            let sql_create_table = format!(
+                "CREATE TABLE IF NOT EXISTS {}{}({})
+                    TIMESTAMP(timestamp) PARTITION BY {} WAL
+                    DEDUP UPSERT KEYS(timestamp, {});",
+                self.table_name,
+                suffix,
+                columns.join(", "),
+                partition,
+                self.key_list()
+            );
+            if execute(api_url, sql_create_table.clone()) {
+                info!(
+                    "Database importer: verified [{}{}] table",
+                    self.table_name, suffix
+                );
+            } else {
+                panic!("Error: creating {}{} table", self.table_name, suffix);
+            }
+            migrate(
+                api_url,
+                &format!("{}{}", self.table_name, suffix),
+                &sql_create_table,
+            );
+        }
+    }
+
+    //
+    // re-aggregate each resolution from the interval before its newest
+    // bucket, or from the start of its source when it is empty
+    //
+    pub fn update(&self, api_url: &str) {
+        let keys = self.key_list();
+        let values = self
+            .values
+            .iter()
+            .map(|(column, aggregate, _)| format!("{}({}) AS {}", aggregate, column, column))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let columns = self
+            .values
+            .iter()
+            .map(|(column, _, _)| *column)
+            .collect::<Vec<&str>>()
+            .join(", ");
+
+        for (suffix, source_suffix, interval, unit, stride, _) in RESOLUTIONS {
+            let last = match last_bucket(api_url, &format!("{}{}", self.table_name, suffix)) {
+                Ok(last) => last,
+                Err(e) => {
+                    error!("rolling up {}{} - {:?}", self.table_name, suffix, e);
+                    continue;
+                }
+            };
+            let source_filter = match last {
+                Some(last) => {
+                    // raw tables are timestamped at insertion; rollups at the bucket
+                    let start = format!("dateadd('{}', -{}, cast('{}' AS TIMESTAMP))", unit, stride, last);
+                    if source_suffix.is_empty() {
+                        format!(
+                            "timestamp >= dateadd('{0}', -{1}, {2}) AND bucket >= {2} AND",
+                            unit, stride, start
+                        )
+                    } else {
+                        format!("bucket >= {} AND", start)
+                    }
+                }
+                None => {
+                    info!("Database importer: backfilling [{}{}]", self.table_name, suffix);
+                    String::new()
+                }
+            };
+            debug!("rolling up {}{}: {}", self.table_name, suffix, source_filter);
+            let sql_rollup = format!(
+                "INSERT INTO {0}{1} (bucket, {2}, {3}, timestamp)
+                    SELECT b, {2}, {4}, b FROM (
+                        SELECT timestamp_floor('{6}', bucket) AS b, {2}, {3}
+                        FROM {0}{5}
+                        WHERE {7}
+                              bucket < timestamp_floor('{6}', now()));",
+                self.table_name, suffix, keys, columns, values, source_suffix, interval, source_filter
+            );
+            if !execute(api_url, sql_rollup) {
+                error!("rolling up {}{}", self.table_name, suffix);
+            }
+        }
+    }
+
+    pub fn drop(&self, api_url: &str, retention_5m: u16, retention_1h: u16, retention_1d: u16) {
+        for ((suffix, _, _, _, _, _), retention_days) in RESOLUTIONS
+            .iter()
+            .zip([retention_5m, retention_1h, retention_1d])
+        {
+            let sql_drop_partition = format!(
+                "ALTER TABLE {}{} DROP PARTITION WHERE timestamp < dateadd('d', -{}, now());",
+                self.table_name, suffix, retention_days
+            );
+            if execute(api_url, sql_drop_partition) {
+                info!(
+                    "Database importer: dropped partition table [{}{}]",
+                    self.table_name, suffix
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[test]
+    fn update_rolls_up_from_the_newest_bucket() {
+        let bytes = ROLLUPS.iter().find(|r| r.table_name == "bytes").unwrap();
+        let (api_url, server) = test_server(5, |query| {
+            let body = match query {
+                "SELECT max(bucket) FROM bytes_5m;" => r#"{"dataset":[[null]]}"#,
+                "SELECT max(bucket) FROM bytes_1h;" => {
+                    r#"{"dataset":[["2024-06-01T10:00:00.000000Z"]]}"#
+                }
+                "SELECT max(bucket) FROM bytes_1d;" => r#"{"error":"table does not exist"}"#,
+                _ => "{}",
+            };
+            (200, String::from(body))
+        });
+        bytes.update(&api_url);
+        let queries: Vec<String> = server.join().unwrap().into_iter().map(|(q, _)| q).collect();
+        let queries: Vec<String> = queries
+            .iter()
+            .map(|q| q.split_whitespace().collect::<Vec<&str>>().join(" "))
+            .collect();
+        assert_eq!(queries.len(), 5);
+
+        // an empty rollup is backfilled from the whole source
+        assert_eq!(
+            queries[1],
+            "INSERT INTO bytes_5m (bucket, observ, sbytes, dbytes, timestamp) \
+             SELECT b, observ, sum(sbytes) AS sbytes, sum(dbytes) AS dbytes, b FROM ( \
+             SELECT timestamp_floor('5m', bucket) AS b, observ, sbytes, dbytes FROM bytes \
+             WHERE bucket < timestamp_floor('5m', now()));"
+        );
+        // the others restart an interval before their newest bucket
+        assert!(queries[3].starts_with("INSERT INTO bytes_1h "));
+        assert!(queries[3].contains(
+            "FROM bytes_5m WHERE bucket >= dateadd('h', -1, cast('2024-06-01T10:00:00.000000Z' AS TIMESTAMP)) AND"
+        ));
+        // a rollup that can't be read is skipped
+        assert_eq!(queries[4], "SELECT max(bucket) FROM bytes_1d;");
+    }
+}
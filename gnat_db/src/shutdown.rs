@@ -0,0 +1,83 @@
+//
+// SIGTERM/SIGINT handling for the importer loop
+//
+// The handler only sets a flag; the loop checks requested() between files
+// and while idle so the file in flight is fully inserted and moved to
+// --processed before the process exits. SIGUSR1 and SIGHUP, from the
+// gnat_run control API, cut an idle wait short so the input is scanned
+// right away.
+//
+
+use crate::health;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static WAKE: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    match signal {
+        libc::SIGUSR1 | libc::SIGHUP => WAKE.store(true, Ordering::SeqCst),
+        _ => SHUTDOWN.store(true, Ordering::SeqCst),
+    }
+}
+
+pub fn install() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGUSR1, handler);
+        libc::signal(libc::SIGHUP, handler);
+    }
+}
+
+pub fn requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+//
+// Sleep for the interval in short steps, or until woken; returns false if
+// shutdown was requested
+//
+pub fn sleep(interval: Duration) -> bool {
+    let step = Duration::from_millis(250);
+    let mut remaining = interval;
+    while !remaining.is_zero() {
+        if requested() {
+            return false;
+        }
+        if WAKE.swap(false, Ordering::SeqCst) {
+            break;
+        }
+        health::beat();
+        let delay = remaining.min(step);
+        thread::sleep(delay);
+        remaining -= delay;
+    }
+    !requested()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn signals_end_an_idle_wait() {
+        let start = Instant::now();
+        assert!(sleep(Duration::from_millis(300)));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        // SIGUSR1 and SIGHUP wake the loop, without stopping it
+        for signal in [libc::SIGUSR1, libc::SIGHUP] {
+            on_signal(signal);
+            let start = Instant::now();
+            assert!(sleep(Duration::from_secs(60)));
+            assert!(start.elapsed() < Duration::from_secs(10));
+        }
+        assert!(!requested());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::TableTrait;
+use crate::{from_row, FromRow};
+
+use std::cell::Cell;
+use std::fs;
+use std::time::SystemTime;
+
+use questdb::ingress::{Buffer, TimestampMicros};
+use tracing::{error, info};
+
+//
+// Operator annotations are maintained in a CSV file with the header:
+//
+//   start,end,kind,observ,exclude,note
+//
+// e.g. 2024-11-05 02:00:00,2024-11-05 04:00:00,maintenance,*,true,core switch upgrade
+//
+// An observ of '*' applies the annotation to all observation points.
+//
+#[derive(Debug)]
+struct AnnotationRecord {
+    start: i64,
+    end: i64,
+    kind: String,
+    observ: String,
+    exclude: bool,
+    note: String,
+}
+
+from_row!(AnnotationRecord { start, end, kind, observ, exclude, note });
+
+pub struct AnnotationTable {
+    pub table_name: &'static str,
+    pub annotation_spec: String,
+    pub last_modified: Cell<Option<SystemTime>>,
+}
+
+fn read_annotations(annotation_spec: &String) -> String {
+    format!(
+        "read_csv('{}', header = true, columns = {{
+            'start': 'TIMESTAMP',
+            'end': 'TIMESTAMP',
+            'kind': 'VARCHAR',
+            'observ': 'VARCHAR',
+            'exclude': 'BOOLEAN',
+            'note': 'VARCHAR'}})",
+        annotation_spec
+    )
+}
+
+//
+// Remove flows from the memtable that fall within an annotated interval
+// marked for exclusion, so tables don't report data from maintenance
+// windows, known outages, pen-tests, etc.
+//
+pub fn exclude_annotated(source: &duckdb::Connection, annotation_spec: &String) {
+    if annotation_spec.is_empty() {
+        return;
+    }
+    let sql_command = format!(
+        "DELETE FROM memtable USING {} AS a
+            WHERE a.exclude
+            AND memtable.stime BETWEEN a.start AND a.end
+            AND (a.observ = '*' OR a.observ = memtable.observ);",
+        read_annotations(annotation_spec)
+    );
+    match source.execute(&sql_command, []) {
+        Ok(count) => {
+            if count > 0 {
+                info!("Annotations: excluded {} annotated records", count);
+            }
+        }
+        Err(e) => error!("applying annotations {} - {:?}", annotation_spec, e),
+    };
+}
+
+impl TableTrait for AnnotationTable {
+    fn table_name(&self) -> &'static str {
+        self.table_name
+    }
+    fn columns(&self) -> &'static [&'static str] {
+        &["stime", "observ"]
+    }
+    fn create(&self, api_url: &String) {
+        let sql_create_table = format!(
+            "CREATE TABLE IF NOT EXISTS {}(
+                observ SYMBOL CAPACITY 64 INDEX,
+                kind SYMBOL CAPACITY 64 INDEX,
+                stop TIMESTAMP,
+                exclude BOOLEAN,
+                note VARCHAR,
+                timestamp TIMESTAMP)
+                TIMESTAMP(timestamp) PARTITION BY DAY WAL
+                DEDUP UPSERT KEYS(timestamp, observ, kind);",
+            self.table_name
+        );
+
+        //
+        // Post the request to the QuestDB API
+        //
+        let url = url::Url::parse_with_params(api_url, &[("query", &sql_create_table)])
+            .expect("invalid url params");
+
+        match reqwest::blocking::get(url) {
+            Ok(r) => info!("Database importer: verified [{}] table: {:?}", self.table_name, r.status()),
+            Err(e) => panic!("Error: creating {} table - {:?}", self.table_name, e),
+        };
+        self.migrate(api_url, &sql_create_table);
+    }
+    fn insert(
+        &self,
+        sink: &mut questdb::ingress::Sender,
+        source: &duckdb::Connection,
+    ) -> anyhow::Result<()> {
+        if self.annotation_spec.is_empty() {
+            return Ok(());
+        }
+        //
+        // only (re)load annotations when the file changes; the change is
+        // recorded once the annotations are flushed, so a failed flush is
+        // retried with the next file
+        //
+        let modified = match fs::metadata(&self.annotation_spec) {
+            Ok(m) => m.modified().ok(),
+            Err(e) => {
+                error!("reading annotations {} - {:?}", self.annotation_spec, e);
+                return Ok(());
+            }
+        };
+        if modified.is_some() && modified == self.last_modified.get() {
+            return Ok(());
+        }
+
+        let sql_command = format!(
+            "SELECT epoch_us(start) AS start,
+                    epoch_us(\"end\") AS \"end\",
+                    kind,
+                    observ,
+                    exclude,
+                    coalesce(note, '') AS note
+                FROM {}
+                ORDER BY start;",
+            read_annotations(&self.annotation_spec)
+        );
+        let mut stmt = match source.prepare(&sql_command) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("loading annotations {} - {:?}", self.annotation_spec, e);
+                return Ok(());
+            }
+        };
+
+        let record_iter = stmt.query_map([], AnnotationRecord::from_row)?;
+        let mut count = 0;
+        let mut buffer = Buffer::new();
+        for r in record_iter {
+            let record = r?;
+            buffer
+                .table(self.table_name)?
+                .symbol("observ", record.observ)?
+                .symbol("kind", record.kind)?
+                .column_ts("stop", TimestampMicros::new(record.end))?
+                .column_bool("exclude", record.exclude)?
+                .column_str("note", record.note)?
+                .at(TimestampMicros::new(record.start))?;
+            count += 1;
+        }
+        if count > 0 {
+            sink.flush(&mut buffer)?;
+            info!("Table [{}]: {} annotations", self.table_name, count);
+        }
+        self.last_modified.set(modified);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_insert, test_memtable};
+
+    fn test_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("gnat_db-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    const ANNOTATIONS: &str = "start,end,kind,observ,exclude,note\n\
+        2024-11-05 02:00:00,2024-11-05 04:00:00,maintenance,*,true,core switch upgrade\n\
+        2024-11-05 04:30:00,2024-11-05 05:30:00,outage,s2,true,\n\
+        2024-11-05 00:30:00,2024-11-05 01:30:00,pentest,*,false,red team\n";
+
+    #[test]
+    fn excluded_intervals_drop_flows() {
+        let annotation_spec = test_file("exclude.csv", ANNOTATIONS);
+        let source = test_memtable(
+            "SELECT * FROM (VALUES
+                ('s1', TIMESTAMP '2024-11-05 01:00:00'), ('s1', TIMESTAMP '2024-11-05 03:00:00'),
+                ('s2', TIMESTAMP '2024-11-05 03:00:00'), ('s1', TIMESTAMP '2024-11-05 05:00:00'),
+                ('s2', TIMESTAMP '2024-11-05 05:00:00')) t(observ, stime)",
+        );
+        exclude_annotated(&source, &annotation_spec);
+        let _ = fs::remove_file(&annotation_spec);
+        let mut stmt = source
+            .prepare("SELECT observ, strftime(stime, '%H:%M') FROM memtable ORDER BY ALL;")
+            .unwrap();
+        let kept: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            kept,
+            vec![
+                (String::from("s1"), String::from("01:00")),
+                (String::from("s1"), String::from("05:00"))
+            ]
+        );
+    }
+
+    #[test]
+    fn annotations_are_written_when_the_file_changes() {
+        let annotation_spec = test_file("insert.csv", ANNOTATIONS);
+        let table = AnnotationTable {
+            table_name: "annotation",
+            annotation_spec: annotation_spec.clone(),
+            last_modified: Cell::new(None),
+        };
+        let source = test_memtable("SELECT 's1' AS observ, TIMESTAMP '2024-11-05' AS stime");
+        let lines = test_insert(&table, &source);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("annotation,observ=*,kind=pentest "));
+        assert!(lines[1].starts_with("annotation,observ=*,kind=maintenance "));
+        assert!(lines[1].contains("exclude=t"));
+        assert!(lines[1].contains("note=\"core switch upgrade\""));
+        assert!(lines[2].contains("note=\"\""));
+        // unchanged, the file isn't read again
+        assert!(test_insert(&table, &source).is_empty());
+        let _ = fs::remove_file(&annotation_spec);
+    }
+}
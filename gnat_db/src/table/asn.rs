@@ -1,6 +1,8 @@
 use crate::TableTrait;
+use crate::{from_row, FromRow};
 
 use questdb::ingress::{Buffer, TimestampMicros, TimestampNanos};
+use tracing::info;
 
 #[derive(Debug)]
 struct AsnRecord {
@@ -11,6 +13,8 @@ struct AsnRecord {
     count: i64,
 }
 
+from_row!(AsnRecord { bucket, observ, dasn, dasnorg, count });
+
 pub struct AsnTable {
     pub table_name: &'static str,
 }
@@ -19,6 +23,19 @@ impl TableTrait for AsnTable {
     fn table_name(&self) -> &'static str {
         self.table_name
     }
+    fn columns(&self) -> &'static [&'static str] {
+        &["stime", "observ", "dasn", "dasnorg"]
+    }
+    fn clickhouse_columns(&self) -> Option<&'static str> {
+        Some("bucket DateTime, observ LowCardinality(String), dasnorg LowCardinality(String), dasn UInt32, count UInt64")
+    }
+    fn clickhouse_query(&self) -> Option<&'static str> {
+        Some(
+            "SELECT time_bucket (INTERVAL '1' minute, stime) AS bucket, observ, dasnorg, dasn, count() AS count
+                FROM memtable
+                GROUP BY all",
+        )
+    }
     fn create(&self, api_url: &String) {
         let sql_create_table = format!(
             "CREATE TABLE IF NOT EXISTS {}(
@@ -35,15 +52,20 @@ impl TableTrait for AsnTable {
         //
         // Post the request to the QuestDB API
         //
-        let url = url::Url::parse_with_params(api_url, &[("query", sql_create_table)])
+        let url = url::Url::parse_with_params(api_url, &[("query", &sql_create_table)])
             .expect("invalid url params");
 
         match reqwest::blocking::get(url) {
-            Ok(r) => println!("Database importer: verified [{}] table: {:?}", self.table_name, r.status()),
+            Ok(r) => info!("Database importer: verified [{}] table: {:?}", self.table_name, r.status()),
             Err(e) => panic!("Error: creating {} table - {:?}", self.table_name, e),
         };
+        self.migrate(api_url, &sql_create_table);
     }
-    fn insert(&self, sink: &mut questdb::ingress::Sender, source: &duckdb::Connection) {
+    fn insert(
+        &self,
+        sink: &mut questdb::ingress::Sender,
+        source: &duckdb::Connection,
+    ) -> anyhow::Result<()> {
         //
         // query DuckDB memtable
         //
@@ -53,50 +75,34 @@ impl TableTrait for AsnTable {
                                             observ,
                                             dasn,                                            
                                             dasnorg,                                            
-                                            count() 
+                                            count() AS count
                                         FROM memtable 
                                         GROUP BY all 
                                         ORDER BY all
-                                        LIMIT 100;").unwrap();            
+                                        LIMIT 100;")?;            
 
-        let record_iter = stmt
-            .query_map([], |row| {
-                Ok(AsnRecord {
-                    bucket: row.get(0).expect("missing bucket"),
-                    observ: row.get(1).expect("missing observ"),
-                    dasn: row.get(2).expect("missing dasn"),
-                    dasnorg: row.get(3).expect("missing dasnorg"),
-                    count: row.get(4).expect("missing count"),
-                })
-            })
-            .unwrap();
+        let record_iter = stmt.query_map([], AsnRecord::from_row)?;
         let mut count = 0;
         let mut buffer = Buffer::new();
         for r in record_iter {
-            let record = r.unwrap();
-            let _ = buffer
-                .table(self.table_name)
-                .unwrap()
-                .symbol("observ", record.observ)
-                .unwrap()
-                .symbol("dasnorg", record.dasnorg)
-                .unwrap()
-                .column_i64("dasn", record.dasn)
-                .unwrap()
-                .column_ts("bucket", TimestampMicros::new(record.bucket))
-                .unwrap()
-                .column_i64("count", record.count)
-                .unwrap()
-                .at(TimestampNanos::now())
-                .unwrap();
+            let record = r?;
+            buffer
+                .table(self.table_name)?
+                .symbol("observ", record.observ)?
+                .symbol("dasnorg", record.dasnorg)?
+                .column_i64("dasn", record.dasn)?
+                .column_ts("bucket", TimestampMicros::new(record.bucket))?
+                .column_i64("count", record.count)?
+                .at(TimestampNanos::now())?;
             if buffer.len() >= (104857600 - 1048576) {
-                sink.flush(&mut buffer).unwrap();
+                sink.flush(&mut buffer)?;
             }
             count += 1;
         }
         if count > 0 {
-            sink.flush(&mut buffer).unwrap();
-            println!("Table [{}]: {} new records", self.table_name, count);
+            sink.flush(&mut buffer)?;
+            info!("Table [{}]: {} new records", self.table_name, count);
         }
+        Ok(())
     }
 }
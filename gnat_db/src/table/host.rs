@@ -0,0 +1,233 @@
+use crate::window::Window;
+use crate::{enable_dedup, internal_address};
+use crate::TableTrait;
+use crate::{from_row, FromRow};
+
+use questdb::ingress::{Buffer, TimestampMicros};
+use tracing::info;
+
+#[derive(Debug)]
+struct HostRecord {
+    bucket: i64,
+    observ: String,
+    host: String,
+    host_id: String,
+    flows: i64,
+    obytes: i64,
+    ibytes: i64,
+    peers: i64,
+    score: f64,
+}
+
+from_row!(HostRecord { bucket, observ, host, host_id, flows, obytes, ibytes, peers, score });
+
+//
+// Hosts seen on both stacks can be tied to one asset with an identity
+// CSV file with the header:
+//
+//   host_id,address
+//
+// e.g. printer-3f,10.1.2.30
+//      printer-3f,fd00::1e30
+//
+// Addresses without a mapping use the address itself as the host_id.
+//
+// peers is a distinct count, so each bucket is written once it is closed
+// (see window.rs), with one row per host timestamped at the bucket.
+//
+pub struct HostTable {
+    pub table_name: &'static str,
+    pub identity_spec: String,
+}
+
+fn read_identities(identity_spec: &String) -> String {
+    if identity_spec.is_empty() {
+        return String::from("(SELECT NULL::VARCHAR AS address, NULL::VARCHAR AS host_id WHERE false)");
+    }
+    format!(
+        "(SELECT address, any_value(host_id) AS host_id
+            FROM read_csv('{}', header = true, columns = {{
+                'host_id': 'VARCHAR',
+                'address': 'VARCHAR'}})
+            GROUP BY address)",
+        identity_spec
+    )
+}
+
+impl TableTrait for HostTable {
+    fn table_name(&self) -> &'static str {
+        self.table_name
+    }
+    fn columns(&self) -> &'static [&'static str] {
+        &["stime", "observ", "saddr", "daddr", "sbytes", "dbytes", "score"]
+    }
+    fn create(&self, api_url: &String) {
+        let sql_create_table = format!(
+            "CREATE TABLE IF NOT EXISTS {}(
+                bucket TIMESTAMP,
+                observ SYMBOL CAPACITY 64 INDEX,
+                host SYMBOL CAPACITY 8192 INDEX,
+                host_id SYMBOL CAPACITY 8192 INDEX,
+                flows LONG,
+                obytes LONG,
+                ibytes LONG,
+                peers LONG,
+                score DOUBLE,
+                timestamp TIMESTAMP)
+                TIMESTAMP(timestamp) PARTITION BY HOUR WAL
+                DEDUP UPSERT KEYS(timestamp, observ, host);",
+            self.table_name
+        );
+
+        //
+        // Post the request to the QuestDB API
+        //
+        let url = url::Url::parse_with_params(api_url, &[("query", &sql_create_table)])
+            .expect("invalid url params");
+
+        match reqwest::blocking::get(url) {
+            Ok(r) => info!("Database importer: verified [{}] table: {:?}", self.table_name, r.status()),
+            Err(e) => panic!("Error: creating {} table - {:?}", self.table_name, e),
+        };
+        self.migrate(api_url, &sql_create_table);
+        enable_dedup(api_url, self.table_name, "timestamp, observ, host");
+    }
+    fn insert(
+        &self,
+        sink: &mut questdb::ingress::Sender,
+        source: &duckdb::Connection,
+    ) -> anyhow::Result<()> {
+        let window = Window {
+            table_name: self.table_name,
+            minutes: 5,
+            columns: self.columns(),
+        };
+        let flows = window.open(source)?;
+        //
+        // query the closed buckets: one record per internal host per 5
+        // minutes, counting both directions with the host as the near side
+        //
+        let sql_command = format!(
+            "SELECT time_bucket (INTERVAL '5' minute, stime) as bucket,
+                    observ,
+                    host,
+                    coalesce(i.host_id, host) AS host_id,
+                    count() AS flows,
+                    sum(obytes)::BIGINT AS obytes,
+                    sum(ibytes)::BIGINT AS ibytes,
+                    count(DISTINCT peer) AS peers,
+                    max(score)::DOUBLE AS score
+                FROM (SELECT stime, observ, saddr AS host, daddr AS peer, sbytes AS obytes, dbytes AS ibytes, score
+                        FROM {1} WHERE closed AND {2}
+                      UNION ALL
+                      SELECT stime, observ, daddr AS host, saddr AS peer, dbytes AS obytes, sbytes AS ibytes, score
+                        FROM {1} WHERE closed AND {3}) AS f
+                LEFT JOIN {0} AS i ON i.address = f.host
+                GROUP BY all
+                ORDER BY all;",
+            read_identities(&self.identity_spec),
+            flows,
+            internal_address("saddr"),
+            internal_address("daddr")
+        );
+        let mut stmt = source.prepare(&sql_command)?;
+
+        let record_iter = stmt.query_map([], HostRecord::from_row)?;
+        let mut count = 0;
+        let mut buffer = Buffer::new();
+        for r in record_iter {
+            let record = r?;
+            buffer
+                .table(self.table_name)?
+                .symbol("observ", record.observ)?
+                .symbol("host", record.host)?
+                .symbol("host_id", record.host_id)?
+                .column_ts("bucket", TimestampMicros::new(record.bucket))?
+                .column_i64("flows", record.flows)?
+                .column_i64("obytes", record.obytes)?
+                .column_i64("ibytes", record.ibytes)?
+                .column_i64("peers", record.peers)?
+                .column_f64("score", record.score)?
+                .at(TimestampMicros::new(record.bucket))?;
+            if buffer.len() >= (104857600 - 1048576) {
+                sink.flush(&mut buffer)?;
+            }
+            count += 1;
+        }
+        if count > 0 {
+            sink.flush(&mut buffer)?;
+            info!("Table [{}]: {} new records", self.table_name, count);
+        }
+        window.commit(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_insert, test_memtable};
+
+    // flows of s1 as (minutes past midnight, saddr, daddr, sbytes, dbytes, score)
+    fn flows(rows: &[(u32, &str, &str, i64, i64, f64)]) -> duckdb::Connection {
+        let values: Vec<String> = rows
+            .iter()
+            .map(|(minute, saddr, daddr, sbytes, dbytes, score)| {
+                format!(
+                    "(TIMESTAMP '2024-01-01' + INTERVAL '{} minutes', 's1', '{}', '{}', {}, {}, {})",
+                    minute, saddr, daddr, sbytes, dbytes, score
+                )
+            })
+            .collect();
+        test_memtable(&format!(
+            "SELECT * FROM (VALUES {}) t(stime, observ, saddr, daddr, sbytes, dbytes, score)",
+            values.join(", ")
+        ))
+    }
+
+    #[test]
+    fn hosts_of_closed_buckets() {
+        let identity_spec = std::env::temp_dir()
+            .join(format!("gnat_db-{}-identities.csv", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(&identity_spec, "host_id,address\nprinter-3f,10.0.0.2\n").unwrap();
+        let table = HostTable {
+            table_name: "host_test",
+            identity_spec: identity_spec.clone(),
+        };
+
+        // both directions count with the internal host as the near side;
+        // the bucket is written once a flow is the grace period past it
+        let lines = test_insert(
+            &table,
+            &flows(&[
+                (1, "10.0.0.1", "8.8.8.8", 100, 1000, 0.5),
+                (2, "10.0.0.1", "1.1.1.1", 10, 20, 0.1),
+                (3, "8.8.8.8", "10.0.0.2", 50, 5, 0.0),
+                (20, "10.0.0.1", "8.8.8.8", 1, 1, 0.0),
+            ]),
+        );
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("host_test,observ=s1,host=10.0.0.1,host_id=10.0.0.1 "));
+        assert!(lines[0].contains("flows=2i,obytes=110i,ibytes=1020i,peers=2i,score=0.5"));
+        assert!(lines[1].starts_with("host_test,observ=s1,host=10.0.0.2,host_id=printer-3f "));
+        assert!(lines[1].contains("flows=1i,obytes=5i,ibytes=50i,peers=1i"));
+
+        // the held bucket isn't closed yet
+        let lines = test_insert(&table, &flows(&[(24, "10.0.0.1", "8.8.8.8", 1, 1, 0.0)]));
+        assert!(lines.is_empty());
+        // now it is, with the flows held from both files; the late flow
+        // for the bucket already written is dropped
+        let lines = test_insert(
+            &table,
+            &flows(&[
+                (2, "10.0.0.1", "8.8.8.8", 1, 1, 0.0),
+                (40, "10.0.0.1", "8.8.8.8", 1, 1, 0.0),
+            ]),
+        );
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("flows=2i,obytes=2i,ibytes=2i,peers=1i"));
+        let _ = std::fs::remove_file(&identity_spec);
+        let _ = std::fs::remove_file(".gnat_db-pending-host_test.parquet");
+    }
+}
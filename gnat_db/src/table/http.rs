@@ -0,0 +1,164 @@
+use crate::TableTrait;
+use crate::{from_row, FromRow};
+
+use questdb::ingress::{Buffer, TimestampMicros, TimestampNanos};
+use tracing::info;
+
+#[derive(Debug)]
+struct HttpRecord {
+    bucket: i64,
+    observ: String,
+    host: String,
+    method: String,
+    status: Option<i64>,
+    useragent: String,
+    count: i64,
+}
+
+from_row!(HttpRecord { bucket, observ, host, method, status, useragent, count });
+
+pub struct HttpTable {
+    pub table_name: &'static str,
+}
+
+impl TableTrait for HttpTable {
+    fn table_name(&self) -> &'static str {
+        self.table_name
+    }
+    fn columns(&self) -> &'static [&'static str] {
+        &["stime", "observ", "httphost", "httpmethod", "httpstatus", "httpuseragent"]
+    }
+    fn clickhouse_columns(&self) -> Option<&'static str> {
+        Some("bucket DateTime, observ LowCardinality(String), host LowCardinality(String), method LowCardinality(String), status Nullable(UInt16), useragent String, count UInt64")
+    }
+    fn clickhouse_query(&self) -> Option<&'static str> {
+        Some(
+            "SELECT time_bucket (INTERVAL '1' minute, stime) AS bucket, observ, coalesce(httphost, '') AS host,
+                    coalesce(httpmethod, '') AS method, httpstatus AS status, coalesce(httpuseragent, '') AS useragent, count() AS count
+                FROM memtable WHERE httpmethod IS NOT NULL OR httpstatus IS NOT NULL
+                GROUP BY all",
+        )
+    }
+    fn create(&self, api_url: &String) {
+        let sql_create_table = format!(
+            "CREATE TABLE IF NOT EXISTS {}(
+                bucket TIMESTAMP,
+                observ SYMBOL CAPACITY 64 INDEX,
+                host SYMBOL CAPACITY 8192 INDEX,
+                method SYMBOL CAPACITY 16,
+                status INT,
+                useragent VARCHAR,
+                count LONG,
+                timestamp TIMESTAMP)
+                TIMESTAMP(timestamp) PARTITION BY HOUR;",
+                self.table_name
+        );
+
+        //
+        // Post the request to the QuestDB API
+        //
+        let url = url::Url::parse_with_params(api_url, &[("query", &sql_create_table)])
+            .expect("invalid url params");
+
+        match reqwest::blocking::get(url) {
+            Ok(r) => info!("Database importer: verified [{}] table: {:?}", self.table_name, r.status()),
+            Err(e) => panic!("Error: creating {} table - {:?}", self.table_name, e),
+        };
+        self.migrate(api_url, &sql_create_table);
+    }
+    fn insert(
+        &self,
+        sink: &mut questdb::ingress::Sender,
+        source: &duckdb::Connection,
+    ) -> anyhow::Result<()> {
+        //
+        // query DuckDB memtable
+        //
+        let mut stmt = source.prepare("SELECT time_bucket (INTERVAL '1' minute, stime) as bucket,
+                                            observ,
+                                            coalesce(httphost, '') AS host,
+                                            coalesce(httpmethod, '') AS method,
+                                            httpstatus AS status,
+                                            coalesce(httpuseragent, '') AS useragent,
+                                            count() AS count
+                                        FROM memtable
+                                        WHERE httpmethod IS NOT NULL OR httpstatus IS NOT NULL
+                                        GROUP BY all
+                                        ORDER BY count DESC
+                                        LIMIT 100;")?;
+
+        let record_iter = stmt.query_map([], HttpRecord::from_row)?;
+        let mut count = 0;
+        let mut buffer = Buffer::new();
+        for r in record_iter {
+            let record = r?;
+            buffer
+                .table(self.table_name)?
+                .symbol("observ", record.observ)?
+                .symbol("host", record.host)?
+                .symbol("method", record.method)?
+                .column_ts("bucket", TimestampMicros::new(record.bucket))?;
+            if let Some(status) = record.status {
+                buffer.column_i64("status", status)?;
+            }
+            buffer
+                .column_str("useragent", record.useragent)?
+                .column_i64("count", record.count)?
+                .at(TimestampNanos::now())?;
+            if buffer.len() >= (104857600 - 1048576) {
+                sink.flush(&mut buffer)?;
+            }
+            count += 1;
+        }
+        if count > 0 {
+            sink.flush(&mut buffer)?;
+            info!("Table [{}]: {} new records", self.table_name, count);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_insert, test_memtable};
+
+    #[test]
+    fn requests_per_minute() {
+        let table = HttpTable {
+            table_name: "http_test",
+        };
+        let source = test_memtable(
+            "SELECT * FROM (VALUES
+                (TIMESTAMP '2024-01-01 00:00:10', 's1', 'example.com', 'GET', 200, 'curl/8.0'),
+                (TIMESTAMP '2024-01-01 00:00:40', 's1', 'example.com', 'GET', 200, 'curl/8.0'),
+                (TIMESTAMP '2024-01-01 00:00:50', 's1', 'example.com', 'POST', NULL, 'Mozilla/5.0 (X11)'),
+                (TIMESTAMP '2024-01-01 00:00:55', 's1', NULL, NULL, NULL, NULL))
+                t(stime, observ, httphost, httpmethod, httpstatus, httpuseragent)",
+        );
+
+        // flows without a method or status aren't requests; a missing
+        // status is left out of the line
+        let lines = test_insert(&table, &source);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("http_test,observ=s1,host=example.com,method=GET "));
+        assert!(lines[0].contains(" bucket=1704067200000000t,"));
+        assert!(lines[0].contains(",status=200i,useragent=\"curl/8.0\",count=2i "));
+        assert!(lines[1].starts_with("http_test,observ=s1,host=example.com,method=POST "));
+        assert!(lines[1].contains(" bucket=1704067200000000t,"));
+        assert!(lines[1].contains(",useragent=\"Mozilla/5.0 (X11)\",count=1i "));
+
+        // the ClickHouse rows count the same requests
+        let mut stmt = source.prepare(table.clickhouse_query().unwrap()).unwrap();
+        let mut counts: Vec<(String, i64)> = stmt
+            .query_map([], |row| Ok((row.get(3)?, row.get(6)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        counts.sort();
+        assert_eq!(
+            counts,
+            vec![(String::from("GET"), 2), (String::from("POST"), 1)]
+        );
+    }
+}
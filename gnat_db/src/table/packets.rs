@@ -1,6 +1,8 @@
 use crate::TableTrait;
+use crate::{from_row, FromRow};
 
 use questdb::ingress::{Buffer, TimestampMicros, TimestampNanos};
+use tracing::info;
 
 #[derive(Debug)]
 struct PacketsRecord {
@@ -10,6 +12,8 @@ struct PacketsRecord {
     dpkts: i64
 }
 
+from_row!(PacketsRecord { bucket, observ, spkts, dpkts });
+
 pub struct PacketsTable {
     pub table_name: &'static str,
 }
@@ -18,6 +22,19 @@ impl TableTrait for PacketsTable {
     fn table_name(&self) -> &'static str {
         self.table_name
     }
+    fn columns(&self) -> &'static [&'static str] {
+        &["stime", "observ", "spkts", "dpkts"]
+    }
+    fn clickhouse_columns(&self) -> Option<&'static str> {
+        Some("bucket DateTime, observ LowCardinality(String), spkts UInt64, dpkts UInt64")
+    }
+    fn clickhouse_query(&self) -> Option<&'static str> {
+        Some(
+            "SELECT time_bucket (INTERVAL '1' minute, stime) AS bucket, observ, sum(spkts)::UBIGINT AS spkts, sum(dpkts)::UBIGINT AS dpkts
+                FROM memtable
+                GROUP BY all",
+        )
+    }
     fn create(&self, api_url: &String) {
         let sql_create_table = format!(
             "CREATE TABLE IF NOT EXISTS {}(
@@ -33,59 +50,50 @@ impl TableTrait for PacketsTable {
         //
         // Post the request to the QuestDB API
         //
-        let url = url::Url::parse_with_params(api_url, &[("query", sql_create_table)])
+        let url = url::Url::parse_with_params(api_url, &[("query", &sql_create_table)])
             .expect("invalid url params");
 
         match reqwest::blocking::get(url) {
-            Ok(r) => println!("Database importer: verified [{}] table: {:?}", self.table_name, r.status()),
+            Ok(r) => info!("Database importer: verified [{}] table: {:?}", self.table_name, r.status()),
             Err(e) => panic!("Error: creating {} table - {:?}", self.table_name, e),
         };
+        self.migrate(api_url, &sql_create_table);
     }
-    fn insert(&self, sink: &mut questdb::ingress::Sender, source: &duckdb::Connection) {
+    fn insert(
+        &self,
+        sink: &mut questdb::ingress::Sender,
+        source: &duckdb::Connection,
+    ) -> anyhow::Result<()> {
         //
         // query DuckDB memtable
         //
         // select bucket, sum(count) from bytes GROUP by bucket order by bucket
-        let mut stmt = source.prepare("SELECT time_bucket (INTERVAL '1' minute, stime) as bucket,observ,sum(spkts),sum(dpkts)
+        let mut stmt = source.prepare("SELECT time_bucket (INTERVAL '1' minute, stime) as bucket,observ,sum(spkts) AS spkts,sum(dpkts) AS dpkts
                                         FROM memtable 
                                         GROUP BY all 
-                                        ORDER BY all;").unwrap();
+                                        ORDER BY all;")?;
 
-        let record_iter = stmt
-            .query_map([], |row| {
-                Ok(PacketsRecord {
-                    bucket: row.get(0).expect("missing bucket"),
-                    observ: row.get(1).expect("missing observ"),
-                    spkts: row.get(2).expect("missing spkts"),
-                    dpkts: row.get(3).expect("missing dpkts"),                    
-                })
-            })
-            .unwrap();
+        let record_iter = stmt.query_map([], PacketsRecord::from_row)?;
         let mut count = 0;
         let mut buffer = Buffer::new();
         for r in record_iter {
-            let record = r.unwrap();
-            let _ = buffer
-                .table(self.table_name)
-                .unwrap()
-                .symbol("observ", record.observ)
-                .unwrap()
-                .column_ts("bucket", TimestampMicros::new(record.bucket))
-                .unwrap()                     
-                .column_i64("spkts", record.spkts)
-                .unwrap()
-                .column_i64("dpkts", record.dpkts)
-                .unwrap()                
-                .at(TimestampNanos::now())
-                .unwrap();
+            let record = r?;
+            buffer
+                .table(self.table_name)?
+                .symbol("observ", record.observ)?
+                .column_ts("bucket", TimestampMicros::new(record.bucket))?                     
+                .column_i64("spkts", record.spkts)?
+                .column_i64("dpkts", record.dpkts)?                
+                .at(TimestampNanos::now())?;
             if buffer.len() >= (104857600 - 1048576) {
-                sink.flush(&mut buffer).unwrap();
+                sink.flush(&mut buffer)?;
             }
             count += 1;
         }      
         if count > 0 {
-            sink.flush(&mut buffer).unwrap();
-            println!("Table [{}]: {} new records", self.table_name, count);
+            sink.flush(&mut buffer)?;
+            info!("Table [{}]: {} new records", self.table_name, count);
         }
+        Ok(())
     }
 }
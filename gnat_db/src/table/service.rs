@@ -0,0 +1,321 @@
+use crate::window::Window;
+use crate::{enable_dedup, internal_address, query};
+use crate::TableTrait;
+use crate::{from_row, FromRow};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use questdb::ingress::{Buffer, TimestampMicros};
+use tracing::{error, info};
+
+// weight of the newest interval in the baseline
+const BASELINE_ALPHA: f64 = 0.1;
+// intervals observed before a baseline is trusted
+const BASELINE_WARMUP: u32 = 12;
+// interval of the baseline, in minutes
+const INTERVAL_MINUTES: u32 = 5;
+// intervals without flows folded in when a service reappears; past a day
+// of silence the mean has decayed to nothing anyway
+const MAX_IDLE_INTERVALS: i64 = 288;
+
+#[derive(Debug)]
+struct ServiceRecord {
+    bucket: i64,
+    observ: String,
+    subnet: String,
+    dport: i32,
+    appid: String,
+    flows: i64,
+    bytes: i64,
+}
+
+from_row!(ServiceRecord { bucket, observ, subnet, dport, appid, flows, bytes });
+
+//
+// Exponentially weighted mean/variance of flows per interval, updated once
+// per closed interval (see window.rs). Intervals without flows count as
+// zero; they are folded in when the service is next seen. The state is
+// written with each row and rebuilt from the table at startup.
+//
+#[derive(Debug, Default, Clone)]
+pub struct ServiceBaseline {
+    mean: f64,
+    variance: f64,
+    samples: u32,
+    // bucket of the last interval scored, in microseconds
+    last_bucket: i64,
+}
+
+impl ServiceBaseline {
+    //
+    // score the interval at bucket, after the idle ones since the last
+    //
+    fn update(&mut self, bucket: i64, value: f64) -> f64 {
+        if self.samples > 0 {
+            let interval = INTERVAL_MINUTES as i64 * 60_000_000;
+            let idle = ((bucket - self.last_bucket) / interval - 1).clamp(0, MAX_IDLE_INTERVALS);
+            for _ in 0..idle {
+                self.score(0.0);
+            }
+        }
+        self.last_bucket = bucket;
+        self.score(value)
+    }
+
+    fn score(&mut self, value: f64) -> f64 {
+        let mut zscore = 0.0;
+        if self.samples >= BASELINE_WARMUP && self.variance > 0.0 {
+            zscore = (value - self.mean) / self.variance.sqrt();
+        }
+        if self.samples == 0 {
+            self.mean = value;
+        } else {
+            let delta = value - self.mean;
+            self.mean += BASELINE_ALPHA * delta;
+            self.variance = (1.0 - BASELINE_ALPHA) * (self.variance + BASELINE_ALPHA * delta * delta);
+        }
+        self.samples += 1;
+        zscore
+    }
+}
+
+pub struct ServiceTable {
+    pub table_name: &'static str,
+    pub baseline: RefCell<HashMap<String, ServiceBaseline>>,
+}
+
+impl ServiceTable {
+    //
+    // The baseline of each service as of its latest row
+    //
+    fn load_baseline(&self, api_url: &str) -> anyhow::Result<usize> {
+        let rows = query(
+            api_url,
+            &format!(
+                "SELECT observ, subnet, dport, appid, cast(bucket AS LONG), baseline, variance, samples
+                    FROM {} LATEST ON timestamp PARTITION BY observ, subnet, dport, appid;",
+                self.table_name
+            ),
+        )?;
+        let mut baseline = self.baseline.borrow_mut();
+        for row in rows.iter() {
+            // rows of older releases don't hold the state
+            let (Some(last_bucket), Some(mean), Some(variance), Some(samples)) = (
+                row[4].as_i64(),
+                row[5].as_f64(),
+                row[6].as_f64(),
+                row[7].as_u64(),
+            ) else {
+                continue;
+            };
+            let key = format!(
+                "{}|{}|{}|{}",
+                row[0].as_str().unwrap_or_default(),
+                row[1].as_str().unwrap_or_default(),
+                row[2].as_i64().unwrap_or_default(),
+                row[3].as_str().unwrap_or_default()
+            );
+            baseline.insert(
+                key,
+                ServiceBaseline {
+                    mean,
+                    variance,
+                    samples: samples as u32,
+                    last_bucket,
+                },
+            );
+        }
+        Ok(baseline.len())
+    }
+}
+
+impl TableTrait for ServiceTable {
+    fn table_name(&self) -> &'static str {
+        self.table_name
+    }
+    fn columns(&self) -> &'static [&'static str] {
+        &["stime", "observ", "daddr", "dport", "appid", "sbytes", "dbytes"]
+    }
+    fn create(&self, api_url: &String) {
+        let sql_create_table = format!(
+            "CREATE TABLE IF NOT EXISTS {}(
+                bucket TIMESTAMP,
+                observ SYMBOL CAPACITY 64 INDEX,
+                subnet SYMBOL CAPACITY 8192 INDEX,
+                dport INT,
+                appid SYMBOL CAPACITY 8192 INDEX,
+                flows LONG,
+                bytes LONG,
+                baseline DOUBLE,
+                zscore DOUBLE,
+                variance DOUBLE,
+                samples INT,
+                timestamp TIMESTAMP)
+                TIMESTAMP(timestamp) PARTITION BY HOUR WAL
+                DEDUP UPSERT KEYS(timestamp, observ, subnet, dport, appid);",
+            self.table_name
+        );
+
+        //
+        // Post the request to the QuestDB API
+        //
+        let url = url::Url::parse_with_params(api_url, &[("query", &sql_create_table)])
+            .expect("invalid url params");
+
+        match reqwest::blocking::get(url) {
+            Ok(r) => info!("Database importer: verified [{}] table: {:?}", self.table_name, r.status()),
+            Err(e) => panic!("Error: creating {} table - {:?}", self.table_name, e),
+        };
+        self.migrate(api_url, &sql_create_table);
+        enable_dedup(api_url, self.table_name, "timestamp, observ, subnet, dport, appid");
+        match self.load_baseline(api_url) {
+            Ok(count) => info!("Database importer: loaded {} [{}] baselines", count, self.table_name),
+            Err(e) => error!("loading {} baselines - {:?}", self.table_name, e),
+        }
+    }
+    fn insert(
+        &self,
+        sink: &mut questdb::ingress::Sender,
+        source: &duckdb::Connection,
+    ) -> anyhow::Result<()> {
+        let window = Window {
+            table_name: self.table_name,
+            minutes: INTERVAL_MINUTES,
+            columns: self.columns(),
+        };
+        let flows = window.open(source)?;
+        //
+        // query the closed buckets: one record per destination service
+        // (dport/appid) per internal /24 subnet per 5 minutes
+        //
+        let sql_command = format!(
+            "SELECT time_bucket (INTERVAL '{} minutes', stime) as bucket,
+                    observ,
+                    CASE WHEN contains(daddr, ':') THEN daddr
+                         ELSE regexp_replace(daddr, '\\.[0-9]+$', '.0/24') END AS subnet,
+                    dport::INTEGER AS dport,
+                    appid,
+                    count() AS flows,
+                    sum(sbytes + dbytes)::BIGINT AS bytes
+                FROM {}
+                WHERE closed AND {}
+                GROUP BY all
+                ORDER BY all;",
+            INTERVAL_MINUTES,
+            flows,
+            internal_address("daddr")
+        );
+        let mut stmt = source.prepare(&sql_command)?;
+
+        let record_iter = stmt.query_map([], ServiceRecord::from_row)?;
+        let mut count = 0;
+        let mut buffer = Buffer::new();
+        //
+        // the baselines are updated once the rows are flushed, so a retry
+        // scores the same intervals against the same state
+        //
+        let mut updated: HashMap<String, ServiceBaseline> = HashMap::new();
+        for r in record_iter {
+            let record = r?;
+            let key = format!(
+                "{}|{}|{}|{}",
+                record.observ, record.subnet, record.dport, record.appid
+            );
+            let service = updated
+                .entry(key.clone())
+                .or_insert_with(|| self.baseline.borrow().get(&key).cloned().unwrap_or_default());
+            if service.samples > 0 && record.bucket <= service.last_bucket {
+                // already scored (written again after a restart)
+                continue;
+            }
+            let zscore = service.update(record.bucket, record.flows as f64);
+            buffer
+                .table(self.table_name)?
+                .symbol("observ", record.observ)?
+                .symbol("subnet", record.subnet)?
+                .symbol("appid", record.appid)?
+                .column_ts("bucket", TimestampMicros::new(record.bucket))?
+                .column_i64("dport", record.dport as i64)?
+                .column_i64("flows", record.flows)?
+                .column_i64("bytes", record.bytes)?
+                .column_f64("baseline", service.mean)?
+                .column_f64("zscore", zscore)?
+                .column_f64("variance", service.variance)?
+                .column_i64("samples", service.samples as i64)?
+                .at(TimestampMicros::new(record.bucket))?;
+            if buffer.len() >= (104857600 - 1048576) {
+                sink.flush(&mut buffer)?;
+            }
+            count += 1;
+        }
+        if count > 0 {
+            sink.flush(&mut buffer)?;
+            info!("Table [{}]: {} new records", self.table_name, count);
+        }
+        window.commit(source)?;
+        self.baseline.borrow_mut().extend(updated);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_insert, test_memtable};
+
+    const INTERVAL: i64 = INTERVAL_MINUTES as i64 * 60_000_000;
+
+    #[test]
+    fn baseline_scores_after_the_warmup() {
+        let mut baseline = ServiceBaseline::default();
+        for i in 0..BASELINE_WARMUP as i64 {
+            let value = if i % 2 == 0 { 10.0 } else { 12.0 };
+            assert_eq!(baseline.update(i * INTERVAL, value), 0.0);
+        }
+        let usual = baseline.clone().update(12 * INTERVAL, 11.0);
+        let spike = baseline.clone().update(12 * INTERVAL, 100.0);
+        assert!(usual.abs() < 1.0);
+        assert!(spike > 10.0);
+
+        // intervals without flows are folded in as zeros
+        let samples = baseline.samples;
+        let mean = baseline.mean;
+        baseline.update(14 * INTERVAL, 11.0);
+        assert_eq!(baseline.samples, samples + 3);
+        assert!(baseline.mean < mean);
+        // and capped past a day
+        baseline.update(10_000 * INTERVAL, 11.0);
+        assert_eq!(
+            baseline.samples,
+            samples + 3 + MAX_IDLE_INTERVALS as u32 + 1
+        );
+    }
+
+    #[test]
+    fn services_per_subnet() {
+        let table = ServiceTable {
+            table_name: "service_test",
+            baseline: RefCell::new(HashMap::new()),
+        };
+        let source = test_memtable(
+            "SELECT * FROM (VALUES
+                (TIMESTAMP '2024-01-01 00:01:00', 's1', '10.0.0.5', 443, 'tls', 100, 200),
+                (TIMESTAMP '2024-01-01 00:02:00', 's1', '10.0.0.9', 443, 'tls', 10, 20),
+                (TIMESTAMP '2024-01-01 00:02:00', 's1', 'fd00::1', 53, 'dns', 1, 1),
+                (TIMESTAMP '2024-01-01 00:03:00', 's1', '8.8.8.8', 53, 'dns', 1, 1),
+                (TIMESTAMP '2024-01-01 00:30:00', 's1', '10.0.0.5', 443, 'tls', 1, 1))
+             t(stime, observ, daddr, dport, appid, sbytes, dbytes)",
+        );
+        let lines = test_insert(&table, &source);
+        let _ = std::fs::remove_file(".gnat_db-pending-service_test.parquet");
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("service_test,observ=s1,subnet=10.0.0.0/24,appid=tls "));
+        assert!(lines[0].contains("dport=443i,flows=2i,bytes=330i"));
+        assert!(lines[1].starts_with("service_test,observ=s1,subnet=fd00::1,appid=dns "));
+        // the baselines are kept for the next file
+        let baseline = table.baseline.borrow();
+        assert_eq!(baseline["s1|10.0.0.0/24|443|tls"].samples, 1);
+        assert_eq!(baseline["s1|10.0.0.0/24|443|tls"].mean, 2.0);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::TableTrait;
+use crate::{from_row, FromRow};
+
+use questdb::ingress::{Buffer, TimestampMicros, TimestampNanos};
+use tracing::info;
+
+#[derive(Debug)]
+struct TrafficRecord {
+    bucket: i64,
+    observ: String,
+    vlan: i64,
+    flows: i64,
+    bytes: i64,
+    packets: i64,
+}
+
+from_row!(TrafficRecord { bucket, observ, vlan, flows, bytes, packets });
+
+pub struct TrafficTable {
+    pub table_name: &'static str,
+}
+
+impl TableTrait for TrafficTable {
+    fn table_name(&self) -> &'static str {
+        self.table_name
+    }
+    fn columns(&self) -> &'static [&'static str] {
+        &["stime", "observ", "svlan", "sbytes", "dbytes", "spkts", "dpkts"]
+    }
+    fn clickhouse_columns(&self) -> Option<&'static str> {
+        Some("bucket DateTime, observ LowCardinality(String), vlan UInt16, flows UInt64, bytes UInt64, packets UInt64")
+    }
+    fn clickhouse_query(&self) -> Option<&'static str> {
+        Some(
+            "SELECT time_bucket (INTERVAL '1' minute, stime) AS bucket, observ, coalesce(svlan, 0) AS vlan,
+                    count()::UBIGINT AS flows, sum(sbytes + dbytes)::UBIGINT AS bytes, sum(spkts + dpkts)::UBIGINT AS packets
+                FROM memtable
+                GROUP BY all",
+        )
+    }
+    fn create(&self, api_url: &String) {
+        let sql_create_table = format!(
+            "CREATE TABLE IF NOT EXISTS {}(
+                bucket TIMESTAMP,
+                observ SYMBOL CAPACITY 64 INDEX,
+                vlan LONG,
+                flows LONG,
+                bytes LONG,
+                packets LONG,
+                timestamp TIMESTAMP) 
+                TIMESTAMP(timestamp) PARTITION BY HOUR;",
+                self.table_name
+        );
+
+        //
+        // Post the request to the QuestDB API
+        //
+        let url = url::Url::parse_with_params(api_url, &[("query", &sql_create_table)])
+            .expect("invalid url params");
+
+        match reqwest::blocking::get(url) {
+            Ok(r) => info!("Database importer: verified [{}] table: {:?}", self.table_name, r.status()),
+            Err(e) => panic!("Error: creating {} table - {:?}", self.table_name, e),
+        };
+        self.migrate(api_url, &sql_create_table);
+    }
+    fn insert(
+        &self,
+        sink: &mut questdb::ingress::Sender,
+        source: &duckdb::Connection,
+    ) -> anyhow::Result<()> {
+        //
+        // query DuckDB memtable
+        //
+
+        let mut stmt = source.prepare("SELECT time_bucket (INTERVAL '1' minute, stime) as bucket,observ,coalesce(svlan, 0)::BIGINT AS vlan,
+                                                                count() AS flows,sum(sbytes + dbytes)::BIGINT AS bytes,sum(spkts + dpkts)::BIGINT AS packets
+                                                            FROM memtable 
+                                                            GROUP BY all 
+                                                            ORDER BY all;")?;
+
+        let record_iter = stmt.query_map([], TrafficRecord::from_row)?;
+        let mut count = 0;
+        let mut buffer = Buffer::new();
+        for r in record_iter {
+            let record = r?;
+            buffer
+                .table(self.table_name)?
+                .symbol("observ", record.observ)?
+                .column_ts("bucket", TimestampMicros::new(record.bucket))?
+                .column_i64("vlan", record.vlan)?
+                .column_i64("flows", record.flows)?
+                .column_i64("bytes", record.bytes)?
+                .column_i64("packets", record.packets)?
+                .at(TimestampNanos::now())?;
+            if buffer.len() >= (104857600 - 1048576) {
+                sink.flush(&mut buffer)?;
+            }
+            count += 1;
+        }
+        if count > 0 {
+            sink.flush(&mut buffer)?;
+            info!("Table [{}]: {} new records", self.table_name, count);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_insert, test_memtable};
+
+    #[test]
+    fn traffic_per_vlan() {
+        let table = TrafficTable {
+            table_name: "traffic_test",
+        };
+        let source = test_memtable(
+            "SELECT * FROM (VALUES
+                (TIMESTAMP '2024-01-01 00:00:10', 's1', 10, 100, 1000, 1, 2),
+                (TIMESTAMP '2024-01-01 00:00:20', 's1', 10, 50, 0, 1, 0),
+                (TIMESTAMP '2024-01-01 00:00:30', 's1', NULL, 7, 3, 1, 1),
+                (TIMESTAMP '2024-01-01 00:01:00', 's1', NULL, 1, 1, 1, 1))
+                t(stime, observ, svlan, sbytes, dbytes, spkts, dpkts)",
+        );
+
+        // untagged flows count as vlan 0
+        let lines = test_insert(&table, &source);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("traffic_test,observ=s1 bucket=1704067200000000t,"));
+        assert!(lines[0].contains(",vlan=0i,flows=1i,bytes=10i,packets=2i "));
+        assert!(lines[1].contains(",vlan=10i,flows=2i,bytes=1150i,packets=4i "));
+        assert!(lines[2].starts_with("traffic_test,observ=s1 bucket=1704067260000000t,"));
+        assert!(lines[2].contains(",vlan=0i,flows=1i,bytes=2i,packets=2i "));
+    }
+}
@@ -0,0 +1,180 @@
+//
+// Buckets written whole. A spool file can end part way through a bucket,
+// so a table counting distinct values or scoring each interval can't write
+// a row per file: the partial rows of a bucket don't combine. Such tables
+// read a window of the flows instead of memtable. Flows of the buckets that
+// may still grow are held in a hidden pending file in the input directory
+// and joined with the next file, and a bucket is written once the newest
+// flow of its observation point is the grace period past its end. Flows
+// arriving for a bucket already written are dropped.
+//
+// The pending file is replaced only after the rows of the closed buckets
+// are flushed, so a retry starts again from the same flows; a bucket
+// written twice (gnat_db stopped between the two) is replaced through the
+// table's DEDUP keys.
+//
+
+use std::fs;
+use std::path::Path;
+
+use tracing::warn;
+
+// seconds past its end before a bucket is written
+pub const GRACE: u32 = 600;
+
+pub struct Window {
+    pub table_name: &'static str,
+    pub minutes: u32,
+    // memtable columns held in the pending file; must include stime and observ
+    pub columns: &'static [&'static str],
+}
+
+impl Window {
+    fn pending_spec(&self) -> String {
+        format!(".gnat_db-pending-{}.parquet", self.table_name)
+    }
+
+    //
+    // Create <table>_window from memtable and the pending file, with closed
+    // set on the flows of the buckets ready to be written; returns its name
+    //
+    pub fn open(&self, source: &duckdb::Connection) -> anyhow::Result<String> {
+        let window = format!("{}_window", self.table_name);
+        let columns = self.columns.join(", ");
+        let pending_spec = self.pending_spec();
+        let pending = if Path::new(&pending_spec).exists() {
+            format!("SELECT {}, true AS held FROM '{}'", columns, pending_spec)
+        } else {
+            format!("SELECT {}, true AS held FROM memtable WHERE false", columns)
+        };
+        let closed = format!(
+            "time_bucket(INTERVAL '{0} minutes', stime) + INTERVAL '{0} minutes' + INTERVAL '{1} seconds'",
+            self.minutes, GRACE
+        );
+        let sql_command = format!(
+            "CREATE OR REPLACE TABLE {0} AS
+                WITH pending AS ({1}),
+                previous AS (SELECT observ, max(stime) AS newest FROM pending GROUP BY observ),
+                arrived AS (
+                    SELECT {2}, false AS held FROM memtable m
+                    WHERE NOT EXISTS (SELECT 1 FROM previous p
+                        WHERE p.observ IS NOT DISTINCT FROM m.observ AND {3} <= p.newest)),
+                flows AS (SELECT * FROM arrived UNION ALL BY NAME SELECT * FROM pending),
+                newest AS (SELECT observ, max(stime) AS newest FROM flows GROUP BY observ)
+                SELECT f.*, {3} <= n.newest AS closed
+                    FROM flows f JOIN newest n ON n.observ IS NOT DISTINCT FROM f.observ;",
+            window, pending, columns, closed
+        );
+        source.execute_batch(&sql_command)?;
+
+        let late: i64 = source.query_row(
+            &format!(
+                "SELECT (SELECT count() FROM memtable) - (SELECT count() FROM {} WHERE NOT held);",
+                window
+            ),
+            [],
+            |row| row.get(0),
+        )?;
+        if late > 0 {
+            warn!("Table [{}]: {} late flows dropped", self.table_name, late);
+        }
+        Ok(window)
+    }
+
+    //
+    // Hold the flows of the buckets still open, once the closed ones are written
+    //
+    pub fn commit(&self, source: &duckdb::Connection) -> anyhow::Result<()> {
+        let pending_spec = self.pending_spec();
+        let tmp_spec = format!("{}.tmp", pending_spec);
+        source.execute_batch(&format!(
+            "COPY (SELECT {} FROM {}_window WHERE NOT closed ORDER BY stime) TO '{}' (FORMAT 'parquet');",
+            self.columns.join(", "),
+            self.table_name,
+            tmp_spec
+        ))?;
+        fs::rename(&tmp_spec, &pending_spec)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_memtable;
+
+    // (observ, minute, closed) of the window over the flows of select
+    fn open(window: &Window, select: &str) -> (duckdb::Connection, Vec<(String, u32, bool)>) {
+        let source = test_memtable(select);
+        let name = window.open(&source).unwrap();
+        let rows = {
+            let mut stmt = source
+                .prepare(&format!(
+                    "SELECT observ, minute(stime), closed FROM {} ORDER BY ALL;",
+                    name
+                ))
+                .unwrap();
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+        (source, rows)
+    }
+
+    fn flows(rows: &[(&str, u32)]) -> String {
+        let values: Vec<String> = rows
+            .iter()
+            .map(|(observ, minute)| {
+                format!(
+                    "('{}', TIMESTAMP '2024-01-01' + INTERVAL '{} minutes')",
+                    observ, minute
+                )
+            })
+            .collect();
+        format!(
+            "SELECT * FROM (VALUES {}) t(observ, stime)",
+            values.join(", ")
+        )
+    }
+
+    #[test]
+    fn buckets_close_per_observation() {
+        let window = Window {
+            table_name: "window_test",
+            minutes: 5,
+            columns: &["stime", "observ"],
+        };
+        let row = |observ: &str, minute: u32, closed: bool| (String::from(observ), minute, closed);
+
+        // s1 has a flow the grace period past its first bucket, s2 doesn't
+        let (source, rows) = open(
+            &window,
+            &flows(&[("s1", 1), ("s1", 15), ("s2", 1), ("s2", 14)]),
+        );
+        assert_eq!(
+            rows,
+            vec![
+                row("s1", 1, true),
+                row("s1", 15, false),
+                row("s2", 1, false),
+                row("s2", 14, false)
+            ]
+        );
+        window.commit(&source).unwrap();
+
+        // the held flows join the next file; s1's late flow is dropped
+        let (source, rows) = open(&window, &flows(&[("s1", 2), ("s2", 16)]));
+        assert_eq!(
+            rows,
+            vec![
+                row("s1", 15, false),
+                row("s2", 1, true),
+                row("s2", 14, false),
+                row("s2", 16, false)
+            ]
+        );
+        window.commit(&source).unwrap();
+        let _ = fs::remove_file(window.pending_spec());
+    }
+}
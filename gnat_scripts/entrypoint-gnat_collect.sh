@@ -3,6 +3,7 @@
 GNAT_GEO_OPTIONS=
 GNAT_GEO_ASN=/var/maxmind/GeoLite2-ASN.mmdb
 GNAT_GEO_COUNTRY=/var/maxmind/GeoLite2-Country.mmdb
+GNAT_GEO_CITY=/var/maxmind/GeoLite2-City.mmdb
 
 if [ -z "${GNAT_INPUT_DIR}" ]; then
     echo "Error: undefined environment variable GNAT_INPUT_DIR"
@@ -35,6 +36,10 @@ if [ -f ${GNAT_GEO_COUNTRY} ]; then
    GNAT_GEO_OPTIONS="${GNAT_GEO_OPTIONS} --country ${GNAT_GEO_COUNTRY}"
 fi
 
+if [ -f ${GNAT_GEO_CITY} ]; then
+   GNAT_GEO_OPTIONS="${GNAT_GEO_OPTIONS} --city ${GNAT_GEO_CITY}"
+fi
+
 /opt/gnat/bin/gnat_collect \
     --observation ${GNAT_OBSERVATION_TAG} \
     --input ${GNAT_INPUT_DIR} \
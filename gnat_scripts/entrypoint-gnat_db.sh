@@ -17,6 +17,20 @@ fi
 if [ -z "${GNAT_QDB_POLLING}" ]; then
     GNAT_QDB_POLLING=60
 fi
+
+GNAT_DB_OPTIONS=
+if [ -f "${GNAT_ANNOTATIONS}" ]; then
+    GNAT_DB_OPTIONS="--annotations ${GNAT_ANNOTATIONS}"
+fi
+if [ -n "${GNAT_QDB_RETENTION_5M}" ]; then
+    GNAT_DB_OPTIONS="${GNAT_DB_OPTIONS} --retention-5m ${GNAT_QDB_RETENTION_5M}"
+fi
+if [ -n "${GNAT_QDB_RETENTION_1H}" ]; then
+    GNAT_DB_OPTIONS="${GNAT_DB_OPTIONS} --retention-1h ${GNAT_QDB_RETENTION_1H}"
+fi
+if [ -n "${GNAT_QDB_RETENTION_1D}" ]; then
+    GNAT_DB_OPTIONS="${GNAT_DB_OPTIONS} --retention-1d ${GNAT_QDB_RETENTION_1D}"
+fi
 #
 # Launch db exporter
 #
@@ -25,5 +39,6 @@ fi
     --host ${GNAT_QDB_HOST} \
     --retention ${GNAT_QDB_RETENTION} \
     --processed ${GNAT_PROCESSED_DIR} \
-    --polling ${GNAT_QDB_POLLING}
+    --polling ${GNAT_QDB_POLLING} \
+    ${GNAT_DB_OPTIONS}
 
@@ -3,6 +3,7 @@
 GNAT_GEO_OPTIONS=
 GNAT_GEO_ASN=/var/maxmind/GeoLite2-ASN.mmdb
 GNAT_GEO_COUNTRY=/var/maxmind/GeoLite2-Country.mmdb
+GNAT_GEO_CITY=/var/maxmind/GeoLite2-City.mmdb
 
 if [ -z "${GNAT_INPUT_DIR}" ]; then
     echo "Error: undefined environment variable GNAT_INPUT_DIR"
@@ -27,6 +28,14 @@ if [ ! -d "${GNAT_PROCESSED_DIR}" ]; then
     mkdir ${GNAT_PROCESSED_DIR}
 fi
 
+GNAT_DPI_OPTIONS=
+if [ ! -z "${GNAT_DNS_OUTPUT_DIR}" ]; then
+    if [ ! -d "${GNAT_DNS_OUTPUT_DIR}" ]; then
+        mkdir ${GNAT_DNS_OUTPUT_DIR}
+    fi
+    GNAT_DPI_OPTIONS="--dns-output ${GNAT_DNS_OUTPUT_DIR}"
+fi
+
 if [ -f ${GNAT_GEO_ASN} ]; then
   GNAT_GEO_OPTIONS="--asn ${GNAT_GEO_ASN}"
 fi
@@ -35,12 +44,17 @@ if [ -f ${GNAT_GEO_COUNTRY} ]; then
    GNAT_GEO_OPTIONS="${GNAT_GEO_OPTIONS} --country ${GNAT_GEO_COUNTRY}"
 fi
 
+if [ -f ${GNAT_GEO_CITY} ]; then
+   GNAT_GEO_OPTIONS="${GNAT_GEO_OPTIONS} --city ${GNAT_GEO_CITY}"
+fi
+
 /opt/gnat/bin/gnat_import \
     --observation ${GNAT_OBSERVATION_TAG} \
     --input ${GNAT_INPUT_DIR} \
     --output ${GNAT_OUTPUT_DIR} \
     --processed ${GNAT_PROCESSED_DIR} \
     --polling true \
+    ${GNAT_DPI_OPTIONS} \
     ${GNAT_GEO_OPTIONS}
     
 
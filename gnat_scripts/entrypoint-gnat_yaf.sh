@@ -28,6 +28,11 @@ if [ -z "${GNAT_OPTIONS}" ]; then
     GNAT_OPTIONS="--entropy --ndpi --verbose --max-payload=2048 --flow-stats --mac --active-timeout 300 --idle-timeout 120 --out ${GNAT_OUTPUT_DIR}/${GNAT_OBSERVATION_TAG} --lock"
 fi
 
+# deep packet inspection records (DNS) for gnat_import --dns-output
+if [ ! -z "${GNAT_DPI}" ]; then
+    GNAT_OPTIONS="${GNAT_OPTIONS} --applabel --plugin-name=/opt/gnat/lib/yaf/dpacketplugin.la"
+fi
+
 export LTDL_LIBRARY_PATH=/opt/gnat/lib/yaf
 
 if [ ! -z "${GNAT_PCAP_LIST}" ]; then